@@ -0,0 +1,123 @@
+//! Compares the throughput of the built-in storage engines under a mixed read/write workload.
+//!
+//! Run with `cargo bench --bench engines`. The workload can be tuned with the following environment
+//! variables: `BENCH_KEYS` (key space size), `BENCH_OPS` (operations per thread), `BENCH_WRITE_PCT`
+//! (percentage of the operations that are writes) and `BENCH_ENGINES` (the engines to compare, separated by
+//! commas, all the built-in ones by default).
+
+use std::{
+    env,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use myrustcache::{
+    cache::{EngineOptions, BUILTIN_ENGINES},
+    Bytes, CacheFactory, ServerCache,
+};
+
+const THREAD_COUNTS: &[usize] = &[1, 2, 4, 8, 16];
+
+fn main() {
+    let keys: usize = env_or("BENCH_KEYS", 100_000);
+    let ops: usize = env_or("BENCH_OPS", 200_000);
+    let write_pct: u64 = env_or("BENCH_WRITE_PCT", 10);
+    let engines: Vec<String> = match env::var("BENCH_ENGINES") {
+        Ok(engines) => engines.split(',').map(|e| e.trim().to_string()).collect(),
+        Err(_) => BUILTIN_ENGINES.iter().map(|e| e.to_string()).collect(),
+    };
+
+    println!("{} keys, {} ops/thread, {}% writes", keys, ops, write_pct);
+    println!(
+        "{:<10} {:>8} {:>14} {:>12}",
+        "engine", "threads", "ops/sec", "elapsed"
+    );
+
+    for engine in &engines {
+        for &threads in THREAD_COUNTS {
+            let options: EngineOptions = EngineOptions::default();
+            let cache: Arc<ServerCache> = match CacheFactory::new_cache_named(engine, &options) {
+                Ok(cache) => cache,
+                Err(e) => {
+                    eprintln!("Skipped the {} engine: {}", engine, e);
+                    break;
+                }
+            };
+            populate(&cache, keys);
+
+            let elapsed: Duration = run(&cache, threads, keys, ops, write_pct);
+            let throughput: f64 = (threads * ops) as f64 / elapsed.as_secs_f64();
+
+            println!(
+                "{:<10} {:>8} {:>14.0} {:>12?}",
+                engine, threads, throughput, elapsed
+            );
+        }
+    }
+}
+
+/// Fill the cache with `keys` entries, so that reads mostly hit.
+fn populate(cache: &Arc<ServerCache>, keys: usize) {
+    let value: Bytes = Bytes::from(&b"benchmark-value"[..]);
+    for i in 0..keys {
+        cache
+            .put(format!("key:{}", i), Bytes::clone(&value), None)
+            .unwrap();
+    }
+}
+
+/// Run the workload on `threads` threads concurrently and return the wall-clock time it took.
+fn run(
+    cache: &Arc<ServerCache>,
+    threads: usize,
+    keys: usize,
+    ops: usize,
+    write_pct: u64,
+) -> Duration {
+    let started: Instant = Instant::now();
+
+    let workers: Vec<thread::JoinHandle<()>> = (0..threads)
+        .map(|t| {
+            let cache: Arc<ServerCache> = Arc::clone(cache);
+            thread::spawn(move || {
+                let mut rng: XorShift = XorShift(0x9E37_79B9_7F4A_7C15 ^ (t as u64 + 1));
+                let value: Bytes = Bytes::from(&b"updated-value"[..]);
+
+                for _ in 0..ops {
+                    let key: String = format!("key:{}", rng.next() as usize % keys);
+                    if rng.next() % 100 < write_pct {
+                        cache.put(key, Bytes::clone(&value), None).unwrap();
+                    } else {
+                        cache.get(&key).unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    started.elapsed()
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// A tiny pseudo random number generator, so that the benchmark does not need extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
@@ -0,0 +1,146 @@
+//! Measures the latency of GETs served while writers keep updating the cache, for every in-memory engine.
+//!
+//! Run with `cargo bench --bench read_latency`. The workload can be tuned with the following environment
+//! variables: `BENCH_KEYS` (key space size), `BENCH_READERS` and `BENCH_WRITERS` (thread counts), and
+//! `BENCH_READS` (GETs measured per reader thread).
+
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use myrustcache::{cache::EngineOptions, Bytes, CacheFactory, ServerCache};
+
+/// The engines keeping everything in memory; the disk-backed ones are dominated by I/O.
+const ENGINES: &[&str] = &["simple", "sharded", "leftright"];
+
+fn main() {
+    let keys: usize = env_or("BENCH_KEYS", 100_000);
+    let readers: usize = env_or("BENCH_READERS", 4);
+    let writers: usize = env_or("BENCH_WRITERS", 2);
+    let reads: usize = env_or("BENCH_READS", 200_000);
+
+    println!(
+        "{} keys, {} readers x {} GETs, {} writers",
+        keys, readers, reads, writers
+    );
+    println!(
+        "{:<10} {:>10} {:>10} {:>10} {:>10}",
+        "engine", "p50", "p99", "p99.9", "max"
+    );
+
+    for engine in ENGINES {
+        let cache: Arc<ServerCache> =
+            CacheFactory::new_cache_named(engine, &EngineOptions::default()).unwrap();
+        populate(&cache, keys);
+
+        let mut latencies: Vec<Duration> = run(&cache, keys, readers, writers, reads);
+        latencies.sort_unstable();
+
+        println!(
+            "{:<10} {:>10?} {:>10?} {:>10?} {:>10?}",
+            engine,
+            percentile(&latencies, 50.0),
+            percentile(&latencies, 99.0),
+            percentile(&latencies, 99.9),
+            latencies.last().copied().unwrap_or_default()
+        );
+    }
+}
+
+/// Fill the cache with `keys` entries, so that reads mostly hit.
+fn populate(cache: &Arc<ServerCache>, keys: usize) {
+    let value: Bytes = Bytes::from(&b"benchmark-value"[..]);
+    for i in 0..keys {
+        cache
+            .put(format!("key:{}", i), Bytes::clone(&value), None)
+            .unwrap();
+    }
+}
+
+/// Run the writers until every reader has measured its GETs, and return the latencies of all GETs.
+fn run(
+    cache: &Arc<ServerCache>,
+    keys: usize,
+    readers: usize,
+    writers: usize,
+    reads: usize,
+) -> Vec<Duration> {
+    let done: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+
+    let writer_threads: Vec<thread::JoinHandle<()>> = (0..writers)
+        .map(|t| {
+            let cache: Arc<ServerCache> = Arc::clone(cache);
+            let done: Arc<AtomicBool> = Arc::clone(&done);
+            thread::spawn(move || {
+                let mut rng: XorShift = XorShift(0x2545_F491_4F6C_DD1D ^ (t as u64 + 1));
+                let value: Bytes = Bytes::from(&b"updated-value"[..]);
+
+                while !done.load(Ordering::Relaxed) {
+                    let key: String = format!("key:{}", rng.next() as usize % keys);
+                    cache.put(key, Bytes::clone(&value), None).unwrap();
+                }
+            })
+        })
+        .collect();
+
+    let reader_threads: Vec<thread::JoinHandle<Vec<Duration>>> = (0..readers)
+        .map(|t| {
+            let cache: Arc<ServerCache> = Arc::clone(cache);
+            thread::spawn(move || {
+                let mut rng: XorShift = XorShift(0x9E37_79B9_7F4A_7C15 ^ (t as u64 + 1));
+                let mut latencies: Vec<Duration> = Vec::with_capacity(reads);
+
+                for _ in 0..reads {
+                    let key: String = format!("key:{}", rng.next() as usize % keys);
+                    let started: Instant = Instant::now();
+                    cache.get(&key).unwrap();
+                    latencies.push(started.elapsed());
+                }
+                latencies
+            })
+        })
+        .collect();
+
+    let latencies: Vec<Duration> = reader_threads
+        .into_iter()
+        .flat_map(|reader| reader.join().unwrap())
+        .collect();
+
+    done.store(true, Ordering::Relaxed);
+    for writer in writer_threads {
+        writer.join().unwrap();
+    }
+
+    latencies
+}
+
+/// The latency below which the given percentage of the (sorted) latencies fall.
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    let index: usize = ((sorted.len() as f64 * pct / 100.0) as usize).min(sorted.len() - 1);
+    sorted[index]
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// A tiny pseudo random number generator, so that the benchmark does not need extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
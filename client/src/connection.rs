@@ -0,0 +1,156 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{Error, Result};
+
+/// How long to wait for the server to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A part of a response: a line, or a value sent as a bulk payload.
+pub(crate) enum Item {
+    Line(String),   // A line other than a value, without its terminator (e.g. `OK`)
+    Value(Vec<u8>), // A value
+    Stale(Vec<u8>), // A value whose soft TTL has elapsed (see `SET ... SOFT`)
+}
+
+/// A connection of the text protocol in the bulk mode (see `HELLO BULK`), on which every argument is sent
+/// as a bulk payload.
+pub(crate) struct Connection {
+    reader: BufReader<TcpStream>, // The responses of the server
+    writer: TcpStream,            // The commands sent to the server
+}
+
+impl Connection {
+    /// Connect to the server of the given address (`host:port`) and switch to the bulk mode.
+    ///
+    /// # Errors
+    /// * `Error::Io` if the server cannot be connected to.
+    /// * `Error::Server` or `Error::Protocol` if the server does not support the bulk mode.
+    pub fn open(address: &str, timeout: Option<Duration>) -> Result<Connection> {
+        let socket_address: SocketAddr = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("cannot resolve {}", address)))?;
+        let stream: TcpStream = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("could not connect to server at {}: {}", address, e),
+                )
+            })?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(timeout)?;
+
+        let mut connection: Connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        connection.writer.write_all(b"HELLO BULK\n")?;
+        match connection.read_item()? {
+            Item::Line(line) if line == "OK BULK" => Ok(connection),
+            Item::Line(line) if is_error(&line) => Err(Error::Server(line)),
+            _ => Err(Error::Protocol("HELLO BULK is not supported".to_string())),
+        }
+    }
+
+    /// Fail the reads of the responses taking longer than the given timeout.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.writer.set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Send a command and read its single-item response.
+    ///
+    /// # Errors
+    /// * `Error::Server` if the server answered with an error.
+    pub fn request(&mut self, arguments: &[&str]) -> Result<Item> {
+        self.send(&[arguments])?;
+        match self.read_item()? {
+            Item::Line(line) if is_error(&line) => Err(Error::Server(line)),
+            item => Ok(item),
+        }
+    }
+
+    /// Send several commands in a single write, then read their single-item responses. The responses of
+    /// all the commands are read even if some failed, so that the connection stays in step.
+    pub fn pipeline(&mut self, commands: &[&[&str]]) -> Result<Vec<Result<Item>>> {
+        self.send(commands)?;
+        let mut items: Vec<Result<Item>> = Vec::with_capacity(commands.len());
+        for _ in commands {
+            items.push(match self.read_item()? {
+                Item::Line(line) if is_error(&line) => Err(Error::Server(line)),
+                item => Ok(item),
+            });
+        }
+        Ok(items)
+    }
+
+    /// Write the given commands, every argument as a bulk payload (`$<length>` on the line of the command,
+    /// and the payload on a line of its own after it).
+    pub fn send(&mut self, commands: &[&[&str]]) -> Result<()> {
+        let mut buffer: Vec<u8> = Vec::new();
+        for arguments in commands {
+            let (name, arguments) = arguments.split_first().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "a command cannot be empty")
+            })?;
+            if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' is not a command name", name),
+                )));
+            }
+            buffer.extend_from_slice(name.as_bytes());
+            for argument in arguments {
+                buffer.extend_from_slice(format!(" ${}", argument.len()).as_bytes());
+            }
+            buffer.push(b'\n');
+            for argument in arguments {
+                buffer.extend_from_slice(argument.as_bytes());
+                buffer.extend_from_slice(b"\r\n");
+            }
+        }
+        self.writer.write_all(&buffer)?;
+        Ok(())
+    }
+
+    /// Read a part of a response: a line, followed by its payload if it announces one (`$<length>` or
+    /// `STALE $<length>`).
+    pub fn read_item(&mut self) -> Result<Item> {
+        let mut line: String = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed by server",
+            )));
+        }
+        line.truncate(line.trim_end_matches(['\r', '\n']).len());
+
+        let (stale, length) = match line.strip_prefix("STALE ") {
+            Some(rest) => (true, rest),
+            None => (false, line.as_str()),
+        };
+        let Some(length) = length
+            .strip_prefix('$')
+            .and_then(|length| length.parse::<usize>().ok())
+        else {
+            return Ok(Item::Line(line));
+        };
+
+        let mut value: Vec<u8> = vec![0; length];
+        self.reader.read_exact(&mut value)?;
+        let mut terminator: String = String::new();
+        self.reader.read_line(&mut terminator)?;
+        match stale {
+            true => Ok(Item::Stale(value)),
+            false => Ok(Item::Value(value)),
+        }
+    }
+}
+
+/// Whether a line of a response is an error (`Error: ...`, or `ERR ...` in the strict mode).
+pub(crate) fn is_error(line: &str) -> bool {
+    line.starts_with("Error: ") || line.starts_with("ERR ")
+}
@@ -0,0 +1,531 @@
+//! A typed client of the text protocol of myrustcache, for Rust services using the cache and for the `client`
+//! CLI.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use myrustcache_client::Client;
+//!
+//! let mut client = Client::connect("127.0.0.1:5050")?;
+//! client.set_with_ttl("greeting", "hello world", Duration::from_secs(60))?;
+//! assert_eq!(client.get("greeting")?, Some("hello world".to_string()));
+//! assert!(client.del("greeting")?);
+//! # Ok::<(), myrustcache_client::Error>(())
+//! ```
+//!
+//! A `Client` reconnects on its own when the server drops its connection, and a `Pool` shares several of
+//! them between threads.
+
+mod connection;
+mod pool;
+
+use std::{fmt, io, thread, time::Duration};
+
+use connection::{is_error, Connection, Item};
+
+pub use pool::{Pool, PooledClient};
+
+/// The commands answering the number of their values on a line, followed by every value.
+const LIST_COMMANDS: [&str; 5] = ["LRANGE", "SMEMBERS", "SINTER", "SUNION", "SDIFF"];
+
+/// The commands changing the state of a connection, sent again (in this order) on every new connection
+/// after they succeeded once, so that a reconnection goes unnoticed.
+const SESSION_COMMANDS: [&str; 4] = ["HELLO", "AUTH", "SELECT", "USE"];
+
+/// The error of a request to the server.
+#[derive(Debug)]
+pub enum Error {
+    /// The connection failed, or a command could not be sent as given.
+    Io(io::Error),
+
+    /// The server answered with an error, kept as answered (e.g. `Error: Missing key`, or
+    /// `ERR 1101 missing_key` after `HELLO STRICT`).
+    Server(String),
+
+    /// The server answered something else than the command calls for (e.g. a value which is not UTF-8).
+    Protocol(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Server(response) => write!(f, "{}", response),
+            Error::Protocol(message) => write!(f, "Unexpected response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The response to a command sent with `Client::execute_reply`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// The key has no value (`NULL`, or `<NULL>` for `RM`).
+    Nil,
+
+    /// A value, as it was set.
+    Value(Vec<u8>),
+
+    /// A value whose soft TTL has elapsed (see `SET ... SOFT`).
+    Stale(Vec<u8>),
+
+    /// Any other line, e.g. `OK`, `NEGATIVE` or an integer.
+    Line(String),
+
+    /// The items of a response made of several: one per key for `MGET`, every value for the commands
+    /// answering their number first (e.g. `LRANGE`), or every entry of `XRANGE` as its ID and its value.
+    Array(Vec<Reply>),
+}
+
+impl From<Item> for Reply {
+    fn from(item: Item) -> Reply {
+        match item {
+            Item::Line(line) if line == "NULL" || line == "<NULL>" => Reply::Nil,
+            Item::Line(line) => Reply::Line(line),
+            Item::Value(value) => Reply::Value(value),
+            Item::Stale(value) => Reply::Stale(value),
+        }
+    }
+}
+
+/// How a `Client` connects again once the server dropped its connection: after a failed attempt, it
+/// waits for the initial delay, then twice as long after every other failed attempt, up to the maximum
+/// delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub attempts: u32, // How many times to try again after a failure (0 for never)
+    pub initial_delay: Duration, // The delay before trying again the first time
+    pub max_delay: Duration, // The longest delay between two attempts
+}
+
+impl Backoff {
+    /// Never connect again after a failed attempt.
+    pub const NONE: Backoff = Backoff {
+        attempts: 0,
+        initial_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+    };
+
+    /// The delay before the given attempt to connect again (0 for the first one).
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay)
+    }
+}
+
+impl Default for Backoff {
+    /// Try again 5 times, from 100 milliseconds up to 5 seconds apart (i.e. for about 3 seconds in all).
+    fn default() -> Backoff {
+        Backoff {
+            attempts: 5,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A client of a myrustcache server, over a connection of the text protocol in the bulk mode (see
+/// `HELLO BULK`): every argument is sent as a bulk payload, so keys and values may hold whitespace and line
+/// terminators, and the values are read back as they were set (e.g. a stored `NULL` is told apart from a
+/// missing key).
+///
+/// The typed methods (`get`, `set`, `set_with_ttl`, `del` and their batch variants) map the responses onto
+/// Rust types, and `execute` sends any other command. The batch variants either send a single command
+/// (`mget`, `mset`) or pipeline one command per key, so they all take a single round trip.
+///
+/// When the server drops the connection (e.g. on a restart, or after an idle timeout), the client connects
+/// again with exponential backoff (see `Backoff`) and sends the session commands which succeeded on the
+/// previous connection (`HELLO STRICT`, `AUTH`, `SELECT` and `USE`) again. The typed methods, which can be
+/// applied twice to the same effect, are then sent again. The commands of `execute` are not, as the server
+/// may have applied them, but the next command goes to the new connection.
+pub struct Client {
+    address: String,                   // The address of the server (host:port)
+    connection: Option<Connection>,    // The connection, or `None` until the next command
+    backoff: Backoff,                  // How to connect again once the connection was dropped
+    timeout: Option<Duration>,         // How long to wait for a response, if not forever
+    session: [Option<Vec<String>>; 4], // The last succeeded command of each `SESSION_COMMANDS`
+}
+
+impl Client {
+    /// Connect to the server of the given address (`host:port`).
+    ///
+    /// # Errors
+    /// * `Error::Io` if the server cannot be connected to.
+    /// * `Error::Server` or `Error::Protocol` if the server does not support the bulk mode.
+    pub fn connect(address: &str) -> Result<Client> {
+        let connection: Connection = Connection::open(address, None)?;
+        Ok(Client {
+            address: address.to_string(),
+            connection: Some(connection),
+            backoff: Backoff::default(),
+            timeout: None,
+            session: Default::default(),
+        })
+    }
+
+    /// Connect again as the given backoff says once the server dropped the connection (see
+    /// `Backoff::default`), or never with `Backoff::NONE`.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Client {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fail the requests whose response takes longer than the given timeout (none by default, as
+    /// commands such as `BLPOP` may wait on purpose). The response of a request which timed out is left
+    /// pending on its connection, so the next request is sent on a new one.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        if let Some(connection) = &self.connection {
+            connection.set_timeout(timeout)?;
+        }
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// Get the value of a key.
+    ///
+    /// # Returns
+    /// * The value of the key (even if its soft TTL has elapsed), or `None` if it has none, or is cached as
+    ///   known to be absent (see `SETNULL`).
+    ///
+    /// # Errors
+    /// * `Error::Protocol` if the value is not UTF-8.
+    pub fn get(&mut self, key: &str) -> Result<Option<String>> {
+        self._call(true, |connection| {
+            let item: Item = connection.request(&["GET", key])?;
+            optional_value(key, item)
+        })
+    }
+
+    /// Get the values of several keys in one command.
+    ///
+    /// # Returns
+    /// * The value of every key (see `get`), in the order of the keys.
+    pub fn mget(&mut self, keys: &[&str]) -> Result<Vec<Option<String>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut arguments: Vec<&str> = Vec::with_capacity(keys.len() + 1);
+        arguments.push("MGET");
+        arguments.extend_from_slice(keys);
+
+        self._call(true, |connection| {
+            connection.send(&[&arguments])?;
+            let mut values: Vec<Option<String>> = Vec::with_capacity(keys.len());
+            for key in keys {
+                match connection.read_item()? {
+                    Item::Line(line) if is_error(&line) => return Err(Error::Server(line)),
+                    item => values.push(optional_value(key, item)?),
+                }
+            }
+            Ok(values)
+        })
+    }
+
+    /// Set the value of a key, without a TTL.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        self._call(true, |connection| {
+            expect_ok(connection.request(&["SET", key, value])?)
+        })
+    }
+
+    /// Set the value of a key, expiring after the given TTL (rounded up to seconds by the server).
+    pub fn set_with_ttl(&mut self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+        let milliseconds: String = ttl.as_millis().to_string();
+        self._call(true, |connection| {
+            expect_ok(connection.request(&["SET", key, value, "PX", &milliseconds])?)
+        })
+    }
+
+    /// Set the values of several keys, without a TTL, as a single write.
+    pub fn mset(&mut self, entries: &[(&str, &str)]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut arguments: Vec<&str> = Vec::with_capacity(entries.len() * 2 + 1);
+        arguments.push("MSET");
+        for (key, value) in entries {
+            arguments.push(key);
+            arguments.push(value);
+        }
+        self._call(true, |connection| {
+            expect_ok(connection.request(&arguments)?)
+        })
+    }
+
+    /// Set the values of several keys, all expiring after the given TTL, with one pipelined `SET` per key.
+    /// Unlike `mset`, the keys are set one after the other.
+    pub fn mset_with_ttl(&mut self, entries: &[(&str, &str)], ttl: Duration) -> Result<()> {
+        let milliseconds: String = ttl.as_millis().to_string();
+        let commands: Vec<[&str; 5]> = entries
+            .iter()
+            .map(|&(key, value)| ["SET", key, value, "PX", &milliseconds])
+            .collect();
+        let commands: Vec<&[&str]> = commands.iter().map(|command| &command[..]).collect();
+
+        self._call(true, |connection| {
+            for item in connection.pipeline(&commands)? {
+                expect_ok(item?)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Delete a key.
+    ///
+    /// # Returns
+    /// * Whether the key had a value.
+    pub fn del(&mut self, key: &str) -> Result<bool> {
+        self._call(true, |connection| {
+            Ok(removed(connection.request(&["RM", key])?))
+        })
+    }
+
+    /// Delete several keys, with one pipelined `RM` per key.
+    ///
+    /// # Returns
+    /// * How many of the keys had a value.
+    pub fn del_many(&mut self, keys: &[&str]) -> Result<usize> {
+        let commands: Vec<[&str; 2]> = keys.iter().map(|&key| ["RM", key]).collect();
+        let commands: Vec<&[&str]> = commands.iter().map(|command| &command[..]).collect();
+
+        self._call(true, |connection| {
+            let mut count: usize = 0;
+            for item in connection.pipeline(&commands)? {
+                count += removed(item?) as usize;
+            }
+            Ok(count)
+        })
+    }
+
+    /// Send any command of the text protocol, given as its tokens (e.g. `["INCRBY", "counter", "5"]`), and
+    /// read its response.
+    ///
+    /// # Returns
+    /// * The lines of the response, the values being decoded (and flagged as `STALE <value>` if their soft
+    ///   TTL has elapsed): one line for most commands, one per key for `MGET`, or the number of the values
+    ///   followed by every value for the commands answering several (e.g. `LRANGE`, `SMEMBERS`, `XRANGE` or
+    ///   `LPOP` with a count).
+    ///
+    /// # Errors
+    /// * `Error::Io` for a `HELLO` command switching the connection out of the bulk mode.
+    /// * `Error::Server` if the server answered with an error.
+    pub fn execute(&mut self, arguments: &[&str]) -> Result<Vec<String>> {
+        let items: Vec<Item> = self._execute_items(arguments)?;
+        Ok(items.into_iter().map(item_text).collect())
+    }
+
+    /// Send any command of the text protocol, as `execute` does, and read its response as a `Reply`, which
+    /// tells the values (as they were set) from the other lines and from the keys without a value.
+    ///
+    /// # Errors
+    /// * `Error::Io` for a `HELLO` command switching the connection out of the bulk mode.
+    /// * `Error::Server` if the server answered with an error.
+    pub fn execute_reply(&mut self, arguments: &[&str]) -> Result<Reply> {
+        let Some(name) = arguments.first().map(|name| name.to_ascii_uppercase()) else {
+            return Ok(Reply::Array(Vec::new()));
+        };
+        let mut items: Vec<Item> = self._execute_items(arguments)?;
+        let reply: Reply = match name.as_str() {
+            "MGET" => Reply::Array(items.into_iter().map(Reply::from).collect()),
+            "XRANGE" => {
+                let mut entries: Vec<Reply> = Vec::new();
+                let mut items = items.into_iter().skip(1);
+                while let (Some(id), Some(value)) = (items.next(), items.next()) {
+                    entries.push(Reply::Array(vec![Reply::from(id), Reply::from(value)]));
+                }
+                Reply::Array(entries)
+            }
+            _ if items.len() > 1 || is_list(&name, arguments) => {
+                Reply::Array(items.into_iter().skip(1).map(Reply::from).collect())
+            }
+            _ => match items.pop() {
+                Some(item) => Reply::from(item),
+                None => Reply::Array(Vec::new()),
+            },
+        };
+        Ok(reply)
+    }
+
+    /// Send any command, and read all the items of its response (see `execute`). The session commands are
+    /// remembered once they succeeded, to be sent again on a new connection.
+    fn _execute_items(&mut self, arguments: &[&str]) -> Result<Vec<Item>> {
+        let Some(name) = arguments.first().map(|name| name.to_ascii_uppercase()) else {
+            return Ok(Vec::new());
+        };
+        if name == "HELLO"
+            && !arguments.get(1).is_some_and(|mode| {
+                mode.eq_ignore_ascii_case("BULK") || mode.eq_ignore_ascii_case("STRICT")
+            })
+        {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the client only supports HELLO BULK and HELLO STRICT",
+            )));
+        }
+
+        let items: Vec<Item> = self._call(false, |connection| {
+            connection.send(&[arguments])?;
+            let first: Item = connection.read_item()?;
+            if let Item::Line(line) = &first {
+                if is_error(line) {
+                    return Err(Error::Server(line.clone()));
+                }
+            }
+            let count: usize = match &first {
+                Item::Line(line) => line.parse::<usize>().unwrap_or(0),
+                _ => 0,
+            };
+            let length: usize = match name.as_str() {
+                "MGET" => arguments.len() - 1,
+                "XRANGE" => 1 + 2 * count,
+                _ if is_list(&name, arguments) => 1 + count,
+                _ => 1,
+            };
+            let mut items: Vec<Item> = vec![first];
+            for _ in 1..length.max(1) {
+                items.push(connection.read_item()?);
+            }
+            Ok(items)
+        })?;
+
+        let is_bulk: bool = name == "HELLO" && arguments[1].eq_ignore_ascii_case("BULK");
+        if let Some(index) = SESSION_COMMANDS.iter().position(|&command| command == name) {
+            if !is_bulk {
+                self.session[index] = Some(
+                    arguments
+                        .iter()
+                        .map(|argument| argument.to_string())
+                        .collect(),
+                );
+            }
+        }
+        Ok(items)
+    }
+
+    /// Exchange a command (or a pipeline of commands) with the server, connecting again first if the
+    /// connection was dropped. A connection failing is dropped, since a response may still be pending on
+    /// it.
+    ///
+    /// # Arguments
+    /// * `idempotent` - Whether the commands can be sent again on a new connection if the server dropped
+    ///   the one they were sent on, as they have the same effect if the server had applied them.
+    /// * `exchange` - Send the commands on the connection and read their responses.
+    fn _call<T>(
+        &mut self,
+        idempotent: bool,
+        exchange: impl Fn(&mut Connection) -> Result<T>,
+    ) -> Result<T> {
+        let mut retried: bool = false;
+        loop {
+            let mut connection: Connection = match self.connection.take() {
+                Some(connection) => connection,
+                None => self._reconnect()?,
+            };
+            match exchange(&mut connection) {
+                Err(Error::Io(e)) if idempotent && !retried && is_disconnection(&e) => {
+                    retried = true
+                }
+                Err(Error::Io(e)) => return Err(Error::Io(e)),
+                result => {
+                    self.connection = Some(connection);
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Connect to the server again, as the backoff says, and send the session commands again.
+    fn _reconnect(&self) -> Result<Connection> {
+        let mut attempt: u32 = 0;
+        loop {
+            match self._open() {
+                Err(Error::Io(_)) if attempt < self.backoff.attempts => {
+                    thread::sleep(self.backoff.delay(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Open a new connection to the server, on which the session commands are sent again.
+    fn _open(&self) -> Result<Connection> {
+        let mut connection: Connection = Connection::open(&self.address, self.timeout)?;
+        for command in self.session.iter().flatten() {
+            let arguments: Vec<&str> = command.iter().map(String::as_str).collect();
+            connection.request(&arguments)?;
+        }
+        Ok(connection)
+    }
+}
+
+/// Whether the given command answers the number of its values on a line, followed by every value.
+fn is_list(name: &str, arguments: &[&str]) -> bool {
+    match name {
+        "LPOP" | "RPOP" => arguments.len() > 2,
+        _ => LIST_COMMANDS.contains(&name),
+    }
+}
+
+/// Whether an error tells that the server dropped the connection.
+fn is_disconnection(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+    )
+}
+
+/// The value of a key from the response to `GET`, or to `MGET` for that key.
+fn optional_value(key: &str, item: Item) -> Result<Option<String>> {
+    match item {
+        Item::Value(value) | Item::Stale(value) => String::from_utf8(value)
+            .map(Some)
+            .map_err(|_| Error::Protocol(format!("the value of {} is not UTF-8", key))),
+        Item::Line(line) if line == "NULL" || line == "NEGATIVE" => Ok(None),
+        Item::Line(line) => Err(Error::Protocol(line)),
+    }
+}
+
+/// Check that a write was answered with `OK`.
+fn expect_ok(item: Item) -> Result<()> {
+    match item {
+        Item::Line(line) if line == "OK" => Ok(()),
+        Item::Line(line) => Err(Error::Protocol(line)),
+        _ => Err(Error::Protocol("a value".to_string())),
+    }
+}
+
+/// Whether the response to `RM` tells that the key had a value (which it answers).
+fn removed(item: Item) -> bool {
+    !matches!(item, Item::Line(line) if line == "<NULL>")
+}
+
+/// A part of a response as a line of text, a value which is not UTF-8 being decoded lossily.
+fn item_text(item: Item) -> String {
+    match item {
+        Item::Line(line) => line,
+        Item::Value(value) => String::from_utf8_lossy(&value).into_owned(),
+        Item::Stale(value) => format!("STALE {}", String::from_utf8_lossy(&value)),
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{Backoff, Client, Error, Result};
+
+/// How long `Pool::get` waits by default for a client to be given back while they are all in use.
+const DEFAULT_CHECKOUT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A pool of clients of a server, shared between threads, so that their requests are sent on several
+/// connections at once rather than one after the other on a single one.
+///
+/// The clients are connected on demand, up to the size of the pool, and given back to the pool when the
+/// `PooledClient` borrowing them is dropped. A thread asking for a client while they are all in use waits
+/// for one to be given back.
+///
+/// ```no_run
+/// use std::{sync::Arc, thread};
+///
+/// use myrustcache_client::Pool;
+///
+/// let pool = Arc::new(Pool::new("127.0.0.1:5050", 8));
+/// let workers: Vec<_> = (0..32)
+///     .map(|index| {
+///         let pool = Arc::clone(&pool);
+///         thread::spawn(move || pool.get()?.set(&format!("key:{}", index), "value"))
+///     })
+///     .collect();
+/// for worker in workers {
+///     worker.join().unwrap()?;
+/// }
+/// # Ok::<(), myrustcache_client::Error>(())
+/// ```
+pub struct Pool {
+    address: String,            // The address of the server (host:port)
+    size: usize,                // The most clients connected at once
+    backoff: Backoff,           // How the clients connect again once their connection was dropped
+    timeout: Option<Duration>,  // How long the clients wait for a response, if not forever
+    checkout_timeout: Duration, // How long to wait for a client while they are all in use
+    state: Mutex<PoolState>,    // The clients not in use, and how many were connected
+    given_back: Condvar,        // Notified when a client is given back, or fails to connect
+}
+
+struct PoolState {
+    idle: Vec<Client>, // The clients not in use
+    open: usize,       // The number of clients connected (or connecting), in use or not
+}
+
+impl Pool {
+    /// Create a pool of at most the given number of clients of the server of the given address
+    /// (`host:port`), none of which is connected until it is needed.
+    pub fn new(address: &str, size: usize) -> Pool {
+        Pool {
+            address: address.to_string(),
+            size: size.max(1),
+            backoff: Backoff::default(),
+            timeout: None,
+            checkout_timeout: DEFAULT_CHECKOUT_TIMEOUT,
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                open: 0,
+            }),
+            given_back: Condvar::new(),
+        }
+    }
+
+    /// Connect the clients again as the given backoff says (see `Client::with_backoff`).
+    pub fn with_backoff(mut self, backoff: Backoff) -> Pool {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fail the requests whose response takes longer than the given timeout (see `Client::set_timeout`).
+    pub fn with_timeout(mut self, timeout: Duration) -> Pool {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Wait at most the given time for a client while they are all in use (30 seconds by default).
+    pub fn with_checkout_timeout(mut self, checkout_timeout: Duration) -> Pool {
+        self.checkout_timeout = checkout_timeout;
+        self
+    }
+
+    /// Borrow a client of the pool: one not in use, a new one if the pool is not full, or else the first
+    /// one given back.
+    ///
+    /// # Errors
+    /// * `Error::Io` if no client was given back within the checkout timeout (`TimedOut`), or if a new
+    ///   client cannot connect.
+    pub fn get(&self) -> Result<PooledClient<'_>> {
+        let deadline: Instant = Instant::now() + self.checkout_timeout;
+        let mut state: MutexGuard<PoolState> = self._lock();
+        loop {
+            if let Some(client) = state.idle.pop() {
+                return Ok(PooledClient {
+                    pool: self,
+                    client: Some(client),
+                });
+            }
+            if state.open < self.size {
+                state.open += 1;
+                drop(state);
+                return match self._connect() {
+                    Ok(client) => Ok(PooledClient {
+                        pool: self,
+                        client: Some(client),
+                    }),
+                    Err(e) => {
+                        self._lock().open -= 1;
+                        self.given_back.notify_one();
+                        Err(e)
+                    }
+                };
+            }
+
+            let now: Instant = Instant::now();
+            if now >= deadline {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "none of the {} clients of the pool was given back within {:?}",
+                        self.size, self.checkout_timeout
+                    ),
+                )));
+            }
+            state = self
+                .given_back
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// The number of clients connected, in use or not.
+    pub fn open(&self) -> usize {
+        self._lock().open
+    }
+
+    /// Connect a new client with the settings of the pool.
+    fn _connect(&self) -> Result<Client> {
+        let mut client: Client = Client::connect(&self.address)?.with_backoff(self.backoff);
+        client.set_timeout(self.timeout)?;
+        Ok(client)
+    }
+
+    fn _lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A client borrowed from a `Pool`, given back to it when dropped. It dereferences to the `Client`.
+pub struct PooledClient<'a> {
+    pool: &'a Pool,         // The pool the client is given back to
+    client: Option<Client>, // The client, only taken when it is given back
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("the client was given back")
+    }
+}
+
+impl DerefMut for PooledClient<'_> {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("the client was given back")
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool._lock().idle.push(client);
+            self.pool.given_back.notify_one();
+        }
+    }
+}
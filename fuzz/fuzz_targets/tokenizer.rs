@@ -0,0 +1,25 @@
+//! Feeds arbitrary messages to the command tokenizer (`cargo +nightly fuzz run tokenizer`).
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use myrustcache::protocol::{self, Tokens};
+
+fuzz_target!(|message: &[u8]| {
+    let tokens: Vec<&[u8]> = Tokens::new(message).collect();
+
+    // Tokens are non-empty and contain no whitespace
+    for token in &tokens {
+        assert!(!token.is_empty());
+        assert!(!token.iter().any(u8::is_ascii_whitespace));
+        let _ = protocol::as_key(token);
+    }
+
+    // Together, they are exactly the message without its whitespace
+    let joined: Vec<u8> = tokens.concat();
+    let expected: Vec<u8> = message
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    assert_eq!(joined, expected);
+});
@@ -0,0 +1,109 @@
+use std::{fmt, str::FromStr};
+
+use crate::utils::glob_match;
+
+/// The commands which are the same as another one, whose permission they share.
+const ALIASES: [(&str, &str); 2] = [("PUT", "SET"), ("RM", "DEL")];
+
+/// The password and permissions of a user of the server, e.g. a read-only user of the `app:` keys.
+///
+/// Parsed from `password=secret,commands=GET|STATS,keys=app:*|shared:*`: the commands the user may execute
+/// (`*` for all of them), and the glob patterns of the keys they may access (`*` for all of them), each
+/// separated by `|`. A user without `commands` (or `keys`) may not execute any command (or access any key).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AclUser {
+    pub password: String,      // The password the user authenticates with
+    pub commands: Vec<String>, // The names of the commands allowed (in upper case), or `*`
+    pub keys: Vec<String>, // The glob patterns of the keys allowed, matched against the namespaced keys
+}
+
+impl AclUser {
+    /// A user allowed every command on every key.
+    pub fn unrestricted(password: &str) -> AclUser {
+        AclUser {
+            password: password.to_string(),
+            commands: vec!["*".to_string()],
+            keys: vec!["*".to_string()],
+        }
+    }
+
+    /// Whether the user may execute every command on every key.
+    pub fn is_unrestricted(&self) -> bool {
+        self.commands.iter().any(|command| command == "*") && self.allows_all_keys()
+    }
+
+    /// Whether the user may execute the command of the given name (in any case), or one of its aliases.
+    pub fn allows_command(&self, command: &[u8]) -> bool {
+        let command: String = String::from_utf8_lossy(command).to_ascii_uppercase();
+        let canonical: &str = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == command)
+            .map_or(command.as_str(), |(_, canonical)| canonical);
+
+        self.commands.iter().any(|allowed| {
+            let allowed: &str = ALIASES
+                .iter()
+                .find(|(alias, _)| alias == allowed)
+                .map_or(allowed.as_str(), |(_, canonical)| canonical);
+            allowed == "*" || allowed == canonical
+        })
+    }
+
+    /// Whether the user may access every key (e.g. to remove them all).
+    pub fn allows_all_keys(&self) -> bool {
+        self.keys.iter().any(|pattern| pattern == "*")
+    }
+
+    /// Whether the user may access the given key (including its namespace, e.g. `sessions:x`).
+    pub fn allows_key(&self, key: &[u8]) -> bool {
+        self.keys
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), key))
+    }
+}
+
+impl FromStr for AclUser {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<AclUser, String> {
+        let mut user: AclUser = AclUser::default();
+
+        for field in text.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let Some((name, value)) = field.split_once('=') else {
+                return Err(format!("'{}' is not a name=value pair", field));
+            };
+            let list = || -> Vec<String> {
+                value
+                    .split('|')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            };
+
+            match name.trim() {
+                "password" => user.password = value.trim().to_string(),
+                "commands" => user.commands = list().iter().map(|c| c.to_uppercase()).collect(),
+                "keys" => user.keys = list(),
+                name => return Err(format!("unknown permission '{}'", name)),
+            }
+        }
+
+        if user.password.is_empty() {
+            return Err("a password is required".to_string());
+        }
+        Ok(user)
+    }
+}
+
+impl fmt::Display for AclUser {
+    /// The permissions of the user, without its password (e.g. `commands=GET|STATS,keys=app:*`).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "commands={},keys={}",
+            self.commands.join("|"),
+            self.keys.join("|")
+        )
+    }
+}
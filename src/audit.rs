@@ -0,0 +1,281 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, MutexGuard},
+};
+
+use log::info;
+
+use crate::{
+    error::{CacheError, CacheResult},
+    http::{self, json_string, KEYS_PATH},
+    resp,
+    stats::CommandProtocol,
+    utils::current_walltime,
+};
+
+/// The size an audit log file grows to before it is rotated, in bytes (100 MiB).
+pub const DEFAULT_AUDIT_LOG_MAX_SIZE: u64 = 100 * 1024 * 1024;
+
+/// The number of rotated audit log files kept besides the current one.
+pub const DEFAULT_AUDIT_LOG_MAX_FILES: usize = 5;
+
+/// The write commands of both the text protocol and RESP.
+const WRITE_COMMANDS: [&str; 39] = [
+    "SET",
+    "MSET",
+    "DEL",
+    "GETSET",
+    "GETDEL",
+    "RENAME",
+    "RENAMENX",
+    "EXPIRE",
+    "EXPIREAT",
+    "PERSIST",
+    "INCR",
+    "DECR",
+    "INCRBY",
+    "DECRBY",
+    "APPEND",
+    "SETBIT",
+    "HSET",
+    "HDEL",
+    "LPUSH",
+    "RPUSH",
+    "LPOP",
+    "RPOP",
+    "BLPOP",
+    "BRPOP",
+    "SADD",
+    "SREM",
+    "ZADD",
+    "ZINCRBY",
+    "ZREM",
+    "PFADD",
+    "PFMERGE",
+    "BF.RESERVE",
+    "BF.ADD",
+    "RATELIMIT",
+    "XADD",
+    "FLUSHALL",
+    "SWAPDB",
+    "IMPORT",
+    "EVAL",
+];
+
+/// The write commands of the text protocol only.
+const TEXT_WRITE_COMMANDS: [&str; 6] = ["PUT", "SETNULL", "RM", "UNDELETE", "FLUSH", "FLUSHNS"];
+
+/// The write commands of RESP only.
+const RESP_WRITE_COMMANDS: [&str; 2] = ["FLUSHDB", "PEXPIREAT"];
+
+/// The write commands of the memcached protocol.
+const MEMCACHED_WRITE_COMMANDS: [&str; 6] =
+    ["set", "add", "replace", "delete", "touch", "flush_all"];
+
+/// Where the entries of the audit log are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditSink {
+    /// The standard output of the server, never rotated.
+    Stdout,
+
+    /// A file, appended to and rotated by size (see `AuditLog`).
+    File(PathBuf),
+}
+
+impl FromStr for AuditSink {
+    type Err = String;
+
+    /// Parse `stdout`, or the path of a file.
+    fn from_str(text: &str) -> Result<AuditSink, String> {
+        match text {
+            "" => Err("empty audit log sink".to_string()),
+            "stdout" => Ok(AuditSink::Stdout),
+            path => Ok(AuditSink::File(PathBuf::from(path))),
+        }
+    }
+}
+
+impl Display for AuditSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditSink::Stdout => write!(f, "stdout"),
+            AuditSink::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A write command, as recorded by the audit log.
+pub(crate) struct AuditEntry<'a> {
+    pub protocol: CommandProtocol, // The protocol the command was sent over
+    pub client: &'a str,           // The address of the client
+    pub user: Option<&'a str>,     // The user the client has authenticated as, if any
+    pub command: &'a [&'a [u8]],   // The command and its arguments
+    pub failed: bool,              // Whether the command was answered with an error
+}
+
+/// The file an audit log is appended to, and its size.
+struct AuditFile {
+    file: File,
+    size: u64,
+}
+
+/// A log of the write commands of the clients, whichever protocol they were sent over, recording who
+/// modified which keys for compliance.
+///
+/// Each write command is appended as a line of JSON once executed: the time (in milliseconds since the
+/// epoch), the protocol and the address of the client, the user it has authenticated as (or `null`), the
+/// name of the command, the keys it writes, and whether it succeeded (`"outcome":"ok"` or `"error"`), e.g.
+///
+/// ```text
+/// {"timestamp":1760523448123,"protocol":"resp","client":"127.0.0.1:51234","user":"alice",
+///  "command":"SET","keys":["a"],"outcome":"ok"}
+/// ```
+///
+/// on a single line. The values are never logged.
+///
+/// A file sink is rotated once it would grow beyond `max_size` bytes: `path` is renamed to `path.1`, which
+/// is renamed to `path.2`, and so on, the oldest of the `max_files` rotated files being removed.
+pub struct AuditLog {
+    sink: AuditSink,
+    max_size: u64,    // The size a file grows to before it is rotated, in bytes
+    max_files: usize, // The number of rotated files kept
+    file: Option<Mutex<AuditFile>>, // The file appended to, unless the sink is the standard output
+}
+
+impl AuditLog {
+    /// Open (or create) the audit log, appending to an existing file.
+    ///
+    /// # Arguments
+    /// * `sink` - Where the entries are written.
+    /// * `max_size` - The size a file grows to before it is rotated, in bytes.
+    /// * `max_files` - The number of rotated files kept besides the current one.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the file cannot be opened.
+    pub fn open(sink: AuditSink, max_size: u64, max_files: usize) -> CacheResult<AuditLog> {
+        let file: Option<Mutex<AuditFile>> = match &sink {
+            AuditSink::Stdout => None,
+            AuditSink::File(path) => {
+                let file: File = OpenOptions::new().append(true).create(true).open(path)?;
+                let size: u64 = file.metadata()?.len();
+                Some(Mutex::new(AuditFile { file, size }))
+            }
+        };
+        info!("Recording the write commands to the audit log {}", sink);
+        Ok(AuditLog {
+            sink,
+            max_size,
+            max_files,
+            file,
+        })
+    }
+
+    /// Record a command executed by a client, if it is a write command.
+    pub(crate) fn record(&self, entry: &AuditEntry) -> CacheResult<()> {
+        let Some((name, keys)) = written_keys(entry.protocol, entry.command) else {
+            return Ok(());
+        };
+        let mut line: String = format!(
+            "{{\"timestamp\":{},\"protocol\":\"{}\",\"client\":{},\"user\":{},\"command\":{},\"keys\":[",
+            current_walltime(),
+            entry.protocol.as_str(),
+            json_string(entry.client),
+            entry.user.map_or("null".to_string(), json_string),
+            json_string(&name),
+        );
+        for (i, key) in keys.iter().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            line.push_str(&json_string(key));
+        }
+        line.push_str(match entry.failed {
+            true => "],\"outcome\":\"error\"}\n",
+            false => "],\"outcome\":\"ok\"}\n",
+        });
+        self._append(line.as_bytes())
+    }
+
+    fn _append(&self, line: &[u8]) -> CacheResult<()> {
+        let (Some(file), AuditSink::File(path)) = (&self.file, &self.sink) else {
+            let mut stdout: io::StdoutLock = io::stdout().lock();
+            stdout.write_all(line)?;
+            return Ok(stdout.flush()?);
+        };
+        let mut file: MutexGuard<AuditFile> = file.lock().map_err(CacheError::from)?;
+        if file.size > 0 && file.size + line.len() as u64 > self.max_size {
+            self._rotate(path)?;
+            file.file = OpenOptions::new().append(true).create(true).open(path)?;
+            file.size = 0;
+        }
+        file.file.write_all(line)?;
+        file.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shift the rotated files by one, the oldest being removed, and the current file to `path.1`.
+    fn _rotate(&self, path: &Path) -> CacheResult<()> {
+        let rotated = |index: usize| -> PathBuf {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+        if self.max_files == 0 {
+            fs::remove_file(path)?;
+            return Ok(());
+        }
+        for index in (1..self.max_files).rev() {
+            let from: PathBuf = rotated(index);
+            if from.exists() {
+                fs::rename(&from, rotated(index + 1))?;
+            }
+        }
+        fs::rename(path, rotated(1))?;
+        info!("Rotated the audit log {}", path.display());
+        Ok(())
+    }
+}
+
+/// The name of a write command and the keys it writes (lossily decoded as UTF-8), or `None` if the command
+/// does not write. The commands of the text protocol and RESP are in uppercase, the ones of memcached in
+/// lowercase, and the HTTP requests are listed as their method and path.
+fn written_keys(protocol: CommandProtocol, command: &[&[u8]]) -> Option<(String, Vec<String>)> {
+    let (name, arguments) = command.split_first()?;
+    let name: &str = std::str::from_utf8(name).ok()?;
+    let keys: Vec<&[u8]> = match protocol {
+        CommandProtocol::Text | CommandProtocol::Resp => {
+            let writes: &[&str] = match protocol {
+                CommandProtocol::Text => &TEXT_WRITE_COMMANDS,
+                _ => &RESP_WRITE_COMMANDS,
+            };
+            if !WRITE_COMMANDS.contains(&name) && !writes.contains(&name) {
+                return None;
+            }
+            match name {
+                "PUT" | "SETNULL" | "RM" | "UNDELETE" => {
+                    arguments.iter().take(1).copied().collect()
+                }
+                name => resp::command_keys(name, arguments),
+            }
+        }
+        CommandProtocol::Memcached if MEMCACHED_WRITE_COMMANDS.contains(&name) => {
+            arguments.iter().take(1).copied().collect()
+        }
+        CommandProtocol::Http if name == "PUT" || name == "DELETE" => {
+            let path: &str = std::str::from_utf8(arguments.first()?).ok()?;
+            let key: String = http::percent_decode(path.strip_prefix(KEYS_PATH)?)?;
+            return Some((name.to_string(), vec![key]));
+        }
+        CommandProtocol::Udp if name == "SET" => arguments.iter().take(1).copied().collect(),
+        _ => return None,
+    };
+    let keys: Vec<String> = keys
+        .iter()
+        .map(|key| String::from_utf8_lossy(key).into_owned())
+        .collect();
+    Some((name.to_string(), keys))
+}
@@ -0,0 +1,48 @@
+use crate::{acl::AclUser, utils::constant_time_eq};
+
+/// The name of the user authenticating with the password of the server, which may do anything.
+pub(crate) const DEFAULT_USER: &str = "default";
+
+/// Who may execute commands on the connections of the server, shared by the listeners of the protocols
+/// supporting `AUTH` (the text protocol, over TCP or WebSocket, and RESP).
+#[derive(Clone, Default)]
+pub(crate) struct Auth {
+    users: Vec<(String, AclUser)>, // The users clients may authenticate as, the default one first (if any)
+}
+
+impl Auth {
+    /// The authentication with the given password of the server (for the default user), if any, and the
+    /// given users. Anyone may execute any command if there are neither.
+    pub fn new(password: Option<String>, users: Vec<(String, AclUser)>) -> Auth {
+        let default: Option<(String, AclUser)> =
+            password.map(|password| (DEFAULT_USER.to_string(), AclUser::unrestricted(&password)));
+        Auth {
+            users: default.into_iter().chain(users).collect(),
+        }
+    }
+
+    /// Whether the clients must authenticate before any other command.
+    pub fn is_required(&self) -> bool {
+        !self.users.is_empty()
+    }
+
+    /// Find the user of the given name, if the given password is theirs.
+    ///
+    /// # Returns
+    /// * The index of the user (see `user`), or `None` if there is no such user or the password is wrong.
+    pub fn authenticate(&self, name: &[u8], password: &[u8]) -> Option<usize> {
+        self.users.iter().position(|(user, acl)| {
+            user.as_bytes() == name && constant_time_eq(acl.password.as_bytes(), password)
+        })
+    }
+
+    /// The name and permissions of the user of the given index (see `authenticate`).
+    pub fn user(&self, index: usize) -> Option<&(String, AclUser)> {
+        self.users.get(index)
+    }
+
+    /// Every user, the default one first (if any).
+    pub fn users(&self) -> &[(String, AclUser)] {
+        &self.users
+    }
+}
@@ -0,0 +1,486 @@
+use std::{
+    env, fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use myrustcache::ServerConfig;
+
+/// A load generator driving a running cache server with a configurable mix of commands, then printing the
+/// throughput and the latency percentiles, in the spirit of `redis-benchmark`.
+///
+/// The workload is configured through environment variables:
+/// * `BENCH_ADDR` - The address of the server (`127.0.0.1:5050` by default).
+/// * `BENCH_CONNECTIONS` - The number of concurrent connections (8 by default).
+/// * `BENCH_REQUESTS` - The number of commands sent over all connections (100000 by default).
+/// * `BENCH_PIPELINE` - The number of commands sent at once on a connection (1 by default).
+/// * `BENCH_KEYS` - The size of the key space (10000 by default).
+/// * `BENCH_DISTRIBUTION` - How the keys are picked: `uniform` (the default) or `zipf` (a few hot keys).
+/// * `BENCH_VALUE_SIZE` - The size of the values set, in bytes (32 by default), or a range of sizes picked
+///   uniformly for every SET (e.g. `16-4096`).
+/// * `BENCH_SET_PCT` - The percentage of SETs, the other commands being GETs (10 by default).
+/// * `BENCH_TTL` - The TTL of the values set, in seconds (none by default).
+/// * `BENCH_OUTPUT` - How the results are printed: `text` (the default) or `csv`, a row per command type
+///   (and one for all of them), to compare the runs against differently configured servers.
+///
+/// Example usage:
+/// ```shell
+/// $ BENCH_CONNECTIONS=32 BENCH_PIPELINE=16 BENCH_DISTRIBUTION=zipf cargo run --release --bin bench
+/// $ BENCH_VALUE_SIZE=16-4096 BENCH_OUTPUT=csv cargo run --release --bin bench > lru.csv
+/// ```
+fn main() {
+    let options: Options = match Options::from_env() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// The workload of a benchmark run.
+#[derive(Debug, Clone)]
+struct Options {
+    address: String,            // The address of the server
+    connections: usize,         // The number of concurrent connections
+    requests: usize,            // The number of commands sent over all connections
+    pipeline: usize,            // The number of commands sent at once on a connection
+    keys: usize,                // The size of the key space
+    distribution: Distribution, // How the keys are picked
+    value_size: ValueSize,      // The size of the values set, in bytes
+    set_pct: u64,               // The percentage of SETs
+    ttl: Option<u64>,           // The TTL of the values set, in seconds
+    output: Output,             // How the results are printed
+}
+
+impl Options {
+    fn from_env() -> Result<Options, String> {
+        let config: ServerConfig = ServerConfig::default();
+
+        let options: Options = Options {
+            address: env::var("BENCH_ADDR")
+                .unwrap_or_else(|_| format!("{}:{}", config.host, config.port)),
+            connections: env_or("BENCH_CONNECTIONS", 8)?,
+            requests: env_or("BENCH_REQUESTS", 100_000)?,
+            pipeline: env_or("BENCH_PIPELINE", 1)?,
+            keys: env_or("BENCH_KEYS", 10_000)?,
+            distribution: env_or("BENCH_DISTRIBUTION", Distribution::Uniform)?,
+            value_size: env_or("BENCH_VALUE_SIZE", ValueSize { min: 32, max: 32 })?,
+            set_pct: env_or("BENCH_SET_PCT", 10)?,
+            ttl: env_or("BENCH_TTL", 0).map(|ttl: u64| if ttl == 0 { None } else { Some(ttl) })?,
+            output: env_or("BENCH_OUTPUT", Output::Text)?,
+        };
+
+        if options.connections == 0 || options.pipeline == 0 || options.keys == 0 {
+            return Err("connections, pipeline and keys must be at least 1".to_string());
+        }
+        if options.set_pct > 100 {
+            return Err("BENCH_SET_PCT must be between 0 and 100".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// How the keys of the commands are picked from the key space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Distribution {
+    Uniform, // Every key is equally likely
+    Zipf,    // The key of rank `n` is picked with a probability proportional to `1 / n`
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Distribution, String> {
+        match text {
+            "uniform" => Ok(Distribution::Uniform),
+            "zipf" => Ok(Distribution::Zipf),
+            _ => Err(format!("unknown distribution '{}'", text)),
+        }
+    }
+}
+
+/// The sizes of the values set: every SET sets a value of a size picked uniformly between `min` and `max`
+/// (inclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ValueSize {
+    min: usize,
+    max: usize,
+}
+
+impl ValueSize {
+    fn pick(&self, rng: &mut XorShift) -> usize {
+        match self.min == self.max {
+            true => self.min,
+            false => self.min + rng.next() as usize % (self.max - self.min + 1),
+        }
+    }
+}
+
+impl fmt::Display for ValueSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.min == self.max {
+            true => write!(f, "{}", self.min),
+            false => write!(f, "{}-{}", self.min, self.max),
+        }
+    }
+}
+
+impl FromStr for ValueSize {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<ValueSize, String> {
+        let (min, max) = text.split_once('-').unwrap_or((text, text));
+        match (min.trim().parse::<usize>(), max.trim().parse::<usize>()) {
+            (Ok(min), Ok(max)) if min <= max => Ok(ValueSize { min, max }),
+            _ => Err(format!("'{}' is not a size or a range of sizes", text)),
+        }
+    }
+}
+
+/// How the results of a run are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Text, // A summary and a table of the latencies, for humans
+    Csv,  // A header and a row per command type, with the workload, for spreadsheets and scripts
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Output, String> {
+        match text {
+            "text" => Ok(Output::Text),
+            "csv" => Ok(Output::Csv),
+            _ => Err(format!("unknown output '{}'", text)),
+        }
+    }
+}
+
+/// Picks the keys of the commands according to a `Distribution`.
+struct KeyPicker {
+    keys: usize,
+    cumulative: Option<Vec<f64>>, // The cumulative probabilities of the keys by rank (Zipf only)
+}
+
+impl KeyPicker {
+    fn new(keys: usize, distribution: Distribution) -> KeyPicker {
+        let cumulative: Option<Vec<f64>> = match distribution {
+            Distribution::Uniform => None,
+            Distribution::Zipf => {
+                let weights: Vec<f64> = (1..=keys).map(|rank| 1.0 / rank as f64).collect();
+                let total: f64 = weights.iter().sum();
+
+                let mut sum: f64 = 0.0;
+                Some(
+                    weights
+                        .iter()
+                        .map(|weight| {
+                            sum += weight / total;
+                            sum
+                        })
+                        .collect(),
+                )
+            }
+        };
+
+        KeyPicker { keys, cumulative }
+    }
+
+    fn pick(&self, rng: &mut XorShift) -> usize {
+        match &self.cumulative {
+            None => rng.next() as usize % self.keys,
+            Some(cumulative) => {
+                let unit: f64 = (rng.next() >> 11) as f64 / (1u64 << 53) as f64;
+                cumulative.partition_point(|&p| p < unit).min(self.keys - 1)
+            }
+        }
+    }
+}
+
+/// The measurements of one connection.
+#[derive(Default)]
+struct Report {
+    gets: Vec<Duration>, // The latencies of the GETs
+    sets: Vec<Duration>, // The latencies of the SETs
+    errors: usize,       // The number of error responses
+}
+
+/// Fill the key space, then run the workload and print the results.
+fn run(options: Options) -> io::Result<()> {
+    if options.output == Output::Text {
+        println!(
+            "{} connections, {} requests, pipeline {}, {} keys ({:?}), {}-byte values, {}% SETs",
+            options.connections,
+            options.requests,
+            options.pipeline,
+            options.keys,
+            options.distribution,
+            options.value_size,
+            options.set_pct
+        );
+    }
+
+    // The values set are prefixes of a value of the largest size
+    let value: Arc<str> = Arc::from("x".repeat(options.value_size.max));
+    prefill(&options, &value)?;
+
+    let picker: Arc<KeyPicker> = Arc::new(KeyPicker::new(options.keys, options.distribution));
+    let started: Instant = Instant::now();
+
+    let connections: Vec<thread::JoinHandle<io::Result<Report>>> = (0..options.connections)
+        .map(|c| {
+            // Spread the requests evenly, the first connections taking the remainder
+            let requests: usize = options.requests / options.connections
+                + usize::from(c < options.requests % options.connections);
+            let options: Options = options.clone();
+            let picker: Arc<KeyPicker> = Arc::clone(&picker);
+            let value: Arc<str> = Arc::clone(&value);
+
+            thread::spawn(move || drive(&options, requests, c as u64, &picker, &value))
+        })
+        .collect();
+
+    let mut total: Report = Report::default();
+    for connection in connections {
+        let report: Report = connection
+            .join()
+            .map_err(|_| io::Error::other("a connection thread panicked"))??;
+        total.gets.extend(report.gets);
+        total.sets.extend(report.sets);
+        total.errors += report.errors;
+    }
+    let elapsed: Duration = started.elapsed();
+
+    let mut all: Vec<Duration> = total.gets.iter().chain(&total.sets).copied().collect();
+    match options.output {
+        Output::Text => {
+            println!(
+                "{} requests in {:?}: {:.0} requests/s, {} errors",
+                all.len(),
+                elapsed,
+                all.len() as f64 / elapsed.as_secs_f64(),
+                total.errors
+            );
+            println!(
+                "{:<6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                "", "count", "p50", "p99", "p99.9", "max"
+            );
+            print_latencies("GET", &mut total.gets);
+            print_latencies("SET", &mut total.sets);
+            print_latencies("all", &mut all);
+        }
+        Output::Csv => {
+            println!(
+                "command,connections,pipeline,keys,distribution,value_size,set_pct,\
+                 count,errors,seconds,requests_per_second,p50_us,p99_us,p99_9_us,max_us"
+            );
+            let errors: usize = total.errors;
+            print_csv_row("GET", &options, &mut total.gets, None, elapsed);
+            print_csv_row("SET", &options, &mut total.sets, None, elapsed);
+            print_csv_row("all", &options, &mut all, Some(errors), elapsed);
+        }
+    }
+    Ok(())
+}
+
+/// Set every key of the key space once, so that the GETs hit.
+fn prefill(options: &Options, value: &str) -> io::Result<()> {
+    let mut stream: TcpStream = connect(&options.address)?;
+    let mut rng: XorShift = XorShift(0x2545_F491_4F6C_DD1D);
+    let mut reader: BufReader<TcpStream> = BufReader::new(stream.try_clone()?);
+    let mut response: Vec<u8> = Vec::new();
+
+    for start in (0..options.keys).step_by(1000) {
+        let end: usize = (start + 1000).min(options.keys);
+        let mut batch: Vec<u8> = Vec::new();
+        for key in start..end {
+            let size: usize = options.value_size.pick(&mut rng);
+            write_set(&mut batch, key, &value[..size], options.ttl);
+        }
+        stream.write_all(&batch)?;
+
+        for _ in start..end {
+            read_response(&mut reader, &mut response)?;
+        }
+    }
+    Ok(())
+}
+
+/// Send the given number of commands on a new connection, `pipeline` at a time, measuring the latency
+/// of each of them from the moment its batch is sent until its response is received.
+fn drive(
+    options: &Options,
+    requests: usize,
+    seed: u64,
+    picker: &KeyPicker,
+    value: &str,
+) -> io::Result<Report> {
+    let mut stream: TcpStream = connect(&options.address)?;
+    stream.set_nodelay(true)?;
+    let mut reader: BufReader<TcpStream> = BufReader::new(stream.try_clone()?);
+
+    let mut rng: XorShift = XorShift(0x9E37_79B9_7F4A_7C15 ^ (seed + 1));
+    let mut report: Report = Report::default();
+    let mut batch: Vec<u8> = Vec::new();
+    let mut is_set: Vec<bool> = Vec::with_capacity(options.pipeline);
+    let mut response: Vec<u8> = Vec::new();
+
+    let mut sent: usize = 0;
+    while sent < requests {
+        let size: usize = options.pipeline.min(requests - sent);
+        batch.clear();
+        is_set.clear();
+
+        for _ in 0..size {
+            let key: usize = picker.pick(&mut rng);
+            let set: bool = rng.next() % 100 < options.set_pct;
+            if set {
+                let size: usize = options.value_size.pick(&mut rng);
+                write_set(&mut batch, key, &value[..size], options.ttl);
+            } else {
+                batch.extend_from_slice(format!("GET key:{}\n", key).as_bytes());
+            }
+            is_set.push(set);
+        }
+
+        let started: Instant = Instant::now();
+        stream.write_all(&batch)?;
+
+        for &set in &is_set {
+            read_response(&mut reader, &mut response)?;
+            let latency: Duration = started.elapsed();
+
+            if response.starts_with(b"Error") {
+                report.errors += 1;
+            }
+            match set {
+                true => report.sets.push(latency),
+                false => report.gets.push(latency),
+            }
+        }
+        sent += size;
+    }
+
+    Ok(report)
+}
+
+fn connect(address: &str) -> io::Result<TcpStream> {
+    TcpStream::connect(address).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("could not connect to server at {}: {}", address, e),
+        )
+    })
+}
+
+fn write_set(batch: &mut Vec<u8>, key: usize, value: &str, ttl: Option<u64>) {
+    match ttl {
+        Some(ttl) => {
+            batch.extend_from_slice(format!("SET key:{} {} {}\n", key, value, ttl).as_bytes())
+        }
+        None => batch.extend_from_slice(format!("SET key:{} {}\n", key, value).as_bytes()),
+    }
+}
+
+/// Read a response, which is a single line.
+fn read_response(reader: &mut BufReader<TcpStream>, response: &mut Vec<u8>) -> io::Result<()> {
+    response.clear();
+    if reader.read_until(b'\n', response)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed by server",
+        ));
+    }
+    Ok(())
+}
+
+fn print_latencies(name: &str, latencies: &mut [Duration]) {
+    if latencies.is_empty() {
+        return;
+    }
+    latencies.sort_unstable();
+
+    println!(
+        "{:<6} {:>10} {:>10?} {:>10?} {:>10?} {:>10?}",
+        name,
+        latencies.len(),
+        percentile(latencies, 50.0),
+        percentile(latencies, 99.0),
+        percentile(latencies, 99.9),
+        latencies[latencies.len() - 1]
+    );
+}
+
+/// Print a CSV row of the results of the given command type, the errors being only counted over all of
+/// them (as the responses do not tell which command failed).
+fn print_csv_row(
+    name: &str,
+    options: &Options,
+    latencies: &mut [Duration],
+    errors: Option<usize>,
+    elapsed: Duration,
+) {
+    if latencies.is_empty() {
+        return;
+    }
+    latencies.sort_unstable();
+
+    let micros = |latency: Duration| latency.as_secs_f64() * 1_000_000.0;
+    println!(
+        "{},{},{},{},{:?},{},{},{},{},{:.3},{:.0},{:.1},{:.1},{:.1},{:.1}",
+        name,
+        options.connections,
+        options.pipeline,
+        options.keys,
+        options.distribution,
+        options.value_size,
+        options.set_pct,
+        latencies.len(),
+        errors.map_or(String::new(), |errors| errors.to_string()),
+        elapsed.as_secs_f64(),
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        micros(percentile(latencies, 50.0)),
+        micros(percentile(latencies, 99.0)),
+        micros(percentile(latencies, 99.9)),
+        micros(latencies[latencies.len() - 1])
+    );
+}
+
+/// The latency below which the given percentage of the (sorted) latencies fall.
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    let index: usize = ((sorted.len() as f64 * pct / 100.0) as usize).min(sorted.len() - 1);
+    sorted[index]
+}
+
+/// Read and parse an environment variable, falling back to the default if it is not set.
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T, String> {
+    match env::var(name) {
+        Ok(text) => text
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not a valid value for {}", text, name)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// A tiny pseudo random number generator, so that the benchmark does not need extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, Read, Stdin, Write},
+    mem,
+    path::PathBuf,
+};
+
+/// The most lines kept in the history, the oldest ones being dropped beyond.
+const MAX_HISTORY: usize = 1000;
+
+/// A line editor for the interactive sessions of the CLI: the line can be edited with the arrow keys (and
+/// the usual Emacs keys, e.g. Ctrl-A, Ctrl-E, Ctrl-K or Ctrl-U), the previous lines are recalled with the
+/// up and down arrows from a history kept across sessions, and Tab completes the command names.
+///
+/// When the standard input is not a terminal (e.g. a file of commands piped to the CLI), the lines are read
+/// as they are, without a prompt nor a history.
+pub struct Editor {
+    history: Vec<String>,                 // The lines entered, the oldest first
+    history_path: Option<PathBuf>,        // The file the history is kept in, if any
+    completions: &'static [&'static str], // The words completed at the start of a line
+    terminal: bool,                       // Whether the standard input is a terminal
+}
+
+impl Editor {
+    /// Create an editor completing the given words, loading the history from the given file (if any).
+    pub fn new(completions: &'static [&'static str], history_path: Option<PathBuf>) -> Editor {
+        // SAFETY: `isatty` only inspects the given file descriptor
+        let terminal: bool = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+        let mut history: Vec<String> = Vec::new();
+        if let Some(path) = history_path.as_ref().filter(|_| terminal) {
+            if let Ok(text) = fs::read_to_string(path) {
+                history = text.lines().map(str::to_string).collect();
+            }
+            if history.len() > MAX_HISTORY {
+                history.drain(..history.len() - MAX_HISTORY);
+                let _ = fs::write(path, history.join("\n") + "\n");
+            }
+        }
+
+        Editor {
+            history,
+            history_path,
+            completions,
+            terminal,
+        }
+    }
+
+    /// Read a line, after writing the given prompt if the standard input is a terminal.
+    ///
+    /// # Returns
+    /// * The line, without its terminator, or `None` once the input is closed (EOF, or Ctrl-D on an empty
+    ///   line).
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        if !self.terminal {
+            let mut input: String = String::new();
+            return match io::stdin().lock().read_line(&mut input)? {
+                0 => Ok(None),
+                _ => Ok(Some(input.trim().to_string())),
+            };
+        }
+
+        let _raw_mode: RawMode = RawMode::enable()?;
+        let line: Option<String> = self._edit(prompt)?;
+        if let Some(line) = &line {
+            self._remember(line);
+        }
+        Ok(line)
+    }
+
+    /// Edit a line in the raw mode of the terminal, until Enter, Ctrl-C (an empty line) or Ctrl-D.
+    fn _edit(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut stdin: Stdin = io::stdin();
+        let mut line: Line = Line::default();
+        let mut recalled: usize = self.history.len(); // The line of the history shown, or the new one
+        let mut edited: Vec<char> = Vec::new(); // The new line, kept while the history is browsed
+        refresh(prompt, &line)?;
+
+        loop {
+            match read_byte(&mut stdin)? {
+                None => return Ok(None),
+                Some(b'\r' | b'\n') => {
+                    print_raw("\r\n")?;
+                    return Ok(Some(
+                        line.chars.iter().collect::<String>().trim().to_string(),
+                    ));
+                }
+                Some(3) => {
+                    // Ctrl-C: drop the line
+                    print_raw("^C\r\n")?;
+                    return Ok(Some(String::new()));
+                }
+                Some(4) if line.chars.is_empty() => {
+                    // Ctrl-D on an empty line: end the session
+                    print_raw("\r\n")?;
+                    return Ok(None);
+                }
+                Some(4) => line.delete(),
+                Some(127 | 8) => line.backspace(),
+                Some(b'\t') => self._complete(prompt, &mut line)?,
+                Some(1) => line.cursor = 0,                   // Ctrl-A
+                Some(5) => line.cursor = line.chars.len(),    // Ctrl-E
+                Some(2) => line.left(),                       // Ctrl-B
+                Some(6) => line.right(),                      // Ctrl-F
+                Some(11) => line.chars.truncate(line.cursor), // Ctrl-K
+                Some(21) => {
+                    // Ctrl-U
+                    line.chars.drain(..line.cursor);
+                    line.cursor = 0;
+                }
+                Some(12) => print_raw("\x1b[H\x1b[2J")?, // Ctrl-L
+                Some(16) => self._recall(&mut line, &mut recalled, &mut edited, true), // Ctrl-P
+                Some(14) => self._recall(&mut line, &mut recalled, &mut edited, false), // Ctrl-N
+                Some(0x1b) => match read_escape(&mut stdin)? {
+                    Some(Key::Up) => self._recall(&mut line, &mut recalled, &mut edited, true),
+                    Some(Key::Down) => self._recall(&mut line, &mut recalled, &mut edited, false),
+                    Some(Key::Left) => line.left(),
+                    Some(Key::Right) => line.right(),
+                    Some(Key::Home) => line.cursor = 0,
+                    Some(Key::End) => line.cursor = line.chars.len(),
+                    Some(Key::Delete) => line.delete(),
+                    None => {}
+                },
+                Some(byte) if byte >= 0x20 => {
+                    if let Some(c) = read_char(&mut stdin, byte)? {
+                        line.insert(c);
+                    }
+                }
+                Some(_) => {}
+            }
+            refresh(prompt, &line)?;
+        }
+    }
+
+    /// Show the previous (or next) line of the history, keeping the new line aside while it is browsed.
+    fn _recall(
+        &self,
+        line: &mut Line,
+        recalled: &mut usize,
+        edited: &mut Vec<char>,
+        previous: bool,
+    ) {
+        let target: usize = match previous {
+            true if *recalled > 0 => *recalled - 1,
+            false if *recalled < self.history.len() => *recalled + 1,
+            _ => return,
+        };
+        if *recalled == self.history.len() {
+            *edited = line.chars.clone();
+        }
+        *recalled = target;
+        line.chars = match self.history.get(target) {
+            Some(text) => text.chars().collect(),
+            None => edited.clone(),
+        };
+        line.cursor = line.chars.len();
+    }
+
+    /// Complete the word under the cursor, if it is the first one of the line (i.e. the command name): with
+    /// the only completion starting with it, or with the longest prefix of all of them. If that does not
+    /// extend the word, the completions are listed under the line.
+    fn _complete(&self, prompt: &str, line: &mut Line) -> io::Result<()> {
+        let start: usize = line.chars[..line.cursor]
+            .iter()
+            .position(|c| !c.is_whitespace())
+            .unwrap_or(line.cursor);
+        if line.chars[start..line.cursor]
+            .iter()
+            .any(|c| c.is_whitespace())
+        {
+            return Ok(());
+        }
+        let word: String = line.chars[start..line.cursor]
+            .iter()
+            .collect::<String>()
+            .to_ascii_uppercase();
+        let candidates: Vec<&str> = self
+            .completions
+            .iter()
+            .copied()
+            .filter(|candidate| candidate.starts_with(&word))
+            .collect();
+
+        let completion: String = match candidates.as_slice() {
+            [] => return Ok(()),
+            [only] => format!("{} ", only),
+            [first, others @ ..] => {
+                let length: usize = others.iter().fold(first.len(), |length, other| {
+                    first
+                        .bytes()
+                        .zip(other.bytes())
+                        .take(length)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                });
+                if length == word.len() {
+                    print_raw(&format!("\r\n{}\r\n", candidates.join("  ")))?;
+                    return refresh(prompt, line);
+                }
+                first[..length].to_string()
+            }
+        };
+        line.chars.splice(start..line.cursor, completion.chars());
+        line.cursor = start + completion.chars().count();
+        Ok(())
+    }
+
+    /// Add a line to the history, and to its file, unless it is empty, repeats the last line, or may hold
+    /// a password (`AUTH`).
+    fn _remember(&mut self, line: &str) {
+        let is_auth: bool = line
+            .split_ascii_whitespace()
+            .next()
+            .is_some_and(|name| name.eq_ignore_ascii_case("AUTH"));
+        if line.is_empty() || is_auth || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        if let Some(path) = &self.history_path {
+            let file: io::Result<File> = OpenOptions::new().create(true).append(true).open(path);
+            if let Ok(mut file) = file {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+}
+
+/// The line being edited, and the position of the cursor in it (in characters).
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+}
+
+/// The keys sent as escape sequences which the editor handles.
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+}
+
+/// The raw mode of the terminal, in which the keys are read one by one without being echoed. The original
+/// mode is restored when it is dropped.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        // SAFETY: `termios` is a plain C struct, which `tcgetattr` fills in
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw: libc::termios = original;
+        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `raw` is a valid `termios`, derived from the current one
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: `original` is the mode `tcgetattr` answered
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original) };
+    }
+}
+
+/// Redraw the prompt and the line, then move the cursor to its position.
+fn refresh(prompt: &str, line: &Line) -> io::Result<()> {
+    let text: String = line.chars.iter().collect();
+    let column: usize = prompt.chars().count() + line.cursor;
+    let mut output: String = format!("\r{}{}\x1b[K\r", prompt, text);
+    if column > 0 {
+        output.push_str(&format!("\x1b[{}C", column));
+    }
+    print_raw(&output)
+}
+
+/// Write to the terminal right away.
+fn print_raw(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()
+}
+
+/// Read a byte of the standard input, or `None` once it is closed.
+fn read_byte(stdin: &mut Stdin) -> io::Result<Option<u8>> {
+    let mut byte: [u8; 1] = [0];
+    match stdin.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Read the rest of a character whose UTF-8 encoding starts with the given byte, if it is valid.
+fn read_char(stdin: &mut Stdin, first: u8) -> io::Result<Option<char>> {
+    let length: usize = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(None),
+    };
+    let mut bytes: Vec<u8> = vec![first];
+    for _ in 1..length {
+        match read_byte(stdin)? {
+            Some(byte) => bytes.push(byte),
+            None => return Ok(None),
+        }
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|text| text.chars().next()))
+}
+
+/// Read the rest of an escape sequence (e.g. `ESC [ A` for the up arrow), once its `ESC` was read.
+fn read_escape(stdin: &mut Stdin) -> io::Result<Option<Key>> {
+    let kind: Option<u8> = read_byte(stdin)?;
+    if kind != Some(b'[') && kind != Some(b'O') {
+        return Ok(None);
+    }
+    let key: Option<Key> = match read_byte(stdin)? {
+        Some(b'A') => Some(Key::Up),
+        Some(b'B') => Some(Key::Down),
+        Some(b'C') => Some(Key::Right),
+        Some(b'D') => Some(Key::Left),
+        Some(b'H') => Some(Key::Home),
+        Some(b'F') => Some(Key::End),
+        Some(digit @ b'0'..=b'9') => {
+            // `ESC [ <number> ~`, e.g. `ESC [ 3 ~` for Delete
+            let mut number: u32 = (digit - b'0') as u32;
+            loop {
+                match read_byte(stdin)? {
+                    Some(digit @ b'0'..=b'9') => number = number * 10 + (digit - b'0') as u32,
+                    Some(b'~') => break,
+                    _ => return Ok(None),
+                }
+            }
+            match number {
+                1 | 7 => Some(Key::Home),
+                4 | 8 => Some(Key::End),
+                3 => Some(Key::Delete),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    Ok(key)
+}
@@ -0,0 +1,416 @@
+mod editor;
+
+use std::{
+    env,
+    io::{self, Write},
+    path::PathBuf,
+    process,
+    str::FromStr,
+};
+
+use myrustcache::{protocol::ErrorCode, ServerConfig};
+use myrustcache_client::{Client, Error, Reply};
+
+use editor::Editor;
+
+/// The environment variable naming the file the history of the interactive sessions is kept in
+/// (`~/.myrustcache_history` by default, none if empty).
+const ENV_HISTORY: &str = "MYRUSTCACHE_CLI_HISTORY";
+
+/// The environment variables naming the host and the port of the server, as for the server itself.
+const ENV_HOST: &str = "MYRUSTCACHE_HOST";
+const ENV_PORT: &str = "MYRUSTCACHE_PORT";
+
+const USAGE: &str =
+    "Usage: client [--host <host>] [--port <port>] [--output text|json|raw] [<command>...]";
+
+/// The commands of the text protocol, completed by Tab at the start of a line.
+const COMMANDS: [&str; 107] = [
+    "ACL",
+    "APPEND",
+    "AUTH",
+    "BF.ADD",
+    "BF.EXISTS",
+    "BF.RESERVE",
+    "BGREWRITEAOF",
+    "BGSAVE",
+    "BITCOUNT",
+    "BLPOP",
+    "BRPOP",
+    "CLIENT",
+    "CONFIG",
+    "DBSIZE",
+    "DECR",
+    "DECRBY",
+    "DEL",
+    "DISCARD",
+    "EVAL",
+    "EXEC",
+    "EXISTS",
+    "EXIT",
+    "EXPIRE",
+    "EXPIREAT",
+    "EXPIRETIME",
+    "EXPORT",
+    "FLUSH",
+    "FLUSHALL",
+    "FLUSHNS",
+    "GET",
+    "GETBIT",
+    "GETDEL",
+    "GETSET",
+    "GETVERSION",
+    "HDEL",
+    "HELLO",
+    "HGET",
+    "HGETALL",
+    "HISTORY",
+    "HSET",
+    "IMPORT",
+    "INCR",
+    "INCRBY",
+    "INFO",
+    "KEYS",
+    "LASTSAVE",
+    "LPOP",
+    "LPUSH",
+    "LRANGE",
+    "MEMORY",
+    "MGET",
+    "MONITOR",
+    "MSET",
+    "MULTI",
+    "OBJECT",
+    "PERSIST",
+    "PFADD",
+    "PFCOUNT",
+    "PFMERGE",
+    "PUBLISH",
+    "PUT",
+    "RANDOMKEY",
+    "RATELIMIT",
+    "RENAME",
+    "RENAMENX",
+    "RM",
+    "ROLE",
+    "RPOP",
+    "RPUSH",
+    "SADD",
+    "SAMPLE",
+    "SAVE",
+    "SCAN",
+    "SDIFF",
+    "SELECT",
+    "SET",
+    "SETBIT",
+    "SETNULL",
+    "SHUTDOWN",
+    "SINTER",
+    "SISMEMBER",
+    "SLOWLOG",
+    "SMEMBERS",
+    "SREM",
+    "STATS",
+    "STRLEN",
+    "SUBSCRIBE",
+    "SUNION",
+    "SWAPDB",
+    "TOUCH",
+    "TTL",
+    "TTLSTATS",
+    "TYPE",
+    "UNDELETE",
+    "UNSUBSCRIBE",
+    "UNWATCH",
+    "USE",
+    "WATCH",
+    "XADD",
+    "XLEN",
+    "XRANGE",
+    "XREAD",
+    "ZADD",
+    "ZINCRBY",
+    "ZRANGE",
+    "ZRANGEBYSCORE",
+    "ZREM",
+];
+
+/// A basic CLI (Command Line Interface) for interacting with the cache server, over the `Client` of the
+/// `myrustcache-client` library.
+///
+/// Sample usage:
+/// ```shell
+/// $ cargo run --bin client
+///
+/// GET x
+/// NULL
+///
+/// SET x hello
+/// OK
+///
+/// GET x
+/// hello
+///
+/// SET y world 10
+/// OK
+///
+/// GET y
+/// world
+///
+/// # Wait for at least 10 seconds
+/// GET y
+/// NULL
+/// ```
+///
+/// A command given on the command line is sent on its own, e.g. for shell scripts, and a file of commands
+/// can be piped to the standard input, one response being printed per command:
+/// ```shell
+/// $ client SET greeting "hello world" 60 && client GET greeting
+/// OK
+/// hello world
+///
+/// $ client --host 10.0.0.5 --output json MGET greeting missing < commands.txt
+/// ["hello world",null]
+/// ```
+/// The exit code is 0 if every command succeeded, 1 if any failed, or 2 for invalid options.
+///
+/// Options (given before the command, if any):
+/// * `--host` - The host of the server (`MYRUSTCACHE_HOST`, or else `127.0.0.1`).
+/// * `--port` - The port of the text protocol of the server (`MYRUSTCACHE_PORT`, or else `5050`).
+/// * `--output` - How the responses are printed: `text` (the default), `json` or `raw` (see `Output`).
+fn main() {
+    let options: Options = match Options::from_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&options) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Options {
+    address: String,      // The address of the server
+    output: Output,       // How the responses are printed
+    command: Vec<String>, // The command to send on its own, if any
+}
+
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let config: ServerConfig = ServerConfig::default();
+        let mut args = env::args().skip(1);
+
+        let mut host: String = env::var(ENV_HOST).unwrap_or(config.host);
+        let mut port: String = env::var(ENV_PORT).unwrap_or_else(|_| config.port.to_string());
+        let mut output: Output = Output::Text;
+        let mut command: Vec<String> = Vec::new();
+
+        while let Some(arg) = args.next() {
+            if arg == "--help" {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            if !arg.starts_with("--") {
+                // The command, whose arguments are taken as they are (even those starting with `--`)
+                command.push(arg);
+                command.extend(args.by_ref());
+                break;
+            }
+
+            let value: String = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", arg))?;
+            match arg.as_str() {
+                "--host" => host = value,
+                "--port" => port = value,
+                "--output" => output = value.parse()?,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+
+        let port: u16 = port
+            .parse()
+            .map_err(|_| format!("'{}' is not a port", port))?;
+        Ok(Options {
+            address: format!("{}:{}", host, port),
+            output,
+            command,
+        })
+    }
+}
+
+/// How the responses are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Text, // The lines of the responses, for humans (a missing value being `NULL`, like a stored `NULL`)
+    Json, // A JSON document per response: a string per value, `null` for none, numbers for integers
+    Raw, // The values as they were set (their bytes, not decoded), nothing for none, errors on stderr
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Output, String> {
+        match text {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            "raw" => Ok(Output::Raw),
+            _ => Err(format!("unknown output '{}' (text, json or raw)", text)),
+        }
+    }
+}
+
+/// Send the command of the options, if any, or else those of the standard input until the user types
+/// "exit" or closes it.
+///
+/// # Returns
+/// * Whether every command succeeded.
+fn run(options: &Options) -> Result<bool, myrustcache_client::Error> {
+    let mut client: Client = Client::connect(&options.address)?;
+
+    // A one-shot command, its arguments being sent as they are (whitespace included)
+    if !options.command.is_empty() {
+        let arguments: Vec<&str> = options.command.iter().map(String::as_str).collect();
+        return Ok(execute(&mut client, &arguments, options.output)?);
+    }
+
+    // Take input from the user until the standard input is closed, with line editing in a terminal
+    let mut editor: Editor = Editor::new(&COMMANDS, history_path());
+    let prompt: String = format!("{}> ", options.address);
+    let mut succeeded: bool = true;
+    while let Some(user_input) = editor.read_line(&prompt)? {
+        // Exit the loop if the user types "exit"
+        if user_input.eq_ignore_ascii_case("exit") {
+            break;
+        }
+        if user_input.is_empty() {
+            continue;
+        }
+
+        // Send the whitespace separated tokens of the user input to the server and print the response
+        let arguments: Vec<&str> = user_input.split_ascii_whitespace().collect();
+        succeeded &= execute(&mut client, &arguments, options.output)?;
+    }
+
+    Ok(succeeded)
+}
+
+/// Send a command and print its response, or its error, in the given output.
+///
+/// # Returns
+/// * Whether the command succeeded.
+fn execute(client: &mut Client, arguments: &[&str], output: Output) -> io::Result<bool> {
+    let error: Error = match output {
+        Output::Text => match client.execute(arguments) {
+            Ok(lines) => {
+                lines.iter().for_each(|line| println!("{}", line));
+                return Ok(true);
+            }
+            Err(e) => e,
+        },
+        Output::Json => match client.execute_reply(arguments) {
+            Ok(reply) => {
+                println!("{}", json_reply(&reply));
+                return Ok(true);
+            }
+            Err(e) => e,
+        },
+        Output::Raw => match client.execute_reply(arguments) {
+            Ok(reply) => {
+                let mut stdout = io::stdout().lock();
+                write_raw_reply(&mut stdout, &reply)?;
+                stdout.flush()?;
+                return Ok(true);
+            }
+            Err(e) => e,
+        },
+    };
+
+    // The client connects again on the next command if the server dropped the connection
+    let message: String = match &error {
+        Error::Server(response) => match ErrorCode::parse(response) {
+            // The errors of the strict mode (after `HELLO STRICT`) are followed by their description
+            Some(code) => format!("{} ({})", response, code.description()),
+            None => response.clone(),
+        },
+        e => format!("Error: {}", e),
+    };
+    match output {
+        Output::Text => println!("{}", message),
+        Output::Json => println!("{{\"error\":{}}}", json_string(&message)),
+        Output::Raw => eprintln!("{}", message),
+    }
+    Ok(false)
+}
+
+/// A reply as a JSON document on a single line.
+fn json_reply(reply: &Reply) -> String {
+    match reply {
+        Reply::Nil => "null".to_string(),
+        Reply::Value(value) => json_string(&String::from_utf8_lossy(value)),
+        Reply::Stale(value) => format!(
+            "{{\"stale\":{}}}",
+            json_string(&String::from_utf8_lossy(value))
+        ),
+        Reply::Line(line) if line.parse::<i64>().is_ok() => line.clone(),
+        Reply::Line(line) => json_string(line),
+        Reply::Array(items) => {
+            let items: Vec<String> = items.iter().map(json_reply).collect();
+            format!("[{}]", items.join(","))
+        }
+    }
+}
+
+/// Write a reply as it is: a value (stale or not) or another line on a line, nothing on the line for no
+/// value, and every item of an array on a line of its own.
+fn write_raw_reply(output: &mut impl Write, reply: &Reply) -> io::Result<()> {
+    match reply {
+        Reply::Nil => output.write_all(b"\n"),
+        Reply::Value(value) | Reply::Stale(value) => {
+            output.write_all(value)?;
+            output.write_all(b"\n")
+        }
+        Reply::Line(line) => writeln!(output, "{}", line),
+        Reply::Array(items) => items
+            .iter()
+            .try_for_each(|item| write_raw_reply(output, item)),
+    }
+}
+
+/// Quote the given text as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted: String = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The file the history of the interactive sessions is kept in, if any.
+fn history_path() -> Option<PathBuf> {
+    match env::var_os(ENV_HISTORY) {
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".myrustcache_history")),
+    }
+}
@@ -0,0 +1,805 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    mem,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use myrustcache::{
+    rdb::{self, ImportStats},
+    ServerConfig, ServerError,
+};
+
+/// The size of the header of the frames of the binary protocol (see the README).
+const HEADER_SIZE: usize = 24;
+
+/// The magic bytes, opcode and flag of the frames used by the loader.
+const REQUEST_MAGIC: u8 = 0xB1;
+const RESPONSE_MAGIC: u8 = 0xB2;
+const OP_SET: u8 = 0x02;
+const FLAG_TTL: u32 = 0x1;
+const STATUS_OK: u8 = 0x00;
+
+/// The largest value accepted by the server in a frame; larger values would close the connection.
+const MAX_VALUE_SIZE: usize = 1024 * 1024;
+
+/// How often the progress bar is refreshed.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+const USAGE: &str =
+    "Usage: loader [--format csv|jsonl|rdb] [--address <host:port>] [--key-column <name>] \
+[--value-column <name>] [--ttl-column <name>] [--workers <n>] [--pipeline <n>] <path>";
+
+/// A bulk loader filling a running cache server with the records of a file, much faster than a client
+/// sending one command at a time.
+///
+/// The records are sent as `SET` frames of the binary protocol (so that keys and values may hold any
+/// character), pipelined over several connections: each worker writes a whole batch of frames before
+/// reading their responses. A progress bar is shown while loading (if stderr is a terminal), followed by
+/// the number of records loaded and the throughput.
+///
+/// Supported formats (inferred from the extension of the file if `--format` is not given):
+/// * `csv` - A header row naming the columns, then one record per row. Fields may be quoted (`"a, b"`),
+///   with `""` standing for a quote.
+/// * `jsonl` - One JSON object per line. String fields are loaded unescaped; numbers, booleans and nested
+///   objects or arrays are loaded as their JSON text. `null` fields are treated as missing.
+/// * `rdb` - A Redis RDB dump, whose string keys of database 0 are loaded along with their TTLs (see
+///   `rdb::read_strings`). The column options do not apply.
+///
+/// Options:
+/// * `--address` - The address of the server (`127.0.0.1:5050` by default).
+/// * `--key-column`, `--value-column` - The columns (or fields) holding the keys and the values (`key` and
+///   `value` by default).
+/// * `--ttl-column` - The column holding the TTLs in seconds, if any. An empty TTL or `0` means none.
+/// * `--workers` - The number of connections loading in parallel (4 by default).
+/// * `--pipeline` - The number of records sent at once on a connection (256 by default).
+/// * `--help` - Print the usage and exit.
+///
+/// Example usage:
+/// ```shell
+/// $ cargo run --release --bin loader -- --ttl-column expires_in --workers 8 users.csv
+/// $ cargo run --release --bin loader -- --address 10.0.0.5:5050 dump.rdb
+/// ```
+fn main() {
+    let options: Options = match Options::from_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Options {
+    path: PathBuf,              // The file to load
+    format: Format,             // The format of the file
+    address: String,            // The address of the server
+    key_column: String,         // The column holding the keys
+    value_column: String,       // The column holding the values
+    ttl_column: Option<String>, // The column holding the TTLs (in seconds), if any
+    workers: usize,             // The number of connections loading in parallel
+    pipeline: usize,            // The number of records sent at once on a connection
+}
+
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let config: ServerConfig = ServerConfig::default();
+        let mut args = std::env::args().skip(1);
+
+        let mut path: Option<PathBuf> = None;
+        let mut format: Option<Format> = None;
+        let mut address: String = format!("{}:{}", config.host, config.port);
+        let mut key_column: String = "key".to_string();
+        let mut value_column: String = "value".to_string();
+        let mut ttl_column: Option<String> = None;
+        let mut workers: usize = 4;
+        let mut pipeline: usize = 256;
+
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            if !arg.starts_with("--") {
+                match path {
+                    None => path = Some(PathBuf::from(arg)),
+                    Some(_) => return Err(format!("unexpected argument '{}'", arg)),
+                }
+                continue;
+            }
+
+            let value: String = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", arg))?;
+            match arg.as_str() {
+                "--format" => format = Some(value.parse()?),
+                "--address" => address = value,
+                "--key-column" => key_column = value,
+                "--value-column" => value_column = value,
+                "--ttl-column" => ttl_column = Some(value),
+                "--workers" => workers = parse_count(&arg, &value)?,
+                "--pipeline" => pipeline = parse_count(&arg, &value)?,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+
+        let path: PathBuf = path.ok_or("missing the path of the file to load")?;
+        let format: Format = match format {
+            Some(format) => format,
+            None => Format::from_extension(&path)
+                .ok_or("cannot infer the format from the extension of the file, use --format")?,
+        };
+
+        Ok(Options {
+            path,
+            format,
+            address,
+            key_column,
+            value_column,
+            ttl_column,
+            workers,
+            pipeline,
+        })
+    }
+}
+
+/// The formats of the files that can be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,   // Comma-separated values, with a header row
+    Jsonl, // One JSON object per line
+    Rdb,   // A Redis RDB dump
+}
+
+impl Format {
+    fn from_extension(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(Format::Csv),
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            "rdb" => Some(Format::Rdb),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Format, String> {
+        match text {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            "rdb" => Ok(Format::Rdb),
+            _ => Err(format!("unknown format '{}'", text)),
+        }
+    }
+}
+
+/// A key to set on the server.
+struct Record {
+    key: String,
+    value: Vec<u8>,
+    ttl: Option<u64>,
+}
+
+/// The progress of the loading, shared by the reader, the workers and the progress bar.
+struct Progress {
+    total_bytes: u64,                   // The size of the file
+    read_bytes: AtomicU64,              // The bytes of the file read so far
+    loaded: AtomicUsize,                // The records set on the server
+    failed: AtomicUsize, // The records rejected (by the server or for being too large)
+    first_error: Mutex<Option<String>>, // The reason of the first rejection, reported at the end
+}
+
+impl Progress {
+    fn fail(&self, reason: String) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut first_error) = self.first_error.lock() {
+            first_error.get_or_insert(reason);
+        }
+    }
+}
+
+/// Counts the bytes read from the file, for the progress bar.
+struct CountingReader<R: Read> {
+    inner: R,
+    progress: Arc<Progress>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let count: usize = self.inner.read(buffer)?;
+        self.progress
+            .read_bytes
+            .fetch_add(count as u64, Ordering::Relaxed);
+        Ok(count)
+    }
+}
+
+/// Connect the workers, then read the file and hand its records to them in batches.
+fn run(options: Options) -> io::Result<()> {
+    let file: File = File::open(&options.path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("could not open {}: {}", options.path.display(), e),
+        )
+    })?;
+    let progress: Arc<Progress> = Arc::new(Progress {
+        total_bytes: file.metadata()?.len(),
+        read_bytes: AtomicU64::new(0),
+        loaded: AtomicUsize::new(0),
+        failed: AtomicUsize::new(0),
+        first_error: Mutex::new(None),
+    });
+
+    // Connect every worker before reading anything, so that an unreachable server fails fast
+    let (sender, receiver) = mpsc::sync_channel::<Vec<Record>>(options.workers * 2);
+    let receiver: Arc<Mutex<Receiver<Vec<Record>>>> = Arc::new(Mutex::new(receiver));
+    let mut workers: Vec<thread::JoinHandle<io::Result<()>>> = Vec::new();
+    for _ in 0..options.workers {
+        let stream: TcpStream = connect(&options.address)?;
+        let receiver: Arc<Mutex<Receiver<Vec<Record>>>> = Arc::clone(&receiver);
+        let progress: Arc<Progress> = Arc::clone(&progress);
+        workers.push(thread::spawn(move || load(stream, &receiver, &progress)));
+    }
+    drop(receiver);
+
+    let started: Instant = Instant::now();
+    let done: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let bar: Option<thread::JoinHandle<()>> = io::stderr().is_terminal().then(|| {
+        let progress: Arc<Progress> = Arc::clone(&progress);
+        let done: Arc<AtomicBool> = Arc::clone(&done);
+        thread::spawn(move || show_progress(&progress, &done, started))
+    });
+
+    let reader: BufReader<CountingReader<File>> = BufReader::new(CountingReader {
+        inner: file,
+        progress: Arc::clone(&progress),
+    });
+    let mut batcher: Batcher = Batcher {
+        sender,
+        batch: Vec::with_capacity(options.pipeline),
+        size: options.pipeline,
+    };
+    let result: io::Result<Option<ImportStats>> = match options.format {
+        Format::Csv => read_csv(reader, &options, &mut batcher).map(|_| None),
+        Format::Jsonl => read_jsonl(reader, &options, &mut batcher).map(|_| None),
+        Format::Rdb => rdb::read_strings(reader, |key, value, ttl| {
+            Ok(batcher.push(Record { key, value, ttl })?)
+        })
+        .map(Some)
+        .map_err(|e| match e {
+            ServerError::Io(e) => e,
+            e => io::Error::other(e.to_string()),
+        }),
+    };
+    let flushed: io::Result<()> = batcher.finish();
+
+    // Let the workers drain the batches sent before reporting any error of the reader
+    let mut outcome: io::Result<()> = Ok(());
+    for worker in workers {
+        let loaded: io::Result<()> = worker
+            .join()
+            .map_err(|_| io::Error::other("a worker thread panicked"))?;
+        outcome = outcome.and(loaded);
+    }
+    done.store(true, Ordering::Relaxed);
+    if let Some(bar) = bar {
+        let _ = bar.join();
+    }
+    outcome?;
+    let stats: Option<ImportStats> = result?;
+    flushed?;
+
+    let elapsed: Duration = started.elapsed();
+    let loaded: usize = progress.loaded.load(Ordering::Relaxed);
+    println!(
+        "Loaded {} records in {:?} ({:.0} records/s), {} failed",
+        loaded,
+        elapsed,
+        loaded as f64 / elapsed.as_secs_f64(),
+        progress.failed.load(Ordering::Relaxed)
+    );
+    if let Some(stats) = stats {
+        println!(
+            "{} keys of the dump had expired, {} were not strings of database 0",
+            stats.expired, stats.skipped
+        );
+    }
+    if let Some(reason) = progress.first_error.lock().ok().and_then(|e| e.clone()) {
+        println!("First failure: {}", reason);
+    }
+    Ok(())
+}
+
+/// Groups the records into batches of `size`, handed to the first idle worker.
+struct Batcher {
+    sender: SyncSender<Vec<Record>>,
+    batch: Vec<Record>,
+    size: usize,
+}
+
+impl Batcher {
+    fn push(&mut self, record: Record) -> io::Result<()> {
+        self.batch.push(record);
+        if self.batch.len() < self.size {
+            return Ok(());
+        }
+        let batch: Vec<Record> = mem::replace(&mut self.batch, Vec::with_capacity(self.size));
+        self.sender
+            .send(batch)
+            .map_err(|_| io::Error::other("every worker has stopped"))
+    }
+
+    /// Send the last (partial) batch, and close the channel for the workers to stop once it is drained.
+    fn finish(self) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        self.sender
+            .send(self.batch)
+            .map_err(|_| io::Error::other("every worker has stopped"))
+    }
+}
+
+/// Read a CSV file, whose first row names the columns.
+fn read_csv<R: BufRead>(reader: R, options: &Options, batcher: &mut Batcher) -> io::Result<()> {
+    let mut csv: CsvReader<R> = CsvReader { reader, line: 0 };
+    let Some(header) = csv.next_record()? else {
+        return Ok(());
+    };
+
+    let column = |name: &str| -> io::Result<usize> {
+        header
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| invalid(1, format!("there is no column named '{}'", name)))
+    };
+    let key_column: usize = column(&options.key_column)?;
+    let value_column: usize = column(&options.value_column)?;
+    let ttl_column: Option<usize> = options.ttl_column.as_deref().map(column).transpose()?;
+
+    while let Some(mut fields) = csv.next_record()? {
+        if fields.len() == 1 && fields[0].is_empty() {
+            continue; // A blank line
+        }
+        if fields.len() != header.len() {
+            let message: String =
+                format!("expected {} fields, found {}", header.len(), fields.len());
+            return Err(invalid(csv.line, message));
+        }
+
+        let ttl: Option<u64> = match ttl_column {
+            Some(column) => parse_ttl(&fields[column]).map_err(|e| invalid(csv.line, e))?,
+            None => None,
+        };
+        batcher.push(Record {
+            key: mem::take(&mut fields[key_column]),
+            value: mem::take(&mut fields[value_column]).into_bytes(),
+            ttl,
+        })?;
+    }
+    Ok(())
+}
+
+/// Reads the records of a CSV file (RFC 4180), where quoted fields may span several lines.
+struct CsvReader<R: BufRead> {
+    reader: R,
+    line: usize, // The number of the last line read
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn next_record(&mut self) -> io::Result<Option<Vec<String>>> {
+        let mut line: String = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+
+        let mut fields: Vec<String> = Vec::new();
+        let mut field: String = String::new();
+        let mut quoted: bool = false;
+        loop {
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (quoted, c) {
+                    (true, '"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    (true, '"') => quoted = false,
+                    (true, c) => field.push(c),
+                    (false, '"') if field.is_empty() => quoted = true,
+                    (false, ',') => fields.push(mem::take(&mut field)),
+                    (false, '\r' | '\n') => {}
+                    (false, c) => field.push(c),
+                }
+            }
+            if !quoted {
+                break;
+            }
+
+            // The line break belongs to the quoted field, which goes on with the next line
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(invalid(self.line, "unterminated quoted field".to_string()));
+            }
+            self.line += 1;
+        }
+
+        fields.push(field);
+        Ok(Some(fields))
+    }
+}
+
+/// Read a file of JSON objects, one per line.
+fn read_jsonl<R: BufRead>(reader: R, options: &Options, batcher: &mut Batcher) -> io::Result<()> {
+    for (index, line) in reader.lines().enumerate() {
+        let line: String = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields: HashMap<String, String> =
+            JsonParser::parse_object(&line).map_err(|e| invalid(index + 1, e))?;
+        let mut field = |name: &str| -> io::Result<String> {
+            fields
+                .remove(name)
+                .ok_or_else(|| invalid(index + 1, format!("missing field '{}'", name)))
+        };
+
+        let key: String = field(&options.key_column)?;
+        let value: String = field(&options.value_column)?;
+        let ttl: Option<u64> = match &options.ttl_column {
+            Some(name) => match fields.get(name) {
+                Some(ttl) => parse_ttl(ttl).map_err(|e| invalid(index + 1, e))?,
+                None => None,
+            },
+            None => None,
+        };
+        batcher.push(Record {
+            key,
+            value: value.into_bytes(),
+            ttl,
+        })?;
+    }
+    Ok(())
+}
+
+/// A minimal parser of flat JSON objects, the fields of which are returned as text.
+struct JsonParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl JsonParser<'_> {
+    fn parse_object(text: &str) -> Result<HashMap<String, String>, String> {
+        let mut parser: JsonParser = JsonParser { text, position: 0 };
+        let mut fields: HashMap<String, String> = HashMap::new();
+
+        parser.expect(b'{')?;
+        if !parser.eat(b'}') {
+            loop {
+                let name: String = parser.string()?;
+                parser.expect(b':')?;
+                if let Some(value) = parser.value()? {
+                    fields.insert(name, value);
+                }
+                if parser.eat(b'}') {
+                    break;
+                }
+                parser.expect(b',')?;
+            }
+        }
+
+        parser.skip_whitespace();
+        if parser.position != text.len() {
+            return Err("unexpected characters after the object".to_string());
+        }
+        Ok(fields)
+    }
+
+    /// Parse a value: strings are unescaped, `null` is `None`, and anything else is kept as JSON text.
+    fn value(&mut self) -> Result<Option<String>, String> {
+        self.skip_whitespace();
+        let start: usize = self.position;
+        match self.peek() {
+            Some(b'"') => self.string().map(Some),
+            Some(b'{' | b'[') => {
+                self.skip_nested()?;
+                Ok(Some(self.text[start..self.position].to_string()))
+            }
+            _ => {
+                while let Some(byte) = self.peek() {
+                    if matches!(byte, b',' | b'}' | b']' | b' ' | b'\t' | b'\r' | b'\n') {
+                        break;
+                    }
+                    self.position += 1;
+                }
+                match &self.text[start..self.position] {
+                    "" => Err(format!("expected a value at column {}", start + 1)),
+                    "null" => Ok(None),
+                    literal => Ok(Some(literal.to_string())),
+                }
+            }
+        }
+    }
+
+    fn skip_nested(&mut self) -> Result<(), String> {
+        let mut depth: usize = 0;
+        loop {
+            match self.peek() {
+                None => return Err("unterminated object or array".to_string()),
+                Some(b'"') => {
+                    self.string()?;
+                    continue;
+                }
+                Some(b'{' | b'[') => depth += 1,
+                Some(b'}' | b']') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.position += 1;
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+            self.position += 1;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut string: String = String::new();
+        loop {
+            let Some(c) = self.text[self.position..].chars().next() else {
+                return Err("unterminated string".to_string());
+            };
+            self.position += c.len_utf8();
+
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escape: Option<u8> = self.peek();
+                    self.position += 1;
+                    string.push(match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err(format!("invalid escape at column {}", self.position)),
+                    });
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    /// Parse the digits of a `\u` escape, combining the surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high: u32 = self.hex4()?;
+        let code: u32 = if (0xD800..0xDC00).contains(&high) {
+            if !self.text[self.position..].starts_with("\\u") {
+                return Err("unpaired surrogate in a \\u escape".to_string());
+            }
+            self.position += 2;
+            let low: u32 = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err("unpaired surrogate in a \\u escape".to_string());
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| "invalid \\u escape".to_string())
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits: &str = self
+            .text
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| "invalid \\u escape".to_string())?;
+        self.position += 4;
+        u32::from_str_radix(digits, 16).map_err(|e| e.to_string())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r' | b'\n')) {
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        match self.eat(byte) {
+            true => Ok(()),
+            false => Err(format!(
+                "expected '{}' at column {}",
+                byte as char,
+                self.position + 1
+            )),
+        }
+    }
+}
+
+/// Set the records of the batches on the server until there are no more batches.
+fn load(
+    mut stream: TcpStream,
+    batches: &Mutex<Receiver<Vec<Record>>>,
+    progress: &Progress,
+) -> io::Result<()> {
+    let mut reader: BufReader<TcpStream> = BufReader::new(stream.try_clone()?);
+    stream.write_all(b"HELLO BINARY\n")?;
+    let mut hello: String = String::new();
+    reader.read_line(&mut hello)?;
+    if hello != "OK BINARY\n" {
+        return Err(io::Error::other(format!(
+            "the server does not support the binary protocol: {}",
+            hello.trim_end()
+        )));
+    }
+
+    let mut frames: Vec<u8> = Vec::new();
+    let mut header: [u8; HEADER_SIZE] = [0; HEADER_SIZE];
+    let mut message: Vec<u8> = Vec::new();
+    loop {
+        // The lock is only held while waiting for a batch, not while loading it
+        let batch: Vec<Record> = match batches.lock().map(|batches| batches.recv()) {
+            Ok(Ok(batch)) => batch,
+            Ok(Err(_)) => return Ok(()),
+            Err(_) => return Err(io::Error::other("a worker thread panicked")),
+        };
+
+        frames.clear();
+        let mut sent: usize = 0;
+        for record in &batch {
+            if record.key.len() > usize::from(u16::MAX) || record.value.len() > MAX_VALUE_SIZE {
+                progress.fail(format!("the key or value of '{}' is too large", record.key));
+                continue;
+            }
+            // The ids start at 1, since the server rejects the frames without one
+            write_set(&mut frames, sent as u32 + 1, record);
+            sent += 1;
+        }
+        stream.write_all(&frames)?;
+
+        for _ in 0..sent {
+            reader.read_exact(&mut header)?;
+            if header[0] != RESPONSE_MAGIC {
+                return Err(io::Error::other("invalid response from the server"));
+            }
+            let length: u32 = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+            message.resize(length as usize, 0);
+            reader.read_exact(&mut message)?;
+
+            match header[1] {
+                STATUS_OK => {
+                    progress.loaded.fetch_add(1, Ordering::Relaxed);
+                }
+                status => progress.fail(format!(
+                    "status {}: {}",
+                    status,
+                    String::from_utf8_lossy(&message)
+                )),
+            }
+        }
+    }
+}
+
+/// Encode the `SET` frame of a record.
+fn write_set(frames: &mut Vec<u8>, id: u32, record: &Record) {
+    let flags: u32 = if record.ttl.is_some() { FLAG_TTL } else { 0 };
+    let ttl: u32 = record
+        .ttl
+        .map_or(0, |ttl| ttl.min(u64::from(u32::MAX)) as u32);
+
+    frames.push(REQUEST_MAGIC);
+    frames.push(OP_SET);
+    frames.extend_from_slice(&(record.key.len() as u16).to_be_bytes());
+    frames.extend_from_slice(&flags.to_be_bytes());
+    frames.extend_from_slice(&id.to_be_bytes());
+    frames.extend_from_slice(&ttl.to_be_bytes());
+    frames.extend_from_slice(&0u32.to_be_bytes());
+    frames.extend_from_slice(&(record.value.len() as u32).to_be_bytes());
+    frames.extend_from_slice(record.key.as_bytes());
+    frames.extend_from_slice(&record.value);
+}
+
+/// Redraw the progress bar until the loading is done.
+fn show_progress(progress: &Progress, done: &AtomicBool, started: Instant) {
+    const WIDTH: usize = 30;
+
+    loop {
+        let finished: bool = done.load(Ordering::Relaxed);
+        let read: u64 = progress.read_bytes.load(Ordering::Relaxed);
+        let ratio: f64 = match progress.total_bytes {
+            0 => 1.0,
+            total => (read as f64 / total as f64).min(1.0),
+        };
+        let filled: usize = (ratio * WIDTH as f64) as usize;
+        let loaded: usize = progress.loaded.load(Ordering::Relaxed);
+
+        eprint!(
+            "\r[{}{}] {:>3.0}% {} records ({:.0}/s)",
+            "#".repeat(filled),
+            "-".repeat(WIDTH - filled),
+            ratio * 100.0,
+            loaded,
+            loaded as f64 / started.elapsed().as_secs_f64().max(0.001)
+        );
+        if finished {
+            eprintln!();
+            return;
+        }
+        thread::sleep(PROGRESS_INTERVAL);
+    }
+}
+
+fn connect(address: &str) -> io::Result<TcpStream> {
+    TcpStream::connect(address).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("could not connect to server at {}: {}", address, e),
+        )
+    })
+}
+
+/// Parse a TTL in seconds, where an empty TTL or `0` means none.
+fn parse_ttl(text: &str) -> Result<Option<u64>, String> {
+    match text.trim() {
+        "" | "0" => Ok(None),
+        ttl => ttl
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid TTL '{}'", text)),
+    }
+}
+
+fn parse_count(option: &str, text: &str) -> Result<usize, String> {
+    match text.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("{} must be a positive number", option)),
+    }
+}
+
+fn invalid(line: usize, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use myrustcache::{CacheServer, ConfigError, ServerConfig};
+
+/// The main entry point for the cache server.
+///
+/// The server is configured through `MYRUSTCACHE_*` environment variables, or the command line flags named
+/// after them, which take precedence (e.g. `--port 6060` for `MYRUSTCACHE_PORT`, see
+/// `ServerConfig::from_env_and_args`). If the configuration is invalid, all problems are reported at once
+/// and the process exits with a non-zero status.
+///
+/// With `--import-rdb <path>`, the string keys of a Redis RDB dump are imported into the cache before the
+/// server starts listening. With `--preload <path>` (`MYRUSTCACHE_PRELOAD`), the key/value/TTL triples of a
+/// seed file (JSON Lines, or CSV) fill the cache before it starts listening too.
+///
+/// On `SIGTERM` or `SIGINT` (Ctrl-C), the server stops accepting connections, gives the open ones
+/// `MYRUSTCACHE_SHUTDOWN_TIMEOUT_SECONDS` to answer the requests in flight, flushes the cache and exits.
+///
+/// Example usage:
+/// ```shell
+/// $ RUST_LOG=debug MYRUSTCACHE_PORT=6060 cargo run --bin server
+/// $ cargo run --bin server -- --host 0.0.0.0 --port 6060 --max-connections 2048 --log-level info
+/// $ cargo run --bin server -- --import-rdb dump.rdb
+/// $ cargo run --bin server -- --preload seed.csv
+/// ```
+fn main() {
+    let (rdb_path, config_args): (Option<PathBuf>, Vec<String>) = parse_arguments();
+
+    // Load and validate the configuration before touching the network
+    let config: ServerConfig = match ServerConfig::from_env_and_args(config_args) {
+        Ok(config) => config,
+        Err(problems) => exit_with_config_errors(&problems),
+    };
+
+    // Initialize the logger, with the configured filter over `RUST_LOG` (changed with `CONFIG SET log_level`)
+    myrustcache::logging::init(config.log_level.as_deref());
+
+    // Start the cache server on the configured address, once the RDB dump (if any) is imported, until
+    // SIGTERM or SIGINT shuts it down
+    let result = CacheServer::from_config(&config).and_then(|server| {
+        if let Some(path) = &rdb_path {
+            server.import_rdb(path)?;
+        }
+        server.shutdown_handle().shutdown_on_signals()?;
+        server.start()
+    });
+    if let Err(e) = result {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Parse the command line arguments, returning the path of the RDB dump to import (if any), and the
+/// arguments left for the configuration.
+fn parse_arguments() -> (Option<PathBuf>, Vec<String>) {
+    let mut args = std::env::args().skip(1);
+    let mut rdb_path: Option<PathBuf> = None;
+    let mut config_args: Vec<String> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--import-rdb" => match args.next() {
+                Some(path) => rdb_path = Some(PathBuf::from(path)),
+                None => exit_with_usage(),
+            },
+            "--help" | "-h" => exit_with_usage(),
+            _ => config_args.push(arg),
+        }
+    }
+    (rdb_path, config_args)
+}
+
+/// Print the usage of the server and exit.
+fn exit_with_usage() -> ! {
+    eprintln!("Usage: server [--import-rdb <path>] [--<setting> <value>]...");
+    eprintln!();
+    eprintln!(
+        "Every MYRUSTCACHE_<SETTING> environment variable can be given as a --<setting> flag,"
+    );
+    eprintln!("which takes precedence, e.g. --port 6060 or --worker-threads 64. Common flags:");
+    eprintln!("  --host <host>             The address to listen on (127.0.0.1)");
+    eprintln!("  --port <port>             The port of the text protocol (5050)");
+    eprintln!("  --unixsocket <path>       A Unix domain socket also serving the text protocol");
+    eprintln!("  --max-connections <n>     The connections served at once (--worker-threads)");
+    eprintln!("  --log-level <filter>      The log filter, as with RUST_LOG (e.g. info)");
+    eprintln!(
+        "  --preload <path>          A seed file of keys (JSON Lines, or CSV) loaded at startup"
+    );
+    eprintln!("  --replicaof <host:port>   The RESP port of the primary to replicate");
+    eprintln!("  --read-only               Reject the writes of the clients (replicas always do)");
+    std::process::exit(2);
+}
+
+/// Print every configuration problem with the name of the offending field and exit.
+fn exit_with_config_errors(problems: &[ConfigError]) -> ! {
+    eprintln!(
+        "Invalid configuration ({} problem(s) found):",
+        problems.len()
+    );
+    for problem in problems {
+        eprintln!("  - {}", problem);
+    }
+    std::process::exit(1);
+}
@@ -0,0 +1,111 @@
+/// The number of bytes of the header of a frame (see the "Binary Protocol" section of the README for its
+/// layout). Requests and responses are made of a header followed by the key and the value.
+pub(crate) const HEADER_SIZE: usize = 24;
+
+/// The first byte of every request, and of every response.
+const REQUEST_MAGIC: u8 = 0xB1;
+const RESPONSE_MAGIC: u8 = 0xB2;
+
+/// The operations of the requests.
+pub(crate) const OP_GET: u8 = 0x01; // Get the value of the key, locking it for `TTL` seconds on a miss if set
+pub(crate) const OP_SET: u8 = 0x02; // Set the value of the key
+pub(crate) const OP_SET_NULL: u8 = 0x03; // Cache the key as known to be absent
+pub(crate) const OP_DELETE: u8 = 0x04; // Remove the key, answering with its value
+pub(crate) const OP_NOOP: u8 = 0x05; // Do nothing (e.g. to check that the connection is alive)
+
+/// The flags of the requests.
+pub(crate) const FLAG_TTL: u32 = 0x1; // The TTL field is set
+pub(crate) const FLAG_SOFT_TTL: u32 = 0x2; // The soft TTL field is set
+
+/// The flags of the responses.
+pub(crate) const FLAG_STALE: u32 = 0x1; // The soft TTL of the value returned has elapsed
+
+/// The statuses of the responses. The value of an error response is its message.
+pub(crate) const STATUS_OK: u8 = 0x00; // Done (along with the value, if any)
+pub(crate) const STATUS_NOT_FOUND: u8 = 0x01; // The key is not cached
+pub(crate) const STATUS_NEGATIVE: u8 = 0x02; // The key is cached as known to be absent
+pub(crate) const STATUS_RECOMPUTE: u8 = 0x03; // The client is expected to set the value (stampede protection)
+pub(crate) const STATUS_INVALID: u8 = 0x04; // The request is invalid (e.g. a key that is not UTF-8)
+pub(crate) const STATUS_UNKNOWN_OPCODE: u8 = 0x05; // The opcode is unknown
+pub(crate) const STATUS_ERROR: u8 = 0x06; // The cache has failed to execute the request
+
+/// A request decoded from a frame, borrowing its key and value from the received data.
+pub(crate) struct Request<'a> {
+    pub opcode: u8,
+    pub id: u32, // Chosen by the client and echoed in the response. Zero (no id) is invalid
+    pub ttl: Option<u64>,
+    pub soft_ttl: Option<u64>,
+    pub key: &'a [u8],
+    pub value: &'a [u8],
+}
+
+/// The outcome of decoding the pending data of a connection.
+pub(crate) enum Decoded<'a> {
+    Request(Request<'a>, usize), // A request, and the number of bytes its frame takes
+    Incomplete,                  // More data is needed to decode the next frame
+    Invalid(&'static str), // The data is not a frame, so the connection cannot be resynchronized
+}
+
+/// Decode the frame at the start of the given data.
+///
+/// # Arguments
+/// * `data` - The data received and not handled yet.
+/// * `max_value_size` - The largest value accepted.
+pub(crate) fn decode(data: &[u8], max_value_size: usize) -> Decoded<'_> {
+    let Some(header) = data.first_chunk::<HEADER_SIZE>() else {
+        return match data.first() {
+            Some(&magic) if magic != REQUEST_MAGIC => Decoded::Invalid("invalid magic"),
+            _ => Decoded::Incomplete,
+        };
+    };
+    if header[0] != REQUEST_MAGIC {
+        return Decoded::Invalid("invalid magic");
+    }
+
+    let key_length: usize = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let flags: u32 = read_u32(header, 4);
+    let value_length: usize = read_u32(header, 20) as usize;
+    if value_length > max_value_size {
+        return Decoded::Invalid("value too large");
+    }
+
+    let length: usize = HEADER_SIZE + key_length + value_length;
+    if data.len() < length {
+        return Decoded::Incomplete;
+    }
+
+    let request: Request = Request {
+        opcode: header[1],
+        id: read_u32(header, 8),
+        ttl: (flags & FLAG_TTL != 0).then(|| u64::from(read_u32(header, 12))),
+        soft_ttl: (flags & FLAG_SOFT_TTL != 0).then(|| u64::from(read_u32(header, 16))),
+        key: &data[HEADER_SIZE..HEADER_SIZE + key_length],
+        value: &data[HEADER_SIZE + key_length..length],
+    };
+    Decoded::Request(request, length)
+}
+
+/// Encode the header of a response, which is followed by its value.
+pub(crate) fn response_header(
+    status: u8,
+    flags: u32,
+    id: u32,
+    value_length: usize,
+) -> [u8; HEADER_SIZE] {
+    let mut header: [u8; HEADER_SIZE] = [0; HEADER_SIZE];
+    header[0] = RESPONSE_MAGIC;
+    header[1] = status;
+    header[4..8].copy_from_slice(&flags.to_be_bytes());
+    header[8..12].copy_from_slice(&id.to_be_bytes());
+    header[20..24].copy_from_slice(&(value_length as u32).to_be_bytes());
+    header
+}
+
+fn read_u32(header: &[u8; HEADER_SIZE], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        header[offset],
+        header[offset + 1],
+        header[offset + 2],
+        header[offset + 3],
+    ])
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{cache::Bytes, error::CacheResult, utils::record_blocked};
+
+/// The outcome of popping from the first non-empty list of some keys (see
+/// `EmbeddedCache::list_pop_or_wait`).
+pub enum BlockingPop {
+    /// A value was popped right away from the list of the key at this index.
+    Popped(usize, Bytes),
+
+    /// Every list was empty, so the caller waits for a value to be pushed to one of them.
+    Waiting(ListWaiter),
+}
+
+/// The callers waiting for a value to be pushed to the lists of some keys (`BLPOP` and `BRPOP`), in the
+/// order they started waiting.
+///
+/// The values pushed to a list are handed over to its waiters right away, one each and the longest waiting
+/// first, before any other caller can pop them: a value is never popped by a waiter that started waiting
+/// after another one, nor lost when both are woken up.
+#[derive(Default)]
+pub(crate) struct ListWaiters {
+    queues: Mutex<HashMap<String, VecDeque<Arc<Waiter>>>>, // The waiters of each key, the longest waiting first
+}
+
+/// A caller waiting for a value to be pushed to the list of one of its keys.
+struct Waiter {
+    keys: Vec<String>, // The keys waited for, in the order they were given
+    left: bool,        // Whether the value is popped from the head of the list (BLPOP)
+    state: Mutex<WaitState>,
+    served: Condvar, // Notified once a value is handed over
+}
+
+enum WaitState {
+    Waiting,
+    Served(usize, Bytes), // The index of the key and the value popped from its list, until taken
+    Done,                 // The value has been taken, or the waiter has given up
+}
+
+impl ListWaiters {
+    /// Pop a value from the first non-empty list of the given keys or, if all of them are empty, queue a
+    /// waiter for the next value pushed to any of them. Both happen as a single step with respect to
+    /// `serve`, so that a value pushed in between is not missed.
+    ///
+    /// # Arguments
+    /// * `pop` - Pops a value from the head (`left`) or the tail of the list of a key, if any.
+    pub fn pop_or_wait(
+        self: &Arc<Self>,
+        keys: &[String],
+        left: bool,
+        pop: &mut dyn FnMut(&str, bool) -> CacheResult<Option<Bytes>>,
+    ) -> CacheResult<BlockingPop> {
+        let mut queues = self.queues.lock()?;
+        for (index, key) in keys.iter().enumerate() {
+            if let Some(value) = pop(key, left)? {
+                return Ok(BlockingPop::Popped(index, value));
+            }
+        }
+
+        let waiter: Arc<Waiter> = Arc::new(Waiter {
+            keys: keys.to_vec(),
+            left,
+            state: Mutex::new(WaitState::Waiting),
+            served: Condvar::new(),
+        });
+        for key in keys {
+            queues
+                .entry(key.clone())
+                .or_default()
+                .push_back(Arc::clone(&waiter));
+        }
+        Ok(BlockingPop::Waiting(ListWaiter {
+            waiters: Arc::clone(self),
+            waiter,
+        }))
+    }
+
+    /// Hand the values of the list of the given key over to its waiters, the longest waiting first, until
+    /// either runs out. Called once values are pushed to the list.
+    ///
+    /// # Arguments
+    /// * `pop` - Pops a value from the head (`left`) or the tail of the list of a key, if any.
+    pub fn serve(
+        &self,
+        key: &str,
+        pop: &mut dyn FnMut(&str, bool) -> CacheResult<Option<Bytes>>,
+    ) -> CacheResult<()> {
+        let mut queues = self.queues.lock()?;
+        while let Some(waiter) = queues.get(key).and_then(|queue| queue.front()).cloned() {
+            let Some(value) = pop(key, waiter.left)? else {
+                break;
+            };
+            let index: usize = waiter.keys.iter().position(|k| k == key).unwrap_or(0);
+            *waiter.state.lock()? = WaitState::Served(index, value);
+            waiter.served.notify_all();
+            remove(&mut queues, &waiter);
+        }
+        Ok(())
+    }
+}
+
+/// Remove a waiter from the queues of all its keys, dropping the queues left empty.
+fn remove(queues: &mut HashMap<String, VecDeque<Arc<Waiter>>>, waiter: &Arc<Waiter>) {
+    for key in &waiter.keys {
+        if let Some(queue) = queues.get_mut(key) {
+            queue.retain(|queued| !Arc::ptr_eq(queued, waiter));
+            if queue.is_empty() {
+                queues.remove(key);
+            }
+        }
+    }
+}
+
+/// A caller waiting for a value to be pushed to the list of one of its keys (see
+/// `EmbeddedCache::list_pop_or_wait`). It stops waiting once dropped, losing the value handed over to it
+/// unless taken with `wait` or `cancel`.
+pub struct ListWaiter {
+    waiters: Arc<ListWaiters>,
+    waiter: Arc<Waiter>,
+}
+
+impl ListWaiter {
+    /// Wait for a value to be handed over, for at most the given time (so that the caller may check in
+    /// between whether it should still wait, e.g. whether its client is still connected).
+    ///
+    /// # Returns
+    /// * The index of the key and the value popped from its list, or `None` if none was pushed in time (or
+    ///   the value has already been taken).
+    pub fn wait(&self, timeout: Duration) -> CacheResult<Option<(usize, Bytes)>> {
+        let deadline: Option<Instant> = Instant::now().checked_add(timeout); // None waits forever
+        let mut state = self.waiter.state.lock()?;
+        loop {
+            if !matches!(*state, WaitState::Waiting) {
+                return Ok(take(&mut state));
+            }
+            let now: Instant = Instant::now();
+            state = match deadline {
+                Some(deadline) => {
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    self.waiter.served.wait_timeout(state, deadline - now)?.0
+                }
+                None => self.waiter.served.wait(state)?,
+            };
+            record_blocked(now);
+        }
+    }
+
+    /// Stop waiting, so that the values pushed next go to the other waiters.
+    ///
+    /// # Returns
+    /// * The index of the key and the value popped from its list if one was handed over in the meantime,
+    ///   which the caller has to take care of (e.g. by pushing it back).
+    pub fn cancel(&self) -> CacheResult<Option<(usize, Bytes)>> {
+        remove(&mut *self.waiters.queues.lock()?, &self.waiter);
+        Ok(take(&mut *self.waiter.state.lock()?))
+    }
+}
+
+impl Drop for ListWaiter {
+    fn drop(&mut self) {
+        if let Ok(mut queues) = self.waiters.queues.lock() {
+            remove(&mut queues, &self.waiter);
+        }
+    }
+}
+
+/// Take the value handed over to a waiter, if any, which then waits no more.
+fn take(state: &mut WaitState) -> Option<(usize, Bytes)> {
+    match std::mem::replace(state, WaitState::Done) {
+        WaitState::Served(index, value) => Some((index, value)),
+        WaitState::Waiting | WaitState::Done => None,
+    }
+}
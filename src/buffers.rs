@@ -0,0 +1,147 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// The default limit of the memory taken by the buffers of all the client connections.
+pub const DEFAULT_BUFFER_LIMIT: usize = 256 * 1024 * 1024;
+
+/// The size of the read buffer of a new (or idle) connection.
+const MIN_READ_BUFFER: usize = 512;
+
+/// The largest read buffer of a connection. It is reached by clients filling the buffer on every read
+/// (e.g. pipelining many commands or sending large values).
+const MAX_READ_BUFFER: usize = 64 * 1024;
+
+/// The memory taken by the buffers of the client connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferStats {
+    pub used: usize, // The number of bytes currently allocated to the buffers of all connections
+    pub limit: usize, // The largest number of bytes the buffers of all connections may take
+}
+
+/// Accounts for the memory taken by the buffers of all the client connections, so that it stays bounded.
+pub(crate) struct BufferBudget {
+    used: AtomicUsize,  // The number of bytes reserved by the connections
+    limit: AtomicUsize, // The largest number of bytes the connections may reserve
+}
+
+impl BufferBudget {
+    pub fn new(limit: usize) -> BufferBudget {
+        BufferBudget {
+            used: AtomicUsize::new(0),
+            limit: AtomicUsize::new(limit),
+        }
+    }
+
+    /// Reserve the given number of bytes, unless the limit would be exceeded.
+    fn try_reserve(&self, bytes: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes)
+                    .filter(|&total| total <= self.limit.load(Ordering::Relaxed))
+            })
+            .is_ok()
+    }
+
+    fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        BufferStats {
+            used: self.used.load(Ordering::Relaxed),
+            limit: self.limit.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Change the limit, e.g. with `CONFIG SET`. The buffers already over a lower limit are kept, but
+    /// cannot grow until the connections give enough memory back.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+}
+
+/// The buffers holding the data received from a client: the one data is read into, and the one holding
+/// the commands that are not complete yet.
+///
+/// The read buffer grows while the client keeps filling it, and both shrink back once the connection is
+/// idle. Every byte they allocate is reserved from the shared `BufferBudget`.
+pub(crate) struct ConnectionBuffers {
+    read: Vec<u8>,             // The buffer data is read into (its length is its size)
+    pending: Vec<u8>,          // The data received but not handled yet, i.e. an incomplete command
+    reserved: usize,           // The number of bytes reserved from the budget
+    budget: Arc<BufferBudget>, // The budget shared by all connections
+}
+
+impl ConnectionBuffers {
+    /// Create the buffers of a new connection. The smallest read buffer is granted even when the budget
+    /// is exhausted, so that every connection can make progress.
+    pub fn new(budget: Arc<BufferBudget>) -> ConnectionBuffers {
+        budget.used.fetch_add(MIN_READ_BUFFER, Ordering::Relaxed);
+
+        ConnectionBuffers {
+            read: vec![0; MIN_READ_BUFFER],
+            pending: Vec::new(),
+            reserved: MIN_READ_BUFFER,
+            budget,
+        }
+    }
+
+    /// The buffer the next read should fill.
+    pub fn read_buffer(&mut self) -> &mut [u8] {
+        &mut self.read
+    }
+
+    /// Move the first `n` bytes of the read buffer to the pending data, growing the read buffer if they
+    /// filled it (and the budget allows it).
+    ///
+    /// # Returns
+    /// * `false` if the pending data cannot grow within the budget, in which case it is left unchanged.
+    pub fn append_read(&mut self, n: usize) -> bool {
+        let needed: usize = self.pending.len() + n;
+        if needed > self.pending.capacity() {
+            let capacity: usize = needed.max(self.pending.capacity() * 2);
+            let extra: usize = capacity - self.pending.capacity();
+
+            if !self.budget.try_reserve(extra) {
+                return false;
+            }
+            self.pending.reserve_exact(capacity - self.pending.len());
+            self.reserved += extra;
+        }
+        self.pending.extend_from_slice(&self.read[..n]);
+
+        if n == self.read.len() && self.read.len() < MAX_READ_BUFFER {
+            let size: usize = (self.read.len() * 2).min(MAX_READ_BUFFER);
+            if self.budget.try_reserve(size - self.read.len()) {
+                self.reserved += size - self.read.len();
+                self.read.resize(size, 0);
+            }
+        }
+        true
+    }
+
+    /// The data received but not handled yet.
+    pub fn pending(&mut self) -> &mut Vec<u8> {
+        &mut self.pending
+    }
+
+    /// Give the memory of an idle connection back: shrink the read buffer to its initial size and the
+    /// pending data to what it holds.
+    pub fn shrink(&mut self) {
+        self.read.truncate(MIN_READ_BUFFER);
+        self.read.shrink_to_fit();
+        self.pending.shrink_to_fit();
+
+        let reserved: usize = self.read.capacity() + self.pending.capacity();
+        self.budget.release(self.reserved.saturating_sub(reserved));
+        self.reserved = self.reserved.min(reserved);
+    }
+}
+
+impl Drop for ConnectionBuffers {
+    fn drop(&mut self) {
+        self.budget.release(self.reserved);
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::{Cache, MaxMemoryPolicy, MemoryStats, PutCondition, SweepStats};
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::current_monotime,
+};
+
+/// The number of shards of the accesses, so that concurrent reads of different keys rarely contend.
+const SHARDS: usize = 16;
+
+/// The fewest keys a shard of the accesses holds before it is pruned of the keys which have expired.
+const MIN_PRUNE_SIZE: usize = 1024;
+
+/// How a key has been read, as tracked by an `AccessTrackingCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyAccess {
+    pub last_access: u64, // When the key was last read, or first written if it never was (monotime)
+    pub reads: u64,       // The number of times the key was read
+}
+
+impl KeyAccess {
+    /// How long ago the key was last read (or first written, if it never was), in seconds.
+    pub fn idle_seconds(&self) -> u64 {
+        current_monotime().saturating_sub(self.last_access) / 1000
+    }
+}
+
+/// A shard of the accesses of an `AccessTrackingCache`.
+struct AccessShard<K> {
+    keys: HashMap<K, KeyAccess>, // The accesses by key
+    prune_at: usize,             // The number of keys beyond which the expired ones are pruned
+}
+
+/// A cache tracking when each of its keys was last read and how often, e.g. to find the keys which are
+/// written but never read (see `access`).
+///
+/// A key is tracked from its first write and forgotten once removed. The keys which expire are forgotten
+/// lazily, once their shard doubles in size. Only `get` and `get_many` count as reads, whoever calls them
+/// (e.g. a command removing members of a set reads the set first): the scans and the writes (even those
+/// reading the value first, like `update`) do not.
+pub struct AccessTrackingCache<K, V> {
+    cache: Arc<dyn Cache<K, V>>,        // Holds the cached data
+    shards: Vec<Mutex<AccessShard<K>>>, // The accesses of the keys, sharded by the hash of the key
+    started: u64, // When the tracking started (monotime), which the keys never seen written count from
+}
+
+impl<K: Hash + Eq + Clone, V> AccessTrackingCache<K, V> {
+    /// Create a new `AccessTrackingCache` tracking the reads of `cache`.
+    pub fn new(cache: Arc<dyn Cache<K, V>>) -> AccessTrackingCache<K, V> {
+        AccessTrackingCache {
+            cache,
+            shards: (0..SHARDS)
+                .map(|_| {
+                    Mutex::new(AccessShard {
+                        keys: HashMap::new(),
+                        prune_at: MIN_PRUNE_SIZE,
+                    })
+                })
+                .collect(),
+            started: current_monotime(),
+        }
+    }
+
+    /// How the given key has been read, or `None` if it has no value. A key holding a value which the
+    /// tracking has not seen written (e.g. written to the underlying cache directly) counts as written when
+    /// the tracking started.
+    pub fn access(&self, key: &K) -> CacheResult<Option<KeyAccess>> {
+        if self.cache.expiration(key)?.is_none() {
+            return Ok(None);
+        }
+        let shard: MutexGuard<AccessShard<K>> = self._shard(key)?;
+        Ok(Some(shard.keys.get(key).copied().unwrap_or(KeyAccess {
+            last_access: self.started,
+            reads: 0,
+        })))
+    }
+
+    fn _shard(&self, key: &K) -> CacheResult<MutexGuard<'_, AccessShard<K>>> {
+        let mut hasher: DefaultHasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let index: usize = hasher.finish() as usize % SHARDS;
+        self.shards[index].lock().map_err(CacheError::from)
+    }
+
+    fn _record_read(&self, key: &K) -> CacheResult<()> {
+        let now: u64 = current_monotime();
+        let mut shard: MutexGuard<AccessShard<K>> = self._shard(key)?;
+        match shard.keys.get_mut(key) {
+            Some(access) => {
+                access.last_access = now;
+                access.reads += 1;
+            }
+            None => {
+                shard.keys.insert(
+                    key.clone(),
+                    KeyAccess {
+                        last_access: now,
+                        reads: 1,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Start tracking a key written, unless it already is.
+    fn _record_write(&self, key: &K) -> CacheResult<()> {
+        let mut shard: MutexGuard<AccessShard<K>> = self._shard(key)?;
+        if shard.keys.contains_key(key) {
+            return Ok(());
+        }
+        if shard.keys.len() >= shard.prune_at {
+            self._prune(&mut shard)?;
+        }
+        shard.keys.insert(
+            key.clone(),
+            KeyAccess {
+                last_access: current_monotime(),
+                reads: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Forget the keys of a shard which no longer hold a value, e.g. because they expired.
+    fn _prune(&self, shard: &mut AccessShard<K>) -> CacheResult<()> {
+        let mut live: HashMap<K, KeyAccess> = HashMap::with_capacity(shard.keys.len());
+        for (key, access) in shard.keys.drain() {
+            if self.cache.expiration(&key)?.is_some() {
+                live.insert(key, access);
+            }
+        }
+        shard.prune_at = (live.len() * 2).max(MIN_PRUNE_SIZE);
+        shard.keys = live;
+        Ok(())
+    }
+
+    fn _forget(&self, key: &K) -> CacheResult<Option<KeyAccess>> {
+        Ok(self._shard(key)?.keys.remove(key))
+    }
+}
+
+impl<K, V> Cache<K, V> for AccessTrackingCache<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync,
+    V: Send + Sync,
+{
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        self._record_write(&key)?;
+        self.cache.put(key, value, ttl)
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        for (key, _) in &entries {
+            self._record_write(key)?;
+        }
+        self.cache.put_many(entries, ttl)
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        let tracked: K = key.clone();
+        let written: bool = self.cache.put_if(key, value, ttl, condition)?;
+        if written {
+            self._record_write(&tracked)?;
+        }
+        Ok(written)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        self._record_write(&key)?;
+        self.cache.update(key, update)
+    }
+
+    fn modify(
+        &self,
+        key: K,
+        modify: &mut dyn FnMut(Option<&mut V>) -> CacheResult<Option<V>>,
+    ) -> CacheResult<()>
+    where
+        V: Clone,
+    {
+        self._record_write(&key)?;
+        self.cache.modify(key, modify)
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let previous: Option<V> = self.cache.replace_if(key.clone(), value, ttl, accept)?;
+        self._record_write(&key)?;
+        Ok(previous)
+    }
+
+    /// The destination key takes over the accesses of the source key.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let tracked: K = to.clone();
+        let renamed: Option<bool> = self.cache.rename(from, to, if_absent)?;
+        if renamed == Some(true) {
+            let access: Option<KeyAccess> = self._forget(from)?;
+            let mut shard: MutexGuard<AccessShard<K>> = self._shard(&tracked)?;
+            match access {
+                Some(access) => shard.keys.insert(tracked, access),
+                None => shard.keys.remove(&tracked),
+            };
+        }
+        Ok(renamed)
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        let value: Option<V> = self.cache.get(key)?;
+        if value.is_some() {
+            self._record_read(key)?;
+        }
+        Ok(value)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        let values: Vec<Option<V>> = self.cache.get_many(keys)?;
+        for (key, value) in keys.iter().zip(&values) {
+            if value.is_some() {
+                self._record_read(key)?;
+            }
+        }
+        Ok(values)
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        self._forget(key)?;
+        self.cache.remove(key)
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let removed: Option<(V, Option<u64>)> = self.cache.remove_entry_if(key, accept)?;
+        self._forget(key)?;
+        Ok(removed)
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        self.cache.expire(key, ttl)
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        self.cache.touch(key)
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        self.cache.expiration(key)
+    }
+
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        self.cache.sweep_expired(max_expired)
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        self.cache.scan(visit)
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        self.cache.scan_with_expirations(visit)
+    }
+
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        self.cache.scan_from(cursor, count, visit)
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        self.cache.len()
+    }
+
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let cleared: Box<dyn Send> = self.cache.clear()?;
+        for shard in &self.shards {
+            let mut shard: MutexGuard<AccessShard<K>> = shard.lock().map_err(CacheError::from)?;
+            shard.keys.clear();
+            shard.prune_at = MIN_PRUNE_SIZE;
+        }
+        Ok(cleared)
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        self.cache.flush()
+    }
+
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        self.cache.memory_stats()
+    }
+
+    fn memory_usage(&self, key: &K) -> CacheResult<Option<usize>> {
+        self.cache.memory_usage(key)
+    }
+
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        self.cache.set_max_memory(max_bytes, policy)
+    }
+}
@@ -0,0 +1,652 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{error, warn};
+
+use super::{group_commit::GroupCommit, Bytes, Cache, SinkError, WriteSink};
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::{current_walltime, monotime_to_walltime},
+};
+
+/// How often the log is synced with `FsyncPolicy::EverySec`.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest key or value of a record accepted when replaying the log.
+const MAX_ARGUMENT_SIZE: usize = 512 * 1024 * 1024;
+
+/// When an `AppendOnlyLog` makes its records durable (i.e. safe from a power failure or an OS crash).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Make every write durable before it is applied to the cache. Concurrent writes share their syncs
+    /// (group commit), so the cost of a sync is amortized over all the writes issued meanwhile.
+    Always,
+
+    /// Sync the log once a second in the background, so that at most the last second of writes is lost on
+    /// a power failure or an OS crash.
+    #[default]
+    EverySec,
+
+    /// Leave the writes to the OS, which flushes them in the background. They survive a crash of the
+    /// process, but the latest ones may be lost on a power failure or an OS crash.
+    No,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<FsyncPolicy, String> {
+        match text {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(format!("unknown fsync policy '{}'", text)),
+        }
+    }
+}
+
+/// What the records of an append-only log are replayed into (see `AppendOnlyLog::replay`): the cache which
+/// wrote them, without the log, so that they are not appended again.
+pub trait LogReplay {
+    /// Put a value, with a TTL (in seconds) if any (`SET`).
+    fn replay_put(&self, key: String, value: Bytes, ttl: Option<u64>) -> CacheResult<()>;
+
+    /// Remove a key, whether it has a value or is known to be absent (`DEL`).
+    fn replay_remove(&self, key: String) -> CacheResult<()>;
+
+    /// Cache a key as known to be absent, with a TTL (in seconds) if any (`SETNULL`).
+    fn replay_negative(&self, key: String, ttl: Option<u64>) -> CacheResult<()>;
+
+    /// Remove every key (`FLUSHALL`).
+    fn replay_clear(&self) -> CacheResult<()>;
+
+    /// Apply a write logged as the command which made it (see `AppendOnlyLog::append_command`).
+    ///
+    /// # Returns
+    /// * Whether the command is known.
+    fn replay_command(&self, arguments: &[Vec<u8>]) -> CacheResult<bool>;
+}
+
+/// A cache without negative entries nor commands of its own replays the `SET` and `DEL` records, a `SETNULL`
+/// removing the key.
+impl LogReplay for dyn Cache<String, Bytes> {
+    fn replay_put(&self, key: String, value: Bytes, ttl: Option<u64>) -> CacheResult<()> {
+        self.put(key, value, ttl)
+    }
+
+    fn replay_remove(&self, key: String) -> CacheResult<()> {
+        self.remove(&key).map(|_| ())
+    }
+
+    fn replay_negative(&self, key: String, _ttl: Option<u64>) -> CacheResult<()> {
+        self.replay_remove(key)
+    }
+
+    fn replay_clear(&self) -> CacheResult<()> {
+        match self.clear() {
+            Ok(_) => Ok(()),
+            Err(CacheError::Unsupported(_)) => {
+                let mut keys: Vec<String> = Vec::new();
+                self.scan(&mut |key, _| keys.push(key.clone()))?;
+                keys.iter().try_for_each(|key| self.remove(key).map(|_| ()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn replay_command(&self, _arguments: &[Vec<u8>]) -> CacheResult<bool> {
+        Ok(false)
+    }
+}
+
+/// What was replayed from an append-only log (see `AppendOnlyLog::replay`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub records: usize,  // The number of records applied to the cache
+    pub truncated: bool, // Whether an incomplete record at the end of the log (e.g. a crash) was cut off
+}
+
+/// An append-only log of the writes of a cache (AOF), replayed when the server starts so that a restart
+/// loses little or no data.
+///
+/// The log is a `WriteSink`: registered with a `WriteThroughCache` in front of the storage engine, it is
+/// given every write before the engine, whichever command or protocol made it. Each write is appended as a
+/// Redis command in RESP, `SET key value` (with `PXAT` and the wall-clock expiration time in milliseconds
+/// for a value with a TTL), `DEL key` or `FLUSHALL`, so that TTLs keep counting down while the server is
+/// not running. The keys cached as known to be absent are appended as `SETNULL key` (with `PXAT` too), and
+/// the writes to the values which are too large to be appended whole every time (e.g. a push to a list) as
+/// the commands which made them (see `append_command`). A write that cannot be appended is rejected, rather
+/// than applied to the cache only.
+///
+/// As the log only grows, it is rewritten from time to time into a compacted one holding a `SET` for each
+/// live key and a `SETNULL` for each key known to be absent (see `start_rewrite`), which then replaces it.
+pub struct AppendOnlyLog {
+    path: PathBuf,                    // Where the log is written
+    state: Mutex<LogState>,           // The log, appended to by one write at a time
+    fsync: FsyncPolicy,               // When the records are made durable
+    commit: Option<Arc<GroupCommit>>, // Makes the writes durable (with `FsyncPolicy::Always` only)
+    dirty: AtomicBool, // Whether records were appended since the last sync (`EverySec`)
+    base_size: AtomicU64, // The size of the log once opened or last rewritten, which its growth is relative to
+}
+
+struct LogState {
+    file: File,               // The log file, opened for appending
+    rewrite: Option<Vec<u8>>, // The records appended since the rewrite in progress started, if any
+}
+
+impl AppendOnlyLog {
+    /// Open (or create) the log at the given path, appending the records after the existing ones, which
+    /// are to be replayed first (see `replay`). With `FsyncPolicy::EverySec`, the log is synced on a thread
+    /// of its own until dropped.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the file cannot be opened.
+    pub fn open(path: &Path, fsync: FsyncPolicy) -> CacheResult<Arc<AppendOnlyLog>> {
+        let file: File = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let commit: Option<Arc<GroupCommit>> = match fsync {
+            FsyncPolicy::Always => Some(Arc::new(GroupCommit::new(file.try_clone()?))),
+            FsyncPolicy::EverySec | FsyncPolicy::No => None,
+        };
+
+        let log: Arc<AppendOnlyLog> = Arc::new(AppendOnlyLog {
+            path: path.to_path_buf(),
+            base_size: AtomicU64::new(file.metadata()?.len()),
+            state: Mutex::new(LogState {
+                file,
+                rewrite: None,
+            }),
+            fsync,
+            commit,
+            dirty: AtomicBool::new(false),
+        });
+        if fsync == FsyncPolicy::EverySec {
+            let weak_log: Weak<AppendOnlyLog> = Arc::downgrade(&log);
+            thread::Builder::new()
+                .name("aof-fsync".to_string())
+                .spawn(move || sync_every_second(weak_log))?;
+        }
+        Ok(log)
+    }
+
+    /// Apply the records of the log to the given cache (see `LogReplay`), in the order they were written.
+    /// The values whose expiration time has passed are removed instead. An incomplete record at the end of
+    /// the log, as left by a crash in the middle of a write, is cut off with a warning.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the log cannot be read, or holds a record which the cache does not know.
+    /// * The error of the cache, if it rejects a write.
+    pub fn replay<R: LogReplay + ?Sized>(&self, cache: &R) -> CacheResult<ReplayStats> {
+        let file: File = File::open(&self.path)?;
+        let mut reader: BufReader<File> = BufReader::new(file);
+        let mut stats: ReplayStats = ReplayStats::default();
+        let mut offset: u64 = 0;
+
+        loop {
+            let (arguments, length): (Vec<Vec<u8>>, u64) = match read_record(&mut reader)? {
+                Record::Complete(arguments, length) => (arguments, length),
+                Record::End => break,
+                Record::Incomplete => {
+                    warn!(
+                        "Cutting off an incomplete record at the end of {} (offset {})",
+                        self.path.display(),
+                        offset
+                    );
+                    self.state.lock()?.file.set_len(offset)?;
+                    self.base_size.store(offset, Ordering::Relaxed);
+                    stats.truncated = true;
+                    break;
+                }
+            };
+            self.apply(cache, arguments, offset)?;
+            offset += length;
+            stats.records += 1;
+        }
+        Ok(stats)
+    }
+
+    /// The path of the log.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The size of the log, in bytes.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the file cannot be inspected.
+    pub fn size(&self) -> CacheResult<u64> {
+        Ok(self.state.lock()?.file.metadata()?.len())
+    }
+
+    /// Whether the log has grown enough since it was opened or last rewritten to be rewritten again: by
+    /// `percentage` percent of its size then, and to at least `min_size` bytes. A rewrite in progress is
+    /// not taken into account.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the file cannot be inspected.
+    pub fn needs_rewrite(&self, percentage: u32, min_size: u64) -> CacheResult<bool> {
+        let size: u64 = self.size()?;
+        let base_size: u64 = self.base_size.load(Ordering::Relaxed);
+        let growth: u64 = base_size.saturating_mul(u64::from(percentage)) / 100;
+        Ok(size >= min_size && size >= base_size.saturating_add(growth).max(1))
+    }
+
+    /// Start rewriting the log: from now on, the records appended are also kept aside until the rewrite
+    /// completes (see `rewrite`), so that none is lost with the log being replaced. The caller makes sure
+    /// that no write is being applied in the meantime (i.e. that every write the storage engine is yet to
+    /// apply is appended after this call), for the compacted log to hold them all.
+    ///
+    /// # Returns
+    /// * Whether the rewrite has started (`false` if another one is in progress).
+    pub fn start_rewrite(&self) -> CacheResult<bool> {
+        let mut state = self.state.lock()?;
+        if state.rewrite.is_some() {
+            return Ok(false);
+        }
+        state.rewrite = Some(Vec::new());
+        Ok(true)
+    }
+
+    /// Give up the rewrite started with `start_rewrite`, e.g. if it cannot be run.
+    pub fn abort_rewrite(&self) -> CacheResult<()> {
+        self.state.lock()?.rewrite = None;
+        Ok(())
+    }
+
+    /// Complete the rewrite started with `start_rewrite`: write a `SET` for each live key of the given cache
+    /// and a `SETNULL` for each key of `negatives` to a temporary file next to the log, followed by the
+    /// records appended since the rewrite started, then sync it and rename it over the log, which the
+    /// records are appended to from then on. A failed rewrite leaves the log as it was.
+    ///
+    /// # Returns
+    /// * The number of keys written.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the compacted log cannot be written.
+    /// * `CacheError::Unsupported` if the caches cannot be scanned with the expiration times.
+    pub fn rewrite(
+        &self,
+        cache: &dyn Cache<String, Bytes>,
+        negatives: &dyn Cache<String, ()>,
+    ) -> CacheResult<usize> {
+        let mut temporary: OsString = self.path.clone().into_os_string();
+        temporary.push(".rewrite");
+        let temporary: PathBuf = PathBuf::from(temporary);
+
+        let result: CacheResult<usize> = self.write_compacted(cache, negatives, &temporary);
+        if result.is_err() {
+            self.abort_rewrite()?;
+            let _ = fs::remove_file(&temporary);
+        }
+        result
+    }
+
+    /// Write the compacted log to the given temporary file, then swap it in (see `rewrite`).
+    fn write_compacted(
+        &self,
+        cache: &dyn Cache<String, Bytes>,
+        negatives: &dyn Cache<String, ()>,
+        temporary: &Path,
+    ) -> CacheResult<usize> {
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(temporary)?);
+        let mut keys: usize = 0;
+        let mut failure: Option<io::Error> = None;
+        cache.scan_with_expirations(&mut |key, value, expires_at| {
+            match writer.write_all(&put_record(key, value, expires_at)) {
+                Ok(()) => keys += 1,
+                Err(e) => failure = Some(e),
+            }
+            failure.is_none()
+        })?;
+        if failure.is_none() {
+            negatives.scan_with_expirations(&mut |key, _, expires_at| {
+                match writer.write_all(&negative_record(key, expires_at)) {
+                    Ok(()) => keys += 1,
+                    Err(e) => failure = Some(e),
+                }
+                failure.is_none()
+            })?;
+        }
+        if let Some(e) = failure {
+            return Err(e.into());
+        }
+        let mut compacted: File = writer.into_inner().map_err(|e| e.into_error())?;
+
+        // The writes wait while the records appended in the meantime are copied and the logs are swapped
+        let mut state = self.state.lock()?;
+        compacted.write_all(state.rewrite.as_deref().unwrap_or_default())?;
+        compacted.sync_all()?;
+        fs::rename(temporary, &self.path)?;
+        if let Some(directory) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            File::open(directory)?.sync_all()?;
+        }
+
+        let file: File = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        if let Some(commit) = &self.commit {
+            commit.replace_file(file.try_clone()?);
+        }
+        self.base_size
+            .store(file.metadata()?.len(), Ordering::Relaxed);
+        *state = LogState {
+            file,
+            rewrite: None,
+        };
+        Ok(keys)
+    }
+
+    /// Make every record appended so far durable, whatever the policy (e.g. before the server shuts down).
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the file cannot be synced.
+    pub fn sync(&self) -> CacheResult<()> {
+        self.dirty.store(false, Ordering::Relaxed);
+        self.state.lock()?.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Append that the given key is cached as known to be absent, until the given expiration time
+    /// (monotime) if any, as a `SETNULL` record.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the record cannot be appended.
+    pub fn append_negative(&self, key: &str, expires_at: Option<u64>) -> CacheResult<()> {
+        let record: Vec<u8> = negative_record(key, expires_at);
+        self.append_record(&record, None)
+            .map_err(|e| CacheError::Storage(e.to_string()))
+    }
+
+    /// Append a write to the value of the given key as the command which made it (e.g. `RPUSH key value`),
+    /// which replaying applies to the value again (see `LogReplay::replay_command`). Called in the same step
+    /// as the value is written, given its new value and expiration time (monotime): as a command may not
+    /// be applied twice, a rewrite in progress (whose scan may already see the new value) is given a `SET`
+    /// of the value instead.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the record cannot be appended.
+    pub fn append_command(
+        &self,
+        arguments: &[&[u8]],
+        key: &str,
+        value: &Bytes,
+        expires_at: Option<u64>,
+    ) -> CacheResult<()> {
+        let record: Vec<u8> = encode(arguments);
+        self.append_record(&record, Some(&|| put_record(key, value, expires_at)))
+            .map_err(|e| CacheError::Storage(e.to_string()))
+    }
+
+    /// Apply a replayed record, at the given offset of the log, to the cache.
+    fn apply<R: LogReplay + ?Sized>(
+        &self,
+        cache: &R,
+        arguments: Vec<Vec<u8>>,
+        offset: u64,
+    ) -> CacheResult<()> {
+        let (command, key, rest): (&[u8], Option<String>, &[Vec<u8>]) = match arguments.as_slice() {
+            [command, key, rest @ ..] => (command, String::from_utf8(key.clone()).ok(), rest),
+            [command] if command == b"FLUSHALL" => return cache.replay_clear(),
+            _ => return Err(self.corrupted(offset)),
+        };
+
+        match (command, key, rest) {
+            (b"SET", Some(key), [value]) => {
+                cache.replay_put(key, Bytes::from(value.as_slice()), None)
+            }
+            (b"SET", Some(key), [value, option, expires_at])
+                if option.eq_ignore_ascii_case(b"PXAT") =>
+            {
+                match self.remaining_ttl(expires_at, offset)? {
+                    Some(ttl) => cache.replay_put(key, Bytes::from(value.as_slice()), Some(ttl)),
+                    None => cache.replay_remove(key),
+                }
+            }
+            (b"SETNULL", Some(key), []) => cache.replay_negative(key, None),
+            (b"SETNULL", Some(key), [option, expires_at])
+                if option.eq_ignore_ascii_case(b"PXAT") =>
+            {
+                match self.remaining_ttl(expires_at, offset)? {
+                    Some(ttl) => cache.replay_negative(key, Some(ttl)),
+                    None => cache.replay_remove(key),
+                }
+            }
+            (b"DEL", Some(key), []) => cache.replay_remove(key),
+            _ => match cache.replay_command(&arguments)? {
+                true => Ok(()),
+                false => Err(self.corrupted(offset)),
+            },
+        }
+    }
+
+    /// The TTL (in seconds) left before the given wall-clock expiration time (in milliseconds) of a record
+    /// at the given offset, or `None` if it has passed.
+    fn remaining_ttl(&self, expires_at: &[u8], offset: u64) -> CacheResult<Option<u64>> {
+        let expires_at: u64 = std::str::from_utf8(expires_at)
+            .ok()
+            .and_then(|expires_at| expires_at.parse().ok())
+            .ok_or_else(|| self.corrupted(offset))?;
+        match expires_at.checked_sub(current_walltime()) {
+            Some(remaining) if remaining > 0 => Ok(Some(remaining.div_ceil(1000))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Append a record, making it durable if the policy says so.
+    fn append(&self, arguments: &[&[u8]]) -> Result<(), SinkError> {
+        self.append_record(&encode(arguments), None)
+    }
+
+    /// Append an encoded record, making it durable if the policy says so. A rewrite in progress is given
+    /// the record too, or the one made by `for_rewrite` if given.
+    fn append_record(
+        &self,
+        record: &[u8],
+        for_rewrite: Option<&dyn Fn() -> Vec<u8>>,
+    ) -> Result<(), SinkError> {
+        let write: Option<u64> = {
+            let mut state = self.state.lock().map_err(|_| "the AOF lock is poisoned")?;
+            state.file.write_all(record)?;
+            if let Some(rewrite) = &mut state.rewrite {
+                match for_rewrite {
+                    Some(for_rewrite) => rewrite.extend_from_slice(&for_rewrite()),
+                    None => rewrite.extend_from_slice(record),
+                }
+            }
+            self.commit.as_ref().map(|commit| commit.record_write())
+        };
+
+        match (&self.commit, write) {
+            (Some(commit), Some(write)) => commit.wait_durable(write)?,
+            _ => self.dirty.store(true, Ordering::Relaxed),
+        }
+        Ok(())
+    }
+
+    fn corrupted(&self, offset: u64) -> CacheError {
+        CacheError::Storage(format!(
+            "invalid record in {} at offset {}",
+            self.path.display(),
+            offset
+        ))
+    }
+}
+
+impl WriteSink<String, Bytes> for AppendOnlyLog {
+    fn on_put(&self, key: &String, value: &Bytes, ttl: Option<u64>) -> Result<(), SinkError> {
+        match ttl {
+            Some(ttl) => {
+                let expires_at: String = current_walltime()
+                    .saturating_add(ttl.saturating_mul(1000))
+                    .to_string();
+                self.append(&[
+                    b"SET",
+                    key.as_bytes(),
+                    value,
+                    b"PXAT",
+                    expires_at.as_bytes(),
+                ])
+            }
+            None => self.append(&[b"SET", key.as_bytes(), value]),
+        }
+    }
+
+    fn on_remove(&self, key: &String) -> Result<(), SinkError> {
+        self.append(&[b"DEL", key.as_bytes()])
+    }
+
+    fn on_clear(&self) -> Result<(), SinkError> {
+        self.append(&[b"FLUSHALL"])
+    }
+}
+
+impl Drop for AppendOnlyLog {
+    fn drop(&mut self) {
+        if self.fsync != FsyncPolicy::No {
+            if let Err(e) = self.sync() {
+                warn!("Failed to sync {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// A record read from the log.
+enum Record {
+    Complete(Vec<Vec<u8>>, u64), // The arguments of the command, and the number of bytes the record takes
+    Incomplete,                  // The log ends in the middle of the record
+    End,                         // The log ends before the record
+}
+
+/// Read the next record of the log, a RESP array of bulk strings.
+fn read_record(reader: &mut impl BufRead) -> CacheResult<Record> {
+    let mut length: u64 = 0;
+    let Some(count) = read_header(reader, b'*', &mut length)? else {
+        return Ok(match length {
+            0 => Record::End,
+            _ => Record::Incomplete,
+        });
+    };
+
+    let mut arguments: Vec<Vec<u8>> = Vec::with_capacity(count.min(16));
+    for _ in 0..count {
+        let Some(size) = read_header(reader, b'$', &mut length)? else {
+            return Ok(Record::Incomplete);
+        };
+        if size > MAX_ARGUMENT_SIZE {
+            return Err(CacheError::Storage(format!(
+                "too large an argument ({} bytes) in the AOF",
+                size
+            )));
+        }
+        let mut argument: Vec<u8> = vec![0; size + 2];
+        match reader.read_exact(&mut argument) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Record::Incomplete),
+            Err(e) => return Err(e.into()),
+        }
+        if !argument.ends_with(b"\r\n") {
+            return Err(CacheError::Storage(
+                "invalid bulk string in the AOF".to_string(),
+            ));
+        }
+        argument.truncate(size);
+        length += size as u64 + 2;
+        arguments.push(argument);
+    }
+    Ok(Record::Complete(arguments, length))
+}
+
+/// Read a `<prefix><number>\r\n` line, adding its length to `length`.
+///
+/// # Returns
+/// * The number, or `None` if the log ends before the end of the line.
+fn read_header(
+    reader: &mut impl BufRead,
+    prefix: u8,
+    length: &mut u64,
+) -> CacheResult<Option<usize>> {
+    let mut line: Vec<u8> = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+    *length += line.len() as u64;
+    if !line.ends_with(b"\n") {
+        return Ok(None);
+    }
+    line.strip_suffix(b"\r\n")
+        .and_then(|line| line.strip_prefix(&[prefix]))
+        .and_then(|number| std::str::from_utf8(number).ok())
+        .and_then(|number| number.parse().ok())
+        .map(Some)
+        .ok_or_else(|| CacheError::Storage("invalid record header in the AOF".to_string()))
+}
+
+/// The `SET` record of a value, with its expiration time (monotime) if any.
+fn put_record(key: &str, value: &[u8], expires_at: Option<u64>) -> Vec<u8> {
+    match expires_at {
+        Some(expires_at) => {
+            let expires_at: String = monotime_to_walltime(expires_at).to_string();
+            encode(&[
+                b"SET",
+                key.as_bytes(),
+                value,
+                b"PXAT",
+                expires_at.as_bytes(),
+            ])
+        }
+        None => encode(&[b"SET", key.as_bytes(), value]),
+    }
+}
+
+/// The `SETNULL` record of a key known to be absent, with its expiration time (monotime) if any.
+fn negative_record(key: &str, expires_at: Option<u64>) -> Vec<u8> {
+    match expires_at {
+        Some(expires_at) => {
+            let expires_at: String = monotime_to_walltime(expires_at).to_string();
+            encode(&[b"SETNULL", key.as_bytes(), b"PXAT", expires_at.as_bytes()])
+        }
+        None => encode(&[b"SETNULL", key.as_bytes()]),
+    }
+}
+
+/// Encode a command as a RESP array of bulk strings.
+fn encode(arguments: &[&[u8]]) -> Vec<u8> {
+    let size: usize = arguments.iter().map(|argument| argument.len() + 16).sum();
+    let mut record: Vec<u8> = Vec::with_capacity(size + 16);
+    record.extend_from_slice(format!("*{}\r\n", arguments.len()).as_bytes());
+    for argument in arguments {
+        record.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+        record.extend_from_slice(argument);
+        record.extend_from_slice(b"\r\n");
+    }
+    record
+}
+
+/// The loop of the background thread of `FsyncPolicy::EverySec`: sync the log every second if records
+/// were appended, until it is dropped.
+fn sync_every_second(log: Weak<AppendOnlyLog>) {
+    loop {
+        thread::sleep(SYNC_INTERVAL);
+        let Some(log) = log.upgrade() else {
+            return;
+        };
+        if log.dirty.load(Ordering::Relaxed) {
+            if let Err(e) = log.sync() {
+                error!("Failed to sync {}: {}", log.path.display(), e);
+            }
+        }
+    }
+}
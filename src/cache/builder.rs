@@ -0,0 +1,259 @@
+use std::{hash::Hash, sync::Arc};
+
+use super::{
+    engine, Cache, Codec, EngineOptions, EvictionPolicy, HeapSize, MaxMemoryPolicy, MemoryStats,
+    PutCondition, SweepStats, DEFAULT_ENGINE,
+};
+use crate::error::CacheResult;
+
+/// Builds the caches embedded in applications, with the storage engines of the server, so that an
+/// application gets the exact same cache in-process without running a server.
+///
+/// A cache bounded by a number of entries or by memory evicts its entries with the LRU policy unless
+/// another one is chosen. Otherwise, the default storage engine is used (see `DEFAULT_ENGINE`).
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// use myrustcache::{Cache, CacheBuilder, EvictionPolicy};
+///
+/// let cache: Arc<dyn Cache<String, String>> = CacheBuilder::new()
+///     .max_entries(10_000)
+///     .ttl_default(300)
+///     .eviction(EvictionPolicy::Lru)
+///     .build()?;
+/// cache.put("greeting".to_string(), "hello".to_string(), None)?; // Expires after 300 seconds
+/// # Ok::<(), myrustcache::CacheError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CacheBuilder {
+    engine: Option<String>,   // The name of the storage engine, if chosen
+    options: EngineOptions,   // The tuning options of the storage engine
+    bounded: bool,            // Whether the number of entries or the memory was bounded
+    ttl_default: Option<u64>, // The TTL of the values put without one, in seconds
+}
+
+impl CacheBuilder {
+    /// Create a builder of an unbounded cache of the default storage engine, whose values live
+    /// indefinitely unless put with a TTL.
+    pub fn new() -> CacheBuilder {
+        CacheBuilder::default()
+    }
+
+    /// Back the cache with the built-in storage engine of the given name (see `BUILTIN_ENGINES`).
+    pub fn engine(mut self, engine: &str) -> CacheBuilder {
+        self.engine = Some(engine.to_string());
+        self
+    }
+
+    /// Tune the storage engine with the given options, replacing those set so far (e.g. by `max_entries`).
+    pub fn engine_options(mut self, options: EngineOptions) -> CacheBuilder {
+        self.options = options;
+        self
+    }
+
+    /// Evict the entries beyond the bounds of the cache with the given policy.
+    pub fn eviction(self, policy: EvictionPolicy) -> CacheBuilder {
+        self.engine(policy.engine())
+    }
+
+    /// Hold at most the given number of entries, evicting the others.
+    pub fn max_entries(mut self, max_entries: usize) -> CacheBuilder {
+        self.options.max_entries = max_entries;
+        self.bounded = true;
+        self
+    }
+
+    /// Let the entries take at most the given number of bytes (see `MaxMemoryPolicy` for the writes beyond).
+    pub fn max_memory(mut self, max_memory: usize) -> CacheBuilder {
+        self.options.max_memory = Some(max_memory);
+        self.bounded = true;
+        self
+    }
+
+    /// Split the cache into the given number of shards, for the engines which are sharded.
+    pub fn shards(mut self, shards: usize) -> CacheBuilder {
+        self.options.shards = shards;
+        self
+    }
+
+    /// Expire the values put without a TTL after the given number of seconds. The values given a TTL, and
+    /// those whose TTL is changed afterwards (e.g. with `Cache::expire`), keep theirs.
+    pub fn ttl_default(mut self, ttl: u64) -> CacheBuilder {
+        self.ttl_default = Some(ttl);
+        self
+    }
+
+    /// Create the cache.
+    ///
+    /// # Errors
+    /// * `CacheError::UnknownEngine` if there is no built-in engine with the chosen name.
+    /// * `CacheError::Storage` if the engine fails to set up its resources.
+    pub fn build<K, V>(&self) -> CacheResult<Arc<dyn Cache<K, V>>>
+    where
+        K: Eq + Hash + Clone + Codec + HeapSize + Send + Sync + 'static,
+        V: Clone + Codec + HeapSize + Send + Sync + 'static,
+    {
+        let engine: &str = match (&self.engine, self.bounded) {
+            (Some(engine), _) => engine,
+            (None, true) => EvictionPolicy::default().engine(),
+            (None, false) => DEFAULT_ENGINE,
+        };
+        let cache: Arc<dyn Cache<K, V>> =
+            engine::builtin::<K, V>(engine, &self.options)?.create()?;
+
+        match self.ttl_default {
+            Some(ttl) => Ok(Arc::new(DefaultTtlCache { cache, ttl })),
+            None => Ok(cache),
+        }
+    }
+}
+
+/// A cache giving the values put without a TTL a default one (see `CacheBuilder::ttl_default`).
+struct DefaultTtlCache<K, V> {
+    cache: Arc<dyn Cache<K, V>>, // Holds the cached data
+    ttl: u64,                    // The TTL of the values put without one, in seconds
+}
+
+impl<K, V> DefaultTtlCache<K, V> {
+    fn _ttl(&self, ttl: Option<u64>) -> Option<u64> {
+        ttl.or(Some(self.ttl))
+    }
+}
+
+impl<K: Send + Sync, V: Send + Sync> Cache<K, V> for DefaultTtlCache<K, V> {
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        self.cache.put(key, value, self._ttl(ttl))
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        self.cache.put_many(entries, self._ttl(ttl))
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        self.cache.put_if(key, value, self._ttl(ttl), condition)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        self.cache.update(key, update)
+    }
+
+    fn modify(
+        &self,
+        key: K,
+        modify: &mut dyn FnMut(Option<&mut V>) -> CacheResult<Option<V>>,
+    ) -> CacheResult<()>
+    where
+        V: Clone,
+    {
+        self.cache.modify(key, modify)
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        self.cache.replace_if(key, value, self._ttl(ttl), accept)
+    }
+
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        self.cache.rename(from, to, if_absent)
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        self.cache.get(key)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        self.cache.get_many(keys)
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        self.cache.remove(key)
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        self.cache.remove_entry_if(key, accept)
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        self.cache.expire(key, ttl)
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        self.cache.touch(key)
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        self.cache.expiration(key)
+    }
+
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        self.cache.sweep_expired(max_expired)
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        self.cache.scan(visit)
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        self.cache.scan_with_expirations(visit)
+    }
+
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        self.cache.scan_from(cursor, count, visit)
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        self.cache.len()
+    }
+
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        self.cache.clear()
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        self.cache.flush()
+    }
+
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        self.cache.memory_stats()
+    }
+
+    fn memory_usage(&self, key: &K) -> CacheResult<Option<usize>> {
+        self.cache.memory_usage(key)
+    }
+
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        self.cache.set_max_memory(max_bytes, policy)
+    }
+}
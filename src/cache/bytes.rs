@@ -0,0 +1,239 @@
+use std::{
+    any::Any,
+    borrow::Borrow,
+    fmt,
+    hash::{Hash, Hasher},
+    mem,
+    ops::Deref,
+    sync::{Arc, OnceLock},
+};
+
+use super::codec::Codec;
+
+/// The largest value stored inline, so that a `Bytes` takes 64 bytes.
+const INLINE_CAPACITY: usize = 62;
+
+/// An immutable, cheaply cloneable byte buffer. This is the value type stored by the server, so values are
+/// binary-safe and can be written to clients without being copied.
+///
+/// Short values (most of them, e.g. tokens and counters) are stored inline, without any heap allocation,
+/// and cloned without touching a shared reference count. Longer ones are shared behind an `Arc`.
+///
+/// A value may also be held in its native form (see `NativeValue`), e.g. a list as the values it holds, so
+/// that it is modified in place rather than decoded and encoded again by every write. Its bytes are then
+/// its encoding, made when they are first read.
+#[derive(Clone)]
+pub struct Bytes(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Inline {
+        len: u8,
+        data: [u8; INLINE_CAPACITY],
+    },
+    Shared(Arc<[u8]>),
+    Native(Arc<Native>),
+}
+
+/// A value held in its native form by a `Bytes`, along with its encoding once made.
+struct Native {
+    value: Box<dyn NativeValue>,
+    encoded: OnceLock<Box<[u8]>>, // Reset when the value is modified
+}
+
+/// A value which a `Bytes` can hold in its native form rather than as its encoding (see
+/// `Bytes::from_native`), to be modified in place with `Bytes::native_mut`.
+pub trait NativeValue: Any + Send + Sync {
+    /// Encode the value as the bytes of the `Bytes` holding it.
+    fn encode(&self) -> Vec<u8>;
+
+    /// The length of the encoding of the value, known without encoding it.
+    fn encoded_len(&self) -> usize;
+
+    /// The bytes the value owns on the heap (see `HeapSize`).
+    fn heap_size(&self) -> usize;
+
+    /// The first bytes of the encoding of the value (e.g. a header telling its type), known without
+    /// encoding it. At most the whole encoding.
+    fn header(&self) -> &[u8];
+
+    /// Copy the value, for it to be modified while a clone of the `Bytes` holding it still reads it.
+    fn clone_native(&self) -> Box<dyn NativeValue>;
+
+    /// The value, to be downcast to its type (see `Bytes::native`).
+    fn as_any(&self) -> &dyn Any;
+
+    /// The value, to be downcast to its type (see `Bytes::native_mut`).
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl Clone for Native {
+    fn clone(&self) -> Native {
+        Native {
+            value: self.value.clone_native(),
+            encoded: OnceLock::new(), // Only cloned to be modified
+        }
+    }
+}
+
+impl Bytes {
+    /// Hold the given value in its native form.
+    pub fn from_native<T: NativeValue>(value: T) -> Bytes {
+        Bytes(Repr::Native(Arc::new(Native {
+            value: Box::new(value),
+            encoded: OnceLock::new(),
+        })))
+    }
+
+    /// Whether the bytes are stored inline rather than on the heap.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Inline { .. })
+    }
+
+    /// The number of bytes, known without encoding a native value.
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Repr::Native(native) => native.value.encoded_len(),
+            _ => (**self).len(),
+        }
+    }
+
+    /// Whether there are no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The first bytes, known without encoding a native value (see `NativeValue::header`): all of them,
+    /// unless the value is native.
+    pub fn header(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Native(native) => native.value.header(),
+            _ => self,
+        }
+    }
+
+    /// The native value held, if it is of the given type.
+    pub fn native<T: NativeValue>(&self) -> Option<&T> {
+        match &self.0 {
+            Repr::Native(native) => native.value.as_any().downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// The native value held, if it is of the given type, to modify in place. The value is copied first if
+    /// a clone of these bytes shares it (copy-on-write), and its encoding is forgotten.
+    pub fn native_mut<T: NativeValue>(&mut self) -> Option<&mut T> {
+        match &mut self.0 {
+            Repr::Native(native) if native.value.as_any().is::<T>() => {
+                let native: &mut Native = Arc::make_mut(native);
+                native.encoded.take();
+                native.value.as_any_mut().downcast_mut()
+            }
+            _ => None,
+        }
+    }
+
+    /// The bytes the value owns on the heap, beyond the reference counts sharing it: none if stored inline.
+    pub(super) fn owned_heap_size(&self) -> usize {
+        match &self.0 {
+            Repr::Inline { .. } => 0,
+            Repr::Shared(bytes) => bytes.len(),
+            Repr::Native(native) => {
+                let encoded: usize = native.encoded.get().map_or(0, |encoded| encoded.len());
+                mem::size_of::<Native>() + native.value.heap_size() + encoded
+            }
+        }
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Inline { len, data } => &data[..*len as usize],
+            Repr::Shared(bytes) => bytes,
+            Repr::Native(native) => native
+                .encoded
+                .get_or_init(|| native.value.encode().into_boxed_slice()),
+        }
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Bytes {
+        if bytes.len() > INLINE_CAPACITY {
+            return Bytes(Repr::Shared(Arc::from(bytes)));
+        }
+
+        let mut data: [u8; INLINE_CAPACITY] = [0; INLINE_CAPACITY];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Bytes(Repr::Inline {
+            len: bytes.len() as u8,
+            data,
+        })
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Bytes {
+        match bytes.len() {
+            len if len > INLINE_CAPACITY => Bytes(Repr::Shared(Arc::from(bytes))),
+            _ => Bytes::from(bytes.as_slice()),
+        }
+    }
+}
+
+impl From<Arc<[u8]>> for Bytes {
+    fn from(bytes: Arc<[u8]>) -> Bytes {
+        Bytes(Repr::Shared(bytes))
+    }
+}
+
+impl Borrow<[u8]> for Bytes {
+    fn borrow(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Default for Bytes {
+    fn default() -> Bytes {
+        Bytes::from(&[][..])
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Bytes) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Bytes {}
+
+impl Hash for Bytes {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl Codec for Bytes {
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Bytes> {
+        Some(Bytes::from(bytes))
+    }
+}
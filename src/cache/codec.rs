@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+/// Conversion of keys and values to and from bytes, for storage engines that keep data outside of the
+/// process memory (e.g. on disk).
+///
+/// Implemented for the key and value types used by the server (`String` and `Bytes`), and for the numbers
+/// and booleans, which are encoded as text (e.g. `42`), so that the clients of the server can read them and
+/// `INCR` the integers. Implement it for your own types to use them with such engines, or to store them in a
+/// cache of bytes (see `TypedCache`).
+pub trait Codec: Sized {
+    /// Encode the value into bytes.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Decode a value previously encoded with `encode`. Returns `None` if the bytes are not a valid encoding.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+impl Codec for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<String> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Vec<u8>> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl Codec for Arc<[u8]> {
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Arc<[u8]>> {
+        Some(Arc::from(bytes))
+    }
+}
+
+impl Codec for i64 {
+    fn encode(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<i64> {
+        std::str::from_utf8(bytes).ok()?.parse().ok()
+    }
+}
+
+impl Codec for u64 {
+    fn encode(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<u64> {
+        std::str::from_utf8(bytes).ok()?.parse().ok()
+    }
+}
+
+impl Codec for f64 {
+    fn encode(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<f64> {
+        std::str::from_utf8(bytes).ok()?.parse().ok()
+    }
+}
+
+impl Codec for bool {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            true => b"1".to_vec(),
+            false => b"0".to_vec(),
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<bool> {
+        match bytes {
+            b"1" => Some(true),
+            b"0" => Some(false),
+            _ => None,
+        }
+    }
+}
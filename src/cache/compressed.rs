@@ -0,0 +1,288 @@
+use std::{
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use log::error;
+
+use super::{
+    memory::{estimated_entry_size, HeapSize},
+    Bytes, Cache, MaxMemoryPolicy, MemoryStats, PutCondition, SweepStats,
+};
+use crate::{
+    compression::{compress_value, decompress_value},
+    error::{CacheError, CacheResult},
+};
+
+/// How much the writes of a `CompressingCache` were compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub compressed_writes: u64,  // The values written compressed
+    pub uncompressed_bytes: u64, // Their total size before they were compressed
+    pub compressed_bytes: u64,   // Their total size once compressed, headers included
+}
+
+impl CompressionStats {
+    /// The bytes the compressed writes have saved, over all the writes so far (including the values which
+    /// have since been overwritten or removed).
+    pub fn saved_bytes(&self) -> u64 {
+        self.uncompressed_bytes
+            .saturating_sub(self.compressed_bytes)
+    }
+}
+
+/// A cache compressing the values of at least `threshold` bytes with LZ4 before they are stored, and
+/// decompressing them when they are read, e.g. for verbose JSON values. A value is only stored compressed
+/// if it saves space, so values which do not compress well (e.g. already compressed images) are stored as
+/// they are.
+///
+/// The storage engine sees the compressed values, so its memory accounting (and eviction) counts their
+/// compressed size, while every reader of the cache sees the original values. A storage engine persisting
+/// its values (e.g. `mmap`) has to be read through a `CompressingCache` afterwards, whatever its threshold.
+pub struct CompressingCache<K> {
+    cache: Arc<dyn Cache<K, Bytes>>, // Holds the values, compressed or not
+    threshold: usize,                // The size from which the values are compressed, in bytes
+    compressed_writes: AtomicU64,    // See `CompressionStats`
+    uncompressed_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+}
+
+impl<K> CompressingCache<K> {
+    /// Create a new `CompressingCache` compressing the values of at least `threshold` bytes put into
+    /// `cache`.
+    pub fn new(cache: Arc<dyn Cache<K, Bytes>>, threshold: usize) -> CompressingCache<K> {
+        CompressingCache {
+            cache,
+            threshold,
+            compressed_writes: AtomicU64::new(0),
+            uncompressed_bytes: AtomicU64::new(0),
+            compressed_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// How much the writes have been compressed so far.
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            compressed_writes: self.compressed_writes.load(Ordering::Relaxed),
+            uncompressed_bytes: self.uncompressed_bytes.load(Ordering::Relaxed),
+            compressed_bytes: self.compressed_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The value to store for the given one, compressed if it is large enough, counting the compression.
+    fn _compress(&self, value: Bytes) -> Bytes {
+        match compress_value(&value, self.threshold) {
+            Some(encoded) => {
+                if encoded.len() < value.len() {
+                    self.compressed_writes.fetch_add(1, Ordering::Relaxed);
+                    self.uncompressed_bytes
+                        .fetch_add(value.len() as u64, Ordering::Relaxed);
+                    self.compressed_bytes
+                        .fetch_add(encoded.len() as u64, Ordering::Relaxed);
+                }
+                Bytes::from(encoded)
+            }
+            None => value,
+        }
+    }
+}
+
+/// The original value of a stored one.
+fn decompress(stored: Bytes) -> CacheResult<Bytes> {
+    Ok(decompress_value(&stored)?.map_or(stored, Bytes::from))
+}
+
+impl<K> Cache<K, Bytes> for CompressingCache<K>
+where
+    K: Hash + Eq + Send + Sync + HeapSize,
+{
+    fn put(&self, key: K, value: Bytes, ttl: Option<u64>) -> CacheResult<()> {
+        self.cache.put(key, self._compress(value), ttl)
+    }
+
+    fn put_many(&self, entries: Vec<(K, Bytes)>, ttl: Option<u64>) -> CacheResult<()> {
+        let entries: Vec<(K, Bytes)> = entries
+            .into_iter()
+            .map(|(key, value)| (key, self._compress(value)))
+            .collect();
+        self.cache.put_many(entries, ttl)
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: Bytes,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        self.cache
+            .put_if(key, self._compress(value), ttl, condition)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&Bytes>) -> CacheResult<Bytes>,
+    ) -> CacheResult<Bytes> {
+        let mut updated: Option<Bytes> = None;
+        let stored: Bytes = self.cache.update(key, &mut |current| {
+            let current: Option<Bytes> = current.cloned().map(decompress).transpose()?;
+            let value: Bytes = update(current.as_ref())?;
+            updated = Some(value.clone());
+            Ok(self._compress(value))
+        })?;
+        match updated {
+            Some(value) => Ok(value),
+            None => decompress(stored),
+        }
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: Bytes,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&Bytes>) -> CacheResult<()>,
+    ) -> CacheResult<Option<Bytes>> {
+        self.cache
+            .replace_if(key, self._compress(value), ttl, &mut |current| {
+                let current: Option<Bytes> = current.cloned().map(decompress).transpose()?;
+                accept(current.as_ref())
+            })?
+            .map(decompress)
+            .transpose()
+    }
+
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        self.cache.rename(from, to, if_absent)
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<Bytes>> {
+        self.cache.get(key)?.map(decompress).transpose()
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<Bytes>>> {
+        self.cache
+            .get_many(keys)?
+            .into_iter()
+            .map(|value| value.map(decompress).transpose())
+            .collect()
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<Bytes>> {
+        self.cache.remove(key)?.map(decompress).transpose()
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&Bytes) -> CacheResult<()>,
+    ) -> CacheResult<Option<(Bytes, Option<u64>)>> {
+        let removed: Option<(Bytes, Option<u64>)> = self
+            .cache
+            .remove_entry_if(key, &mut |value| accept(&decompress(value.clone())?))?;
+        match removed {
+            Some((value, expires_at)) => Ok(Some((decompress(value)?, expires_at))),
+            None => Ok(None),
+        }
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        self.cache.expire(key, ttl)
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        self.cache.touch(key)
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        self.cache.expiration(key)
+    }
+
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        self.cache.sweep_expired(max_expired)
+    }
+
+    /// The values which cannot be decompressed are skipped (and logged), so that a single corrupt value does
+    /// not stop a scan (e.g. a dump).
+    fn scan(&self, visit: &mut dyn FnMut(&K, &Bytes)) -> CacheResult<()> {
+        self.cache.scan(&mut |key, value| {
+            if let Some(value) = scanned(value) {
+                visit(key, &value);
+            }
+        })
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &Bytes, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        self.cache
+            .scan_with_expirations(&mut |key, value, expires_at| match scanned(value) {
+                Some(value) => visit(key, &value, expires_at),
+                None => true,
+            })
+    }
+
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &Bytes),
+    ) -> CacheResult<u64> {
+        self.cache.scan_from(cursor, count, &mut |key, value| {
+            if let Some(value) = scanned(value) {
+                visit(key, &value);
+            }
+        })
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        self.cache.len()
+    }
+
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+    {
+        self.cache.clear()
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        self.cache.flush()
+    }
+
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        self.cache.memory_stats()
+    }
+
+    /// The size of the value as it is stored, compressed or not, is estimated if the storage engine does not
+    /// tell it.
+    fn memory_usage(&self, key: &K) -> CacheResult<Option<usize>> {
+        match self.cache.memory_usage(key) {
+            Err(CacheError::Unsupported(_)) => {
+                let stored: Option<Bytes> = self.cache.get(key)?;
+                Ok(stored.map(|stored| estimated_entry_size(key, &stored)))
+            }
+            usage => usage,
+        }
+    }
+
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        self.cache.set_max_memory(max_bytes, policy)
+    }
+}
+
+/// The original value of a stored one visited by a scan, or `None` if it is corrupt.
+fn scanned(stored: &Bytes) -> Option<Bytes> {
+    match decompress(stored.clone()) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            error!("Skipped a value which cannot be read: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::{env, fmt, hash::Hash, path::PathBuf, str::FromStr, sync::Arc};
+
+use super::{
+    Cache, Codec, HeapSize, LeftRightCache, LfuCache, LruCache, MaxMemoryPolicy, MmapCache,
+    ShardedCache, SimpleCache, SyncPolicy, TieredCache,
+};
+use crate::error::{CacheError, CacheResult};
+
+/// The name of the storage engine used when none is configured.
+pub const DEFAULT_ENGINE: &str = "sharded";
+
+/// The names of the storage engines shipped with the crate, selectable through the configuration.
+pub const BUILTIN_ENGINES: &[&str] = &[
+    "simple",
+    "sharded",
+    "leftright",
+    "tiered",
+    "mmap",
+    "lru",
+    "lfu",
+];
+
+/// Tuning options passed to the built-in storage engines. Each engine uses only the options relevant to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineOptions {
+    pub shards: usize, // The number of shards of the `sharded` and `leftright` engines
+    pub tiered_memory_entries: usize, // The capacity of the memory tier of the `tiered` engine
+    pub tiered_disk_dir: PathBuf, // The directory of the disk tier of the `tiered` engine
+    pub tiered_disk_bytes: Option<usize>, // The most bytes the disk tier of the `tiered` engine may take
+    pub mmap_path: PathBuf,               // The log file of the `mmap` engine
+    pub mmap_sync: SyncPolicy,            // When the `mmap` engine makes its writes durable
+    pub max_entries: usize, // The maximum number of entries of the `lru` and `lfu` engines
+    pub max_memory: Option<usize>, // The most bytes the entries of the `lru` and `lfu` engines may take
+    pub max_memory_policy: MaxMemoryPolicy, // What the `lru` and `lfu` engines do with the writes beyond
+}
+
+impl Default for EngineOptions {
+    fn default() -> EngineOptions {
+        EngineOptions {
+            shards: ShardedCache::<(), ()>::default_shard_count(),
+            tiered_memory_entries: 100_000,
+            tiered_disk_dir: env::temp_dir(),
+            tiered_disk_bytes: None,
+            mmap_path: PathBuf::from("myrustcache.mmap"),
+            mmap_sync: SyncPolicy::default(),
+            max_entries: 1_000_000,
+            max_memory: None,
+            max_memory_policy: MaxMemoryPolicy::default(),
+        }
+    }
+}
+
+/// Which entries a cache bounded by a number of entries evicts once full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used entry (see `LruCache`).
+    #[default]
+    Lru,
+
+    /// Evict the least frequently used entry, the access counts decaying over time (see `LfuCache`).
+    Lfu,
+}
+
+impl EvictionPolicy {
+    /// The name of the built-in storage engine evicting the entries with this policy.
+    pub fn engine(&self) -> &'static str {
+        match self {
+            EvictionPolicy::Lru => "lru",
+            EvictionPolicy::Lfu => "lfu",
+        }
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<EvictionPolicy, String> {
+        match text {
+            "lru" => Ok(EvictionPolicy::Lru),
+            "lfu" => Ok(EvictionPolicy::Lfu),
+            _ => Err(format!("unknown eviction policy '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.engine())
+    }
+}
+
+/// A storage engine creates the `Cache` instances that actually hold the data.
+///
+/// The built-in engines can be selected by name (see `BUILTIN_ENGINES`). Third parties can plug their own
+/// backend into the `CacheFactory` (and hence into an embedded `CacheServer`) by implementing this trait:
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use myrustcache::{Cache, CacheFactory, CacheResult, SimpleCache, StorageEngine};
+///
+/// struct MyEngine;
+///
+/// impl StorageEngine<String, u64> for MyEngine {
+///     fn name(&self) -> &'static str {
+///         "my-engine"
+///     }
+///
+///     fn create(&self) -> CacheResult<Arc<dyn Cache<String, u64>>> {
+///         Ok(Arc::new(SimpleCache::new())) // Replace with your own `Cache` implementation
+///     }
+/// }
+///
+/// let cache = CacheFactory::new_cache_with(&MyEngine)?;
+/// # Ok::<(), myrustcache::CacheError>(())
+/// ```
+pub trait StorageEngine<K, V>: Send + Sync {
+    /// The unique name of the engine (e.g. `simple`). Used for selecting the engine and for logging.
+    fn name(&self) -> &'static str;
+
+    /// Create a new, empty cache backed by this engine.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the resources of the engine (e.g. files) cannot be set up.
+    fn create(&self) -> CacheResult<Arc<dyn Cache<K, V>>>;
+}
+
+/// The engine creating `SimpleCache` instances: a single map guarded by one read-write lock.
+pub struct SimpleEngine;
+
+impl<K, V> StorageEngine<K, V> for SimpleEngine
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "simple"
+    }
+
+    fn create(&self) -> CacheResult<Arc<dyn Cache<K, V>>> {
+        Ok(Arc::new(SimpleCache::new()))
+    }
+}
+
+/// The engine creating `ShardedCache` instances: the key space split across independently locked shards.
+pub struct ShardedEngine {
+    pub shards: usize, // The number of shards of each created cache
+}
+
+impl Default for ShardedEngine {
+    fn default() -> ShardedEngine {
+        ShardedEngine {
+            shards: EngineOptions::default().shards,
+        }
+    }
+}
+
+impl<K, V> StorageEngine<K, V> for ShardedEngine
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "sharded"
+    }
+
+    fn create(&self) -> CacheResult<Arc<dyn Cache<K, V>>> {
+        Ok(Arc::new(ShardedCache::new(self.shards)))
+    }
+}
+
+/// The engine creating `LeftRightCache` instances: sharded maps kept twice, so reads never take a lock.
+pub struct LeftRightEngine {
+    pub shards: usize, // The number of shards of each created cache
+}
+
+impl Default for LeftRightEngine {
+    fn default() -> LeftRightEngine {
+        LeftRightEngine {
+            shards: EngineOptions::default().shards,
+        }
+    }
+}
+
+impl<K, V> StorageEngine<K, V> for LeftRightEngine
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "leftright"
+    }
+
+    fn create(&self) -> CacheResult<Arc<dyn Cache<K, V>>> {
+        Ok(Arc::new(LeftRightCache::new(self.shards)))
+    }
+}
+
+/// The engine creating `TieredCache` instances: a bounded memory tier overflowing to a disk tier.
+pub struct TieredEngine {
+    pub memory_entries: usize, // The maximum number of entries kept in memory
+    pub disk_dir: PathBuf,     // The directory in which the disk tier is created
+    pub disk_bytes: Option<usize>, // The most bytes the values spilled to disk may take, if bounded
+}
+
+impl<K, V> StorageEngine<K, V> for TieredEngine
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Codec + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "tiered"
+    }
+
+    fn create(&self) -> CacheResult<Arc<dyn Cache<K, V>>> {
+        let cache: TieredCache<K, V> = TieredCache::new(self.memory_entries, &self.disk_dir)?;
+        Ok(Arc::new(match self.disk_bytes {
+            Some(max_bytes) => cache.with_disk_capacity(max_bytes),
+            None => cache,
+        }))
+    }
+}
+
+/// The engine creating `MmapCache` instances: a persistent, memory-mapped log with an in-memory index.
+pub struct MmapEngine {
+    pub path: PathBuf, // The log file; it must not be shared by two caches at the same time
+    pub sync: SyncPolicy, // When the writes are made durable
+}
+
+impl<K, V> StorageEngine<K, V> for MmapEngine
+where
+    K: Eq + Hash + Codec + Send + Sync + 'static,
+    V: Codec + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "mmap"
+    }
+
+    fn create(&self) -> CacheResult<Arc<dyn Cache<K, V>>> {
+        Ok(Arc::new(MmapCache::open_with_sync(&self.path, self.sync)?))
+    }
+}
+
+/// The engine creating `LruCache` instances: a bounded map evicting the least recently used entries.
+pub struct LruEngine {
+    pub max_entries: usize, // The maximum number of entries of each created cache
+    pub max_memory: Option<usize>, // The most bytes the entries of each created cache may take
+    pub max_memory_policy: MaxMemoryPolicy, // What happens to the writes beyond `max_memory`
+}
+
+impl<K, V> StorageEngine<K, V> for LruEngine
+where
+    K: Eq + Hash + HeapSize + Send + Sync + 'static,
+    V: Clone + HeapSize + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "lru"
+    }
+
+    fn create(&self) -> CacheResult<Arc<dyn Cache<K, V>>> {
+        let cache: LruCache<K, V> = LruCache::new(self.max_entries);
+        Ok(Arc::new(match self.max_memory {
+            Some(max_bytes) => cache.with_max_memory(max_bytes, self.max_memory_policy),
+            None => cache,
+        }))
+    }
+}
+
+/// The engine creating `LfuCache` instances: a bounded map evicting the least frequently used entries.
+pub struct LfuEngine {
+    pub max_entries: usize, // The maximum number of entries of each created cache
+    pub max_memory: Option<usize>, // The most bytes the entries of each created cache may take
+    pub max_memory_policy: MaxMemoryPolicy, // What happens to the writes beyond `max_memory`
+}
+
+impl<K, V> StorageEngine<K, V> for LfuEngine
+where
+    K: Eq + Hash + HeapSize + Send + Sync + 'static,
+    V: Clone + HeapSize + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "lfu"
+    }
+
+    fn create(&self) -> CacheResult<Arc<dyn Cache<K, V>>> {
+        let cache: LfuCache<K, V> = LfuCache::new(self.max_entries);
+        Ok(Arc::new(match self.max_memory {
+            Some(max_bytes) => cache.with_max_memory(max_bytes, self.max_memory_policy),
+            None => cache,
+        }))
+    }
+}
+
+/// Look up the built-in storage engine with the given name, configured with the given options.
+///
+/// # Errors
+/// * `CacheError::UnknownEngine` if there is no built-in engine with that name.
+pub(super) fn builtin<K, V>(
+    name: &str,
+    options: &EngineOptions,
+) -> CacheResult<Box<dyn StorageEngine<K, V>>>
+where
+    K: Eq + Hash + Clone + Codec + HeapSize + Send + Sync + 'static,
+    V: Clone + Codec + HeapSize + Send + Sync + 'static,
+{
+    match name {
+        "simple" => Ok(Box::new(SimpleEngine)),
+        "sharded" => Ok(Box::new(ShardedEngine {
+            shards: options.shards,
+        })),
+        "leftright" => Ok(Box::new(LeftRightEngine {
+            shards: options.shards,
+        })),
+        "tiered" => Ok(Box::new(TieredEngine {
+            memory_entries: options.tiered_memory_entries,
+            disk_dir: options.tiered_disk_dir.clone(),
+            disk_bytes: options.tiered_disk_bytes,
+        })),
+        "mmap" => Ok(Box::new(MmapEngine {
+            path: options.mmap_path.clone(),
+            sync: options.mmap_sync,
+        })),
+        "lru" => Ok(Box::new(LruEngine {
+            max_entries: options.max_entries,
+            max_memory: options.max_memory,
+            max_memory_policy: options.max_memory_policy,
+        })),
+        "lfu" => Ok(Box::new(LfuEngine {
+            max_entries: options.max_entries,
+            max_memory: options.max_memory,
+            max_memory_policy: options.max_memory_policy,
+        })),
+        _ => Err(CacheError::UnknownEngine(name.to_string())),
+    }
+}
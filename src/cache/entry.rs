@@ -0,0 +1,39 @@
+use crate::utils::{current_monotime, expires_after};
+
+/// Cached value with an optional time of expiration (i.e. when the value is no longer valid).
+#[derive(Clone)]
+pub(crate) struct CacheEntry<V> {
+    pub value: V, // The cached value (cloned on every read, so it should be cheap to clone)
+    pub expires_at: Option<u64>, // The time at which the value expires (in milliseconds since the Unix epoch).
+    pub ttl: Option<u64>, // The time-to-live (in seconds) the value was given, which `touch` restarts
+}
+
+impl<V> CacheEntry<V> {
+    pub fn new(value: V, ttl: Option<u64>) -> CacheEntry<V> {
+        CacheEntry {
+            value,
+            expires_at: ttl.map(|t| expires_after(current_monotime(), t)),
+            ttl,
+        }
+    }
+
+    /// Change the time-to-live (in seconds) of the value, counted from now.
+    pub fn set_ttl(&mut self, ttl: Option<u64>) {
+        self.expires_at = ttl.map(|t| expires_after(current_monotime(), t));
+        self.ttl = ttl;
+    }
+
+    /// Restart the time-to-live of the value from now, for its whole duration.
+    pub fn touch(&mut self) {
+        self.set_ttl(self.ttl);
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(current_monotime())
+    }
+
+    /// Whether the value has expired at the given monotonic time (see `current_monotime`).
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|t| now >= t)
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    hash::Hash,
+    sync::Arc,
+};
+
+/// An index of the keys with an expiration time, bucketed by that time (in milliseconds, see
+/// `current_monotime`).
+///
+/// It is kept alongside the map of a cache, so the expired keys can be found in O(expired) instead of
+/// scanning the whole map. The keys are shared with the map through an `Arc`, so they are not duplicated.
+pub(crate) struct ExpiryIndex<K> {
+    buckets: BTreeMap<u64, HashSet<Arc<K>>>, // The keys by expiration time, soonest first
+}
+
+// Not derived, since the keys are shared and need not be `Clone`
+impl<K> Clone for ExpiryIndex<K> {
+    fn clone(&self) -> ExpiryIndex<K> {
+        ExpiryIndex {
+            buckets: self.buckets.clone(),
+        }
+    }
+}
+
+impl<K> ExpiryIndex<K> {
+    pub fn new() -> ExpiryIndex<K> {
+        ExpiryIndex {
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// The number of keys that have expired at the given time.
+    pub fn count_expired(&self, now: u64) -> usize {
+        self.buckets.range(..=now).map(|(_, keys)| keys.len()).sum()
+    }
+}
+
+impl<K: Eq + Hash> ExpiryIndex<K> {
+    /// Index a key expiring at the given time.
+    pub fn insert(&mut self, key: &Arc<K>, expires_at: u64) {
+        self.buckets
+            .entry(expires_at)
+            .or_default()
+            .insert(Arc::clone(key));
+    }
+
+    /// Forget a key previously indexed with the given expiration time.
+    pub fn remove(&mut self, key: &K, expires_at: u64) {
+        if let Some(bucket) = self.buckets.get_mut(&expires_at) {
+            bucket.remove(key);
+            if bucket.is_empty() {
+                self.buckets.remove(&expires_at);
+            }
+        }
+    }
+
+    /// Move a key to its new expiration time, if it changed.
+    pub fn reschedule(&mut self, key: &Arc<K>, from: Option<u64>, to: Option<u64>) {
+        if from == to {
+            return;
+        }
+        if let Some(from) = from {
+            self.remove(key, from);
+        }
+        if let Some(to) = to {
+            self.insert(key, to);
+        }
+    }
+
+    /// Remove and return (at most `limit` of) the keys that have expired at the given time.
+    pub fn pop_expired(&mut self, now: u64, limit: usize) -> Vec<Arc<K>> {
+        let mut expired: Vec<Arc<K>> = Vec::new();
+
+        while expired.len() < limit {
+            let Some(mut bucket) = self.buckets.first_entry() else {
+                break;
+            };
+            if *bucket.key() > now {
+                break;
+            }
+
+            let wanted: usize = limit - expired.len();
+            if bucket.get().len() <= wanted {
+                expired.extend(bucket.remove());
+            } else {
+                let keys: Vec<Arc<K>> = bucket.get().iter().take(wanted).cloned().collect();
+                for key in &keys {
+                    bucket.get_mut().remove(key);
+                }
+                expired.extend(keys);
+            }
+        }
+
+        expired
+    }
+}
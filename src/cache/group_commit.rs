@@ -0,0 +1,100 @@
+use std::{
+    fs::File,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+/// Makes the writes of a log file durable with group commits.
+///
+/// Every write is numbered. A writer waiting for its write to be durable either finds it already synced,
+/// or waits for the sync in progress, or syncs the file itself. A single `fsync` covers every write issued
+/// before it started, so under concurrent load many writers share each sync, and the durable write
+/// throughput grows with the concurrency instead of being bound to one `fsync` per write.
+pub(crate) struct GroupCommit {
+    written: AtomicU64,        // The number of the last write issued to the file
+    state: Mutex<CommitState>, // The state of the syncs
+    synced: Condvar,           // Notified whenever a sync completes
+}
+
+struct CommitState {
+    file: Arc<File>, // A handle to the log file, used for syncing it
+    synced: u64,     // The number of the last write known to be durable
+    syncing: bool,   // Whether a writer is syncing the file at the moment
+}
+
+impl GroupCommit {
+    /// Create a new `GroupCommit` for the given (duplicated) handle of a log file.
+    pub fn new(file: File) -> GroupCommit {
+        GroupCommit {
+            written: AtomicU64::new(0),
+            state: Mutex::new(CommitState {
+                file: Arc::new(file),
+                synced: 0,
+                syncing: false,
+            }),
+            synced: Condvar::new(),
+        }
+    }
+
+    /// Record a write that has just been issued to the file and return its number, which is then passed to
+    /// `wait_durable`. Writes must be recorded in the order they are issued.
+    pub fn record_write(&self) -> u64 {
+        self.written.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Switch to a new log file (e.g. after a compaction) whose content is already durable, which makes
+    /// every write recorded so far durable.
+    pub fn replace_file(&self, file: File) {
+        let mut state: MutexGuard<CommitState> = self.lock();
+        state.file = Arc::new(file);
+        state.synced = state.synced.max(self.written.load(Ordering::Acquire));
+        self.synced.notify_all();
+    }
+
+    /// Block until the write with the given number is durable, syncing the file if no other writer is.
+    ///
+    /// # Errors
+    /// * The I/O error of the sync covering the write, if this thread ran it. Waiting writers retry with a
+    ///   sync of their own instead.
+    pub fn wait_durable(&self, write: u64) -> io::Result<()> {
+        let mut state: MutexGuard<CommitState> = self.lock();
+
+        loop {
+            if state.synced >= write {
+                return Ok(());
+            }
+
+            if state.syncing {
+                state = self
+                    .synced
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+                continue;
+            }
+
+            // Become the leader of the next group: sync every write issued so far
+            state.syncing = true;
+            let file: Arc<File> = Arc::clone(&state.file);
+            let target: u64 = self.written.load(Ordering::Acquire);
+            drop(state);
+
+            let result: io::Result<()> = file.sync_data();
+
+            state = self.lock();
+            state.syncing = false;
+            if result.is_ok() {
+                state.synced = state.synced.max(target);
+            }
+            self.synced.notify_all();
+
+            result?;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CommitState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CacheResult,
+    utils::{current_monotime, record_blocked},
+};
+
+/// Time-limited recompute leases protecting a cache from stampedes (a.k.a. thundering herds).
+///
+/// When a key is missing, the first caller acquires the lease of the key and is expected to recompute the
+/// value and put it into the cache; the other callers wait until the lease is released instead of hitting
+/// the backend too. Leases expire on their own, so a caller that never comes back cannot block the key
+/// forever. Unlike `SingleFlight`, the recomputation happens outside of the process (e.g. in a client of the
+/// server), which is why the lease has to be released explicitly.
+pub struct RecomputeLeases<K> {
+    leases: Mutex<HashMap<K, u64>>, // The expiration time (monotonic, in milliseconds) of each lease by key
+    released: Condvar,              // Notified whenever a lease is released
+}
+
+impl<K> RecomputeLeases<K> {
+    /// Create a new `RecomputeLeases` with no lease held.
+    pub fn new() -> RecomputeLeases<K> {
+        RecomputeLeases {
+            leases: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+}
+
+impl<K> Default for RecomputeLeases<K> {
+    fn default() -> RecomputeLeases<K> {
+        RecomputeLeases::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone> RecomputeLeases<K> {
+    /// Acquire the lease of the given key, unless another caller holds it.
+    ///
+    /// # Arguments
+    /// * `key` - The key to recompute.
+    /// * `ttl` - The lifetime of the lease in seconds, i.e. how long the caller is given to recompute.
+    ///
+    /// # Returns
+    /// * `true` if the lease was acquired, so the caller has to recompute the value.
+    pub fn try_acquire(&self, key: &K, ttl: u64) -> CacheResult<bool> {
+        let now: u64 = current_monotime();
+        let mut leases = self.leases.lock()?;
+
+        if leases.get(key).is_some_and(|&expires_at| expires_at > now) {
+            return Ok(false);
+        }
+
+        leases.insert(key.clone(), now.saturating_add(ttl.saturating_mul(1000)));
+        Ok(true)
+    }
+
+    /// Release the lease of the given key, if any, waking up the callers waiting for it.
+    pub fn release(&self, key: &K) -> CacheResult<()> {
+        if self.leases.lock()?.remove(key).is_some() {
+            self.released.notify_all();
+        }
+        Ok(())
+    }
+
+    /// Wait until the lease of the given key is released or expires, for at most `timeout`.
+    pub fn wait(&self, key: &K, timeout: Duration) -> CacheResult<()> {
+        let deadline: u64 = current_monotime().saturating_add(timeout.as_millis() as u64);
+        let mut leases = self.leases.lock()?;
+
+        loop {
+            let now: u64 = current_monotime();
+            let Some(&expires_at) = leases.get(key) else {
+                return Ok(());
+            };
+
+            if expires_at <= now {
+                leases.remove(key);
+                return Ok(());
+            }
+            if deadline <= now {
+                return Ok(());
+            }
+
+            let wait: Duration = Duration::from_millis(expires_at.min(deadline) - now);
+            let started: Instant = Instant::now();
+            leases = self.released.wait_timeout(leases, wait)?.0;
+            record_blocked(started);
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use std::{
+    cell::UnsafeCell,
+    collections::HashMap,
+    hash::{BuildHasher, Hash, RandomState},
+    mem,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use super::{
+    entry::CacheEntry, expiry::ExpiryIndex, Cache, PutCondition, ShardedCache, SweepStats,
+};
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::{current_monotime, expires_after},
+};
+
+/// The number of reader counters of each version of a shard. Readers on different threads mostly use
+/// different counters, so they do not contend on a single cache line.
+const READER_STRIPES: usize = 16;
+
+/// A `Cache` implementation whose reads never take a lock, based on the left-right technique.
+///
+/// Every shard keeps two copies of its map: readers use one while the (serialized) writers update the
+/// other, then the copies are swapped and the writer waits for the readers of the old copy to leave before
+/// applying the same change to it. Readers only increment and decrement a counter, so they are never
+/// blocked by writers, and a GET takes a stable, short time under heavy mixed load. The price is paid by
+/// the writers, which apply every change twice and wait for in-flight reads, and by memory, since the
+/// entries are stored twice (keys and values are cloned; use shared values such as `Bytes`).
+pub struct LeftRightCache<K, V> {
+    shards: Box<[LeftRight<K, V>]>, // The shards, each one with its own pair of maps
+    hasher: RandomState,            // Used to map a key to its shard
+    expirations: AtomicU64,         // The number of expired entries deleted so far
+}
+
+type Map<K, V> = HashMap<K, CacheEntry<V>>;
+
+/// A single shard: a pair of maps along with the state coordinating its readers and writers.
+struct LeftRight<K, V> {
+    maps: [UnsafeCell<Map<K, V>>; 2], // The two copies of the map
+    read_index: AtomicUsize, // The copy the new readers use (the other one belongs to the writer)
+    version: AtomicUsize,    // The reader counters the new readers increment (0 or 1)
+    readers: [[StripedCounter; READER_STRIPES]; 2], // The readers in flight, by version
+    writer: Mutex<ExpiryIndex<K>>, // Serializes the writers and holds the expiry index they maintain
+}
+
+// The maps are only mutated by the writer holding the lock, and only once no reader can see them
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for LeftRight<K, V> {}
+unsafe impl<K: Send, V: Send> Send for LeftRight<K, V> {}
+
+/// A reader counter padded to its own cache line.
+#[repr(align(64))]
+#[derive(Default)]
+struct StripedCounter(AtomicUsize);
+
+/// Decrements the reader counter it was created for once the read is over, even if it panics.
+struct ReadGuard<'a>(&'a AtomicUsize);
+
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Release);
+    }
+}
+
+thread_local! {
+    /// The reader stripe of the current thread.
+    static READER_STRIPE: usize = {
+        static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+        NEXT_STRIPE.fetch_add(1, Ordering::Relaxed) % READER_STRIPES
+    };
+}
+
+impl<K, V> LeftRightCache<K, V> {
+    /// Create a new, empty `LeftRightCache` with the given number of shards (at least one).
+    pub fn new(shards: usize) -> LeftRightCache<K, V> {
+        LeftRightCache {
+            shards: (0..shards.max(1)).map(|_| LeftRight::new()).collect(),
+            hasher: RandomState::new(),
+            expirations: AtomicU64::new(0),
+        }
+    }
+
+    /// The number of expired entries deleted so far. Expired entries are deleted when they are accessed.
+    pub fn expirations(&self) -> u64 {
+        self.expirations.load(Ordering::Relaxed)
+    }
+}
+
+impl<K, V> Default for LeftRightCache<K, V> {
+    fn default() -> LeftRightCache<K, V> {
+        LeftRightCache::new(ShardedCache::<K, V>::default_shard_count())
+    }
+}
+
+impl<K: Hash, V> LeftRightCache<K, V> {
+    /// Get the shard responsible for the given key.
+    fn shard(&self, key: &K) -> &LeftRight<K, V> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Get the index of the shard responsible for the given key.
+    fn shard_index(&self, key: &K) -> usize {
+        let hash: u64 = self.hasher.hash_one(key);
+        (hash % self.shards.len() as u64) as usize
+    }
+}
+
+impl<K, V> Cache<K, V> for LeftRightCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        self.shard(&key).write(|expiry, map| {
+            insert_entry(expiry, map, &key, &entry);
+        })
+    }
+
+    /// The values are put shard by shard (readers never wait for a lock), so a reader may see the values
+    /// of some of the shards only.
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        let mut batches: HashMap<usize, Vec<(K, CacheEntry<V>)>> = HashMap::new();
+        for (key, value) in entries {
+            batches
+                .entry(self.shard_index(&key))
+                .or_default()
+                .push((key, CacheEntry::new(value, ttl)));
+        }
+
+        for (index, entries) in batches {
+            self.shards[index].write(|mut expiry, map| {
+                for (key, entry) in &entries {
+                    insert_entry(expiry.as_deref_mut(), map, key, entry);
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        // The time is taken once, so that both copies of the map make the same decision
+        let now: u64 = current_monotime();
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+
+        self.shard(&key).write(|expiry, map| {
+            let present: bool = map.get(&key).is_some_and(|old| !old.is_expired_at(now));
+            if !condition.allows(present) {
+                return false;
+            }
+            insert_entry(expiry, map, &key, &entry);
+            true
+        })
+    }
+
+    /// The value replaced is checked once, by the first application to the copies of the map.
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let now: u64 = current_monotime();
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut replaced: Option<CacheResult<Option<V>>> = None;
+
+        self.shard(&key).write(|expiry, map| {
+            if expiry.is_some() {
+                let previous: Option<V> = map
+                    .get(&key)
+                    .filter(|old| !old.is_expired_at(now))
+                    .map(|old| old.value.clone());
+                replaced = Some(accept(previous.as_ref()).map(|_| previous));
+            }
+            if let Some(Ok(_)) = &replaced {
+                insert_entry(expiry, map, &key, &entry);
+            }
+        })?;
+
+        match replaced {
+            Some(result) => result,
+            None => Err(CacheError::LockPoisoned),
+        }
+    }
+
+    /// The new value is computed once, by the first application to the copies of the map.
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let now: u64 = current_monotime();
+        let mut updated: Option<CacheResult<CacheEntry<V>>> = None;
+
+        self.shard(&key).write(|expiry, map| {
+            if expiry.is_some() {
+                let current: Option<&CacheEntry<V>> =
+                    map.get(&key).filter(|entry| !entry.is_expired_at(now));
+                let (expires_at, ttl): (Option<u64>, Option<u64>) =
+                    current.map_or((None, None), |entry| (entry.expires_at, entry.ttl));
+                updated = Some(
+                    update(current.map(|entry| &entry.value)).map(|value| CacheEntry {
+                        value,
+                        expires_at,
+                        ttl,
+                    }),
+                );
+            }
+            if let Some(Ok(entry)) = &updated {
+                insert_entry(expiry, map, &key, entry);
+            }
+        })?;
+
+        match updated {
+            Some(result) => result.map(|entry| entry.value),
+            None => Err(CacheError::LockPoisoned),
+        }
+    }
+
+    /// A rename across shards holds the writer locks of both (taken in the order of the shards, so that
+    /// two renames cannot deadlock) and inserts into the target before removing from the source: a reader
+    /// may briefly see the value under both keys, but never under neither.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let now: u64 = current_monotime();
+        let (source, target): (usize, usize) = (self.shard_index(from), self.shard_index(&to));
+
+        if source == target {
+            return self.shards[source].write(|mut expiry, map| {
+                let entry: CacheEntry<V> = match map.get(from) {
+                    Some(entry) if !entry.is_expired_at(now) => entry.clone(),
+                    _ => return None,
+                };
+                if *from == to {
+                    return Some(!if_absent);
+                }
+                if if_absent && map.get(&to).is_some_and(|old| !old.is_expired_at(now)) {
+                    return Some(false);
+                }
+                remove_key(expiry.as_deref_mut(), map, from);
+                insert_entry(expiry, map, &to, &entry);
+                Some(true)
+            });
+        }
+
+        let mut first = self.shards[source.min(target)].writer.lock()?;
+        let mut second = self.shards[source.max(target)].writer.lock()?;
+        let (source_expiry, target_expiry) = match source < target {
+            true => (&mut *first, &mut *second),
+            false => (&mut *second, &mut *first),
+        };
+
+        // With the writer locks held, the read copies are up to date and cannot change
+        let entry: CacheEntry<V> = match self.shards[source].read(|map| {
+            map.get(from)
+                .filter(|entry| !entry.is_expired_at(now))
+                .cloned()
+        }) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if if_absent
+            && self.shards[target]
+                .read(|map| map.get(&to).is_some_and(|old| !old.is_expired_at(now)))
+        {
+            return Ok(Some(false));
+        }
+
+        self.shards[target]._write_locked(target_expiry, |expiry, map| {
+            insert_entry(expiry, map, &to, &entry);
+        });
+        self.shards[source]._write_locked(source_expiry, |expiry, map| {
+            remove_key(expiry, map, from);
+        });
+        Ok(Some(true))
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        let shard: &LeftRight<K, V> = self.shard(key);
+
+        let found: Option<Result<V, ()>> = shard.read(|map| {
+            map.get(key).map(|entry| match entry.is_expired() {
+                false => Ok(entry.value.clone()),
+                true => Err(()),
+            })
+        });
+
+        match found {
+            Some(Ok(value)) => Ok(Some(value)),
+            None => Ok(None),
+            Some(Err(())) => {
+                // Expired, so delete it, unless it has been replaced since the read. The time is taken
+                // once, so that both copies of the map make the same decision.
+                let now: u64 = current_monotime();
+                let deleted: bool = shard.write(|expiry, map| {
+                    if !map.get(key).is_some_and(|entry| entry.is_expired_at(now)) {
+                        return false;
+                    }
+                    let old: Option<CacheEntry<V>> = map.remove(key);
+                    if let (Some(expiry), Some(expires_at)) =
+                        (expiry, old.and_then(|old| old.expires_at))
+                    {
+                        expiry.remove(key, expires_at);
+                    }
+                    true
+                })?;
+
+                if deleted {
+                    self.expirations.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        Ok(self.remove_entry(key)?.map(|(value, _)| value))
+    }
+
+    /// The value removed is checked once, by the first application to the copies of the map.
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let now: u64 = current_monotime();
+        let mut accepted: Option<CacheResult<()>> = None;
+
+        let removed: Option<CacheEntry<V>> = self.shard(key).write(|expiry, map| {
+            if expiry.is_some() {
+                let current: Option<&CacheEntry<V>> =
+                    map.get(key).filter(|entry| !entry.is_expired_at(now));
+                accepted = Some(current.map_or(Ok(()), |entry| accept(&entry.value)));
+            }
+            match &accepted {
+                Some(Ok(())) => remove_key(expiry, map, key),
+                _ => None,
+            }
+        })?;
+
+        match accepted {
+            Some(Ok(())) => {}
+            Some(Err(error)) => return Err(error),
+            None => return Err(CacheError::LockPoisoned),
+        }
+        match removed {
+            Some(entry) if entry.is_expired() => {
+                self.expirations.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+            maybe_entry => Ok(maybe_entry.map(|entry| (entry.value, entry.expires_at))),
+        }
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        // The times are taken once, so that both copies of the map make the same change
+        let now: u64 = current_monotime();
+        let expires_at: Option<u64> = ttl.map(|t| expires_after(now, t));
+
+        self.shard(key).write(|expiry, map| {
+            let Some(entry) = map.get_mut(key).filter(|entry| !entry.is_expired_at(now)) else {
+                return false;
+            };
+            let old_expires_at: Option<u64> = entry.expires_at;
+            entry.expires_at = expires_at;
+            entry.ttl = ttl;
+            if let Some(expiry) = expiry {
+                expiry.reschedule(&Arc::new(key.clone()), old_expires_at, expires_at);
+            }
+            true
+        })
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        // The time is taken once, so that both copies of the map make the same change
+        let now: u64 = current_monotime();
+
+        self.shard(key).write(|expiry, map| {
+            let Some(entry) = map.get_mut(key).filter(|entry| !entry.is_expired_at(now)) else {
+                return false;
+            };
+            let old_expires_at: Option<u64> = entry.expires_at;
+            entry.expires_at = entry.ttl.map(|t| expires_after(now, t));
+            if let Some(expiry) = expiry {
+                expiry.reschedule(&Arc::new(key.clone()), old_expires_at, entry.expires_at);
+            }
+            true
+        })
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        Ok(self.shard(key).read(|map| {
+            map.get(key)
+                .filter(|entry| !entry.is_expired())
+                .map(|entry| entry.expires_at)
+        }))
+    }
+
+    /// Delete the expired entries found through the expiry index of every shard, one shard at a time,
+    /// each shard taking an equal share of `max_expired`.
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let mut stats: SweepStats = SweepStats::default();
+        let per_shard: usize = max_expired.div_ceil(self.shards.len().max(1));
+
+        for shard in self.shards.iter() {
+            let mut expired: Vec<Arc<K>> = Vec::new();
+            shard.write(|expiry, map| {
+                // The keys are popped from the index by the first application, then reused by the second
+                if let Some(expiry) = expiry {
+                    expired = expiry.pop_expired(current_monotime(), per_shard);
+                }
+                for key in &expired {
+                    map.remove(&**key);
+                }
+            })?;
+
+            stats.examined += expired.len();
+            stats.expired += expired.len();
+        }
+
+        self.expirations
+            .fetch_add(stats.expired as u64, Ordering::Relaxed);
+        Ok(stats)
+    }
+
+    /// Count under the lock of the writers of each shard, so its map and expiry index agree.
+    fn len(&self) -> CacheResult<usize> {
+        let now: u64 = current_monotime();
+        let mut len: usize = 0;
+        for shard in self.shards.iter() {
+            let expiry = shard.writer.lock()?;
+            len += shard.read(|map| map.len()) - expiry.count_expired(now);
+        }
+        Ok(len)
+    }
+
+    /// Take both copies of the map of every shard out, one shard at a time.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let mut cleared: Vec<Map<K, V>> = Vec::with_capacity(2 * self.shards.len());
+        for shard in self.shards.iter() {
+            shard.write(|expiry, map| {
+                if let Some(expiry) = expiry {
+                    *expiry = ExpiryIndex::new();
+                }
+                cleared.push(mem::take(map));
+            })?;
+        }
+        Ok(Box::new(cleared))
+    }
+}
+
+/// Insert (or replace) an entry into a copy of the map, keeping the expiry index in sync when it is given
+/// (see `LeftRight::write`), and return the entry replaced.
+fn insert_entry<K, V>(
+    expiry: Option<&mut ExpiryIndex<K>>,
+    map: &mut Map<K, V>,
+    key: &K,
+    entry: &CacheEntry<V>,
+) -> Option<CacheEntry<V>>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    let old: Option<CacheEntry<V>> = map.insert(key.clone(), entry.clone());
+    if let Some(expiry) = expiry {
+        expiry.reschedule(
+            &Arc::new(key.clone()),
+            old.as_ref().and_then(|old| old.expires_at),
+            entry.expires_at,
+        );
+    }
+    old
+}
+
+/// Remove an entry from a copy of the map, keeping the expiry index in sync when it is given (see
+/// `LeftRight::write`).
+fn remove_key<K, V>(
+    expiry: Option<&mut ExpiryIndex<K>>,
+    map: &mut Map<K, V>,
+    key: &K,
+) -> Option<CacheEntry<V>>
+where
+    K: Eq + Hash,
+{
+    let old: Option<CacheEntry<V>> = map.remove(key);
+    if let (Some(expiry), Some(expires_at)) = (expiry, old.as_ref().and_then(|old| old.expires_at))
+    {
+        expiry.remove(key, expires_at);
+    }
+    old
+}
+
+impl<K, V> LeftRight<K, V> {
+    fn new() -> LeftRight<K, V> {
+        LeftRight {
+            maps: [
+                UnsafeCell::new(HashMap::new()),
+                UnsafeCell::new(HashMap::new()),
+            ],
+            read_index: AtomicUsize::new(0),
+            version: AtomicUsize::new(0),
+            readers: Default::default(),
+            writer: Mutex::new(ExpiryIndex::new()),
+        }
+    }
+
+    /// Run a read-only operation on the copy of the map the readers currently use, without locking.
+    fn read<R>(&self, operation: impl FnOnce(&Map<K, V>) -> R) -> R {
+        let stripe: usize = READER_STRIPE.with(|stripe| *stripe);
+        let version: usize = self.version.load(Ordering::SeqCst);
+
+        let counter: &AtomicUsize = &self.readers[version][stripe].0;
+        counter.fetch_add(1, Ordering::SeqCst);
+        let _guard: ReadGuard = ReadGuard(counter);
+
+        // SAFETY: the writer does not touch this copy until our counter is back to zero
+        let map: &Map<K, V> = unsafe { &*self.maps[self.read_index.load(Ordering::SeqCst)].get() };
+        operation(map)
+    }
+
+    /// Apply a change to both copies of the map, one after the other, and return the result of the first
+    /// application. The operation must be deterministic (e.g. it must not look at the clock), so that both
+    /// copies end up identical. The expiry index is only given to the first application.
+    fn write<R>(
+        &self,
+        operation: impl FnMut(Option<&mut ExpiryIndex<K>>, &mut Map<K, V>) -> R,
+    ) -> CacheResult<R> {
+        let mut expiry = self.writer.lock()?;
+        Ok(self._write_locked(&mut expiry, operation))
+    }
+
+    /// Apply a change like `write`, with the writer lock already held by the caller (the given expiry index
+    /// is the one it guards).
+    fn _write_locked<R>(
+        &self,
+        expiry: &mut ExpiryIndex<K>,
+        mut operation: impl FnMut(Option<&mut ExpiryIndex<K>>, &mut Map<K, V>) -> R,
+    ) -> R {
+        // SAFETY: the readers never use the copy that is not the read one, and writers are serialized
+        let write_index: usize = 1 - self.read_index.load(Ordering::SeqCst);
+        let result: R = operation(Some(expiry), unsafe { &mut *self.maps[write_index].get() });
+
+        // Publish the updated copy, then wait until no reader uses the other one anymore
+        self.read_index.store(write_index, Ordering::SeqCst);
+        self.wait_for_readers();
+
+        // SAFETY: all readers use the updated copy now
+        operation(None, unsafe { &mut *self.maps[1 - write_index].get() });
+        result
+    }
+
+    /// Wait until every reader that may have seen the previous read copy has left. Readers that start
+    /// meanwhile increment the counters of the new version, so the wait always ends.
+    fn wait_for_readers(&self) {
+        let previous: usize = self.version.load(Ordering::SeqCst);
+        let next: usize = 1 - previous;
+
+        // Stragglers of the version before the previous one may still be in flight
+        self.wait_for_version(next);
+        self.version.store(next, Ordering::SeqCst);
+        self.wait_for_version(previous);
+    }
+
+    fn wait_for_version(&self, version: usize) {
+        while self.readers[version]
+            .iter()
+            .any(|counter| counter.0.load(Ordering::SeqCst) != 0)
+        {
+            thread::yield_now();
+        }
+    }
+}
@@ -0,0 +1,653 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    mem,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use super::{
+    entry::CacheEntry,
+    expiry::ExpiryIndex,
+    listener::{Removal, RemovalCause, RemovalListener},
+    memory::{entry_size, HeapSize, MaxMemoryPolicy, MemoryStats},
+    Cache, PutCondition, SweepStats,
+};
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::current_monotime,
+};
+
+/// How many accesses per entry the cache can hold are served before the access counts are halved (see
+/// `LfuCache`).
+const DECAY_ACCESSES_PER_ENTRY: u64 = 8;
+
+/// A `Cache` implementation holding at most a given number of entries: once full, every new key evicts
+/// the least frequently used one (the least recently used one among those used as often).
+///
+/// Every read and write of an entry counts as an access. So that the keys which were hot long ago do not
+/// stay forever, the access counts of all the entries decay: they are halved every time the cache has
+/// served 8 accesses per entry it can hold. Unlike LRU, a burst of keys read once (e.g. a scan) cannot
+/// evict the hot keys, which suits workloads with a stable set of hot keys and a long tail of cold ones.
+///
+/// Marking an entry as used and evicting one take O(log n), and the decay O(n) amortized over the
+/// accesses in between.
+///
+/// The cache can also be bounded by the memory taken by its entries (see `with_max_memory`), counting
+/// their keys and values along with the bookkeeping of the cache.
+pub struct LfuCache<K, V> {
+    entries: Mutex<LfuEntries<K, V>>,
+    max_entries: usize, // The maximum number of entries, beyond which the least frequently used are evicted
+    listener: Option<Arc<dyn RemovalListener<K, V>>>, // Notified of the entries removed, if any
+}
+
+/// An entry of an `LfuCache` along with its place in the eviction order.
+struct LfuEntry<V> {
+    entry: CacheEntry<V>,
+    size: usize,      // The bytes taken by the entry (see `memory::entry_size`)
+    uses: u64,        // The (decayed) number of accesses
+    last_access: u64, // The tick of the last access, breaking the ties between entries used as often
+}
+
+struct LfuEntries<K, V> {
+    map: HashMap<Arc<K>, LfuEntry<V>>,    // The entries by key
+    order: BTreeMap<(u64, u64), Arc<K>>, // The keys by access count and tick, the next one evicted first
+    expiry: ExpiryIndex<K>,              // The keys of the entries with a TTL by expiration time
+    tick: u64,                           // A logical clock incremented on every access
+    used_bytes: usize,                   // The bytes taken by the entries
+    decay_at: u64,                       // The tick at which the access counts are halved next
+    evictions: u64,                      // The number of entries evicted so far
+    expirations: u64,                    // The number of expired entries deleted so far
+    removals: Option<Vec<Removal<K, V>>>, // The entries removed for the listener, if the cache has one
+    max_bytes: Option<usize>,             // The most memory the entries may take, if limited
+    max_memory_policy: MaxMemoryPolicy,   // What happens to the writes beyond `max_bytes`
+}
+
+impl<K, V> LfuCache<K, V> {
+    /// Create a new, empty `LfuCache`.
+    ///
+    /// # Arguments
+    /// * `max_entries` - The maximum number of entries kept (at least one).
+    pub fn new(max_entries: usize) -> LfuCache<K, V> {
+        let max_entries: usize = max_entries.max(1);
+        LfuCache {
+            entries: Mutex::new(LfuEntries {
+                map: HashMap::new(),
+                order: BTreeMap::new(),
+                expiry: ExpiryIndex::new(),
+                tick: 0,
+                used_bytes: 0,
+                decay_at: decay_period(max_entries),
+                evictions: 0,
+                expirations: 0,
+                removals: None,
+                max_bytes: None,
+                max_memory_policy: MaxMemoryPolicy::default(),
+            }),
+            max_entries,
+            listener: None,
+        }
+    }
+
+    /// Bound the cache by the memory taken by its entries as well.
+    ///
+    /// # Arguments
+    /// * `max_bytes` - The most bytes the entries may take.
+    /// * `policy` - Whether the writes beyond evict the least frequently used entries, or are rejected.
+    pub fn with_max_memory(mut self, max_bytes: usize, policy: MaxMemoryPolicy) -> LfuCache<K, V> {
+        let entries = self
+            .entries
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        entries.max_bytes = Some(max_bytes);
+        entries.max_memory_policy = policy;
+        self
+    }
+
+    /// Notify the given listener of the entries removed, expired or evicted (see `RemovalListener`).
+    pub fn with_removal_listener(
+        mut self,
+        listener: impl RemovalListener<K, V> + 'static,
+    ) -> LfuCache<K, V> {
+        self.entries
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .removals = Some(Vec::new());
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// The maximum number of entries kept.
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Release the lock of the entries, then hand the entries removed under it over to the listener.
+    fn _notify(&self, mut entries: MutexGuard<'_, LfuEntries<K, V>>) {
+        let removals: Vec<Removal<K, V>> = match &mut entries.removals {
+            Some(removals) if !removals.is_empty() => mem::take(removals),
+            _ => return,
+        };
+        drop(entries);
+
+        if let Some(listener) = &self.listener {
+            for (key, value, cause) in removals {
+                listener.on_removal(&key, &value, cause);
+            }
+        }
+    }
+
+    /// The memory taken by the entries, and the number of entries evicted so far.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        MemoryStats {
+            used_bytes: entries.used_bytes,
+            max_bytes: entries.max_bytes,
+            evictions: entries.evictions,
+        }
+    }
+
+    /// The number of entries evicted so far to make room for new ones.
+    pub fn evictions(&self) -> u64 {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .evictions
+    }
+
+    /// The number of expired entries deleted so far.
+    pub fn expirations(&self) -> u64 {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .expirations
+    }
+}
+
+/// The number of accesses between two decays of the access counts of a cache of the given capacity.
+fn decay_period(max_entries: usize) -> u64 {
+    (max_entries as u64).saturating_mul(DECAY_ACCESSES_PER_ENTRY)
+}
+
+/// The bytes taken by the bookkeeping of an entry of an `LfuCache`: its slot in the map and in the
+/// eviction order.
+fn entry_overhead<K, V>() -> usize {
+    mem::size_of::<(Arc<K>, LfuEntry<V>)>() + mem::size_of::<((u64, u64), Arc<K>)>()
+}
+
+impl<K: Eq + Hash, V: Clone> LfuEntries<K, V> {
+    /// Get the value of the given key, counting the access. An expired entry is deleted instead.
+    fn lookup(&mut self, key: &K, max_entries: usize) -> Option<V> {
+        let value: V = match self.map.get(key) {
+            Some(lfu) if !lfu.entry.is_expired() => lfu.entry.value.clone(),
+            Some(_) => {
+                if let Some((key, entry)) = self.remove(key) {
+                    self.record(key, entry.value, RemovalCause::Expired);
+                }
+                self.expirations += 1;
+                return None;
+            }
+            None => return None,
+        };
+
+        self.touch(key, max_entries);
+        Some(value)
+    }
+}
+
+impl<K: Eq + Hash, V> LfuEntries<K, V> {
+    /// Count an access, halving the access counts of all the entries when they are due to decay.
+    fn next_tick(&mut self, max_entries: usize) -> u64 {
+        self.tick += 1;
+        if self.tick >= self.decay_at {
+            self.decay_at = self.tick.saturating_add(decay_period(max_entries));
+
+            let order: BTreeMap<(u64, u64), Arc<K>> = mem::take(&mut self.order);
+            for ((uses, last_access), key) in order {
+                if let Some(lfu) = self.map.get_mut(&key) {
+                    lfu.uses = uses / 2;
+                }
+                self.order.insert((uses / 2, last_access), key);
+            }
+        }
+        self.tick
+    }
+
+    /// Insert an entry for a key which has none, taking the given bytes and used as many times as given
+    /// (this one included), keeping the expiry index in sync.
+    fn insert(&mut self, key: K, entry: CacheEntry<V>, uses: u64, size: usize, max_entries: usize) {
+        let tick: u64 = self.next_tick(max_entries);
+        let key: Arc<K> = Arc::new(key);
+
+        self.order.insert((uses, tick), Arc::clone(&key));
+        if let Some(expires_at) = entry.expires_at {
+            self.expiry.insert(&key, expires_at);
+        }
+        self.used_bytes += size;
+        self.map.insert(
+            key,
+            LfuEntry {
+                entry,
+                size,
+                uses,
+                last_access: tick,
+            },
+        );
+    }
+
+    /// Count a use of the entry of the given key, which must exist.
+    fn touch(&mut self, key: &K, max_entries: usize) {
+        let tick: u64 = self.next_tick(max_entries);
+        let Some((key, lfu)) = self.map.get_key_value(key) else {
+            return;
+        };
+        let (key, uses, last_access): (Arc<K>, u64, u64) =
+            (Arc::clone(key), lfu.uses, lfu.last_access);
+
+        self.order.remove(&(uses, last_access));
+        self.order
+            .insert((uses.saturating_add(1), tick), Arc::clone(&key));
+        if let Some(lfu) = self.map.get_mut(&key) {
+            lfu.uses = uses.saturating_add(1);
+            lfu.last_access = tick;
+        }
+    }
+
+    /// Remove the entry of the given key along with its access count, keeping the eviction order and the
+    /// expiry index in sync.
+    fn take(&mut self, key: &K) -> Option<(Arc<K>, LfuEntry<V>)> {
+        let (key, lfu): (Arc<K>, LfuEntry<V>) = self.map.remove_entry(key)?;
+        self.order.remove(&(lfu.uses, lfu.last_access));
+        if let Some(expires_at) = lfu.entry.expires_at {
+            self.expiry.remove(&key, expires_at);
+        }
+        self.used_bytes -= lfu.size;
+        Some((key, lfu))
+    }
+
+    /// Remove the entry of the given key, if any.
+    fn remove(&mut self, key: &K) -> Option<(Arc<K>, CacheEntry<V>)> {
+        let (key, lfu): (Arc<K>, LfuEntry<V>) = self.take(key)?;
+        Some((key, lfu.entry))
+    }
+
+    /// Keep an entry removed for the listener of the cache, if it has one.
+    fn record(&mut self, key: Arc<K>, value: V, cause: RemovalCause) {
+        if let Some(removals) = &mut self.removals {
+            removals.push((key, value, cause));
+        }
+    }
+
+    /// Change the TTL of a live entry with the given function (e.g. `CacheEntry::touch`), without counting it as a use, keeping the expiry index in sync.
+    ///
+    /// # Returns
+    /// * `false` if the key has no live entry.
+    fn retime(&mut self, key: &K, retime: impl FnOnce(&mut CacheEntry<V>)) -> bool {
+        let Some((key, lfu)) = self.map.get_key_value(key) else {
+            return false;
+        };
+        if lfu.entry.is_expired() {
+            return false;
+        }
+        let key: Arc<K> = Arc::clone(key);
+
+        let Some(lfu) = self.map.get_mut(&key) else {
+            return false;
+        };
+        let old_expires_at: Option<u64> = lfu.entry.expires_at;
+        retime(&mut lfu.entry);
+        self.expiry
+            .reschedule(&key, old_expires_at, lfu.entry.expires_at);
+        true
+    }
+
+    /// Evict the least frequently used entries until at most `max_entries` are left, taking at most
+    /// `max_bytes` (if limited). Expired entries are not counted as evicted.
+    fn evict_overflow(&mut self, max_entries: usize, max_bytes: Option<usize>) {
+        while self.map.len() > max_entries || max_bytes.is_some_and(|max| self.used_bytes > max) {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            let Some(lfu) = self.map.remove(&key) else {
+                continue;
+            };
+            self.used_bytes -= lfu.size;
+
+            if let Some(expires_at) = lfu.entry.expires_at {
+                self.expiry.remove(&key, expires_at);
+            }
+            match lfu.entry.is_expired() {
+                true => {
+                    self.record(key, lfu.entry.value, RemovalCause::Expired);
+                    self.expirations += 1;
+                }
+                false => {
+                    self.record(key, lfu.entry.value, RemovalCause::Evicted);
+                    self.evictions += 1;
+                }
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash + HeapSize, V: HeapSize> LfuCache<K, V> {
+    /// Put an entry under the lock of the entries, making room for it.
+    fn _put(
+        &self,
+        entries: &mut LfuEntries<K, V>,
+        key: K,
+        entry: CacheEntry<V>,
+    ) -> CacheResult<()> {
+        let size: usize = entry_size(&key, &entry.value, entry_overhead::<K, V>());
+        let replaced: usize = entries.map.get(&key).map_or(0, |lfu| lfu.size);
+        self._check_memory(entries, size, replaced)?;
+
+        // Make room before inserting, as the entry may be used the least and would be evicted right away
+        let uses: u64 = entries.take(&key).map_or(0, |(_, old)| old.uses);
+        entries.evict_overflow(
+            self.max_entries - 1,
+            entries.max_bytes.map(|max_bytes| max_bytes - size),
+        );
+        entries.insert(key, entry, uses.saturating_add(1), size, self.max_entries);
+        Ok(())
+    }
+
+    /// Check that an entry taking the given bytes fits in the max memory of the cache (if limited), once
+    /// the given bytes are freed.
+    fn _check_memory(
+        &self,
+        entries: &LfuEntries<K, V>,
+        size: usize,
+        freed: usize,
+    ) -> CacheResult<()> {
+        if let Some(max_bytes) = entries.max_bytes {
+            let evicting: bool = entries.max_memory_policy == MaxMemoryPolicy::Evict;
+            if size > max_bytes || (!evicting && entries.used_bytes - freed + size > max_bytes) {
+                return Err(CacheError::OutOfMemory);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> Cache<K, V> for LfuCache<K, V>
+where
+    K: Eq + Hash + HeapSize + Send + Sync,
+    V: Clone + HeapSize + Send + Sync,
+{
+    /// # Errors
+    /// * `CacheError::OutOfMemory` if the entry does not fit in the max memory of the cache, or would take
+    ///   the cache over it with `MaxMemoryPolicy::NoEviction`.
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut entries = self.entries.lock()?;
+        let result: CacheResult<()> = self._put(&mut entries, key, entry);
+        self._notify(entries);
+        result
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        let mut locked = self.entries.lock()?;
+        let result: CacheResult<()> = entries
+            .into_iter()
+            .try_for_each(|(key, value)| self._put(&mut locked, key, CacheEntry::new(value, ttl)));
+        self._notify(locked);
+        result
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut entries = self.entries.lock()?;
+        let present: bool = entries
+            .map
+            .get(&key)
+            .is_some_and(|lfu| !lfu.entry.is_expired());
+        if !condition.allows(present) {
+            return Ok(false);
+        }
+
+        let result: CacheResult<()> = self._put(&mut entries, key, entry);
+        self._notify(entries);
+        result.map(|_| true)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let mut entries = self.entries.lock()?;
+        let current: Option<&CacheEntry<V>> = entries
+            .map
+            .get(&key)
+            .map(|lfu| &lfu.entry)
+            .filter(|entry| !entry.is_expired());
+        let (expires_at, ttl): (Option<u64>, Option<u64>) =
+            current.map_or((None, None), |entry| (entry.expires_at, entry.ttl));
+        let value: V = update(current.map(|entry| &entry.value))?;
+
+        let entry: CacheEntry<V> = CacheEntry {
+            value: value.clone(),
+            expires_at,
+            ttl,
+        };
+        let result: CacheResult<()> = self._put(&mut entries, key, entry);
+        self._notify(entries);
+        result.map(|_| value)
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let mut entries = self.entries.lock()?;
+        let previous: Option<V> = entries
+            .map
+            .get(&key)
+            .map(|lfu| &lfu.entry)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.value.clone());
+        accept(previous.as_ref())?;
+
+        let result: CacheResult<()> = self._put(&mut entries, key, CacheEntry::new(value, ttl));
+        self._notify(entries);
+        result.map(|_| previous)
+    }
+
+    /// The entry moved keeps its access count, counting the move as a use.
+    ///
+    /// # Errors
+    /// * `CacheError::OutOfMemory` if the entry does not fit in the max memory of the cache under its new
+    ///   key (see `put`), in which case it is left under `from`.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let mut entries = self.entries.lock()?;
+        let Some(lfu) = entries.map.get(from).filter(|lfu| !lfu.entry.is_expired()) else {
+            return Ok(None);
+        };
+        let replaced: Option<&LfuEntry<V>> = entries.map.get(&to);
+        if *from == to || (if_absent && replaced.is_some_and(|lfu| !lfu.entry.is_expired())) {
+            return Ok(Some(!if_absent));
+        }
+
+        let size: usize = entry_size(&to, &lfu.entry.value, entry_overhead::<K, V>());
+        let freed: usize = lfu.size + replaced.map_or(0, |lfu| lfu.size);
+        self._check_memory(&entries, size, freed)?;
+
+        let Some((_, lfu)) = entries.take(from) else {
+            return Ok(None);
+        };
+        entries.take(&to);
+        let max_bytes: Option<usize> = entries.max_bytes.map(|max_bytes| max_bytes - size);
+        entries.evict_overflow(self.max_entries - 1, max_bytes);
+        entries.insert(
+            to,
+            lfu.entry,
+            lfu.uses.saturating_add(1),
+            size,
+            self.max_entries,
+        );
+        self._notify(entries);
+        Ok(Some(true))
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        let mut entries = self.entries.lock()?;
+        let value: Option<V> = entries.lookup(key, self.max_entries);
+        self._notify(entries);
+        Ok(value)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        let mut entries = self.entries.lock()?;
+        let values: Vec<Option<V>> = keys
+            .iter()
+            .map(|key| entries.lookup(key, self.max_entries))
+            .collect();
+        self._notify(entries);
+        Ok(values)
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        Ok(self.remove_entry(key)?.map(|(value, _)| value))
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let mut entries = self.entries.lock()?;
+        let current: Option<&CacheEntry<V>> = entries.map.get(key).map(|lfu| &lfu.entry);
+        if let Some(entry) = current.filter(|entry| !entry.is_expired()) {
+            accept(&entry.value)?;
+        }
+        let removed: Option<(V, Option<u64>)> = match entries.remove(key) {
+            Some((key, entry)) if entry.is_expired() => {
+                entries.record(key, entry.value, RemovalCause::Expired);
+                entries.expirations += 1;
+                None
+            }
+            Some((key, entry)) => {
+                if entries.removals.is_some() {
+                    entries.record(key, entry.value.clone(), RemovalCause::Removed);
+                }
+                Some((entry.value, entry.expires_at))
+            }
+            None => None,
+        };
+        self._notify(entries);
+        Ok(removed)
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        Ok(self.entries.lock()?.retime(key, |entry| entry.set_ttl(ttl)))
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        Ok(self.entries.lock()?.retime(key, CacheEntry::touch))
+    }
+
+    /// Tell the expiration time without counting it as a use of the entry.
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        let entries = self.entries.lock()?;
+        Ok(entries
+            .map
+            .get(key)
+            .filter(|lfu| !lfu.entry.is_expired())
+            .map(|lfu| lfu.entry.expires_at))
+    }
+
+    /// Delete the expired entries found through the expiry index, so only they are examined.
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let mut entries = self.entries.lock()?;
+
+        let expired: Vec<Arc<K>> = entries.expiry.pop_expired(current_monotime(), max_expired);
+        for key in &expired {
+            // Already out of the expiry index, so only taken out of the map and the eviction order
+            if let Some((key, lfu)) = entries.map.remove_entry(&**key) {
+                entries.order.remove(&(lfu.uses, lfu.last_access));
+                entries.used_bytes -= lfu.size;
+                entries.record(key, lfu.entry.value, RemovalCause::Expired);
+            }
+        }
+
+        entries.expirations += expired.len() as u64;
+        self._notify(entries);
+        Ok(SweepStats {
+            examined: expired.len(),
+            expired: expired.len(),
+        })
+    }
+
+    /// Visit the live entries under the lock, without counting their accesses.
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        self.scan_with_expirations(&mut |key, value, _| {
+            visit(key, value);
+            true
+        })
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        let entries = self.entries.lock()?;
+        let now: u64 = current_monotime();
+
+        entries.map.iter().all(|(key, lfu)| {
+            lfu.entry.is_expired_at(now) || visit(key, &lfu.entry.value, lfu.entry.expires_at)
+        });
+        Ok(())
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        let entries = self.entries.lock()?;
+        Ok(entries.map.len() - entries.expiry.count_expired(current_monotime()))
+    }
+
+    /// Take the entries out under the lock, keeping the counters and the access clock.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let mut entries = self.entries.lock()?;
+        entries.used_bytes = 0;
+        Ok(Box::new((
+            mem::take(&mut entries.map),
+            mem::take(&mut entries.order),
+            mem::replace(&mut entries.expiry, ExpiryIndex::new()),
+        )))
+    }
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        Some(LfuCache::memory_stats(self))
+    }
+
+    fn memory_usage(&self, key: &K) -> CacheResult<Option<usize>> {
+        let entries = self.entries.lock()?;
+        Ok(entries
+            .map
+            .get(key)
+            .filter(|entry| !entry.entry.is_expired())
+            .map(|entry| entry.size))
+    }
+
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        let mut entries = self.entries.lock()?;
+        entries.max_bytes = max_bytes;
+        entries.max_memory_policy = policy;
+        if policy == MaxMemoryPolicy::Evict {
+            entries.evict_overflow(self.max_entries, max_bytes);
+        }
+        self._notify(entries);
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+/// Why an entry left a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalCause {
+    /// The entry was removed by a caller (e.g. with `Cache::remove`).
+    Removed,
+
+    /// The TTL of the entry elapsed.
+    Expired,
+
+    /// The entry was evicted to make room for others, as the eviction policy of the cache says.
+    Evicted,
+}
+
+impl RemovalCause {
+    /// The name of the cause, e.g. for the labels of metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RemovalCause::Removed => "removed",
+            RemovalCause::Expired => "expired",
+            RemovalCause::Evicted => "evicted",
+        }
+    }
+}
+
+/// Notified of the entries leaving a bounded cache (see `LruCache::with_removal_listener`), e.g. to write the
+/// evicted entries back to a slower store, or to count the removals by cause.
+///
+/// The listener is called on the thread whose operation removed the entries, once the cache has released
+/// its lock, so it may use the cache. An entry replaced by a new value of its key is not removed.
+///
+/// Implemented for the closures taking the key, the value and the cause of the removal:
+///
+/// ```no_run
+/// use myrustcache::{cache::RemovalCause, Cache, LruCache};
+///
+/// let cache = LruCache::new(10_000).with_removal_listener(
+///     |key: &String, value: &String, cause: RemovalCause| {
+///         if cause == RemovalCause::Evicted {
+///             println!("write {} = {} back to the database", key, value);
+///         }
+///     },
+/// );
+/// cache.put("x".to_string(), "ABC".to_string(), Some(60))?;
+/// # Ok::<(), myrustcache::CacheError>(())
+/// ```
+pub trait RemovalListener<K, V>: Send + Sync {
+    /// Called with an entry which left the cache.
+    fn on_removal(&self, key: &K, value: &V, cause: RemovalCause);
+}
+
+impl<K, V, F> RemovalListener<K, V> for F
+where
+    F: Fn(&K, &V, RemovalCause) + Send + Sync,
+{
+    fn on_removal(&self, key: &K, value: &V, cause: RemovalCause) {
+        self(key, value, cause)
+    }
+}
+
+/// An entry removed under the lock of a cache, until it is handed over to the removal listener.
+pub(super) type Removal<K, V> = (Arc<K>, V, RemovalCause);
@@ -0,0 +1,287 @@
+use std::{error::Error, fmt, hash::Hash, sync::Arc};
+
+use super::{
+    singleflight::SingleFlight, Cache, MaxMemoryPolicy, MemoryStats, PutCondition, SweepStats,
+};
+use crate::error::{CacheError, CacheResult};
+
+/// The result of a loader: the loaded value, `None` if the key does not exist in the source, or an error.
+pub type LoadResult<V> = Result<Option<V>, Box<dyn Error + Send + Sync>>;
+
+/// A loader registered with a `LoadingCache`.
+type Loader<K, V> = Box<dyn Fn(&K) -> LoadResult<V> + Send + Sync>;
+
+/// A read-through cache: misses are filled by a loader (e.g. a database query) registered up front.
+///
+/// Concurrent misses for the same key are deduplicated, so the loader runs once while the other callers
+/// wait for its result. The loader is blocking; an async loader can be adapted by blocking on its future
+/// inside the closure.
+///
+/// ```no_run
+/// use myrustcache::{cache::LoadingCache, CacheFactory};
+///
+/// let users = LoadingCache::new(CacheFactory::new_cache(), Some(300), |id: &u64| {
+///     Ok(Some(format!("user-{}", id))) // Replace with a database query
+/// });
+///
+/// let user: Option<String> = users.get_or_load(&42)?;
+/// # Ok::<(), myrustcache::CacheError>(())
+/// ```
+///
+/// When the value depends on the caller, it can be computed on the spot instead (see `get_or_compute`):
+///
+/// ```no_run
+/// use myrustcache::{cache::LoadingCache, CacheFactory};
+///
+/// let reports = LoadingCache::without_loader(CacheFactory::new_cache());
+/// let report: String = reports.get_or_compute(&"daily".to_string(), Some(60), || {
+///     Ok::<_, String>("...".to_string()) // Replace with an expensive computation
+/// })?;
+/// # Ok::<(), myrustcache::CacheError>(())
+/// ```
+pub struct LoadingCache<K, V> {
+    cache: Arc<dyn Cache<K, V>>,                    // Holds the loaded values
+    loader: Loader<K, V>,                           // Invoked on cache misses
+    ttl: Option<u64>,                               // The TTL (in seconds) of the loaded values
+    loads: SingleFlight<K, CacheResult<Option<V>>>, // The loads in flight by key
+}
+
+impl<K, V> LoadingCache<K, V> {
+    /// Create a new `LoadingCache`.
+    ///
+    /// # Arguments
+    /// * `cache` - The cache holding the loaded values.
+    /// * `ttl` - The time-to-live (in seconds) of the loaded values, or `None` to keep them indefinitely.
+    /// * `loader` - Loads the value of a key missing from the cache.
+    pub fn new(
+        cache: Arc<dyn Cache<K, V>>,
+        ttl: Option<u64>,
+        loader: impl Fn(&K) -> LoadResult<V> + Send + Sync + 'static,
+    ) -> LoadingCache<K, V> {
+        LoadingCache {
+            cache,
+            loader: Box::new(loader),
+            ttl,
+            loads: SingleFlight::new(),
+        }
+    }
+
+    /// Create a new `LoadingCache` without a loader, whose misses are only filled by `get_or_compute`.
+    pub fn without_loader(cache: Arc<dyn Cache<K, V>>) -> LoadingCache<K, V> {
+        LoadingCache::new(cache, None, |_| Ok(None))
+    }
+}
+
+impl<K, V> LoadingCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Get the value of the given key, loading (and caching) it on a miss.
+    ///
+    /// # Returns
+    /// * The cached or loaded value, or `None` if the loader did not find the key (which is not cached).
+    ///
+    /// # Errors
+    /// * `CacheError::Loader` if the loader failed. Every caller waiting for that load receives the error.
+    pub fn get_or_load(&self, key: &K) -> CacheResult<Option<V>> {
+        if let Some(value) = self.cache.get(key)? {
+            return Ok(Some(value));
+        }
+
+        self.loads.run(key, || {
+            // A load that completed while we were checking the cache may have filled it already
+            if let Some(value) = self.cache.get(key)? {
+                return Ok(Some(value));
+            }
+
+            match (self.loader)(key) {
+                Ok(Some(value)) => {
+                    self.cache.put(key.clone(), value.clone(), self.ttl)?;
+                    Ok(Some(value))
+                }
+                Ok(None) => Ok(None),
+                Err(e) => Err(CacheError::Loader(e.to_string())),
+            }
+        })
+    }
+
+    /// Get the value of the given key, computing (and caching) it on a miss, e.g. for the values computed
+    /// differently by every caller, rather than by the loader registered up front.
+    ///
+    /// Concurrent misses for the same key are deduplicated, along with those of `get_or_load`: a single
+    /// caller computes the value while the others wait for it, and drop their own computation.
+    ///
+    /// # Arguments
+    /// * `key` - The key of the value.
+    /// * `ttl` - The time-to-live (in seconds) of the computed value, or `None` to keep it indefinitely.
+    /// * `compute` - Computes the value of the key missing from the cache.
+    ///
+    /// # Errors
+    /// * `CacheError::Loader` if the computation failed. Every caller waiting for it receives the error, and
+    ///   nothing is cached.
+    pub fn get_or_compute<E: fmt::Display>(
+        &self,
+        key: &K,
+        ttl: Option<u64>,
+        compute: impl FnOnce() -> Result<V, E>,
+    ) -> CacheResult<V> {
+        if let Some(value) = self.cache.get(key)? {
+            return Ok(value);
+        }
+
+        let mut compute: Option<_> = Some(compute);
+        loop {
+            let loaded: Option<V> = self.loads.run(key, || {
+                // A computation that completed while we were checking the cache may have filled it already
+                if let Some(value) = self.cache.get(key)? {
+                    return Ok(Some(value));
+                }
+
+                let compute = compute.take().expect("a computation runs once");
+                match compute() {
+                    Ok(value) => {
+                        self.cache.put(key.clone(), value.clone(), ttl)?;
+                        Ok(Some(value))
+                    }
+                    Err(e) => Err(CacheError::Loader(e.to_string())),
+                }
+            })?;
+
+            // Only a load of `get_or_load` which did not find the key leaves it missing: compute it then
+            if let Some(value) = loaded {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+impl<K, V> Cache<K, V> for LoadingCache<K, V>
+where
+    K: Send + Sync,
+    V: Send + Sync,
+{
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        self.cache.put(key, value, ttl)
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        self.cache.put_if(key, value, ttl, condition)
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        self.cache.put_many(entries, ttl)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        self.cache.update(key, update)
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        self.cache.replace_if(key, value, ttl, accept)
+    }
+
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        self.cache.rename(from, to, if_absent)
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        self.cache.get(key)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        self.cache.get_many(keys)
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        self.cache.remove(key)
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        self.cache.remove_entry_if(key, accept)
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        self.cache.expire(key, ttl)
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        self.cache.touch(key)
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        self.cache.expiration(key)
+    }
+
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        self.cache.sweep_expired(max_expired)
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        self.cache.scan(visit)
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        self.cache.scan_with_expirations(visit)
+    }
+
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        self.cache.scan_from(cursor, count, visit)
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        self.cache.len()
+    }
+
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        self.cache.clear()
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        self.cache.flush()
+    }
+
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        self.cache.memory_stats()
+    }
+
+    fn memory_usage(&self, key: &K) -> CacheResult<Option<usize>> {
+        self.cache.memory_usage(key)
+    }
+
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        self.cache.set_max_memory(max_bytes, policy)
+    }
+}
@@ -0,0 +1,687 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    mem,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use super::{
+    entry::CacheEntry,
+    expiry::ExpiryIndex,
+    listener::{Removal, RemovalCause, RemovalListener},
+    memory::{entry_size, HeapSize, MaxMemoryPolicy, MemoryStats},
+    slab::Slab,
+    Cache, PutCondition, SweepStats,
+};
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::current_monotime,
+};
+
+/// A `Cache` implementation holding at most a given number of entries: once full, every new key evicts
+/// the least recently used one (i.e. the one neither read nor written for the longest time).
+///
+/// The entries are linked in recency order, so both marking an entry as used and evicting one take O(1).
+/// Since every read updates that order, reads take the same exclusive lock as writes.
+///
+/// The cache can also be bounded by the memory taken by its entries (see `with_max_memory`), counting
+/// their keys and values along with the bookkeeping of the cache.
+pub struct LruCache<K, V> {
+    entries: Mutex<LruEntries<K, V>>,
+    max_entries: usize, // The maximum number of entries, beyond which the least recently used are evicted
+    listener: Option<Arc<dyn RemovalListener<K, V>>>, // Notified of the entries removed, if any
+}
+
+/// An entry of an `LruCache`, linked to its neighbours in recency order.
+struct LruNode<K, V> {
+    key: Arc<K>, // Shared with the map and the expiry index
+    entry: CacheEntry<V>,
+    size: usize,         // The bytes taken by the entry (see `memory::entry_size`)
+    prev: Option<usize>, // The slot of the more recently used entry, if any
+    next: Option<usize>, // The slot of the less recently used entry, if any
+}
+
+struct LruEntries<K, V> {
+    map: HashMap<Arc<K>, usize>, // The slab slots of the entries by key
+    slab: Slab<LruNode<K, V>>,   // The entries
+    expiry: ExpiryIndex<K>,      // The keys of the entries with a TTL by expiration time
+    head: Option<usize>,         // The slot of the most recently used entry
+    tail: Option<usize>,         // The slot of the least recently used entry, the next one evicted
+    used_bytes: usize,           // The bytes taken by the entries
+    evictions: u64,              // The number of entries evicted so far
+    expirations: u64,            // The number of expired entries deleted so far
+    removals: Option<Vec<Removal<K, V>>>, // The entries removed for the listener, if the cache has one
+    max_bytes: Option<usize>,             // The most memory the entries may take, if limited
+    max_memory_policy: MaxMemoryPolicy,   // What happens to the writes beyond `max_bytes`
+}
+
+impl<K, V> LruCache<K, V> {
+    /// Create a new, empty `LruCache`.
+    ///
+    /// # Arguments
+    /// * `max_entries` - The maximum number of entries kept (at least one).
+    pub fn new(max_entries: usize) -> LruCache<K, V> {
+        LruCache {
+            entries: Mutex::new(LruEntries {
+                map: HashMap::new(),
+                slab: Slab::new(),
+                expiry: ExpiryIndex::new(),
+                head: None,
+                tail: None,
+                used_bytes: 0,
+                evictions: 0,
+                expirations: 0,
+                removals: None,
+                max_bytes: None,
+                max_memory_policy: MaxMemoryPolicy::default(),
+            }),
+            max_entries: max_entries.max(1),
+            listener: None,
+        }
+    }
+
+    /// Bound the cache by the memory taken by its entries as well.
+    ///
+    /// # Arguments
+    /// * `max_bytes` - The most bytes the entries may take.
+    /// * `policy` - Whether the writes beyond evict the least recently used entries, or are rejected.
+    pub fn with_max_memory(mut self, max_bytes: usize, policy: MaxMemoryPolicy) -> LruCache<K, V> {
+        let entries = self
+            .entries
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        entries.max_bytes = Some(max_bytes);
+        entries.max_memory_policy = policy;
+        self
+    }
+
+    /// Notify the given listener of the entries removed, expired or evicted (see `RemovalListener`).
+    pub fn with_removal_listener(
+        mut self,
+        listener: impl RemovalListener<K, V> + 'static,
+    ) -> LruCache<K, V> {
+        self.entries
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .removals = Some(Vec::new());
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// The maximum number of entries kept.
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Release the lock of the entries, then hand the entries removed under it over to the listener.
+    fn _notify(&self, mut entries: MutexGuard<'_, LruEntries<K, V>>) {
+        let removals: Vec<Removal<K, V>> = match &mut entries.removals {
+            Some(removals) if !removals.is_empty() => mem::take(removals),
+            _ => return,
+        };
+        drop(entries);
+
+        if let Some(listener) = &self.listener {
+            for (key, value, cause) in removals {
+                listener.on_removal(&key, &value, cause);
+            }
+        }
+    }
+
+    /// The memory taken by the entries, and the number of entries evicted so far.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        MemoryStats {
+            used_bytes: entries.used_bytes,
+            max_bytes: entries.max_bytes,
+            evictions: entries.evictions,
+        }
+    }
+
+    /// The number of entries evicted so far to make room for new ones.
+    pub fn evictions(&self) -> u64 {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .evictions
+    }
+
+    /// The number of expired entries deleted so far.
+    pub fn expirations(&self) -> u64 {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .expirations
+    }
+}
+
+/// The bytes taken by the bookkeeping of an entry of an `LruCache`: its node and its slot in the map.
+fn node_overhead<K, V>() -> usize {
+    mem::size_of::<LruNode<K, V>>() + mem::size_of::<(Arc<K>, usize)>()
+}
+
+impl<K, V> LruEntries<K, V> {
+    /// Take the entry in the given slot out of the recency order.
+    fn unlink(&mut self, index: usize) {
+        let Some(node) = self.slab.get(index) else {
+            return;
+        };
+        let (prev, next): (Option<usize>, Option<usize>) = (node.prev, node.next);
+
+        match prev.and_then(|prev| self.slab.get_mut(prev)) {
+            Some(prev) => prev.next = next,
+            None => self.head = next,
+        }
+        match next.and_then(|next| self.slab.get_mut(next)) {
+            Some(next) => next.prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    /// Put the entry in the given slot first in the recency order, i.e. mark it as the most recently used.
+    fn push_front(&mut self, index: usize) {
+        let head: Option<usize> = self.head;
+        if let Some(node) = self.slab.get_mut(index) {
+            node.prev = None;
+            node.next = head;
+        }
+        match head.and_then(|head| self.slab.get_mut(head)) {
+            Some(head) => head.prev = Some(index),
+            None => self.tail = Some(index),
+        }
+        self.head = Some(index);
+    }
+
+    /// Mark the entry in the given slot as the most recently used.
+    fn touch(&mut self, index: usize) {
+        if self.head != Some(index) {
+            self.unlink(index);
+            self.push_front(index);
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> LruEntries<K, V> {
+    /// Get the value of the given key, marking it as the most recently used one. An expired entry is
+    /// deleted instead.
+    fn lookup(&mut self, key: &K) -> Option<V> {
+        let &index = self.map.get(key)?;
+
+        match self.slab.get(index) {
+            Some(node) if !node.entry.is_expired() => {
+                let value: V = node.entry.value.clone();
+                self.touch(index);
+                Some(value)
+            }
+            _ => {
+                if let Some((key, entry)) = self.remove_at(index) {
+                    self.record(key, entry.value, RemovalCause::Expired);
+                }
+                self.expirations += 1;
+                None
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> LruEntries<K, V> {
+    /// Insert (or replace in its slot) an entry taking the given bytes as the most recently used one,
+    /// keeping the expiry index in sync.
+    fn insert(&mut self, key: K, entry: CacheEntry<V>, size: usize) {
+        let expires_at: Option<u64> = entry.expires_at;
+
+        let key: Arc<K> = match self.map.get(&key) {
+            Some(&index) => {
+                self.touch(index);
+                let Some(node) = self.slab.get_mut(index) else {
+                    return;
+                };
+                if let Some(old_expires_at) = node.entry.expires_at {
+                    self.expiry.remove(&node.key, old_expires_at);
+                }
+                node.entry = entry;
+                self.used_bytes = self.used_bytes - node.size + size;
+                node.size = size;
+                Arc::clone(&node.key)
+            }
+            None => {
+                let key: Arc<K> = Arc::new(key);
+                let index: usize = self.slab.insert(LruNode {
+                    key: Arc::clone(&key),
+                    entry,
+                    size,
+                    prev: None,
+                    next: None,
+                });
+                self.used_bytes += size;
+                self.map.insert(Arc::clone(&key), index);
+                self.push_front(index);
+                key
+            }
+        };
+
+        if let Some(expires_at) = expires_at {
+            self.expiry.insert(&key, expires_at);
+        }
+    }
+
+    /// Get the node of the given key, if any (expired or not).
+    fn node(&self, key: &K) -> Option<&LruNode<K, V>> {
+        self.slab.get(*self.map.get(key)?)
+    }
+
+    /// Remove the entry in the given slot, keeping the map and the expiry index in sync.
+    fn remove_at(&mut self, index: usize) -> Option<(Arc<K>, CacheEntry<V>)> {
+        self.unlink(index);
+        let node: LruNode<K, V> = self.slab.remove(index)?;
+        self.used_bytes -= node.size;
+        self.map.remove(&node.key);
+        if let Some(expires_at) = node.entry.expires_at {
+            self.expiry.remove(&node.key, expires_at);
+        }
+        Some((node.key, node.entry))
+    }
+
+    /// Remove the entry of the given key, if any.
+    fn remove(&mut self, key: &K) -> Option<(Arc<K>, CacheEntry<V>)> {
+        let index: usize = *self.map.get(key)?;
+        self.remove_at(index)
+    }
+
+    /// Keep an entry removed for the listener of the cache, if it has one.
+    fn record(&mut self, key: Arc<K>, value: V, cause: RemovalCause) {
+        if let Some(removals) = &mut self.removals {
+            removals.push((key, value, cause));
+        }
+    }
+
+    /// Change the TTL of a live entry with the given function (e.g. `CacheEntry::touch`), without changing its recency, keeping the expiry index in sync.
+    ///
+    /// # Returns
+    /// * `false` if the key has no live entry.
+    fn retime(&mut self, key: &K, retime: impl FnOnce(&mut CacheEntry<V>)) -> bool {
+        let Some(&index) = self.map.get(key) else {
+            return false;
+        };
+        let Some(node) = self
+            .slab
+            .get_mut(index)
+            .filter(|node| !node.entry.is_expired())
+        else {
+            return false;
+        };
+
+        let old_expires_at: Option<u64> = node.entry.expires_at;
+        retime(&mut node.entry);
+        self.expiry
+            .reschedule(&node.key, old_expires_at, node.entry.expires_at);
+        true
+    }
+
+    /// Evict the least recently used entries until at most `max_entries` are left, taking at most
+    /// `max_bytes` (if limited). Expired entries are not counted as evicted.
+    fn evict_overflow(&mut self, max_entries: usize, max_bytes: Option<usize>) {
+        while self.map.len() > max_entries || max_bytes.is_some_and(|max| self.used_bytes > max) {
+            let Some(tail) = self.tail else {
+                break;
+            };
+            match self.remove_at(tail) {
+                Some((key, entry)) if entry.is_expired() => {
+                    self.record(key, entry.value, RemovalCause::Expired);
+                    self.expirations += 1;
+                }
+                Some((key, entry)) => {
+                    self.record(key, entry.value, RemovalCause::Evicted);
+                    self.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash + HeapSize, V: HeapSize> LruCache<K, V> {
+    /// Put an entry under the lock of the entries, making room for it.
+    fn _put(
+        &self,
+        entries: &mut LruEntries<K, V>,
+        key: K,
+        entry: CacheEntry<V>,
+    ) -> CacheResult<()> {
+        let size: usize = entry_size(&key, &entry.value, node_overhead::<K, V>());
+        let replaced: usize = entries.node(&key).map_or(0, |node| node.size);
+        self._check_memory(entries, size, replaced)?;
+
+        entries.insert(key, entry, size);
+        entries.evict_overflow(self.max_entries, entries.max_bytes);
+        Ok(())
+    }
+
+    /// Check that an entry taking the given bytes fits in the max memory of the cache (if limited), once
+    /// the given bytes are freed.
+    fn _check_memory(
+        &self,
+        entries: &LruEntries<K, V>,
+        size: usize,
+        freed: usize,
+    ) -> CacheResult<()> {
+        if let Some(max_bytes) = entries.max_bytes {
+            let evicting: bool = entries.max_memory_policy == MaxMemoryPolicy::Evict;
+            if size > max_bytes || (!evicting && entries.used_bytes - freed + size > max_bytes) {
+                return Err(CacheError::OutOfMemory);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> Cache<K, V> for LruCache<K, V>
+where
+    K: Eq + Hash + HeapSize + Send + Sync,
+    V: Clone + HeapSize + Send + Sync,
+{
+    /// # Errors
+    /// * `CacheError::OutOfMemory` if the entry does not fit in the max memory of the cache, or would take
+    ///   the cache over it with `MaxMemoryPolicy::NoEviction`.
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut entries = self.entries.lock()?;
+        let result: CacheResult<()> = self._put(&mut entries, key, entry);
+        self._notify(entries);
+        result
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        let mut locked = self.entries.lock()?;
+        let result: CacheResult<()> = entries
+            .into_iter()
+            .try_for_each(|(key, value)| self._put(&mut locked, key, CacheEntry::new(value, ttl)));
+        self._notify(locked);
+        result
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut entries = self.entries.lock()?;
+        let present: bool = match entries.map.get(&key) {
+            Some(&index) => entries
+                .slab
+                .get(index)
+                .is_some_and(|node| !node.entry.is_expired()),
+            None => false,
+        };
+        if !condition.allows(present) {
+            return Ok(false);
+        }
+
+        let result: CacheResult<()> = self._put(&mut entries, key, entry);
+        self._notify(entries);
+        result.map(|_| true)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let mut entries = self.entries.lock()?;
+        let current: Option<&CacheEntry<V>> = entries
+            .map
+            .get(&key)
+            .and_then(|&index| entries.slab.get(index))
+            .map(|node| &node.entry)
+            .filter(|entry| !entry.is_expired());
+        let (expires_at, ttl): (Option<u64>, Option<u64>) =
+            current.map_or((None, None), |entry| (entry.expires_at, entry.ttl));
+        let value: V = update(current.map(|entry| &entry.value))?;
+
+        let entry: CacheEntry<V> = CacheEntry {
+            value: value.clone(),
+            expires_at,
+            ttl,
+        };
+        let result: CacheResult<()> = self._put(&mut entries, key, entry);
+        self._notify(entries);
+        result.map(|_| value)
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let mut entries = self.entries.lock()?;
+        let previous: Option<V> = entries
+            .node(&key)
+            .map(|node| &node.entry)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.value.clone());
+        accept(previous.as_ref())?;
+
+        let result: CacheResult<()> = self._put(&mut entries, key, CacheEntry::new(value, ttl));
+        self._notify(entries);
+        result.map(|_| previous)
+    }
+
+    /// The entry moved is the most recently used one.
+    ///
+    /// # Errors
+    /// * `CacheError::OutOfMemory` if the entry does not fit in the max memory of the cache under its new
+    ///   key (see `put`), in which case it is left under `from`.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let mut entries = self.entries.lock()?;
+        let Some(node) = entries.node(from).filter(|node| !node.entry.is_expired()) else {
+            return Ok(None);
+        };
+        let replaced: Option<&LruNode<K, V>> = entries.node(&to);
+        if *from == to || (if_absent && replaced.is_some_and(|node| !node.entry.is_expired())) {
+            return Ok(Some(!if_absent));
+        }
+
+        // Checked before the entry is taken out, so that the `_put` below cannot fail
+        let size: usize = entry_size(&to, &node.entry.value, node_overhead::<K, V>());
+        let freed: usize = node.size + replaced.map_or(0, |node| node.size);
+        self._check_memory(&entries, size, freed)?;
+
+        let Some((_, entry)) = entries.remove(from) else {
+            return Ok(None);
+        };
+        let result: CacheResult<()> = self._put(&mut entries, to, entry);
+        self._notify(entries);
+        result.map(|_| Some(true))
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        let mut entries = self.entries.lock()?;
+        let value: Option<V> = entries.lookup(key);
+        self._notify(entries);
+        Ok(value)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        let mut entries = self.entries.lock()?;
+        let values: Vec<Option<V>> = keys.iter().map(|key| entries.lookup(key)).collect();
+        self._notify(entries);
+        Ok(values)
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        Ok(self.remove_entry(key)?.map(|(value, _)| value))
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let mut entries = self.entries.lock()?;
+        if let Some(node) = entries.node(key).filter(|node| !node.entry.is_expired()) {
+            accept(&node.entry.value)?;
+        }
+        let removed: Option<(V, Option<u64>)> = match entries.remove(key) {
+            Some((key, entry)) if entry.is_expired() => {
+                entries.record(key, entry.value, RemovalCause::Expired);
+                entries.expirations += 1;
+                None
+            }
+            Some((key, entry)) => {
+                if entries.removals.is_some() {
+                    entries.record(key, entry.value.clone(), RemovalCause::Removed);
+                }
+                Some((entry.value, entry.expires_at))
+            }
+            None => None,
+        };
+        self._notify(entries);
+        Ok(removed)
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        Ok(self.entries.lock()?.retime(key, |entry| entry.set_ttl(ttl)))
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        Ok(self.entries.lock()?.retime(key, CacheEntry::touch))
+    }
+
+    /// Tell the expiration time without changing the recency of the entry.
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        let entries = self.entries.lock()?;
+        Ok(entries
+            .map
+            .get(key)
+            .and_then(|&index| entries.slab.get(index))
+            .filter(|node| !node.entry.is_expired())
+            .map(|node| node.entry.expires_at))
+    }
+
+    /// Delete the expired entries found through the expiry index, so only they are examined.
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let mut entries = self.entries.lock()?;
+
+        let expired: Vec<Arc<K>> = entries.expiry.pop_expired(current_monotime(), max_expired);
+        for key in &expired {
+            if let Some(&index) = entries.map.get(&**key) {
+                // Already out of the expiry index, so only unlinked and taken out of the map
+                entries.unlink(index);
+                if let Some(node) = entries.slab.remove(index) {
+                    entries.used_bytes -= node.size;
+                    entries.record(node.key, node.entry.value, RemovalCause::Expired);
+                }
+                entries.map.remove(&**key);
+            }
+        }
+
+        entries.expirations += expired.len() as u64;
+        self._notify(entries);
+        Ok(SweepStats {
+            examined: expired.len(),
+            expired: expired.len(),
+        })
+    }
+
+    /// Visit the live entries under the lock, most recently used first, without changing their recency.
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        self.scan_with_expirations(&mut |key, value, _| {
+            visit(key, value);
+            true
+        })
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        let entries = self.entries.lock()?;
+        let now: u64 = current_monotime();
+
+        let mut cursor: Option<usize> = entries.head;
+        while let Some(node) = cursor.and_then(|index| entries.slab.get(index)) {
+            if !node.entry.is_expired_at(now)
+                && !visit(&node.key, &node.entry.value, node.entry.expires_at)
+            {
+                break;
+            }
+            cursor = node.next;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        let entries = self.entries.lock()?;
+        Ok(entries.map.len() - entries.expiry.count_expired(current_monotime()))
+    }
+
+    /// Take the entries out under the lock, keeping the counters.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let mut entries = self.entries.lock()?;
+        entries.head = None;
+        entries.tail = None;
+        entries.used_bytes = 0;
+        Ok(Box::new((
+            mem::take(&mut entries.map),
+            mem::replace(&mut entries.slab, Slab::new()),
+            mem::replace(&mut entries.expiry, ExpiryIndex::new()),
+        )))
+    }
+
+    /// The cursor is the slot to resume from, since an entry keeps its slot while it is live (whatever its
+    /// recency). The entries visited keep their recency.
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        let entries = self.entries.lock()?;
+        let now: u64 = current_monotime();
+
+        let mut count: usize = count.max(1);
+        let next: Option<usize> = entries
+            .slab
+            .visit_from(cursor as usize, &mut count, |node| {
+                let live: bool = !node.entry.is_expired_at(now);
+                if live {
+                    visit(&node.key, &node.entry.value);
+                }
+                live
+            });
+        Ok(next.map_or(0, |slot| slot as u64))
+    }
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        Some(LruCache::memory_stats(self))
+    }
+
+    fn memory_usage(&self, key: &K) -> CacheResult<Option<usize>> {
+        let entries = self.entries.lock()?;
+        Ok(entries
+            .map
+            .get(key)
+            .and_then(|&index| entries.slab.get(index))
+            .filter(|node| !node.entry.is_expired())
+            .map(|node| node.size))
+    }
+
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        let mut entries = self.entries.lock()?;
+        entries.max_bytes = max_bytes;
+        entries.max_memory_policy = policy;
+        if policy == MaxMemoryPolicy::Evict {
+            entries.evict_overflow(self.max_entries, max_bytes);
+        }
+        self._notify(entries);
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::{fmt, mem, str::FromStr, sync::Arc};
+
+use super::Bytes;
+
+/// The size of the reference counts heading the allocation of an `Arc`.
+const ARC_HEADER: usize = 2 * mem::size_of::<usize>();
+
+/// The number of bytes a key or value owns on the heap (beyond its own `mem::size_of`), for the caches
+/// accounting for the memory of their entries (see `LruCache::with_max_memory`).
+///
+/// Implemented for the key and value types used by the server (`String` and `Bytes`), and for the numbers
+/// and booleans; implement it for your own types to use them with such caches.
+pub trait HeapSize {
+    /// The bytes allocated on the heap for the value, including the allocations it shares with its clones.
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for Vec<u8> {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for Arc<[u8]> {
+    fn heap_size(&self) -> usize {
+        ARC_HEADER + self.len()
+    }
+}
+
+impl HeapSize for Bytes {
+    fn heap_size(&self) -> usize {
+        match self.is_inline() {
+            true => 0,
+            false => ARC_HEADER + self.owned_heap_size(),
+        }
+    }
+}
+
+impl HeapSize for i64 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for u64 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for f64 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for bool {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+/// The bytes taken by an entry whose key is shared behind an `Arc`, given the size of the bookkeeping
+/// the cache keeps for it (e.g. its node and its slot in the map).
+pub(super) fn entry_size<K: HeapSize, V: HeapSize>(key: &K, value: &V, overhead: usize) -> usize {
+    ARC_HEADER + mem::size_of::<K>() + key.heap_size() + value.heap_size() + overhead
+}
+
+/// The bytes an entry would take in a cache accounting for its memory, estimated for the caches which do
+/// not: its key, its value, and a slot of a map holding them.
+pub(crate) fn estimated_entry_size<K: HeapSize, V: HeapSize>(key: &K, value: &V) -> usize {
+    entry_size(key, value, mem::size_of::<(Arc<K>, V)>())
+}
+
+/// What a cache does with a write that would take it over its max memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MaxMemoryPolicy {
+    /// Evict entries, as the eviction policy of the cache says, until the write fits.
+    #[default]
+    Evict,
+
+    /// Reject the write with `CacheError::OutOfMemory`, keeping every entry until it expires or is removed.
+    NoEviction,
+}
+
+impl FromStr for MaxMemoryPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<MaxMemoryPolicy, String> {
+        match text {
+            "evict" => Ok(MaxMemoryPolicy::Evict),
+            "noeviction" => Ok(MaxMemoryPolicy::NoEviction),
+            _ => Err(format!("unknown maxmemory policy '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for MaxMemoryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaxMemoryPolicy::Evict => write!(f, "evict"),
+            MaxMemoryPolicy::NoEviction => write!(f, "noeviction"),
+        }
+    }
+}
+
+/// The memory taken by the entries of a cache accounting for it (see `Cache::memory_stats`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub used_bytes: usize, // The bytes taken by the keys, the values and their bookkeeping
+    pub max_bytes: Option<usize>, // The most bytes the entries may take, if limited
+    pub evictions: u64,    // The number of entries evicted so far to make room for others
+}
@@ -0,0 +1,838 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    hash::Hash,
+    io,
+    marker::PhantomData,
+    os::unix::{fs::FileExt, io::AsRawFd},
+    path::{Path, PathBuf},
+    ptr, slice,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use log::{debug, info, warn};
+
+use super::{
+    codec::Codec, expiry::ExpiryIndex, group_commit::GroupCommit, Cache, PutCondition, SweepStats,
+};
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::{current_monotime, expires_after, monotime_to_walltime, walltime_to_monotime},
+};
+
+/// Marks the beginning of a record. Anything else (e.g. the zeroed, preallocated tail) ends the log.
+const RECORD_MAGIC: u8 = 0xA5;
+
+/// Record kinds.
+const RECORD_PUT: u8 = 0;
+const RECORD_REMOVE: u8 = 1;
+
+/// magic (1) + kind (1) + key length (4) + value length (4) + expiration (8) + checksum (4)
+const HEADER_LEN: usize = 22;
+
+/// The file grows (and is remapped) in steps of at least this many bytes.
+const MIN_GROWTH: u64 = 1024 * 1024;
+
+/// The log is compacted once it is larger than this and more than half of it is garbage.
+const MIN_COMPACTION_SIZE: u64 = 4 * 1024 * 1024;
+
+/// When an `MmapCache` makes its writes durable (i.e. safe from a power failure or an OS crash).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave the writes to the OS, which flushes them in the background. They survive a crash of the
+    /// process, but the latest ones may be lost on a power failure or an OS crash.
+    #[default]
+    Never,
+
+    /// Make every write durable before acknowledging it. Concurrent writes share their syncs (group
+    /// commit), so the cost of a sync is amortized over all the writes issued meanwhile.
+    Always,
+}
+
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<SyncPolicy, String> {
+        match text {
+            "never" => Ok(SyncPolicy::Never),
+            "always" => Ok(SyncPolicy::Always),
+            _ => Err(format!("unknown sync policy '{}'", text)),
+        }
+    }
+}
+
+/// A persistent `Cache` implementation keeping the values in a memory-mapped, append-only log file.
+///
+/// Every put and remove is appended to the log, while an in-memory index maps each key to the location
+/// of its latest value. Values are read straight from the mapping, so the OS page cache decides which
+/// parts of the data stay in memory. On startup, the log is scanned once to rebuild the index, which makes
+/// recovery near-instant. Expiration times are stored as wall-clock times, so TTLs keep counting down while
+/// the process is not running.
+///
+/// A log file must be opened by a single `MmapCache` at a time.
+pub struct MmapCache<K, V> {
+    state: RwLock<MmapState<K>>,
+    commit: Option<Arc<GroupCommit>>, // Makes the writes durable (with `SyncPolicy::Always` only)
+    expirations: AtomicU64,           // The number of expired entries deleted so far
+    _values: PhantomData<fn() -> V>,  // The values live in the log, encoded with `Codec`
+}
+
+/// The location of the latest value of a key in the log.
+struct Slot {
+    record: u64,             // The offset of the record
+    value: u64,              // The offset of the value bytes
+    value_len: usize,        // The length of the value bytes
+    expires_at: Option<u64>, // The monotonic expiration time (see `current_monotime`)
+}
+
+impl Slot {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| current_monotime() >= t)
+    }
+
+    /// The length of the whole record (header, key and value).
+    fn record_len(&self) -> u64 {
+        self.value + self.value_len as u64 - self.record
+    }
+}
+
+struct MmapState<K> {
+    path: PathBuf,                    // The path of the log file
+    file: File,                       // The log file, opened for reading and writing
+    map: Mapping,                     // A read-only shared mapping of the whole file
+    index: HashMap<Arc<K>, Slot>,     // The location of the latest value of every key
+    expiry: ExpiryIndex<K>,           // The keys of the entries with a TTL by expiration time
+    end: u64,                         // The offset at which the next record is appended
+    garbage: u64,                     // The number of bytes taken by superseded or removed records
+    commit: Option<Arc<GroupCommit>>, // Told about the new file when the log is compacted
+}
+
+impl<K, V> MmapCache<K, V>
+where
+    K: Eq + Hash + Codec,
+{
+    /// Open (or create) the log file at the given path and rebuild the index from its records.
+    ///
+    /// A torn record at the end of the log (e.g. after a crash) ends the recovery; it and anything after
+    /// it is overwritten by the next write.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the file cannot be opened, grown or mapped.
+    pub fn open(path: &Path) -> CacheResult<MmapCache<K, V>> {
+        MmapCache::open_with_sync(path, SyncPolicy::Never)
+    }
+
+    /// Open (or create) the log file at the given path like `open`, making the writes durable according to
+    /// the given policy.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the file cannot be opened, grown or mapped.
+    pub fn open_with_sync(path: &Path, sync: SyncPolicy) -> CacheResult<MmapCache<K, V>> {
+        let file: File = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let len: u64 = file.metadata()?.len().max(MIN_GROWTH);
+        file.set_len(len)?;
+
+        let commit: Option<Arc<GroupCommit>> = match sync {
+            SyncPolicy::Never => None,
+            SyncPolicy::Always => Some(Arc::new(GroupCommit::new(file.try_clone()?))),
+        };
+
+        let mut state: MmapState<K> = MmapState {
+            path: path.to_path_buf(),
+            map: Mapping::new(&file, len as usize)?,
+            file,
+            index: HashMap::new(),
+            expiry: ExpiryIndex::new(),
+            end: 0,
+            garbage: 0,
+            commit: commit.clone(),
+        };
+        state.recover();
+
+        info!(
+            "Recovered {} keys from {} ({} bytes of log)",
+            state.index.len(),
+            path.display(),
+            state.end
+        );
+        Ok(MmapCache {
+            state: RwLock::new(state),
+            commit,
+            expirations: AtomicU64::new(0),
+            _values: PhantomData,
+        })
+    }
+
+    /// The number of expired entries deleted so far. Expired entries are deleted when they are accessed.
+    pub fn expirations(&self) -> u64 {
+        self.expirations.load(Ordering::Relaxed)
+    }
+}
+
+impl<K, V> MmapCache<K, V> {
+    /// Wait until the given write is durable, if the sync policy requires it.
+    fn wait_durable(&self, write: Option<u64>) -> CacheResult<()> {
+        if let (Some(commit), Some(write)) = (&self.commit, write) {
+            commit.wait_durable(write)?;
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> Cache<K, V> for MmapCache<K, V>
+where
+    K: Eq + Hash + Codec + Send + Sync,
+    V: Codec + Send + Sync,
+{
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        let mut state = self.state.write()?;
+        let expires_at: Option<u64> = ttl.map(|t| expires_after(current_monotime(), t));
+
+        state.append_put(key, &value.encode(), expires_at)?;
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state); // Let the other writers append while we wait, so they join the same group
+        self.wait_durable(write)
+    }
+
+    /// The records are appended under a single lock, and made durable together.
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        let mut state = self.state.write()?;
+        let expires_at: Option<u64> = ttl.map(|t| expires_after(current_monotime(), t));
+
+        for (key, value) in entries {
+            state.append_put(key, &value.encode(), expires_at)?;
+        }
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state);
+        self.wait_durable(write)
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        let mut state = self.state.write()?;
+        let present: bool = state.index.get(&key).is_some_and(|slot| !slot.is_expired());
+        if !condition.allows(present) {
+            return Ok(false);
+        }
+        let expires_at: Option<u64> = ttl.map(|t| expires_after(current_monotime(), t));
+
+        state.append_put(key, &value.encode(), expires_at)?;
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state);
+        self.wait_durable(write)?;
+        Ok(true)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let mut state = self.state.write()?;
+        let (current, expires_at): (Option<V>, Option<u64>) = match state.index.get(&key) {
+            Some(slot) if !slot.is_expired() => (Some(state.decode(slot)?), slot.expires_at),
+            _ => (None, None),
+        };
+        let value: V = update(current.as_ref())?;
+
+        state.append_put(key, &value.encode(), expires_at)?;
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state);
+        self.wait_durable(write)?;
+        Ok(value)
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let mut state = self.state.write()?;
+        let previous: Option<V> = match state.index.get(&key) {
+            Some(slot) if !slot.is_expired() => Some(state.decode(slot)?),
+            _ => None,
+        };
+        accept(previous.as_ref())?;
+        let expires_at: Option<u64> = ttl.map(|t| expires_after(current_monotime(), t));
+
+        state.append_put(key, &value.encode(), expires_at)?;
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state);
+        self.wait_durable(write)?;
+        Ok(previous)
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        {
+            let state = self.state.read()?;
+            match state.index.get(key) {
+                Some(slot) if !slot.is_expired() => return state.decode(slot).map(Some),
+                Some(_) => {} // Expired, so it has to be deleted under the write lock
+                None => return Ok(None),
+            }
+        }
+
+        // The entry may have been replaced (or deleted) while no lock was held, so look it up again. The
+        // record of an expired entry carries its expiration time, so no tombstone is needed.
+        let mut state = self.state.write()?;
+        match state.index.get(key) {
+            Some(slot) if !slot.is_expired() => state.decode(slot).map(Some),
+            Some(_) => {
+                if let Some(slot) = state.remove_slot(key) {
+                    state.garbage += slot.record_len();
+                }
+                self.expirations.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        // The expired entries are left to the writers (and the sweeper) to delete
+        let state = self.state.read()?;
+        keys.iter()
+            .map(|key| match state.index.get(key) {
+                Some(slot) if !slot.is_expired() => state.decode(slot).map(Some),
+                _ => Ok(None),
+            })
+            .collect()
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        Ok(self.remove_entry(key)?.map(|(value, _)| value))
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let mut state = self.state.write()?;
+
+        let entry: Option<(V, Option<u64>)> = match state.index.get(key) {
+            Some(slot) if !slot.is_expired() => {
+                let value: V = state.decode(slot)?;
+                accept(&value)?;
+                Some((value, slot.expires_at))
+            }
+            Some(_) => {
+                self.expirations.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => return Ok(None),
+        };
+
+        // Append a tombstone, so the removal survives a restart
+        state.append_remove(key)?;
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state);
+        self.wait_durable(write)?;
+
+        Ok(entry)
+    }
+
+    /// Append the value under its new key, then a tombstone for the old one. A crash in between leaves the
+    /// value under both keys after a restart, rather than under neither.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let mut state = self.state.write()?;
+        let Some(slot) = state.index.get(from).filter(|slot| !slot.is_expired()) else {
+            return Ok(None);
+        };
+        let taken: bool = state.index.get(&to).is_some_and(|slot| !slot.is_expired());
+        if *from == to || (if_absent && taken) {
+            return Ok(Some(!if_absent));
+        }
+
+        let start: usize = slot.value as usize;
+        let value: Vec<u8> = state.map.as_slice()[start..start + slot.value_len].to_vec();
+        let expires_at: Option<u64> = slot.expires_at;
+        state.append_put(to, &value, expires_at)?;
+        state.append_remove(from)?;
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state);
+        self.wait_durable(write)?;
+        Ok(Some(true))
+    }
+
+    /// Append the value again with its new expiration time, so the change survives a restart.
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        let mut state = self.state.write()?;
+        let expires_at: Option<u64> = ttl.map(|t| expires_after(current_monotime(), t));
+
+        if !state.append_expire(key, expires_at)? {
+            return Ok(false);
+        }
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state);
+        self.wait_durable(write)?;
+        Ok(true)
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        let state = self.state.read()?;
+        Ok(state
+            .index
+            .get(key)
+            .filter(|slot| !slot.is_expired())
+            .map(|slot| slot.expires_at))
+    }
+
+    /// Delete the expired entries found through the expiry index, so only they are examined.
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let mut state = self.state.write()?;
+
+        // The records of expired entries carry their expiration time, so no tombstones are needed
+        let expired: Vec<Arc<K>> = state.expiry.pop_expired(current_monotime(), max_expired);
+        for key in &expired {
+            if let Some(slot) = state.index.remove(&**key) {
+                state.garbage += slot.record_len();
+            }
+        }
+
+        if !expired.is_empty() {
+            self.expirations
+                .fetch_add(expired.len() as u64, Ordering::Relaxed);
+            state.compact_if_needed()?;
+        }
+        Ok(SweepStats {
+            examined: expired.len(),
+            expired: expired.len(),
+        })
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        let state = self.state.read()?;
+        Ok(state.index.len() - state.expiry.count_expired(current_monotime()))
+    }
+
+    /// Append a tombstone for every key, so the removal survives a restart, then compact the log (which
+    /// is all garbage by then). The values live in the log, so there is nothing left to drop.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let mut state = self.state.write()?;
+        let keys: Vec<Arc<K>> = state.index.keys().cloned().collect();
+        for key in &keys {
+            state.append_remove(key)?;
+        }
+        state.compact_if_needed()?;
+
+        let write: Option<u64> = self.commit.as_ref().map(|commit| commit.record_write());
+        drop(state);
+        self.wait_durable(write)?;
+        Ok(Box::new(()))
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        let state = self.state.read()?;
+        state.file.sync_data()?;
+        Ok(())
+    }
+}
+
+impl<K: Eq + Hash + Codec> MmapState<K> {
+    /// Scan the log from the beginning and rebuild the index.
+    fn recover(&mut self) {
+        let mut offset: u64 = 0;
+
+        while let Some(record) = Record::parse(self.map.as_slice(), offset) {
+            let record_len: u64 = record.len() as u64;
+            let Some(key) = K::decode(record.key) else {
+                warn!(
+                    "Undecodable key at offset {} of {}",
+                    offset,
+                    self.path.display()
+                );
+                break;
+            };
+
+            let expires_at: Option<u64> = record.expires_at.map(walltime_to_monotime);
+            let expired: bool = expires_at.is_some_and(|t| current_monotime() >= t);
+            let slot: Option<Slot> = (record.kind == RECORD_PUT && !expired).then(|| Slot {
+                record: offset,
+                value: offset + (HEADER_LEN + record.key.len()) as u64,
+                value_len: record.value.len(),
+                expires_at,
+            });
+
+            if let Some(old) = self.remove_slot(&key) {
+                self.garbage += old.record_len();
+            }
+            if let Some(slot) = slot {
+                self.insert_slot(key, slot);
+            } else {
+                self.garbage += record_len;
+            }
+
+            offset += record_len;
+        }
+
+        self.end = offset;
+    }
+
+    /// Append a put record to the log and point the index of the key to it.
+    fn append_put(&mut self, key: K, value: &[u8], expires_at: Option<u64>) -> CacheResult<()> {
+        let key_bytes: Vec<u8> = key.encode();
+        let walltime: Option<u64> = expires_at.map(monotime_to_walltime);
+        let record: u64 = self.append(RECORD_PUT, &key_bytes, value, walltime)?;
+
+        let slot: Slot = Slot {
+            record,
+            value: record + (HEADER_LEN + key_bytes.len()) as u64,
+            value_len: value.len(),
+            expires_at,
+        };
+
+        if let Some(old) = self.insert_slot(key, slot) {
+            self.garbage += old.record_len();
+        }
+        Ok(())
+    }
+
+    /// Append a copy of the put record of a live key with a new expiration time, and point the index of
+    /// the key to it.
+    ///
+    /// # Returns
+    /// * `false` if the key has no live value.
+    fn append_expire(&mut self, key: &K, expires_at: Option<u64>) -> CacheResult<bool> {
+        let Some(slot) = self.index.get(key).filter(|slot| !slot.is_expired()) else {
+            return Ok(false);
+        };
+        let start: usize = slot.value as usize;
+        let value: Vec<u8> = self.map.as_slice()[start..start + slot.value_len].to_vec();
+
+        let key_bytes: Vec<u8> = key.encode();
+        let walltime: Option<u64> = expires_at.map(monotime_to_walltime);
+        let record: u64 = self.append(RECORD_PUT, &key_bytes, &value, walltime)?;
+
+        let Some((key, slot)) = self.index.get_key_value(key) else {
+            return Ok(false);
+        };
+        let (key, old_expires_at): (Arc<K>, Option<u64>) = (Arc::clone(key), slot.expires_at);
+        self.expiry.reschedule(&key, old_expires_at, expires_at);
+        if let Some(slot) = self.index.get_mut(&key) {
+            self.garbage += slot.record_len();
+            *slot = Slot {
+                record,
+                value: record + (HEADER_LEN + key_bytes.len()) as u64,
+                value_len: value.len(),
+                expires_at,
+            };
+        }
+        Ok(true)
+    }
+
+    /// Append a tombstone record to the log and remove the key from the index.
+    fn append_remove(&mut self, key: &K) -> CacheResult<()> {
+        let key_bytes: Vec<u8> = key.encode();
+        let record: u64 = self.append(RECORD_REMOVE, &key_bytes, &[], None)?;
+
+        // Tombstones are garbage as soon as they are written
+        self.garbage += self.end - record;
+        if let Some(old) = self.remove_slot(key) {
+            self.garbage += old.record_len();
+        }
+        Ok(())
+    }
+
+    /// Point the index of the key to the given slot, keeping the expiry index in sync.
+    ///
+    /// # Returns
+    /// * The slot the key pointed to, if any.
+    fn insert_slot(&mut self, key: K, slot: Slot) -> Option<Slot> {
+        let old: Option<Slot> = self.remove_slot(&key);
+        let key: Arc<K> = Arc::new(key);
+        if let Some(expires_at) = slot.expires_at {
+            self.expiry.insert(&key, expires_at);
+        }
+        self.index.insert(key, slot);
+        old
+    }
+
+    /// Remove the key from the index, keeping the expiry index in sync.
+    fn remove_slot(&mut self, key: &K) -> Option<Slot> {
+        let slot: Slot = self.index.remove(key)?;
+        if let Some(expires_at) = slot.expires_at {
+            self.expiry.remove(key, expires_at);
+        }
+        Some(slot)
+    }
+
+    /// Append a record to the end of the log and return its offset.
+    fn append(
+        &mut self,
+        kind: u8,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> CacheResult<u64> {
+        let bytes: Vec<u8> = Record::encode(kind, key, value, expires_at);
+        let offset: u64 = self.end;
+
+        self.ensure_capacity(offset + bytes.len() as u64)?;
+        self.file.write_all_at(&bytes, offset)?;
+        self.end += bytes.len() as u64;
+
+        Ok(offset)
+    }
+
+    /// Grow the file (and the mapping) so that it can hold at least `len` bytes.
+    fn ensure_capacity(&mut self, len: u64) -> CacheResult<()> {
+        let capacity: u64 = self.map.len as u64;
+        if len <= capacity {
+            return Ok(());
+        }
+
+        let new_capacity: u64 = len.max(capacity * 2).max(capacity + MIN_GROWTH);
+        self.file.set_len(new_capacity)?;
+        self.map = Mapping::new(&self.file, new_capacity as usize)?;
+
+        debug!("Grew {} to {} bytes", self.path.display(), new_capacity);
+        Ok(())
+    }
+
+    /// Rewrite the log with the live records only, once it is large and more than half of it is garbage.
+    fn compact_if_needed(&mut self) -> CacheResult<()> {
+        if self.end < MIN_COMPACTION_SIZE || self.garbage * 2 < self.end {
+            return Ok(());
+        }
+
+        let compacted_path: PathBuf = self.path.with_extension("compact");
+        let compacted: File = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&compacted_path)?;
+
+        let live: u64 = self.end - self.garbage;
+        compacted.set_len(live.max(MIN_GROWTH))?;
+
+        let mut offset: u64 = 0;
+        let mut relocated: Vec<(u64, u64)> = Vec::with_capacity(self.index.len());
+
+        for slot in self.index.values().filter(|slot| !slot.is_expired()) {
+            let start: usize = slot.record as usize;
+            let end: usize = (slot.value as usize) + slot.value_len;
+            compacted.write_all_at(&self.map.as_slice()[start..end], offset)?;
+
+            relocated.push((slot.record, offset));
+            offset += (end - start) as u64;
+        }
+
+        compacted.sync_data()?;
+        fs::rename(&compacted_path, &self.path)?;
+        if let Some(commit) = &self.commit {
+            commit.replace_file(compacted.try_clone()?);
+        }
+
+        // Point the index to the new locations, dropping the expired entries that were not copied
+        let moves: HashMap<u64, u64> = relocated.into_iter().collect();
+        let expiry: &mut ExpiryIndex<K> = &mut self.expiry;
+        self.index
+            .retain(|key, slot| match moves.get(&slot.record) {
+                Some(&new_record) => {
+                    slot.value = slot.value - slot.record + new_record;
+                    slot.record = new_record;
+                    true
+                }
+                None => {
+                    if let Some(expires_at) = slot.expires_at {
+                        expiry.remove(key, expires_at);
+                    }
+                    false
+                }
+            });
+
+        info!(
+            "Compacted {} from {} to {} bytes",
+            self.path.display(),
+            self.end,
+            offset
+        );
+
+        let capacity: usize = compacted.metadata()?.len() as usize;
+        self.map = Mapping::new(&compacted, capacity)?;
+        self.file = compacted;
+        self.end = offset;
+        self.garbage = 0;
+        Ok(())
+    }
+}
+
+impl<K> MmapState<K> {
+    /// Decode the value the given slot points to.
+    fn decode<V: Codec>(&self, slot: &Slot) -> CacheResult<V> {
+        let start: usize = slot.value as usize;
+        let bytes: &[u8] = &self.map.as_slice()[start..start + slot.value_len];
+        V::decode(bytes).ok_or_else(|| self.corrupted())
+    }
+
+    /// The total length of the record the given slot points to.
+    fn corrupted(&self) -> CacheError {
+        CacheError::Storage(format!("corrupted record in {}", self.path.display()))
+    }
+}
+
+impl<K> Drop for MmapState<K> {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.sync_data() {
+            warn!("Failed to sync {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// A record of the log, borrowed from the mapping.
+struct Record<'a> {
+    kind: u8,
+    expires_at: Option<u64>, // The wall-clock expiration time (see `current_walltime`)
+    key: &'a [u8],
+    value: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// Parse the record at the given offset. Returns `None` at the end of the log, or if the record is
+    /// incomplete or corrupted.
+    fn parse(data: &'a [u8], offset: u64) -> Option<Record<'a>> {
+        let data: &[u8] = data.get(offset as usize..)?;
+        let header: &[u8] = data.get(..HEADER_LEN)?;
+
+        if header[0] != RECORD_MAGIC || header[1] > RECORD_REMOVE {
+            return None;
+        }
+
+        let key_len: usize = u32::from_le_bytes(header[2..6].try_into().ok()?) as usize;
+        let value_len: usize = u32::from_le_bytes(header[6..10].try_into().ok()?) as usize;
+        let expires_at: u64 = u64::from_le_bytes(header[10..18].try_into().ok()?);
+        let checksum: u32 = u32::from_le_bytes(header[18..22].try_into().ok()?);
+
+        let key: &[u8] = data.get(HEADER_LEN..HEADER_LEN + key_len)?;
+        let value: &[u8] = data.get(HEADER_LEN + key_len..HEADER_LEN + key_len + value_len)?;
+
+        if checksum != Record::checksum(&header[..18], key, value) {
+            return None;
+        }
+
+        Some(Record {
+            kind: header[1],
+            expires_at: (expires_at != 0).then_some(expires_at),
+            key,
+            value,
+        })
+    }
+
+    /// Encode a record (header included) into bytes.
+    fn encode(kind: u8, key: &[u8], value: &[u8], expires_at: Option<u64>) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(HEADER_LEN + key.len() + value.len());
+        bytes.push(RECORD_MAGIC);
+        bytes.push(kind);
+        bytes.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&expires_at.unwrap_or(0).to_le_bytes());
+
+        let checksum: u32 = Record::checksum(&bytes, key, value);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes.extend_from_slice(key);
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    fn len(&self) -> usize {
+        HEADER_LEN + self.key.len() + self.value.len()
+    }
+
+    /// FNV-1a over the header fields, the key and the value, used to detect torn writes.
+    fn checksum(header: &[u8], key: &[u8], value: &[u8]) -> u32 {
+        let mut hash: u32 = 0x811C_9DC5;
+        for byte in header.iter().chain(key).chain(value) {
+            hash ^= *byte as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+        hash
+    }
+}
+
+/// A read-only, shared memory mapping of a whole file.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is read-only and only unmapped on drop, so it can be shared across threads.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(file: &File, len: usize) -> io::Result<Mapping> {
+        // SAFETY: We map a file we own for reading only; the mapping is released exactly once in `drop`.
+        let ptr: *mut libc::c_void = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: `ptr` points to a live mapping of `len` bytes (the file is never shrunk while mapped).
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` are exactly what `mmap` returned and was called with.
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
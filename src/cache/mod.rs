@@ -0,0 +1,562 @@
+use std::{hash::Hash, sync::Arc};
+
+use crate::error::{CacheError, CacheResult};
+
+mod access;
+mod aof;
+mod builder;
+mod bytes;
+mod codec;
+mod compressed;
+mod engine;
+mod entry;
+mod expiry;
+mod group_commit;
+mod lease;
+mod leftright;
+mod lfu;
+mod listener;
+mod loading;
+mod lru;
+mod memory;
+mod mmap;
+mod routed;
+mod sharded;
+mod simple;
+mod singleflight;
+mod sink;
+mod slab;
+mod sweeper;
+mod tiered;
+mod typed;
+
+pub use access::{AccessTrackingCache, KeyAccess};
+pub use aof::{AppendOnlyLog, FsyncPolicy, LogReplay, ReplayStats};
+pub use builder::CacheBuilder;
+pub use bytes::{Bytes, NativeValue};
+pub use codec::Codec;
+pub use compressed::{CompressingCache, CompressionStats};
+pub use engine::{
+    EngineOptions, EvictionPolicy, LeftRightEngine, LfuEngine, LruEngine, MmapEngine,
+    ShardedEngine, SimpleEngine, StorageEngine, TieredEngine, BUILTIN_ENGINES, DEFAULT_ENGINE,
+};
+pub use lease::RecomputeLeases;
+pub use leftright::LeftRightCache;
+pub use lfu::LfuCache;
+pub use listener::{RemovalCause, RemovalListener};
+pub use loading::{LoadResult, LoadingCache};
+pub use lru::LruCache;
+pub(crate) use memory::estimated_entry_size;
+pub use memory::{HeapSize, MaxMemoryPolicy, MemoryStats};
+pub use mmap::{MmapCache, SyncPolicy};
+pub use routed::RoutedCache;
+pub use sharded::ShardedCache;
+pub use simple::SimpleCache;
+pub use singleflight::SingleFlight;
+pub use sink::{SinkError, WriteBehindCache, WriteBehindOptions, WriteSink, WriteThroughCache};
+pub use slab::SlabStats;
+pub use sweeper::{ExpirationSweeper, SweepStats, SweeperOptions};
+pub use tiered::TieredCache;
+pub use typed::TypedCache;
+
+/// A basic cache interface allowing put, get, and remove operations.
+///
+/// The cache is generic over its key (`K`) and value (`V`) types. Values are handed out by cloning, so large
+/// or structured values are usually stored behind an `Arc` (e.g. `Cache<String, Arc<MyRecord>>`) to make
+/// reads cheap and to share them across threads.
+pub trait Cache<K, V>: Send + Sync {
+    /// Puts a key-value pair into the cache with an optional time-to-live (TTL) in seconds.
+    ///
+    /// # Arguments
+    /// * `key` - The key to associate with the value.
+    /// * `value` - The value to store in the cache.
+    /// * `ttl` - An optional time-to-live (TTL) in seconds for the key-value pair. If `None`, the key-value
+    ///   pair lives indefinitely. Otherwise, it is invalidated after the TTL has elapsed.
+    ///
+    /// # Errors
+    /// * `CacheError::LockPoisoned` if the cache became unusable because a thread panicked while writing.
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()>;
+
+    /// Puts a key-value pair into the cache like `put`, only if the key currently has a value (or has none),
+    /// as a single step: no other write to the key can come in between.
+    ///
+    /// # Arguments
+    /// * `condition` - Whether the key must have a value, or none, for the value to be put.
+    ///
+    /// # Returns
+    /// * Whether the value was put.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache cannot put values conditionally, which is the default.
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        let _ = (key, value, ttl, condition);
+        Err(CacheError::Unsupported("put_if"))
+    }
+
+    /// Puts the given key-value pairs into the cache as a single write, taking the lock of the cache once.
+    /// The default puts them one by one, so a concurrent reader may see some of them only.
+    ///
+    /// # Arguments
+    /// * `entries` - The key-value pairs to put. A key given twice keeps its last value.
+    /// * `ttl` - An optional time-to-live (TTL) in seconds for all the values. If `None`, they live
+    ///   indefinitely.
+    ///
+    /// # Errors
+    /// * If the write fails midway (e.g. with `CacheError::OutOfMemory`), the pairs put so far are kept.
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        for (key, value) in entries {
+            self.put(key, value, ttl)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the value associated with the given key with one computed from the current value, as a
+    /// single step: no other write to the key can come in between (e.g. to increment a counter). The value
+    /// keeps its TTL, while a new key gets none.
+    ///
+    /// # Arguments
+    /// * `key` - The key whose value is replaced.
+    /// * `update` - Given the current value (`None` if the key has no value, or it has expired), returns
+    ///   the new value, or an error to leave the key unchanged.
+    ///
+    /// # Returns
+    /// * The new value.
+    ///
+    /// # Errors
+    /// * The error returned by `update`.
+    /// * `CacheError::Unsupported` if the cache cannot update its values in place, which is the default.
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let _ = (key, update);
+        Err(CacheError::Unsupported("update"))
+    }
+
+    /// Modifies the value associated with the given key in place like `update`, as a single step, rather
+    /// than replacing it with a new one (e.g. to push to a list without copying it). The value keeps its
+    /// TTL, while a new key gets none.
+    ///
+    /// By default, the value is modified as a copy which then replaces it (see `update`), as the caches
+    /// which check the values they take (e.g. `LruCache` with a max memory) have to.
+    ///
+    /// # Arguments
+    /// * `key` - The key whose value is modified.
+    /// * `modify` - Given the current value to modify (`None` if the key has no value, or it has expired),
+    ///   returns the value to give the key if it has none (`None` to leave it without), or an error, which
+    ///   leaves the key unchanged only if returned before modifying the value.
+    ///
+    /// # Errors
+    /// * The error returned by `modify`.
+    /// * `CacheError::Unsupported` if the cache cannot update its values in place, which is the default.
+    fn modify(
+        &self,
+        key: K,
+        modify: &mut dyn FnMut(Option<&mut V>) -> CacheResult<Option<V>>,
+    ) -> CacheResult<()>
+    where
+        V: Clone,
+    {
+        let mut left_absent: bool = false;
+        let updated: CacheResult<V> = self.update(key, &mut |current| {
+            let value: Option<V> = match current.cloned() {
+                Some(mut value) => modify(Some(&mut value)).map(|_| Some(value))?,
+                None => modify(None)?,
+            };
+            left_absent = value.is_none();
+            value.ok_or(CacheError::Unsupported("modify")) // Leaves the key without a value
+        });
+        match updated {
+            Err(_) if left_absent => Ok(()),
+            updated => updated.map(|_| ()),
+        }
+    }
+
+    /// Puts a key-value pair into the cache like `put`, returning the value it replaces, as a single step:
+    /// no other write to the key can come in between (e.g. to claim a token).
+    ///
+    /// # Returns
+    /// * The previous value of the key, or `None` if it had none (or it had expired).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache cannot replace its values in a single step.
+    fn replace(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<Option<V>> {
+        self.replace_if(key, value, ttl, &mut |_| Ok(()))
+    }
+
+    /// Puts a key-value pair into the cache like `replace`, only if `accept` accepts the value it replaces,
+    /// as a single step: no other write to the key can come in between the check and the put (e.g. to
+    /// only replace a value of some type).
+    ///
+    /// # Arguments
+    /// * `accept` - Given the current value (`None` if the key has no value, or it has expired), returns an
+    ///   error to leave the key unchanged.
+    ///
+    /// # Errors
+    /// * The error returned by `accept`.
+    /// * `CacheError::Unsupported` if the cache cannot replace its values in a single step, which is the
+    ///   default.
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let _ = (key, value, ttl, accept);
+        Err(CacheError::Unsupported("replace"))
+    }
+
+    /// Moves the value of a key to another key along with its TTL, as a single write: no other write to
+    /// either key can come in between. The value of the other key, if any, is replaced.
+    ///
+    /// # Arguments
+    /// * `from` - The key whose value is moved.
+    /// * `to` - The key the value is moved to.
+    /// * `if_absent` - Whether to only move the value if `to` has no value (or it has expired).
+    ///
+    /// # Returns
+    /// * `None` if `from` has no value (or it has expired), otherwise whether the value was moved.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache cannot move its values, which is the default.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let _ = (from, to, if_absent);
+        Err(CacheError::Unsupported("rename"))
+    }
+
+    /// Gets the value associated with the given key from the cache.
+    ///
+    /// # Arguments
+    /// * `key` - The key to look up in the cache.
+    ///
+    /// # Returns
+    /// * An `Option` containing the value associated with the key if it exists and has not expired.
+    fn get(&self, key: &K) -> CacheResult<Option<V>>;
+
+    /// Gets the values associated with the given keys, taking the lock of the cache once. The default gets
+    /// them one by one.
+    ///
+    /// # Returns
+    /// * The value of every key, in the order of the keys (`None` for a key without one, or if it expired).
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Removes the key-value pair associated with the given key from the cache.
+    ///
+    /// # Arguments
+    /// * `key` - The key to remove from the cache.
+    ///
+    /// # Returns
+    /// * An `Option` containing the value associated with the key if it existed and was removed.
+    fn remove(&self, key: &K) -> CacheResult<Option<V>>;
+
+    /// Removes the key-value pair associated with the given key like `remove`, also returning when the value
+    /// would have expired (e.g. to restore it later with the rest of its TTL).
+    ///
+    /// # Returns
+    /// * The removed value and its expiration time (monotime, `None` without a TTL), if the key existed and
+    ///   had not expired.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache does not tell the expiration times.
+    fn remove_entry(&self, key: &K) -> CacheResult<Option<(V, Option<u64>)>> {
+        self.remove_entry_if(key, &mut |_| Ok(()))
+    }
+
+    /// Removes the key-value pair associated with the given key like `remove_entry`, only if `accept`
+    /// accepts its value, as a single step: no other write to the key can come in between the check and
+    /// the removal (e.g. to only remove a value of some type).
+    ///
+    /// # Arguments
+    /// * `accept` - Given the value of the key (if it has one, and it has not expired), returns an error to
+    ///   leave the key unchanged.
+    ///
+    /// # Errors
+    /// * The error returned by `accept`.
+    /// * `CacheError::Unsupported` if the cache does not tell the expiration times, which is the default.
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let _ = (key, accept);
+        Err(CacheError::Unsupported("remove_entry"))
+    }
+
+    /// Changes the time-to-live (TTL) of the value associated with the given key, keeping the value.
+    ///
+    /// # Arguments
+    /// * `key` - The key whose TTL is changed.
+    /// * `ttl` - The new TTL in seconds, counted from now. If `None`, the value lives indefinitely.
+    ///
+    /// # Returns
+    /// * `false` if the key has no value, or it has expired.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache cannot change the TTLs, which is the default.
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        let _ = (key, ttl);
+        Err(CacheError::Unsupported("expire"))
+    }
+
+    /// Restarts the time-to-live of the value associated with the given key from now, for the TTL it was
+    /// given when put (or by `expire`), keeping the value. A value without a TTL is left as it is.
+    ///
+    /// # Returns
+    /// * `false` if the key has no value, or it has expired.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache does not keep the TTLs of its values, which is the default.
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        let _ = key;
+        Err(CacheError::Unsupported("touch"))
+    }
+
+    /// Tells when the value associated with the given key expires.
+    ///
+    /// # Returns
+    /// * `None` if the key has no value (or it has expired), otherwise the expiration time of the value
+    ///   (monotime, `None` without a TTL).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache does not tell the expiration times, which is the default.
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        let _ = key;
+        Err(CacheError::Unsupported("expiration"))
+    }
+
+    /// Deletes the expired entries of the cache (or of a portion of it, for caches swept incrementally),
+    /// which would otherwise only be deleted when accessed. Called periodically by an `ExpirationSweeper`.
+    ///
+    /// The default implementation does nothing, which suits caches that expire their entries themselves.
+    ///
+    /// # Arguments
+    /// * `max_expired` - The most expired entries to delete, which bounds how long the sweep holds the
+    ///   locks of the cache. The others are left to the next sweeps.
+    ///
+    /// # Returns
+    /// * How many entries were examined and how many of them were expired.
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let _ = max_expired;
+        Ok(SweepStats::default())
+    }
+
+    /// Visits the live entries of the cache as they were at a single point in time, while writes go on
+    /// (e.g. to save or replicate the whole cache). The writes made during the scan are not visited.
+    ///
+    /// # Arguments
+    /// * `visit` - Called with every key and value of the snapshot, in no particular order.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache cannot take snapshots, which is the default.
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        let _ = visit;
+        Err(CacheError::Unsupported("scan"))
+    }
+
+    /// Visits the live entries of the cache along with their expiration times like `scan`, until `visit`
+    /// returns `false` (e.g. to sample the remaining TTLs of the cache, or to dump it).
+    ///
+    /// # Arguments
+    /// * `visit` - Called with the key, value and expiration time (monotime, `None` without a TTL) of the
+    ///   entries of the snapshot, in no particular order. Returns whether to go on.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache cannot take snapshots, which is the default.
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        let _ = visit;
+        Err(CacheError::Unsupported("scan_with_expirations"))
+    }
+
+    /// Visits a batch of the live entries, resuming an iteration where the previous batch left it. Unlike
+    /// `scan`, nothing is held between the batches, and each one only holds the locks it reads under: the
+    /// entries live for the whole iteration are visited exactly once, while the ones written or removed in
+    /// the meantime may or may not be.
+    ///
+    /// # Arguments
+    /// * `cursor` - Where to resume the iteration: 0 to start it, else the cursor returned by the previous
+    ///   batch.
+    /// * `count` - The number of entries to visit, a hint the last batch falls short of.
+    /// * `visit` - Called with the key and value of every entry of the batch.
+    ///
+    /// # Returns
+    /// * The cursor of the next batch, or 0 once the iteration is over.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache cannot be iterated incrementally, which is the default.
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        let _ = (cursor, count, visit);
+        Err(CacheError::Unsupported("scan_from"))
+    }
+
+    /// Counts the live entries of the cache, leaving out the expired ones not deleted yet.
+    ///
+    /// The default implementation counts the entries visited by `scan`.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache can neither count its entries nor take snapshots.
+    fn len(&self) -> CacheResult<usize> {
+        let mut len: usize = 0;
+        self.scan(&mut |_, _| len += 1)?;
+        Ok(len)
+    }
+
+    /// Whether the cache has no live entry.
+    fn is_empty(&self) -> CacheResult<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Removes every entry of the cache at once, e.g. to start over.
+    ///
+    /// # Returns
+    /// * The entries removed, which are only dropped along with the returned box. So the caller chooses
+    ///   where the drop happens (e.g. on a background thread, since dropping a large cache takes a while).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the cache cannot be cleared at once, which is the default. Its keys can
+    ///   still be removed one by one.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        Err(CacheError::Unsupported("clear"))
+    }
+
+    /// Makes the writes made so far durable, for the caches persisting their data (e.g. before the server
+    /// shuts down).
+    ///
+    /// The default implementation does nothing, which suits the caches keeping their data in memory only.
+    fn flush(&self) -> CacheResult<()> {
+        Ok(())
+    }
+
+    /// The memory taken by the entries of the cache, for the caches accounting for it (e.g. to report it
+    /// along with the limit and the evictions).
+    ///
+    /// The default implementation returns `None`, for the caches which do not account for their memory.
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        None
+    }
+
+    /// The bytes taken by the entry of the given key (its key, its value and their bookkeeping), as counted
+    /// towards the memory of the cache, for the caches accounting for it (see `memory_stats`).
+    ///
+    /// # Returns
+    /// * `None` if the key has no value (or it has expired).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` by default, for the caches which do not account for their memory.
+    fn memory_usage(&self, _key: &K) -> CacheResult<Option<usize>> {
+        Err(CacheError::Unsupported("memory_usage"))
+    }
+
+    /// Change the most bytes the entries of the cache may take (`None` for no limit) and what happens to
+    /// the writes beyond, for the caches accounting for their memory (see `memory_stats`). Under the `Evict`
+    /// policy, the entries beyond a lowered limit are evicted right away; under `NoEviction`, they are kept
+    /// and the writes are rejected until enough of them are gone.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` by default, for the caches which do not account for their memory.
+    fn set_max_memory(
+        &self,
+        _max_bytes: Option<usize>,
+        _policy: MaxMemoryPolicy,
+    ) -> CacheResult<()> {
+        Err(CacheError::Unsupported("set_max_memory"))
+    }
+}
+
+/// When a conditional put (see `Cache::put_if`) writes its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PutCondition {
+    /// Only if the key has no value (or it has expired), e.g. to take a lock or to create a key once.
+    IfAbsent,
+
+    /// Only if the key has a value, e.g. to refresh a value without resurrecting a deleted key.
+    IfPresent,
+}
+
+impl PutCondition {
+    /// Whether the value is put, given whether the key has a value.
+    pub fn allows(&self, present: bool) -> bool {
+        match self {
+            PutCondition::IfAbsent => !present,
+            PutCondition::IfPresent => present,
+        }
+    }
+}
+
+/// Creates the cache instances shared by the server and embedding applications.
+pub struct CacheFactory;
+
+impl CacheFactory {
+    /// Create a new, empty cache backed by the default storage engine.
+    pub fn new_cache<K, V>() -> Arc<dyn Cache<K, V>>
+    where
+        K: Eq + Hash + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        Arc::new(ShardedCache::default())
+    }
+
+    /// Create a new, empty cache holding at most the given number of entries, evicting the entries beyond
+    /// with the given policy.
+    pub fn new_bounded_cache<K, V>(
+        policy: EvictionPolicy,
+        max_entries: usize,
+    ) -> Arc<dyn Cache<K, V>>
+    where
+        K: Eq + Hash + HeapSize + Send + Sync + 'static,
+        V: Clone + HeapSize + Send + Sync + 'static,
+    {
+        match policy {
+            EvictionPolicy::Lru => Arc::new(LruCache::new(max_entries)),
+            EvictionPolicy::Lfu => Arc::new(LfuCache::new(max_entries)),
+        }
+    }
+
+    /// Create a new, empty cache backed by the given storage engine, which may be a custom one.
+    pub fn new_cache_with<K, V>(
+        engine: &dyn StorageEngine<K, V>,
+    ) -> CacheResult<Arc<dyn Cache<K, V>>> {
+        engine.create()
+    }
+
+    /// Create a new, empty cache backed by the built-in storage engine with the given name.
+    ///
+    /// # Arguments
+    /// * `engine` - The name of the engine (see `BUILTIN_ENGINES`).
+    /// * `options` - The tuning options of the engine (e.g. the number of shards).
+    ///
+    /// # Errors
+    /// * `CacheError::UnknownEngine` if there is no built-in engine with that name.
+    /// * `CacheError::Storage` if the engine fails to set up its resources.
+    pub fn new_cache_named<K, V>(
+        engine: &str,
+        options: &EngineOptions,
+    ) -> CacheResult<Arc<dyn Cache<K, V>>>
+    where
+        K: Eq + Hash + Clone + Codec + HeapSize + Send + Sync + 'static,
+        V: Clone + Codec + HeapSize + Send + Sync + 'static,
+    {
+        engine::builtin::<K, V>(engine, options)?.create()
+    }
+}
@@ -0,0 +1,329 @@
+use std::{collections::HashMap, sync::Arc};
+
+use super::{Cache, MaxMemoryPolicy, MemoryStats, PutCondition, SweepStats};
+use crate::{
+    error::{CacheError, CacheResult},
+    namespaces::namespace_of,
+    utils::current_monotime,
+};
+
+/// A cache keeping the keys of some namespaces in storage engines of their own, and the other keys in a
+/// default one, e.g. a large `archive` namespace in the persistent `mmap` engine while the hot keys stay
+/// in memory. A key belongs to the namespace before its first `:` (see `namespaces::namespace_of`), in
+/// every database.
+///
+/// Each engine only ever sees its own keys, so the writes touching several engines at once (`put_many`, or
+/// `rename` across namespaces) are made engine by engine, and a reader may see some of them only.
+pub struct RoutedCache<V> {
+    routes: Vec<Arc<dyn Cache<String, V>>>, // The default engine, then those of the namespaces
+    namespaces: HashMap<String, usize>,     // The index of the engine of every routed namespace
+}
+
+impl<V> RoutedCache<V> {
+    /// Create a new `RoutedCache` keeping every key in the given cache, until namespaces are routed away.
+    pub fn new(default: Arc<dyn Cache<String, V>>) -> RoutedCache<V> {
+        RoutedCache {
+            routes: vec![default],
+            namespaces: HashMap::new(),
+        }
+    }
+
+    /// Keep the keys of the given namespace in the given cache, which should be empty of any other key.
+    pub fn with_namespace(
+        mut self,
+        namespace: &str,
+        cache: Arc<dyn Cache<String, V>>,
+    ) -> RoutedCache<V> {
+        self.namespaces
+            .insert(namespace.to_string(), self.routes.len());
+        self.routes.push(cache);
+        self
+    }
+
+    /// The namespaces kept in engines of their own.
+    pub fn routed_namespaces(&self) -> Vec<&str> {
+        let mut namespaces: Vec<&str> = self.namespaces.keys().map(String::as_str).collect();
+        namespaces.sort_unstable();
+        namespaces
+    }
+
+    /// Get the index of the engine keeping the given key.
+    fn _route_index(&self, key: &str) -> usize {
+        match self.namespaces.is_empty() {
+            true => 0,
+            false => namespace_of(key)
+                .and_then(|namespace| self.namespaces.get(namespace))
+                .map_or(0, |&index| index),
+        }
+    }
+
+    /// Get the engine keeping the given key.
+    fn _route(&self, key: &str) -> &Arc<dyn Cache<String, V>> {
+        &self.routes[self._route_index(key)]
+    }
+}
+
+impl<V> Cache<String, V> for RoutedCache<V>
+where
+    V: Clone + Send + Sync,
+{
+    fn put(&self, key: String, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        self._route(&key).put(key, value, ttl)
+    }
+
+    /// The keys of every engine are put as a single write of that engine, one engine after the other.
+    fn put_many(&self, entries: Vec<(String, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        let mut batches: Vec<Vec<(String, V)>> = vec![Vec::new(); self.routes.len()];
+        for (key, value) in entries {
+            batches[self._route_index(&key)].push((key, value));
+        }
+        for (route, batch) in self.routes.iter().zip(batches) {
+            if !batch.is_empty() {
+                route.put_many(batch, ttl)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn put_if(
+        &self,
+        key: String,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        self._route(&key).put_if(key, value, ttl, condition)
+    }
+
+    fn update(
+        &self,
+        key: String,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        self._route(&key).update(key, update)
+    }
+
+    fn modify(
+        &self,
+        key: String,
+        modify: &mut dyn FnMut(Option<&mut V>) -> CacheResult<Option<V>>,
+    ) -> CacheResult<()>
+    where
+        V: Clone,
+    {
+        self._route(&key).modify(key, modify)
+    }
+
+    fn replace_if(
+        &self,
+        key: String,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        self._route(&key).replace_if(key, value, ttl, accept)
+    }
+
+    /// A value moved to a key of another engine is removed from its engine, then put into the other one
+    /// with the rest of its TTL (or put back if that fails), so a reader may briefly see neither key.
+    fn rename(&self, from: &String, to: String, if_absent: bool) -> CacheResult<Option<bool>> {
+        let (source, target): (usize, usize) = (self._route_index(from), self._route_index(&to));
+        if source == target {
+            return self.routes[source].rename(from, to, if_absent);
+        }
+
+        if self.routes[source].expiration(from)?.is_none() {
+            return Ok(None);
+        }
+        if if_absent && self.routes[target].expiration(&to)?.is_some() {
+            return Ok(Some(false));
+        }
+        let Some((value, expires_at)) = self.routes[source].remove_entry(from)? else {
+            return Ok(None);
+        };
+        let ttl: Option<u64> = expires_at
+            .map(|expires_at| expires_at.saturating_sub(current_monotime()).div_ceil(1000));
+        if let Err(e) = self.routes[target].put(to, value.clone(), ttl) {
+            self.routes[source].put(from.clone(), value, ttl)?;
+            return Err(e);
+        }
+        Ok(Some(true))
+    }
+
+    fn get(&self, key: &String) -> CacheResult<Option<V>> {
+        self._route(key).get(key)
+    }
+
+    /// The keys of every engine are looked up at once, one engine after the other.
+    fn get_many(&self, keys: &[String]) -> CacheResult<Vec<Option<V>>> {
+        let mut batches: Vec<(Vec<usize>, Vec<String>)> =
+            vec![(Vec::new(), Vec::new()); self.routes.len()];
+        for (position, key) in keys.iter().enumerate() {
+            let (positions, keys) = &mut batches[self._route_index(key)];
+            positions.push(position);
+            keys.push(key.clone());
+        }
+
+        let mut values: Vec<Option<V>> = vec![None; keys.len()];
+        for (route, (positions, keys)) in self.routes.iter().zip(batches) {
+            if keys.is_empty() {
+                continue;
+            }
+            for (position, value) in positions.into_iter().zip(route.get_many(&keys)?) {
+                values[position] = value;
+            }
+        }
+        Ok(values)
+    }
+
+    fn remove(&self, key: &String) -> CacheResult<Option<V>> {
+        self._route(key).remove(key)
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &String,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        self._route(key).remove_entry_if(key, accept)
+    }
+
+    fn expire(&self, key: &String, ttl: Option<u64>) -> CacheResult<bool> {
+        self._route(key).expire(key, ttl)
+    }
+
+    fn touch(&self, key: &String) -> CacheResult<bool> {
+        self._route(key).touch(key)
+    }
+
+    fn expiration(&self, key: &String) -> CacheResult<Option<Option<u64>>> {
+        self._route(key).expiration(key)
+    }
+
+    /// Sweep every engine in turn, each one taking an equal share of `max_expired`.
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let mut stats: SweepStats = SweepStats::default();
+        let per_route: usize = max_expired.div_ceil(self.routes.len());
+        for route in &self.routes {
+            stats += route.sweep_expired(per_route)?;
+        }
+        Ok(stats)
+    }
+
+    /// Scan the engines one after the other, each one as of its own point in time.
+    fn scan(&self, visit: &mut dyn FnMut(&String, &V)) -> CacheResult<()> {
+        for route in &self.routes {
+            route.scan(visit)?;
+        }
+        Ok(())
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&String, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        let mut going_on: bool = true;
+        for route in &self.routes {
+            route.scan_with_expirations(&mut |key, value, expires_at| {
+                going_on = visit(key, value, expires_at);
+                going_on
+            })?;
+            if !going_on {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Visit the engines one after the other. The cursor packs the engine being visited with the cursor to
+    /// resume from within it (`cursor * engines + engine`).
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&String, &V),
+    ) -> CacheResult<u64> {
+        let routes: u64 = self.routes.len() as u64;
+        let mut route: u64 = cursor % routes;
+        let mut inner: u64 = cursor / routes;
+
+        let mut count: usize = count.max(1);
+        while let Some(cache) = self.routes.get(route as usize) {
+            let mut visited: usize = 0;
+            let next: u64 = cache.scan_from(inner, count, &mut |key, value| {
+                visited += 1;
+                visit(key, value);
+            })?;
+            if next != 0 {
+                return Ok(next * routes + route);
+            }
+            count = count.saturating_sub(visited);
+            route += 1;
+            inner = 0;
+            if count == 0 && (route as usize) < self.routes.len() {
+                return Ok(route);
+            }
+        }
+        Ok(0)
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        self.routes.iter().map(|route| route.len()).sum()
+    }
+
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        V: 'static,
+    {
+        let cleared: Vec<Box<dyn Send>> = self
+            .routes
+            .iter()
+            .map(|route| route.clear())
+            .collect::<CacheResult<_>>()?;
+        Ok(Box::new(cleared))
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        for route in &self.routes {
+            route.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The memory of the engines accounting for it, added up.
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        let mut total: Option<MemoryStats> = None;
+        for stats in self.routes.iter().filter_map(|route| route.memory_stats()) {
+            total = Some(match total {
+                Some(total) => MemoryStats {
+                    used_bytes: total.used_bytes + stats.used_bytes,
+                    max_bytes: total.max_bytes.zip(stats.max_bytes).map(|(a, b)| a + b),
+                    evictions: total.evictions + stats.evictions,
+                },
+                None => stats,
+            });
+        }
+        total
+    }
+
+    fn memory_usage(&self, key: &String) -> CacheResult<Option<usize>> {
+        self._route(key).memory_usage(key)
+    }
+
+    /// Every engine accounting for its memory takes the limit, each of them being bounded on its own.
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        let mut bounded: bool = false;
+        for route in self
+            .routes
+            .iter()
+            .filter(|route| route.memory_stats().is_some())
+        {
+            route.set_max_memory(max_bytes, policy)?;
+            bounded = true;
+        }
+        match bounded {
+            true => Ok(()),
+            false => Err(CacheError::Unsupported("set_max_memory")),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    hash::{BuildHasher, Hash, RandomState},
+    thread,
+};
+
+use super::{
+    simple::{GetBatch, PutBatch, Snapshot},
+    Cache, PutCondition, SimpleCache, SlabStats, SweepStats,
+};
+use crate::error::CacheResult;
+
+/// A `Cache` implementation partitioning the keys across independently locked shards.
+///
+/// Each key is hashed to exactly one shard, and every shard is a `SimpleCache` with its own `RwLock`. So,
+/// writes to keys living in different shards no longer serialize on a single lock.
+pub struct ShardedCache<K, V> {
+    shards: Box<[SimpleCache<K, V>]>, // The shards, each one guarding its part of the key space
+    hasher: RandomState,              // Used to map a key to its shard
+}
+
+impl<K, V> ShardedCache<K, V> {
+    /// Create a new, empty `ShardedCache` with the given number of shards (at least one).
+    pub fn new(shards: usize) -> ShardedCache<K, V> {
+        ShardedCache {
+            shards: (0..shards.max(1)).map(|_| SimpleCache::new()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The number of shards used when none is specified: the number of CPUs available to the process.
+    pub fn default_shard_count() -> usize {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    }
+
+    /// The number of shards the key space is partitioned into.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The number of expired entries deleted so far, across all shards.
+    pub fn expirations(&self) -> u64 {
+        self.shards.iter().map(SimpleCache::expirations).sum()
+    }
+
+    /// The occupancy of the slabs holding the entries, across all shards.
+    pub fn slab_stats(&self) -> SlabStats {
+        let mut stats: SlabStats = SlabStats::default();
+        for shard in self.shards.iter() {
+            stats += shard.slab_stats();
+        }
+        stats
+    }
+}
+
+impl<K: Hash, V> ShardedCache<K, V> {
+    /// Get the shard responsible for the given key.
+    fn shard(&self, key: &K) -> &SimpleCache<K, V> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Get the index of the shard responsible for the given key.
+    fn shard_index(&self, key: &K) -> usize {
+        let hash: u64 = self.hasher.hash_one(key);
+        (hash % self.shards.len() as u64) as usize
+    }
+}
+
+impl<K, V> Default for ShardedCache<K, V> {
+    fn default() -> ShardedCache<K, V> {
+        ShardedCache::new(ShardedCache::<K, V>::default_shard_count())
+    }
+}
+
+impl<K, V> Cache<K, V> for ShardedCache<K, V>
+where
+    K: Eq + Hash + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        self.shard(&key).put(key, value, ttl)
+    }
+
+    /// The shards of the keys are locked all at once, in the order of the shards, so that a reader sees
+    /// either none or all of the values.
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        let mut batches: BTreeMap<usize, Vec<(K, V)>> = BTreeMap::new();
+        for (key, value) in entries {
+            batches
+                .entry(self.shard_index(&key))
+                .or_default()
+                .push((key, value));
+        }
+
+        let batches: Vec<PutBatch<K, V>> = batches
+            .into_iter()
+            .map(|(index, entries)| (&self.shards[index], entries))
+            .collect();
+        SimpleCache::put_all(batches, ttl)
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        self.shard(&key).put_if(key, value, ttl, condition)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        self.shard(&key).update(key, update)
+    }
+
+    fn modify(
+        &self,
+        key: K,
+        modify: &mut dyn FnMut(Option<&mut V>) -> CacheResult<Option<V>>,
+    ) -> CacheResult<()>
+    where
+        V: Clone,
+    {
+        self.shard(&key).modify(key, modify)
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        self.shard(&key).replace_if(key, value, ttl, accept)
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        self.shard(key).get(key)
+    }
+
+    /// The shards of the keys are locked all at once, so that the values are read as of the same time.
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        let mut batches: BTreeMap<usize, Vec<(usize, &K)>> = BTreeMap::new();
+        for (position, key) in keys.iter().enumerate() {
+            batches
+                .entry(self.shard_index(key))
+                .or_default()
+                .push((position, key));
+        }
+
+        let batches: Vec<GetBatch<K, V>> = batches
+            .into_iter()
+            .map(|(index, keys)| (&self.shards[index], keys))
+            .collect();
+        SimpleCache::get_all(&batches, keys.len())
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        self.shard(key).remove(key)
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        self.shard(key).remove_entry_if(key, accept)
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        self.shard(key).expire(key, ttl)
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        self.shard(key).touch(key)
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        self.shard(key).expiration(key)
+    }
+
+    /// Sweep every shard in turn, so only one of them is locked at a time, each shard taking an equal share
+    /// of `max_expired`.
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let mut stats: SweepStats = SweepStats::default();
+        let per_shard: usize = max_expired.div_ceil(self.shards.len().max(1));
+        for shard in self.shards.iter() {
+            stats += shard.sweep_expired(per_shard)?;
+        }
+        Ok(stats)
+    }
+
+    /// Snapshot all the shards at once, so that the scan is consistent across shards, then visit them
+    /// without holding any lock.
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        let snapshots: Vec<Snapshot<K, V>> = SimpleCache::snapshot_all(&self.shards)?;
+        for snapshot in &snapshots {
+            snapshot.for_each(visit);
+        }
+        Ok(())
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        let snapshots: Vec<Snapshot<K, V>> = SimpleCache::snapshot_all(&self.shards)?;
+        for snapshot in &snapshots {
+            if !snapshot.for_each_with_expiration(visit) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lock the shards of both keys (in the order of the shards) when they differ.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let (source, target): (usize, usize) = (self.shard_index(from), self.shard_index(&to));
+        match source == target {
+            true => self.shards[source].rename(from, to, if_absent),
+            false => SimpleCache::rename_between(
+                &self.shards[source],
+                &self.shards[target],
+                from,
+                to,
+                if_absent,
+                source < target,
+            ),
+        }
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    /// Clear the shards one after the other, locking one at a time.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let cleared: Vec<Box<dyn Send>> = self
+            .shards
+            .iter()
+            .map(|shard| shard.clear())
+            .collect::<CacheResult<_>>()?;
+        Ok(Box::new(cleared))
+    }
+
+    /// Visit the shards one after the other, locking one at a time. The cursor packs the shard being
+    /// visited with the slot to resume from within it (`slot * shards + shard`).
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        let shards: u64 = self.shards.len() as u64;
+        let mut shard: u64 = cursor % shards;
+        let mut slot: usize = (cursor / shards) as usize;
+
+        let mut count: usize = count.max(1);
+        while let Some(cache) = self.shards.get(shard as usize) {
+            if let Some(next) = cache.scan_slots(slot, &mut count, visit)? {
+                return Ok(next as u64 * shards + shard);
+            }
+            shard += 1;
+            slot = 0;
+        }
+        Ok(0)
+    }
+}
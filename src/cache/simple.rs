@@ -0,0 +1,595 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+use super::{
+    entry::CacheEntry,
+    expiry::ExpiryIndex,
+    slab::{Slab, SlabStats},
+    Cache, PutCondition, SweepStats,
+};
+use crate::{error::CacheResult, utils::current_monotime};
+
+/// The entries to put into one of several caches at once (see `SimpleCache::put_all`).
+pub(super) type PutBatch<'a, K, V> = (&'a SimpleCache<K, V>, Vec<(K, V)>);
+
+/// The keys to look up in one of several caches at once, along with their positions in the values looked
+/// up (see `SimpleCache::get_all`).
+pub(super) type GetBatch<'a, 'k, K, V> = (&'a SimpleCache<K, V>, Vec<(usize, &'k K)>);
+
+/// The default `Cache` implementation: a single in-memory map guarded by a read-write lock.
+pub struct SimpleCache<K, V> {
+    /// A HashMap to store key-value pairs in memory. By using a `RwLock`, we can allow multiple readers
+    /// concurrently. Write operations are exclusive. So, only one writer can modify the cache at a time.
+    /// For a more fine-grained locking mechanism, we can consider external crates like `dashmap` or `flurry`.
+    ///
+    /// The entries are shared with the snapshots taken by `scan`, and copied by the first write made while
+    /// a snapshot is alive (copy-on-write). So a scan never blocks the writers for its whole duration.
+    cache: RwLock<Arc<Entries<K, V>>>,
+    expirations: AtomicU64, // The number of expired entries deleted so far
+}
+
+/// The entries of a `SimpleCache`, along with the index of their expiration times.
+///
+/// The entries live in a slab whose slots are reused, while the map only holds their slot indices. So the
+/// churn of entries does not go through the allocator, and the map stays compact when it grows.
+struct Entries<K, V> {
+    map: HashMap<Arc<K>, usize>, // The slab slots of the entries by key
+    slab: Slab<(Arc<K>, CacheEntry<V>)>, // The entries, along with their keys (see `scan_slots`)
+    expiry: ExpiryIndex<K>,      // The keys of the entries with a TTL by expiration time
+}
+
+impl<K, V> SimpleCache<K, V> {
+    /// Create a new, empty `SimpleCache`.
+    pub fn new() -> SimpleCache<K, V> {
+        SimpleCache {
+            cache: RwLock::new(Arc::new(Entries::new())),
+            expirations: AtomicU64::new(0),
+        }
+    }
+
+    /// The number of expired entries deleted so far. Expired entries are deleted when they are accessed.
+    pub fn expirations(&self) -> u64 {
+        self.expirations.load(Ordering::Relaxed)
+    }
+
+    /// The occupancy of the slab holding the entries.
+    pub fn slab_stats(&self) -> SlabStats {
+        self.cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .slab
+            .stats()
+    }
+}
+
+impl<K, V> Default for SimpleCache<K, V> {
+    fn default() -> SimpleCache<K, V> {
+        SimpleCache::new()
+    }
+}
+
+impl<K, V> Entries<K, V> {
+    fn new() -> Entries<K, V> {
+        Entries {
+            map: HashMap::new(),
+            slab: Slab::new(),
+            expiry: ExpiryIndex::new(),
+        }
+    }
+}
+
+// Not derived, since the keys are shared and need not be `Clone`
+impl<K, V: Clone> Clone for Entries<K, V> {
+    fn clone(&self) -> Entries<K, V> {
+        Entries {
+            map: self.map.clone(),
+            slab: self.slab.clone(),
+            expiry: self.expiry.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Entries<K, V> {
+    /// Get the entry of the given key, if any.
+    fn get(&self, key: &K) -> Option<&CacheEntry<V>> {
+        self.slab.get(*self.map.get(key)?).map(|(_, entry)| entry)
+    }
+
+    /// Get the entry of the given key to modify, if any. Its expiration time is not to be changed (see
+    /// `retime`).
+    fn get_mut(&mut self, key: &K) -> Option<&mut CacheEntry<V>> {
+        self.slab
+            .get_mut(*self.map.get(key)?)
+            .map(|(_, entry)| entry)
+    }
+
+    /// Insert (or replace in its slot) an entry, keeping the expiry index in sync.
+    fn insert(&mut self, key: K, entry: CacheEntry<V>) {
+        let expires_at: Option<u64> = entry.expires_at;
+
+        let key: Arc<K> = match self.map.get_key_value(&key) {
+            Some((key, &index)) => {
+                let key: Arc<K> = key.clone();
+                if let Some((_, old)) = self.slab.get_mut(index) {
+                    if let Some(old_expires_at) = old.expires_at {
+                        self.expiry.remove(&key, old_expires_at);
+                    }
+                    *old = entry;
+                }
+                key
+            }
+            None => {
+                let key: Arc<K> = Arc::new(key);
+                let index: usize = self.slab.insert((key.clone(), entry));
+                self.map.insert(key.clone(), index);
+                key
+            }
+        };
+
+        if let Some(expires_at) = expires_at {
+            self.expiry.insert(&key, expires_at);
+        }
+    }
+
+    /// Change the TTL of a live entry with the given function (e.g. `CacheEntry::touch`), keeping the expiry index in sync.
+    ///
+    /// # Returns
+    /// * `false` if the key has no live entry.
+    fn retime(&mut self, key: &K, retime: impl FnOnce(&mut CacheEntry<V>)) -> bool {
+        let Some((key, &index)) = self.map.get_key_value(key) else {
+            return false;
+        };
+        let Some((_, entry)) = self
+            .slab
+            .get_mut(index)
+            .filter(|(_, entry)| !entry.is_expired())
+        else {
+            return false;
+        };
+
+        let old_expires_at: Option<u64> = entry.expires_at;
+        retime(entry);
+        self.expiry
+            .reschedule(key, old_expires_at, entry.expires_at);
+        true
+    }
+
+    /// Remove an entry, keeping the expiry index in sync.
+    fn remove(&mut self, key: &K) -> Option<CacheEntry<V>> {
+        let (_, entry): (Arc<K>, CacheEntry<V>) = self.slab.remove(self.map.remove(key)?)?;
+        if let Some(expires_at) = entry.expires_at {
+            self.expiry.remove(key, expires_at);
+        }
+        Some(entry)
+    }
+}
+
+/// Move the live entry of a key of `source` to a key of `target` (or of `source` itself if `None`), keeping
+/// its TTL (see `Cache::rename`). The entries shared with a snapshot are only copied if they change.
+fn rename_entry<K: Eq + Hash, V: Clone>(
+    source: &mut Arc<Entries<K, V>>,
+    target: Option<&mut Arc<Entries<K, V>>>,
+    from: &K,
+    to: K,
+    if_absent: bool,
+) -> Option<bool> {
+    source.get(from).filter(|entry| !entry.is_expired())?;
+    let taken: bool = match target.as_deref() {
+        Some(target) => target.get(&to),
+        None => source.get(&to),
+    }
+    .is_some_and(|entry| !entry.is_expired());
+    if if_absent && taken {
+        return Some(false);
+    }
+
+    let entry: CacheEntry<V> = Arc::make_mut(source).remove(from)?;
+    match target {
+        Some(target) => Arc::make_mut(target).insert(to, entry),
+        None => Arc::make_mut(source).insert(to, entry),
+    }
+    Some(true)
+}
+
+/// The entries of a `SimpleCache` at a point in time (see `SimpleCache::snapshot`).
+pub(super) struct Snapshot<K, V> {
+    entries: Arc<Entries<K, V>>, // The entries, shared with the cache until it is written to
+    taken_at: u64,               // When the snapshot was taken (monotime)
+}
+
+impl<K, V> Snapshot<K, V> {
+    /// Visit the entries that were live when the snapshot was taken.
+    pub fn for_each(&self, visit: &mut dyn FnMut(&K, &V)) {
+        for (key, &index) in &self.entries.map {
+            match self.entries.slab.get(index) {
+                Some((_, entry)) if !entry.is_expired_at(self.taken_at) => visit(key, &entry.value),
+                _ => {}
+            }
+        }
+    }
+
+    /// Visit the entries that were live when the snapshot was taken along with their expiration times,
+    /// until `visit` returns `false`.
+    ///
+    /// # Returns
+    /// * Whether every entry was visited.
+    pub fn for_each_with_expiration(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> bool {
+        self.entries
+            .map
+            .iter()
+            .all(|(key, &index)| match self.entries.slab.get(index) {
+                Some((_, entry)) if !entry.is_expired_at(self.taken_at) => {
+                    visit(key, &entry.value, entry.expires_at)
+                }
+                _ => true,
+            })
+    }
+}
+
+impl<K, V> SimpleCache<K, V> {
+    /// Visit the live entries in slot order from the given slot, under the read lock, until `count` of them
+    /// are visited (see `Cache::scan_from`).
+    ///
+    /// # Returns
+    /// * The slot to resume from, or `None` once every slot is visited.
+    pub(super) fn scan_slots(
+        &self,
+        from: usize,
+        count: &mut usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<Option<usize>> {
+        let entries: RwLockReadGuard<Arc<Entries<K, V>>> = self.cache.read()?;
+        let now: u64 = current_monotime();
+        Ok(entries.slab.visit_from(from, count, |(key, entry)| {
+            let live: bool = !entry.is_expired_at(now);
+            if live {
+                visit(key, &entry.value);
+            }
+            live
+        }))
+    }
+
+    /// Take a snapshot of the entries. It takes no more than cloning an `Arc` under the read lock.
+    pub(super) fn snapshot(&self) -> CacheResult<Snapshot<K, V>> {
+        let entries: RwLockReadGuard<Arc<Entries<K, V>>> = self.cache.read()?;
+        Ok(Snapshot {
+            entries: Arc::clone(&entries),
+            taken_at: current_monotime(),
+        })
+    }
+
+    /// Take snapshots of several caches at the same point in time, i.e. with no write in between.
+    pub(super) fn snapshot_all(caches: &[SimpleCache<K, V>]) -> CacheResult<Vec<Snapshot<K, V>>> {
+        // Hold all the read locks at once, only for the time of cloning the `Arc`s
+        let guards: Vec<RwLockReadGuard<Arc<Entries<K, V>>>> = caches
+            .iter()
+            .map(|cache| cache.cache.read())
+            .collect::<Result<_, _>>()?;
+
+        let taken_at: u64 = current_monotime();
+        Ok(guards
+            .iter()
+            .map(|entries| Snapshot {
+                entries: Arc::clone(entries),
+                taken_at,
+            })
+            .collect())
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> SimpleCache<K, V> {
+    /// Put batches of entries into several caches (e.g. the shards of a `ShardedCache`) as a single write,
+    /// holding all their write locks at once. The caches are locked in the order of the batches, which must
+    /// be the same for every caller (e.g. the order of the shards) so that two writers cannot deadlock.
+    pub(super) fn put_all(batches: Vec<PutBatch<K, V>>, ttl: Option<u64>) -> CacheResult<()> {
+        let mut guards: Vec<RwLockWriteGuard<Arc<Entries<K, V>>>> = batches
+            .iter()
+            .map(|(cache, _)| cache.cache.write())
+            .collect::<Result<_, _>>()?;
+
+        for (guard, (_, entries)) in guards.iter_mut().zip(batches) {
+            let cache: &mut Entries<K, V> = Arc::make_mut(guard);
+            for (key, value) in entries {
+                cache.insert(key, CacheEntry::new(value, ttl));
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the value of a key of one cache to a key of another (e.g. two shards of a `ShardedCache`) as a
+    /// single write, holding both write locks (see `Cache::rename`). The caches are locked in the order
+    /// given by `source_first`, which must follow a fixed order of the caches (e.g. the order of the
+    /// shards) so that two renames in opposite directions cannot deadlock.
+    pub(super) fn rename_between(
+        source: &SimpleCache<K, V>,
+        target: &SimpleCache<K, V>,
+        from: &K,
+        to: K,
+        if_absent: bool,
+        source_first: bool,
+    ) -> CacheResult<Option<bool>> {
+        let (mut source_guard, mut target_guard) = match source_first {
+            true => {
+                let source_guard = source.cache.write()?;
+                (source_guard, target.cache.write()?)
+            }
+            false => {
+                let target_guard = target.cache.write()?;
+                (source.cache.write()?, target_guard)
+            }
+        };
+        Ok(rename_entry(
+            &mut source_guard,
+            Some(&mut target_guard),
+            from,
+            to,
+            if_absent,
+        ))
+    }
+
+    /// Get the values of keys from several caches (e.g. the shards of a `ShardedCache`) at the same point
+    /// in time, holding all their read locks at once. Every batch gives the keys to look up in its cache,
+    /// along with their positions in the returned values.
+    pub(super) fn get_all(batches: &[GetBatch<K, V>], len: usize) -> CacheResult<Vec<Option<V>>> {
+        let guards: Vec<RwLockReadGuard<Arc<Entries<K, V>>>> = batches
+            .iter()
+            .map(|(cache, _)| cache.cache.read())
+            .collect::<Result<_, _>>()?;
+
+        // The expired entries are left to the writers (and the sweeper) to delete
+        let mut values: Vec<Option<V>> = vec![None; len];
+        for (entries, (_, keys)) in guards.iter().zip(batches) {
+            for &(position, key) in keys {
+                values[position] = entries
+                    .get(key)
+                    .filter(|entry| !entry.is_expired())
+                    .map(|entry| entry.value.clone());
+            }
+        }
+        Ok(values)
+    }
+}
+
+impl<K, V> Cache<K, V> for SimpleCache<K, V>
+where
+    K: Eq + Hash + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut cache = self.cache.write()?;
+        Arc::make_mut(&mut cache).insert(key, entry);
+        Ok(())
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        SimpleCache::put_all(vec![(self, entries)], ttl)
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut cache = self.cache.write()?;
+        let present: bool = cache.get(&key).is_some_and(|entry| !entry.is_expired());
+        if !condition.allows(present) {
+            return Ok(false);
+        }
+
+        Arc::make_mut(&mut cache).insert(key, entry);
+        Ok(true)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let mut cache = self.cache.write()?;
+        let current: Option<&CacheEntry<V>> = cache.get(&key).filter(|entry| !entry.is_expired());
+        let (expires_at, ttl): (Option<u64>, Option<u64>) =
+            current.map_or((None, None), |entry| (entry.expires_at, entry.ttl));
+        let value: V = update(current.map(|entry| &entry.value))?;
+
+        let entry: CacheEntry<V> = CacheEntry {
+            value: value.clone(),
+            expires_at,
+            ttl,
+        };
+        Arc::make_mut(&mut cache).insert(key, entry);
+        Ok(value)
+    }
+
+    fn modify(
+        &self,
+        key: K,
+        modify: &mut dyn FnMut(Option<&mut V>) -> CacheResult<Option<V>>,
+    ) -> CacheResult<()>
+    where
+        V: Clone,
+    {
+        let mut cache = self.cache.write()?;
+        if cache.get(&key).is_some_and(|entry| !entry.is_expired()) {
+            if let Some(entry) = Arc::make_mut(&mut cache).get_mut(&key) {
+                modify(Some(&mut entry.value))?;
+            }
+            return Ok(());
+        }
+
+        if let Some(value) = modify(None)? {
+            Arc::make_mut(&mut cache).insert(key, CacheEntry::new(value, None));
+        }
+        Ok(())
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let mut cache = self.cache.write()?;
+        let previous: Option<V> = cache
+            .get(&key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.value.clone());
+        accept(previous.as_ref())?;
+
+        Arc::make_mut(&mut cache).insert(key, CacheEntry::new(value, ttl));
+        Ok(previous)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        SimpleCache::get_all(&[(self, keys.iter().enumerate().collect())], keys.len())
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        match self.cache.read()?.get(key) {
+            Some(entry) if !entry.is_expired() => return Ok(Some(entry.value.clone())),
+            Some(_) => {} // Expired, so it has to be deleted under the write lock
+            None => return Ok(None),
+        }
+
+        // The entry may have been replaced (or deleted) while no lock was held, so look it up again
+        let mut cache = self.cache.write()?;
+        match cache.get(key) {
+            Some(entry) if !entry.is_expired() => Ok(Some(entry.value.clone())),
+            Some(_) => {
+                Arc::make_mut(&mut cache).remove(key);
+                self.expirations.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        Ok(self.remove_entry(key)?.map(|(value, _)| value))
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let mut cache = self.cache.write()?;
+        if let Some(entry) = cache.get(key).filter(|entry| !entry.is_expired()) {
+            accept(&entry.value)?;
+        }
+        match Arc::make_mut(&mut cache).remove(key) {
+            Some(entry) if entry.is_expired() => {
+                self.expirations.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+            maybe_entry => Ok(maybe_entry.map(|entry| (entry.value, entry.expires_at))),
+        }
+    }
+
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        let mut cache = self.cache.write()?;
+        // Only copied (while a snapshot is alive) when there is an entry to change
+        if cache.get(key).is_none_or(|entry| entry.is_expired()) {
+            return Ok(false);
+        }
+        Ok(Arc::make_mut(&mut cache).retime(key, |entry| entry.set_ttl(ttl)))
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        let mut cache = self.cache.write()?;
+        if cache.get(key).is_none_or(|entry| entry.is_expired()) {
+            return Ok(false);
+        }
+        Ok(Arc::make_mut(&mut cache).retime(key, CacheEntry::touch))
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        match self.cache.read()?.get(key) {
+            Some(entry) if !entry.is_expired() => Ok(Some(entry.expires_at)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Delete the expired entries found through the expiry index, so only they are examined.
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let mut cache = self.cache.write()?;
+        let cache: &mut Entries<K, V> = Arc::make_mut(&mut cache);
+
+        let expired: Vec<Arc<K>> = cache.expiry.pop_expired(current_monotime(), max_expired);
+        for key in &expired {
+            if let Some(index) = cache.map.remove(&**key) {
+                cache.slab.remove(index);
+            }
+        }
+
+        self.expirations
+            .fetch_add(expired.len() as u64, Ordering::Relaxed);
+        Ok(SweepStats {
+            examined: expired.len(),
+            expired: expired.len(),
+        })
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        self.snapshot()?.for_each(visit);
+        Ok(())
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        self.snapshot()?.for_each_with_expiration(visit);
+        Ok(())
+    }
+
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let mut cache: RwLockWriteGuard<Arc<Entries<K, V>>> = self.cache.write()?;
+        Ok(rename_entry(&mut cache, None, from, to, if_absent))
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        let cache: RwLockReadGuard<Arc<Entries<K, V>>> = self.cache.read()?;
+        Ok(cache.map.len() - cache.expiry.count_expired(current_monotime()))
+    }
+
+    /// Swap in empty entries under the write lock. The entries removed are still shared with the snapshots
+    /// alive, if any.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let mut cache: RwLockWriteGuard<Arc<Entries<K, V>>> = self.cache.write()?;
+        Ok(Box::new(mem::replace(
+            &mut *cache,
+            Arc::new(Entries::new()),
+        )))
+    }
+
+    /// The cursor is the slot to resume from, since an entry keeps its slot while it is live.
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        let mut count: usize = count.max(1);
+        let next: Option<usize> = self.scan_slots(cursor as usize, &mut count, visit)?;
+        Ok(next.map_or(0, |slot| slot as u64))
+    }
+}
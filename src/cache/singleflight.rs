@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+/// Deduplicates concurrent executions of the same computation (a.k.a. "singleflight").
+///
+/// While a computation for a key is in flight, other callers asking for the same key wait for it and receive
+/// a clone of its result instead of running it again. Once it completes, the next call starts a new one.
+pub struct SingleFlight<K, T> {
+    calls: Mutex<HashMap<K, Arc<Call<T>>>>, // The computations in flight by key
+}
+
+/// A computation in flight, which the waiting callers block on.
+struct Call<T> {
+    state: Mutex<CallState<T>>,
+    done: Condvar,
+}
+
+enum CallState<T> {
+    Running,
+    Done(T),
+    Abandoned, // The leader panicked, so a waiter has to take over
+}
+
+impl<K, T> SingleFlight<K, T> {
+    /// Create a new `SingleFlight` with no computation in flight.
+    pub fn new() -> SingleFlight<K, T> {
+        SingleFlight {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K, T> Default for SingleFlight<K, T> {
+    fn default() -> SingleFlight<K, T> {
+        SingleFlight::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> SingleFlight<K, T> {
+    /// Run the computation for the given key, unless one is already in flight, in which case wait for it
+    /// and return its result.
+    ///
+    /// # Arguments
+    /// * `key` - Identifies the computation. Calls with equal keys are deduplicated.
+    /// * `compute` - The computation. It runs on the calling thread, at most once per call of `run`.
+    pub fn run(&self, key: &K, compute: impl FnOnce() -> T) -> T {
+        let mut compute: Option<_> = Some(compute);
+
+        loop {
+            let (call, leader): (Arc<Call<T>>, bool) = {
+                let mut calls = lock(&self.calls);
+                match calls.get(key) {
+                    Some(call) => (Arc::clone(call), false),
+                    None => {
+                        let call: Arc<Call<T>> = Arc::new(Call {
+                            state: Mutex::new(CallState::Running),
+                            done: Condvar::new(),
+                        });
+                        calls.insert(key.clone(), Arc::clone(&call));
+                        (call, true)
+                    }
+                }
+            };
+
+            if leader {
+                // The guard completes the call even if the computation panics, so waiters never hang
+                let mut guard: LeaderGuard<K, T> = LeaderGuard {
+                    flight: self,
+                    key,
+                    call: &call,
+                    result: None,
+                };
+                let result: T = (compute.take().expect("a leader computes once"))();
+                guard.result = Some(result.clone());
+                return result;
+            }
+
+            let mut state = lock(&call.state);
+            while matches!(*state, CallState::Running) {
+                state = call
+                    .done
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+
+            if let CallState::Done(result) = &*state {
+                return result.clone();
+            }
+            // The leader was abandoned: retry, possibly becoming the leader ourselves
+        }
+    }
+}
+
+/// Publishes the result of the leader of a call (or marks it abandoned) and wakes the waiters up.
+struct LeaderGuard<'a, K: Eq + Hash, T> {
+    flight: &'a SingleFlight<K, T>,
+    key: &'a K,
+    call: &'a Call<T>,
+    result: Option<T>,
+}
+
+impl<K: Eq + Hash, T> Drop for LeaderGuard<'_, K, T> {
+    fn drop(&mut self) {
+        lock(&self.flight.calls).remove(self.key);
+
+        *lock(&self.call.state) = match self.result.take() {
+            Some(result) => CallState::Done(result),
+            None => CallState::Abandoned,
+        };
+        self.call.done.notify_all();
+    }
+}
+
+/// Lock a mutex, ignoring poisoning: the guarded bookkeeping stays consistent even if a holder panicked.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
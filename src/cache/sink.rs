@@ -0,0 +1,620 @@
+use std::{
+    error::Error,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{error, warn};
+
+use super::{Cache, MaxMemoryPolicy, MemoryStats, PutCondition, SweepStats};
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::current_monotime,
+};
+
+/// The error returned by a `WriteSink` when the external store rejects or fails a write.
+pub type SinkError = Box<dyn Error + Send + Sync>;
+
+/// An external store (e.g. a database) kept in sync with the writes of a cache.
+///
+/// Register a sink with a `WriteThroughCache` to apply writes synchronously, or with a `WriteBehindCache`
+/// to apply them in the background.
+pub trait WriteSink<K, V>: Send + Sync {
+    /// Apply a put of the cache to the store.
+    fn on_put(&self, key: &K, value: &V, ttl: Option<u64>) -> Result<(), SinkError>;
+
+    /// Apply a removal of the cache to the store.
+    fn on_remove(&self, key: &K) -> Result<(), SinkError>;
+
+    /// Apply the removal of every entry of the cache at once (see `Cache::clear`) to the store.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the store cannot be cleared at once, which is the default. The keys
+    ///   of the cache are then removed one by one, each applied with `on_remove`.
+    fn on_clear(&self) -> Result<(), SinkError> {
+        Err(Box::new(CacheError::Unsupported("clear")))
+    }
+}
+
+/// A cache applying every write to a sink synchronously, before the cache itself (write-through).
+///
+/// If the sink fails, the write is not applied to the cache and the error is returned to the caller, so
+/// the cache never holds data the store has not accepted.
+pub struct WriteThroughCache<K, V> {
+    cache: Arc<dyn Cache<K, V>>,    // Holds the cached data
+    sink: Arc<dyn WriteSink<K, V>>, // Receives every write before the cache does
+}
+
+impl<K, V> WriteThroughCache<K, V> {
+    /// Create a new `WriteThroughCache` applying the writes of `cache` to `sink`.
+    pub fn new(
+        cache: Arc<dyn Cache<K, V>>,
+        sink: Arc<dyn WriteSink<K, V>>,
+    ) -> WriteThroughCache<K, V> {
+        WriteThroughCache { cache, sink }
+    }
+}
+
+impl<K: Clone + Send + Sync, V: Clone + Send + Sync> Cache<K, V> for WriteThroughCache<K, V> {
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        self.sink
+            .on_put(&key, &value, ttl)
+            .map_err(|e| CacheError::Sink(e.to_string()))?;
+        self.cache.put(key, value, ttl)
+    }
+
+    /// The sink is given the values one by one, so a failure midway leaves it with some of them, while the
+    /// cache takes none.
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        for (key, value) in &entries {
+            self.sink
+                .on_put(key, value, ttl)
+                .map_err(|e| CacheError::Sink(e.to_string()))?;
+        }
+        self.cache.put_many(entries, ttl)
+    }
+
+    /// The condition is checked before the sink is given the value, so a write to the key coming in
+    /// between may leave the sink with a value the cache declined.
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        if !condition.allows(self.cache.expiration(&key)?.is_some()) {
+            return Ok(false);
+        }
+        self.sink
+            .on_put(&key, &value, ttl)
+            .map_err(|e| CacheError::Sink(e.to_string()))?;
+        self.cache.put_if(key, value, ttl, condition)
+    }
+
+    /// The sink is given the new value (with the TTL the value had before) before the cache takes it.
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let ttl: Option<u64> = remaining_ttl(self.cache.expiration(&key)?.flatten());
+        let sink_key: K = key.clone();
+
+        self.cache.update(key, &mut |current| {
+            let value: V = update(current)?;
+            self.sink
+                .on_put(&sink_key, &value, ttl)
+                .map_err(|e| CacheError::Sink(e.to_string()))?;
+            Ok(value)
+        })
+    }
+
+    /// The value is modified in place, then given to the sink (with the TTL the key had, a new key getting
+    /// none). Unlike the other writes, a failure of the sink leaves the value modified in the cache, since
+    /// the modified value is only known once it is.
+    fn modify(
+        &self,
+        key: K,
+        modify: &mut dyn FnMut(Option<&mut V>) -> CacheResult<Option<V>>,
+    ) -> CacheResult<()>
+    where
+        V: Clone,
+    {
+        let ttl: Option<u64> = remaining_ttl(self.cache.expiration(&key)?.flatten());
+        let sink_key: K = key.clone();
+
+        self.cache.modify(key, &mut |current| match current {
+            Some(value) => {
+                let created: Option<V> = modify(Some(&mut *value))?;
+                self.sink
+                    .on_put(&sink_key, value, ttl)
+                    .map_err(|e| CacheError::Sink(e.to_string()))?;
+                Ok(created)
+            }
+            None => {
+                let created: Option<V> = modify(None)?;
+                if let Some(value) = &created {
+                    self.sink
+                        .on_put(&sink_key, value, None)
+                        .map_err(|e| CacheError::Sink(e.to_string()))?;
+                }
+                Ok(created)
+            }
+        })
+    }
+
+    /// The sink is given the new value once it is accepted, before the cache takes it.
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let (sink_key, sink_value): (K, V) = (key.clone(), value.clone());
+        self.cache.replace_if(key, value, ttl, &mut |current| {
+            accept(current)?;
+            self.sink
+                .on_put(&sink_key, &sink_value, ttl)
+                .map_err(|e| CacheError::Sink(e.to_string()))
+        })
+    }
+
+    /// The sink is told to remove the source key, then given the value under the destination key (with
+    /// its TTL), before the cache renames it.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let (Some(value), Some(expires_at)) = (self.cache.get(from)?, self.cache.expiration(from)?)
+        else {
+            return Ok(None);
+        };
+        if if_absent && self.cache.expiration(&to)?.is_some() {
+            return Ok(Some(false));
+        }
+        self.sink
+            .on_remove(from)
+            .map_err(|e| CacheError::Sink(e.to_string()))?;
+        self.sink
+            .on_put(&to, &value, remaining_ttl(expires_at))
+            .map_err(|e| CacheError::Sink(e.to_string()))?;
+        self.cache.rename(from, to, if_absent)
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        self.cache.get(key)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        self.cache.get_many(keys)
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        self.sink
+            .on_remove(key)
+            .map_err(|e| CacheError::Sink(e.to_string()))?;
+        self.cache.remove(key)
+    }
+
+    /// The sink is told of the removal once the value is accepted, before the cache drops it (or after the
+    /// cache is found to have no value for the key).
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let mut removing: bool = false;
+        let removed: Option<(V, Option<u64>)> = self.cache.remove_entry_if(key, &mut |value| {
+            accept(value)?;
+            removing = true;
+            self.sink
+                .on_remove(key)
+                .map_err(|e| CacheError::Sink(e.to_string()))
+        })?;
+        if !removing {
+            self.sink
+                .on_remove(key)
+                .map_err(|e| CacheError::Sink(e.to_string()))?;
+        }
+        Ok(removed)
+    }
+
+    /// The sink is given the value again, with its new TTL.
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        let Some(value) = self.cache.get(key)? else {
+            return Ok(false);
+        };
+        self.sink
+            .on_put(key, &value, ttl)
+            .map_err(|e| CacheError::Sink(e.to_string()))?;
+        self.cache.expire(key, ttl)
+    }
+
+    /// The sink is given the value again, with its restarted TTL.
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        if !self.cache.touch(key)? {
+            return Ok(false);
+        }
+        if let (Some(value), Some(expires_at)) = (self.cache.get(key)?, self.cache.expiration(key)?)
+        {
+            self.sink
+                .on_put(key, &value, remaining_ttl(expires_at))
+                .map_err(|e| CacheError::Sink(e.to_string()))?;
+        }
+        Ok(true)
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        self.cache.expiration(key)
+    }
+
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        self.cache.sweep_expired(max_expired)
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        self.cache.scan(visit)
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        self.cache.scan_with_expirations(visit)
+    }
+
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        self.cache.scan_from(cursor, count, visit)
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        self.cache.len()
+    }
+
+    /// The sink is told to remove every entry before the cache does. If it cannot (see
+    /// `WriteSink::on_clear`), the cache is not cleared either, and its keys are to be removed one by one.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        self.sink
+            .on_clear()
+            .map_err(|e| match e.downcast::<CacheError>() {
+                Ok(e) if matches!(*e, CacheError::Unsupported(_)) => *e,
+                Ok(e) => CacheError::Sink(e.to_string()),
+                Err(e) => CacheError::Sink(e.to_string()),
+            })?;
+        self.cache.clear()
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        self.cache.flush()
+    }
+
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        self.cache.memory_stats()
+    }
+
+    fn memory_usage(&self, key: &K) -> CacheResult<Option<usize>> {
+        self.cache.memory_usage(key)
+    }
+
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        self.cache.set_max_memory(max_bytes, policy)
+    }
+}
+
+/// Tuning options of a `WriteBehindCache`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteBehindOptions {
+    pub queue_capacity: usize, // The maximum number of pending writes. Writers block while it is full.
+    pub max_retries: u32,      // How many times a failed write is retried before it is dropped
+    pub retry_backoff: Duration, // The delay before the first retry, doubled after every failed retry
+}
+
+impl Default for WriteBehindOptions {
+    fn default() -> WriteBehindOptions {
+        WriteBehindOptions {
+            queue_capacity: 10_000,
+            max_retries: 5,
+            retry_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// A write waiting in the queue of a `WriteBehindCache`.
+enum WriteOp<K, V> {
+    Put(K, V, Option<u64>),
+    Remove(K),
+}
+
+/// A cache applying its writes to a sink in the background (write-behind).
+///
+/// Writes are applied to the cache immediately and queued for a background thread, which applies them to
+/// the sink in order, retrying failed ones with an exponential backoff. Writes still failing after the last
+/// retry are logged and dropped. Dropping the cache waits until all queued writes have been applied.
+pub struct WriteBehindCache<K, V> {
+    cache: Arc<dyn Cache<K, V>>,                     // Holds the cached data
+    queue: Mutex<Option<SyncSender<WriteOp<K, V>>>>, // Feeds the background thread (`None` once dropped)
+    worker: Option<JoinHandle<()>>,                  // The background thread applying the writes
+}
+
+impl<K, V> WriteBehindCache<K, V>
+where
+    K: Send + 'static,
+    V: Send + 'static,
+{
+    /// Create a new `WriteBehindCache` applying the writes of `cache` to `sink` in the background.
+    ///
+    /// # Errors
+    /// * `CacheError::Sink` if the background thread cannot be spawned.
+    pub fn new(
+        cache: Arc<dyn Cache<K, V>>,
+        sink: Arc<dyn WriteSink<K, V>>,
+        options: WriteBehindOptions,
+    ) -> CacheResult<WriteBehindCache<K, V>> {
+        let (sender, receiver) = mpsc::sync_channel::<WriteOp<K, V>>(options.queue_capacity);
+        let worker: JoinHandle<()> = thread::Builder::new()
+            .name("write-behind".to_string())
+            .spawn(move || apply_writes(receiver, sink, options))
+            .map_err(|e| {
+                CacheError::Sink(format!("cannot spawn the write-behind thread: {}", e))
+            })?;
+
+        Ok(WriteBehindCache {
+            cache,
+            queue: Mutex::new(Some(sender)),
+            worker: Some(worker),
+        })
+    }
+}
+
+impl<K, V> WriteBehindCache<K, V> {
+    /// Queue a write for the background thread, blocking while the queue is full.
+    fn enqueue(&self, op: WriteOp<K, V>) -> CacheResult<()> {
+        let sender: SyncSender<WriteOp<K, V>> = match self.queue.lock()?.as_ref() {
+            Some(sender) => sender.clone(),
+            None => {
+                return Err(CacheError::Sink(
+                    "the write-behind queue is closed".to_string(),
+                ))
+            }
+        };
+
+        sender
+            .send(op)
+            .map_err(|_| CacheError::Sink("the write-behind thread has stopped".to_string()))
+    }
+}
+
+impl<K, V> Cache<K, V> for WriteBehindCache<K, V>
+where
+    K: Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        self.cache.put(key.clone(), value.clone(), ttl)?;
+        self.enqueue(WriteOp::Put(key, value, ttl))
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        self.cache.put_many(entries.clone(), ttl)?;
+        for (key, value) in entries {
+            self.enqueue(WriteOp::Put(key, value, ttl))?;
+        }
+        Ok(())
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        if !self
+            .cache
+            .put_if(key.clone(), value.clone(), ttl, condition)?
+        {
+            return Ok(false);
+        }
+        self.enqueue(WriteOp::Put(key, value, ttl))?;
+        Ok(true)
+    }
+
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let value: V = self.cache.update(key.clone(), update)?;
+        let ttl: Option<u64> = remaining_ttl(self.cache.expiration(&key)?.flatten());
+        self.enqueue(WriteOp::Put(key, value.clone(), ttl))?;
+        Ok(value)
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let previous: Option<V> = self
+            .cache
+            .replace_if(key.clone(), value.clone(), ttl, accept)?;
+        self.enqueue(WriteOp::Put(key, value, ttl))?;
+        Ok(previous)
+    }
+
+    /// The removal of the source key and the value under the destination key are queued.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let renamed: Option<bool> = self.cache.rename(from, to.clone(), if_absent)?;
+        if renamed != Some(true) {
+            return Ok(renamed);
+        }
+        self.enqueue(WriteOp::Remove(from.clone()))?;
+        if let Some(value) = self.cache.get(&to)? {
+            let ttl: Option<u64> = remaining_ttl(self.cache.expiration(&to)?.flatten());
+            self.enqueue(WriteOp::Put(to, value, ttl))?;
+        }
+        Ok(renamed)
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        self.cache.get(key)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        self.cache.get_many(keys)
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        let removed: Option<V> = self.cache.remove(key)?;
+        self.enqueue(WriteOp::Remove(key.clone()))?;
+        Ok(removed)
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let removed: Option<(V, Option<u64>)> = self.cache.remove_entry_if(key, accept)?;
+        self.enqueue(WriteOp::Remove(key.clone()))?;
+        Ok(removed)
+    }
+
+    /// The value is queued again, with its new TTL.
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        if !self.cache.expire(key, ttl)? {
+            return Ok(false);
+        }
+        if let Some(value) = self.cache.get(key)? {
+            self.enqueue(WriteOp::Put(key.clone(), value, ttl))?;
+        }
+        Ok(true)
+    }
+
+    /// The value is queued again, with its restarted TTL.
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        if !self.cache.touch(key)? {
+            return Ok(false);
+        }
+        if let (Some(value), Some(expires_at)) = (self.cache.get(key)?, self.cache.expiration(key)?)
+        {
+            self.enqueue(WriteOp::Put(key.clone(), value, remaining_ttl(expires_at)))?;
+        }
+        Ok(true)
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        self.cache.expiration(key)
+    }
+
+    // Expired entries are deleted from the cache only, the store is expected to expire them on its own
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        self.cache.sweep_expired(max_expired)
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(&K, &V)) -> CacheResult<()> {
+        self.cache.scan(visit)
+    }
+
+    fn scan_with_expirations(
+        &self,
+        visit: &mut dyn FnMut(&K, &V, Option<u64>) -> bool,
+    ) -> CacheResult<()> {
+        self.cache.scan_with_expirations(visit)
+    }
+
+    fn scan_from(
+        &self,
+        cursor: u64,
+        count: usize,
+        visit: &mut dyn FnMut(&K, &V),
+    ) -> CacheResult<u64> {
+        self.cache.scan_from(cursor, count, visit)
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        self.cache.len()
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        self.cache.flush()
+    }
+
+    fn memory_stats(&self) -> Option<MemoryStats> {
+        self.cache.memory_stats()
+    }
+
+    fn memory_usage(&self, key: &K) -> CacheResult<Option<usize>> {
+        self.cache.memory_usage(key)
+    }
+
+    fn set_max_memory(&self, max_bytes: Option<usize>, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        self.cache.set_max_memory(max_bytes, policy)
+    }
+}
+
+impl<K, V> Drop for WriteBehindCache<K, V> {
+    fn drop(&mut self) {
+        // Closing the queue lets the background thread drain it and stop
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.take();
+        }
+
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("The write-behind thread panicked");
+            }
+        }
+    }
+}
+
+/// The TTL (in seconds, rounded up) left until the given expiration time (monotime), given to the sinks.
+fn remaining_ttl(expires_at: Option<u64>) -> Option<u64> {
+    expires_at.map(|expires_at| expires_at.saturating_sub(current_monotime()).div_ceil(1000))
+}
+
+/// The loop of the background thread: apply every queued write to the sink until the queue is closed.
+fn apply_writes<K, V>(
+    receiver: Receiver<WriteOp<K, V>>,
+    sink: Arc<dyn WriteSink<K, V>>,
+    options: WriteBehindOptions,
+) {
+    for op in receiver {
+        let mut backoff: Duration = options.retry_backoff;
+
+        for attempt in 0..=options.max_retries {
+            let result: Result<(), SinkError> = match &op {
+                WriteOp::Put(key, value, ttl) => sink.on_put(key, value, *ttl),
+                WriteOp::Remove(key) => sink.on_remove(key),
+            };
+
+            match result {
+                Ok(()) => break,
+                Err(e) if attempt < options.max_retries => {
+                    warn!(
+                        "Write-behind attempt {} failed, retrying: {}",
+                        attempt + 1,
+                        e
+                    );
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                }
+                Err(e) => error!("Write-behind dropped after {} attempts: {}", attempt + 1, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{mem, ops::AddAssign};
+
+/// The occupancy of the slab(s) holding the entries of a cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SlabStats {
+    pub slots: usize,    // The number of slots allocated so far (the slabs never shrink)
+    pub occupied: usize, // The number of slots holding an entry; the others are reused by the next inserts
+}
+
+impl AddAssign for SlabStats {
+    fn add_assign(&mut self, other: SlabStats) {
+        self.slots += other.slots;
+        self.occupied += other.occupied;
+    }
+}
+
+/// A slot of a `Slab`: either a value, or a link of the list of free slots.
+#[derive(Clone)]
+enum Slot<T> {
+    Occupied(T),
+    Free(Option<usize>), // The next free slot, if any
+}
+
+/// A growable arena of values addressed by index. The slots of the removed values are reused by the next
+/// inserts (most recently freed first), so a high churn of entries causes no allocations once the slab
+/// has grown to the working set.
+#[derive(Clone)]
+pub(crate) struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    free: Option<usize>, // The head of the list of free slots
+    occupied: usize,     // The number of occupied slots
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Slab<T> {
+        Slab {
+            slots: Vec::new(),
+            free: None,
+            occupied: 0,
+        }
+    }
+
+    /// Store a value in a free slot (or a new one if none is free) and return the index of the slot.
+    pub fn insert(&mut self, value: T) -> usize {
+        self.occupied += 1;
+
+        match self.free {
+            Some(index) => {
+                let slot: Slot<T> = mem::replace(&mut self.slots[index], Slot::Occupied(value));
+                if let Slot::Free(next) = slot {
+                    self.free = next;
+                }
+                index
+            }
+            None => {
+                self.slots.push(Slot::Occupied(value));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        match self.slots.get(index) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        match self.slots.get_mut(index) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Take the value out of the given slot, which becomes free.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        let slot: &mut Slot<T> = self.slots.get_mut(index)?;
+        if let Slot::Free(_) = slot {
+            return None;
+        }
+
+        self.occupied -= 1;
+        let next: Option<usize> = self.free.replace(index);
+        match mem::replace(slot, Slot::Free(next)) {
+            Slot::Occupied(value) => Some(value),
+            Slot::Free(_) => None,
+        }
+    }
+
+    /// Visit the values in slot order from the given slot, until `visit` has accepted `count` of them.
+    /// A value keeps its slot while it is stored, so resuming from the returned slot visits the values
+    /// stored all along exactly once, however the others come and go in between.
+    ///
+    /// # Arguments
+    /// * `from` - The slot to start from.
+    /// * `count` - The values left to accept, decremented by every value `visit` accepts.
+    /// * `visit` - Called with every value visited. Returns whether to accept (count) it.
+    ///
+    /// # Returns
+    /// * The slot to resume from, or `None` once the end of the slab is reached.
+    pub fn visit_from(
+        &self,
+        from: usize,
+        count: &mut usize,
+        mut visit: impl FnMut(&T) -> bool,
+    ) -> Option<usize> {
+        let mut index: usize = from;
+        while *count > 0 {
+            match self.slots.get(index)? {
+                Slot::Occupied(value) if visit(value) => *count -= 1,
+                _ => {}
+            }
+            index += 1;
+        }
+        (index < self.slots.len()).then_some(index)
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            slots: self.slots.len(),
+            occupied: self.occupied,
+        }
+    }
+}
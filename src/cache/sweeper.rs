@@ -0,0 +1,142 @@
+use std::{
+    ops::AddAssign,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{debug, warn};
+
+use super::Cache;
+use crate::error::{CacheError, CacheResult};
+
+/// The outcome of a `Cache::sweep_expired` call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SweepStats {
+    pub examined: usize, // The number of entries checked for expiration
+    pub expired: usize,  // The number of expired entries deleted
+}
+
+impl AddAssign for SweepStats {
+    fn add_assign(&mut self, other: SweepStats) {
+        self.examined += other.examined;
+        self.expired += other.expired;
+    }
+}
+
+/// Tuning options of an `ExpirationSweeper`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SweeperOptions {
+    pub min_interval: Duration, // The delay between sweeps while they keep finding many expired entries
+    pub max_interval: Duration, // The delay between sweeps while they find (almost) nothing to delete
+    pub max_expired: usize, // The most expired entries deleted by a sweep, bounding how long it holds locks
+}
+
+impl Default for SweeperOptions {
+    fn default() -> SweeperOptions {
+        SweeperOptions {
+            min_interval: Duration::from_millis(10),
+            max_interval: Duration::from_millis(1000),
+            max_expired: 10_000,
+        }
+    }
+}
+
+/// A sweep finding more than this share (1/N) of the examined entries expired is followed by another one
+/// after `SweeperOptions::min_interval`.
+const BUSY_SWEEP_RATIO: usize = 4;
+
+/// A background thread proactively deleting the expired entries of a cache, so entries that are never
+/// accessed again do not hold memory forever.
+///
+/// The sweeps adapt to the amount of expired data: while they keep finding many expired entries (or as many
+/// as `max_expired`), they run every `min_interval`; otherwise the delay doubles up to `max_interval`. The thread stops when the
+/// sweeper is dropped, or when the swept cache is dropped (the sweeper does not keep it alive).
+pub struct ExpirationSweeper {
+    stop: Option<Sender<()>>,       // Dropped to wake up and stop the thread
+    worker: Option<JoinHandle<()>>, // The sweeping thread
+}
+
+impl ExpirationSweeper {
+    /// Start sweeping the given cache in the background.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the sweeping thread cannot be spawned.
+    pub fn spawn<K, V>(
+        cache: &Arc<dyn Cache<K, V>>,
+        options: SweeperOptions,
+    ) -> CacheResult<ExpirationSweeper>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let cache: Weak<dyn Cache<K, V>> = Arc::downgrade(cache);
+        ExpirationSweeper::spawn_with(options, move |max_expired| {
+            cache
+                .upgrade()
+                .map(|cache| cache.sweep_expired(max_expired))
+        })
+    }
+
+    /// Start calling the given sweep function in the background, with the most expired entries it may
+    /// delete. The thread stops once the function returns `None` (e.g. because the swept caches have been
+    /// dropped).
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the sweeping thread cannot be spawned.
+    pub(crate) fn spawn_with(
+        options: SweeperOptions,
+        mut sweep: impl FnMut(usize) -> Option<CacheResult<SweepStats>> + Send + 'static,
+    ) -> CacheResult<ExpirationSweeper> {
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let worker: JoinHandle<()> = thread::Builder::new()
+            .name("expiration-sweeper".to_string())
+            .spawn(move || {
+                let mut interval: Duration = options.max_interval;
+
+                // Sleep until the next sweep, unless the sweeper is dropped in the meantime
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    interval = match sweep(options.max_expired) {
+                        None => break,
+                        Some(Ok(stats))
+                            if stats.expired >= options.max_expired
+                                || stats.expired * BUSY_SWEEP_RATIO > stats.examined =>
+                        {
+                            debug!(
+                                "Swept {} expired entries out of {}",
+                                stats.expired, stats.examined
+                            );
+                            options.min_interval
+                        }
+                        Some(Ok(_)) => interval.saturating_mul(2).min(options.max_interval),
+                        Some(Err(e)) => {
+                            warn!("Failed to sweep the expired entries: {}", e);
+                            options.max_interval
+                        }
+                    };
+                }
+            })
+            .map_err(|e| {
+                CacheError::Storage(format!("cannot spawn the expiration sweeper: {}", e))
+            })?;
+
+        Ok(ExpirationSweeper {
+            stop: Some(stop),
+            worker: Some(worker),
+        })
+    }
+}
+
+impl Drop for ExpirationSweeper {
+    fn drop(&mut self) {
+        self.stop.take(); // Disconnecting the channel wakes up the thread
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
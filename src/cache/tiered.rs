@@ -0,0 +1,715 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    hash::Hash,
+    mem,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+use log::debug;
+
+use super::{codec::Codec, entry::CacheEntry, Cache, PutCondition, SweepStats};
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::{current_monotime, expires_after},
+};
+
+/// Used to give every disk tier of the process its own file.
+static NEXT_TIER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A two-tier `Cache` implementation: hot entries live in memory, while the least recently used entries
+/// are spilled to a file on the local disk once the memory tier is full.
+///
+/// Entries found in the disk tier are transparently promoted back to the memory tier on access. Only
+/// the values are spilled; the keys of the disk tier are indexed in memory. The disk tier is an overflow
+/// area, not a persistent store, so its file is discarded when the cache is dropped. It is unbounded
+/// unless given a capacity (see `with_disk_capacity`), beyond which the entries spilled first are evicted.
+pub struct TieredCache<K, V> {
+    tiers: Mutex<Tiers<K, V>>, // Both tiers, guarded together since every read updates the recency order
+    memory_capacity: usize,    // The maximum number of entries kept in the memory tier
+}
+
+/// An entry of the memory tier along with the tick of its last access (see `Tiers::recency`).
+struct HotEntry<V> {
+    entry: CacheEntry<V>,
+    last_access: u64,
+}
+
+struct Tiers<K, V> {
+    memory: HashMap<K, HotEntry<V>>, // The memory tier
+    recency: BTreeMap<u64, K>, // Keys of the memory tier by last access tick, least recent first
+    tick: u64,                 // A logical clock incremented on every access
+    disk: DiskTier<K>,         // The disk tier
+    expirations: u64,          // The number of expired entries deleted so far (from either tier)
+}
+
+/// The location of a spilled value in the file of the disk tier.
+struct DiskSlot {
+    offset: u64,
+    len: usize,
+    expires_at: Option<u64>,
+    ttl: Option<u64>, // The TTL the value was given (see `CacheEntry::ttl`)
+    spill: u64,       // The sequence of the spill (see `DiskTier::spills`)
+}
+
+/// An append-only file holding the spilled values, with an in-memory index of their locations.
+///
+/// Space of promoted or removed values is reclaimed by rewriting the file once more than half of it is
+/// garbage.
+struct DiskTier<K> {
+    path: PathBuf,               // The path of the data file
+    file: File,                  // The data file, opened for reading and writing
+    index: HashMap<K, DiskSlot>, // The location of every spilled value by key
+    end: u64,                    // The offset at which the next value is appended
+    garbage: u64, // The number of bytes in the file no longer referenced by the index
+    spills: BTreeMap<u64, K>, // The keys of the spilled values by spill sequence, oldest first
+    next_spill: u64, // The sequence of the next spill
+    capacity: Option<u64>, // The most bytes the spilled values may take, if bounded
+    evictions: u64, // The number of spilled values evicted so far to stay within the capacity
+}
+
+impl<K, V> TieredCache<K, V> {
+    /// Create a new, empty `TieredCache`.
+    ///
+    /// # Arguments
+    /// * `memory_capacity` - The maximum number of entries kept in memory (at least one).
+    /// * `disk_dir` - The existing directory in which the file of the disk tier is created.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the file of the disk tier cannot be created.
+    pub fn new(memory_capacity: usize, disk_dir: &Path) -> CacheResult<TieredCache<K, V>> {
+        let id: usize = NEXT_TIER_ID.fetch_add(1, Ordering::Relaxed);
+        let path: PathBuf =
+            disk_dir.join(format!("myrustcache-tier-{}-{}.data", process::id(), id));
+
+        Ok(TieredCache {
+            tiers: Mutex::new(Tiers {
+                memory: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                disk: DiskTier::create(path)?,
+                expirations: 0,
+            }),
+            memory_capacity: memory_capacity.max(1),
+        })
+    }
+
+    /// Bound the disk tier to the given number of bytes of spilled values: spilling more evicts the values
+    /// spilled first, which are then gone from the cache.
+    pub fn with_disk_capacity(self, max_bytes: usize) -> TieredCache<K, V> {
+        self.tiers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .disk
+            .capacity = Some(max_bytes as u64);
+        self
+    }
+
+    /// The number of expired entries deleted so far. Expired entries are deleted when they are accessed.
+    pub fn expirations(&self) -> u64 {
+        self.tiers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .expirations
+    }
+
+    /// The number of spilled entries evicted so far for the disk tier to stay within its capacity.
+    pub fn disk_evictions(&self) -> u64 {
+        self.tiers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .disk
+            .evictions
+    }
+}
+
+impl<K, V> Cache<K, V> for TieredCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Clone + Codec + Send + Sync,
+{
+    fn put(&self, key: K, value: V, ttl: Option<u64>) -> CacheResult<()> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut tiers = self.tiers.lock()?;
+
+        // The new value supersedes the spilled one, if any
+        tiers.disk.discard(&key);
+        tiers.insert_hot(key, entry);
+        tiers.spill_overflow(self.memory_capacity)
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>, ttl: Option<u64>) -> CacheResult<()> {
+        let mut tiers = self.tiers.lock()?;
+        for (key, value) in entries {
+            tiers.disk.discard(&key);
+            tiers.insert_hot(key, CacheEntry::new(value, ttl));
+        }
+        tiers.spill_overflow(self.memory_capacity)
+    }
+
+    fn put_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        let entry: CacheEntry<V> = CacheEntry::new(value, ttl);
+        let mut tiers = self.tiers.lock()?;
+        if !condition.allows(tiers.is_live(&key)) {
+            return Ok(false);
+        }
+
+        tiers.disk.discard(&key);
+        tiers.insert_hot(key, entry);
+        tiers.spill_overflow(self.memory_capacity)?;
+        Ok(true)
+    }
+
+    /// A spilled value is promoted to the memory tier first, like on a read.
+    fn update(
+        &self,
+        key: K,
+        update: &mut dyn FnMut(Option<&V>) -> CacheResult<V>,
+    ) -> CacheResult<V> {
+        let mut tiers = self.tiers.lock()?;
+        if !tiers.memory.contains_key(&key) {
+            if let Some(entry) = tiers.take_spilled(&key)? {
+                tiers.insert_hot(key.clone(), entry);
+            }
+        }
+
+        let current: Option<&CacheEntry<V>> = tiers
+            .memory
+            .get(&key)
+            .map(|hot| &hot.entry)
+            .filter(|entry| !entry.is_expired());
+        let (expires_at, ttl): (Option<u64>, Option<u64>) =
+            current.map_or((None, None), |entry| (entry.expires_at, entry.ttl));
+        let result: CacheResult<V> = update(current.map(|entry| &entry.value));
+
+        if let Ok(value) = &result {
+            let entry: CacheEntry<V> = CacheEntry {
+                value: value.clone(),
+                expires_at,
+                ttl,
+            };
+            tiers.insert_hot(key, entry);
+        }
+        tiers.spill_overflow(self.memory_capacity)?;
+        result
+    }
+
+    fn replace_if(
+        &self,
+        key: K,
+        value: V,
+        ttl: Option<u64>,
+        accept: &mut dyn FnMut(Option<&V>) -> CacheResult<()>,
+    ) -> CacheResult<Option<V>> {
+        let mut tiers = self.tiers.lock()?;
+        if !tiers.memory.contains_key(&key) {
+            if let Some(entry) = tiers.take_spilled(&key)? {
+                tiers.insert_hot(key.clone(), entry);
+            }
+        }
+
+        let previous: Option<V> = tiers
+            .memory
+            .get(&key)
+            .map(|hot| &hot.entry)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.value.clone());
+        if let Err(error) = accept(previous.as_ref()) {
+            tiers.spill_overflow(self.memory_capacity)?;
+            return Err(error);
+        }
+
+        tiers.disk.discard(&key);
+        tiers.insert_hot(key, CacheEntry::new(value, ttl));
+        tiers.spill_overflow(self.memory_capacity)?;
+        Ok(previous)
+    }
+
+    /// The entry moved lands in the memory tier, as the most recently used one.
+    fn rename(&self, from: &K, to: K, if_absent: bool) -> CacheResult<Option<bool>> {
+        let mut tiers = self.tiers.lock()?;
+        if !tiers.is_live(from) {
+            return Ok(None);
+        }
+        if *from == to || (if_absent && tiers.is_live(&to)) {
+            return Ok(Some(!if_absent));
+        }
+
+        let entry: Option<CacheEntry<V>> = match tiers.memory.remove(from) {
+            Some(hot) => {
+                tiers.recency.remove(&hot.last_access);
+                Some(hot.entry)
+            }
+            None => tiers.take_spilled(from)?,
+        };
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+
+        // The value moved supersedes the spilled one, if any
+        tiers.disk.discard(&to);
+        tiers.insert_hot(to, entry);
+        tiers.spill_overflow(self.memory_capacity)?;
+        Ok(Some(true))
+    }
+
+    fn get(&self, key: &K) -> CacheResult<Option<V>> {
+        self.tiers.lock()?.lookup(key, self.memory_capacity)
+    }
+
+    fn get_many(&self, keys: &[K]) -> CacheResult<Vec<Option<V>>> {
+        let mut tiers = self.tiers.lock()?;
+        keys.iter()
+            .map(|key| tiers.lookup(key, self.memory_capacity))
+            .collect()
+    }
+
+    fn remove(&self, key: &K) -> CacheResult<Option<V>> {
+        Ok(self.remove_entry(key)?.map(|(value, _)| value))
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &K,
+        accept: &mut dyn FnMut(&V) -> CacheResult<()>,
+    ) -> CacheResult<Option<(V, Option<u64>)>> {
+        let mut tiers = self.tiers.lock()?;
+        if !tiers.memory.contains_key(key) {
+            if let Some(entry) = tiers.take_spilled(key)? {
+                tiers.insert_hot(key.clone(), entry);
+            }
+        }
+        let current: Option<&CacheEntry<V>> = tiers.memory.get(key).map(|hot| &hot.entry);
+        if let Some(entry) = current.filter(|entry| !entry.is_expired()) {
+            if let Err(error) = accept(&entry.value) {
+                tiers.spill_overflow(self.memory_capacity)?;
+                return Err(error);
+            }
+        }
+
+        let entry: Option<CacheEntry<V>> = match tiers.memory.remove(key) {
+            Some(hot) => {
+                tiers.recency.remove(&hot.last_access);
+                if hot.entry.is_expired() {
+                    tiers.expirations += 1;
+                    return Ok(None);
+                }
+                Some(hot.entry)
+            }
+            None => tiers.take_spilled(key)?,
+        };
+        Ok(entry.map(|entry| (entry.value, entry.expires_at)))
+    }
+
+    /// Change the TTL of the entry in whichever tier it lives, without promoting it.
+    fn expire(&self, key: &K, ttl: Option<u64>) -> CacheResult<bool> {
+        let mut tiers = self.tiers.lock()?;
+
+        if let Some(hot) = tiers.memory.get_mut(key) {
+            if hot.entry.is_expired() {
+                return Ok(false);
+            }
+            hot.entry.set_ttl(ttl);
+            return Ok(true);
+        }
+
+        match tiers.disk.index.get_mut(key) {
+            Some(slot) if !slot.is_expired() => {
+                slot.expires_at = ttl.map(|t| expires_after(current_monotime(), t));
+                slot.ttl = ttl;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn touch(&self, key: &K) -> CacheResult<bool> {
+        let mut tiers = self.tiers.lock()?;
+
+        if let Some(hot) = tiers.memory.get_mut(key) {
+            if hot.entry.is_expired() {
+                return Ok(false);
+            }
+            hot.entry.touch();
+            return Ok(true);
+        }
+
+        match tiers.disk.index.get_mut(key) {
+            Some(slot) if !slot.is_expired() => {
+                slot.expires_at = slot.ttl.map(|t| expires_after(current_monotime(), t));
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn expiration(&self, key: &K) -> CacheResult<Option<Option<u64>>> {
+        let tiers = self.tiers.lock()?;
+        let expiration: Option<Option<u64>> = match tiers.memory.get(key) {
+            Some(hot) => (!hot.entry.is_expired()).then_some(hot.entry.expires_at),
+            None => tiers
+                .disk
+                .index
+                .get(key)
+                .filter(|slot| !slot.is_expired())
+                .map(|slot| slot.expires_at),
+        };
+        Ok(expiration)
+    }
+
+    fn sweep_expired(&self, max_expired: usize) -> CacheResult<SweepStats> {
+        let mut tiers = self.tiers.lock()?;
+        let mut stats: SweepStats = SweepStats {
+            examined: tiers.memory.len(),
+            expired: 0,
+        };
+
+        let expired: Vec<(K, u64)> = tiers
+            .memory
+            .iter()
+            .filter(|(_, hot)| hot.entry.is_expired())
+            .take(max_expired)
+            .map(|(key, hot)| (key.clone(), hot.last_access))
+            .collect();
+        for (key, last_access) in expired {
+            tiers.memory.remove(&key);
+            tiers.recency.remove(&last_access);
+            stats.expired += 1;
+        }
+
+        stats += tiers.disk.sweep_expired(max_expired - stats.expired)?;
+        tiers.expirations += stats.expired as u64;
+        Ok(stats)
+    }
+
+    fn len(&self) -> CacheResult<usize> {
+        let tiers = self.tiers.lock()?;
+        let hot: usize = tiers
+            .memory
+            .values()
+            .filter(|hot| !hot.entry.is_expired())
+            .count();
+        let spilled: usize = tiers
+            .disk
+            .index
+            .values()
+            .filter(|slot| !slot.is_expired())
+            .count();
+        Ok(hot + spilled)
+    }
+
+    /// Take both tiers out under the lock, the file of the disk tier being compacted to nothing.
+    fn clear(&self) -> CacheResult<Box<dyn Send>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let mut tiers = self.tiers.lock()?;
+        let memory: HashMap<K, HotEntry<V>> = mem::take(&mut tiers.memory);
+        let recency: BTreeMap<u64, K> = mem::take(&mut tiers.recency);
+        let spilled: HashMap<K, DiskSlot> = tiers.disk.clear()?;
+        Ok(Box::new((memory, recency, spilled)))
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone + Codec> Tiers<K, V> {
+    /// Insert (or replace) an entry of the memory tier, marking it as the most recently used one.
+    fn insert_hot(&mut self, key: K, entry: CacheEntry<V>) {
+        self.tick += 1;
+        let hot: HotEntry<V> = HotEntry {
+            entry,
+            last_access: self.tick,
+        };
+
+        if let Some(old) = self.memory.insert(key.clone(), hot) {
+            self.recency.remove(&old.last_access);
+        }
+        self.recency.insert(self.tick, key);
+    }
+
+    /// Get the value of the given key, marking it as the most recently used one. A spilled value is
+    /// promoted to the memory tier (if it has not expired), spilling another one if the tier is full.
+    fn lookup(&mut self, key: &K, memory_capacity: usize) -> CacheResult<Option<V>> {
+        if self.memory.contains_key(key) {
+            return Ok(self.touch(key));
+        }
+
+        // Not in memory, so promote it from the disk tier (if it is there and has not expired)
+        let Some(entry) = self.take_spilled(key)? else {
+            return Ok(None);
+        };
+
+        let value: V = entry.value.clone();
+        self.insert_hot(key.clone(), entry);
+        self.spill_overflow(memory_capacity)?;
+
+        Ok(Some(value))
+    }
+
+    /// Whether the key has a live value, in either tier.
+    fn is_live(&self, key: &K) -> bool {
+        match self.memory.get(key) {
+            Some(hot) => !hot.entry.is_expired(),
+            None => self
+                .disk
+                .index
+                .get(key)
+                .is_some_and(|slot| !slot.is_expired()),
+        }
+    }
+
+    /// Get the value of an entry of the memory tier, marking it as the most recently used one. An expired
+    /// entry is deleted instead.
+    fn touch(&mut self, key: &K) -> Option<V> {
+        let hot: &mut HotEntry<V> = self.memory.get_mut(key)?;
+        if hot.entry.is_expired() {
+            let last_access: u64 = hot.last_access;
+            self.memory.remove(key);
+            self.recency.remove(&last_access);
+            self.expirations += 1;
+            return None;
+        }
+
+        self.tick += 1;
+        let key: K = self.recency.remove(&hot.last_access)?;
+        hot.last_access = self.tick;
+        self.recency.insert(self.tick, key);
+
+        Some(hot.entry.value.clone())
+    }
+
+    /// Remove the entry of the given key from the disk tier and return it, unless it has expired (in which
+    /// case it is deleted all the same).
+    fn take_spilled(&mut self, key: &K) -> CacheResult<Option<CacheEntry<V>>> {
+        let (entry, expired): (Option<CacheEntry<V>>, bool) = self.disk.take(key)?;
+        if expired {
+            self.expirations += 1;
+        }
+        Ok(entry)
+    }
+
+    /// Move the least recently used entries to the disk tier until the memory tier fits its capacity.
+    /// Expired entries are dropped instead of being spilled.
+    fn spill_overflow(&mut self, capacity: usize) -> CacheResult<()> {
+        while self.memory.len() > capacity {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+
+            if let Some(hot) = self.memory.remove(&key) {
+                if !hot.entry.is_expired() {
+                    self.disk.spill(key, &hot.entry)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DiskSlot {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| current_monotime() >= t)
+    }
+}
+
+impl<K> DiskTier<K> {
+    fn create(path: PathBuf) -> CacheResult<DiskTier<K>> {
+        let file: File = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+
+        debug!("Disk tier created at {}", path.display());
+        Ok(DiskTier {
+            path,
+            file,
+            index: HashMap::new(),
+            end: 0,
+            garbage: 0,
+            spills: BTreeMap::new(),
+            next_spill: 0,
+            capacity: None,
+            evictions: 0,
+        })
+    }
+}
+
+impl<K: Eq + Hash + Clone> DiskTier<K> {
+    /// Append the value of an entry to the file and index it under the given key, evicting the values
+    /// spilled first if the tier goes over its capacity.
+    fn spill<V: Codec>(&mut self, key: K, entry: &CacheEntry<V>) -> CacheResult<()> {
+        let bytes: Vec<u8> = entry.value.encode();
+        self.file.write_all_at(&bytes, self.end)?;
+
+        let slot: DiskSlot = DiskSlot {
+            offset: self.end,
+            len: bytes.len(),
+            expires_at: entry.expires_at,
+            ttl: entry.ttl,
+            spill: self.next_spill,
+        };
+        self.end += bytes.len() as u64;
+        self.spills.insert(self.next_spill, key.clone());
+        self.next_spill += 1;
+
+        if let Some(old) = self.index.insert(key, slot) {
+            self.garbage += old.len as u64;
+            self.spills.remove(&old.spill);
+        }
+
+        if let Some(capacity) = self.capacity {
+            while self.end - self.garbage > capacity {
+                let Some((_, oldest)) = self.spills.pop_first() else {
+                    break;
+                };
+                if let Some(slot) = self.index.remove(&oldest) {
+                    self.garbage += slot.len as u64;
+                    self.evictions += 1;
+                }
+            }
+            self.compact_if_needed()?;
+        }
+        Ok(())
+    }
+
+    /// Remove the entry of the given key from the disk tier and return it, unless it has expired.
+    ///
+    /// # Returns
+    /// * The entry (if it was spilled and has not expired), and whether an expired entry was deleted.
+    fn take<V: Codec>(&mut self, key: &K) -> CacheResult<(Option<CacheEntry<V>>, bool)> {
+        let Some(slot) = self.index.remove(key) else {
+            return Ok((None, false));
+        };
+        self.garbage += slot.len as u64;
+        self.spills.remove(&slot.spill);
+
+        let expired: bool = slot.is_expired();
+        let entry: Option<CacheEntry<V>> = if expired {
+            None
+        } else {
+            let mut bytes: Vec<u8> = vec![0; slot.len];
+            self.file.read_exact_at(&mut bytes, slot.offset)?;
+
+            let value: V = V::decode(&bytes).ok_or_else(|| {
+                CacheError::Storage(format!("corrupted value in {}", self.path.display()))
+            })?;
+            Some(CacheEntry {
+                value,
+                expires_at: slot.expires_at,
+                ttl: slot.ttl,
+            })
+        };
+
+        self.compact_if_needed()?;
+        Ok((entry, expired))
+    }
+
+    /// Forget (at most `max_expired` of) the spilled values that have expired.
+    fn sweep_expired(&mut self, max_expired: usize) -> CacheResult<SweepStats> {
+        let now: u64 = current_monotime();
+        let examined: usize = self.index.len();
+        let mut garbage: u64 = 0;
+        let mut expired: usize = 0;
+
+        let spills: &mut BTreeMap<u64, K> = &mut self.spills;
+        self.index.retain(|_, slot| {
+            if expired < max_expired && slot.expires_at.is_some_and(|t| now >= t) {
+                garbage += slot.len as u64;
+                expired += 1;
+                spills.remove(&slot.spill);
+                return false;
+            }
+            true
+        });
+        self.garbage += garbage;
+
+        if expired > 0 {
+            self.compact_if_needed()?;
+        }
+        Ok(SweepStats { examined, expired })
+    }
+
+    /// Forget every spilled value, compacting the file to nothing.
+    ///
+    /// # Returns
+    /// * The index of the values forgotten.
+    fn clear(&mut self) -> CacheResult<HashMap<K, DiskSlot>> {
+        let index: HashMap<K, DiskSlot> = mem::take(&mut self.index);
+        self.spills.clear();
+        self.garbage = self.end;
+        self.compact_if_needed()?;
+        Ok(index)
+    }
+
+    /// Forget the spilled value of the given key, if any.
+    fn discard(&mut self, key: &K) {
+        if let Some(slot) = self.index.remove(key) {
+            self.garbage += slot.len as u64;
+            self.spills.remove(&slot.spill);
+        }
+    }
+
+    /// Rewrite the file with the live values only, once more than half of it is garbage.
+    ///
+    /// The live values are copied one by one into a new file which then replaces the current one, so the
+    /// compaction never needs more memory than the largest value.
+    fn compact_if_needed(&mut self) -> CacheResult<()> {
+        if self.garbage == 0 || self.garbage * 2 < self.end {
+            return Ok(());
+        }
+
+        let compacted_path: PathBuf = self.path.with_extension("compact");
+        let compacted: File = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&compacted_path)?;
+
+        let mut offset: u64 = 0;
+        let mut buffer: Vec<u8> = Vec::new();
+
+        for slot in self.index.values_mut() {
+            buffer.resize(slot.len, 0);
+            self.file.read_exact_at(&mut buffer, slot.offset)?;
+            compacted.write_all_at(&buffer, offset)?;
+
+            slot.offset = offset;
+            offset += slot.len as u64;
+        }
+
+        fs::rename(&compacted_path, &self.path)?;
+        debug!(
+            "Disk tier {} compacted from {} to {} bytes",
+            self.path.display(),
+            self.end,
+            offset
+        );
+
+        self.file = compacted;
+        self.end = offset;
+        self.garbage = 0;
+        Ok(())
+    }
+}
+
+impl<K> Drop for DiskTier<K> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
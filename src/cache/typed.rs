@@ -0,0 +1,109 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use super::{Bytes, Cache, Codec, PutCondition};
+use crate::error::{CacheError, CacheResult};
+
+/// A cache of values of a given type, stored encoded (see `Codec`) in a cache of bytes, such as the one of a
+/// `CacheServer`. So an application can cache its own types in-process, while the network clients read and
+/// write the same keys as bytes.
+///
+/// The values are encoded on every write and decoded on every read. For the values read often, prefer a
+/// `Cache<K, V>` of the type itself (e.g. built by a `CacheBuilder`), which hands out clones instead.
+///
+/// ```no_run
+/// use myrustcache::{CacheBuilder, TypedCache};
+///
+/// let visits: TypedCache<u64> = TypedCache::new(CacheBuilder::new().build()?);
+/// visits.put("visits:home", &41, None)?;
+/// visits.update("visits:home", |visits| Ok(visits.unwrap_or(0) + 1))?;
+/// assert_eq!(visits.get("visits:home")?, Some(42));
+/// # Ok::<(), myrustcache::CacheError>(())
+/// ```
+pub struct TypedCache<T> {
+    cache: Arc<dyn Cache<String, Bytes>>, // Holds the encoded values
+    value_type: PhantomData<fn() -> T>,   // The type of the values, which are only held encoded
+}
+
+impl<T: Codec> TypedCache<T> {
+    /// Create a new `TypedCache` storing its values in the given cache of bytes.
+    pub fn new(cache: Arc<dyn Cache<String, Bytes>>) -> TypedCache<T> {
+        TypedCache {
+            cache,
+            value_type: PhantomData,
+        }
+    }
+
+    /// The cache of bytes the values are stored in.
+    pub fn cache(&self) -> &Arc<dyn Cache<String, Bytes>> {
+        &self.cache
+    }
+
+    /// Put a value, encoded, with an optional TTL in seconds (see `Cache::put`).
+    pub fn put(&self, key: &str, value: &T, ttl: Option<u64>) -> CacheResult<()> {
+        self.cache
+            .put(key.to_string(), Bytes::from(value.encode()), ttl)
+    }
+
+    /// Put a value only if the key has a value, or has none (see `Cache::put_if`).
+    ///
+    /// # Returns
+    /// * Whether the value was put.
+    pub fn put_if(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        self.cache
+            .put_if(key.to_string(), Bytes::from(value.encode()), ttl, condition)
+    }
+
+    /// Get the value of a key, decoded.
+    ///
+    /// # Errors
+    /// * `CacheError::Decode` if the value is not a valid encoding of the type (e.g. a network client set
+    ///   the key to another kind of value).
+    pub fn get(&self, key: &str) -> CacheResult<Option<T>> {
+        self.cache
+            .get(&key.to_string())?
+            .map(|value| decode(key, &value))
+            .transpose()
+    }
+
+    /// Remove the value of a key.
+    ///
+    /// # Returns
+    /// * The value removed, decoded, if the key had one.
+    pub fn remove(&self, key: &str) -> CacheResult<Option<T>> {
+        self.cache
+            .remove(&key.to_string())?
+            .map(|value| decode(key, &value))
+            .transpose()
+    }
+
+    /// Replace the value of a key with one computed from the current value, as a single step (see
+    /// `Cache::update`).
+    ///
+    /// # Returns
+    /// * The new value.
+    ///
+    /// # Errors
+    /// * The error returned by `update`, or `CacheError::Decode` if the current value cannot be decoded,
+    ///   leaving the key unchanged.
+    pub fn update(
+        &self,
+        key: &str,
+        mut update: impl FnMut(Option<T>) -> CacheResult<T>,
+    ) -> CacheResult<T> {
+        let value: Bytes = self.cache.update(key.to_string(), &mut |current| {
+            let current: Option<T> = current.map(|value| decode(key, value)).transpose()?;
+            Ok(Bytes::from(update(current)?.encode()))
+        })?;
+        decode(key, &value)
+    }
+}
+
+fn decode<T: Codec>(key: &str, value: &[u8]) -> CacheResult<T> {
+    T::decode(value).ok_or_else(|| CacheError::Decode(key.to_string()))
+}
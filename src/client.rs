@@ -0,0 +1,400 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::server::parse_number;
+
+/// The default lag beyond which a replica is not read from. A Redis primary pings its replicas every 10
+/// seconds when idle, so a lower lag would take idle replicas for lagging ones.
+pub const DEFAULT_MAX_LAG: Duration = Duration::from_secs(30);
+
+/// How long the state of a replica is trusted before it is checked again with `ROLE`.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait for a node to accept a connection, then for each of its responses.
+const NODE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The commands which only read the cache, so that a replica can answer them (`GET` taking a lock aside).
+const READ_COMMANDS: [&str; 14] = [
+    "GET",
+    "MGET",
+    "EXISTS",
+    "KEYS",
+    "RANDOMKEY",
+    "SAMPLE",
+    "DBSIZE",
+    "HISTORY",
+    "GETVERSION",
+    "STATS",
+    "TTLSTATS",
+    "TTL",
+    "EXPIRETIME",
+    "TYPE",
+];
+
+/// Where a `Client` sends the commands which only read the cache. The writes always go to the primary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPreference {
+    /// Read from the primary, so that the reads see the writes of the client (the default).
+    Primary,
+
+    /// Read from the replicas in turn, falling back to the primary while none of them is usable.
+    Replica,
+
+    /// Read from the node answering the fastest among the primary and the usable replicas.
+    Nearest,
+}
+
+impl FromStr for ReadPreference {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<ReadPreference, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "primary" => Ok(ReadPreference::Primary),
+            "replica" => Ok(ReadPreference::Replica),
+            "nearest" => Ok(ReadPreference::Nearest),
+            _ => Err(format!(
+                "'{}' is not a read preference (primary, replica or nearest)",
+                text
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReadPreference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadPreference::Primary => write!(f, "primary"),
+            ReadPreference::Replica => write!(f, "replica"),
+            ReadPreference::Nearest => write!(f, "nearest"),
+        }
+    }
+}
+
+/// A connection to a node, exchanging the commands and responses of the text protocol.
+struct Connection {
+    reader: BufReader<TcpStream>, // The responses of the node
+    writer: TcpStream,            // The commands sent to the node
+}
+
+impl Connection {
+    /// Connect to the node of the given address (`host:port`), selecting the given namespace if any.
+    fn open(address: &str, namespace: Option<&str>) -> io::Result<Connection> {
+        let socket_address: SocketAddr = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("cannot resolve {}", address)))?;
+        let stream: TcpStream = TcpStream::connect_timeout(&socket_address, NODE_TIMEOUT)?;
+        stream.set_read_timeout(Some(NODE_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+
+        let mut connection: Connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        if let Some(namespace) = namespace {
+            let response: String = connection.request(&format!("SELECT {}", namespace))?;
+            if response != "OK" {
+                return Err(io::Error::other(format!(
+                    "{} cannot select {}: {}",
+                    address, namespace, response
+                )));
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Send a command and read its response, without its line terminator. The lines of a response spanning
+    /// several ones (i.e. the one of `MGET`, unless it is an error) are separated by `\n`.
+    fn request(&mut self, command: &str) -> io::Result<String> {
+        self.writer.write_all(command.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+
+        let mut response: String = String::new();
+        for line in 0..response_lines(command) {
+            if self.reader.read_line(&mut response)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the node closed the connection",
+                ));
+            }
+            if line == 0 && (response.starts_with("Error: ") || response.starts_with("ERR ")) {
+                break;
+            }
+        }
+        response.truncate(response.trim_end_matches(['\r', '\n']).len());
+        Ok(response)
+    }
+}
+
+/// A node of the cluster, connected to on demand.
+struct Node {
+    address: String,                // The address of the node (host:port)
+    connection: Option<Connection>, // The connection to the node, or `None` until the next command
+    usable: bool,                   // Whether the node can be read from, as of the last check
+    checked_at: Option<Instant>,    // When the node was last checked, if ever
+    latency: Duration,              // How long the node took to answer the last check
+}
+
+impl Node {
+    fn new(address: &str) -> Node {
+        Node {
+            address: address.to_string(),
+            connection: None,
+            usable: false,
+            checked_at: None,
+            latency: Duration::ZERO,
+        }
+    }
+
+    /// Send a command to the node, connecting to it first if needed. The connection is dropped on error,
+    /// since the response of the command may still be pending on it.
+    fn request(&mut self, command: &str, namespace: Option<&str>) -> io::Result<String> {
+        let connection: &mut Connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self
+                .connection
+                .insert(Connection::open(&self.address, namespace)?),
+        };
+        let response: io::Result<String> = connection.request(command);
+        if response.is_err() {
+            self.connection = None;
+        }
+        response
+    }
+
+    /// Check whether the node can be read from, unless it was checked recently: a replica has to be
+    /// linked to its primary, and to have heard from it within the given lag.
+    fn check(&mut self, max_lag: Duration, namespace: Option<&str>) -> bool {
+        if self
+            .checked_at
+            .is_some_and(|checked_at| checked_at.elapsed() < HEALTH_CHECK_INTERVAL)
+        {
+            return self.usable;
+        }
+
+        let first_check: bool = self.checked_at.is_none();
+        let started_at: Instant = Instant::now();
+        let response: io::Result<String> = self.request("ROLE", namespace);
+        self.latency = started_at.elapsed();
+        self.checked_at = Some(Instant::now());
+
+        let usable: bool = match &response {
+            Ok(role) => match role_lag(role) {
+                Some(lag) => lag <= max_lag.as_secs(),
+                None => role == "role=primary",
+            },
+            Err(_) => false,
+        };
+        if first_check || usable != self.usable {
+            match &response {
+                Ok(role) => debug!("{} is now {} ({})", self.address, usable_text(usable), role),
+                Err(e) => warn!("{} is now {} ({})", self.address, usable_text(usable), e),
+            }
+        }
+        self.usable = usable;
+        usable
+    }
+}
+
+/// A client of the text protocol of a primary node and its replicas (see `CacheServer::with_replica_of`),
+/// sending the writes to the primary and the reads where its `ReadPreference` says.
+///
+/// The replicas are checked with `ROLE` at most once a second, and only read from while they are linked to
+/// the primary and have heard from it within the maximum lag. The reads fall back to the primary when no
+/// replica is usable, or when the replica they were sent to fails.
+///
+/// ```no_run
+/// use myrustcache::client::{Client, ReadPreference};
+///
+/// let mut client = Client::connect("10.0.0.1:5050")?
+///     .with_replicas(&["10.0.0.2:5050", "10.0.0.3:5050"])
+///     .with_read_preference(ReadPreference::Replica);
+/// client.execute("SET x ABC 60")?;
+/// println!("{}", client.execute("GET x")?);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Client {
+    primary: Node,                   // The node taking the writes
+    replicas: Vec<Node>,             // The nodes replicating the primary, read from if preferred
+    read_preference: ReadPreference, // Where the reads are sent
+    max_lag: Duration,               // The lag beyond which a replica is not read from
+    namespace: Option<String>,       // The namespace selected on every node (see `select`)
+    next_replica: usize,             // The replica the next read is sent to first (round robin)
+}
+
+impl Client {
+    /// Connect to the primary node of the given address (`host:port`). The reads go to the primary until
+    /// replicas and a read preference are given.
+    ///
+    /// # Errors
+    /// * `io::Error` if the primary cannot be connected to.
+    pub fn connect(primary: &str) -> io::Result<Client> {
+        let mut primary: Node = Node::new(primary);
+        primary.connection = Some(Connection::open(&primary.address, None)?);
+        primary.usable = true;
+
+        Ok(Client {
+            primary,
+            replicas: Vec::new(),
+            read_preference: ReadPreference::Primary,
+            max_lag: DEFAULT_MAX_LAG,
+            namespace: None,
+            next_replica: 0,
+        })
+    }
+
+    /// Read from the replicas of the given addresses (`host:port`), connected to on their first read.
+    pub fn with_replicas(mut self, replicas: &[&str]) -> Client {
+        self.replicas = replicas.iter().map(|address| Node::new(address)).collect();
+        self
+    }
+
+    /// Send the reads where the given preference says.
+    pub fn with_read_preference(mut self, read_preference: ReadPreference) -> Client {
+        self.read_preference = read_preference;
+        self
+    }
+
+    /// Stop reading from a replica whose primary was last heard from longer ago than the given lag
+    /// (`DEFAULT_MAX_LAG` by default). The lag is measured in whole seconds.
+    pub fn with_max_lag(mut self, max_lag: Duration) -> Client {
+        self.max_lag = max_lag;
+        self
+    }
+
+    /// Select the given namespace on every node, as `SELECT <namespace>` does on a connection.
+    ///
+    /// # Returns
+    /// * The response of the primary, e.g. `OK`.
+    pub fn select(&mut self, namespace: &str) -> io::Result<String> {
+        let response: String = self
+            .primary
+            .request(&format!("SELECT {}", namespace), self.namespace.as_deref())?;
+        if response == "OK" {
+            self.namespace = Some(namespace.to_string());
+            // The replicas select the namespace when they are connected again
+            for replica in &mut self.replicas {
+                replica.connection = None;
+            }
+        }
+        Ok(response)
+    }
+
+    /// Send a command of the text protocol (e.g. `GET x`) and read its response (e.g. `ABC` or `NULL`, or
+    /// one line per key for `MGET`, e.g. `ABC\nNULL`). The reads go where the read preference says, and any
+    /// other command to the primary.
+    ///
+    /// # Errors
+    /// * `io::Error` if the command holds a line terminator, or if the primary cannot be reached.
+    pub fn execute(&mut self, command: &str) -> io::Result<String> {
+        if command.contains(['\r', '\n']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a command cannot hold a line terminator",
+            ));
+        }
+        if !is_read(command) || self.read_preference == ReadPreference::Primary {
+            return self.primary.request(command, self.namespace.as_deref());
+        }
+
+        let namespace: Option<&str> = self.namespace.as_deref();
+        let replica: Option<usize> = match self.read_preference {
+            ReadPreference::Nearest => {
+                let primary_latency: Duration = match self.primary.check(self.max_lag, namespace) {
+                    true => self.primary.latency,
+                    false => Duration::MAX,
+                };
+                nearest_replica(&mut self.replicas, self.max_lag, namespace)
+                    .filter(|&index| self.replicas[index].latency < primary_latency)
+            }
+            _ => {
+                let count: usize = self.replicas.len();
+                let start: usize = self.next_replica;
+                self.next_replica = (start + 1) % count.max(1);
+                (0..count)
+                    .map(|offset| (start + offset) % count)
+                    .find(|&index| self.replicas[index].check(self.max_lag, namespace))
+            }
+        };
+
+        if let Some(index) = replica {
+            let replica: &mut Node = &mut self.replicas[index];
+            match replica.request(command, namespace) {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    warn!(
+                        "Failed to read from {}, reading from the primary: {}",
+                        replica.address, e
+                    );
+                    replica.usable = false;
+                }
+            }
+        }
+        self.primary.request(command, namespace)
+    }
+}
+
+/// The number of lines of the response to the given command: one per key for `MGET`, or else one.
+fn response_lines(command: &str) -> usize {
+    let mut tokens = command.split_ascii_whitespace();
+    match tokens.next() {
+        Some("MGET") => tokens.count().max(1),
+        _ => 1,
+    }
+}
+
+/// Whether the given command only reads the cache.
+fn is_read(command: &str) -> bool {
+    let mut tokens = command.split_ascii_whitespace();
+    match tokens.next() {
+        Some("GET") => !tokens.any(|token| token == "LOCK"),
+        Some(name) => READ_COMMANDS.contains(&name),
+        None => false,
+    }
+}
+
+/// The usable replica answering the fastest, if any.
+fn nearest_replica(
+    replicas: &mut [Node],
+    max_lag: Duration,
+    namespace: Option<&str>,
+) -> Option<usize> {
+    let mut nearest: Option<usize> = None;
+    for index in 0..replicas.len() {
+        if !replicas[index].check(max_lag, namespace) {
+            continue;
+        }
+        if nearest.is_none_or(|nearest| replicas[index].latency < replicas[nearest].latency) {
+            nearest = Some(index);
+        }
+    }
+    nearest
+}
+
+/// The lag of a replica linked to its primary, in seconds, from its answer to `ROLE` (e.g.
+/// `role=replica link=up lag=2`).
+fn role_lag(role: &str) -> Option<u64> {
+    let mut fields = role.split(' ');
+    if fields.next() != Some("role=replica") || fields.next() != Some("link=up") {
+        return None;
+    }
+    fields
+        .next()
+        .and_then(|lag| lag.strip_prefix("lag="))
+        .and_then(|lag| parse_number(lag.as_bytes()))
+}
+
+/// How the state of a node is logged.
+fn usable_text(usable: bool) -> &'static str {
+    match usable {
+        true => "usable",
+        false => "unusable",
+    }
+}
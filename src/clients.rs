@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::{
+    connection::ClientStream,
+    error::{CacheError, CacheResult},
+};
+
+/// The longest command name kept as the last command of a client, in bytes.
+const MAX_COMMAND_NAME_SIZE: usize = 32;
+
+/// A client connection, as listed by `CLIENT LIST` (see `EmbeddedCache::clients`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub id: u64,                      // Increases with every connection, never reused
+    pub address: String,              // The address of the client (IP:Port, or `unix:<fd>`)
+    pub protocol: &'static str,       // The protocol of the listener the client connected to
+    pub age: Duration,                // How long ago the client connected
+    pub idle: Duration, // How long ago the client sent its last command (or connected)
+    pub commands: u64,  // The commands the client has sent
+    pub last_command: Option<String>, // The name of the last command, in uppercase, if any was sent
+}
+
+/// What a client has done since it connected.
+#[derive(Debug)]
+struct ClientActivity {
+    last_active: Instant, // When the client sent its last command, or connected
+    commands: u64,        // The commands the client has sent
+    last_command: Option<String>, // The name of the last command, if any
+}
+
+/// A client connection registered, along with a clone of its stream to disconnect it.
+struct ClientEntry {
+    id: u64,
+    protocol: &'static str,
+    connected: Instant,
+    activity: Mutex<ClientActivity>,
+    stream: ClientStream,
+}
+
+/// The client connections open across all the listeners, by address, inspected with `CLIENT LIST` and
+/// disconnected with `CLIENT KILL`.
+///
+/// The connections register themselves once accepted (see `register`) and unregister once closed, when
+/// their `RegisteredClient` guard is dropped.
+pub(crate) struct ClientRegistry {
+    clients: RwLock<HashMap<String, Arc<ClientEntry>>>, // The connections open, by address
+    next_id: AtomicU64,                                 // The id of the next connection registered
+}
+
+impl ClientRegistry {
+    pub fn new() -> ClientRegistry {
+        ClientRegistry {
+            clients: RwLock::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Register a client connection until the returned guard is dropped.
+    ///
+    /// # Arguments
+    /// * `protocol` - The protocol of the listener which accepted the connection.
+    /// * `stream` - The client connection, of which a clone is kept to disconnect it.
+    ///
+    /// # Errors
+    /// * If the stream cannot be cloned.
+    pub fn register(
+        self: &Arc<ClientRegistry>,
+        protocol: &'static str,
+        stream: &ClientStream,
+    ) -> CacheResult<RegisteredClient> {
+        let address: String = stream.peer_address();
+        let now: Instant = Instant::now();
+        let entry: ClientEntry = ClientEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            protocol,
+            connected: now,
+            activity: Mutex::new(ClientActivity {
+                last_active: now,
+                commands: 0,
+                last_command: None,
+            }),
+            stream: stream.try_clone()?,
+        };
+        self.clients
+            .write()
+            .map_err(CacheError::from)?
+            .insert(address.clone(), Arc::new(entry));
+        Ok(RegisteredClient {
+            registry: Arc::clone(self),
+            address,
+        })
+    }
+
+    /// Count a command sent by a client, if it is registered (e.g. not a UDP client).
+    ///
+    /// # Arguments
+    /// * `address` - The address of the client.
+    /// * `name` - The name of the command.
+    pub fn record_command(&self, address: &str, name: &[u8]) -> CacheResult<()> {
+        let Some(entry) = self._entry(address)? else {
+            return Ok(());
+        };
+        let name: &[u8] = &name[..name.len().min(MAX_COMMAND_NAME_SIZE)];
+        let mut activity: MutexGuard<ClientActivity> =
+            entry.activity.lock().map_err(CacheError::from)?;
+        activity.last_active = Instant::now();
+        activity.commands += 1;
+        activity.last_command = Some(String::from_utf8_lossy(name).to_ascii_uppercase());
+        Ok(())
+    }
+
+    /// The client connections open, in the order they connected.
+    pub fn list(&self) -> CacheResult<Vec<ClientInfo>> {
+        let clients = self.clients.read().map_err(CacheError::from)?;
+        let mut list: Vec<ClientInfo> = Vec::with_capacity(clients.len());
+        for (address, entry) in clients.iter() {
+            let activity: MutexGuard<ClientActivity> =
+                entry.activity.lock().map_err(CacheError::from)?;
+            list.push(ClientInfo {
+                id: entry.id,
+                address: address.clone(),
+                protocol: entry.protocol,
+                age: entry.connected.elapsed(),
+                idle: activity.last_active.elapsed(),
+                commands: activity.commands,
+                last_command: activity.last_command.clone(),
+            });
+        }
+        list.sort_by_key(|client| client.id);
+        Ok(list)
+    }
+
+    /// Disconnect a client, which notices once it next reads or writes (e.g. right away while it waits
+    /// for a command).
+    ///
+    /// # Returns
+    /// * Whether a client was connected from the given address.
+    pub fn kill(&self, address: &str) -> CacheResult<bool> {
+        let Some(entry) = self._entry(address)? else {
+            return Ok(false);
+        };
+        info!("Disconnecting the {} client {}", entry.protocol, address);
+        // The client may have just closed the connection itself
+        let _ = entry.stream.shutdown(Shutdown::Both);
+        Ok(true)
+    }
+
+    fn _entry(&self, address: &str) -> CacheResult<Option<Arc<ClientEntry>>> {
+        let clients = self.clients.read().map_err(CacheError::from)?;
+        Ok(clients.get(address).cloned())
+    }
+}
+
+/// Keeps a client connection registered (see `ClientRegistry::register`) until dropped.
+pub(crate) struct RegisteredClient {
+    registry: Arc<ClientRegistry>,
+    address: String,
+}
+
+impl Drop for RegisteredClient {
+    fn drop(&mut self) {
+        if let Ok(mut clients) = self.registry.clients.write() {
+            clients.remove(&self.address);
+        }
+    }
+}
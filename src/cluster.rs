@@ -0,0 +1,160 @@
+use std::str::FromStr;
+
+/// The number of hash slots the keyspace is split into, as in Redis Cluster.
+pub const SLOT_COUNT: usize = 16384;
+
+/// The hash slot of a key: the CRC16 (XMODEM) of the key modulo `SLOT_COUNT`, as in Redis Cluster, so that
+/// cluster-aware Redis clients route the keys the same way. If the key holds a non-empty hash tag (the
+/// part between the first `{` and the next `}`, e.g. `user` in `{user}:1`), only the tag is hashed, so that
+/// related keys can be kept in the same slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let hashed: &[u8] = match key.iter().position(|&byte| byte == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|&byte| byte == b'}') {
+            Some(length) if length > 0 => &key[open + 1..open + 1 + length],
+            _ => key,
+        },
+        None => key,
+    };
+    crc16(hashed) % SLOT_COUNT as u16
+}
+
+/// Which node of the cluster owns each hash slot.
+///
+/// Parsed from `host:port=0-8191;host:port=8192-16383`: the RESP address of every node, followed by the
+/// ranges of the slots it owns (a single slot, or an inclusive range), separated by `,`. Every slot has to
+/// be owned by exactly one node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotMap {
+    nodes: Vec<String>, // The RESP addresses of the nodes (host:port), in the order they are given
+    owners: Vec<u16>,   // The index of the node owning each slot
+}
+
+impl SlotMap {
+    /// The RESP addresses of the nodes (`host:port`).
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// The address of the node owning the given slot.
+    pub fn owner(&self, slot: u16) -> &str {
+        &self.nodes[self.owners[slot as usize] as usize]
+    }
+
+    /// The ranges of consecutive slots owned by the same node, in the order of the slots.
+    ///
+    /// # Returns
+    /// * The first and last slot of each range (inclusive), and the address of its node.
+    pub fn ranges(&self) -> Vec<(u16, u16, &str)> {
+        let mut ranges: Vec<(u16, u16, &str)> = Vec::new();
+        for (slot, &owner) in self.owners.iter().enumerate() {
+            let node: &str = &self.nodes[owner as usize];
+            match ranges.last_mut() {
+                Some((_, last, last_node)) if *last_node == node => *last = slot as u16,
+                _ => ranges.push((slot as u16, slot as u16, node)),
+            }
+        }
+        ranges
+    }
+}
+
+impl FromStr for SlotMap {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<SlotMap, String> {
+        let mut nodes: Vec<String> = Vec::new();
+        let mut owners: Vec<Option<u16>> = vec![None; SLOT_COUNT];
+
+        for node in text
+            .split(';')
+            .map(str::trim)
+            .filter(|node| !node.is_empty())
+        {
+            let Some((address, ranges)) = node.split_once('=') else {
+                return Err(format!("'{}' is not a host:port=slots entry", node));
+            };
+            let address: &str = address.trim();
+            if nodes.iter().any(|known| known == address) {
+                return Err(format!("the node {} is given twice", address));
+            }
+            let index: u16 = nodes.len() as u16;
+            nodes.push(address.to_string());
+
+            for range in ranges.split(',').map(str::trim) {
+                let (first, last) = range.split_once('-').unwrap_or((range, range));
+                let (Ok(first), Ok(last)) =
+                    (first.trim().parse::<usize>(), last.trim().parse::<usize>())
+                else {
+                    return Err(format!("'{}' is not a slot range", range));
+                };
+                if first > last || last >= SLOT_COUNT {
+                    return Err(format!(
+                        "'{}' is not a slot range within 0-{}",
+                        range,
+                        SLOT_COUNT - 1
+                    ));
+                }
+                if let Some(slot) = (first..=last).find(|&slot| owners[slot].is_some()) {
+                    return Err(format!("the slot {} is assigned twice", slot));
+                }
+                owners[first..=last].fill(Some(index));
+            }
+        }
+
+        let owners: Vec<u16> = match owners.iter().position(Option::is_none) {
+            Some(slot) => return Err(format!("the slot {} is not assigned to any node", slot)),
+            None => owners.into_iter().flatten().collect(),
+        };
+        Ok(SlotMap { nodes, owners })
+    }
+}
+
+/// The cluster a node belongs to: which node owns each slot, and which of the nodes it is.
+pub(crate) struct Cluster {
+    slots: SlotMap, // Which node owns each slot
+    myself: String, // The address of this node in the slot map
+}
+
+impl Cluster {
+    pub fn new(slots: SlotMap, myself: String) -> Cluster {
+        Cluster { slots, myself }
+    }
+
+    pub fn slots(&self) -> &SlotMap {
+        &self.slots
+    }
+
+    /// The number of slots this node owns.
+    pub fn owned_slots(&self) -> usize {
+        (0..SLOT_COUNT as u16)
+            .filter(|&slot| self.slots.owner(slot) == self.myself)
+            .count()
+    }
+
+    /// The error to answer a command on the given keys with, unless this node owns their slot: `MOVED` to
+    /// the node owning it, or `CROSSSLOT` if the keys hash to different slots.
+    pub fn redirect(&self, keys: &[&[u8]]) -> Option<String> {
+        let (first, others) = keys.split_first()?;
+        let slot: u16 = key_slot(first);
+        if others.iter().any(|key| key_slot(key) != slot) {
+            return Some("CROSSSLOT Keys in request don't hash to the same slot".to_string());
+        }
+        let owner: &str = self.slots.owner(slot);
+        (owner != self.myself).then(|| format!("MOVED {} {}", slot, owner))
+    }
+}
+
+/// The CRC16 of the given data, with the XMODEM parameters (polynomial 0x1021, no reflection, initial
+/// value 0), which Redis Cluster hashes the keys with.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
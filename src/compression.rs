@@ -0,0 +1,336 @@
+use crate::error::{CacheError, CacheResult};
+
+/// The header of the compressed values, unlikely to start a value by chance. A value which does is stored
+/// behind the header too (see `METHOD_STORED`), so that it is never mistaken for a compressed one.
+const COMPRESSED_MAGIC: [u8; 4] = [0xC1, b'M', b'R', b'Z'];
+
+/// The byte following `COMPRESSED_MAGIC` in the values stored as they are.
+const METHOD_STORED: u8 = 0;
+
+/// The byte following `COMPRESSED_MAGIC` in the values compressed with LZ4.
+const METHOD_LZ4: u8 = 1;
+
+/// The length of the header: `COMPRESSED_MAGIC`, the method, then the length of the original value as a
+/// 32-bit big-endian integer.
+const HEADER_SIZE: usize = COMPRESSED_MAGIC.len() + 1 + 4;
+
+/// The shortest match of LZ4.
+const MIN_MATCH: usize = 4;
+
+/// The number of bytes ending a block which are always literals.
+const LAST_LITERALS: usize = 5;
+
+/// How far from the end of a block the last match may start.
+const MATCH_FIND_LIMIT: usize = 12;
+
+/// The farthest a match may refer back to.
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+/// The number of bits of the hashes of the sequences looked up for matches.
+const HASH_LOG: u32 = 14;
+
+/// Encode a value for storage, compressed with LZ4 if it is at least `threshold` bytes long and compressing
+/// it saves space.
+///
+/// # Returns
+/// * The encoded value, or `None` if the value is stored as it is.
+pub fn compress_value(value: &[u8], threshold: usize) -> Option<Vec<u8>> {
+    if value.len() >= threshold && value.len() <= u32::MAX as usize {
+        let compressed: Vec<u8> = lz4_compress(value);
+        if HEADER_SIZE + compressed.len() < value.len() {
+            return Some(framed(METHOD_LZ4, value.len(), &compressed));
+        }
+    }
+    match value.starts_with(&COMPRESSED_MAGIC) {
+        true => Some(framed(METHOD_STORED, value.len(), value)),
+        false => None,
+    }
+}
+
+/// Decode a value encoded by `compress_value`.
+///
+/// # Returns
+/// * The original value, or `None` if the value was stored as it is.
+///
+/// # Errors
+/// * `CacheError::Storage` if the value is not a valid encoding.
+pub fn decompress_value(stored: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+    let Some(rest) = stored.strip_prefix(&COMPRESSED_MAGIC) else {
+        return Ok(None);
+    };
+    let invalid = || CacheError::Storage("a compressed value is corrupt".to_string());
+    let [method, a, b, c, d, payload @ ..] = rest else {
+        return Err(invalid());
+    };
+    let length: usize = u32::from_be_bytes([*a, *b, *c, *d]) as usize;
+    match *method {
+        METHOD_STORED if payload.len() == length => Ok(Some(payload.to_vec())),
+        METHOD_LZ4 => lz4_decompress(payload, length)
+            .map(Some)
+            .ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+/// The header and the payload of an encoded value.
+fn framed(method: u8, length: usize, payload: &[u8]) -> Vec<u8> {
+    let mut encoded: Vec<u8> = Vec::with_capacity(HEADER_SIZE + payload.len());
+    encoded.extend_from_slice(&COMPRESSED_MAGIC);
+    encoded.push(method);
+    encoded.extend_from_slice(&(length as u32).to_be_bytes());
+    encoded.extend_from_slice(payload);
+    encoded
+}
+
+/// Compress data as an LZ4 block (without the frame around it), finding the matches greedily.
+pub fn lz4_compress(input: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(input.len() / 2 + 16);
+    let mut anchor: usize = 0; // The start of the literals not written yet
+
+    if input.len() > MATCH_FIND_LIMIT {
+        // The positions of the sequences of 4 bytes by their hash, plus one (0 for none)
+        let mut table: Vec<u32> = vec![0; 1 << HASH_LOG];
+        let match_start_limit: usize = input.len() - MATCH_FIND_LIMIT;
+        let match_end_limit: usize = input.len() - LAST_LITERALS;
+        let mut position: usize = 0;
+        while position < match_start_limit {
+            let sequence: u32 = read_u32(input, position);
+            let hash: usize = (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize;
+            let candidate: usize = table[hash] as usize;
+            table[hash] = position as u32 + 1;
+            if candidate == 0
+                || position - (candidate - 1) > MAX_OFFSET
+                || read_u32(input, candidate - 1) != sequence
+            {
+                position += 1;
+                continue;
+            }
+
+            let candidate: usize = candidate - 1;
+            let mut length: usize = MIN_MATCH;
+            while position + length < match_end_limit
+                && input[candidate + length] == input[position + length]
+            {
+                length += 1;
+            }
+            write_sequence(
+                &mut output,
+                &input[anchor..position],
+                Some((position - candidate, length)),
+            );
+            position += length;
+            anchor = position;
+        }
+    }
+
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+/// Decompress an LZ4 block into the given number of bytes.
+///
+/// # Returns
+/// * The decompressed data, or `None` if the block is invalid or does not decompress to `length` bytes.
+pub fn lz4_decompress(input: &[u8], length: usize) -> Option<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::with_capacity(length);
+    let mut position: usize = 0;
+    loop {
+        let token: u8 = *input.get(position)?;
+        position += 1;
+
+        let literals: usize = read_length(input, &mut position, (token >> 4) as usize)?;
+        let end: usize = position.checked_add(literals)?;
+        if output.len() + literals > length {
+            return None;
+        }
+        output.extend_from_slice(input.get(position..end)?);
+        position = end;
+        if position == input.len() {
+            break;
+        }
+
+        let offset: usize =
+            u16::from_le_bytes([*input.get(position)?, *input.get(position + 1)?]) as usize;
+        position += 2;
+        if offset == 0 || offset > output.len() {
+            return None;
+        }
+        let matched: usize =
+            read_length(input, &mut position, (token & 0x0F) as usize)? + MIN_MATCH;
+        if output.len() + matched > length {
+            return None;
+        }
+        // The match may overlap the bytes it copies, so they are copied one by one
+        let start: usize = output.len() - offset;
+        for index in start..start + matched {
+            output.push(output[index]);
+        }
+    }
+    (output.len() == length).then_some(output)
+}
+
+/// Write a sequence of an LZ4 block: literals, then a match (its offset and length), if any.
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_length: usize = matched.map_or(0, |(_, length)| length - MIN_MATCH);
+    output.push(((literals.len().min(15) as u8) << 4) | match_length.min(15) as u8);
+    write_length(output, literals.len());
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        write_length(output, match_length);
+    }
+}
+
+/// Write the bytes extending a length beyond the 15 of its token.
+fn write_length(output: &mut Vec<u8>, length: usize) {
+    if length < 15 {
+        return;
+    }
+    let mut rest: usize = length - 15;
+    while rest >= 255 {
+        output.push(255);
+        rest -= 255;
+    }
+    output.push(rest as u8);
+}
+
+/// Read a length, given the 4 bits of its token, extended by the following bytes if they are all set.
+fn read_length(input: &[u8], position: &mut usize, length: usize) -> Option<usize> {
+    let mut length: usize = length;
+    if length == 15 {
+        loop {
+            let byte: u8 = *input.get(*position)?;
+            *position += 1;
+            length = length.checked_add(byte as usize)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(length)
+}
+
+fn read_u32(input: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes([
+        input[position],
+        input[position + 1],
+        input[position + 2],
+        input[position + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Data of the given length which compresses somewhat, made of pseudo-random words.
+    fn text(length: usize) -> Vec<u8> {
+        let words: [&[u8]; 6] = [
+            b"cache ", b"key ", b"value ", b"expire ", b"evict ", b"\0\xff ",
+        ];
+        let mut seed: u64 = 42;
+        let mut data: Vec<u8> = Vec::with_capacity(length + 8);
+        while data.len() < length {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            data.extend_from_slice(words[(seed >> 33) as usize % words.len()]);
+        }
+        data.truncate(length);
+        data
+    }
+
+    /// Data of the given length which does not compress.
+    fn noise(length: usize) -> Vec<u8> {
+        let mut seed: u64 = 7;
+        (0..length)
+            .map(|_| {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                (seed >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(data: &[u8]) {
+        let compressed: Vec<u8> = lz4_compress(data);
+        assert_eq!(
+            lz4_decompress(&compressed, data.len()).as_deref(),
+            Some(data)
+        );
+    }
+
+    #[test]
+    fn lz4_round_trips() {
+        for length in [0, 1, 4, 12, 13, 15, 16, 100, 4096, 70_000, 300_000] {
+            round_trip(&text(length));
+            round_trip(&noise(length));
+            round_trip(&vec![b'a'; length]);
+        }
+    }
+
+    #[test]
+    fn lz4_compresses_repeated_data() {
+        let data: Vec<u8> = vec![b'a'; 100_000];
+        assert!(lz4_compress(&data).len() < 1_000);
+        let data: Vec<u8> = text(100_000);
+        assert!(lz4_compress(&data).len() < data.len() / 2);
+    }
+
+    #[test]
+    fn lz4_decompresses_overlapping_matches() {
+        // The literals `ab`, then a match of 8 bytes 2 bytes back, then the literal `c`
+        let block: &[u8] = &[0x24, b'a', b'b', 0x02, 0x00, 0x10, b'c'];
+        assert_eq!(
+            lz4_decompress(block, 11).as_deref(),
+            Some(&b"abababababc"[..])
+        );
+    }
+
+    #[test]
+    fn lz4_rejects_invalid_blocks() {
+        let compressed: Vec<u8> = lz4_compress(&text(1_000));
+        assert_eq!(lz4_decompress(&compressed, 999), None);
+        assert_eq!(lz4_decompress(&compressed, 1_001), None);
+        for end in 0..compressed.len() - 1 {
+            assert_eq!(lz4_decompress(&compressed[..end], 1_000), None);
+        }
+        // A match referring back before the start of the data
+        assert_eq!(lz4_decompress(&[0x10, b'a', 0x02, 0x00, 0x00], 5), None);
+        // A match with no offset
+        assert_eq!(lz4_decompress(&[0x10, b'a', 0x00, 0x00, 0x00], 5), None);
+    }
+
+    #[test]
+    fn values_are_compressed_from_the_threshold() {
+        let value: Vec<u8> = text(1_000);
+        assert_eq!(compress_value(&value, 1_001), None);
+        let stored: Vec<u8> = compress_value(&value, 1_000).unwrap();
+        assert!(stored.len() < value.len());
+        assert_eq!(decompress_value(&stored).unwrap(), Some(value));
+    }
+
+    #[test]
+    fn incompressible_values_are_stored_as_they_are() {
+        let value: Vec<u8> = noise(1_000);
+        assert_eq!(compress_value(&value, 0), None);
+        assert_eq!(decompress_value(&value).unwrap(), None);
+    }
+
+    #[test]
+    fn values_starting_with_the_header_are_framed() {
+        let mut value: Vec<u8> = COMPRESSED_MAGIC.to_vec();
+        value.extend_from_slice(b"value");
+        let stored: Vec<u8> = compress_value(&value, usize::MAX).unwrap();
+        assert_ne!(stored, value);
+        assert_eq!(decompress_value(&stored).unwrap(), Some(value));
+    }
+
+    #[test]
+    fn corrupt_values_are_rejected() {
+        let stored: Vec<u8> = compress_value(&text(1_000), 0).unwrap();
+        assert!(decompress_value(&stored[..stored.len() - 1]).is_err());
+        assert!(decompress_value(&stored[..HEADER_SIZE - 1]).is_err());
+
+        let mut stored: Vec<u8> = stored;
+        stored[COMPRESSED_MAGIC.len()] = 9;
+        assert!(decompress_value(&stored).is_err());
+    }
+}
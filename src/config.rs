@@ -0,0 +1,2007 @@
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fmt, fs,
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    acl::AclUser,
+    audit::{AuditSink, DEFAULT_AUDIT_LOG_MAX_FILES, DEFAULT_AUDIT_LOG_MAX_SIZE},
+    auth::DEFAULT_USER,
+    buffers::DEFAULT_BUFFER_LIMIT,
+    cache::{
+        EngineOptions, FsyncPolicy, MaxMemoryPolicy, SweeperOptions, SyncPolicy, BUILTIN_ENGINES,
+        DEFAULT_ENGINE,
+    },
+    cluster::SlotMap,
+    embedded::{DEFAULT_SCRIPT_TIME_LIMIT_MS, MAX_TTL_JITTER},
+    events::KeyspaceNotifications,
+    history::DEFAULT_HISTORY_BYTES,
+    ipfilter::IpRange,
+    namespaces::{self, NamespaceQuota},
+    shutdown::DEFAULT_SHUTDOWN_TIMEOUT,
+    slowlog::{DEFAULT_SLOWLOG_MAX_LEN, DEFAULT_SLOWLOG_THRESHOLD_US},
+    warmup::WarmupSource,
+    workers::{RejectionPolicy, WorkerOptions},
+};
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 5050;
+
+/// Environment variables the server configuration is read from.
+const ENV_HOST: &str = "MYRUSTCACHE_HOST";
+const ENV_PORT: &str = "MYRUSTCACHE_PORT";
+const ENV_RESP_PORT: &str = "MYRUSTCACHE_RESP_PORT";
+const ENV_MEMCACHED_PORT: &str = "MYRUSTCACHE_MEMCACHED_PORT";
+const ENV_HTTP_PORT: &str = "MYRUSTCACHE_HTTP_PORT";
+const ENV_WEBSOCKET_PORT: &str = "MYRUSTCACHE_WEBSOCKET_PORT";
+const ENV_WEBSOCKET_ORIGINS: &str = "MYRUSTCACHE_WEBSOCKET_ORIGINS";
+const ENV_WEBSOCKET_TOKEN: &str = "MYRUSTCACHE_WEBSOCKET_TOKEN";
+const ENV_UDP_PORT: &str = "MYRUSTCACHE_UDP_PORT";
+const ENV_METRICS_PORT: &str = "MYRUSTCACHE_METRICS_PORT";
+const ENV_UNIXSOCKET: &str = "MYRUSTCACHE_UNIXSOCKET";
+const ENV_REPLICA_OF: &str = "MYRUSTCACHE_REPLICA_OF";
+const ENV_REPLICA_AUTH: &str = "MYRUSTCACHE_REPLICA_AUTH";
+const ENV_ACCEPT_REPLICAS: &str = "MYRUSTCACHE_ACCEPT_REPLICAS";
+const ENV_READ_ONLY: &str = "MYRUSTCACHE_READ_ONLY";
+const ENV_CLUSTER_NODES: &str = "MYRUSTCACHE_CLUSTER_NODES";
+const ENV_CLUSTER_ADDRESS: &str = "MYRUSTCACHE_CLUSTER_ADDRESS";
+const ENV_REQUIREPASS: &str = "MYRUSTCACHE_REQUIREPASS";
+const ENV_ACL_USERS: &str = "MYRUSTCACHE_ACL_USERS";
+const ENV_ALLOW_IPS: &str = "MYRUSTCACHE_ALLOW_IPS";
+const ENV_DENY_IPS: &str = "MYRUSTCACHE_DENY_IPS";
+const ENV_STORAGE_ENGINE: &str = "MYRUSTCACHE_STORAGE_ENGINE";
+const ENV_SHARDS: &str = "MYRUSTCACHE_SHARDS";
+const ENV_TIERED_MEMORY_ENTRIES: &str = "MYRUSTCACHE_TIERED_MEMORY_ENTRIES";
+const ENV_TIERED_DISK_DIR: &str = "MYRUSTCACHE_TIERED_DISK_DIR";
+const ENV_TIERED_DISK_BYTES: &str = "MYRUSTCACHE_TIERED_DISK_BYTES";
+const ENV_MMAP_PATH: &str = "MYRUSTCACHE_MMAP_PATH";
+const ENV_MMAP_SYNC: &str = "MYRUSTCACHE_MMAP_SYNC";
+const ENV_MAX_ENTRIES: &str = "MYRUSTCACHE_MAX_ENTRIES";
+const ENV_MAXMEMORY: &str = "MYRUSTCACHE_MAXMEMORY";
+const ENV_MAXMEMORY_POLICY: &str = "MYRUSTCACHE_MAXMEMORY_POLICY";
+const ENV_SWEEP_INTERVAL_MS: &str = "MYRUSTCACHE_SWEEP_INTERVAL_MS";
+const ENV_SWEEP_MAX_EXPIRED: &str = "MYRUSTCACHE_SWEEP_MAX_EXPIRED";
+const ENV_TTL_JITTER_PERCENT: &str = "MYRUSTCACHE_TTL_JITTER_PERCENT";
+const ENV_CONNECTION_BUFFER_LIMIT: &str = "MYRUSTCACHE_CONNECTION_BUFFER_LIMIT";
+const ENV_NAMESPACE_QUOTAS: &str = "MYRUSTCACHE_NAMESPACE_QUOTAS";
+const ENV_NAMESPACE_ENGINES: &str = "MYRUSTCACHE_NAMESPACE_ENGINES";
+const ENV_WARMUP_SOURCE: &str = "MYRUSTCACHE_WARMUP_SOURCE";
+const ENV_PRELOAD: &str = "MYRUSTCACHE_PRELOAD";
+const ENV_SNAPSHOT_PATH: &str = "MYRUSTCACHE_SNAPSHOT_PATH";
+const ENV_SNAPSHOT_INTERVAL_SECONDS: &str = "MYRUSTCACHE_SNAPSHOT_INTERVAL_SECONDS";
+const ENV_APPENDONLY_PATH: &str = "MYRUSTCACHE_APPENDONLY_PATH";
+const ENV_APPENDFSYNC: &str = "MYRUSTCACHE_APPENDFSYNC";
+const ENV_AOF_REWRITE_PERCENTAGE: &str = "MYRUSTCACHE_AOF_REWRITE_PERCENTAGE";
+const ENV_AOF_REWRITE_MIN_SIZE: &str = "MYRUSTCACHE_AOF_REWRITE_MIN_SIZE";
+const ENV_EXPORT_DIR: &str = "MYRUSTCACHE_EXPORT_DIR";
+const ENV_TOMBSTONE_WINDOW_SECONDS: &str = "MYRUSTCACHE_TOMBSTONE_WINDOW_SECONDS";
+const ENV_HISTORY_VERSIONS: &str = "MYRUSTCACHE_HISTORY_VERSIONS";
+const ENV_HISTORY_BYTES: &str = "MYRUSTCACHE_HISTORY_BYTES";
+const ENV_SLIDING_EXPIRATION: &str = "MYRUSTCACHE_SLIDING_EXPIRATION";
+const ENV_TRACK_ACCESS: &str = "MYRUSTCACHE_TRACK_ACCESS";
+const ENV_DATABASES: &str = "MYRUSTCACHE_DATABASES";
+const ENV_KEYSPACE_NOTIFICATIONS: &str = "MYRUSTCACHE_KEYSPACE_NOTIFICATIONS";
+const ENV_SCRIPT_TIME_LIMIT_MS: &str = "MYRUSTCACHE_SCRIPT_TIME_LIMIT_MS";
+const ENV_MAX_KEY_BYTES: &str = "MYRUSTCACHE_MAX_KEY_BYTES";
+const ENV_MAX_VALUE_BYTES: &str = "MYRUSTCACHE_MAX_VALUE_BYTES";
+const ENV_COMPRESSION_THRESHOLD: &str = "MYRUSTCACHE_COMPRESSION_THRESHOLD";
+const ENV_SLOWLOG_THRESHOLD_US: &str = "MYRUSTCACHE_SLOWLOG_THRESHOLD_US";
+const ENV_SLOWLOG_MAX_LEN: &str = "MYRUSTCACHE_SLOWLOG_MAX_LEN";
+const ENV_AUDIT_LOG: &str = "MYRUSTCACHE_AUDIT_LOG";
+const ENV_AUDIT_LOG_MAX_SIZE: &str = "MYRUSTCACHE_AUDIT_LOG_MAX_SIZE";
+const ENV_AUDIT_LOG_MAX_FILES: &str = "MYRUSTCACHE_AUDIT_LOG_MAX_FILES";
+const ENV_LOG_LEVEL: &str = "MYRUSTCACHE_LOG_LEVEL";
+const ENV_CONFIG: &str = "MYRUSTCACHE_CONFIG";
+const ENV_WORKER_THREADS: &str = "MYRUSTCACHE_WORKER_THREADS";
+const ENV_WORKER_QUEUE: &str = "MYRUSTCACHE_WORKER_QUEUE";
+const ENV_WORKER_REJECTION: &str = "MYRUSTCACHE_WORKER_REJECTION";
+const ENV_SHUTDOWN_TIMEOUT_SECONDS: &str = "MYRUSTCACHE_SHUTDOWN_TIMEOUT_SECONDS";
+const ENV_MAXCLIENTS: &str = "MYRUSTCACHE_MAXCLIENTS";
+const ENV_IDLE_TIMEOUT_SECONDS: &str = "MYRUSTCACHE_IDLE_TIMEOUT_SECONDS";
+const ENV_COMMAND_TIMEOUT_MS: &str = "MYRUSTCACHE_COMMAND_TIMEOUT_MS";
+const ENV_CLIENT_OUTPUT_BYTES: &str = "MYRUSTCACHE_CLIENT_OUTPUT_BYTES";
+const ENV_RATE_LIMIT: &str = "MYRUSTCACHE_RATE_LIMIT";
+const ENV_RATE_LIMIT_BURST: &str = "MYRUSTCACHE_RATE_LIMIT_BURST";
+
+/// Every environment variable of the configuration, each of which can also be given as a command line flag
+/// named after it (e.g. `--worker-threads` for `MYRUSTCACHE_WORKER_THREADS`).
+const SETTINGS: [&str; 74] = [
+    ENV_HOST,
+    ENV_PORT,
+    ENV_RESP_PORT,
+    ENV_MEMCACHED_PORT,
+    ENV_HTTP_PORT,
+    ENV_WEBSOCKET_PORT,
+    ENV_WEBSOCKET_ORIGINS,
+    ENV_WEBSOCKET_TOKEN,
+    ENV_UDP_PORT,
+    ENV_METRICS_PORT,
+    ENV_UNIXSOCKET,
+    ENV_REPLICA_OF,
+    ENV_REPLICA_AUTH,
+    ENV_ACCEPT_REPLICAS,
+    ENV_READ_ONLY,
+    ENV_CLUSTER_NODES,
+    ENV_CLUSTER_ADDRESS,
+    ENV_REQUIREPASS,
+    ENV_ACL_USERS,
+    ENV_ALLOW_IPS,
+    ENV_DENY_IPS,
+    ENV_STORAGE_ENGINE,
+    ENV_SHARDS,
+    ENV_TIERED_MEMORY_ENTRIES,
+    ENV_TIERED_DISK_DIR,
+    ENV_TIERED_DISK_BYTES,
+    ENV_MMAP_PATH,
+    ENV_MMAP_SYNC,
+    ENV_MAX_ENTRIES,
+    ENV_MAXMEMORY,
+    ENV_MAXMEMORY_POLICY,
+    ENV_SWEEP_INTERVAL_MS,
+    ENV_SWEEP_MAX_EXPIRED,
+    ENV_TTL_JITTER_PERCENT,
+    ENV_CONNECTION_BUFFER_LIMIT,
+    ENV_NAMESPACE_QUOTAS,
+    ENV_NAMESPACE_ENGINES,
+    ENV_WARMUP_SOURCE,
+    ENV_PRELOAD,
+    ENV_SNAPSHOT_PATH,
+    ENV_SNAPSHOT_INTERVAL_SECONDS,
+    ENV_APPENDONLY_PATH,
+    ENV_APPENDFSYNC,
+    ENV_AOF_REWRITE_PERCENTAGE,
+    ENV_AOF_REWRITE_MIN_SIZE,
+    ENV_EXPORT_DIR,
+    ENV_TOMBSTONE_WINDOW_SECONDS,
+    ENV_HISTORY_VERSIONS,
+    ENV_HISTORY_BYTES,
+    ENV_SLIDING_EXPIRATION,
+    ENV_TRACK_ACCESS,
+    ENV_DATABASES,
+    ENV_KEYSPACE_NOTIFICATIONS,
+    ENV_SCRIPT_TIME_LIMIT_MS,
+    ENV_MAX_KEY_BYTES,
+    ENV_MAX_VALUE_BYTES,
+    ENV_COMPRESSION_THRESHOLD,
+    ENV_SLOWLOG_THRESHOLD_US,
+    ENV_SLOWLOG_MAX_LEN,
+    ENV_AUDIT_LOG,
+    ENV_AUDIT_LOG_MAX_SIZE,
+    ENV_AUDIT_LOG_MAX_FILES,
+    ENV_WORKER_THREADS,
+    ENV_WORKER_QUEUE,
+    ENV_WORKER_REJECTION,
+    ENV_SHUTDOWN_TIMEOUT_SECONDS,
+    ENV_MAXCLIENTS,
+    ENV_IDLE_TIMEOUT_SECONDS,
+    ENV_COMMAND_TIMEOUT_MS,
+    ENV_CLIENT_OUTPUT_BYTES,
+    ENV_RATE_LIMIT,
+    ENV_RATE_LIMIT_BURST,
+    ENV_LOG_LEVEL,
+    ENV_CONFIG,
+];
+
+/// The prefix of the environment variables, which the command line flags go without.
+const ENV_PREFIX: &str = "MYRUSTCACHE_";
+
+/// The flags naming a setting other than after its environment variable.
+const FLAG_ALIASES: [(&str, &str); 2] = [
+    ("--max-connections", ENV_WORKER_THREADS),
+    ("--replicaof", ENV_REPLICA_OF),
+];
+
+/// The settings whose flag may go without a value, meaning `true` (e.g. `--read-only`).
+const SWITCHES: [&str; 4] = [
+    ENV_ACCEPT_REPLICAS,
+    ENV_READ_ONLY,
+    ENV_SLIDING_EXPIRATION,
+    ENV_TRACK_ACCESS,
+];
+
+/// The lower limit of the memory of the connection buffers: enough for a command of the maximum size.
+pub(crate) const MIN_CONNECTION_BUFFER_LIMIT: usize = 2 * 1024 * 1024;
+
+/// The upper limit of the shard count. More shards than this only waste memory.
+const MAX_SHARDS: usize = 4096;
+
+/// The upper limit of the number of databases, as their indexes are parsed from the keys of the cache.
+const MAX_DATABASES: usize = 1024;
+
+/// A single problem found in the server configuration.
+///
+/// Problems are collected rather than reported one at a time, so that an operator can fix all of them
+/// before the next start attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub field: &'static str, // The name of the offending configuration field (e.g. `port`)
+    pub message: String,     // A human readable description of what is wrong and how to fix it
+}
+
+impl ConfigError {
+    fn new(field: &'static str, message: impl Into<String>) -> ConfigError {
+        ConfigError {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// The settings the cache server is started with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub host: String, // The host (IP address or host name) the server listens on
+    pub port: u16,    // The TCP port the server listens on
+
+    /// The TCP port the server also listens on for clients speaking the Redis protocol (RESP). Zero, the
+    /// default, disables it.
+    pub resp_port: u16,
+
+    /// The TCP port the server also listens on for clients speaking the memcached text protocol. Zero, the
+    /// default, disables it.
+    pub memcached_port: u16,
+
+    /// The TCP port the server also listens on for HTTP clients (the REST gateway). Zero, the default,
+    /// disables it.
+    pub http_port: u16,
+
+    /// The TCP port the server also listens on for WebSocket clients (e.g. browsers). Zero, the default,
+    /// disables it.
+    pub websocket_port: u16,
+
+    /// The origins of the pages allowed to open a WebSocket connection. Any origin is allowed if empty.
+    pub websocket_origins: Vec<String>,
+
+    /// The token WebSocket clients must present, if any.
+    pub websocket_token: Option<String>,
+
+    /// The UDP port the server also listens on for single-datagram `GET` and `SET` commands. It may be the
+    /// same as a TCP port. Zero, the default, disables it.
+    pub udp_port: u16,
+
+    /// The TCP port the server also listens on for Prometheus to scrape its metrics (`GET /metrics`). Zero,
+    /// the default, disables it.
+    pub metrics_port: u16,
+
+    /// The path of a Unix domain socket the server also serves the text protocol on, for the clients of the
+    /// same host. With a socket, `port` may be zero to serve the text protocol on the socket only.
+    pub unix_socket: Option<PathBuf>,
+
+    /// The address (`host:port`) of the primary the cache replicates, if any: a Redis server, or the RESP
+    /// port of another node accepting replicas. The cache is then read-only for the clients.
+    pub replica_of: Option<String>,
+
+    /// The password the replica authenticates to the primary with, if it requires one.
+    pub replica_auth: Option<String>,
+
+    /// Whether other nodes may attach to the cache as replicas, with `SYNC` or `PSYNC` on the RESP port, and
+    /// be streamed its writes (see `ReplicationFeed`). Off by default.
+    pub accept_replicas: bool,
+
+    /// Whether the writes of the clients are rejected (with `read_only` errors, or `READONLY` over RESP),
+    /// while the reads go on, e.g. to expose a node to the services which should only read it. A replica is
+    /// always read-only. Off by default, and changed at runtime with `CONFIG SET read_only`.
+    pub read_only: bool,
+
+    /// Which node of the cluster owns each hash slot, as set with `host:port=0-8191;host:port=8192-16383`
+    /// (see `SlotMap`), the addresses being the RESP ports of the nodes. The RESP clients are then redirected
+    /// with `MOVED` to the node owning their keys, and the text clients answered with `moved` errors naming
+    /// it. The other listeners cannot be enabled in a cluster. None, the default, serves every key.
+    pub cluster_nodes: Option<SlotMap>,
+
+    /// The address of this node among the `cluster_nodes`.
+    pub cluster_address: Option<String>,
+
+    /// The password the clients must present with `AUTH` before any other command, if any.
+    pub requirepass: Option<String>,
+
+    /// The users the clients may also authenticate as with `AUTH <user> <password>`, with restricted
+    /// permissions, as set with `reader:password=secret,commands=GET,keys=app:*;writer:...` (see `AclUser`).
+    pub acl_users: Vec<(String, AclUser)>,
+
+    /// The addresses the clients may connect from, as IP ranges in CIDR notation separated by commas (e.g.
+    /// `10.0.0.0/8,192.168.1.7`). Empty by default, allowing any address not denied.
+    pub allow_ips: Vec<IpRange>,
+
+    /// The addresses the clients may not connect from, even within `allow_ips`, in the same notation. Empty
+    /// by default.
+    pub deny_ips: Vec<IpRange>,
+
+    /// The name of the storage engine holding the cached data (one of `cache::BUILTIN_ENGINES`).
+    pub storage_engine: String,
+
+    /// The namespaces whose keys are held by a storage engine of their own rather than by `storage_engine`,
+    /// as set with `archive:mmap;sessions:lru` (see `RoutedCache`). None by default.
+    pub namespace_engines: Vec<(String, String)>,
+
+    /// The number of shards of the `sharded` and `leftright` storage engines. Defaults to the number of CPUs.
+    pub shards: usize,
+
+    /// The maximum number of entries the `tiered` storage engine keeps in memory before spilling to disk.
+    pub tiered_memory_entries: usize,
+
+    /// The existing directory in which the `tiered` storage engine creates its disk tier.
+    pub tiered_disk_dir: PathBuf,
+
+    /// The most bytes the values spilled to disk by the `tiered` storage engine may take, the values spilled
+    /// first being evicted beyond. Zero, the default, sets no limit.
+    pub tiered_disk_bytes: usize,
+
+    /// The log file of the `mmap` storage engine. Its directory must exist.
+    pub mmap_path: PathBuf,
+
+    /// When the `mmap` storage engine makes its writes durable: `never` (left to the OS) or `always` (before
+    /// acknowledging them, sharing the syncs between concurrent writes).
+    pub mmap_sync: SyncPolicy,
+
+    /// The maximum number of entries of the `lru` and `lfu` storage engines, beyond which entries are
+    /// evicted (the least recently and the least frequently used ones respectively).
+    pub max_entries: usize,
+
+    /// The most bytes the entries of the `lru` and `lfu` storage engines may take, counting their keys, their
+    /// values and the bookkeeping of the engine. Zero means no limit.
+    pub maxmemory: usize,
+
+    /// What the `lru` and `lfu` storage engines do with the writes beyond `maxmemory`: `evict` entries, or
+    /// reject the writes (`noeviction`).
+    pub maxmemory_policy: MaxMemoryPolicy,
+
+    /// The longest delay (in milliseconds) between two sweeps deleting the expired entries in the background.
+    /// Sweeps run more often while they find many expired entries. Zero disables the background sweeps.
+    pub sweep_interval_ms: u64,
+
+    /// The most expired entries a background sweep deletes, which bounds how long it holds the locks of the
+    /// cache. The sweeps run more often while they reach it.
+    pub sweep_max_expired: usize,
+
+    /// How much (in percent, either way) the TTLs are spread randomly, so that entries written together do
+    /// not all expire together. Zero keeps the TTLs exact. Commands can override it (`JITTER`).
+    pub ttl_jitter_percent: u32,
+
+    /// The largest number of bytes the buffers of all the client connections may take. A client whose
+    /// command cannot be buffered within this limit is disconnected.
+    pub connection_buffer_limit: usize,
+
+    /// The limits of the namespaces which have one, as set with
+    /// `sessions:entries=10000,policy=evict;users:bytes=1048576` (see `NamespaceQuota`).
+    pub namespace_quotas: Vec<(String, NamespaceQuota)>,
+
+    /// Where the data is loaded from before the server starts listening (see `WarmupSource`): a dump file, an
+    /// `http://` URL of a dump, or `peer:host:port` for the HTTP listener of another node. None by default.
+    pub warmup_source: Option<WarmupSource>,
+
+    /// A seed file of key/value/TTL triples (JSON Lines, or CSV with a `.csv` extension) loaded before the
+    /// server starts listening, filling the keys not restored otherwise. None by default.
+    pub preload: Option<PathBuf>,
+
+    /// The file the snapshots of the cache are saved to (on `SAVE`, `BGSAVE`, every
+    /// `snapshot_interval_seconds` and at shutdown) and loaded from at startup. None, the default, disables
+    /// the snapshots.
+    pub snapshot_path: Option<PathBuf>,
+
+    /// How often (in seconds) a snapshot is saved in the background. Zero, the default, only saves them on
+    /// `SAVE`, `BGSAVE` and at shutdown.
+    pub snapshot_interval_seconds: u64,
+
+    /// The append-only log every write is recorded to and which is replayed at startup (see
+    /// `AppendOnlyLog`), taking precedence over the snapshot unless empty. None, the default, disables it.
+    pub appendonly_path: Option<PathBuf>,
+
+    /// When the records of the append-only log are made durable: `always` (before the write is applied),
+    /// `everysec` (once a second in the background, the default) or `no` (left to the OS).
+    pub appendfsync: FsyncPolicy,
+
+    /// How much the append-only log grows (in percent of its size once last rewritten, 100 by default)
+    /// before it is rewritten in the background, as with `BGREWRITEAOF`. Zero only rewrites it on demand.
+    pub aof_rewrite_percentage: u32,
+
+    /// The size (in bytes) below which the append-only log is not rewritten automatically, 64 MiB by
+    /// default.
+    pub aof_rewrite_min_size: u64,
+
+    /// The directory whose files `EXPORT` and `IMPORT` write and read the datasets of the cache to and from,
+    /// as JSON Lines or CBOR (see `DatasetFormat`). None, the default, disables both commands.
+    pub export_dir: Option<PathBuf>,
+
+    /// How long (in seconds) the deleted values are kept as tombstones, which `UNDELETE` restores, before
+    /// being purged in the background. Zero, the default, deletes the values right away.
+    pub tombstone_window_seconds: u64,
+
+    /// How many versions of each key (values written and removals) are kept for `HISTORY` and `GETVERSION`.
+    /// Zero, the default, keeps no history.
+    pub history_versions: usize,
+
+    /// The most bytes of keys and values the versions of all the keys take, the oldest versions being
+    /// dropped first.
+    pub history_bytes: usize,
+
+    /// Whether reading a value restarts its TTL (sliding expiration), e.g. to keep the sessions in use alive.
+    /// Off by default, where a value expires once its TTL is over however often it is read.
+    pub sliding_expiration: bool,
+
+    /// Whether the server tracks when each key was last read and how often (see `OBJECT IDLETIME` and
+    /// `OBJECT FREQ`), e.g. to find the keys which are written but never read. Off by default, as it takes a
+    /// lock and some memory per key.
+    pub track_access: bool,
+
+    /// The number of logical databases the clients can switch between with `SELECT <index>`, each with its
+    /// own keys. 1, the default, keeps a single database (and `SELECT` for the namespaces only).
+    pub databases: usize,
+
+    /// The kinds of keyspace events (`set`, `del`, `expired` and `evicted`, separated by `,`, or `all`)
+    /// published on the `__keyspace@<db>__:<key>` and `__keyevent@<db>__:<event>` channels. None by default.
+    pub keyspace_notifications: KeyspaceNotifications,
+
+    /// How long a script run by `EVAL` may run, in milliseconds, before it is stopped with an error. The
+    /// commands of the other clients wait for the script meanwhile. 5000 by default.
+    pub script_time_limit_ms: u64,
+
+    /// The longest key the clients may write, in bytes, the longer ones being rejected with an error. Zero,
+    /// the default, sets no limit.
+    pub max_key_bytes: usize,
+
+    /// The longest value the clients may write, in bytes (a collection as a whole), the longer ones being
+    /// rejected with an error. Zero, the default, sets no limit but the 1 MiB of a command.
+    pub max_value_bytes: usize,
+
+    /// The size from which the values are compressed with LZ4, in bytes, if it saves space (see
+    /// `CompressingCache`). Zero, the default, compresses no value. The values already compressed in a
+    /// persistent storage engine can only be read with a threshold set.
+    pub compression_threshold: usize,
+
+    /// How long a command runs before it is kept in the slow log (see `SLOWLOG`), in microseconds. Zero logs
+    /// every command. 10000 by default.
+    pub slowlog_threshold_us: u64,
+
+    /// The number of slow commands kept, the oldest being dropped first. Zero disables the slow log. 128 by
+    /// default.
+    pub slowlog_max_len: usize,
+
+    /// Where the write commands of the clients are recorded as lines of JSON (see `AuditLog`): `stdout`, or
+    /// the path of a file. None, the default, disables the audit log.
+    pub audit_log: Option<AuditSink>,
+
+    /// The size an audit log file grows to before it is rotated, in bytes. 100 MiB by default.
+    pub audit_log_max_size: u64,
+
+    /// The number of rotated audit log files kept besides the current one, the oldest being removed first.
+    /// 5 by default.
+    pub audit_log_max_files: usize,
+
+    /// The number of threads serving the client connections of all the listeners, each connection taking a
+    /// thread until it is closed.
+    pub worker_threads: usize,
+
+    /// The number of connections accepted while every worker thread is busy which may wait for one.
+    pub worker_queue: usize,
+
+    /// What happens to the connections accepted beyond the queue: `close` (closed right away, the default)
+    /// or `wait` (left in the backlog of the listener until the queue has room).
+    pub worker_rejection: RejectionPolicy,
+
+    /// How long (in seconds) the open connections are given to answer the requests in flight when the
+    /// server shuts down (on `SIGTERM` or `SIGINT`), before being dropped.
+    pub shutdown_timeout_seconds: u64,
+
+    /// The most clients served at once across all the listeners, beyond which new connections are answered
+    /// with an error and closed. Zero, the default, sets no limit (but `worker_threads` and `worker_queue`).
+    pub maxclients: usize,
+
+    /// How long (in seconds) a client may send nothing before its connection is closed. Zero, the default,
+    /// keeps idle connections open.
+    pub idle_timeout_seconds: u64,
+
+    /// How long (in milliseconds) the commands a client sends at once may take, not counting the time
+    /// blocked waiting for other clients (e.g. by `BLPOP`), before it is answered with an error and
+    /// disconnected. It also bounds how long a client may leave its responses unread. Zero, the default,
+    /// sets no limit.
+    pub command_timeout_ms: u64,
+
+    /// How many bytes of responses a client may leave unread before the server stops writing to it, and
+    /// disconnects it unless it reads them within `command_timeout_ms` (10 seconds if unlimited). Zero,
+    /// the default, leaves the buffering to the OS.
+    pub client_output_bytes: usize,
+
+    /// How many commands per second each connection of the text protocol may send, the commands beyond
+    /// being answered with an error. Zero, the default, sets no limit.
+    pub rate_limit: u32,
+
+    /// How many commands a connection may send at once above `rate_limit` after a pause. Zero, the default,
+    /// allows bursts of `rate_limit` commands.
+    pub rate_limit_burst: u32,
+
+    /// The log filter, as with `RUST_LOG` (e.g. `info`, or `myrustcache=debug`), which it overrides. None by
+    /// default, leaving the filter to `RUST_LOG` (errors only if it is not set either).
+    pub log_level: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            host: DEFAULT_HOST.to_string(),
+            port: DEFAULT_PORT,
+            resp_port: 0,
+            memcached_port: 0,
+            http_port: 0,
+            websocket_port: 0,
+            websocket_origins: Vec::new(),
+            websocket_token: None,
+            udp_port: 0,
+            metrics_port: 0,
+            unix_socket: None,
+            replica_of: None,
+            replica_auth: None,
+            accept_replicas: false,
+            read_only: false,
+            cluster_nodes: None,
+            cluster_address: None,
+            requirepass: None,
+            acl_users: Vec::new(),
+            allow_ips: Vec::new(),
+            deny_ips: Vec::new(),
+            storage_engine: DEFAULT_ENGINE.to_string(),
+            namespace_engines: Vec::new(),
+            shards: EngineOptions::default().shards,
+            tiered_memory_entries: EngineOptions::default().tiered_memory_entries,
+            tiered_disk_dir: EngineOptions::default().tiered_disk_dir,
+            tiered_disk_bytes: 0,
+            mmap_path: EngineOptions::default().mmap_path,
+            mmap_sync: EngineOptions::default().mmap_sync,
+            max_entries: EngineOptions::default().max_entries,
+            maxmemory: 0,
+            maxmemory_policy: MaxMemoryPolicy::default(),
+            sweep_interval_ms: SweeperOptions::default().max_interval.as_millis() as u64,
+            sweep_max_expired: SweeperOptions::default().max_expired,
+            ttl_jitter_percent: 0,
+            connection_buffer_limit: DEFAULT_BUFFER_LIMIT,
+            namespace_quotas: Vec::new(),
+            warmup_source: None,
+            preload: None,
+            snapshot_path: None,
+            snapshot_interval_seconds: 0,
+            appendonly_path: None,
+            appendfsync: FsyncPolicy::default(),
+            aof_rewrite_percentage: 100,
+            aof_rewrite_min_size: 64 * 1024 * 1024,
+            export_dir: None,
+            tombstone_window_seconds: 0,
+            history_versions: 0,
+            history_bytes: DEFAULT_HISTORY_BYTES,
+            sliding_expiration: false,
+            track_access: false,
+            databases: 1,
+            keyspace_notifications: KeyspaceNotifications::NONE,
+            script_time_limit_ms: DEFAULT_SCRIPT_TIME_LIMIT_MS,
+            max_key_bytes: 0,
+            max_value_bytes: 0,
+            compression_threshold: 0,
+            slowlog_threshold_us: DEFAULT_SLOWLOG_THRESHOLD_US,
+            slowlog_max_len: DEFAULT_SLOWLOG_MAX_LEN,
+            audit_log: None,
+            audit_log_max_size: DEFAULT_AUDIT_LOG_MAX_SIZE,
+            audit_log_max_files: DEFAULT_AUDIT_LOG_MAX_FILES,
+            worker_threads: WorkerOptions::default().threads,
+            worker_queue: WorkerOptions::default().queue_depth,
+            worker_rejection: WorkerOptions::default().rejection,
+            shutdown_timeout_seconds: DEFAULT_SHUTDOWN_TIMEOUT.as_secs(),
+            maxclients: 0,
+            idle_timeout_seconds: 0,
+            command_timeout_ms: 0,
+            client_output_bytes: 0,
+            rate_limit: 0,
+            rate_limit_burst: 0,
+            log_level: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Build the configuration from the defaults overridden by the `MYRUSTCACHE_*` environment variables,
+    /// then validate it.
+    ///
+    /// # Returns
+    /// * The validated configuration, or every problem found while reading and validating it.
+    pub fn from_env() -> Result<ServerConfig, Vec<ConfigError>> {
+        ServerConfig::from_env_and_args(Vec::new())
+    }
+
+    /// Build the configuration from the defaults overridden by the configuration file, if any, then by the
+    /// `MYRUSTCACHE_*` environment variables, then by the given command line flags, and validate it.
+    ///
+    /// Every environment variable has a flag named after it, e.g. `--port 6060` for `MYRUSTCACHE_PORT=6060`
+    /// or `--worker-threads 64` for `MYRUSTCACHE_WORKER_THREADS=64` (`--max-connections` being an alias).
+    /// The configuration file is given with `--config` (or `MYRUSTCACHE_CONFIG`). It is written in TOML, each
+    /// setting being named after its variable, at the top level or in a table of its prefix:
+    ///
+    /// ```toml
+    /// host = "0.0.0.0"
+    /// storage_engine = "mmap"
+    /// websocket_origins = ["https://app.example.com"]
+    ///
+    /// [worker]
+    /// threads = 64 # Or worker_threads = 64 at the top level
+    /// ```
+    ///
+    /// Only the subset of TOML the settings need is supported: strings, integers, booleans and single-line
+    /// arrays (joined with commas, like the lists of the environment variables).
+    ///
+    /// # Returns
+    /// * The validated configuration, or every problem found while reading and validating it, including
+    ///   the unknown flags and the flags without a value.
+    pub fn from_env_and_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<ServerConfig, Vec<ConfigError>> {
+        let mut config: ServerConfig = ServerConfig::default();
+        let mut problems: Vec<ConfigError> = Vec::new();
+        let mut settings: Settings = Settings::from_args(args, &mut problems);
+        settings.load_file(&mut problems);
+
+        if let Some(host) = settings.get(ENV_HOST) {
+            config.host = host;
+        }
+
+        if let Some(port) = settings.get(ENV_PORT) {
+            match port.trim() {
+                "0" => config.port = 0, // Only valid along with a Unix domain socket (see `validate`)
+                port => match parse_port(port) {
+                    Ok(port) => config.port = port,
+                    Err(message) => problems.push(ConfigError::new("port", message)),
+                },
+            }
+        }
+
+        if let Some(port) = settings.get(ENV_RESP_PORT) {
+            match port.trim() {
+                "0" => config.resp_port = 0,
+                port => match parse_port(port) {
+                    Ok(port) => config.resp_port = port,
+                    Err(message) => problems.push(ConfigError::new("resp_port", message)),
+                },
+            }
+        }
+
+        if let Some(port) = settings.get(ENV_MEMCACHED_PORT) {
+            match port.trim() {
+                "0" => config.memcached_port = 0,
+                port => match parse_port(port) {
+                    Ok(port) => config.memcached_port = port,
+                    Err(message) => problems.push(ConfigError::new("memcached_port", message)),
+                },
+            }
+        }
+
+        if let Some(port) = settings.get(ENV_HTTP_PORT) {
+            match port.trim() {
+                "0" => config.http_port = 0,
+                port => match parse_port(port) {
+                    Ok(port) => config.http_port = port,
+                    Err(message) => problems.push(ConfigError::new("http_port", message)),
+                },
+            }
+        }
+
+        if let Some(port) = settings.get(ENV_WEBSOCKET_PORT) {
+            match port.trim() {
+                "0" => config.websocket_port = 0,
+                port => match parse_port(port) {
+                    Ok(port) => config.websocket_port = port,
+                    Err(message) => problems.push(ConfigError::new("websocket_port", message)),
+                },
+            }
+        }
+
+        if let Some(origins) = settings.get(ENV_WEBSOCKET_ORIGINS) {
+            config.websocket_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        if let Some(token) = settings.get(ENV_WEBSOCKET_TOKEN) {
+            config.websocket_token = Some(token).filter(|token| !token.is_empty());
+        }
+
+        if let Some(path) = settings.get(ENV_UNIXSOCKET) {
+            config.unix_socket =
+                Some(PathBuf::from(path.trim())).filter(|p| !p.as_os_str().is_empty());
+        }
+
+        if let Some(port) = settings.get(ENV_UDP_PORT) {
+            match port.trim() {
+                "0" => config.udp_port = 0,
+                port => match parse_port(port) {
+                    Ok(port) => config.udp_port = port,
+                    Err(message) => problems.push(ConfigError::new("udp_port", message)),
+                },
+            }
+        }
+
+        if let Some(port) = settings.get(ENV_METRICS_PORT) {
+            match port.trim() {
+                "0" => config.metrics_port = 0,
+                port => match parse_port(port) {
+                    Ok(port) => config.metrics_port = port,
+                    Err(message) => problems.push(ConfigError::new("metrics_port", message)),
+                },
+            }
+        }
+
+        if let Some(address) = settings.get(ENV_REPLICA_OF) {
+            config.replica_of = Some(address.trim().to_string()).filter(|a| !a.is_empty());
+        }
+
+        if let Some(password) = settings.get(ENV_REPLICA_AUTH) {
+            config.replica_auth = Some(password).filter(|password| !password.is_empty());
+        }
+
+        if let Some(accept) = env_parsed(
+            &settings,
+            ENV_ACCEPT_REPLICAS,
+            "accept_replicas",
+            &mut problems,
+        ) {
+            config.accept_replicas = accept;
+        }
+
+        if let Some(read_only) = env_parsed(&settings, ENV_READ_ONLY, "read_only", &mut problems) {
+            config.read_only = read_only;
+        }
+
+        if let Some(nodes) = settings
+            .get(ENV_CLUSTER_NODES)
+            .filter(|n| !n.trim().is_empty())
+        {
+            match nodes.parse::<SlotMap>() {
+                Ok(slots) => config.cluster_nodes = Some(slots),
+                Err(message) => problems.push(ConfigError::new("cluster_nodes", message)),
+            }
+        }
+
+        if let Some(address) = settings.get(ENV_CLUSTER_ADDRESS) {
+            config.cluster_address = Some(address.trim().to_string()).filter(|a| !a.is_empty());
+        }
+
+        if let Some(password) = settings.get(ENV_REQUIREPASS) {
+            config.requirepass = Some(password).filter(|password| !password.is_empty());
+        }
+
+        if let Some(users) = settings.get(ENV_ACL_USERS) {
+            match parse_acl_users(&users) {
+                Ok(users) => config.acl_users = users,
+                Err(message) => problems.push(ConfigError::new("acl_users", message)),
+            }
+        }
+
+        if let Some(ranges) = settings.get(ENV_ALLOW_IPS) {
+            match parse_ip_ranges(&ranges) {
+                Ok(ranges) => config.allow_ips = ranges,
+                Err(message) => problems.push(ConfigError::new("allow_ips", message)),
+            }
+        }
+
+        if let Some(ranges) = settings.get(ENV_DENY_IPS) {
+            match parse_ip_ranges(&ranges) {
+                Ok(ranges) => config.deny_ips = ranges,
+                Err(message) => problems.push(ConfigError::new("deny_ips", message)),
+            }
+        }
+
+        if let Some(engine) = settings.get(ENV_STORAGE_ENGINE) {
+            config.storage_engine = engine;
+        }
+
+        if let Some(engines) = settings.get(ENV_NAMESPACE_ENGINES) {
+            match parse_namespace_engines(&engines) {
+                Ok(engines) => config.namespace_engines = engines,
+                Err(message) => problems.push(ConfigError::new("namespace_engines", message)),
+            }
+        }
+
+        if let Some(shards) = env_parsed(&settings, ENV_SHARDS, "shards", &mut problems) {
+            config.shards = shards;
+        }
+
+        if let Some(entries) = env_parsed(
+            &settings,
+            ENV_TIERED_MEMORY_ENTRIES,
+            "tiered_memory_entries",
+            &mut problems,
+        ) {
+            config.tiered_memory_entries = entries;
+        }
+
+        if let Some(dir) = settings.get(ENV_TIERED_DISK_DIR) {
+            config.tiered_disk_dir = PathBuf::from(dir);
+        }
+
+        if let Some(max) = env_parsed(
+            &settings,
+            ENV_TIERED_DISK_BYTES,
+            "tiered_disk_bytes",
+            &mut problems,
+        ) {
+            config.tiered_disk_bytes = max;
+        }
+
+        if let Some(path) = settings.get(ENV_MMAP_PATH) {
+            config.mmap_path = PathBuf::from(path);
+        }
+
+        if let Some(sync) = env_parsed(&settings, ENV_MMAP_SYNC, "mmap_sync", &mut problems) {
+            config.mmap_sync = sync;
+        }
+
+        if let Some(entries) = env_parsed(&settings, ENV_MAX_ENTRIES, "max_entries", &mut problems)
+        {
+            config.max_entries = entries;
+        }
+
+        if let Some(maxmemory) = env_parsed(&settings, ENV_MAXMEMORY, "maxmemory", &mut problems) {
+            config.maxmemory = maxmemory;
+        }
+
+        if let Some(policy) = env_parsed(
+            &settings,
+            ENV_MAXMEMORY_POLICY,
+            "maxmemory_policy",
+            &mut problems,
+        ) {
+            config.maxmemory_policy = policy;
+        }
+
+        if let Some(interval) = env_parsed(
+            &settings,
+            ENV_SWEEP_INTERVAL_MS,
+            "sweep_interval_ms",
+            &mut problems,
+        ) {
+            config.sweep_interval_ms = interval;
+        }
+
+        if let Some(max_expired) = env_parsed(
+            &settings,
+            ENV_SWEEP_MAX_EXPIRED,
+            "sweep_max_expired",
+            &mut problems,
+        ) {
+            config.sweep_max_expired = max_expired;
+        }
+
+        if let Some(percent) = env_parsed(
+            &settings,
+            ENV_TTL_JITTER_PERCENT,
+            "ttl_jitter_percent",
+            &mut problems,
+        ) {
+            config.ttl_jitter_percent = percent;
+        }
+
+        if let Some(limit) = env_parsed(
+            &settings,
+            ENV_CONNECTION_BUFFER_LIMIT,
+            "connection_buffer_limit",
+            &mut problems,
+        ) {
+            config.connection_buffer_limit = limit;
+        }
+
+        if let Some(quotas) = settings.get(ENV_NAMESPACE_QUOTAS) {
+            match parse_namespace_quotas(&quotas) {
+                Ok(quotas) => config.namespace_quotas = quotas,
+                Err(message) => problems.push(ConfigError::new("namespace_quotas", message)),
+            }
+        }
+
+        if let Some(source) = settings.get(ENV_WARMUP_SOURCE) {
+            match source.parse::<WarmupSource>() {
+                Ok(source) => config.warmup_source = Some(source),
+                Err(message) => problems.push(ConfigError::new("warmup_source", message)),
+            }
+        }
+
+        if let Some(path) = settings.get(ENV_PRELOAD) {
+            config.preload = Some(PathBuf::from(path.trim())).filter(|p| !p.as_os_str().is_empty());
+        }
+
+        if let Some(path) = settings.get(ENV_SNAPSHOT_PATH) {
+            config.snapshot_path =
+                Some(PathBuf::from(path.trim())).filter(|p| !p.as_os_str().is_empty());
+        }
+
+        if let Some(interval) = env_parsed(
+            &settings,
+            ENV_SNAPSHOT_INTERVAL_SECONDS,
+            "snapshot_interval_seconds",
+            &mut problems,
+        ) {
+            config.snapshot_interval_seconds = interval;
+        }
+
+        if let Some(path) = settings.get(ENV_APPENDONLY_PATH) {
+            config.appendonly_path =
+                Some(PathBuf::from(path.trim())).filter(|p| !p.as_os_str().is_empty());
+        }
+
+        if let Some(fsync) = env_parsed(&settings, ENV_APPENDFSYNC, "appendfsync", &mut problems) {
+            config.appendfsync = fsync;
+        }
+
+        if let Some(percentage) = env_parsed(
+            &settings,
+            ENV_AOF_REWRITE_PERCENTAGE,
+            "aof_rewrite_percentage",
+            &mut problems,
+        ) {
+            config.aof_rewrite_percentage = percentage;
+        }
+
+        if let Some(size) = env_parsed(
+            &settings,
+            ENV_AOF_REWRITE_MIN_SIZE,
+            "aof_rewrite_min_size",
+            &mut problems,
+        ) {
+            config.aof_rewrite_min_size = size;
+        }
+
+        if let Some(path) = settings.get(ENV_EXPORT_DIR) {
+            config.export_dir =
+                Some(PathBuf::from(path.trim())).filter(|p| !p.as_os_str().is_empty());
+        }
+
+        if let Some(window) = env_parsed(
+            &settings,
+            ENV_TOMBSTONE_WINDOW_SECONDS,
+            "tombstone_window_seconds",
+            &mut problems,
+        ) {
+            config.tombstone_window_seconds = window;
+        }
+
+        if let Some(versions) = env_parsed(
+            &settings,
+            ENV_HISTORY_VERSIONS,
+            "history_versions",
+            &mut problems,
+        ) {
+            config.history_versions = versions;
+        }
+
+        if let Some(level) = settings.get(ENV_LOG_LEVEL) {
+            config.log_level = Some(level.trim().to_string()).filter(|level| !level.is_empty());
+        }
+
+        if let Some(bytes) =
+            env_parsed(&settings, ENV_HISTORY_BYTES, "history_bytes", &mut problems)
+        {
+            config.history_bytes = bytes;
+        }
+
+        if let Some(sliding) = env_parsed(
+            &settings,
+            ENV_SLIDING_EXPIRATION,
+            "sliding_expiration",
+            &mut problems,
+        ) {
+            config.sliding_expiration = sliding;
+        }
+
+        if let Some(track) = env_parsed(&settings, ENV_TRACK_ACCESS, "track_access", &mut problems)
+        {
+            config.track_access = track;
+        }
+
+        if let Some(databases) = env_parsed(&settings, ENV_DATABASES, "databases", &mut problems) {
+            config.databases = databases;
+        }
+
+        if let Some(notifications) = settings.get(ENV_KEYSPACE_NOTIFICATIONS) {
+            match notifications.parse::<KeyspaceNotifications>() {
+                Ok(notifications) => config.keyspace_notifications = notifications,
+                Err(message) => problems.push(ConfigError::new("keyspace_notifications", message)),
+            }
+        }
+
+        if let Some(limit) = env_parsed(
+            &settings,
+            ENV_SCRIPT_TIME_LIMIT_MS,
+            "script_time_limit_ms",
+            &mut problems,
+        ) {
+            config.script_time_limit_ms = limit;
+        }
+
+        if let Some(max) = env_parsed(&settings, ENV_MAX_KEY_BYTES, "max_key_bytes", &mut problems)
+        {
+            config.max_key_bytes = max;
+        }
+
+        if let Some(max) = env_parsed(
+            &settings,
+            ENV_MAX_VALUE_BYTES,
+            "max_value_bytes",
+            &mut problems,
+        ) {
+            config.max_value_bytes = max;
+        }
+
+        if let Some(threshold) = env_parsed(
+            &settings,
+            ENV_COMPRESSION_THRESHOLD,
+            "compression_threshold",
+            &mut problems,
+        ) {
+            config.compression_threshold = threshold;
+        }
+
+        if let Some(threshold) = env_parsed(
+            &settings,
+            ENV_SLOWLOG_THRESHOLD_US,
+            "slowlog_threshold_us",
+            &mut problems,
+        ) {
+            config.slowlog_threshold_us = threshold;
+        }
+
+        if let Some(max_len) = env_parsed(
+            &settings,
+            ENV_SLOWLOG_MAX_LEN,
+            "slowlog_max_len",
+            &mut problems,
+        ) {
+            config.slowlog_max_len = max_len;
+        }
+
+        if let Some(sink) = settings.get(ENV_AUDIT_LOG) {
+            config.audit_log = sink.trim().parse::<AuditSink>().ok();
+        }
+
+        if let Some(size) = env_parsed(
+            &settings,
+            ENV_AUDIT_LOG_MAX_SIZE,
+            "audit_log_max_size",
+            &mut problems,
+        ) {
+            config.audit_log_max_size = size;
+        }
+
+        if let Some(files) = env_parsed(
+            &settings,
+            ENV_AUDIT_LOG_MAX_FILES,
+            "audit_log_max_files",
+            &mut problems,
+        ) {
+            config.audit_log_max_files = files;
+        }
+
+        if let Some(threads) = env_parsed(
+            &settings,
+            ENV_WORKER_THREADS,
+            "worker_threads",
+            &mut problems,
+        ) {
+            config.worker_threads = threads;
+        }
+
+        if let Some(queue) = env_parsed(&settings, ENV_WORKER_QUEUE, "worker_queue", &mut problems)
+        {
+            config.worker_queue = queue;
+        }
+
+        if let Some(rejection) = env_parsed(
+            &settings,
+            ENV_WORKER_REJECTION,
+            "worker_rejection",
+            &mut problems,
+        ) {
+            config.worker_rejection = rejection;
+        }
+
+        if let Some(timeout) = env_parsed(
+            &settings,
+            ENV_SHUTDOWN_TIMEOUT_SECONDS,
+            "shutdown_timeout_seconds",
+            &mut problems,
+        ) {
+            config.shutdown_timeout_seconds = timeout;
+        }
+
+        if let Some(max_clients) =
+            env_parsed(&settings, ENV_MAXCLIENTS, "maxclients", &mut problems)
+        {
+            config.maxclients = max_clients;
+        }
+
+        if let Some(timeout) = env_parsed(
+            &settings,
+            ENV_IDLE_TIMEOUT_SECONDS,
+            "idle_timeout_seconds",
+            &mut problems,
+        ) {
+            config.idle_timeout_seconds = timeout;
+        }
+
+        if let Some(timeout) = env_parsed(
+            &settings,
+            ENV_COMMAND_TIMEOUT_MS,
+            "command_timeout_ms",
+            &mut problems,
+        ) {
+            config.command_timeout_ms = timeout;
+        }
+
+        if let Some(bytes) = env_parsed(
+            &settings,
+            ENV_CLIENT_OUTPUT_BYTES,
+            "client_output_bytes",
+            &mut problems,
+        ) {
+            config.client_output_bytes = bytes;
+        }
+
+        if let Some(rate) = env_parsed(&settings, ENV_RATE_LIMIT, "rate_limit", &mut problems) {
+            config.rate_limit = rate;
+        }
+
+        if let Some(burst) = env_parsed(
+            &settings,
+            ENV_RATE_LIMIT_BURST,
+            "rate_limit_burst",
+            &mut problems,
+        ) {
+            config.rate_limit_burst = burst;
+        }
+
+        problems.extend(config.validate());
+
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Check the configuration as a whole and return all problems found (empty if it is valid).
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut problems: Vec<ConfigError> = Vec::new();
+
+        if self.port == 0 && self.unix_socket.is_none() {
+            problems.push(ConfigError::new(
+                "port",
+                "must be between 1 and 65535 (0 is only valid with a unix_socket)",
+            ));
+        }
+
+        if self.resp_port != 0 && self.resp_port == self.port {
+            problems.push(ConfigError::new(
+                "resp_port",
+                "must be different from the port of the text protocol",
+            ));
+        }
+
+        if self.memcached_port != 0
+            && (self.memcached_port == self.port || self.memcached_port == self.resp_port)
+        {
+            problems.push(ConfigError::new(
+                "memcached_port",
+                "must be different from the ports of the text protocol and RESP",
+            ));
+        }
+
+        if self.http_port != 0
+            && [self.port, self.resp_port, self.memcached_port].contains(&self.http_port)
+        {
+            problems.push(ConfigError::new(
+                "http_port",
+                "must be different from the ports of the other protocols",
+            ));
+        }
+
+        if self.websocket_port != 0
+            && [
+                self.port,
+                self.resp_port,
+                self.memcached_port,
+                self.http_port,
+            ]
+            .contains(&self.websocket_port)
+        {
+            problems.push(ConfigError::new(
+                "websocket_port",
+                "must be different from the ports of the other protocols",
+            ));
+        }
+
+        if self.metrics_port != 0
+            && [
+                self.port,
+                self.resp_port,
+                self.memcached_port,
+                self.http_port,
+                self.websocket_port,
+            ]
+            .contains(&self.metrics_port)
+        {
+            problems.push(ConfigError::new(
+                "metrics_port",
+                "must be different from the ports of the other protocols",
+            ));
+        }
+
+        if self.host.trim().is_empty() {
+            problems.push(ConfigError::new("host", "must not be empty"));
+        } else if (self.host.as_str(), self.port).to_socket_addrs().is_err() {
+            problems.push(ConfigError::new(
+                "host",
+                format!(
+                    "'{}' is neither an IP address nor a resolvable host name",
+                    self.host
+                ),
+            ));
+        }
+
+        if let Some(address) = &self.replica_of {
+            let port: Option<u16> = address
+                .rsplit_once(':')
+                .filter(|(host, _)| !host.is_empty())
+                .and_then(|(_, port)| parse_port(port).ok());
+            if port.is_none() {
+                problems.push(ConfigError::new(
+                    "replica_of",
+                    format!("'{}' is not a host:port address", address),
+                ));
+            }
+        }
+
+        if self.accept_replicas && self.resp_port == 0 {
+            problems.push(ConfigError::new(
+                "accept_replicas",
+                "requires a resp_port, which the replicas attach to",
+            ));
+        }
+
+        match (&self.cluster_nodes, &self.cluster_address) {
+            (Some(slots), Some(address)) => {
+                if !slots.nodes().contains(address) {
+                    problems.push(ConfigError::new(
+                        "cluster_address",
+                        format!("{} is not one of the cluster_nodes", address),
+                    ));
+                }
+                if self.resp_port == 0 {
+                    problems.push(ConfigError::new(
+                        "cluster_nodes",
+                        "requires a resp_port, which the clients are redirected between",
+                    ));
+                }
+                if self.databases > 1 {
+                    problems.push(ConfigError::new(
+                        "cluster_nodes",
+                        "cannot be used with several databases, as a cluster only has database 0",
+                    ));
+                }
+                let unredirected: [(&str, u16); 4] = [
+                    ("memcached_port", self.memcached_port),
+                    ("http_port", self.http_port),
+                    ("websocket_port", self.websocket_port),
+                    ("udp_port", self.udp_port),
+                ];
+                for (parameter, _) in unredirected.iter().filter(|(_, port)| *port != 0) {
+                    problems.push(ConfigError::new(
+                        parameter,
+                        "cannot be used with cluster_nodes, as its clients cannot be redirected between the nodes",
+                    ));
+                }
+            }
+            (Some(_), None) => problems.push(ConfigError::new(
+                "cluster_address",
+                "is required with cluster_nodes",
+            )),
+            (None, Some(_)) => problems.push(ConfigError::new(
+                "cluster_address",
+                "requires cluster_nodes",
+            )),
+            (None, None) => {}
+        }
+
+        if !BUILTIN_ENGINES.contains(&self.storage_engine.as_str()) {
+            problems.push(ConfigError::new(
+                "storage_engine",
+                format!(
+                    "unknown engine '{}', expected one of: {}",
+                    self.storage_engine,
+                    BUILTIN_ENGINES.join(", ")
+                ),
+            ));
+        }
+
+        for (index, (namespace, engine)) in self.namespace_engines.iter().enumerate() {
+            let message: Option<String> = if !namespaces::is_valid_namespace(namespace.as_bytes()) {
+                Some(format!(
+                    "'{}' is not a namespace (1 to 64 letters, digits, '_' and '-')",
+                    namespace
+                ))
+            } else if self.namespace_engines[..index]
+                .iter()
+                .any(|(other, _)| other == namespace)
+            {
+                Some(format!("'{}' is given an engine twice", namespace))
+            } else if !BUILTIN_ENGINES.contains(&engine.as_str()) {
+                Some(format!(
+                    "unknown engine '{}' of '{}', expected one of: {}",
+                    engine,
+                    namespace,
+                    BUILTIN_ENGINES.join(", ")
+                ))
+            } else {
+                None
+            };
+            if let Some(message) = message {
+                problems.push(ConfigError::new("namespace_engines", message));
+            }
+        }
+
+        if !(1..=MAX_SHARDS).contains(&self.shards) {
+            problems.push(ConfigError::new(
+                "shards",
+                format!("must be between 1 and {}", MAX_SHARDS),
+            ));
+        }
+
+        if self.ttl_jitter_percent > MAX_TTL_JITTER {
+            problems.push(ConfigError::new(
+                "ttl_jitter_percent",
+                format!("must be between 0 and {}", MAX_TTL_JITTER),
+            ));
+        }
+
+        if self.connection_buffer_limit < MIN_CONNECTION_BUFFER_LIMIT {
+            problems.push(ConfigError::new(
+                "connection_buffer_limit",
+                format!("must be at least {} bytes", MIN_CONNECTION_BUFFER_LIMIT),
+            ));
+        }
+
+        for (namespace, _) in &self.namespace_quotas {
+            if !namespaces::is_valid_namespace(namespace.as_bytes()) {
+                problems.push(ConfigError::new(
+                    "namespace_quotas",
+                    format!(
+                        "'{}' is not a namespace (1 to 64 letters, digits, '_' and '-')",
+                        namespace
+                    ),
+                ));
+            }
+        }
+
+        for (index, (name, _)) in self.acl_users.iter().enumerate() {
+            let message: Option<&str> = if name.is_empty() || name.contains(char::is_whitespace) {
+                Some("is not a user name (no whitespace)")
+            } else if name == DEFAULT_USER {
+                Some("is the user of the requirepass password, and cannot be redefined")
+            } else if self.acl_users[..index]
+                .iter()
+                .any(|(other, _)| other == name)
+            {
+                Some("is defined twice")
+            } else {
+                None
+            };
+            if let Some(message) = message {
+                problems.push(ConfigError::new(
+                    "acl_users",
+                    format!("'{}' {}", name, message),
+                ));
+            }
+        }
+
+        if self.worker_threads == 0 {
+            problems.push(ConfigError::new("worker_threads", "must be at least 1"));
+        }
+
+        if self.script_time_limit_ms == 0 {
+            problems.push(ConfigError::new(
+                "script_time_limit_ms",
+                "must be at least 1",
+            ));
+        }
+
+        if self.history_versions > 0 && self.history_bytes == 0 {
+            problems.push(ConfigError::new(
+                "history_bytes",
+                "must be at least 1 when versions are kept",
+            ));
+        }
+
+        if let Some(WarmupSource::File(path)) = &self.warmup_source {
+            if !path.is_file() {
+                problems.push(ConfigError::new(
+                    "warmup_source",
+                    format!("{} does not exist or is not a file", path.display()),
+                ));
+            }
+        }
+
+        if let Some(path) = &self.preload {
+            if !path.is_file() {
+                problems.push(ConfigError::new(
+                    "preload",
+                    format!("{} does not exist or is not a file", path.display()),
+                ));
+            }
+            if self.replica_of.is_some() {
+                problems.push(ConfigError::new(
+                    "preload",
+                    "cannot be used with replica_of, as a replica is read-only",
+                ));
+            } else if self.read_only {
+                problems.push(ConfigError::new("preload", "cannot be used with read_only"));
+            }
+        }
+
+        match &self.snapshot_path {
+            Some(path) => check_file_path("snapshot_path", path, &mut problems),
+            None if self.snapshot_interval_seconds > 0 => problems.push(ConfigError::new(
+                "snapshot_interval_seconds",
+                "requires a snapshot_path",
+            )),
+            None => {}
+        }
+
+        if let Some(path) = &self.appendonly_path {
+            check_file_path("appendonly_path", path, &mut problems);
+            if self.snapshot_path.as_ref() == Some(path) {
+                problems.push(ConfigError::new(
+                    "appendonly_path",
+                    "must differ from the snapshot_path",
+                ));
+            }
+        }
+
+        if let Some(AuditSink::File(path)) = &self.audit_log {
+            check_file_path("audit_log", path, &mut problems);
+        }
+        if self.audit_log_max_size == 0 {
+            problems.push(ConfigError::new("audit_log_max_size", "must be at least 1"));
+        }
+
+        if let Some(directory) = self.export_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            problems.push(ConfigError::new(
+                "export_dir",
+                format!(
+                    "{} does not exist or is not a directory",
+                    directory.display()
+                ),
+            ));
+        }
+
+        if self.uses_engine("tiered") {
+            if self.tiered_memory_entries == 0 {
+                problems.push(ConfigError::new(
+                    "tiered_memory_entries",
+                    "must be at least 1",
+                ));
+            }
+
+            if !self.tiered_disk_dir.is_dir() {
+                problems.push(ConfigError::new(
+                    "tiered_disk_dir",
+                    format!(
+                        "{} does not exist or is not a directory",
+                        self.tiered_disk_dir.display()
+                    ),
+                ));
+            }
+        }
+
+        if self.uses_engine("mmap") {
+            let dir: &Path = match self.mmap_path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+
+            if self.mmap_path.is_dir() {
+                problems.push(ConfigError::new(
+                    "mmap_path",
+                    format!("{} is a directory, not a file", self.mmap_path.display()),
+                ));
+            } else if !dir.is_dir() {
+                problems.push(ConfigError::new(
+                    "mmap_path",
+                    format!("directory {} does not exist", dir.display()),
+                ));
+            }
+        }
+
+        if self.sliding_expiration && self.uses_engine("mmap") {
+            problems.push(ConfigError::new(
+                "sliding_expiration",
+                "is not supported by the mmap storage engine, which does not keep the TTLs of its values",
+            ));
+        }
+
+        if !(1..=MAX_DATABASES).contains(&self.databases) {
+            problems.push(ConfigError::new(
+                "databases",
+                format!("must be between 1 and {}", MAX_DATABASES),
+            ));
+        }
+
+        if self.sweep_interval_ms > 0 && self.sweep_max_expired == 0 {
+            problems.push(ConfigError::new("sweep_max_expired", "must be at least 1"));
+        }
+
+        let bounded: bool = self.uses_engine("lru") || self.uses_engine("lfu");
+        if bounded && self.max_entries == 0 {
+            problems.push(ConfigError::new("max_entries", "must be at least 1"));
+        }
+
+        if !bounded && self.maxmemory > 0 {
+            problems.push(ConfigError::new(
+                "maxmemory",
+                "is only enforced by the lru and lfu storage engines",
+            ));
+        }
+
+        problems
+    }
+
+    /// The tuning options of the storage engine derived from this configuration.
+    pub fn engine_options(&self) -> EngineOptions {
+        EngineOptions {
+            shards: self.shards,
+            tiered_memory_entries: self.tiered_memory_entries,
+            tiered_disk_dir: self.tiered_disk_dir.clone(),
+            tiered_disk_bytes: Some(self.tiered_disk_bytes).filter(|&max| max > 0),
+            mmap_path: self.mmap_path.clone(),
+            mmap_sync: self.mmap_sync,
+            max_entries: self.max_entries,
+            max_memory: Some(self.maxmemory).filter(|&maxmemory| maxmemory > 0),
+            max_memory_policy: self.maxmemory_policy,
+        }
+    }
+
+    /// The tuning options of the storage engine of the given namespace (see `namespace_engines`), which keeps
+    /// its `mmap` log next to `mmap_path`, suffixed with the namespace (e.g. `myrustcache.mmap.archive`).
+    pub fn namespace_engine_options(&self, namespace: &str) -> EngineOptions {
+        let mut path: OsString = self.mmap_path.clone().into_os_string();
+        path.push(format!(".{}", namespace));
+        EngineOptions {
+            mmap_path: PathBuf::from(path),
+            ..self.engine_options()
+        }
+    }
+
+    /// Whether the given storage engine holds any key, either as `storage_engine` or as the engine of a
+    /// namespace.
+    pub fn uses_engine(&self, engine: &str) -> bool {
+        self.storage_engine == engine
+            || self
+                .namespace_engines
+                .iter()
+                .any(|(_, other)| other == engine)
+    }
+
+    /// The options of the pool of threads serving the client connections.
+    pub fn worker_options(&self) -> WorkerOptions {
+        WorkerOptions {
+            threads: self.worker_threads,
+            queue_depth: self.worker_queue,
+            rejection: self.worker_rejection,
+        }
+    }
+
+    /// The options of the background expiration sweeper, or `None` if it is disabled.
+    pub fn sweeper_options(&self) -> Option<SweeperOptions> {
+        if self.sweep_interval_ms == 0 {
+            return None;
+        }
+
+        let max_interval: Duration = Duration::from_millis(self.sweep_interval_ms);
+        Some(SweeperOptions {
+            min_interval: SweeperOptions::default().min_interval.min(max_interval),
+            max_interval,
+            max_expired: self.sweep_max_expired,
+        })
+    }
+}
+
+/// Parse a port number, explaining why the given text is not a usable port.
+fn parse_port(text: &str) -> Result<u16, String> {
+    match text.trim().parse::<u64>() {
+        Ok(port) if (1..=u16::MAX as u64).contains(&port) => Ok(port as u16),
+        Ok(port) => Err(format!(
+            "{} is out of range, it must be between 1 and 65535",
+            port
+        )),
+        Err(_) => Err(format!("'{}' is not a valid port number", text)),
+    }
+}
+
+/// Parse the quotas of the namespaces: `<namespace>:<quota>` separated by `;`, where a quota is made of
+/// `entries=N`, `bytes=N`, `writes=N` (per second) and `policy=reject|evict`, separated by `,`.
+fn parse_namespace_quotas(text: &str) -> Result<Vec<(String, NamespaceQuota)>, String> {
+    text.split(';')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (namespace, quota) = part
+                .split_once(':')
+                .ok_or_else(|| format!("'{}' is not a <namespace>:<quota> pair", part))?;
+            let quota: NamespaceQuota = quota
+                .parse()
+                .map_err(|e| format!("invalid quota of '{}': {}", namespace.trim(), e))?;
+            Ok((namespace.trim().to_string(), quota))
+        })
+        .collect()
+}
+
+/// Parse the storage engines of the namespaces: `<namespace>:<engine>` separated by `;`.
+fn parse_namespace_engines(text: &str) -> Result<Vec<(String, String)>, String> {
+    text.split(';')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (namespace, engine) = part
+                .split_once(':')
+                .ok_or_else(|| format!("'{}' is not a <namespace>:<engine> pair", part))?;
+            Ok((namespace.trim().to_string(), engine.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Parse IP ranges in CIDR notation separated by commas, e.g. `10.0.0.0/8,192.168.1.7,fd00::/8`.
+fn parse_ip_ranges(text: &str) -> Result<Vec<IpRange>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Parse the users of the ACL, e.g. `reader:password=secret,commands=GET,keys=app:*;writer:...`.
+fn parse_acl_users(text: &str) -> Result<Vec<(String, AclUser)>, String> {
+    text.split(';')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (name, user) = part
+                .split_once(':')
+                .ok_or_else(|| format!("'{}' is not a <user>:<permissions> pair", part))?;
+            let user: AclUser = user
+                .parse()
+                .map_err(|e| format!("invalid permissions of '{}': {}", name.trim(), e))?;
+            Ok((name.trim().to_string(), user))
+        })
+        .collect()
+}
+
+/// The values of the settings, by the name of their environment variable: those given as command line flags,
+/// over those of the environment, over those of the configuration file.
+struct Settings {
+    flags: HashMap<&'static str, (String, String)>, // The flag and value given for a setting, by its variable
+    file: HashMap<&'static str, (String, String)>, // The location (`path:line`) and value of a setting in the file
+}
+
+impl Settings {
+    /// Read the settings given as `--<flag> <value>` pairs (or as a lone `--<flag>` for the `SWITCHES`),
+    /// recording a problem for every other argument.
+    fn from_args(
+        args: impl IntoIterator<Item = String>,
+        problems: &mut Vec<ConfigError>,
+    ) -> Settings {
+        let mut flags: HashMap<&'static str, (String, String)> = HashMap::new();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(flag) = args.next() {
+            if !flag.starts_with("--") {
+                problems.push(ConfigError::new(
+                    "arguments",
+                    format!("unexpected argument '{}'", flag),
+                ));
+                continue;
+            }
+            let setting: Option<&'static str> = setting_of_flag(&flag);
+            let switch: bool = setting.is_some_and(|name| SWITCHES.contains(&name));
+            let value: String = match args.next_if(|value| !switch || !value.starts_with("--")) {
+                Some(value) => value,
+                None if switch => "true".to_string(),
+                None => {
+                    problems.push(ConfigError::new(
+                        "arguments",
+                        format!("missing value for {}", flag),
+                    ));
+                    break;
+                }
+            };
+            match setting {
+                Some(name) => {
+                    flags.insert(name, (flag, value));
+                }
+                None => problems.push(ConfigError::new(
+                    "arguments",
+                    format!("unknown flag '{}'", flag),
+                )),
+            }
+        }
+        Settings {
+            flags,
+            file: HashMap::new(),
+        }
+    }
+
+    /// Read the settings of the configuration file, if one is given, recording a problem for every unknown
+    /// setting and for a file which cannot be read or parsed.
+    fn load_file(&mut self, problems: &mut Vec<ConfigError>) {
+        let Some(path) = self.get(ENV_CONFIG) else {
+            return;
+        };
+        let entries: Vec<(String, String, usize)> = match fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| parse_config_file(&text))
+        {
+            Ok(entries) => entries,
+            Err(message) => {
+                problems.push(ConfigError::new("config", format!("{}: {}", path, message)));
+                return;
+            }
+        };
+
+        for (key, value, line) in entries {
+            match setting_of_key(&key) {
+                Some(name) => {
+                    self.file
+                        .insert(name, (format!("{}:{}", path, line), value));
+                }
+                None => problems.push(ConfigError::new(
+                    "config",
+                    format!("{}:{}: unknown setting '{}'", path, line, key),
+                )),
+            }
+        }
+    }
+
+    /// The value of the setting of the given environment variable, if set.
+    fn get(&self, name: &str) -> Option<String> {
+        if let Some((_, value)) = self.flags.get(name) {
+            return Some(value.clone());
+        }
+        env::var(name)
+            .ok()
+            .or_else(|| self.file.get(name).map(|(_, value)| value.clone()))
+    }
+
+    /// Where the setting of the given environment variable was set, for the reports of invalid values.
+    fn origin<'a>(&'a self, name: &'a str) -> &'a str {
+        if let Some((flag, _)) = self.flags.get(name) {
+            return flag;
+        }
+        match self.file.get(name) {
+            Some((location, _)) if env::var_os(name).is_none() => location,
+            _ => name,
+        }
+    }
+}
+
+/// The environment variable of the setting of the given command line flag (e.g. `MYRUSTCACHE_PORT` for
+/// `--port`), if any.
+fn setting_of_flag(flag: &str) -> Option<&'static str> {
+    if let Some((_, name)) = FLAG_ALIASES.iter().find(|(alias, _)| *alias == flag) {
+        return Some(name);
+    }
+    let flag: &str = flag.strip_prefix("--")?;
+    SETTINGS.into_iter().find(|name| {
+        let setting: &str = &name[ENV_PREFIX.len()..];
+        setting.len() == flag.len()
+            && setting
+                .bytes()
+                .zip(flag.bytes())
+                .all(|(s, f)| s.to_ascii_lowercase() == f || (s == b'_' && f == b'-'))
+    })
+}
+
+/// The environment variable of the setting of the given key of the configuration file (e.g.
+/// `MYRUSTCACHE_WORKER_THREADS` for `worker_threads`, or `threads` in the `[worker]` table), if any. The
+/// configuration file cannot name another one.
+fn setting_of_key(key: &str) -> Option<&'static str> {
+    SETTINGS
+        .into_iter()
+        .filter(|&name| name != ENV_CONFIG)
+        .find(|name| {
+            let setting: &str = &name[ENV_PREFIX.len()..];
+            setting.len() == key.len()
+                && setting.bytes().zip(key.bytes()).all(|(s, k)| {
+                    s.to_ascii_lowercase() == k || (s == b'_' && matches!(k, b'.' | b'-'))
+                })
+        })
+}
+
+/// Parse a configuration file, written in the subset of TOML the settings need.
+///
+/// # Returns
+/// * The key (prefixed by its table, e.g. `worker.threads`), value and line of every setting, or what is
+///   wrong with the file and where.
+fn parse_config_file(text: &str) -> Result<Vec<(String, String, usize)>, String> {
+    let mut entries: Vec<(String, String, usize)> = Vec::new();
+    let mut table: Option<String> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let number: usize = index + 1;
+        let line: &str = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            match name.strip_suffix(']').map(str::trim) {
+                Some(name) if is_bare_key(name) => table = Some(name.to_string()),
+                _ => return Err(format!("line {}: invalid table '{}'", number, line)),
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected 'key = value'", number));
+        };
+        let key: &str = key.trim();
+        if !is_bare_key(key) {
+            return Err(format!("line {}: invalid key '{}'", number, key));
+        }
+        let value: String =
+            parse_config_value(value.trim()).map_err(|e| format!("line {}: {}", number, e))?;
+        let key: String = match &table {
+            Some(table) => format!("{}.{}", table, key),
+            None => key.to_string(),
+        };
+        entries.push((key, value, number));
+    }
+    Ok(entries)
+}
+
+/// Parse the value of a setting of the configuration file into the text of its environment variable: a
+/// string (`"..."` with escapes, or `'...'`), an integer, a boolean, or an array of them joined with commas.
+fn parse_config_value(text: &str) -> Result<String, String> {
+    if let Some(items) = text.strip_prefix('[') {
+        let items: &str = items.strip_suffix(']').ok_or("unterminated array")?;
+        return items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(parse_config_value)
+            .collect::<Result<Vec<String>, String>>()
+            .map(|items| items.join(","));
+    }
+    if let Some(literal) = text.strip_prefix('\'') {
+        return match literal.strip_suffix('\'') {
+            Some(literal) if !literal.contains('\'') => Ok(literal.to_string()),
+            _ => Err(format!("invalid string {}", text)),
+        };
+    }
+    if let Some(string) = text.strip_prefix('"') {
+        let string: &str = string
+            .strip_suffix('"')
+            .ok_or_else(|| format!("invalid string {}", text))?;
+        let mut value: String = String::with_capacity(string.len());
+        let mut chars = string.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    _ => return Err(format!("invalid escape in {}", text)),
+                },
+                '"' => return Err(format!("invalid string {}", text)),
+                _ => value.push(c),
+            }
+        }
+        return Ok(value);
+    }
+    if text == "true" || text == "false" {
+        return Ok(text.to_string());
+    }
+
+    let digits: String = text.replace('_', "");
+    match !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        true => Ok(digits),
+        false => Err(format!("unsupported value {}", text)),
+    }
+}
+
+/// Cut the comment (from `#`, outside of the strings) off a line of the configuration file.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped: bool = false;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '#') => return &line[..index],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(open), _) if c == open && !escaped => quote = None,
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Whether a key (or table name) of the configuration file is a bare key: letters, digits, `_`, `-` and
+/// the `.` of the dotted keys.
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.'))
+}
+
+/// Read and parse a setting, recording a problem for the given field if it cannot be parsed.
+///
+/// # Returns
+/// * The parsed value, or `None` if the setting is not set or is invalid.
+fn env_parsed<T: FromStr>(
+    settings: &Settings,
+    name: &str,
+    field: &'static str,
+    problems: &mut Vec<ConfigError>,
+) -> Option<T> {
+    let text: String = settings.get(name)?;
+
+    match text.trim().parse::<T>() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            problems.push(ConfigError::new(
+                field,
+                format!(
+                    "'{}' is not a valid value (set by {})",
+                    text,
+                    settings.origin(name)
+                ),
+            ));
+            None
+        }
+    }
+}
+
+/// Check that a file can be created (or replaced) at the given path, recording a problem for the given
+/// field if it is a directory or its parent directory does not exist.
+fn check_file_path(field: &'static str, path: &Path, problems: &mut Vec<ConfigError>) {
+    if path.is_dir() {
+        problems.push(ConfigError::new(
+            field,
+            format!("{} is a directory", path.display()),
+        ));
+        return;
+    }
+
+    let directory: &Path = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    if !directory.is_dir() {
+        problems.push(ConfigError::new(
+            field,
+            format!(
+                "{} does not exist or is not a directory",
+                directory.display()
+            ),
+        ));
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    io::{self, IoSlice, Read, Write},
+    mem,
+    net::{IpAddr, Shutdown, TcpListener, TcpStream},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{error, info, log, warn};
+
+use crate::{
+    buffers::{BufferBudget, ConnectionBuffers},
+    clients::RegisteredClient,
+    embedded::EmbeddedCache,
+    error::ServerError,
+    ipfilter::IpFilter,
+    shutdown::{ShutdownHandle, TrackedConnection, SHUTDOWN_POLL_INTERVAL},
+    stats::ConnectedClient,
+    utils::blocked_time,
+    workers::WorkerPool,
+};
+
+/// How long a connection stays without receiving anything before its buffers are shrunk.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a client whose output is limited may leave its responses unread before being disconnected,
+/// unless its commands have a timeout.
+const OUTPUT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The limits of the client connections, shared by all the listeners.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionLimits {
+    pub max_clients: usize, // The most connections open at once, beyond which they are rejected (0 for no limit)
+    pub idle_timeout: Arc<IdleTimeout>, // How long a connection may send nothing before being closed, if limited
+    pub ip_filter: Arc<IpFilter>,       // The addresses the TCP clients may connect from
+    pub command_timeout: Option<Duration>, // How long the commands received at once may take, if limited
+    pub output_limit: usize, // The most bytes of responses left unread by a client (0 for the OS default)
+}
+
+/// How long a connection may send nothing before being closed, shared by the open connections so that a
+/// change (e.g. with `CONFIG SET`) applies to them too, the next time they wake up.
+#[derive(Debug, Default)]
+pub(crate) struct IdleTimeout {
+    millis: AtomicU64, // The timeout in milliseconds (0 for no limit)
+}
+
+impl IdleTimeout {
+    /// The timeout, or `None` if the connections may stay idle forever.
+    pub fn get(&self) -> Option<Duration> {
+        match self.millis.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(Duration::from_millis(millis)),
+        }
+    }
+
+    /// Change the timeout, `None` letting the connections stay idle forever.
+    pub fn set(&self, timeout: Option<Duration>) {
+        let millis: u64 = timeout.map_or(0, |t| t.as_millis().clamp(1, u64::MAX as u128) as u64);
+        self.millis.store(millis, Ordering::Relaxed);
+    }
+}
+
+/// The connection of a client, accepted by a TCP listener or by a Unix domain socket listener (for the
+/// clients running on the same host).
+pub(crate) enum ClientStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl ClientStream {
+    /// The address of the client (IP:Port, or `unix:<fd>` since the clients of a Unix domain socket have
+    /// no address), for logging purposes.
+    pub fn peer_address(&self) -> String {
+        match self {
+            ClientStream::Tcp(stream) => match stream.peer_addr() {
+                Ok(addr) => format!("{}:{}", addr.ip(), addr.port()),
+                Err(_) => "Unknown".to_string(),
+            },
+            ClientStream::Unix(stream) => format!("unix:{}", stream.as_raw_fd()),
+        }
+    }
+
+    /// The IP address of the client, or `None` for the clients of a Unix domain socket.
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            ClientStream::Tcp(stream) => stream.peer_addr().ok().map(|addr| addr.ip()),
+            ClientStream::Unix(_) => None,
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => stream.set_read_timeout(timeout),
+            ClientStream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => stream.set_write_timeout(timeout),
+            ClientStream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    /// Set the size of the buffer of the socket holding the data written but not sent yet (i.e. not read
+    /// by the client), beyond which writes block. The OS may round it (e.g. Linux doubles it).
+    pub fn set_send_buffer_size(&self, bytes: usize) -> io::Result<()> {
+        let size: libc::c_int = bytes.try_into().unwrap_or(libc::c_int::MAX);
+        // SAFETY: the descriptor is owned by the stream, and `size` outlives the call.
+        let result: libc::c_int = unsafe {
+            libc::setsockopt(
+                self.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                &size as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<ClientStream> {
+        match self {
+            ClientStream::Tcp(stream) => stream.try_clone().map(ClientStream::Tcp),
+            ClientStream::Unix(stream) => stream.try_clone().map(ClientStream::Unix),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => stream.shutdown(how),
+            ClientStream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    /// Whether the client has closed the connection (or it was shut down for reading, e.g. while the
+    /// server shuts down), checked without waiting nor consuming the data received.
+    pub fn is_closed(&self) -> bool {
+        let mut byte: u8 = 0;
+        // SAFETY: the buffer is a single valid byte, and the descriptor is owned by the stream.
+        let received: isize = unsafe {
+            libc::recv(
+                self.as_raw_fd(),
+                (&mut byte as *mut u8).cast(),
+                1,
+                libc::MSG_PEEK | libc::MSG_DONTWAIT,
+            )
+        };
+        match received {
+            0 => true,
+            ..0 => !matches!(
+                io::Error::last_os_error().kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+            ),
+            _ => false,
+        }
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            ClientStream::Tcp(stream) => stream.as_raw_fd(),
+            ClientStream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for &ClientStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Tcp(stream) => (&*stream).read(buffer),
+            ClientStream::Unix(stream) => (&*stream).read(buffer),
+        }
+    }
+}
+
+impl Write for &ClientStream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Tcp(stream) => (&*stream).write(buffer),
+            ClientStream::Unix(stream) => (&*stream).write(buffer),
+        }
+    }
+
+    fn write_vectored(&mut self, buffers: &[IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            ClientStream::Tcp(stream) => (&*stream).write_vectored(buffers),
+            ClientStream::Unix(stream) => (&*stream).write_vectored(buffers),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => (&*stream).flush(),
+            ClientStream::Unix(stream) => (&*stream).flush(),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        (&*self).write(buffer)
+    }
+
+    fn write_vectored(&mut self, buffers: &[IoSlice<'_>]) -> io::Result<usize> {
+        (&*self).write_vectored(buffers)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+/// A listener accepting the connections of the clients, over TCP or a Unix domain socket.
+pub(crate) enum ClientListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl ClientListener {
+    /// Wait for the next client to connect.
+    fn accept(&self) -> io::Result<ClientStream> {
+        match self {
+            ClientListener::Tcp(listener) => listener.accept().map(|(s, _)| ClientStream::Tcp(s)),
+            ClientListener::Unix(listener) => listener.accept().map(|(s, _)| ClientStream::Unix(s)),
+        }
+    }
+
+    /// Wait for the next client to connect, for at most the given time.
+    ///
+    /// # Returns
+    /// * The connection of the client, or `None` if none connected in time.
+    fn accept_within(&self, timeout: Duration) -> io::Result<Option<ClientStream>> {
+        let mut poll_fd: libc::pollfd = libc::pollfd {
+            fd: self.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `poll_fd` is a single valid entry, and the descriptor is owned by the listener.
+        let ready: libc::c_int =
+            unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+
+        match ready {
+            0 => Ok(None),
+            ..0 => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => Ok(None),
+                e => Err(e),
+            },
+            _ => self.accept().map(Some),
+        }
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            ClientListener::Tcp(listener) => listener.as_raw_fd(),
+            ClientListener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// A handler of the connection of a client speaking one of the protocols served (e.g. the text protocol or
+/// RESP). The protocols only differ in how they handle the data received, so the read loop is shared.
+pub(crate) trait ClientHandler: Sized + Send + 'static {
+    /// The name of the protocol, for logging purposes.
+    const PROTOCOL: &'static str;
+
+    /// The settings of the listener, shared by all its handlers (`()` if the protocol has none).
+    type Options: Send + Sync + 'static;
+
+    /// Create a handler for a new client connection.
+    ///
+    /// # Arguments
+    /// * `stream` - The client connection (over TCP or a Unix domain socket).
+    /// * `cache` - A reference to the cache instance shared across all handlers.
+    /// * `buffers` - The buffers of the connection, accounted in the budget shared across all handlers.
+    /// * `options` - The settings of the listener.
+    fn new(
+        stream: ClientStream,
+        cache: Arc<EmbeddedCache>,
+        buffers: ConnectionBuffers,
+        options: &Arc<Self::Options>,
+    ) -> Self;
+
+    /// The address of the client (see `ClientStream::peer_address`), for logging purposes.
+    fn address(&self) -> &str;
+
+    /// The client connection, along with the buffers of the data received from it.
+    fn connection(&mut self) -> (&ClientStream, &mut ConnectionBuffers);
+
+    /// Handle every complete request of the pending data (draining them), then flush the responses. The
+    /// connection is closed on errors, since it is unusable once a response cannot be written.
+    fn handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError>;
+
+    /// Tell the client that its request does not fit in the buffer memory, before disconnecting it.
+    fn reject_overflow(&mut self);
+
+    /// Tell the client that the server already serves as many clients as it may, before disconnecting it.
+    fn reject_too_many_clients(&mut self);
+
+    /// Tell the client that its commands took longer than the command timeout, before disconnecting it.
+    fn reject_timeout(&mut self);
+
+    /// Whether the client only listens (e.g. to keyspace events), so that it is not idle while sending
+    /// nothing.
+    fn is_listening(&self) -> bool {
+        false
+    }
+
+    /// Whether the client has asked to close the connection.
+    fn is_closing(&self) -> bool {
+        false
+    }
+
+    /// Read requests from the client, execute them, and send the responses back until the connection is
+    /// closed, or has been idle for too long, or breaks the limits of its commands and responses.
+    ///
+    /// The commands are not interrupted: once the commands received at once took longer than the command
+    /// timeout (not counting the time blocked waiting for other clients), the client is answered with an
+    /// error and disconnected. A client leaving more responses unread than the output limit allows, for
+    /// longer than the command timeout, is disconnected as well.
+    fn execute(mut self, limits: &ConnectionLimits) {
+        let idle_timeout: Option<Duration> = limits.idle_timeout.get();
+        info!(
+            "New {} client connected from {}...",
+            Self::PROTOCOL,
+            self.address()
+        );
+
+        // Wake up when the client goes idle, to give the memory of its buffers back (or close the connection)
+        let read_timeout: Duration = idle_timeout.map_or(IDLE_TIMEOUT, |t| t.min(IDLE_TIMEOUT));
+        if let Err(e) = self.connection().0.set_read_timeout(Some(read_timeout)) {
+            warn!("Cannot detect when {} goes idle: {}", self.address(), e);
+        }
+
+        // The writes block once the client leaves enough responses unread, until it reads them or too late
+        if limits.output_limit > 0 {
+            if let Err(e) = self
+                .connection()
+                .0
+                .set_send_buffer_size(limits.output_limit)
+            {
+                warn!("Cannot limit the output of {}: {}", self.address(), e);
+            }
+        }
+        let write_timeout: Option<Duration> = match limits.output_limit {
+            0 => limits.command_timeout,
+            _ => Some(limits.command_timeout.unwrap_or(OUTPUT_TIMEOUT)),
+        };
+        if let Err(e) = self.connection().0.set_write_timeout(write_timeout) {
+            warn!("Cannot detect when {} stops reading: {}", self.address(), e);
+        }
+
+        let mut last_received: Instant = Instant::now();
+        while !self.is_closing() {
+            // `read` needs a mutable reference, which `&ClientStream` provides without borrowing the writer
+            let (mut stream, buffers) = self.connection();
+            match stream.read(buffers.read_buffer()) {
+                // There is no data to read (i.e. the client has closed the connection)
+                Ok(0) => {
+                    info!("Connection closed by {}", self.address());
+                    break;
+                }
+
+                // We have received some data...
+                Ok(n) => {
+                    last_received = Instant::now();
+                    if !buffers.append_read(n) {
+                        warn!(
+                            "Out of buffer memory for the requests of {}",
+                            self.address()
+                        );
+                        self.reject_overflow();
+                        break;
+                    }
+
+                    // Take the pending data out of the buffers, since the handler needs `self` mutably
+                    let mut pending: Vec<u8> = mem::take(self.connection().1.pending());
+                    let blocked: Duration = blocked_time();
+                    let result: Result<(), ServerError> = self.handle_pending(&mut pending);
+                    let busy: Duration = last_received
+                        .elapsed()
+                        .saturating_sub(blocked_time().saturating_sub(blocked));
+                    *self.connection().1.pending() = pending;
+
+                    match result {
+                        Err(ServerError::Io(e))
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            warn!(
+                                "Closing the connection to {}, which does not read its responses",
+                                self.address()
+                            );
+                            break;
+                        }
+                        Err(e) => {
+                            log!(
+                                e.log_level(),
+                                "Closing the connection to {}: {}",
+                                self.address(),
+                                e
+                            );
+                            break;
+                        }
+                        Ok(()) => {}
+                    }
+                    if let Some(timeout) = limits.command_timeout.filter(|&t| busy > t) {
+                        warn!(
+                            "Closing the connection to {}, whose commands took {:?} (more than {:?})",
+                            self.address(),
+                            busy,
+                            timeout
+                        );
+                        self.reject_timeout();
+                        break;
+                    }
+                }
+
+                // The client is idle
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    buffers.shrink();
+
+                    // Wake up again once the client would have been idle for too long, as the timeout is now
+                    let idle: Duration = last_received.elapsed();
+                    let read_timeout: Duration = match limits.idle_timeout.get() {
+                        Some(timeout) if !self.is_listening() => {
+                            if idle >= timeout {
+                                info!("Closing the idle connection to {}", self.address());
+                                break;
+                            }
+                            (timeout - idle).min(IDLE_TIMEOUT)
+                        }
+                        _ => IDLE_TIMEOUT,
+                    };
+                    let _ = self.connection().0.set_read_timeout(Some(read_timeout));
+                }
+
+                // An error occurred while reading from the stream
+                Err(e) => {
+                    let e: ServerError = ServerError::Io(e);
+                    log!(
+                        e.log_level(),
+                        "Error reading from {}: {}",
+                        self.address(),
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Accept the clients of a listener, serving each one of them on a worker of the given pool, until the
+/// server shuts down.
+pub(crate) fn accept_clients<H: ClientHandler>(
+    listener: ClientListener,
+    cache: Arc<EmbeddedCache>,
+    budget: Arc<BufferBudget>,
+    workers: Arc<WorkerPool>,
+    options: Arc<H::Options>,
+    limits: ConnectionLimits,
+    shutdown: ShutdownHandle,
+) {
+    while !shutdown.is_shutting_down() {
+        match listener.accept_within(SHUTDOWN_POLL_INTERVAL) {
+            // A new client has connected to the server
+            Ok(Some(stream)) => {
+                // The clients of a denied address are dropped before anything is set up for them
+                if let Some(ip) = stream.peer_ip().filter(|_| !limits.ip_filter.is_open()) {
+                    if !limits.ip_filter.permits(ip) {
+                        warn!(
+                            "Denied address, dropping the {} connection from {}",
+                            H::PROTOCOL,
+                            stream.peer_address()
+                        );
+                        continue;
+                    }
+                }
+
+                let tracked: TrackedConnection = match shutdown.track(&stream) {
+                    Ok(tracked) => tracked,
+                    Err(e) => {
+                        error!("Failed to accept a {} connection: {}", H::PROTOCOL, e);
+                        continue;
+                    }
+                };
+                let registered: RegisteredClient = match cache.register_client(H::PROTOCOL, &stream)
+                {
+                    Ok(registered) => registered,
+                    Err(e) => {
+                        error!("Failed to accept a {} connection: {}", H::PROTOCOL, e);
+                        continue;
+                    }
+                };
+                let client: ConnectedClient = cache.stats().client_connected();
+                let cache: Arc<EmbeddedCache> = Arc::clone(&cache);
+                let buffers: ConnectionBuffers = ConnectionBuffers::new(Arc::clone(&budget));
+                let mut handler: H = H::new(stream, cache, buffers, &options);
+                let address: String = handler.address().to_string();
+
+                // The connection just accepted is already counted
+                if limits.max_clients > 0 && shutdown.open_connections() > limits.max_clients {
+                    warn!(
+                        "Too many clients, closing the {} connection from {}",
+                        H::PROTOCOL,
+                        address
+                    );
+                    handler.reject_too_many_clients();
+                    continue;
+                }
+
+                let limits: ConnectionLimits = limits.clone();
+                let job = move || {
+                    handler.execute(&limits);
+                    drop(client);
+                    drop(registered);
+                    drop(tracked);
+                };
+                if !workers.execute(job) {
+                    warn!(
+                        "Every worker is busy, closing the {} connection from {}",
+                        H::PROTOCOL,
+                        address
+                    );
+                }
+            }
+
+            // No client has connected yet
+            Ok(None) => {}
+
+            // An error occurred while accepting the connection
+            Err(e) => {
+                error!("Failed to accept a {} connection: {}", H::PROTOCOL, e);
+            }
+        }
+    }
+}
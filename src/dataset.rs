@@ -0,0 +1,757 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
+
+use log::info;
+
+use crate::{
+    embedded::EmbeddedCache,
+    error::{CacheError, ServerError},
+    http::json_string,
+    rdb::ImportStats,
+    utils::{base64_decode, base64_encode, current_walltime, expires_after, random_u64},
+    Bytes,
+};
+
+/// The largest string accepted in a CBOR dataset, so that a corrupt length cannot exhaust the memory.
+const MAX_STRING_SIZE: u64 = 512 * 1024 * 1024;
+
+/// The deepest nesting of the CBOR values skipped (those of unknown fields) accepted.
+const MAX_DEPTH: usize = 32;
+
+/// The major types of CBOR, held in the 3 high bits of the first byte of every item.
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+/// The simple value `null` of CBOR.
+const SIMPLE_NULL: u64 = 22;
+
+/// The portable formats a dataset is exported to and imported from. Either holds one record per key, with
+/// its value and expiration time (in milliseconds since the epoch, if any), so that the TTLs keep counting
+/// while the dataset sits in a file.
+///
+/// * `Json` - JSON Lines, one object per key: `{"key": "user:1", "value": "Alice", "expires_at":
+///   1767225600000}`. A value that is not UTF-8 is held in base64 as `value_base64` instead. On import, a
+///   `ttl` in seconds is also accepted in place of `expires_at`, and other fields are ignored.
+/// * `Cbor` - A CBOR sequence (RFC 8742) of maps with the same fields, the value being a byte string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatasetFormat {
+    Json,
+    Cbor,
+}
+
+impl DatasetFormat {
+    /// The format told by the extension of the given file: `Cbor` for `.cbor`, and `Json` otherwise.
+    pub fn from_path(path: &Path) -> DatasetFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("cbor") => DatasetFormat::Cbor,
+            _ => DatasetFormat::Json,
+        }
+    }
+}
+
+impl FromStr for DatasetFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<DatasetFormat, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "json" | "jsonl" => Ok(DatasetFormat::Json),
+            "cbor" => Ok(DatasetFormat::Cbor),
+            _ => Err(format!(
+                "unknown dataset format '{}' (expected json or cbor)",
+                text
+            )),
+        }
+    }
+}
+
+/// How a dataset is imported into a cache which already holds keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportMode {
+    /// The keys of the dataset are put into the cache, replacing the values of those it already holds, and
+    /// the other keys are kept.
+    #[default]
+    Merge,
+
+    /// The cache is cleared first, so that it holds the keys of the dataset only.
+    Replace,
+}
+
+impl FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<ImportMode, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "merge" => Ok(ImportMode::Merge),
+            "replace" => Ok(ImportMode::Replace),
+            _ => Err(format!(
+                "unknown import mode '{}' (expected merge or replace)",
+                text
+            )),
+        }
+    }
+}
+
+/// Export every key of the cache (of every database) with its value and expiration time in the given
+/// format (see `DatasetFormat`). The keys are scanned while the writes go on, so the dataset is not a
+/// point-in-time copy of the cache.
+///
+/// # Returns
+/// * The number of keys exported.
+///
+/// # Errors
+/// * `ServerError::Io` if the dataset cannot be written.
+/// * `ServerError::Cache` if the storage engine cannot be scanned for its keys.
+pub fn export<W: Write>(
+    cache: &EmbeddedCache,
+    format: DatasetFormat,
+    mut writer: W,
+) -> Result<usize, ServerError> {
+    let mut exported: usize = 0;
+    let mut result: io::Result<()> = Ok(());
+    cache.for_each_value(&mut |key, value, expires_at| {
+        if result.is_ok() {
+            result = match format {
+                DatasetFormat::Json => write_json_record(&mut writer, key, value, expires_at),
+                DatasetFormat::Cbor => write_cbor_record(&mut writer, key, value, expires_at),
+            };
+            exported += 1;
+        }
+    })?;
+    result?;
+
+    writer.flush()?;
+    Ok(exported)
+}
+
+/// Import a dataset in the given format (see `DatasetFormat`) into the cache, replacing the values of the
+/// keys it already holds. The keys which have expired since the dataset was exported are left out.
+///
+/// # Errors
+/// * `ServerError::Io` if the dataset cannot be read or is not valid.
+/// * `ServerError::Cache` if a key cannot be put into the cache.
+pub fn import<R: BufRead>(
+    reader: R,
+    format: DatasetFormat,
+    cache: &EmbeddedCache,
+) -> Result<ImportStats, ServerError> {
+    read_records(reader, format, |key, value, ttl| {
+        cache.apply_put(&key, Bytes::from(value), ttl)?;
+        Ok(())
+    })
+}
+
+/// Read the records of a dataset in the given format, handing each of them to `visit` along with its value
+/// and remaining TTL (in seconds, rounded up). Expired keys are only counted.
+///
+/// # Errors
+/// * `ServerError::Io` if the dataset cannot be read or is not valid.
+/// * Any error returned by `visit`, which stops the reading.
+pub fn read_records<R: BufRead>(
+    reader: R,
+    format: DatasetFormat,
+    mut visit: impl FnMut(String, Vec<u8>, Option<u64>) -> Result<(), ServerError>,
+) -> Result<ImportStats, ServerError> {
+    let mut stats: ImportStats = ImportStats::default();
+    let mut record = |key: Option<String>, value: Vec<u8>, expires_at: Option<u64>| {
+        let Some(key) = key else {
+            stats.skipped += 1;
+            return Ok(());
+        };
+        let ttl: Option<u64> = match expires_at {
+            Some(expires_at) => match expires_at.checked_sub(current_walltime()) {
+                Some(remaining) if remaining > 0 => Some(remaining.div_ceil(1000)),
+                _ => {
+                    stats.expired += 1;
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+        visit(key, value, ttl)?;
+        stats.imported += 1;
+        Ok(())
+    };
+
+    match format {
+        DatasetFormat::Json => read_json_records(reader, &mut record)?,
+        DatasetFormat::Cbor => read_cbor_records(reader, &mut record)?,
+    }
+    Ok(stats)
+}
+
+/// The datasets exported to and imported from the files of a directory on `EXPORT` and `IMPORT`. Only the
+/// files right under the directory can be named, so that a client cannot write to (or read from) any other
+/// path the server can access.
+///
+/// A dataset is written to a temporary file next to it and then renamed, so that a failed export leaves the
+/// previous file of the same name whole.
+pub(crate) struct Datasets {
+    directory: PathBuf, // The directory holding the dataset files
+}
+
+impl Datasets {
+    pub fn new(directory: PathBuf) -> Datasets {
+        Datasets { directory }
+    }
+
+    /// Export the cache to the given file of the directory (see `export`).
+    ///
+    /// # Arguments
+    /// * `cache` - The cache to export.
+    /// * `name` - The name of the file, under the directory.
+    /// * `format` - The format of the dataset, or `None` to tell it from the extension of the file.
+    ///
+    /// # Returns
+    /// * The number of keys exported.
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the name is not that of a file of the directory (`InvalidInput`), or the
+    ///   dataset cannot be written.
+    /// * `ServerError::Cache` if the storage engine cannot be scanned for its keys.
+    pub fn export(
+        &self,
+        cache: &EmbeddedCache,
+        name: &str,
+        format: Option<DatasetFormat>,
+    ) -> Result<usize, ServerError> {
+        let started: Instant = Instant::now();
+        let path: PathBuf = self._path(name)?;
+        let format: DatasetFormat = format.unwrap_or_else(|| DatasetFormat::from_path(&path));
+
+        // Concurrent exports to the same file write temporary files of their own
+        let mut temporary: OsString = path.clone().into_os_string();
+        temporary.push(format!(".{:016x}.tmp", random_u64()));
+        let temporary: PathBuf = PathBuf::from(temporary);
+
+        let written = || -> Result<usize, ServerError> {
+            let mut writer: BufWriter<File> = BufWriter::new(File::create(&temporary)?);
+            let keys: usize = export(cache, format, &mut writer)?;
+            writer
+                .into_inner()
+                .map_err(|e| e.into_error())?
+                .sync_all()?;
+            fs::rename(&temporary, &path)?;
+            Ok(keys)
+        };
+        let keys: usize = written().inspect_err(|_| {
+            let _ = fs::remove_file(&temporary);
+        })?;
+
+        info!(
+            "Exported {} key(s) to {} in {:?}",
+            keys,
+            path.display(),
+            started.elapsed()
+        );
+        Ok(keys)
+    }
+
+    /// Import the given file of the directory into the cache (see `import`). When replacing the keys of the
+    /// cache, the whole dataset is read once before the cache is cleared, so that an invalid dataset leaves
+    /// the cache untouched.
+    ///
+    /// # Arguments
+    /// * `cache` - The cache to import the dataset into.
+    /// * `name` - The name of the file, under the directory.
+    /// * `format` - The format of the dataset, or `None` to tell it from the extension of the file.
+    /// * `mode` - Whether the keys of the dataset are merged into the cache, or replace its keys.
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the name is not that of a file of the directory (`InvalidInput`), or the
+    ///   dataset cannot be read (`NotFound` if there is no such file) or is not valid (`InvalidData`).
+    /// * `ServerError::Cache` if the cache is read-only, or a key cannot be put into it.
+    pub fn import(
+        &self,
+        cache: &EmbeddedCache,
+        name: &str,
+        format: Option<DatasetFormat>,
+        mode: ImportMode,
+    ) -> Result<ImportStats, ServerError> {
+        let started: Instant = Instant::now();
+        let path: PathBuf = self._path(name)?;
+        let format: DatasetFormat = format.unwrap_or_else(|| DatasetFormat::from_path(&path));
+        if cache.is_read_only() {
+            return Err(CacheError::ReadOnly.into());
+        }
+
+        if mode == ImportMode::Replace {
+            let reader: BufReader<File> = BufReader::new(File::open(&path)?);
+            read_records(reader, format, |_, _, _| Ok(()))?;
+            cache.clear(false)?;
+        }
+        let stats: ImportStats = import(BufReader::new(File::open(&path)?), format, cache)?;
+
+        info!(
+            "Imported {} key(s) from {} in {:?} ({} expired, {} skipped)",
+            stats.imported,
+            path.display(),
+            started.elapsed(),
+            stats.expired,
+            stats.skipped
+        );
+        Ok(stats)
+    }
+
+    /// The path of the given file of the directory, rejecting the names which are not those of a file right
+    /// under it (e.g. `../etc/passwd` or `.hidden`).
+    fn _path(&self, name: &str) -> io::Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid file name '{}'", name),
+            ));
+        }
+        Ok(self.directory.join(name))
+    }
+}
+
+/// Write a record of a JSON dataset, as a line holding an object.
+fn write_json_record<W: Write>(
+    writer: &mut W,
+    key: &str,
+    value: &[u8],
+    expires_at: Option<u64>,
+) -> io::Result<()> {
+    let mut line: String = format!("{{\"key\":{}", json_string(key));
+    match std::str::from_utf8(value) {
+        Ok(text) => line.push_str(&format!(",\"value\":{}", json_string(text))),
+        Err(_) => line.push_str(&format!(",\"value_base64\":\"{}\"", base64_encode(value))),
+    }
+    if let Some(expires_at) = expires_at {
+        line.push_str(&format!(",\"expires_at\":{}", expires_at));
+    }
+    line.push_str("}\n");
+    writer.write_all(line.as_bytes())
+}
+
+/// Read the records of a JSON dataset, one object per line (blank lines being skipped), handing each of
+/// them to `record` along with its value and expiration time.
+fn read_json_records<R: BufRead>(
+    reader: R,
+    record: &mut impl FnMut(Option<String>, Vec<u8>, Option<u64>) -> Result<(), ServerError>,
+) -> Result<(), ServerError> {
+    for (index, line) in reader.lines().enumerate() {
+        let line: String = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |message: String| invalid(format!("JSON line {}: {}", index + 1, message));
+
+        let mut key: Option<String> = None;
+        let mut value: Option<Vec<u8>> = None;
+        let mut expires_at: Option<u64> = None;
+        for (name, field) in JsonParser::parse_object(&line).map_err(error)? {
+            match (name.as_str(), field) {
+                ("key", JsonField::Text(text)) => key = Some(text),
+                ("value", JsonField::Text(text)) => value = Some(text.into_bytes()),
+                ("value_base64", JsonField::Text(text)) => match base64_decode(&text) {
+                    Some(bytes) => value = Some(bytes),
+                    None => return Err(error("value_base64 is not base64".to_string()).into()),
+                },
+                ("expires_at" | "ttl", JsonField::Null) => {}
+                ("expires_at", JsonField::Literal(literal)) => match literal.parse() {
+                    Ok(time) => expires_at = Some(time),
+                    Err(_) => return Err(error("expires_at is not a time".to_string()).into()),
+                },
+                ("ttl", JsonField::Literal(literal)) => match literal.parse::<u64>() {
+                    Ok(0) => {}
+                    Ok(ttl) => {
+                        expires_at = Some(expires_after(current_walltime(), ttl));
+                    }
+                    Err(_) => return Err(error("ttl is not a number".to_string()).into()),
+                },
+                ("key" | "value" | "value_base64" | "expires_at" | "ttl", _) => {
+                    return Err(error(format!("{} has the wrong type", name)).into());
+                }
+                _ => {}
+            }
+        }
+
+        let Some(key) = key else {
+            return Err(error("missing field 'key'".to_string()).into());
+        };
+        let Some(value) = value else {
+            return Err(error("missing field 'value'".to_string()).into());
+        };
+        record(Some(key), value, expires_at)?;
+    }
+    Ok(())
+}
+
+/// A field of a JSON object, as far as datasets are concerned.
+enum JsonField {
+    Null,
+    Text(String),    // A string, unescaped
+    Literal(String), // A number or a boolean, as JSON text
+    Nested,          // An object or an array, skipped
+}
+
+/// A minimal parser of flat JSON objects.
+struct JsonParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl JsonParser<'_> {
+    fn parse_object(text: &str) -> Result<Vec<(String, JsonField)>, String> {
+        let mut parser: JsonParser = JsonParser { text, position: 0 };
+        let mut fields: Vec<(String, JsonField)> = Vec::new();
+
+        parser.expect(b'{')?;
+        if !parser.eat(b'}') {
+            loop {
+                let name: String = parser.string()?;
+                parser.expect(b':')?;
+                fields.push((name, parser.value()?));
+                if parser.eat(b'}') {
+                    break;
+                }
+                parser.expect(b',')?;
+            }
+        }
+
+        parser.skip_whitespace();
+        if parser.position != text.len() {
+            return Err("unexpected characters after the object".to_string());
+        }
+        Ok(fields)
+    }
+
+    fn value(&mut self) -> Result<JsonField, String> {
+        self.skip_whitespace();
+        let start: usize = self.position;
+        match self.peek() {
+            Some(b'"') => self.string().map(JsonField::Text),
+            Some(b'{' | b'[') => self.skip_nested().map(|_| JsonField::Nested),
+            _ => {
+                while let Some(byte) = self.peek() {
+                    if matches!(byte, b',' | b'}' | b']' | b' ' | b'\t' | b'\r' | b'\n') {
+                        break;
+                    }
+                    self.position += 1;
+                }
+                match &self.text[start..self.position] {
+                    "" => Err(format!("expected a value at column {}", start + 1)),
+                    "null" => Ok(JsonField::Null),
+                    literal => Ok(JsonField::Literal(literal.to_string())),
+                }
+            }
+        }
+    }
+
+    fn skip_nested(&mut self) -> Result<(), String> {
+        let mut depth: usize = 0;
+        loop {
+            match self.peek() {
+                None => return Err("unterminated object or array".to_string()),
+                Some(b'"') => {
+                    self.string()?;
+                    continue;
+                }
+                Some(b'{' | b'[') => depth += 1,
+                Some(b'}' | b']') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.position += 1;
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+            self.position += 1;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut string: String = String::new();
+        loop {
+            let Some(c) = self.text[self.position..].chars().next() else {
+                return Err("unterminated string".to_string());
+            };
+            self.position += c.len_utf8();
+
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escape: Option<u8> = self.peek();
+                    self.position += 1;
+                    string.push(match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err(format!("invalid escape at column {}", self.position)),
+                    });
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    /// Parse the digits of a `\u` escape, combining the surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high: u32 = self.hex4()?;
+        let code: u32 = if (0xD800..0xDC00).contains(&high) {
+            if !self.text[self.position..].starts_with("\\u") {
+                return Err("unpaired surrogate in a \\u escape".to_string());
+            }
+            self.position += 2;
+            let low: u32 = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err("unpaired surrogate in a \\u escape".to_string());
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| "invalid \\u escape".to_string())
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits: &str = self
+            .text
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| "invalid \\u escape".to_string())?;
+        self.position += 4;
+        u32::from_str_radix(digits, 16).map_err(|e| e.to_string())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r' | b'\n')) {
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        match self.eat(byte) {
+            true => Ok(()),
+            false => Err(format!(
+                "expected '{}' at column {}",
+                byte as char,
+                self.position + 1
+            )),
+        }
+    }
+}
+
+/// Write a record of a CBOR dataset, as a map of its fields.
+fn write_cbor_record<W: Write>(
+    writer: &mut W,
+    key: &str,
+    value: &[u8],
+    expires_at: Option<u64>,
+) -> io::Result<()> {
+    write_cbor_head(writer, MAJOR_MAP, 2 + u64::from(expires_at.is_some()))?;
+    write_cbor_text(writer, "key")?;
+    write_cbor_text(writer, key)?;
+    write_cbor_text(writer, "value")?;
+    write_cbor_head(writer, MAJOR_BYTES, value.len() as u64)?;
+    writer.write_all(value)?;
+    if let Some(expires_at) = expires_at {
+        write_cbor_text(writer, "expires_at")?;
+        write_cbor_head(writer, MAJOR_UNSIGNED, expires_at)?;
+    }
+    Ok(())
+}
+
+/// Write the head of a CBOR item: its major type, and its argument in the shortest encoding that holds it.
+fn write_cbor_head<W: Write>(writer: &mut W, major: u8, argument: u64) -> io::Result<()> {
+    let major: u8 = major << 5;
+    match argument {
+        0..=23 => writer.write_all(&[major | argument as u8]),
+        24..=0xFF => writer.write_all(&[major | 24, argument as u8]),
+        0x100..=0xFFFF => {
+            writer.write_all(&[major | 25])?;
+            writer.write_all(&(argument as u16).to_be_bytes())
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            writer.write_all(&[major | 26])?;
+            writer.write_all(&(argument as u32).to_be_bytes())
+        }
+        _ => {
+            writer.write_all(&[major | 27])?;
+            writer.write_all(&argument.to_be_bytes())
+        }
+    }
+}
+
+fn write_cbor_text<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    write_cbor_head(writer, MAJOR_TEXT, text.len() as u64)?;
+    writer.write_all(text.as_bytes())
+}
+
+/// Read the records of a CBOR dataset, a map after the other until the end of the file, handing each of
+/// them to `record` along with its value and expiration time. A record whose key is a byte string that is
+/// not UTF-8 is handed over without its key, to be skipped.
+fn read_cbor_records<R: Read>(
+    reader: R,
+    record: &mut impl FnMut(Option<String>, Vec<u8>, Option<u64>) -> Result<(), ServerError>,
+) -> Result<(), ServerError> {
+    let mut cbor: CborReader<R> = CborReader { reader };
+    let mut index: usize = 0;
+
+    loop {
+        index += 1;
+        let error = |message: &str| invalid(format!("CBOR record {}: {}", index, message));
+        let fields: u64 = match cbor.head()? {
+            None => return Ok(()),
+            Some((MAJOR_MAP, fields)) => fields,
+            Some(_) => return Err(error("not a map").into()),
+        };
+
+        let mut key: Option<Option<String>> = None;
+        let mut value: Option<Vec<u8>> = None;
+        let mut expires_at: Option<u64> = None;
+        for _ in 0..fields {
+            let name: Vec<u8> = match cbor.item()? {
+                (MAJOR_TEXT, length) => cbor.bytes(length)?,
+                _ => return Err(error("a field name is not a text string").into()),
+            };
+            match (name.as_slice(), cbor.item()?) {
+                (b"key", (MAJOR_TEXT | MAJOR_BYTES, length)) => {
+                    key = Some(String::from_utf8(cbor.bytes(length)?).ok());
+                }
+                (b"value", (MAJOR_TEXT | MAJOR_BYTES, length)) => {
+                    value = Some(cbor.bytes(length)?);
+                }
+                (b"expires_at" | b"ttl", (MAJOR_SIMPLE, SIMPLE_NULL)) => {}
+                (b"expires_at", (MAJOR_UNSIGNED, time)) => expires_at = Some(time),
+                (b"ttl", (MAJOR_UNSIGNED, 0)) => {}
+                (b"ttl", (MAJOR_UNSIGNED, ttl)) => {
+                    expires_at = Some(current_walltime().saturating_add(ttl.saturating_mul(1000)));
+                }
+                (b"key" | b"value" | b"expires_at" | b"ttl", _) => {
+                    let name: String = String::from_utf8_lossy(&name).into_owned();
+                    return Err(error(&format!("{} has the wrong type", name)).into());
+                }
+                (_, (major, argument)) => cbor.skip(major, argument, 0)?,
+            }
+        }
+
+        let Some(key) = key else {
+            return Err(error("missing field 'key'").into());
+        };
+        let Some(value) = value else {
+            return Err(error("missing field 'value'").into());
+        };
+        record(key, value, expires_at)?;
+    }
+}
+
+/// Reads the items of CBOR, in their definite-length encodings only.
+struct CborReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> CborReader<R> {
+    /// Read the head of an item: its major type, and its argument (the value of an integer or a simple
+    /// value, or the length of a string, an array or a map).
+    ///
+    /// # Returns
+    /// * The head, or `None` at the end of the data.
+    fn head(&mut self) -> io::Result<Option<(u8, u64)>> {
+        let mut initial: [u8; 1] = [0];
+        match self.reader.read_exact(&mut initial) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+
+        let (major, info) = (initial[0] >> 5, initial[0] & 0x1F);
+        let size: usize = match info {
+            0..=23 => return Ok(Some((major, u64::from(info)))),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => {
+                return Err(invalid(
+                    "CBOR dataset: indefinite lengths are not supported".to_string(),
+                ))
+            }
+        };
+        let mut argument: [u8; 8] = [0; 8];
+        self.reader.read_exact(&mut argument[8 - size..])?;
+        Ok(Some((major, u64::from_be_bytes(argument))))
+    }
+
+    /// Read the head of an item within a record, which the data must not end before.
+    fn item(&mut self) -> io::Result<(u8, u64)> {
+        self.head()?
+            .ok_or_else(|| invalid("CBOR dataset: truncated record".to_string()))
+    }
+
+    fn bytes(&mut self, length: u64) -> io::Result<Vec<u8>> {
+        if length > MAX_STRING_SIZE {
+            return Err(invalid(format!(
+                "CBOR dataset: string of {} bytes is too large",
+                length
+            )));
+        }
+        let mut bytes: Vec<u8> = vec![0; length as usize];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Skip the rest of an item whose head was read, along with the items it holds.
+    fn skip(&mut self, major: u8, argument: u64, depth: usize) -> io::Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("CBOR dataset: nested too deeply".to_string()));
+        }
+
+        let items: u64 = match major {
+            MAJOR_UNSIGNED | MAJOR_NEGATIVE | MAJOR_SIMPLE => 0,
+            MAJOR_BYTES | MAJOR_TEXT => {
+                self.bytes(argument)?;
+                0
+            }
+            MAJOR_ARRAY => argument,
+            MAJOR_MAP => argument.saturating_mul(2),
+            MAJOR_TAG => 1, // The tagged item
+            _ => unreachable!("the major type of CBOR takes 3 bits"),
+        };
+        for _ in 0..items {
+            let (major, argument) = self.item()?;
+            self.skip(major, argument, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid dataset: {}", message),
+    )
+}
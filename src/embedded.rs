@@ -0,0 +1,3237 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        mpsc::Receiver,
+        Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+
+use crate::{
+    audit::{AuditEntry, AuditLog},
+    blocking::{BlockingPop, ListWaiters},
+    cache::{
+        estimated_entry_size, AccessTrackingCache, AppendOnlyLog, Bytes, Cache, CacheBuilder,
+        CacheFactory, CompressingCache, CompressionStats, EngineOptions, ExpirationSweeper,
+        KeyAccess, LogReplay, MaxMemoryPolicy, MemoryStats, PutCondition, RecomputeLeases,
+        ReplayStats, RoutedCache, SweepStats, SweeperOptions, WriteThroughCache,
+    },
+    clients::{ClientInfo, ClientRegistry, RegisteredClient},
+    config::ServerConfig,
+    connection::ClientStream,
+    error::{CacheError, CacheResult},
+    events::{KeyspaceEvent, KeyspaceEventKind, KeyspaceEvents, KeyspaceNotifications},
+    history::{Version, VersionHistory},
+    monitor::{self, MONITOR_CHANNEL},
+    namespaces::{self, NamespaceQuota, NamespaceQuotas, NamespaceUsage},
+    primary::ReplicationFeed,
+    pubsub::PubSub,
+    script::{Reply, Script},
+    server::ServerCache,
+    slowlog::SlowLog,
+    stats::{CacheStats, CommandProtocol},
+    utils::{
+        current_monotime, current_walltime, expires_after, glob_match, monotime_to_walltime,
+        random_u64,
+    },
+    values::{
+        self, BloomFilter, Collection, Hash, HyperLogLog, List, RateBucket, RateDecision,
+        ScoreBound, Set, SetOperation, SortedSet, Stream, StreamId, ValueType,
+    },
+};
+
+/// The largest TTL jitter, in percent. A larger one could make TTLs negative.
+pub const MAX_TTL_JITTER: u32 = 100;
+
+/// How many keys a background clear removes at a time, pausing for `CLEAR_BATCH_PAUSE` in between so that
+/// the other writes to the cache are not held up.
+const CLEAR_BATCH_SIZE: usize = 1000;
+const CLEAR_BATCH_PAUSE: Duration = Duration::from_millis(1);
+
+/// Checks the value of a key in the same step as it is removed, its error leaving the key unchanged.
+type AcceptRemoval<'a> = &'a mut dyn FnMut(&Bytes) -> CacheResult<()>;
+
+/// The upper bounds of the buckets of a `TtlHistogram`, in seconds of remaining TTL: 1 minute, 5 minutes,
+/// 15 minutes, 1 hour, 6 hours and 1 day. A last bucket counts the longer TTLs.
+pub const TTL_BUCKETS: [u64; 6] = [60, 300, 900, 3600, 21600, 86400];
+
+/// How many keys a TTL histogram examines unless told otherwise.
+pub const DEFAULT_TTL_SAMPLE_SIZE: usize = 10_000;
+
+/// How long a script may run unless told otherwise (see `EmbeddedCache::with_script_time_limit`), in
+/// milliseconds, like the `lua-time-limit` of Redis.
+pub const DEFAULT_SCRIPT_TIME_LIMIT_MS: u64 = 5000;
+
+/// The outcome of looking up a key of an `EmbeddedCache`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// The key is cached with this value.
+    Fresh(Bytes),
+
+    /// The key is cached with this value, but its soft TTL has elapsed, so it should be refreshed.
+    Stale(Bytes),
+
+    /// The key is cached as known to be absent (see `EmbeddedCache::put_negative`).
+    Negative,
+
+    /// The key is not cached.
+    Missing,
+
+    /// The key is not cached and the caller holds its recompute lease (see `EmbeddedCache::get_or_lock`),
+    /// so it is expected to compute the value and put it into the cache.
+    Recompute,
+}
+
+/// A value removed while soft delete is enabled, kept until its recovery window is over (see
+/// `EmbeddedCache::with_tombstone_window`).
+#[derive(Clone)]
+struct Tombstone {
+    value: Bytes,            // The removed value
+    expires_at: Option<u64>, // When the value would have expired (monotime), if put with a TTL
+    stale_at: Option<u64>, // When the value would have become stale (monotime), if put with a soft TTL
+}
+
+/// The keys the background flushes have yet to remove (see `EmbeddedCache::clear_prefix_in_background`).
+/// A key written in the meantime is taken off, so that its new value is kept.
+#[derive(Default)]
+struct PendingFlushes {
+    pending: AtomicBool, // Whether any key is pending, checked without locking by the writes
+    keys: Mutex<HashSet<String>>, // The keys listed by the flushes, and neither removed nor written since
+}
+
+impl PendingFlushes {
+    /// Add the keys listed by a flush.
+    fn add(&self, keys: &[String]) -> CacheResult<()> {
+        let mut pending = self.keys.lock()?;
+        pending.extend(keys.iter().cloned());
+        self.pending.store(!pending.is_empty(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether the given key is pending.
+    fn contains(&self, key: &str) -> CacheResult<bool> {
+        Ok(self.pending.load(Ordering::Relaxed) && self.keys.lock()?.contains(key))
+    }
+
+    /// Take the given key off the pending ones, as it is written or removed, returning whether it was
+    /// pending.
+    fn take(&self, key: &str) -> CacheResult<bool> {
+        if !self.pending.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let mut pending = self.keys.lock()?;
+        let taken: bool = pending.remove(key);
+        self.pending.store(!pending.is_empty(), Ordering::Relaxed);
+        Ok(taken)
+    }
+}
+
+/// The keys of a part of the keyspace of an `EmbeddedCache` (see `EmbeddedCache::keyspace_stats`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceStats {
+    pub keys: usize,          // The keys holding a value
+    pub negative_keys: usize, // The keys cached as known to be absent
+    pub bytes: usize,         // The size of the keys and values holding a value
+}
+
+/// The memory taken by the entries of an `EmbeddedCache` (see `EmbeddedCache::memory_report`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub keys: usize,              // The keys holding a value
+    pub used_bytes: usize,        // The bytes taken by the keys, the values and their bookkeeping
+    pub max_bytes: Option<usize>, // The most bytes the entries may take, if limited
+    pub evictions: u64,           // The number of entries evicted so far to make room for others
+    pub estimated: bool, // Whether the bytes are estimated, the storage engine not accounting for them
+}
+
+impl MemoryReport {
+    /// The average bytes taken by a key, or 0 without any.
+    pub fn bytes_per_key(&self) -> usize {
+        self.used_bytes.checked_div(self.keys).unwrap_or(0)
+    }
+}
+
+/// The remaining TTLs of a sample of the keys of an `EmbeddedCache` (see `EmbeddedCache::ttl_histogram`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TtlHistogram {
+    pub sampled: usize,    // The keys holding a value which were sampled
+    pub persistent: usize, // The sampled keys without a TTL
+    pub buckets: [usize; TTL_BUCKETS.len() + 1], // The sampled keys with a TTL, by bucket
+}
+
+/// The full feature set of the server (negative caching, soft TTLs, stampede protection, and the storage
+/// engine of the configuration), available in-process without any networking.
+///
+/// A `CacheServer` is a thin protocol layer on top of an `EmbeddedCache`, so both have the same semantics.
+/// An application can even serve the cache it uses in-process to remote clients (see
+/// `CacheServer::with_embedded`).
+///
+/// ```no_run
+/// use myrustcache::{embedded::Lookup, EmbeddedCache, ServerConfig};
+///
+/// let cache = EmbeddedCache::from_config(&ServerConfig::from_env().unwrap_or_default())?;
+/// cache.put("x", b"ABC".as_slice().into(), Some(60), Some(30))?;
+///
+/// match cache.get("x")? {
+///     Lookup::Fresh(value) => println!("{:?}", value),
+///     Lookup::Stale(value) => println!("{:?} (refresh me)", value),
+///     Lookup::Negative | Lookup::Missing | Lookup::Recompute => println!("not cached"),
+/// }
+/// # Ok::<(), myrustcache::CacheError>(())
+/// ```
+pub struct EmbeddedCache {
+    cache: Arc<ServerCache>,                                 // The cached values
+    unlogged: Arc<ServerCache>, // The cache under the append-only log, if any (see `_update_as`)
+    leases: RecomputeLeases<String>, // The recompute leases handed out by `get_or_lock`
+    negatives: Arc<dyn Cache<String, ()>>, // The keys cached as known to be absent
+    soft_expiry: Arc<dyn Cache<String, u64>>, // When the values put with a soft TTL become stale (monotime)
+    tombstones: Arc<dyn Cache<String, Tombstone>>, // The values removed within their recovery window
+    tombstone_window: Option<u64>, // How long the removed values can be restored, in seconds (see `undelete`)
+    history: Option<VersionHistory>, // The last versions of the keys (see `with_version_history`)
+    ttl_jitter: AtomicU32, // The default spread of the TTLs, in percent (see `with_ttl_jitter`)
+    max_key_bytes: AtomicUsize, // The longest key written, or 0 for no limit (see `with_max_sizes`)
+    max_value_bytes: AtomicUsize, // The longest value written, or 0 for no limit (see `with_max_sizes`)
+    max_memory: Mutex<(usize, MaxMemoryPolicy)>, // The max memory of the engine, 0 for none (see `set_max_memory`)
+    read_only: AtomicBool, // Whether the writes of the clients are rejected (see `with_read_only`)
+    replica: bool,         // Whether the cache replicates a primary (see `with_replica`)
+    replicated_at: AtomicU64, // When the primary was last heard from (walltime), or 0 if its link is down
+    events: Arc<KeyspaceEvents>, // Publishes the changes of the keys to the subscribers (see `subscribe`)
+    pubsub: Arc<PubSub>, // Delivers the messages published on channels to their subscribers (see `publish`)
+    monitors: Arc<PubSub>, // Delivers the commands executed to the clients which have sent `MONITOR`
+    clients: Arc<ClientRegistry>, // The client connections open (see `clients` and `kill_client`)
+    quotas: NamespaceQuotas, // The limits of the namespaces (see `with_namespace_quotas`)
+    sliding_expiration: bool, // Whether reading a value restarts its TTL (see `with_sliding_expiration`)
+    databases: usize,         // The number of logical databases (see `with_databases`)
+    collection_writes: Mutex<()>, // Serializes the writes to collections (see `_update_collection`)
+    flushes: PendingFlushes, // The keys left to the background flushes (see `clear_prefix_in_background`)
+    list_waiters: Arc<ListWaiters>, // The callers waiting for values to be pushed to lists (see `list_pop_or_wait`)
+    transactions: RwLock<()>, // Isolates the transactions of the clients (see `transaction_lock`)
+    script_time_limit: AtomicU64, // How long a script may run, in milliseconds (see `eval`)
+    append_only_log: Option<Arc<AppendOnlyLog>>, // Logs the writes to the storage engine, if enabled
+    replication_feed: Option<Arc<ReplicationFeed>>, // Streams the writes to the replicas, if they are accepted
+    stats: Arc<CacheStats>, // Counts the lookups, the expirations, the clients and the commands (see `info`)
+    slow_log: SlowLog, // The last commands which took longer than a threshold (see `with_slow_log`)
+    audit_log: Option<AuditLog>, // Records the write commands of the clients (see `with_audit_log`)
+    access: Option<Arc<AccessTrackingCache<String, Bytes>>>, // Tracks the reads of the keys, if enabled
+    compression: Option<Arc<CompressingCache<String>>>, // Compresses the large values, if enabled
+    _sweeper: Option<ExpirationSweeper>, // Deletes the expired entries of all the above in the background
+}
+
+impl EmbeddedCache {
+    /// Create a new `EmbeddedCache` on top of the given cache, which may be backed by any storage engine.
+    /// Its expired entries are deleted in the background with the default `SweeperOptions`.
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the expiration sweeper cannot be started.
+    pub fn new(cache: Arc<ServerCache>) -> CacheResult<EmbeddedCache> {
+        EmbeddedCache::with_sweeper(cache, Some(SweeperOptions::default()))
+    }
+
+    /// Create a new `EmbeddedCache` on top of the given cache, deleting its expired entries in the
+    /// background with the given options (or only when they are accessed, if `None`).
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the expiration sweeper cannot be started.
+    pub fn with_sweeper(
+        cache: Arc<ServerCache>,
+        sweeper: Option<SweeperOptions>,
+    ) -> CacheResult<EmbeddedCache> {
+        let negatives: Arc<dyn Cache<String, ()>> = CacheFactory::new_cache();
+        let soft_expiry: Arc<dyn Cache<String, u64>> = CacheFactory::new_cache();
+        let tombstones: Arc<dyn Cache<String, Tombstone>> = CacheFactory::new_cache();
+        let pubsub: Arc<PubSub> = Arc::new(PubSub::new());
+        let events: Arc<KeyspaceEvents> = Arc::new(KeyspaceEvents::new(Arc::clone(&pubsub)));
+        let stats: Arc<CacheStats> = Arc::new(CacheStats::default());
+
+        let sweeper: Option<CacheResult<ExpirationSweeper>> = sweeper.map(|options| {
+            let weak_cache: Weak<ServerCache> = Arc::downgrade(&cache);
+            let weak_negatives: Weak<dyn Cache<String, ()>> = Arc::downgrade(&negatives);
+            let weak_soft_expiry: Weak<dyn Cache<String, u64>> = Arc::downgrade(&soft_expiry);
+            let weak_tombstones: Weak<dyn Cache<String, Tombstone>> = Arc::downgrade(&tombstones);
+            let weak_events: Weak<KeyspaceEvents> = Arc::downgrade(&events);
+            let weak_stats: Weak<CacheStats> = Arc::downgrade(&stats);
+
+            // Sweep the four caches together (purging the tombstones whose window is over), until the
+            // `EmbeddedCache` is dropped
+            ExpirationSweeper::spawn_with(options, move |max_expired| {
+                let cache: Arc<ServerCache> = weak_cache.upgrade()?;
+                let negatives: Arc<dyn Cache<String, ()>> = weak_negatives.upgrade()?;
+                let soft_expiry: Arc<dyn Cache<String, u64>> = weak_soft_expiry.upgrade()?;
+                let tombstones: Arc<dyn Cache<String, Tombstone>> = weak_tombstones.upgrade()?;
+                let events: Arc<KeyspaceEvents> = weak_events.upgrade()?;
+                let counters: Arc<CacheStats> = weak_stats.upgrade()?;
+
+                let sweep = || -> CacheResult<SweepStats> {
+                    // The caches share the budget of the sweep, in this order
+                    let mut stats: SweepStats = cache.sweep_expired(max_expired)?;
+                    counters.record_expired(stats.expired);
+                    stats += negatives.sweep_expired(max_expired.saturating_sub(stats.expired))?;
+                    stats +=
+                        soft_expiry.sweep_expired(max_expired.saturating_sub(stats.expired))?;
+                    stats += tombstones.sweep_expired(max_expired.saturating_sub(stats.expired))?;
+
+                    // Tell the subscribers about the keys which have expired since the last sweep
+                    events.publish_expired(current_monotime(), |key| {
+                        Ok(cache.get(&key.to_string())?.is_none())
+                    })?;
+                    Ok(stats)
+                };
+                Some(sweep())
+            })
+        });
+        let sweeper: Option<ExpirationSweeper> = sweeper.transpose()?;
+
+        Ok(EmbeddedCache {
+            unlogged: Arc::clone(&cache),
+            cache,
+            leases: RecomputeLeases::new(),
+            negatives,
+            soft_expiry,
+            tombstones,
+            tombstone_window: None,
+            history: None,
+            ttl_jitter: AtomicU32::new(0),
+            max_key_bytes: AtomicUsize::new(0),
+            max_value_bytes: AtomicUsize::new(0),
+            max_memory: Mutex::new((0, MaxMemoryPolicy::default())),
+            read_only: AtomicBool::new(false),
+            replica: false,
+            replicated_at: AtomicU64::new(0),
+            events,
+            pubsub,
+            monitors: Arc::new(PubSub::new()),
+            clients: Arc::new(ClientRegistry::new()),
+            quotas: NamespaceQuotas::new(Vec::new()),
+            sliding_expiration: false,
+            databases: 1,
+            collection_writes: Mutex::new(()),
+            flushes: PendingFlushes::default(),
+            list_waiters: Arc::new(ListWaiters::default()),
+            transactions: RwLock::new(()),
+            script_time_limit: AtomicU64::new(DEFAULT_SCRIPT_TIME_LIMIT_MS),
+            append_only_log: None,
+            replication_feed: None,
+            stats,
+            slow_log: SlowLog::default(),
+            audit_log: None,
+            access: None,
+            compression: None,
+            _sweeper: sweeper,
+        })
+    }
+
+    /// Spread the TTLs given to `put` and `put_negative` randomly by up to `percent` percent either way
+    /// (at most `MAX_TTL_JITTER`), so that entries written together do not all expire (and get refilled)
+    /// together. Zero, the default, keeps the TTLs exact.
+    pub fn with_ttl_jitter(self, percent: u32) -> EmbeddedCache {
+        self.set_ttl_jitter(percent);
+        self
+    }
+
+    /// Change the spread of the TTLs of the next writes (see `with_ttl_jitter`), e.g. with `CONFIG SET`.
+    pub fn set_ttl_jitter(&self, percent: u32) {
+        self.ttl_jitter
+            .store(percent.min(MAX_TTL_JITTER), Ordering::Relaxed);
+    }
+
+    /// The spread of the TTLs, in percent (see `with_ttl_jitter`).
+    pub fn ttl_jitter(&self) -> u32 {
+        self.ttl_jitter.load(Ordering::Relaxed)
+    }
+
+    /// Reject the writes of a key longer than `max_key_bytes`, or of a value longer than `max_value_bytes`
+    /// (e.g. a client pushing a 100 MB value), with `CacheError::KeyTooLarge` or `CacheError::ValueTooLarge`,
+    /// counting them (see `CacheStats::oversized_writes`). The value of a collection is limited as a whole,
+    /// once encoded. Zero, the default, sets no limit. The writes replicated from a primary or replayed from
+    /// the append-only log are not limited.
+    pub fn with_max_sizes(self, max_key_bytes: usize, max_value_bytes: usize) -> EmbeddedCache {
+        self.set_max_key_bytes(max_key_bytes);
+        self.set_max_value_bytes(max_value_bytes);
+        self
+    }
+
+    /// Change the longest key of the next writes (see `with_max_sizes`), e.g. with `CONFIG SET`.
+    pub fn set_max_key_bytes(&self, max_key_bytes: usize) {
+        self.max_key_bytes.store(max_key_bytes, Ordering::Relaxed);
+    }
+
+    /// Change the longest value of the next writes (see `with_max_sizes`), e.g. with `CONFIG SET`.
+    pub fn set_max_value_bytes(&self, max_value_bytes: usize) {
+        self.max_value_bytes
+            .store(max_value_bytes, Ordering::Relaxed);
+    }
+
+    /// The longest key written, in bytes, or 0 for no limit (see `with_max_sizes`).
+    pub fn max_key_bytes(&self) -> usize {
+        self.max_key_bytes.load(Ordering::Relaxed)
+    }
+
+    /// The longest value written, in bytes, or 0 for no limit (see `with_max_sizes`).
+    pub fn max_value_bytes(&self) -> usize {
+        self.max_value_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Publish the given kinds of keyspace events on the pub/sub channels (see `publish`), e.g. for the
+    /// clients which keep a local copy of some keys to invalidate it: `__keyspace@<db>__:<key>` with the
+    /// name of the event, and `__keyevent@<db>__:<event>` with the key. None are published by default.
+    pub fn with_keyspace_notifications(
+        self,
+        notifications: KeyspaceNotifications,
+    ) -> EmbeddedCache {
+        if let Err(e) = self.set_keyspace_notifications(notifications) {
+            error!("Failed to enable the keyspace notifications: {}", e);
+        }
+        self
+    }
+
+    /// Change the kinds of keyspace events published on the pub/sub channels (see
+    /// `with_keyspace_notifications`), e.g. with `CONFIG SET`.
+    pub fn set_keyspace_notifications(
+        &self,
+        notifications: KeyspaceNotifications,
+    ) -> CacheResult<()> {
+        self.events.set_notifications(notifications)
+    }
+
+    /// The kinds of keyspace events published on the pub/sub channels (see `with_keyspace_notifications`).
+    pub fn keyspace_notifications(&self) -> KeyspaceNotifications {
+        self.events.notifications()
+    }
+
+    /// Stop the scripts run by `eval` once they have run for the given number of milliseconds, failing with
+    /// `CacheError::Script` (`DEFAULT_SCRIPT_TIME_LIMIT_MS` by default). The commands of the other clients of
+    /// the servers wait for a script, so a short limit keeps a runaway script from stalling them.
+    pub fn with_script_time_limit(self, millis: u64) -> EmbeddedCache {
+        self.set_script_time_limit(millis);
+        self
+    }
+
+    /// Change how long the next scripts may run (see `with_script_time_limit`), e.g. with `CONFIG SET`.
+    pub fn set_script_time_limit(&self, millis: u64) {
+        self.script_time_limit.store(millis, Ordering::Relaxed);
+    }
+
+    /// How long a script may run, in milliseconds (see `with_script_time_limit`).
+    pub fn script_time_limit(&self) -> u64 {
+        self.script_time_limit.load(Ordering::Relaxed)
+    }
+
+    /// Reject the writes (with `CacheError::ReadOnly`) if `read_only`, e.g. so that a replica only holds the
+    /// data of its primary, or so that a node can be exposed to the services which should only read it. The
+    /// reads, and the replication itself, go on.
+    pub fn with_read_only(self, read_only: bool) -> EmbeddedCache {
+        self.set_read_only(read_only);
+        self
+    }
+
+    /// Start or stop rejecting the writes (see `with_read_only`), e.g. with `CONFIG SET`.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    /// Mark the cache as the replica of a primary (see `CacheServer::with_replica_of`), which `ROLE` reports.
+    /// A replica is made read-only too.
+    pub fn with_replica(mut self, replica: bool) -> EmbeddedCache {
+        self.replica = replica;
+        match replica {
+            true => self.with_read_only(true),
+            false => self,
+        }
+    }
+
+    /// Limit the keys of the given namespaces (the keys prefixed with `<namespace>:`), so that the writes to a
+    /// namespace over its quota are rejected with `CacheError::QuotaExceeded` or evict the least recently
+    /// written keys of the same namespace, never those of the other namespaces.
+    pub fn with_namespace_quotas(mut self, quotas: Vec<(String, NamespaceQuota)>) -> EmbeddedCache {
+        self.quotas = NamespaceQuotas::new(quotas);
+        self
+    }
+
+    /// Keep the values removed by `remove` and `clear_prefix` (or `clear_prefix_in_background`) as tombstones
+    /// for the given number of seconds, during which `undelete` restores them, e.g. after a mistaken bulk
+    /// deletion. The background sweeps then purge them for good. Zero, the default, removes the values
+    /// right away. The tombstones hold their values, so they take memory until purged.
+    pub fn with_tombstone_window(mut self, seconds: u64) -> EmbeddedCache {
+        self.tombstone_window = Some(seconds).filter(|&seconds| seconds > 0);
+        self
+    }
+
+    /// How long the removed values can be restored, in seconds, if soft delete is enabled (see
+    /// `with_tombstone_window`).
+    pub fn tombstone_window(&self) -> Option<u64> {
+        self.tombstone_window
+    }
+
+    /// Keep the last `max_versions` values written to each key, and its removals, for `versions` to tell
+    /// what was cached when. The oldest versions are dropped once all the keys take more than `max_bytes`
+    /// bytes of keys and values. Zero versions, the default, keeps no history.
+    pub fn with_version_history(mut self, max_versions: usize, max_bytes: usize) -> EmbeddedCache {
+        self.history = match max_versions {
+            0 => None,
+            _ => Some(VersionHistory::new(max_versions, max_bytes)),
+        };
+        self
+    }
+
+    /// Restart the TTL of a value whenever it is read (see `touch`) if `sliding`, so that the values in use
+    /// (e.g. sessions) stay cached while the others expire. Every read then takes the write lock of the
+    /// value. A read-only replica leaves the TTLs to its primary.
+    pub fn with_sliding_expiration(mut self, sliding: bool) -> EmbeddedCache {
+        self.sliding_expiration = sliding;
+        self
+    }
+
+    /// Split the keys into the given number of logical databases, which the clients of the server switch
+    /// between with `SELECT <index>`. The keys of database 0 are cached as they are, and those of the others
+    /// behind the prefix of their database (see `namespaces::database_prefix`). One, the default, keeps a
+    /// single database.
+    pub fn with_databases(mut self, databases: usize) -> EmbeddedCache {
+        self.databases = databases.max(1);
+        self
+    }
+
+    /// The number of logical databases (see `with_databases`).
+    pub fn databases(&self) -> usize {
+        self.databases
+    }
+
+    /// Whether the writes of the clients are rejected (see `with_read_only`).
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Whether the cache replicates a primary (see `with_replica`).
+    pub fn is_replica(&self) -> bool {
+        self.replica
+    }
+
+    /// How long ago the primary was last heard from, in seconds, while the cache replicates it (see
+    /// `CacheServer::with_replica_of`). A Redis primary pings its replicas every 10 seconds when idle.
+    ///
+    /// # Returns
+    /// * `None` if the cache is not a replica, or if its link to the primary is down.
+    pub fn replication_lag(&self) -> Option<u64> {
+        match self.replicated_at.load(Ordering::Relaxed) {
+            0 => None,
+            replicated_at => Some(current_walltime().saturating_sub(replicated_at) / 1000),
+        }
+    }
+
+    /// Record that the primary has just been heard from.
+    pub(crate) fn mark_replicated(&self) {
+        self.replicated_at
+            .store(current_walltime(), Ordering::Relaxed);
+    }
+
+    /// Record that the link to the primary is down.
+    pub(crate) fn mark_replication_lost(&self) {
+        self.replicated_at.store(0, Ordering::Relaxed);
+    }
+
+    /// Whether the versions of the keys are kept (see `with_version_history`).
+    pub fn keeps_versions(&self) -> bool {
+        self.history.is_some()
+    }
+
+    /// Make the writes made so far durable, if the storage engine persists the data (see `Cache::flush`).
+    ///
+    /// # Errors
+    /// * `CacheError::Storage` if the data cannot be written out.
+    pub fn flush(&self) -> CacheResult<()> {
+        if let Some(log) = &self.append_only_log {
+            log.sync()?;
+        }
+        self.cache.flush()
+    }
+
+    /// The append-only log the writes are recorded to, if enabled (see `from_config`).
+    pub(crate) fn append_only_log(&self) -> Option<&Arc<AppendOnlyLog>> {
+        self.append_only_log.as_ref()
+    }
+
+    /// The stream of the writes to the replicas attached, if they are accepted (see `from_config`).
+    pub(crate) fn replication_feed(&self) -> Option<&Arc<ReplicationFeed>> {
+        self.replication_feed.as_ref()
+    }
+
+    /// Rewrite the append-only log on a thread of its own into a compacted one, holding a `SET` for each
+    /// live key and a `SETNULL` for each key known to be absent, which then replaces it (see `AppendOnlyLog::rewrite`). The failures are logged.
+    ///
+    /// Called holding the exclusive side of the transaction lock (see `transaction_lock`), so that no
+    /// command of a client is half applied when the rewrite starts.
+    ///
+    /// # Returns
+    /// * Whether the rewrite has started (`false` if another one is in progress).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if there is no append-only log.
+    /// * `CacheError::Storage` if the thread cannot be spawned.
+    pub(crate) fn rewrite_append_only_log(&self) -> CacheResult<bool> {
+        let Some(log) = &self.append_only_log else {
+            return Err(CacheError::Unsupported("rewrite_append_only_log"));
+        };
+        if !log.start_rewrite()? {
+            return Ok(false);
+        }
+
+        let rewritten: Arc<AppendOnlyLog> = Arc::clone(log);
+        let cache: Arc<ServerCache> = Arc::clone(&self.cache);
+        let negatives: Arc<dyn Cache<String, ()>> = Arc::clone(&self.negatives);
+        let spawned = thread::Builder::new()
+            .name("aof-rewrite".to_string())
+            .spawn(move || {
+                let started: Instant = Instant::now();
+                match rewritten.rewrite(&*cache, &*negatives) {
+                    Ok(keys) => info!(
+                        "Rewrote the append-only log {} with {} key(s) in {:?}",
+                        rewritten.path().display(),
+                        keys,
+                        started.elapsed()
+                    ),
+                    Err(e) => error!("Failed to rewrite the append-only log: {}", e),
+                }
+            });
+        if let Err(e) = spawned {
+            log.abort_rewrite()?;
+            return Err(e.into());
+        }
+        Ok(true)
+    }
+
+    /// The memory taken by the entries, if the storage engine accounts for it (see `Cache::memory_stats`).
+    pub fn memory_stats(&self) -> Option<MemoryStats> {
+        self.cache.memory_stats()
+    }
+
+    /// Change the most bytes the entries of the storage engine may take (0 for no limit), e.g. with
+    /// `CONFIG SET`. Lowering it evicts the entries beyond right away, unless the policy is `noeviction`
+    /// (see `Cache::set_max_memory`).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the limit is not 0 and the storage engine does not account for its
+    ///   memory (only the `lru` and `lfu` engines do).
+    pub fn set_max_memory(&self, max_bytes: usize) -> CacheResult<()> {
+        let mut max_memory = self.max_memory.lock()?;
+        self._apply_max_memory(max_bytes, max_memory.1)?;
+        max_memory.0 = max_bytes;
+        Ok(())
+    }
+
+    /// Change what the storage engine does with the writes beyond its max memory (see `set_max_memory`).
+    pub fn set_max_memory_policy(&self, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        let mut max_memory = self.max_memory.lock()?;
+        self._apply_max_memory(max_memory.0, policy)?;
+        max_memory.1 = policy;
+        Ok(())
+    }
+
+    /// The most bytes the entries of the storage engine may take (0 for no limit), and what it does with
+    /// the writes beyond (see `set_max_memory`).
+    pub fn max_memory(&self) -> (usize, MaxMemoryPolicy) {
+        *self
+            .max_memory
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Bound the memory of the storage engine, which needs no bound to be left without one.
+    fn _apply_max_memory(&self, max_bytes: usize, policy: MaxMemoryPolicy) -> CacheResult<()> {
+        match self
+            .cache
+            .set_max_memory(Some(max_bytes).filter(|&max_bytes| max_bytes > 0), policy)
+        {
+            Err(CacheError::Unsupported(_)) if max_bytes == 0 => Ok(()),
+            result => result,
+        }
+    }
+
+    /// The bytes taken by the given key, its value and their bookkeeping, as answered by `MEMORY USAGE`:
+    /// as counted towards the max memory by the storage engines accounting for it (see `memory_stats`), and
+    /// estimated from the sizes of the key and the value with the others.
+    ///
+    /// # Returns
+    /// * `None` if the key has no value.
+    pub fn memory_usage(&self, key: &str) -> CacheResult<Option<usize>> {
+        let key: String = key.to_string();
+        match self.cache.memory_usage(&key) {
+            Err(CacheError::Unsupported(_)) => {}
+            usage => return usage,
+        }
+        let value: Option<Bytes> = self.cache.get(&key)?;
+        Ok(value.map(|value| estimated_entry_size(&key, &value)))
+    }
+
+    /// Whether the reads of the keys are tracked (see `key_access`).
+    pub fn tracks_access(&self) -> bool {
+        self.access.is_some()
+    }
+
+    /// When the given key was last read and how often, as answered by `OBJECT IDLETIME` and `OBJECT FREQ`
+    /// (see `AccessTrackingCache`).
+    ///
+    /// # Returns
+    /// * `None` if the key has no value.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the reads of the keys are not tracked (see
+    ///   `ServerConfig::track_access`).
+    pub fn key_access(&self, key: &str) -> CacheResult<Option<KeyAccess>> {
+        match &self.access {
+            Some(access) => access.access(&key.to_string()),
+            None => Err(CacheError::Unsupported("access tracking")),
+        }
+    }
+
+    /// How much the values written have been compressed (see `CompressingCache`), or `None` if they are
+    /// not (see `ServerConfig::compression_threshold`).
+    pub fn compression_stats(&self) -> Option<CompressionStats> {
+        self.compression
+            .as_ref()
+            .map(|compression| compression.stats())
+    }
+
+    /// Sum up the memory taken by the entries, as answered by `MEMORY STATS`. With a storage engine which
+    /// does not account for it, the bytes of every entry are estimated (see `memory_usage`), scanning the
+    /// whole cache.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine neither accounts for its memory nor can be scanned.
+    pub fn memory_report(&self) -> CacheResult<MemoryReport> {
+        if let Some(memory) = self.cache.memory_stats() {
+            return Ok(MemoryReport {
+                keys: self.cache.len()?,
+                used_bytes: memory.used_bytes,
+                max_bytes: memory.max_bytes,
+                evictions: memory.evictions,
+                estimated: false,
+            });
+        }
+        let mut report: MemoryReport = MemoryReport {
+            estimated: true,
+            ..MemoryReport::default()
+        };
+        self.cache.scan(&mut |key, value| {
+            report.keys += 1;
+            report.used_bytes += estimated_entry_size(key, value);
+        })?;
+        Ok(report)
+    }
+
+    /// The counters of the cache: lookups, expirations, clients and commands.
+    pub fn stats(&self) -> &Arc<CacheStats> {
+        &self.stats
+    }
+
+    /// Keep the last `max_len` commands which took longer than `threshold_us` microseconds (every command if
+    /// zero) in the slow log, rather than the last 128 commands taking longer than 10 milliseconds. A
+    /// `max_len` of zero disables the log.
+    pub fn with_slow_log(self, threshold_us: u64, max_len: usize) -> EmbeddedCache {
+        self.slow_log.set_threshold_us(threshold_us);
+        if let Err(e) = self.slow_log.set_max_len(max_len) {
+            error!("Failed to size the slow log: {}", e);
+        }
+        self
+    }
+
+    /// The last commands which took longer than a threshold (see `with_slow_log`).
+    pub fn slow_log(&self) -> &SlowLog {
+        &self.slow_log
+    }
+
+    /// Record the write commands of the clients, with who sent them and whether they succeeded, to the
+    /// given audit log.
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> EmbeddedCache {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Count a command executed over the given protocol, as the last command of its client (see
+    /// `clients`), logging it if it was slow (see `with_slow_log`) or writes (see `with_audit_log`), and
+    /// feeding it to the clients which have sent `MONITOR`, if any.
+    ///
+    /// # Arguments
+    /// * `protocol` - The protocol the command was sent over.
+    /// * `elapsed` - How long the command took.
+    /// * `client` - The address of the client which sent the command.
+    /// * `user` - The user the client has authenticated as, if any.
+    /// * `failed` - Whether the command was answered with an error.
+    /// * `command` - Lists the command and its arguments.
+    pub(crate) fn record_command<'a>(
+        &self,
+        protocol: CommandProtocol,
+        elapsed: Duration,
+        client: impl Display,
+        user: Option<&str>,
+        failed: bool,
+        command: impl FnOnce() -> Vec<&'a [u8]>,
+    ) {
+        self.stats.record_command(protocol, elapsed);
+
+        let client: String = client.to_string();
+        let arguments: Vec<&'a [u8]> = command();
+        if let Some(name) = arguments.first() {
+            if let Err(e) = self.clients.record_command(&client, name) {
+                error!("Failed to count a command from {}: {}", client, e);
+            }
+        }
+        if self.monitors.has_subscribers() {
+            let line: String = monitor::format_command(protocol, &client, &arguments);
+            if let Err(e) = self
+                .monitors
+                .publish(MONITOR_CHANNEL, Bytes::from(line.into_bytes()))
+            {
+                error!(
+                    "Failed to feed a command from {} to the monitors: {}",
+                    client, e
+                );
+            }
+        }
+        if let Some(audit_log) = &self.audit_log {
+            let entry: AuditEntry = AuditEntry {
+                protocol,
+                client: &client,
+                user,
+                command: &arguments,
+                failed,
+            };
+            if let Err(e) = audit_log.record(&entry) {
+                error!("Failed to audit a command from {}: {}", client, e);
+            }
+        }
+        if let Err(e) = self.slow_log.record(elapsed, &client, || arguments) {
+            error!("Failed to log a slow command from {}: {}", client, e);
+        }
+    }
+
+    /// The client connections open across all the listeners, in the order they connected, as listed by
+    /// `CLIENT LIST`.
+    pub fn clients(&self) -> CacheResult<Vec<ClientInfo>> {
+        self.clients.list()
+    }
+
+    /// Disconnect the client connected from the given address, e.g. with `CLIENT KILL`.
+    ///
+    /// # Returns
+    /// * Whether a client was connected from the address.
+    pub fn kill_client(&self, address: &str) -> CacheResult<bool> {
+        self.clients.kill(address)
+    }
+
+    /// Register a client connection accepted by a listener, until the returned guard is dropped.
+    pub(crate) fn register_client(
+        &self,
+        protocol: &'static str,
+        stream: &ClientStream,
+    ) -> CacheResult<RegisteredClient> {
+        self.clients.register(protocol, stream)
+    }
+
+    /// The statistics reported by the `INFO` command, as named values: the uptime, the clients and the
+    /// commands, then the keys and the memory taken by the entries (if the storage engine accounts for
+    /// them), then the hits, the misses, the expired entries, the writes rejected for their size, the
+    /// compression of the values (if enabled) and the evicted entries.
+    pub fn info(&self) -> CacheResult<Vec<(&'static str, u64)>> {
+        let mut info: Vec<(&'static str, u64)> = vec![
+            ("uptime_seconds", self.stats.uptime().as_secs()),
+            ("connected_clients", self.stats.connected_clients() as u64),
+            ("commands_processed", self.stats.commands_processed()),
+        ];
+        match self.len() {
+            Ok(keys) => info.push(("keys", keys as u64)),
+            Err(CacheError::Unsupported(_)) => {}
+            Err(e) => return Err(e),
+        }
+        let memory: Option<MemoryStats> = self.memory_stats();
+        if let Some(memory) = &memory {
+            info.push(("used_memory", memory.used_bytes as u64));
+            info.push(("maxmemory", memory.max_bytes.unwrap_or(0) as u64));
+        }
+        info.push(("hits", self.stats.hits()));
+        info.push(("misses", self.stats.misses()));
+        info.push(("expired", self.stats.expired()));
+        info.push(("oversized_writes", self.stats.oversized_writes()));
+        if let Some(compression) = self.compression_stats() {
+            info.push(("compressed_writes", compression.compressed_writes));
+            info.push(("compression_saved_bytes", compression.saved_bytes()));
+        }
+        if let Some(memory) = &memory {
+            info.push(("evicted", memory.evictions));
+        }
+        Ok(info)
+    }
+
+    /// Create a new `EmbeddedCache` backed by the storage engine of an already validated configuration.
+    /// The network settings of the configuration are ignored.
+    ///
+    /// With a `compression_threshold`, the storage engine is wrapped first so that the large values are
+    /// compressed (see `CompressingCache`), and the log and the replicas see the original values. With
+    /// `track_access`, it is wrapped so that the reads of the keys are tracked (see `key_access`), and with
+    /// `accept_replicas`, so that every write is streamed to the replicas attached (see `ReplicationFeed`).
+    /// With an append-only log (`appendonly_path`), the log is replayed into the cache, which is then
+    /// wrapped so that every write is appended to the log before it is applied (see `AppendOnlyLog`).
+    ///
+    /// # Errors
+    /// * `CacheError::UnknownEngine` or `CacheError::Storage` if the storage engine cannot be set up, or the
+    ///   append-only log cannot be opened or replayed, or the audit log cannot be opened.
+    pub fn from_config(config: &ServerConfig) -> CacheResult<EmbeddedCache> {
+        let mut cache: Arc<ServerCache> = CacheBuilder::new()
+            .engine(&config.storage_engine)
+            .engine_options(config.engine_options())
+            .build()?;
+        info!("Using the {} storage engine", config.storage_engine);
+
+        if !config.namespace_engines.is_empty() {
+            let mut routed: RoutedCache<Bytes> = RoutedCache::new(cache);
+            for (namespace, engine) in &config.namespace_engines {
+                let options: EngineOptions = config.namespace_engine_options(namespace);
+                routed = routed
+                    .with_namespace(namespace, CacheFactory::new_cache_named(engine, &options)?);
+                info!(
+                    "Using the {} storage engine for namespace {}",
+                    engine, namespace
+                );
+            }
+            cache = Arc::new(routed);
+        }
+
+        let compression: Option<Arc<CompressingCache<String>>> = match config.compression_threshold
+        {
+            0 => None,
+            threshold => {
+                let compressing: Arc<CompressingCache<String>> =
+                    Arc::new(CompressingCache::new(cache, threshold));
+                cache = Arc::clone(&compressing) as _;
+                info!("Compressing the values of at least {} bytes", threshold);
+                Some(compressing)
+            }
+        };
+
+        let access: Option<Arc<AccessTrackingCache<String, Bytes>>> = match config.track_access {
+            true => {
+                let tracking: Arc<AccessTrackingCache<String, Bytes>> =
+                    Arc::new(AccessTrackingCache::new(cache));
+                cache = Arc::clone(&tracking) as _;
+                Some(tracking)
+            }
+            false => None,
+        };
+
+        let replication_feed: Option<Arc<ReplicationFeed>> = match config.accept_replicas {
+            true => {
+                let feed: Arc<ReplicationFeed> = Arc::new(ReplicationFeed::new());
+                cache = Arc::new(WriteThroughCache::new(cache, Arc::clone(&feed) as _));
+                Some(feed)
+            }
+            false => None,
+        };
+
+        let mut cache: EmbeddedCache =
+            EmbeddedCache::with_sweeper(cache, config.sweeper_options())?
+                .with_ttl_jitter(config.ttl_jitter_percent)
+                .with_max_sizes(config.max_key_bytes, config.max_value_bytes)
+                .with_namespace_quotas(config.namespace_quotas.clone())
+                .with_tombstone_window(config.tombstone_window_seconds)
+                .with_version_history(config.history_versions, config.history_bytes)
+                .with_sliding_expiration(config.sliding_expiration)
+                .with_databases(config.databases)
+                .with_keyspace_notifications(config.keyspace_notifications)
+                .with_script_time_limit(config.script_time_limit_ms)
+                .with_slow_log(config.slowlog_threshold_us, config.slowlog_max_len);
+        if let Some(sink) = &config.audit_log {
+            cache = cache.with_audit_log(AuditLog::open(
+                sink.clone(),
+                config.audit_log_max_size,
+                config.audit_log_max_files,
+            )?);
+        }
+        cache.max_memory = Mutex::new((config.maxmemory, config.maxmemory_policy));
+
+        // The log is replayed through the cache before it is wrapped, so that the records are not appended
+        // again
+        if let Some(path) = &config.appendonly_path {
+            let started: Instant = Instant::now();
+            let log: Arc<AppendOnlyLog> = AppendOnlyLog::open(path, config.appendfsync)?;
+            let stats: ReplayStats = log.replay(&cache)?;
+            info!(
+                "Replayed {} record(s) of the append-only log {} in {:?}{}",
+                stats.records,
+                path.display(),
+                started.elapsed(),
+                match stats.truncated {
+                    true => " (an incomplete record was cut off)",
+                    false => "",
+                }
+            );
+            cache.cache = Arc::new(WriteThroughCache::new(
+                Arc::clone(&cache.unlogged),
+                Arc::clone(&log) as _,
+            ));
+            cache.append_only_log = Some(log);
+        }
+        cache.replication_feed = replication_feed;
+        cache.access = access;
+        cache.compression = compression;
+        Ok(cache)
+    }
+
+    /// Look up the given key.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a string (e.g. a hash).
+    pub fn get(&self, key: &str) -> CacheResult<Lookup> {
+        let key: String = key.to_string();
+        let value: Option<Bytes> = self.cache.get(&key)?;
+        self._lookup(&key, value)
+    }
+
+    /// Look up the given keys at once, taking the lock of the cache once (see `Cache::get_many`).
+    ///
+    /// # Returns
+    /// * The lookup of every key, in the order of the keys (`Lookup::Missing` for the keys holding a value
+    ///   of another type than a string).
+    pub fn get_many(&self, keys: &[&str]) -> CacheResult<Vec<Lookup>> {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        let values: Vec<Option<Bytes>> = self.cache.get_many(&keys)?;
+        keys.iter()
+            .zip(values)
+            .map(|(key, value)| match self._lookup(key, value) {
+                Err(CacheError::WrongType) => Ok(Lookup::Missing),
+                lookup => lookup,
+            })
+            .collect()
+    }
+
+    /// List the keys cached with a value which match the given glob pattern (see `utils::glob_match`), e.g.
+    /// `session:*`. Every key of the cache is visited, so it is meant for inspecting the cache.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub fn keys(&self, pattern: &str) -> CacheResult<Vec<String>> {
+        let mut keys: Vec<String> = Vec::new();
+        self.cache.scan(&mut |key, _| {
+            if glob_match(pattern.as_bytes(), key.as_bytes()) {
+                keys.push(key.clone());
+            }
+        })?;
+        Ok(keys)
+    }
+
+    /// Pick keys cached with a value at random, each of the keys matching the given glob pattern (and
+    /// accepted by `accept`) being as likely to be picked as any other, e.g. to estimate what the keyspace is
+    /// made of without listing every key. The keys are sampled as they are scanned (reservoir sampling), so
+    /// every key is visited but only `count` of them are kept.
+    ///
+    /// # Arguments
+    /// * `pattern` - The glob pattern the keys picked match (see `utils::glob_match`).
+    /// * `count` - The number of distinct keys to pick, all of them being picked if fewer match.
+    /// * `accept` - Whether a key matching the pattern may be picked (e.g. if the client may access it).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub fn sample_keys(
+        &self,
+        pattern: &str,
+        count: usize,
+        accept: &dyn Fn(&str) -> bool,
+    ) -> CacheResult<Vec<String>> {
+        let mut sample: Vec<String> = Vec::with_capacity(count.min(1024));
+        let mut seen: u64 = 0;
+        self.cache.scan(&mut |key, _| {
+            if count == 0 || !glob_match(pattern.as_bytes(), key.as_bytes()) || !accept(key) {
+                return;
+            }
+            seen += 1;
+            if sample.len() < count {
+                sample.push(key.clone());
+            } else {
+                // The n-th key replaces one of the sample with a probability of count / n
+                let index: u64 = random_u64() % seen;
+                if index < count as u64 {
+                    sample[index as usize] = key.clone();
+                }
+            }
+        })?;
+        Ok(sample)
+    }
+
+    /// Iterate the keys cached with a value incrementally (see `Cache::scan_from`): unlike `keys`, every
+    /// call only locks the cache while it examines a batch of keys, so a large cache can be walked without
+    /// stalling the writers.
+    ///
+    /// # Arguments
+    /// * `cursor` - 0 to start the iteration, else the cursor returned by the previous call.
+    /// * `pattern` - The glob pattern the keys returned match (see `utils::glob_match`).
+    /// * `count` - The number of keys to examine, before they are matched (so fewer may be returned).
+    ///
+    /// # Returns
+    /// * The cursor of the next call (0 once the iteration is over), and the keys of this one.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine cannot be iterated incrementally.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        pattern: &str,
+        count: usize,
+    ) -> CacheResult<(u64, Vec<String>)> {
+        let mut keys: Vec<String> = Vec::new();
+        let next: u64 = self.cache.scan_from(cursor, count, &mut |key, _| {
+            if glob_match(pattern.as_bytes(), key.as_bytes()) {
+                keys.push(key.clone());
+            }
+        })?;
+        Ok((next, keys))
+    }
+
+    /// Count how many of the given keys are cached with a value, without reading the values. A key given
+    /// twice is counted twice.
+    pub fn exists(&self, keys: &[&str]) -> CacheResult<usize> {
+        let mut existing: usize = 0;
+        for key in keys {
+            if self.cache.expiration(&key.to_string())?.is_some() {
+                existing += 1;
+            }
+        }
+        Ok(existing)
+    }
+
+    /// The type of the value of the given key (see `ValueType`), or `None` if the key has no value.
+    pub fn value_type(&self, key: &str) -> CacheResult<Option<ValueType>> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(value.as_ref().map(ValueType::of))
+    }
+
+    /// Tell how the given key is cached, given its value in the cache (if any).
+    fn _lookup(&self, key: &String, value: Option<Bytes>) -> CacheResult<Lookup> {
+        self.stats.record_lookup(value.is_some());
+        if let Some(value) = value {
+            values::expect_string(Some(&value))?;
+            let value: Bytes = values::decode_string(value);
+            if self.sliding_expiration && !self.is_read_only() {
+                self._touch(key)?;
+            }
+            return match self.soft_expiry.get(key)? {
+                Some(stale_at) if current_monotime() >= stale_at => Ok(Lookup::Stale(value)),
+                _ => Ok(Lookup::Fresh(value)),
+            };
+        }
+
+        if self.negatives.get(key)?.is_some() {
+            return Ok(Lookup::Negative);
+        }
+        Ok(Lookup::Missing)
+    }
+
+    /// Look up the given key with stampede protection: on a miss, the first caller acquires the recompute
+    /// lease of the key and receives `Lookup::Recompute`, while the other callers wait (for at most
+    /// `lock_ttl` seconds) for the value to be put.
+    ///
+    /// # Arguments
+    /// * `key` - The key to look up.
+    /// * `lock_ttl` - How long (in seconds) the recomputing caller is given to put the value, which is also
+    ///   the longest time the other callers wait for it.
+    ///
+    /// # Returns
+    /// * `Lookup::Missing` if the value has not shown up in time.
+    pub fn get_or_lock(&self, key: &str, lock_ttl: u64) -> CacheResult<Lookup> {
+        let lookup: Lookup = self.get(key)?;
+        if lookup != Lookup::Missing {
+            return Ok(lookup);
+        }
+
+        let key: String = key.to_string();
+        let timeout: Duration = Duration::from_secs(lock_ttl);
+        let deadline: u64 = current_monotime().saturating_add(lock_ttl.saturating_mul(1000));
+
+        loop {
+            if self.leases.try_acquire(&key, lock_ttl)? {
+                return Ok(Lookup::Recompute);
+            }
+
+            self.leases.wait(&key, timeout)?;
+            let lookup: Lookup = self.get(&key)?;
+
+            // The recomputing caller gave up (or its lease expired) without a value, so retry until we run
+            // out of time
+            if lookup != Lookup::Missing || current_monotime() >= deadline {
+                return Ok(lookup);
+            }
+        }
+    }
+
+    /// Put a value into the cache, releasing the recompute lease of the key (if any). The TTLs are spread
+    /// by the jitter of the cache (see `with_ttl_jitter`).
+    ///
+    /// # Arguments
+    /// * `key` - The key to associate with the value.
+    /// * `value` - The value to store in the cache.
+    /// * `ttl` - An optional time-to-live (in seconds) after which the value expires.
+    /// * `soft_ttl` - An optional time-to-live (in seconds) after which the value is still served, but as
+    ///   `Lookup::Stale`. It is only meaningful when shorter than `ttl`.
+    pub fn put(
+        &self,
+        key: &str,
+        value: Bytes,
+        ttl: Option<u64>,
+        soft_ttl: Option<u64>,
+    ) -> CacheResult<()> {
+        self.put_with_jitter(key, value, ttl, soft_ttl, self.ttl_jitter())
+    }
+
+    /// Put a value into the cache like `put`, but spread the TTLs by the given jitter (in percent, at most
+    /// `MAX_TTL_JITTER`) instead of the jitter of the cache. Both TTLs are spread in the same proportion, so
+    /// the soft TTL stays the shorter one.
+    pub fn put_with_jitter(
+        &self,
+        key: &str,
+        value: Bytes,
+        ttl: Option<u64>,
+        soft_ttl: Option<u64>,
+        jitter: u32,
+    ) -> CacheResult<()> {
+        self._check_writable()?;
+        let (ttl, soft_ttl): (Option<u64>, Option<u64>) = jitter_ttls(ttl, soft_ttl, jitter);
+        let value: Bytes = values::encode_string(value);
+        self._put(key, value, ttl, soft_ttl, true, None).map(|_| ())
+    }
+
+    /// Put the given values into the cache without a TTL, as a single write (see `Cache::put_many`): a
+    /// reader sees either none or all of them, unless the storage engine puts them shard by shard.
+    pub fn put_many(&self, entries: Vec<(&str, Bytes)>) -> CacheResult<()> {
+        self._check_writable()?;
+        let entries: Vec<(String, Bytes)> = entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), values::encode_string(value)))
+            .collect();
+
+        for (key, value) in &entries {
+            self._check_sizes(key, value.len())?;
+        }
+        for (key, value) in &entries {
+            for evicted in self.quotas.admit(key, key.len() + value.len(), None)? {
+                self._evict(&evicted)?;
+            }
+        }
+        for (key, _) in &entries {
+            self.flushes.take(key)?;
+        }
+        self.cache.put_many(entries.clone(), None)?;
+
+        for (key, value) in entries {
+            if let Some(history) = &self.history {
+                history.record(&key, Some(value))?;
+            }
+            self.negatives.remove(&key)?;
+            self.soft_expiry.remove(&key)?;
+            self.events.track_expiration(&key, None)?;
+            self.events.publish(KeyspaceEventKind::Set, &key)?;
+            self.leases.release(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Put a value into the cache without a TTL, returning the value it replaces, as a single step: no other
+    /// write to the key can come in between (see `Cache::replace`).
+    ///
+    /// # Returns
+    /// * The previous value of the key, or `None` if it had none.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a string, which is left
+    ///   unchanged (its type being checked in the same step as the value is replaced).
+    pub fn get_set(&self, key: &str, value: Bytes) -> CacheResult<Option<Bytes>> {
+        self._check_writable()?;
+        let key: String = key.to_string();
+        let value: Bytes = values::encode_string(value);
+        self._check_sizes(&key, value.len())?;
+
+        // Checked before the quotas, so that a declined write is not counted. The write itself checks it
+        // again, as the key may have been written to in between.
+        values::expect_string(self.cache.get(&key)?.as_ref())?;
+
+        for evicted in self.quotas.admit(&key, key.len() + value.len(), None)? {
+            self._evict(&evicted)?;
+        }
+        self.flushes.take(&key)?;
+        let previous: Option<Bytes> =
+            self.cache
+                .replace_if(key.clone(), value.clone(), None, &mut |current| {
+                    values::expect_string(current)
+                })?;
+
+        if let Some(history) = &self.history {
+            history.record(&key, Some(value))?;
+        }
+        self.negatives.remove(&key)?;
+        self.soft_expiry.remove(&key)?;
+        self.events.track_expiration(&key, None)?;
+        self.events.publish(KeyspaceEventKind::Set, &key)?;
+        self.leases.release(&key)?;
+        Ok(previous.map(values::decode_string))
+    }
+
+    /// Put a value into the cache like `put`, only if the key is cached with a value (or is not), as a
+    /// single step: no other write to the key can come in between.
+    ///
+    /// # Returns
+    /// * Whether the value was put.
+    pub fn put_if(
+        &self,
+        key: &str,
+        value: Bytes,
+        ttl: Option<u64>,
+        soft_ttl: Option<u64>,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        self.put_if_with_jitter(key, value, ttl, soft_ttl, self.ttl_jitter(), condition)
+    }
+
+    /// Put a value into the cache like `put_if`, but spread the TTLs by the given jitter like
+    /// `put_with_jitter`.
+    pub fn put_if_with_jitter(
+        &self,
+        key: &str,
+        value: Bytes,
+        ttl: Option<u64>,
+        soft_ttl: Option<u64>,
+        jitter: u32,
+        condition: PutCondition,
+    ) -> CacheResult<bool> {
+        self._check_writable()?;
+        let (ttl, soft_ttl): (Option<u64>, Option<u64>) = jitter_ttls(ttl, soft_ttl, jitter);
+        let value: Bytes = values::encode_string(value);
+        self._put(key, value, ttl, soft_ttl, true, Some(condition))
+    }
+
+    /// Put a value, with its TTLs already spread, unless the condition (if any) declines it.
+    fn _put(
+        &self,
+        key: &str,
+        value: Bytes,
+        ttl: Option<u64>,
+        soft_ttl: Option<u64>,
+        enforce_quota: bool,
+        condition: Option<PutCondition>,
+    ) -> CacheResult<bool> {
+        let key: String = key.to_string();
+
+        // Checked before the quotas, so that a declined write is not counted. The write itself checks it
+        // again, as the key may have been written to in between.
+        if let Some(condition) = condition {
+            if !condition.allows(self.cache.expiration(&key)?.is_some()) {
+                return Ok(false);
+            }
+        }
+
+        let size: usize = key.len() + value.len();
+        match enforce_quota {
+            true => {
+                self._check_sizes(&key, value.len())?;
+                for evicted in self.quotas.admit(&key, size, expiration_time(ttl))? {
+                    self._evict(&evicted)?;
+                }
+            }
+            false => self.quotas.record(&key, size, expiration_time(ttl))?,
+        }
+
+        self.flushes.take(&key)?;
+        match condition {
+            Some(condition) => {
+                if !self
+                    .cache
+                    .put_if(key.clone(), value.clone(), ttl, condition)?
+                {
+                    return Ok(false);
+                }
+            }
+            None => self.cache.put(key.clone(), value.clone(), ttl)?,
+        }
+        if let Some(history) = &self.history {
+            history.record(&key, Some(value))?;
+        }
+        self.negatives.remove(&key)?;
+
+        match soft_ttl {
+            Some(soft_ttl) => {
+                let stale_at: u64 =
+                    current_monotime().saturating_add(soft_ttl.saturating_mul(1000));
+                self.soft_expiry.put(key.clone(), stale_at, ttl)?;
+            }
+            None => {
+                self.soft_expiry.remove(&key)?;
+            }
+        }
+
+        self.events.track_expiration(&key, expiration_time(ttl))?;
+        self.events.publish(KeyspaceEventKind::Set, &key)?;
+        self.leases.release(&key)?;
+        Ok(true)
+    }
+
+    /// Cache the given key as known to be absent (negative caching), replacing its value if any. The TTL is
+    /// spread by the jitter of the cache (see `with_ttl_jitter`).
+    ///
+    /// # Arguments
+    /// * `key` - The key known to be absent.
+    /// * `ttl` - An optional time-to-live (in seconds) after which the key is no longer cached.
+    pub fn put_negative(&self, key: &str, ttl: Option<u64>) -> CacheResult<()> {
+        self._check_writable()?;
+        let key: String = key.to_string();
+        let factor: f64 = jitter_factor(self.ttl_jitter());
+        let ttl: Option<u64> = ttl.map(|ttl| apply_jitter(ttl, factor));
+        self.flushes.take(&key)?;
+
+        // A key is either cached with a value or cached as missing, never both
+        if let (Some(_), Some(history)) = (self.cache.remove(&key)?, &self.history) {
+            history.record(&key, None)?;
+        }
+        self.quotas.forget(&key)?;
+        self.soft_expiry.remove(&key)?;
+        if let Some(log) = &self.append_only_log {
+            log.append_negative(&key, expiration_time(ttl))?;
+        }
+        self.negatives.put(key.clone(), (), ttl)?;
+
+        self.events.track_expiration(&key, None)?;
+        self.events.publish(KeyspaceEventKind::Del, &key)?;
+        self.leases.release(&key)
+    }
+
+    /// Change the TTL of the value of the given key (and of its soft TTL, if any, which still elapses at the
+    /// same time), or remove it if `ttl` is `None`. The TTL is spread by the jitter of the cache (see
+    /// `with_ttl_jitter`).
+    ///
+    /// # Returns
+    /// * `false` if the key is not cached with a value.
+    pub fn expire(&self, key: &str, ttl: Option<u64>) -> CacheResult<bool> {
+        self._check_writable()?;
+        let ttl: Option<u64> = ttl.map(|ttl| apply_jitter(ttl, jitter_factor(self.ttl_jitter())));
+        self._expire(key, ttl)
+    }
+
+    /// Expire the value of the given key at the given wall-clock time (in milliseconds since the Unix epoch,
+    /// rounded up to the second), or remove it right away if that time has passed. Unlike `expire`, the time
+    /// is not spread by the jitter of the cache.
+    ///
+    /// # Returns
+    /// * `false` if the key is not cached with a value.
+    pub fn expire_at(&self, key: &str, at: u64) -> CacheResult<bool> {
+        self._check_writable()?;
+        match at.checked_sub(current_walltime()) {
+            Some(remaining) if remaining > 0 => self._expire(key, Some(remaining.div_ceil(1000))),
+            _ => Ok(self.remove(key)?.is_some()),
+        }
+    }
+
+    fn _expire(&self, key: &str, ttl: Option<u64>) -> CacheResult<bool> {
+        let key: String = key.to_string();
+        if !self.cache.expire(&key, ttl)? {
+            return Ok(false);
+        }
+
+        self.quotas.set_expiration(&key, expiration_time(ttl))?;
+        self.events.track_expiration(&key, expiration_time(ttl))?;
+        self.events.touch_watched(&key)?;
+        self.soft_expiry.expire(&key, ttl)?;
+        Ok(true)
+    }
+
+    /// Restart the TTL of the values of the given keys from now, for the TTL they were given when put (or by
+    /// `expire`). The values without a TTL are left as they are.
+    ///
+    /// # Returns
+    /// * The number of keys cached with a value. A key given twice is counted twice.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine does not keep the TTLs of its values.
+    pub fn touch(&self, keys: &[&str]) -> CacheResult<usize> {
+        self._check_writable()?;
+        let mut touched: usize = 0;
+        for key in keys {
+            if self._touch(&key.to_string())? {
+                touched += 1;
+            }
+        }
+        Ok(touched)
+    }
+
+    fn _touch(&self, key: &String) -> CacheResult<bool> {
+        if !self.cache.touch(key)? {
+            return Ok(false);
+        }
+
+        // The soft TTL, if any, still elapses at the same time, but its record lives as long as the value
+        let expires_at: Option<u64> = self.cache.expiration(key)?.flatten();
+        self.quotas.set_expiration(key, expires_at)?;
+        self.events.track_expiration(key, expires_at)?;
+        self.soft_expiry.touch(key)?;
+        Ok(true)
+    }
+
+    /// How long the value of the given key has left to live, in milliseconds.
+    ///
+    /// # Returns
+    /// * `None` if the key is not cached with a value, or `Some(None)` if its value has no TTL.
+    pub fn remaining_ttl(&self, key: &str) -> CacheResult<Option<Option<u64>>> {
+        let expiration: Option<Option<u64>> = self.cache.expiration(&key.to_string())?;
+        let now: u64 = current_monotime();
+        Ok(
+            expiration
+                .map(|expires_at| expires_at.map(|expires_at| expires_at.saturating_sub(now))),
+        )
+    }
+
+    /// When the value of the given key expires, as a wall-clock time in milliseconds since the Unix epoch,
+    /// which unlike the monotonic expiration times of the storage engines stays meaningful across restarts
+    /// and nodes.
+    ///
+    /// # Returns
+    /// * `None` if the key is not cached with a value, or `Some(None)` if its value has no TTL.
+    pub fn expire_time(&self, key: &str) -> CacheResult<Option<Option<u64>>> {
+        let expiration: Option<Option<u64>> = self.cache.expiration(&key.to_string())?;
+        Ok(expiration.map(|expires_at| expires_at.map(monotime_to_walltime)))
+    }
+
+    /// Add the given delta to the integer held by the given key, as a single step (a missing key counting
+    /// as 0). The value is kept as its decimal digits, with its TTL if any.
+    ///
+    /// # Returns
+    /// * The new value of the counter.
+    ///
+    /// # Errors
+    /// * `CacheError::NotAnInteger` if the value is not a 64-bit decimal integer, or if the sum overflows.
+    pub fn incr_by(&self, key: &str, delta: i64) -> CacheResult<i64> {
+        let mut counter: i64 = 0;
+        self._update(key, &mut |current| {
+            values::expect_string(current)?;
+            let value: i64 = match current {
+                Some(current) => std::str::from_utf8(current)
+                    .ok()
+                    .and_then(|text| text.parse::<i64>().ok())
+                    .ok_or(CacheError::NotAnInteger)?,
+                None => 0,
+            };
+            counter = value.checked_add(delta).ok_or(CacheError::NotAnInteger)?;
+            Ok(Bytes::from(counter.to_string().into_bytes()))
+        })?;
+        Ok(counter)
+    }
+
+    /// Append the given suffix to the value of the given key as a single step, the key being given the
+    /// suffix as its value if it has none. The value keeps its TTL, if any.
+    ///
+    /// # Returns
+    /// * The length of the new value, in bytes.
+    pub fn append(&self, key: &str, suffix: &[u8]) -> CacheResult<usize> {
+        let mut length: usize = 0;
+        self._update(key, &mut |current| {
+            values::expect_string(current)?;
+            let mut value: Vec<u8> = current
+                .map(|current| values::decode_string(current.clone()).to_vec())
+                .unwrap_or_default();
+            value.extend_from_slice(suffix);
+            length = value.len();
+            Ok(values::encode_string(Bytes::from(value)))
+        })?;
+        Ok(length)
+    }
+
+    /// Set or clear the bit of the value of the given key at the given offset as a single step (see
+    /// `values::set_bit`), the value growing with zero bytes as needed (the key being given one if it has
+    /// none). The value keeps its TTL, if any. The offsets are meant to stay below
+    /// `values::MAX_BITMAP_BYTES` bytes.
+    ///
+    /// # Returns
+    /// * The previous bit.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a string.
+    pub fn setbit(&self, key: &str, offset: usize, bit: bool) -> CacheResult<bool> {
+        let mut previous: bool = false;
+        self._update(key, &mut |current| {
+            values::expect_string(current)?;
+            let mut value: Vec<u8> = current
+                .map(|current| values::decode_string(current.clone()).to_vec())
+                .unwrap_or_default();
+            previous = values::set_bit(&mut value, offset, bit);
+            Ok(values::encode_string(Bytes::from(value)))
+        })?;
+        Ok(previous)
+    }
+
+    /// The bit of the value of the given key at the given offset (see `values::get_bit`), `false` if the
+    /// key has no value.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a string.
+    pub fn getbit(&self, key: &str, offset: usize) -> CacheResult<bool> {
+        match self.get(key)? {
+            Lookup::Fresh(value) | Lookup::Stale(value) => Ok(values::get_bit(&value, offset)),
+            _ => Ok(false),
+        }
+    }
+
+    /// The number of bits set in the value of the given key, from the `start` byte to the `stop` one (see
+    /// `values::count_bits`), 0 if the key has no value.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a string.
+    pub fn bitcount(&self, key: &str, start: i64, stop: i64) -> CacheResult<u64> {
+        match self.get(key)? {
+            Lookup::Fresh(value) | Lookup::Stale(value) => {
+                Ok(values::count_bits(&value, start, stop))
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// Set fields of the hash of the given key as a single step, the key being given a new hash if it has
+    /// no value. The value keeps its TTL, if any.
+    ///
+    /// # Returns
+    /// * The number of fields added (rather than changed).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a hash.
+    pub fn hset(&self, key: &str, fields: &[(&[u8], Bytes)]) -> CacheResult<usize> {
+        let arguments = fields
+            .iter()
+            .flat_map(|(field, value)| [*field, value.as_ref()]);
+        let command: Vec<&[u8]> = logged_command(b"HSET", key, arguments);
+        let mut added: usize = 0;
+        self._update_collection(key, &command, &mut |hash: &mut Hash| {
+            added = fields
+                .iter()
+                .filter(|(field, value)| hash.set(field, value.clone()))
+                .count();
+            Ok(())
+        })?;
+        Ok(added)
+    }
+
+    /// The value of a field of the hash of the given key, if the key has a value and the field is set.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a hash.
+    pub fn hget(&self, key: &str, field: &[u8]) -> CacheResult<Option<Bytes>> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(Hash::read(value.as_ref())?.get(field).cloned())
+    }
+
+    /// The fields of the hash of the given key and their values, in the order the fields were first set
+    /// (none if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a hash.
+    pub fn hgetall(&self, key: &str) -> CacheResult<Vec<(Bytes, Bytes)>> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(Hash::read(value.as_ref())?.fields().to_vec())
+    }
+
+    /// Remove fields of the hash of the given key as a single step, the key being removed along with the
+    /// last field of its hash.
+    ///
+    /// # Returns
+    /// * The number of fields removed.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a hash.
+    pub fn hdel(&self, key: &str, fields: &[&[u8]]) -> CacheResult<usize> {
+        let current: Option<Bytes> = self.cache.get(&key.to_string())?;
+        if Hash::read(current.as_ref())?.is_empty() {
+            return Ok(0);
+        }
+        drop(current); // Shares the value, which the write would then copy to modify it
+
+        let command: Vec<&[u8]> = logged_command(b"HDEL", key, fields.iter().copied());
+        let mut removed: usize = 0;
+        self._update_collection(key, &command, &mut |hash: &mut Hash| {
+            removed = fields.iter().filter(|field| hash.remove(field)).count();
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+
+    /// Push values at the head (`left`) or the tail of the list of the given key as a single step, the key
+    /// being given a new list if it has no value. The values are pushed one after the other, so pushing
+    /// `a b c` at the head makes the list start with `c b a`. The value keeps its TTL, if any. The values
+    /// pushed are then handed over to the callers waiting for them (see `list_pop_or_wait`), if any.
+    ///
+    /// # Returns
+    /// * The length of the list, before any value is handed over.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a list.
+    pub fn list_push(&self, key: &str, items: &[Bytes], left: bool) -> CacheResult<usize> {
+        let name: &[u8] = if left { b"LPUSH" } else { b"RPUSH" };
+        let command: Vec<&[u8]> = logged_command(name, key, items.iter().map(|item| item.as_ref()));
+        let mut length: usize = 0;
+        self._update_collection(key, &command, &mut |list: &mut List| {
+            for item in items {
+                list.push(item.clone(), left);
+            }
+            length = list.len();
+            Ok(())
+        })?;
+
+        self.list_waiters
+            .serve(key, &mut |key, left| self._list_pop_one(key, left))?;
+        Ok(length)
+    }
+
+    /// Pop up to `count` values from the head (`left`) or the tail of the list of the given key as a single
+    /// step, the key being removed along with the last value of its list.
+    ///
+    /// # Returns
+    /// * The values popped, in the order they were popped (none if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a list.
+    pub fn list_pop(&self, key: &str, count: usize, left: bool) -> CacheResult<Vec<Bytes>> {
+        let current: Option<Bytes> = self.cache.get(&key.to_string())?;
+        if List::read(current.as_ref())?.is_empty() || count == 0 {
+            return Ok(Vec::new());
+        }
+        drop(current); // Shares the value, which the write would then copy to modify it
+
+        let name: &[u8] = if left { b"LPOP" } else { b"RPOP" };
+        let count_argument: String = count.to_string();
+        let command: Vec<&[u8]> = logged_command(name, key, [count_argument.as_bytes()]);
+        let mut popped: Vec<Bytes> = Vec::new();
+        self._update_collection(key, &command, &mut |list: &mut List| {
+            popped = (0..count).map_while(|_| list.pop(left)).collect();
+            Ok(())
+        })?;
+        Ok(popped)
+    }
+
+    /// Pop a value from the head (`left`) or the tail of the first non-empty list of the given keys or, if
+    /// all of them are empty, start waiting for the next value pushed to any of them with `list_push`
+    /// (`BLPOP` and `BRPOP`). The values pushed are handed over to the callers waiting for them in the order
+    /// they started waiting, one each.
+    ///
+    /// # Returns
+    /// * The index of the key and the value popped from its list, or the waiter to wait with.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if a key holds a value of another type than a list.
+    pub fn list_pop_or_wait(&self, keys: &[&str], left: bool) -> CacheResult<BlockingPop> {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        self.list_waiters
+            .pop_or_wait(&keys, left, &mut |key, left| self._list_pop_one(key, left))
+    }
+
+    /// Pop a value from the head (`left`) or the tail of the first non-empty list of the given keys,
+    /// waiting for one to be pushed to any of them for at most the given time (or forever if `None`) if all
+    /// of them are empty (see `list_pop_or_wait`).
+    ///
+    /// # Returns
+    /// * The index of the key and the value popped from its list, or `None` if none was pushed in time.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if a key holds a value of another type than a list.
+    pub fn list_blocking_pop(
+        &self,
+        keys: &[&str],
+        left: bool,
+        timeout: Option<Duration>,
+    ) -> CacheResult<Option<(usize, Bytes)>> {
+        let waiter = match self.list_pop_or_wait(keys, left)? {
+            BlockingPop::Popped(index, value) => return Ok(Some((index, value))),
+            BlockingPop::Waiting(waiter) => waiter,
+        };
+        let deadline: Option<Instant> = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let wait: Duration = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => Duration::MAX,
+            };
+            if let Some(popped) = waiter.wait(wait)? {
+                return Ok(Some(popped));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return waiter.cancel();
+            }
+        }
+    }
+
+    /// The values of the list of the given key from the `start` index to the `stop` one, both included,
+    /// where negative indexes count from the tail (none if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a list.
+    pub fn list_range(&self, key: &str, start: i64, stop: i64) -> CacheResult<Vec<Bytes>> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(List::read(value.as_ref())?.range(start, stop))
+    }
+
+    /// Add members to the set of the given key as a single step, the key being given a new set if it has no
+    /// value. The value keeps its TTL, if any.
+    ///
+    /// # Returns
+    /// * The number of members added (rather than already in the set).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a set.
+    pub fn sadd(&self, key: &str, members: &[Bytes]) -> CacheResult<usize> {
+        let arguments = members.iter().map(|member| member.as_ref());
+        let command: Vec<&[u8]> = logged_command(b"SADD", key, arguments);
+        let mut added: usize = 0;
+        self._update_collection(key, &command, &mut |set: &mut Set| {
+            added = members
+                .iter()
+                .filter(|member| set.insert((*member).clone()))
+                .count();
+            Ok(())
+        })?;
+        Ok(added)
+    }
+
+    /// Remove members from the set of the given key as a single step, the key being removed along with the
+    /// last member of its set.
+    ///
+    /// # Returns
+    /// * The number of members removed.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a set.
+    pub fn srem(&self, key: &str, members: &[&[u8]]) -> CacheResult<usize> {
+        let current: Option<Bytes> = self.cache.get(&key.to_string())?;
+        if Set::read(current.as_ref())?.is_empty() {
+            return Ok(0);
+        }
+        drop(current); // Shares the value, which the write would then copy to modify it
+
+        let command: Vec<&[u8]> = logged_command(b"SREM", key, members.iter().copied());
+        let mut removed: usize = 0;
+        self._update_collection(key, &command, &mut |set: &mut Set| {
+            removed = members.iter().filter(|member| set.remove(member)).count();
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+
+    /// Whether the given member is in the set of the given key (`false` if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a set.
+    pub fn sismember(&self, key: &str, member: &[u8]) -> CacheResult<bool> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(Set::read(value.as_ref())?.contains(member))
+    }
+
+    /// The members of the set of the given key, in no particular order (none if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a set.
+    pub fn smembers(&self, key: &str) -> CacheResult<Vec<Bytes>> {
+        self.set_combine(&[key], SetOperation::Union)
+    }
+
+    /// Combine the sets of the given keys (see `Set::combine`), a key without a value counting as an empty
+    /// set. The sets are read together, but not as a single step.
+    ///
+    /// # Returns
+    /// * The members of the combined set, in no particular order.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if a key holds a value of another type than a set.
+    pub fn set_combine(&self, keys: &[&str], operation: SetOperation) -> CacheResult<Vec<Bytes>> {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        let values: Vec<Option<Bytes>> = self.cache.get_many(&keys)?;
+        let sets: Vec<Cow<Set>> = values
+            .iter()
+            .map(|value| Set::read(value.as_ref()))
+            .collect::<CacheResult<Vec<Cow<Set>>>>()?;
+        let sets: Vec<&Set> = sets.iter().map(|set| set.as_ref()).collect();
+        Ok(Set::combine(&sets, operation))
+    }
+
+    /// Add members with their scores to the sorted set of the given key (or change their scores) as a
+    /// single step, the key being given a new sorted set if it has no value. The value keeps its TTL, if
+    /// any.
+    ///
+    /// # Returns
+    /// * The number of members added (rather than already in the sorted set).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a sorted set.
+    pub fn zadd(&self, key: &str, members: &[(f64, &[u8])]) -> CacheResult<usize> {
+        let scores: Vec<String> = members.iter().map(|(score, _)| score.to_string()).collect();
+        let arguments = scores
+            .iter()
+            .zip(members)
+            .flat_map(|(score, (_, member))| [score.as_bytes(), *member]);
+        let command: Vec<&[u8]> = logged_command(b"ZADD", key, arguments);
+        let mut added: usize = 0;
+        self._update_collection(key, &command, &mut |set: &mut SortedSet| {
+            added = members
+                .iter()
+                .filter(|(score, member)| set.insert(member, *score))
+                .count();
+            Ok(())
+        })?;
+        Ok(added)
+    }
+
+    /// Add the given increment to the score of a member of the sorted set of the given key as a single
+    /// step, the member being added with the increment as its score if it is not in the sorted set (and the
+    /// key being given a new sorted set if it has no value). The value keeps its TTL, if any.
+    ///
+    /// # Returns
+    /// * The new score of the member.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a sorted set.
+    /// * `CacheError::NotANumber` if the new score would not be a number.
+    pub fn zincrby(&self, key: &str, member: &[u8], increment: f64) -> CacheResult<f64> {
+        let increment_argument: String = increment.to_string();
+        let command: Vec<&[u8]> =
+            logged_command(b"ZINCRBY", key, [increment_argument.as_bytes(), member]);
+        let mut score: f64 = 0.0;
+        self._update_collection(key, &command, &mut |set: &mut SortedSet| {
+            score = set.increment(member, increment)?;
+            Ok(())
+        })?;
+        Ok(score)
+    }
+
+    /// Remove members from the sorted set of the given key as a single step, the key being removed along
+    /// with the last member of its sorted set.
+    ///
+    /// # Returns
+    /// * The number of members removed.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a sorted set.
+    pub fn zrem(&self, key: &str, members: &[&[u8]]) -> CacheResult<usize> {
+        let current: Option<Bytes> = self.cache.get(&key.to_string())?;
+        if SortedSet::read(current.as_ref())?.is_empty() {
+            return Ok(0);
+        }
+        drop(current); // Shares the value, which the write would then copy to modify it
+
+        let command: Vec<&[u8]> = logged_command(b"ZREM", key, members.iter().copied());
+        let mut removed: usize = 0;
+        self._update_collection(key, &command, &mut |set: &mut SortedSet| {
+            removed = members.iter().filter(|member| set.remove(member)).count();
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+
+    /// The score of a member of the sorted set of the given key, if the key has a value and the member is
+    /// in its sorted set.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a sorted set.
+    pub fn zscore(&self, key: &str, member: &[u8]) -> CacheResult<Option<f64>> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(SortedSet::read(value.as_ref())?.score(member))
+    }
+
+    /// The members of the sorted set of the given key from the `start` rank to the `stop` one, both
+    /// included, with their scores, where negative ranks count from the highest score (none if the key has
+    /// no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a sorted set.
+    pub fn zrange(&self, key: &str, start: i64, stop: i64) -> CacheResult<Vec<(Bytes, f64)>> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(SortedSet::read(value.as_ref())?.range(start, stop))
+    }
+
+    /// The members of the sorted set of the given key whose scores are within the given bounds, with their
+    /// scores, by score (none if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a sorted set.
+    pub fn zrange_by_score(
+        &self,
+        key: &str,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> CacheResult<Vec<(Bytes, f64)>> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(SortedSet::read(value.as_ref())?.range_by_score(min, max))
+    }
+
+    /// Add elements to the HyperLogLog of the given key as a single step, the key being given a new
+    /// HyperLogLog if it has no value. The value keeps its TTL, if any.
+    ///
+    /// # Returns
+    /// * Whether the estimate of the HyperLogLog may have changed (or the key was given one).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a HyperLogLog.
+    pub fn pfadd(&self, key: &str, elements: &[&[u8]]) -> CacheResult<bool> {
+        let command: Vec<&[u8]> = logged_command(b"PFADD", key, elements.iter().copied());
+        let mut changed: bool = false;
+        self._update_as(key, Some(&command), &mut |current| {
+            let mut hll: HyperLogLog =
+                HyperLogLog::decode(current.map(|current| current.as_ref()))?;
+            changed = current.is_none();
+            for element in elements {
+                changed |= hll.add(element);
+            }
+            Ok(hll.encode())
+        })?;
+        Ok(changed)
+    }
+
+    /// The estimated number of distinct elements added to the HyperLogLogs of the given keys (0 for a key
+    /// without a value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if a key holds a value of another type than a HyperLogLog.
+    pub fn pfcount(&self, keys: &[&str]) -> CacheResult<u64> {
+        Ok(self._merged_hyperloglog(keys)?.count())
+    }
+
+    /// Merge the HyperLogLogs of the given source keys into the one of the destination key, which is given
+    /// a new HyperLogLog if it has no value (keeping its TTL otherwise). The sources are read together,
+    /// but not in the same step as the destination is written.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if a key holds a value of another type than a HyperLogLog.
+    pub fn pfmerge(&self, destination: &str, sources: &[&str]) -> CacheResult<()> {
+        let merged: HyperLogLog = self._merged_hyperloglog(sources)?;
+        self._update(destination, &mut |current| {
+            let mut hll: HyperLogLog =
+                HyperLogLog::decode(current.map(|current| current.as_ref()))?;
+            hll.merge(&merged);
+            Ok(hll.encode())
+        })?;
+        Ok(())
+    }
+
+    /// The HyperLogLogs of the given keys merged into one (see `HyperLogLog::merge`).
+    fn _merged_hyperloglog(&self, keys: &[&str]) -> CacheResult<HyperLogLog> {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        let mut merged: HyperLogLog = HyperLogLog::default();
+        for value in self.cache.get_many(&keys)? {
+            merged.merge(&HyperLogLog::decode(value.as_deref())?);
+        }
+        Ok(merged)
+    }
+
+    /// Give the given key an empty Bloom filter of the given sizing (see `BloomFilter::new`), unless it
+    /// already has a value.
+    ///
+    /// # Returns
+    /// * Whether the key was given the filter (`false` if it has a value already).
+    pub fn bf_reserve(&self, key: &str, filter: BloomFilter) -> CacheResult<bool> {
+        self._check_writable()?;
+        let condition: Option<PutCondition> = Some(PutCondition::IfAbsent);
+        self._put(key, filter.encode(), None, None, true, condition)
+    }
+
+    /// Add an element to the Bloom filter of the given key as a single step, the key being given a filter
+    /// of the default capacity and error rate if it has no value. The value keeps its TTL, if any.
+    ///
+    /// # Returns
+    /// * Whether the element was not in the filter yet (`false` if it may have been added already).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a Bloom filter.
+    pub fn bf_add(&self, key: &str, element: &[u8]) -> CacheResult<bool> {
+        let command: Vec<&[u8]> = logged_command(b"BF.ADD", key, [element]);
+        let mut added: bool = false;
+        self._update_as(key, Some(&command), &mut |current| {
+            let mut filter: BloomFilter =
+                BloomFilter::decode(current.map(|current| current.as_ref()))?;
+            added = filter.add(element);
+            Ok(filter.encode())
+        })?;
+        Ok(added)
+    }
+
+    /// Whether the element may have been added to the Bloom filter of the given key (`false` if it
+    /// certainly was not, or the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a Bloom filter.
+    pub fn bf_exists(&self, key: &str, element: &[u8]) -> CacheResult<bool> {
+        match self.cache.get(&key.to_string())? {
+            Some(value) => Ok(BloomFilter::decode(Some(&value))?.contains(element)),
+            None => Ok(false),
+        }
+    }
+
+    /// Take tokens from the token bucket of the given key as a single step, so that clients sharing a rate
+    /// limit cannot both take the last tokens. A key without a value is given a full bucket, and the key
+    /// then expires once the bucket is full again, when it would be the same as no bucket.
+    ///
+    /// # Arguments
+    /// * `key` - The key of the bucket, e.g. `ratelimit:api:alice`.
+    /// * `capacity` - The most tokens the bucket holds, at least 1.
+    /// * `refill_rate` - The tokens added per second, above 0.
+    /// * `cost` - The tokens to take, if there are enough left (0 only telling how many are left).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a token bucket.
+    pub fn rate_limit(
+        &self,
+        key: &str,
+        capacity: u64,
+        refill_rate: f64,
+        cost: u64,
+    ) -> CacheResult<RateDecision> {
+        let mut decision: RateDecision = RateDecision::default();
+        self._update(key, &mut |current| {
+            let now: u64 = current_walltime();
+            let mut bucket: RateBucket =
+                RateBucket::decode(current.map(|current| current.as_ref()), capacity, now)?;
+            decision = bucket.take(capacity, refill_rate, cost, now);
+            Ok(bucket.encode())
+        })?;
+        self._expire(key, Some(decision.full_after.div_ceil(1000).max(1)))?;
+        Ok(decision)
+    }
+
+    /// Append a value to the stream of the given key as a single step, the key being given a new stream if
+    /// it has no value, then trim the stream to its `max_len` newest entries, if given. The value keeps its
+    /// TTL, if any.
+    ///
+    /// # Returns
+    /// * The ID of the value, above the ones of all the values appended to the stream before it.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a stream.
+    pub fn xadd(&self, key: &str, value: Bytes, max_len: Option<usize>) -> CacheResult<StreamId> {
+        self._xadd(key, value, current_walltime(), max_len)
+    }
+
+    /// Append a value to the stream of the given key like `xadd`, its ID being made of the given walltime
+    /// (see `Stream::append`). The write is logged with the walltime, so that replaying it gives the value
+    /// the same ID.
+    fn _xadd(
+        &self,
+        key: &str,
+        value: Bytes,
+        now: u64,
+        max_len: Option<usize>,
+    ) -> CacheResult<StreamId> {
+        let (now_argument, max_len_argument): (String, Option<String>) =
+            (now.to_string(), max_len.map(|max_len| max_len.to_string()));
+        let arguments: Vec<&[u8]> = match &max_len_argument {
+            Some(max_len) => vec![
+                b"MAXLEN",
+                max_len.as_bytes(),
+                now_argument.as_bytes(),
+                &value,
+            ],
+            None => vec![now_argument.as_bytes(), &value],
+        };
+        let command: Vec<&[u8]> = logged_command(b"XADD", key, arguments);
+        let mut id: StreamId = StreamId::MIN;
+        self._update_as(key, Some(&command), &mut |current| {
+            let mut stream: Stream = Stream::decode(current.map(|current| current.as_ref()))?;
+            id = stream.append(value.clone(), now, max_len);
+            Ok(stream.encode())
+        })?;
+        Ok(id)
+    }
+
+    /// The entries of the stream of the given key whose IDs are from `start` to `end`, both included, up to
+    /// `count` of them if given (none if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a stream.
+    pub fn xrange(
+        &self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> CacheResult<Vec<(StreamId, Bytes)>> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(Stream::decode(value.as_deref())?.range(start, end, count))
+    }
+
+    /// The entries of the streams of the given keys appended after the given IDs (the last ones a consumer
+    /// read), up to `count` of them per stream if given. The streams are read together.
+    ///
+    /// # Returns
+    /// * The entries of every stream, in the order of the keys (none if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if a key holds a value of another type than a stream.
+    pub fn xread(
+        &self,
+        streams: &[(&str, StreamId)],
+        count: Option<usize>,
+    ) -> CacheResult<Vec<Vec<(StreamId, Bytes)>>> {
+        let keys: Vec<String> = streams.iter().map(|(key, _)| key.to_string()).collect();
+        let values: Vec<Option<Bytes>> = self.cache.get_many(&keys)?;
+        let mut entries: Vec<Vec<(StreamId, Bytes)>> = Vec::with_capacity(streams.len());
+        for ((_, after), value) in streams.iter().zip(values) {
+            let stream: Stream = Stream::decode(value.as_deref())?;
+            entries.push(match after.next() {
+                Some(start) => stream.range(start, StreamId::MAX, count),
+                None => Vec::new(),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// The number of entries of the stream of the given key (0 if the key has no value).
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a stream.
+    pub fn xlen(&self, key: &str) -> CacheResult<usize> {
+        let value: Option<Bytes> = self.cache.get(&key.to_string())?;
+        Ok(Stream::decode(value.as_deref())?.len())
+    }
+
+    /// Raise the given flag once the given key changes: is written, removed or given another TTL, or once the
+    /// cache is cleared (expiring only counts while the expired events are published, see `subscribe`).
+    /// Used by `WATCH`, to abort the transactions whose keys have changed.
+    pub(crate) fn watch(&self, key: &str, changed: &Arc<AtomicBool>) -> CacheResult<()> {
+        self.events.watch(key, changed)
+    }
+
+    /// Stop raising the given flag when the given keys change (see `watch`).
+    pub(crate) fn unwatch(&self, keys: &[String], changed: &Arc<AtomicBool>) -> CacheResult<()> {
+        self.events.unwatch(keys, changed)
+    }
+
+    /// Run a script (see `script::Script`) on the given keys, which it reads and writes with `redis.call`:
+    /// `GET`, `SET` (with `EX`, `PX`, `NX` and `XX`), `DEL`, `EXISTS`, `INCR`, `INCRBY`, `DECR`, `DECRBY`,
+    /// `EXPIRE` and `TTL`. The script only reaches the keys it is given, so that the permissions checked on
+    /// them hold. The servers run a script while holding `transaction_lock`, so that no command of another
+    /// client comes in between its commands. The writes of a script failing midway are kept.
+    ///
+    /// # Arguments
+    /// * `source` - The source of the script.
+    /// * `keys` - The keys of the script (its `KEYS` table), each with the key of the cache it stands for
+    ///   (e.g. within the selected database of the client).
+    /// * `args` - The other arguments of the script (its `ARGV` table).
+    ///
+    /// # Returns
+    /// * The reply of the script, i.e. the value it returns.
+    ///
+    /// # Errors
+    /// * `CacheError::Script` if the script is invalid, fails or exceeds its time limit (see
+    ///   `with_script_time_limit`).
+    pub fn eval(
+        &self,
+        source: &[u8],
+        keys: &[(&str, String)],
+        args: &[&[u8]],
+    ) -> CacheResult<Reply> {
+        let script: Script = Script::parse(source).map_err(CacheError::Script)?;
+        let names: Vec<&[u8]> = keys.iter().map(|(name, _)| name.as_bytes()).collect();
+        let time_limit: Duration = Duration::from_millis(self.script_time_limit());
+        let mut call = |command: &[Vec<u8>]| match self._script_command(command, keys) {
+            Ok(reply) => reply,
+            Err(CacheError::WrongType) => Reply::Error(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+            ),
+            Err(e) => Reply::Error(format!("ERR {}", e)),
+        };
+        script
+            .run(&names, args, time_limit, &mut call)
+            .map_err(CacheError::Script)
+    }
+
+    /// Execute a command called by a script (see `eval`), answering its invalid arguments as error replies.
+    fn _script_command(&self, command: &[Vec<u8>], keys: &[(&str, String)]) -> CacheResult<Reply> {
+        let name: String = String::from_utf8_lossy(&command[0]).to_ascii_uppercase();
+        let arguments: &[Vec<u8>] = &command[1..];
+        let key_count: usize = match name.as_str() {
+            "DEL" | "EXISTS" => arguments.len(),
+            "GET" | "SET" | "INCR" | "INCRBY" | "DECR" | "DECRBY" | "EXPIRE" | "TTL" => {
+                arguments.len().min(1)
+            }
+            _ => {
+                return Ok(Reply::Error(format!(
+                    "ERR unknown command '{}' in scripts",
+                    name.to_lowercase()
+                )))
+            }
+        };
+        let mut cache_keys: Vec<&str> = Vec::with_capacity(key_count);
+        for argument in &arguments[..key_count] {
+            match keys
+                .iter()
+                .find(|(key, _)| key.as_bytes() == argument.as_slice())
+            {
+                Some((_, cache_key)) => cache_keys.push(cache_key),
+                None => {
+                    return Ok(Reply::Error(format!(
+                        "ERR the script may only access the keys given to it, not '{}'",
+                        String::from_utf8_lossy(argument)
+                    )))
+                }
+            }
+        }
+        let integer = |argument: &[u8]| std::str::from_utf8(argument).ok()?.parse::<i64>().ok();
+
+        let reply: Reply = match (
+            name.as_str(),
+            cache_keys.as_slice(),
+            &arguments[key_count..],
+        ) {
+            ("GET", [key], []) => match self.get(key)? {
+                Lookup::Fresh(value) | Lookup::Stale(value) => Reply::Bulk(value.to_vec()),
+                _ => Reply::Nil,
+            },
+            ("SET", [key], [value, options @ ..]) => {
+                let mut ttl: Option<u64> = None;
+                let mut condition: Option<PutCondition> = None;
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    let option: String = String::from_utf8_lossy(option).to_ascii_uppercase();
+                    match (option.as_str(), ttl, condition) {
+                        ("EX" | "PX", None, _) => {
+                            // TTLs are kept in seconds, so milliseconds are rounded up
+                            ttl = match (
+                                option.as_str(),
+                                options.next().and_then(|ttl| integer(ttl)),
+                            ) {
+                                ("EX", Some(seconds)) if seconds > 0 => Some(seconds as u64),
+                                ("PX", Some(millis)) if millis > 0 => {
+                                    Some((millis as u64).div_ceil(1000))
+                                }
+                                _ => {
+                                    return Ok(Reply::Error(
+                                        "ERR invalid expire time in 'set' command".to_string(),
+                                    ))
+                                }
+                            };
+                        }
+                        ("NX", _, None | Some(PutCondition::IfAbsent)) => {
+                            condition = Some(PutCondition::IfAbsent)
+                        }
+                        ("XX", _, None | Some(PutCondition::IfPresent)) => {
+                            condition = Some(PutCondition::IfPresent)
+                        }
+                        _ => return Ok(Reply::Error("ERR syntax error".to_string())),
+                    }
+                }
+                let value: Bytes = value.as_slice().into();
+                match condition {
+                    Some(condition) => match self.put_if(key, value, ttl, None, condition)? {
+                        true => Reply::Status("OK".to_string()),
+                        false => Reply::Nil,
+                    },
+                    None => {
+                        self.put(key, value, ttl, None)?;
+                        Reply::Status("OK".to_string())
+                    }
+                }
+            }
+            ("DEL", keys, _) if !keys.is_empty() => {
+                let mut removed: i64 = 0;
+                for key in keys {
+                    removed += i64::from(self.remove(key)?.is_some());
+                }
+                Reply::Integer(removed)
+            }
+            ("EXISTS", keys, _) if !keys.is_empty() => Reply::Integer(self.exists(keys)? as i64),
+            ("INCR" | "DECR", [key], []) => {
+                let delta: i64 = if name == "INCR" { 1 } else { -1 };
+                Reply::Integer(self.incr_by(key, delta)?)
+            }
+            ("INCRBY" | "DECRBY", [key], [delta]) => {
+                let delta: Option<i64> = match name.as_str() {
+                    "INCRBY" => integer(delta),
+                    _ => integer(delta).and_then(i64::checked_neg),
+                };
+                match delta {
+                    Some(delta) => Reply::Integer(self.incr_by(key, delta)?),
+                    None => Reply::Error("ERR value is not an integer or out of range".to_string()),
+                }
+            }
+            ("EXPIRE", [key], [ttl]) => match integer(ttl) {
+                // A TTL of zero (or less) deletes the key, as in Redis
+                Some(ttl) if ttl <= 0 => Reply::Integer(i64::from(self.remove(key)?.is_some())),
+                Some(ttl) => Reply::Integer(i64::from(self.expire(key, Some(ttl as u64))?)),
+                None => Reply::Error("ERR value is not an integer or out of range".to_string()),
+            },
+            ("TTL", [key], []) => Reply::Integer(match self.remaining_ttl(key)? {
+                Some(Some(remaining)) => remaining.div_ceil(1000) as i64,
+                Some(None) => -1,
+                None => -2,
+            }),
+            _ => Reply::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_lowercase()
+            )),
+        };
+        Ok(reply)
+    }
+
+    /// Take the shared side of the lock isolating the transactions of the clients, which the servers hold
+    /// while executing a command of a client, so that it does not interleave with the transaction of another
+    /// (see `transaction_lock`).
+    pub(crate) fn command_lock(&self) -> CacheResult<RwLockReadGuard<'_, ()>> {
+        Ok(self.transactions.read()?)
+    }
+
+    /// Take the exclusive side of the lock isolating the transactions of the clients, which the servers hold
+    /// while executing the commands of a transaction (`EXEC`): the commands of the other clients wait until
+    /// they are all executed. The writes of the embedding application, the replication and the background
+    /// sweeps are not isolated.
+    pub(crate) fn transaction_lock(&self) -> CacheResult<RwLockWriteGuard<'_, ()>> {
+        Ok(self.transactions.write()?)
+    }
+
+    /// Pop a value from the head (`left`) or the tail of the list of the given key, if any.
+    fn _list_pop_one(&self, key: &str, left: bool) -> CacheResult<Option<Bytes>> {
+        Ok(self.list_pop(key, 1, left)?.into_iter().next())
+    }
+
+    /// Modify the hash, list or (sorted) set of the given key in place (see `Cache::modify`), the key being
+    /// given an empty one if it has no value, and removed if it is left empty. The writes to collections
+    /// are serialized, so that a key emptied by a write is not removed after another one has added to it.
+    ///
+    /// The command which made the write (e.g. `RPUSH key value`) is appended to the append-only log, if
+    /// any, rather than the collection (see `AppendOnlyLog::append_command`). The collection is modified as
+    /// a copy if a max value size is set (see `with_max_sizes`), so that a write taking it over the max
+    /// size is undone.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type.
+    /// * The error returned by `modify`, which must return it before modifying the collection.
+    /// * `CacheError::ValueTooLarge` if the write would take the value over the max size.
+    /// * An error appending the command to the append-only log, the write being made nonetheless.
+    fn _update_collection<T: Collection>(
+        &self,
+        key: &str,
+        command: &[&[u8]],
+        modify: &mut dyn FnMut(&mut T) -> CacheResult<()>,
+    ) -> CacheResult<()> {
+        self._check_writable()?;
+        let _guard = self.collection_writes.lock()?;
+        let key: String = key.to_string();
+        let expires_at: Option<u64> = match &self.append_only_log {
+            Some(_) => self.unlogged.expiration(&key)?.flatten(),
+            None => None,
+        };
+
+        let mut written: Option<Bytes> = None;
+        let mut write = |value: &mut Bytes| -> CacheResult<()> {
+            T::modify(value)?;
+            let previous: Option<Bytes> = (self.max_value_bytes() > 0).then(|| value.clone());
+            let modified: CacheResult<()> = T::modify(value)
+                .and_then(&mut *modify)
+                .and_then(|_| self._check_sizes(&key, value.len()));
+            if let Err(error) = modified {
+                if let Some(previous) = previous {
+                    *value = previous;
+                }
+                return Err(error);
+            }
+
+            written = Some(value.clone());
+            match &self.append_only_log {
+                Some(log) => log.append_command(command, &key, value, expires_at),
+                None => Ok(()),
+            }
+        };
+        self.flushes.take(&key)?;
+        let modified: CacheResult<()> =
+            self.unlogged
+                .modify(key.clone(), &mut |current| match current {
+                    Some(value) => write(value).map(|_| None),
+                    None => {
+                        let mut value: Bytes = Bytes::from_native(T::default());
+                        write(&mut value).map(|_| Some(value))
+                    }
+                });
+
+        // A write failing to be logged is made nonetheless
+        let Some(value) = written else {
+            return modified;
+        };
+        let emptied: bool = values::is_empty_collection(&value);
+        self._updated(&key, value)?;
+        if emptied {
+            self._remove(&key, false)?;
+        }
+        modified
+    }
+
+    /// Replace the value of the given key with the one computed from it (see `Cache::update`).
+    fn _update(
+        &self,
+        key: &str,
+        update: &mut dyn FnMut(Option<&Bytes>) -> CacheResult<Bytes>,
+    ) -> CacheResult<Bytes> {
+        self._update_as(key, None, update)
+    }
+
+    /// Replace the value of the given key with the one computed from it like `_update`. If given, the
+    /// command which made the write (e.g. `PFADD key element`) is appended to the append-only log rather
+    /// than the new value, which may be much larger (see `AppendOnlyLog::append_command`).
+    fn _update_as(
+        &self,
+        key: &str,
+        command: Option<&[&[u8]]>,
+        update: &mut dyn FnMut(Option<&Bytes>) -> CacheResult<Bytes>,
+    ) -> CacheResult<Bytes> {
+        self._check_writable()?;
+        let key: String = key.to_string();
+
+        self.flushes.take(&key)?;
+        let log: Option<(&AppendOnlyLog, &[&[u8]])> = self.append_only_log.as_deref().zip(command);
+        let value: Bytes = match log {
+            Some((log, command)) => {
+                let expires_at: Option<u64> = self.unlogged.expiration(&key)?.flatten();
+                self.unlogged.update(key.clone(), &mut |current| {
+                    let value: Bytes = update(current)?;
+                    self._check_sizes(&key, value.len())?;
+                    log.append_command(command, &key, &value, expires_at)?;
+                    Ok(value)
+                })?
+            }
+            None => self.cache.update(key.clone(), &mut |current| {
+                let value: Bytes = update(current)?;
+                self._check_sizes(&key, value.len())?;
+                Ok(value)
+            })?,
+        };
+        self._updated(&key, value.clone())?;
+        Ok(value)
+    }
+
+    /// Account for the new value of the given key written by an update: its quota, its history, its
+    /// negative entry and its keyspace event.
+    fn _updated(&self, key: &String, value: Bytes) -> CacheResult<()> {
+        let expires_at: Option<u64> = self.cache.expiration(key)?.flatten();
+        self.quotas
+            .record(key, key.len() + value.len(), expires_at)?;
+
+        if let Some(history) = &self.history {
+            history.record(key, Some(value))?;
+        }
+        self.negatives.remove(key)?;
+
+        self.events.publish(KeyspaceEventKind::Set, key)?;
+        self.leases.release(key)?;
+        Ok(())
+    }
+
+    /// Rename the given key as a single step: its value moves to the new key along with its TTL (and soft
+    /// TTL, if any), replacing the value of the new key unless `if_absent`.
+    ///
+    /// # Returns
+    /// * `None` if the key is not cached with a value, or whether it was renamed (`false` if `if_absent`
+    ///   and the new key has a value).
+    ///
+    /// # Errors
+    /// * `CacheError::ReadOnly` if the cache is read-only.
+    pub fn rename(&self, from: &str, to: &str, if_absent: bool) -> CacheResult<Option<bool>> {
+        self._check_writable()?;
+        let (from, to): (String, String) = (from.to_string(), to.to_string());
+
+        self.flushes.take(&to)?;
+        let renamed: Option<bool> = self.cache.rename(&from, to.clone(), if_absent)?;
+        if renamed != Some(true) || from == to {
+            return Ok(renamed);
+        }
+        let value: Option<Bytes> = self.cache.get(&to)?;
+        let expires_at: Option<u64> = self.cache.expiration(&to)?.flatten();
+
+        self.quotas.forget(&from)?;
+        if let Some(value) = &value {
+            self.quotas
+                .record(&to, to.len() + value.len(), expires_at)?;
+        }
+        if let Some(history) = &self.history {
+            history.record(&from, None)?;
+            history.record(&to, value)?;
+        }
+        self.negatives.remove(&to)?;
+        if self.soft_expiry.rename(&from, to.clone(), false)?.is_none() {
+            self.soft_expiry.remove(&to)?;
+        }
+
+        self.events.track_expiration(&from, None)?;
+        self.events.track_expiration(&to, expires_at)?;
+        self.events.publish(KeyspaceEventKind::Del, &from)?;
+        self.events.publish(KeyspaceEventKind::Set, &to)?;
+        self.leases.release(&from)?;
+        self.leases.release(&to)?;
+        Ok(renamed)
+    }
+
+    /// Swap the keys of two databases (see `with_databases`), renaming them one at a time: the clients of
+    /// the databases may see some of the keys swapped and the others not until it returns. The keys known
+    /// to be absent in either database are forgotten.
+    ///
+    /// # Returns
+    /// * The number of keys moved.
+    ///
+    /// # Errors
+    /// * `CacheError::ReadOnly` if the cache is read-only.
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub fn swap_databases(&self, first: usize, second: usize) -> CacheResult<usize> {
+        self._check_writable()?;
+        if first == second {
+            return Ok(0);
+        }
+
+        // The keys known to be absent are forgotten first (with a removal, so that the append-only log
+        // forgets them too)
+        let mut absent: Vec<String> = Vec::new();
+        self.negatives.scan(&mut |key, _| {
+            if [first, second].contains(&namespaces::split_database(key).0) {
+                absent.push(key.clone());
+            }
+        })?;
+        for key in &absent {
+            self._remove(key, false)?;
+        }
+
+        // The keys of the first database are parked in a database no client can select, so that those of
+        // the second one can take their place
+        let parked: usize = usize::MAX;
+        let mut moved: usize = 0;
+        for (from, to) in [(first, parked), (second, first), (parked, second)] {
+            let (from_prefix, to_prefix): (String, String) = (
+                namespaces::database_prefix(from),
+                namespaces::database_prefix(to),
+            );
+            for key in self.keys("*")? {
+                if !namespaces::in_keyspace(&key, &from_prefix) {
+                    continue;
+                }
+                let renamed: String = format!("{}{}", to_prefix, &key[from_prefix.len()..]);
+                if self.rename(&key, &renamed, false)?.is_some() && to != parked {
+                    moved += 1;
+                }
+            }
+        }
+        Ok(moved)
+    }
+
+    /// Remove the given key, whether it is cached with a value or as known to be absent.
+    ///
+    /// # Returns
+    /// * The removed value, if the key was cached with one.
+    pub fn remove(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        self._check_writable()?;
+        Ok(self._remove(key, true)?.map(values::decode_string))
+    }
+
+    /// Remove the given key like `remove`, only if it holds a string (or nothing).
+    ///
+    /// # Returns
+    /// * The removed value, if the key was cached with one.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the key holds a value of another type than a string, which is left
+    ///   unchanged (its type being checked in the same step as the key is removed).
+    pub fn get_del(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        self._check_writable()?;
+        let mut accept = |value: &Bytes| values::expect_string(Some(value));
+        let removed: Option<Bytes> =
+            self._remove_as(key, true, KeyspaceEventKind::Del, Some(&mut accept))?;
+        Ok(removed.map(values::decode_string))
+    }
+
+    /// Restore the value of a key removed within the tombstone window (see `with_tombstone_window`), with
+    /// the rest of its TTL and soft TTL.
+    ///
+    /// # Returns
+    /// * `false` if the key has no tombstone (it was not removed, or its window is over), if its value has
+    ///   expired since, or if the key has been given a value again since.
+    ///
+    /// # Errors
+    /// * `CacheError::ReadOnly` if the cache is read-only.
+    /// * `CacheError::QuotaExceeded` if the value no longer fits within the quota of its namespace.
+    pub fn undelete(&self, key: &str) -> CacheResult<bool> {
+        self._check_writable()?;
+        let key: String = key.to_string();
+        if self.cache.get(&key)?.is_some() {
+            return Ok(false);
+        }
+        let Some(tombstone) = self.tombstones.get(&key)? else {
+            return Ok(false);
+        };
+
+        // The TTLs of the cache are in seconds, so the remaining time is rounded up
+        let now: u64 = current_monotime();
+        let remaining = |time: u64| time.saturating_sub(now).div_ceil(1000);
+        let ttl: Option<u64> = match tombstone.expires_at {
+            Some(expires_at) if expires_at <= now => return Ok(false),
+            expires_at => expires_at.map(remaining),
+        };
+        let soft_ttl: Option<u64> = tombstone.stale_at.map(remaining);
+
+        let condition: Option<PutCondition> = Some(PutCondition::IfAbsent);
+        if !self._put(&key, tombstone.value, ttl, soft_ttl, true, condition)? {
+            return Ok(false);
+        }
+        self.tombstones.remove(&key)?;
+        Ok(true)
+    }
+
+    /// Remove the given key, keeping its value as a tombstone if `tombstone` and soft delete is enabled.
+    fn _remove(&self, key: &str, tombstone: bool) -> CacheResult<Option<Bytes>> {
+        self._remove_as(key, tombstone, KeyspaceEventKind::Del, None)
+    }
+
+    /// Remove a key evicted by the quota of its namespace to make room for another.
+    fn _evict(&self, key: &str) -> CacheResult<()> {
+        self._remove_as(key, false, KeyspaceEventKind::Evicted, None)?;
+        Ok(())
+    }
+
+    /// Remove a key like `_remove`, publishing the given kind of event if it had a value. If given, `accept`
+    /// checks the value in the same step as it is removed (see `Cache::remove_entry_if`), its error leaving
+    /// the key unchanged.
+    fn _remove_as(
+        &self,
+        key: &str,
+        tombstone: bool,
+        kind: KeyspaceEventKind,
+        accept: Option<AcceptRemoval>,
+    ) -> CacheResult<Option<Bytes>> {
+        let key: String = key.to_string();
+
+        let removed: Option<Bytes> = match (self.tombstone_window.filter(|_| tombstone), accept) {
+            (Some(window), accept) => self._bury(&key, window, accept)?,
+            (None, Some(accept)) => self
+                .cache
+                .remove_entry_if(&key, accept)?
+                .map(|(value, _)| value),
+            (None, None) => self.cache.remove(&key)?,
+        };
+        self.quotas.forget(&key)?;
+        self.negatives.remove(&key)?;
+        self.soft_expiry.remove(&key)?;
+        self.leases.release(&key)?;
+
+        self.events.track_expiration(&key, None)?;
+        if removed.is_some() {
+            if let Some(history) = &self.history {
+                history.record(&key, None)?;
+            }
+            self.events.publish(kind, &key)?;
+        }
+        Ok(removed)
+    }
+
+    /// The versions of the given key kept by the version history (see `with_version_history`), newest
+    /// first. Empty if the key has none, or if no history is kept.
+    pub fn versions(&self, key: &str) -> CacheResult<Vec<Version>> {
+        match &self.history {
+            Some(history) => Ok(history
+                .versions(key)?
+                .into_iter()
+                .map(|version| Version {
+                    value: version.value.map(values::decode_string),
+                    ..version
+                })
+                .collect()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Remove the value of the given key (if accepted, see `_remove_as`), keeping it as a tombstone for
+    /// `window` seconds.
+    fn _bury(
+        &self,
+        key: &str,
+        window: u64,
+        accept: Option<AcceptRemoval>,
+    ) -> CacheResult<Option<Bytes>> {
+        let key: String = key.to_string();
+        let stale_at: Option<u64> = self.soft_expiry.get(&key)?;
+        let removed: Option<(Bytes, Option<u64>)> = match accept {
+            Some(accept) => self.cache.remove_entry_if(&key, accept)?,
+            None => self.cache.remove_entry(&key)?,
+        };
+        let Some((value, expires_at)) = removed else {
+            return Ok(None);
+        };
+
+        let tombstone: Tombstone = Tombstone {
+            value: value.clone(),
+            expires_at,
+            stale_at,
+        };
+        self.tombstones.put(key, tombstone, Some(window))?;
+        Ok(Some(value))
+    }
+
+    /// Subscribe to the changes of the keys matching the given glob pattern (see `utils::glob_match`): the
+    /// values set, the keys removed (or evicted), and the keys whose TTL has elapsed. Expired events are only published
+    /// by the background sweeps, for the keys put with a TTL after the first subscription.
+    ///
+    /// The events are queued for the subscriber, which is dropped (i.e. finds the receiver disconnected)
+    /// if it falls behind by more than `events::EVENT_QUEUE_CAPACITY` events.
+    pub fn subscribe(&self, pattern: &str) -> CacheResult<Receiver<KeyspaceEvent>> {
+        self.events.subscribe(pattern)
+    }
+
+    /// Publish a message on the given channel, queued for the clients subscribed to the channel with
+    /// `SUBSCRIBE`. A subscriber falling behind by more than `pubsub::MESSAGE_QUEUE_CAPACITY` messages is
+    /// disconnected.
+    ///
+    /// # Returns
+    /// * The number of subscribers the message was queued for.
+    pub fn publish(&self, channel: &str, message: Bytes) -> CacheResult<usize> {
+        self.pubsub.publish(channel, message)
+    }
+
+    /// The channels of the messages published with `publish`, which the client connections subscribe to.
+    pub(crate) fn pubsub(&self) -> &Arc<PubSub> {
+        &self.pubsub
+    }
+
+    /// The feed of the commands executed, which the client connections subscribe to with `MONITOR` (on
+    /// `monitor::MONITOR_CHANNEL`). A monitor falling behind by more than `pubsub::MESSAGE_QUEUE_CAPACITY`
+    /// commands is disconnected.
+    pub(crate) fn monitors(&self) -> &Arc<PubSub> {
+        &self.monitors
+    }
+
+    /// Count the keys starting with the given prefix within its database (see `namespaces::in_keyspace`),
+    /// e.g. those of a namespace of the server (all those of database 0 if it is empty).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub fn keyspace_stats(&self, prefix: &str) -> CacheResult<KeyspaceStats> {
+        let mut stats: KeyspaceStats = KeyspaceStats::default();
+        self.cache.scan(&mut |key, value| {
+            if namespaces::in_keyspace(key, prefix) {
+                stats.keys += 1;
+                stats.bytes += key.len() + value.len();
+            }
+        })?;
+        self.negatives.scan(&mut |key, _| {
+            if namespaces::in_keyspace(key, prefix) {
+                stats.negative_keys += 1;
+            }
+        })?;
+        Ok(stats)
+    }
+
+    /// What the given namespace takes of its quota (see `with_namespace_quotas`), or `None` if it has no
+    /// quota. Only the keys written since the quota was set are counted.
+    pub fn namespace_usage(&self, namespace: &str) -> CacheResult<Option<NamespaceUsage>> {
+        self.quotas.usage(namespace)
+    }
+
+    /// Count how long the keys starting with the given prefix have left to live, bucketed by `TTL_BUCKETS`,
+    /// e.g. to foresee the waves of expirations or tune the default TTLs. Only a sample of the keys is
+    /// examined, so that the histogram stays cheap on a large cache.
+    ///
+    /// # Arguments
+    /// * `prefix` - The prefix of the keys to examine within its database (all those of database 0 if it is
+    ///   empty).
+    /// * `sample_size` - The most keys to examine, taken in no particular order.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub fn ttl_histogram(&self, prefix: &str, sample_size: usize) -> CacheResult<TtlHistogram> {
+        let mut histogram: TtlHistogram = TtlHistogram::default();
+        let now: u64 = current_monotime();
+        self.cache
+            .scan_with_expirations(&mut |key, _, expires_at| {
+                if histogram.sampled >= sample_size {
+                    return false;
+                }
+                if !namespaces::in_keyspace(key, prefix) {
+                    return true;
+                }
+
+                histogram.sampled += 1;
+                match expires_at {
+                    Some(expires_at) => {
+                        let remaining: u64 = expires_at.saturating_sub(now) / 1000;
+                        let bucket: usize = TTL_BUCKETS
+                            .iter()
+                            .position(|&bound| remaining < bound)
+                            .unwrap_or(TTL_BUCKETS.len());
+                        histogram.buckets[bucket] += 1;
+                    }
+                    None => histogram.persistent += 1,
+                }
+                true
+            })?;
+        Ok(histogram)
+    }
+
+    /// Visit the keys holding a value as they were at a single point in time, e.g. to dump the cache. The
+    /// keys cached as known to be absent and the soft TTLs are not visited.
+    ///
+    /// # Arguments
+    /// * `visit` - Called with every key and value, and the expiration time of the value (in milliseconds
+    ///   since the epoch, `None` without a TTL), in no particular order.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub fn for_each_value(
+        &self,
+        visit: &mut dyn FnMut(&str, &Bytes, Option<u64>),
+    ) -> CacheResult<()> {
+        self.cache
+            .scan_with_expirations(&mut |key, value, expires_at| {
+                visit(key, value, expires_at.map(monotime_to_walltime));
+                true
+            })
+    }
+
+    /// Remove the keys starting with the given prefix within its database (see `namespaces::in_keyspace`),
+    /// e.g. to flush a namespace of the server (all those of database 0 if it is empty).
+    ///
+    /// # Returns
+    /// * The number of keys removed.
+    ///
+    /// # Errors
+    /// * `CacheError::ReadOnly` if the cache is read-only.
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub fn clear_prefix(&self, prefix: &str) -> CacheResult<usize> {
+        self._check_writable()?;
+        self._clear(prefix, true)
+    }
+
+    /// Remove the keys starting with the given prefix like `clear_prefix`, but incrementally on a background
+    /// thread, `CLEAR_BATCH_SIZE` keys at a time, so that clearing a large part of the cache does not hold up
+    /// the other commands. The keys are listed before returning, and those written afterwards are kept with
+    /// their new value, even if they were listed.
+    ///
+    /// # Returns
+    /// * The number of keys being removed.
+    ///
+    /// # Errors
+    /// * `CacheError::ReadOnly` if the cache is read-only.
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub fn clear_prefix_in_background(self: &Arc<Self>, prefix: &str) -> CacheResult<usize> {
+        self._check_writable()?;
+        let keys: Vec<String> = self._keys_with_prefix(prefix)?;
+        let count: usize = keys.len();
+        self.flushes.add(&keys)?;
+
+        let cache: Arc<EmbeddedCache> = Arc::clone(self);
+        let prefix: String = prefix.to_string();
+        thread::spawn(move || {
+            for batch in keys.chunks(CLEAR_BATCH_SIZE) {
+                for key in batch {
+                    if let Err(e) = cache._remove_flushed(key) {
+                        error!("Failed to clear the keys starting with '{}': {}", prefix, e);
+                        // The keys left are no longer pending, so that the writes stop checking them
+                        for key in &keys {
+                            let _ = cache.flushes.take(key);
+                        }
+                        return;
+                    }
+                }
+                thread::sleep(CLEAR_BATCH_PAUSE);
+            }
+            info!("Cleared {} keys starting with '{}'", keys.len(), prefix);
+        });
+        Ok(count)
+    }
+
+    /// Remove a key listed by a background flush, unless it has been written since. The key is taken off
+    /// the pending ones in the same step as its value is removed, so that a write coming in between is
+    /// either made first and removed, or kept.
+    fn _remove_flushed(&self, key: &str) -> CacheResult<()> {
+        if !self.flushes.contains(key)? {
+            return Ok(());
+        }
+        let mut accept = |_: &Bytes| match self.flushes.take(key)? {
+            true => Ok(()),
+            false => Err(CacheError::Unsupported("flush")),
+        };
+        match self._remove_as(key, true, KeyspaceEventKind::Del, Some(&mut accept)) {
+            Ok(Some(_)) | Err(CacheError::Unsupported("flush")) => Ok(()),
+            // A key known to be absent has no value to check (and the storage engine may not check them)
+            Ok(None) => self.flushes.take(key).map(|_| ()),
+            Err(CacheError::Unsupported(_)) => {
+                self.flushes.take(key)?;
+                self._remove(key, true).map(|_| ())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The number of keys cached with a value (see `Cache::len`).
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine can neither count nor scan its keys.
+    pub fn len(&self) -> CacheResult<usize> {
+        self.cache.len()
+    }
+
+    /// Whether no key is cached with a value.
+    pub fn is_empty(&self) -> CacheResult<bool> {
+        self.cache.is_empty()
+    }
+
+    /// Remove every key at once (see `Cache::clear`), along with the keys known to be absent and the values
+    /// which could be restored. The version history is kept. With a storage engine which cannot be cleared
+    /// at once, the keys are removed one by one instead.
+    ///
+    /// # Arguments
+    /// * `in_background` - Whether the entries removed are dropped on a background thread, rather than
+    ///   before returning. Either way, the keys written afterwards are kept.
+    ///
+    /// # Errors
+    /// * `CacheError::ReadOnly` if the cache is read-only.
+    /// * `CacheError::Unsupported` if the storage engine can neither be cleared nor scanned for its keys.
+    pub fn clear(&self, in_background: bool) -> CacheResult<()> {
+        self._check_writable()?;
+        self._clear_all(in_background)
+    }
+
+    /// Remove every key at once like `clear`, even from a read-only cache.
+    fn _clear_all(&self, in_background: bool) -> CacheResult<()> {
+        let cleared: Box<dyn Send> = match self.cache.clear() {
+            Ok(cleared) => cleared,
+            Err(CacheError::Unsupported(_)) => {
+                for index in 0..self.databases {
+                    self._clear(&namespaces::database_prefix(index), false)?;
+                }
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let cleared: Vec<Box<dyn Send>> = vec![
+            cleared,
+            self.negatives.clear()?,
+            self.soft_expiry.clear()?,
+            self.tombstones.clear()?,
+        ];
+        self.quotas.forget_all()?;
+        self.events.forget_expirations()?;
+        self.events.touch_all_watched()?;
+
+        if in_background {
+            thread::spawn(move || drop(cleared));
+        }
+        Ok(())
+    }
+
+    /// Put a value with an exact TTL (without jitter nor soft TTL), even into a read-only cache. Used to
+    /// apply the writes of a primary or of an imported dump.
+    pub(crate) fn apply_put(&self, key: &str, value: Bytes, ttl: Option<u64>) -> CacheResult<()> {
+        self._put(key, value, ttl, None, false, None).map(|_| ())
+    }
+
+    /// Change the exact TTL of a key like `expire`, even in a read-only cache.
+    pub(crate) fn apply_expire(&self, key: &str, ttl: Option<u64>) -> CacheResult<bool> {
+        self._expire(key, ttl)
+    }
+
+    /// Remove a key like `remove`, even from a read-only cache.
+    pub(crate) fn apply_remove(&self, key: &str) -> CacheResult<Option<Bytes>> {
+        self._remove(key, false)
+    }
+
+    /// Remove all the keys, even from a read-only cache.
+    ///
+    /// # Errors
+    /// * `CacheError::Unsupported` if the storage engine cannot be scanned for its keys.
+    pub(crate) fn apply_clear(&self) -> CacheResult<usize> {
+        self._clear("", false)
+    }
+
+    fn _clear(&self, prefix: &str, tombstone: bool) -> CacheResult<usize> {
+        let keys: Vec<String> = self._keys_with_prefix(prefix)?;
+        for key in &keys {
+            self._remove(key, tombstone)?;
+        }
+        Ok(keys.len())
+    }
+
+    /// The keys starting with the given prefix within its database, whether they hold a value or are known
+    /// to be absent.
+    fn _keys_with_prefix(&self, prefix: &str) -> CacheResult<Vec<String>> {
+        let mut keys: Vec<String> = Vec::new();
+        let mut collect = |key: &String| {
+            if namespaces::in_keyspace(key, prefix) {
+                keys.push(key.clone());
+            }
+        };
+        self.cache.scan(&mut |key, _| collect(key))?;
+        self.negatives.scan(&mut |key, _| collect(key))?;
+        Ok(keys)
+    }
+
+    /// Check that a key and its value are within the max sizes of the writes (see `with_max_sizes`),
+    /// counting the writes rejected.
+    fn _check_sizes(&self, key: &str, value_len: usize) -> CacheResult<()> {
+        let max_key_bytes: usize = self.max_key_bytes();
+        let max_value_bytes: usize = self.max_value_bytes();
+        let error: CacheError = if max_key_bytes > 0 && key.len() > max_key_bytes {
+            CacheError::KeyTooLarge(max_key_bytes)
+        } else if max_value_bytes > 0 && value_len > max_value_bytes {
+            CacheError::ValueTooLarge(max_value_bytes)
+        } else {
+            return Ok(());
+        };
+        self.stats.record_oversized_write();
+        Err(error)
+    }
+
+    fn _check_writable(&self) -> CacheResult<()> {
+        match self.is_read_only() {
+            true => Err(CacheError::ReadOnly),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The append-only log is replayed through the cache before it is wrapped with the log (see `from_config`),
+/// the values being put with their exact TTLs, and the writes logged as commands made again.
+impl LogReplay for EmbeddedCache {
+    fn replay_put(&self, key: String, value: Bytes, ttl: Option<u64>) -> CacheResult<()> {
+        self.apply_put(&key, value, ttl)
+    }
+
+    fn replay_remove(&self, key: String) -> CacheResult<()> {
+        self.apply_remove(&key).map(|_| ())
+    }
+
+    fn replay_negative(&self, key: String, ttl: Option<u64>) -> CacheResult<()> {
+        self._remove(&key, false)?;
+        self.negatives.put(key, (), ttl)
+    }
+
+    fn replay_clear(&self) -> CacheResult<()> {
+        self._clear_all(false)
+    }
+
+    fn replay_command(&self, arguments: &[Vec<u8>]) -> CacheResult<bool> {
+        let arguments: Vec<&[u8]> = arguments.iter().map(Vec::as_slice).collect();
+        let (name, key, arguments): (&[u8], &str, &[&[u8]]) = match arguments.as_slice() {
+            [name, key, arguments @ ..] => match std::str::from_utf8(key) {
+                Ok(key) => (name, key, arguments),
+                Err(_) => return Ok(false),
+            },
+            _ => return Ok(false),
+        };
+        let number = |argument: &[u8]| std::str::from_utf8(argument).ok()?.parse::<u64>().ok();
+        let owned = |arguments: &[&[u8]]| arguments.iter().map(|a| Bytes::from(*a)).collect();
+
+        match (name, arguments) {
+            (b"HSET", fields) if fields.len() % 2 == 0 => {
+                let fields: Vec<(&[u8], Bytes)> = fields
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0], Bytes::from(pair[1])))
+                    .collect();
+                self.hset(key, &fields)?;
+            }
+            (b"HDEL", fields) => {
+                self.hdel(key, fields)?;
+            }
+            (b"LPUSH" | b"RPUSH", items) => {
+                let items: Vec<Bytes> = owned(items);
+                self.list_push(key, &items, name == b"LPUSH")?;
+            }
+            (b"LPOP" | b"RPOP", [count]) => match number(count) {
+                Some(count) => {
+                    self.list_pop(key, count as usize, name == b"LPOP")?;
+                }
+                None => return Ok(false),
+            },
+            (b"SADD", members) => {
+                let members: Vec<Bytes> = owned(members);
+                self.sadd(key, &members)?;
+            }
+            (b"SREM", members) => {
+                self.srem(key, members)?;
+            }
+            (b"ZADD", members) if members.len() % 2 == 0 => {
+                let mut scored: Vec<(f64, &[u8])> = Vec::with_capacity(members.len() / 2);
+                for pair in members.chunks_exact(2) {
+                    match values::parse_score(pair[0]) {
+                        Some(score) => scored.push((score, pair[1])),
+                        None => return Ok(false),
+                    }
+                }
+                self.zadd(key, &scored)?;
+            }
+            (b"ZINCRBY", [increment, member]) => match values::parse_score(increment) {
+                Some(increment) => {
+                    self.zincrby(key, member, increment)?;
+                }
+                None => return Ok(false),
+            },
+            (b"ZREM", members) => {
+                self.zrem(key, members)?;
+            }
+            (b"PFADD", elements) => {
+                self.pfadd(key, elements)?;
+            }
+            (b"BF.ADD", [element]) => {
+                self.bf_add(key, element)?;
+            }
+            (b"XADD", [now, value]) => match number(now) {
+                Some(now) => {
+                    self._xadd(key, Bytes::from(*value), now, None)?;
+                }
+                None => return Ok(false),
+            },
+            (b"XADD", [b"MAXLEN", max_len, now, value]) => match (number(max_len), number(now)) {
+                (Some(max_len), Some(now)) => {
+                    self._xadd(key, Bytes::from(*value), now, Some(max_len as usize))?;
+                }
+                _ => return Ok(false),
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// A write logged as the command which made it (see `EmbeddedCache::_update_as`): its name, the key, then
+/// its arguments.
+fn logged_command<'a>(
+    name: &'a [u8],
+    key: &'a str,
+    arguments: impl IntoIterator<Item = &'a [u8]>,
+) -> Vec<&'a [u8]> {
+    [name, key.as_bytes()]
+        .into_iter()
+        .chain(arguments)
+        .collect()
+}
+
+/// The monotime at which an entry put now with the given TTL (in seconds) expires.
+fn expiration_time(ttl: Option<u64>) -> Option<u64> {
+    ttl.map(|ttl| expires_after(current_monotime(), ttl))
+}
+
+/// Pick the random factor the TTLs are multiplied by for the given jitter (in percent).
+fn jitter_factor(jitter: u32) -> f64 {
+    if jitter == 0 {
+        return 1.0;
+    }
+
+    let unit: f64 = (random_u64() >> 11) as f64 / (1u64 << 53) as f64; // Uniform in [0, 1)
+    let spread: f64 = jitter.min(MAX_TTL_JITTER) as f64 / 100.0;
+    1.0 + spread * (2.0 * unit - 1.0)
+}
+
+/// Spread a TTL and a soft TTL by the given jitter (in percent), in the same proportion so that the soft
+/// TTL stays the shorter one.
+fn jitter_ttls(ttl: Option<u64>, soft_ttl: Option<u64>, jitter: u32) -> (Option<u64>, Option<u64>) {
+    let factor: f64 = jitter_factor(jitter);
+    (
+        ttl.map(|ttl| apply_jitter(ttl, factor)),
+        soft_ttl.map(|soft_ttl| apply_jitter(soft_ttl, factor)),
+    )
+}
+
+/// Multiply a TTL by a jitter factor. A non-zero TTL stays at least one second long.
+fn apply_jitter(ttl: u64, factor: f64) -> u64 {
+    if ttl == 0 || factor == 1.0 {
+        return ttl;
+    }
+    ((ttl as f64 * factor).round() as u64).max(1)
+}
@@ -0,0 +1,195 @@
+use std::{error::Error, fmt, io, sync::PoisonError};
+
+use log::Level;
+
+/// Errors returned by the `Cache` operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheError {
+    /// A thread panicked while holding the lock of the cache, so the cached data may be inconsistent.
+    LockPoisoned,
+
+    /// There is no built-in storage engine with the given name.
+    UnknownEngine(String),
+
+    /// The storage backing the cache failed (e.g. a disk tier could not be read or written).
+    Storage(String),
+
+    /// The loader filling a cache miss failed.
+    Loader(String),
+
+    /// The sink keeping an external store in sync with the cache failed.
+    Sink(String),
+
+    /// The cache does not support the given operation.
+    Unsupported(&'static str),
+
+    /// The cache is read-only (see `EmbeddedCache::with_read_only`), e.g. a replica, which only its primary
+    /// writes to.
+    ReadOnly,
+
+    /// A write would make a namespace exceed its quota (see `namespaces::NamespaceQuota`).
+    QuotaExceeded(String),
+
+    /// A write would take the cache over its max memory (see `MaxMemoryPolicy::NoEviction`).
+    OutOfMemory,
+
+    /// The key written is longer than the given max size, in bytes (see `EmbeddedCache::with_max_sizes`).
+    KeyTooLarge(usize),
+
+    /// The value written is longer than the given max size, in bytes (see `EmbeddedCache::with_max_sizes`).
+    ValueTooLarge(usize),
+
+    /// The value of a counter (see `EmbeddedCache::incr_by`) is not an integer, or would overflow.
+    NotAnInteger,
+
+    /// The key holds a value of another data type than the command works on (see `values::ValueType`).
+    WrongType,
+
+    /// The score of a sorted set member would not be a number (see `values::SortedSet::increment`).
+    NotANumber,
+
+    /// A script is invalid, fails or runs for longer than its time limit (see `EmbeddedCache::eval`).
+    Script(String),
+
+    /// The value of the given key is not a valid encoding of the type it is read as (see `TypedCache`).
+    Decode(String),
+}
+
+impl CacheError {
+    /// The level the failure of a client command with this error is logged at: `Error` for the faults of
+    /// the server (e.g. a failing storage), `Warn` once the cache is full, and `Debug` for the errors caused
+    /// by the command itself (e.g. `WrongType`, or a write to a read-only cache), which its client is
+    /// answered anyway.
+    pub fn log_level(&self) -> Level {
+        match self {
+            CacheError::LockPoisoned
+            | CacheError::UnknownEngine(_)
+            | CacheError::Storage(_)
+            | CacheError::Loader(_)
+            | CacheError::Sink(_) => Level::Error,
+            CacheError::OutOfMemory => Level::Warn,
+            CacheError::Unsupported(_)
+            | CacheError::ReadOnly
+            | CacheError::QuotaExceeded(_)
+            | CacheError::KeyTooLarge(_)
+            | CacheError::ValueTooLarge(_)
+            | CacheError::NotAnInteger
+            | CacheError::WrongType
+            | CacheError::NotANumber
+            | CacheError::Script(_)
+            | CacheError::Decode(_) => Level::Debug,
+        }
+    }
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::LockPoisoned => write!(f, "cache lock is poisoned"),
+            CacheError::UnknownEngine(name) => write!(f, "unknown storage engine '{}'", name),
+            CacheError::Storage(message) => write!(f, "storage failure: {}", message),
+            CacheError::Loader(message) => write!(f, "loader failure: {}", message),
+            CacheError::Sink(message) => write!(f, "sink failure: {}", message),
+            CacheError::Unsupported(operation) => write!(f, "{} is not supported", operation),
+            CacheError::ReadOnly => write!(f, "the cache is read-only"),
+            CacheError::QuotaExceeded(message) => write!(f, "quota exceeded: {}", message),
+            CacheError::OutOfMemory => write!(f, "the cache has reached its max memory"),
+            CacheError::KeyTooLarge(max) => write!(f, "the key is longer than {} bytes", max),
+            CacheError::ValueTooLarge(max) => write!(f, "the value is longer than {} bytes", max),
+            CacheError::NotAnInteger => write!(f, "the value is not an integer or out of range"),
+            CacheError::WrongType => write!(f, "the key holds a value of another type"),
+            CacheError::NotANumber => write!(f, "the score would not be a number"),
+            CacheError::Script(message) => write!(f, "script error: {}", message),
+            CacheError::Decode(key) => write!(f, "the value of '{}' cannot be decoded", key),
+        }
+    }
+}
+
+impl Error for CacheError {}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> CacheError {
+        CacheError::Storage(e.to_string())
+    }
+}
+
+impl<T> From<PoisonError<T>> for CacheError {
+    fn from(_: PoisonError<T>) -> CacheError {
+        CacheError::LockPoisoned
+    }
+}
+
+/// Errors returned by the `CacheServer` and its client handlers.
+#[derive(Debug)]
+pub enum ServerError {
+    /// The server could not bind to (i.e. listen on) the given address.
+    Bind { address: String, source: io::Error },
+
+    /// Reading from or writing to a client connection failed.
+    Io(io::Error),
+
+    /// A cache operation failed while executing a client command.
+    Cache(CacheError),
+}
+
+impl ServerError {
+    /// The level the failure of a client command with this error is logged at (see `CacheError::log_level`):
+    /// `Debug` for the I/O errors caused by the client, i.e. a request not following the protocol (which is
+    /// reported on its own) or a connection it dropped, and `Error` for the other ones.
+    pub fn log_level(&self) -> Level {
+        match self {
+            ServerError::Cache(e) => e.log_level(),
+            ServerError::Io(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::InvalidData
+                        | io::ErrorKind::InvalidInput
+                        | io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::BrokenPipe
+                ) =>
+            {
+                Level::Debug
+            }
+            ServerError::Bind { .. } | ServerError::Io(_) => Level::Error,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Bind { address, source } => {
+                write!(f, "failed to start the server on {}: {}", address, source)
+            }
+            ServerError::Io(e) => write!(f, "I/O error: {}", e),
+            ServerError::Cache(e) => write!(f, "cache error: {}", e),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServerError::Bind { source, .. } => Some(source),
+            ServerError::Io(e) => Some(e),
+            ServerError::Cache(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        ServerError::Io(e)
+    }
+}
+
+impl From<CacheError> for ServerError {
+    fn from(e: CacheError) -> ServerError {
+        ServerError::Cache(e)
+    }
+}
+
+/// A shorthand for the results of the `Cache` operations.
+pub type CacheResult<T> = Result<T, CacheError>;
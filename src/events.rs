@@ -0,0 +1,355 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    cache::Bytes, error::CacheResult, namespaces::split_database, pubsub::PubSub, utils::glob_match,
+};
+
+/// How many events a subscriber may fall behind by before it is dropped.
+pub const EVENT_QUEUE_CAPACITY: usize = 1024;
+
+/// What happened to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyspaceEventKind {
+    /// The key was given a value.
+    Set,
+
+    /// The key was removed (or cached as known to be absent).
+    Del,
+
+    /// The TTL of the key elapsed.
+    Expired,
+
+    /// The key was removed to make room for others (by the quota of its namespace).
+    Evicted,
+}
+
+impl KeyspaceEventKind {
+    /// Every kind of event, in the order of their bits in `KeyspaceNotifications`.
+    pub const ALL: [KeyspaceEventKind; 4] = [
+        KeyspaceEventKind::Set,
+        KeyspaceEventKind::Del,
+        KeyspaceEventKind::Expired,
+        KeyspaceEventKind::Evicted,
+    ];
+
+    /// The name of the event, as sent to the remote subscribers.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyspaceEventKind::Set => "set",
+            KeyspaceEventKind::Del => "del",
+            KeyspaceEventKind::Expired => "expired",
+            KeyspaceEventKind::Evicted => "evicted",
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+/// The kinds of keyspace events published on the pub/sub channels (see `PubSub`), for the clients which
+/// cannot subscribe with `EmbeddedCache::subscribe` (e.g. to invalidate their local caches).
+///
+/// Parsed from the names of the events separated by `,` (e.g. `set,del`), `all` or `none` (the default).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceNotifications(u8);
+
+impl KeyspaceNotifications {
+    /// Every kind of event.
+    pub const ALL: KeyspaceNotifications = KeyspaceNotifications(0b1111);
+
+    /// No event at all, the default.
+    pub const NONE: KeyspaceNotifications = KeyspaceNotifications(0);
+
+    /// Whether the given kind of event is published.
+    pub fn contains(&self, kind: KeyspaceEventKind) -> bool {
+        self.0 & kind.bit() != 0
+    }
+
+    /// Whether no event is published.
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromStr for KeyspaceNotifications {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<KeyspaceNotifications, String> {
+        match text.trim() {
+            "all" => return Ok(KeyspaceNotifications::ALL),
+            "none" | "" => return Ok(KeyspaceNotifications::NONE),
+            _ => {}
+        }
+
+        let mut notifications: KeyspaceNotifications = KeyspaceNotifications::NONE;
+        for name in text.split(',').map(str::trim) {
+            let Some(kind) = KeyspaceEventKind::ALL
+                .into_iter()
+                .find(|kind| kind.as_str() == name)
+            else {
+                return Err(format!("unknown keyspace event '{}'", name));
+            };
+            notifications.0 |= kind.bit();
+        }
+        Ok(notifications)
+    }
+}
+
+impl fmt::Display for KeyspaceNotifications {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            KeyspaceNotifications::NONE => write!(f, "none"),
+            KeyspaceNotifications::ALL => write!(f, "all"),
+            notifications => {
+                let names: Vec<&str> = KeyspaceEventKind::ALL
+                    .into_iter()
+                    .filter(|kind| notifications.contains(*kind))
+                    .map(|kind| kind.as_str())
+                    .collect();
+                write!(f, "{}", names.join(","))
+            }
+        }
+    }
+}
+
+/// A change of a key of an `EmbeddedCache`, as received by its subscribers (see
+/// `EmbeddedCache::subscribe`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyspaceEvent {
+    pub kind: KeyspaceEventKind,
+    pub key: String,
+}
+
+/// A subscriber to the events of the keys matching a pattern.
+struct Subscriber {
+    pattern: String,                   // A glob pattern (see `utils::glob_match`)
+    sender: SyncSender<KeyspaceEvent>, // The queue of the events not received yet
+}
+
+/// Publishes the keyspace events of a cache to its subscribers, and on the pub/sub channels of the kinds
+/// of events notified (see `KeyspaceNotifications`): `__keyspace@<db>__:<key>` with the name of the event
+/// as the message, and `__keyevent@<db>__:<event>` with the key (within its database) as the message.
+///
+/// Expired events need the expiration times of the keys, which are only tracked while there are
+/// subscribers or expired events are notified: the keys put with a TTL before expire silently.
+pub(crate) struct KeyspaceEvents {
+    active: AtomicBool, // Whether there are subscribers, checked without locking by the writes
+    subscribers: Mutex<Vec<Subscriber>>,
+    expirations: Mutex<Expirations>,
+    notifications: AtomicU8, // The kinds of events published on the channels (see `KeyspaceNotifications`)
+    pubsub: Arc<PubSub>,     // Publishes the events notified on the channels
+    watching: AtomicBool,    // Whether any key is watched, checked without locking by the writes
+    watches: Mutex<HashMap<String, Vec<Arc<AtomicBool>>>>, // The flags raised once a watched key changes
+}
+
+/// The expiration times (monotime) of the keys put with a TTL, ordered to find the elapsed ones quickly.
+#[derive(Default)]
+struct Expirations {
+    by_key: HashMap<String, u64>,
+    by_time: BTreeSet<(u64, String)>,
+}
+
+impl KeyspaceEvents {
+    pub fn new(pubsub: Arc<PubSub>) -> KeyspaceEvents {
+        KeyspaceEvents {
+            active: AtomicBool::new(false),
+            subscribers: Mutex::new(Vec::new()),
+            expirations: Mutex::new(Expirations::default()),
+            notifications: AtomicU8::new(KeyspaceNotifications::NONE.0),
+            pubsub,
+            watching: AtomicBool::new(false),
+            watches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Raise the given flag once the given key changes (e.g. for `WATCH`), i.e. on its next event.
+    pub fn watch(&self, key: &str, changed: &Arc<AtomicBool>) -> CacheResult<()> {
+        let mut watches = self.watches.lock()?;
+        let flags: &mut Vec<Arc<AtomicBool>> = watches.entry(key.to_string()).or_default();
+        if !flags.iter().any(|flag| Arc::ptr_eq(flag, changed)) {
+            flags.push(Arc::clone(changed));
+        }
+        self.watching.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Stop raising the given flag when the given keys change.
+    pub fn unwatch(&self, keys: &[String], changed: &Arc<AtomicBool>) -> CacheResult<()> {
+        let mut watches = self.watches.lock()?;
+        for key in keys {
+            if let Some(flags) = watches.get_mut(key) {
+                flags.retain(|flag| !Arc::ptr_eq(flag, changed));
+                if flags.is_empty() {
+                    watches.remove(key);
+                }
+            }
+        }
+        self.watching.store(!watches.is_empty(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Raise the flags watching the given key, which has changed without an event (e.g. its TTL).
+    pub fn touch_watched(&self, key: &str) -> CacheResult<()> {
+        if !self.watching.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut watches = self.watches.lock()?;
+        for flag in watches.remove(key).into_iter().flatten() {
+            flag.store(true, Ordering::Relaxed);
+        }
+        self.watching.store(!watches.is_empty(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Raise the flags watching any key, e.g. once they are all removed.
+    pub fn touch_all_watched(&self) -> CacheResult<()> {
+        let mut watches = self.watches.lock()?;
+        for flag in watches.drain().flat_map(|(_, flags)| flags) {
+            flag.store(true, Ordering::Relaxed);
+        }
+        self.watching.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The kinds of events published on the pub/sub channels.
+    pub fn notifications(&self) -> KeyspaceNotifications {
+        KeyspaceNotifications(self.notifications.load(Ordering::Relaxed))
+    }
+
+    /// Change the kinds of events published on the pub/sub channels, taking effect with the next event.
+    pub fn set_notifications(&self, notifications: KeyspaceNotifications) -> CacheResult<()> {
+        self.notifications.store(notifications.0, Ordering::Relaxed);
+        if !self._tracking() {
+            *self.expirations.lock()? = Expirations::default();
+        }
+        Ok(())
+    }
+
+    /// Subscribe to the events of the keys matching the given glob pattern.
+    pub fn subscribe(&self, pattern: &str) -> CacheResult<Receiver<KeyspaceEvent>> {
+        let (sender, receiver) = mpsc::sync_channel::<KeyspaceEvent>(EVENT_QUEUE_CAPACITY);
+        let mut subscribers = self.subscribers.lock()?;
+        subscribers.push(Subscriber {
+            pattern: pattern.to_string(),
+            sender,
+        });
+        self.active.store(true, Ordering::Relaxed);
+        Ok(receiver)
+    }
+
+    /// Publish an event to the subscribers of its key. The subscribers which are gone or too far behind
+    /// are dropped (the latter find their receiver disconnected once they have caught up).
+    pub fn publish(&self, kind: KeyspaceEventKind, key: &str) -> CacheResult<()> {
+        self.touch_watched(key)?;
+        if self.notifications().contains(kind) {
+            let (database, key) = split_database(key);
+            let keyspace: String = format!("__keyspace@{}__:{}", database, key);
+            let keyevent: String = format!("__keyevent@{}__:{}", database, kind.as_str());
+            self.pubsub
+                .publish(&keyspace, Bytes::from(kind.as_str().as_bytes()))?;
+            self.pubsub
+                .publish(&keyevent, Bytes::from(key.as_bytes()))?;
+        }
+        if !self.active.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let mut subscribers = self.subscribers.lock()?;
+        subscribers.retain(|subscriber| {
+            if !glob_match(subscriber.pattern.as_bytes(), key.as_bytes()) {
+                return true;
+            }
+            let event: KeyspaceEvent = KeyspaceEvent {
+                kind,
+                key: key.to_string(),
+            };
+            match subscriber.sender.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+
+        if subscribers.is_empty() {
+            self.active.store(false, Ordering::Relaxed);
+            if !self._tracking() {
+                *self.expirations.lock()? = Expirations::default();
+            }
+        }
+        Ok(())
+    }
+
+    /// Track when the given key expires (a monotime), or stop tracking it if `None`.
+    pub fn track_expiration(&self, key: &str, expires_at: Option<u64>) -> CacheResult<()> {
+        if !self._tracking() {
+            return Ok(());
+        }
+
+        let mut expirations = self.expirations.lock()?;
+        if let Some(previous) = expirations.by_key.remove(key) {
+            expirations.by_time.remove(&(previous, key.to_string()));
+        }
+        if let Some(expires_at) = expires_at {
+            expirations.by_key.insert(key.to_string(), expires_at);
+            expirations.by_time.insert((expires_at, key.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Stop tracking when the keys expire, e.g. once they are all removed.
+    pub fn forget_expirations(&self) -> CacheResult<()> {
+        *self.expirations.lock()? = Expirations::default();
+        Ok(())
+    }
+
+    /// Publish the expired events of the keys whose expiration time has passed, if they are indeed gone.
+    ///
+    /// # Arguments
+    /// * `now` - The current monotime.
+    /// * `is_gone` - Whether a key is no longer cached (deleting it if it is expired).
+    pub fn publish_expired(
+        &self,
+        now: u64,
+        is_gone: impl Fn(&str) -> CacheResult<bool>,
+    ) -> CacheResult<()> {
+        if !self._tracking() {
+            return Ok(());
+        }
+
+        let mut expired: Vec<String> = Vec::new();
+        {
+            let mut expirations = self.expirations.lock()?;
+            while let Some((expires_at, _)) = expirations.by_time.first() {
+                if *expires_at > now {
+                    break;
+                }
+                if let Some((_, key)) = expirations.by_time.pop_first() {
+                    expirations.by_key.remove(&key);
+                    expired.push(key);
+                }
+            }
+        }
+
+        for key in expired {
+            if is_gone(&key)? {
+                self.publish(KeyspaceEventKind::Expired, &key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the expiration times of the keys are tracked, for the subscribers or the notifications.
+    fn _tracking(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+            || self.notifications().contains(KeyspaceEventKind::Expired)
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use crate::{cache::Bytes, error::CacheResult, utils::current_walltime};
+
+/// The default budget of the version history, in bytes of keys and values.
+pub const DEFAULT_HISTORY_BYTES: usize = 64 * 1024 * 1024;
+
+/// A version of the value of a key, as kept by the version history of an `EmbeddedCache` (see
+/// `EmbeddedCache::with_version_history`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub written_at: u64, // When the version was written (in milliseconds since the epoch)
+    pub value: Option<Bytes>, // The value written, or `None` if the key was removed
+}
+
+/// A version of a key, numbered across all the keys in the order they were written.
+struct Recorded {
+    sequence: u64, // The number of the version, which orders the versions of all the keys
+    version: Version,
+}
+
+/// The versions of the keys, newest last, within the bounds of the history.
+#[derive(Default)]
+struct Versions {
+    by_key: HashMap<String, VecDeque<Recorded>>,
+    by_sequence: BTreeMap<u64, String>, // The keys of the versions, oldest first (the eviction order)
+    bytes: usize,                       // The size of the keys and values of all the versions
+    next_sequence: u64,                 // The number of the next version written
+}
+
+impl Versions {
+    /// Drop the oldest version of the given key.
+    fn drop_oldest(&mut self, key: &str) {
+        let Some(versions) = self.by_key.get_mut(key) else {
+            return;
+        };
+        if let Some(oldest) = versions.pop_front() {
+            self.by_sequence.remove(&oldest.sequence);
+            self.bytes -= size_of_version(key, &oldest.version);
+        }
+        if versions.is_empty() {
+            self.by_key.remove(key);
+        }
+    }
+}
+
+/// Keeps the last versions of the values of the keys, e.g. to find out what was cached when a bug happened.
+///
+/// Each key keeps at most `max_versions` versions, and all the keys at most `max_bytes` bytes of keys and
+/// values, the oldest versions being dropped first. The versions outlive the values: the history of a key
+/// removed or expired is kept until it is pushed out by newer versions.
+pub(crate) struct VersionHistory {
+    max_versions: usize,
+    max_bytes: usize,
+    versions: Mutex<Versions>,
+}
+
+impl VersionHistory {
+    pub fn new(max_versions: usize, max_bytes: usize) -> VersionHistory {
+        VersionHistory {
+            max_versions,
+            max_bytes,
+            versions: Mutex::new(Versions::default()),
+        }
+    }
+
+    /// Record a new version of the given key: the value written, or `None` if the key was removed.
+    pub fn record(&self, key: &str, value: Option<Bytes>) -> CacheResult<()> {
+        let version: Version = Version {
+            written_at: current_walltime(),
+            value,
+        };
+        let size: usize = size_of_version(key, &version);
+        if size > self.max_bytes {
+            return Ok(());
+        }
+
+        let mut versions = self.versions.lock()?;
+        let sequence: u64 = versions.next_sequence;
+        versions.next_sequence += 1;
+        versions.by_sequence.insert(sequence, key.to_string());
+        versions.bytes += size;
+        let key_versions: &mut VecDeque<Recorded> =
+            versions.by_key.entry(key.to_string()).or_default();
+        key_versions.push_back(Recorded { sequence, version });
+
+        if key_versions.len() > self.max_versions {
+            versions.drop_oldest(key);
+        }
+        // The oldest version of all is the oldest version of its key
+        while versions.bytes > self.max_bytes {
+            let Some((_, oldest)) = versions.by_sequence.first_key_value() else {
+                break;
+            };
+            let oldest: String = oldest.clone();
+            versions.drop_oldest(&oldest);
+        }
+        Ok(())
+    }
+
+    /// The versions of the given key, newest first.
+    pub fn versions(&self, key: &str) -> CacheResult<Vec<Version>> {
+        let versions = self.versions.lock()?;
+        Ok(versions
+            .by_key
+            .get(key)
+            .map(|versions| {
+                versions
+                    .iter()
+                    .rev()
+                    .map(|recorded| recorded.version.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+/// The memory a version is accounted for: its key and value.
+fn size_of_version(key: &str, version: &Version) -> usize {
+    key.len() + version.value.as_ref().map_or(0, |value| value.len())
+}
@@ -0,0 +1,507 @@
+use std::{
+    io::{BufWriter, IoSlice, Write},
+    str,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, log, warn};
+use memchr::memmem;
+
+use crate::{
+    buffers::ConnectionBuffers,
+    cache::Bytes,
+    connection::{ClientHandler, ClientStream},
+    embedded::{EmbeddedCache, Lookup},
+    error::{CacheError, ServerError},
+    metrics::{self, METRICS_CONTENT_TYPE, METRICS_PATH},
+    protocol, rdb,
+    server::{parse_number, write_all_vectored},
+    stats::CommandProtocol,
+};
+
+/// The largest request head (request line and headers) accepted.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// The largest request body (i.e. value) accepted, like the longest command of the text protocol.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// The path under which the keys are exposed (e.g. `/keys/my_key`).
+pub(crate) const KEYS_PATH: &str = "/keys/";
+
+/// The path of the RDB dump of the cache, which other nodes fetch to warm up (see `warmup::WarmupSource`).
+const DUMP_PATH: &str = "/dump";
+
+/// A request received from an HTTP client, borrowing its parts from the received data.
+struct HttpRequest<'a> {
+    method: &'a str,
+    path: &'a str,          // The path of the target, without its query
+    query: Option<&'a str>, // The query of the target (after `?`), if any
+    ttl: Option<&'a str>,   // The value of the `X-TTL` header, if any
+    keep_alive: bool,       // Whether the connection stays open once the request is answered
+    body: &'a [u8],
+}
+
+/// The outcome of parsing the pending data of a connection.
+enum Parsed<'a> {
+    Request(HttpRequest<'a>, usize), // A request, and the number of bytes it takes
+    Incomplete,                      // More data is needed to parse the next request
+    Invalid(u16, &'static str), // The data is not a valid request: the status to answer, and why
+}
+
+/// What an HTTP listener serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HttpRoutes {
+    Keys,    // The keys as REST resources, and the dump of the cache
+    Metrics, // Only the metrics of the server, for Prometheus to scrape (see `metrics::render`)
+}
+
+/// A handler for a client speaking HTTP/1.1, exposing the keys as REST resources.
+///
+/// `GET`, `PUT` and `DELETE` requests on `/keys/{key}` get, set and delete the value of the key, which is
+/// the body of the request or response. The TTL of a value is set with the `X-TTL` header or the `ttl`
+/// query parameter. Errors are answered with a JSON body (e.g. `{"error": "Key not found"}`).
+///
+/// `GET /dump` answers with an RDB dump of the whole cache (see `rdb::export`).
+///
+/// On the metrics listener, only `GET /metrics` is served, answering with the metrics of the server in the
+/// Prometheus text format.
+pub(crate) struct HttpClientHandler {
+    address: String, // The address of the client (IP:Port). Used for logging purposes.
+    stream: BufWriter<ClientStream>, // The client connection, buffering the responses until the next flush
+    cache: Arc<EmbeddedCache>,       // A reference to the cache instance shared across all handlers
+    buffers: ConnectionBuffers, // The received data, in buffers sized to the traffic of the client
+    routes: HttpRoutes,         // What the listener of the connection serves
+    closing: bool,              // Whether the connection is closed once the responses are flushed
+    answered_error: bool, // Whether the request being handled was answered with an error status
+}
+
+impl ClientHandler for HttpClientHandler {
+    const PROTOCOL: &'static str = "HTTP";
+    type Options = HttpRoutes;
+
+    fn new(
+        stream: ClientStream,
+        cache: Arc<EmbeddedCache>,
+        buffers: ConnectionBuffers,
+        options: &Arc<HttpRoutes>,
+    ) -> HttpClientHandler {
+        HttpClientHandler {
+            address: stream.peer_address(),
+            stream: BufWriter::new(stream),
+            cache,
+            buffers,
+            routes: **options,
+            closing: false,
+            answered_error: false,
+        }
+    }
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn connection(&mut self) -> (&ClientStream, &mut ConnectionBuffers) {
+        (self.stream.get_ref(), &mut self.buffers)
+    }
+
+    fn handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        self._handle_pending(pending)
+    }
+
+    fn reject_overflow(&mut self) {
+        self.closing = true;
+        let _ = self._write_error(503, "Out of buffer memory");
+        let _ = self.stream.flush();
+    }
+
+    fn reject_too_many_clients(&mut self) {
+        self.closing = true;
+        let _ = self._write_error(503, "Too many clients");
+        let _ = self.stream.flush();
+    }
+
+    fn reject_timeout(&mut self) {
+        self.closing = true;
+        let _ = self._write_error(503, "Command timeout exceeded");
+        let _ = self.stream.flush();
+    }
+
+    fn is_closing(&self) -> bool {
+        self.closing
+    }
+}
+
+impl HttpClientHandler {
+    /// Handle the complete requests of the given pending data (pipelined requests are answered in order),
+    /// draining them.
+    fn _handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        let mut start: usize = 0;
+
+        while !self.closing {
+            match parse(&pending[start..]) {
+                Parsed::Request(request, length) => {
+                    self.closing = !request.keep_alive;
+                    self._handle_request(&request)?;
+                    start += length;
+                }
+                Parsed::Incomplete => break,
+                Parsed::Invalid(status, reason) => {
+                    // The end of the faulty request is unknown, so the connection cannot be resynchronized
+                    warn!("Invalid request from {}: {}", self.address, reason);
+                    self.closing = true;
+                    self._write_error(status, reason)?;
+                }
+            }
+        }
+        pending.drain(..start);
+
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Handle a request, reporting cache errors to the client.
+    fn _handle_request(&mut self, request: &HttpRequest) -> Result<(), ServerError> {
+        debug!(
+            "Received request from {} -> {} {}",
+            self.address, request.method, request.path
+        );
+        if self.routes == HttpRoutes::Metrics {
+            return self._handle_metrics_request(request);
+        }
+        let started: Instant = Instant::now();
+        self.answered_error = false;
+
+        let result: Result<(), ServerError> = match request.path.strip_prefix(KEYS_PATH) {
+            Some(key) => self._handle_key_request(key, request),
+            None if request.path == DUMP_PATH => match request.method {
+                "GET" => self._handle_dump(),
+                _ => self._write_method_not_allowed("GET"),
+            },
+            None => self._write_error(404, "Not found"),
+        };
+        let elapsed: Duration = started.elapsed();
+        let failed: bool = result.is_err() || self.answered_error;
+        self.cache.record_command(
+            CommandProtocol::Http,
+            elapsed,
+            &self.address,
+            None,
+            failed,
+            || vec![request.method.as_bytes(), request.path.as_bytes()],
+        );
+
+        match result {
+            Err(ServerError::Cache(e)) => {
+                log!(
+                    e.log_level(),
+                    "Failed to execute the request from {}: {}",
+                    self.address,
+                    e
+                );
+                match e {
+                    CacheError::OutOfMemory => self._write_error(507, &e.to_string()),
+                    CacheError::KeyTooLarge(_) => self._write_error(414, &e.to_string()),
+                    CacheError::ValueTooLarge(_) => self._write_error(413, &e.to_string()),
+                    e => self._write_error(500, &e.to_string()),
+                }
+            }
+            other => other,
+        }
+    }
+
+    /// Handle a request to the metrics listener, which only serves `GET /metrics`. Scrapes are not counted
+    /// as commands.
+    fn _handle_metrics_request(&mut self, request: &HttpRequest) -> Result<(), ServerError> {
+        if request.path != METRICS_PATH {
+            return self._write_error(404, "Not found");
+        }
+        if request.method != "GET" {
+            return self._write_method_not_allowed("GET");
+        }
+
+        match metrics::render(&self.cache) {
+            Ok(body) => self._write_response(200, METRICS_CONTENT_TYPE, &[], body.as_bytes()),
+            Err(e) => {
+                error!("Failed to render the metrics for {}: {}", self.address, e);
+                self._write_error(500, &e.to_string())
+            }
+        }
+    }
+
+    /// Handle a request on `/keys/{key}`, leaving cache errors to the caller.
+    fn _handle_key_request(&mut self, key: &str, request: &HttpRequest) -> Result<(), ServerError> {
+        let Some(key) = percent_decode(key)
+            .filter(|key| !key.is_empty() && protocol::as_key(key.as_bytes()).is_some())
+        else {
+            return self._write_error(400, "Key must be valid percent-encoded UTF-8");
+        };
+
+        match request.method {
+            "GET" => self._handle_get(&key),
+            "PUT" => self._handle_put(&key, request),
+            "DELETE" => self._handle_delete(&key),
+            _ => self._write_method_not_allowed("GET, PUT, DELETE"),
+        }
+    }
+
+    /// Handle `GET /keys/{key}`. Stale values are flagged with the `X-Cache-Status: stale` header.
+    fn _handle_get(&mut self, key: &str) -> Result<(), ServerError> {
+        match self.cache.get(key)? {
+            Lookup::Fresh(value) => self._write_value(&value, "fresh"),
+            Lookup::Stale(value) => self._write_value(&value, "stale"),
+            Lookup::Negative => self._write_response(
+                404,
+                "application/json",
+                &["X-Cache-Status: negative"],
+                br#"{"error": "Key cached as absent"}"#,
+            ),
+            Lookup::Missing | Lookup::Recompute => self._write_error(404, "Key not found"),
+        }
+    }
+
+    /// Handle `PUT /keys/{key}`, the body being the value, with an optional TTL in seconds (the `X-TTL`
+    /// header, or else the `ttl` query parameter).
+    fn _handle_put(&mut self, key: &str, request: &HttpRequest) -> Result<(), ServerError> {
+        let maybe_ttl: Option<&str> = request.ttl.or_else(|| {
+            request.query.and_then(|query| {
+                query
+                    .split('&')
+                    .find_map(|parameter| parameter.strip_prefix("ttl="))
+            })
+        });
+
+        let ttl: Option<u64> = match maybe_ttl {
+            Some(ttl) => match parse_number(ttl.trim().as_bytes()) {
+                Some(ttl) if ttl > 0 => Some(ttl),
+                _ => return self._write_error(400, "TTL must be a positive number of seconds"),
+            },
+            None => None,
+        };
+
+        self.cache.put(key, Bytes::from(request.body), ttl, None)?;
+        self._write_response(204, "", &[], b"")
+    }
+
+    /// Handle `DELETE /keys/{key}`.
+    fn _handle_delete(&mut self, key: &str) -> Result<(), ServerError> {
+        match self.cache.remove(key)? {
+            Some(_) => self._write_response(204, "", &[], b""),
+            None => self._write_error(404, "Key not found"),
+        }
+    }
+
+    /// Handle `GET /dump`, answering with an RDB dump of the cache, built in memory.
+    fn _handle_dump(&mut self) -> Result<(), ServerError> {
+        let mut dump: Vec<u8> = Vec::new();
+        let keys: usize = rdb::export(&self.cache, &mut dump)?;
+        info!(
+            "Dumped {} key(s) ({} bytes) for {}",
+            keys,
+            dump.len(),
+            self.address
+        );
+        self._write_response(200, "application/octet-stream", &[], &dump)
+    }
+
+    fn _write_method_not_allowed(&mut self, allowed: &str) -> Result<(), ServerError> {
+        let header: String = format!("Allow: {}", allowed);
+        self._write_response(
+            405,
+            "application/json",
+            &[&header],
+            br#"{"error": "Method not allowed"}"#,
+        )
+    }
+
+    fn _write_value(&mut self, value: &[u8], status: &str) -> Result<(), ServerError> {
+        let header: String = format!("X-Cache-Status: {}", status);
+        self._write_response(200, "application/octet-stream", &[&header], value)
+    }
+
+    /// Write an error response with a JSON body holding its message.
+    fn _write_error(&mut self, status: u16, message: &str) -> Result<(), ServerError> {
+        let body: String = format!("{{\"error\": {}}}", json_string(message));
+        self._write_response(status, "application/json", &[], body.as_bytes())
+    }
+
+    /// Write a response, its body straight from the given buffer.
+    ///
+    /// # Arguments
+    /// * `status` - The status code of the response.
+    /// * `content_type` - The type of the body, if it is not empty.
+    /// * `headers` - The additional headers, as `Name: value` lines without their line terminator.
+    /// * `body` - The body of the response.
+    fn _write_response(
+        &mut self,
+        status: u16,
+        content_type: &str,
+        headers: &[&str],
+        body: &[u8],
+    ) -> Result<(), ServerError> {
+        self.answered_error |= status >= 400;
+        let mut head: String = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n",
+            status,
+            reason_phrase(status),
+            body.len()
+        );
+        if !content_type.is_empty() {
+            head.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        for header in headers {
+            head.push_str(header);
+            head.push_str("\r\n");
+        }
+        if self.closing {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+
+        write_all_vectored(
+            &mut self.stream,
+            &mut [IoSlice::new(head.as_bytes()), IoSlice::new(body)],
+        )?;
+        debug!("Response sent to {}: {}", self.address, status);
+        Ok(())
+    }
+}
+
+/// Parse the request at the start of the given data. Bodies must be sized by `Content-Length`, since
+/// chunked requests are not supported.
+fn parse(data: &[u8]) -> Parsed<'_> {
+    let Some(head_end) = memmem::find(data, b"\r\n\r\n") else {
+        return match data.len() > MAX_HEAD_SIZE {
+            true => Parsed::Invalid(431, "Request header fields too large"),
+            false => Parsed::Incomplete,
+        };
+    };
+    if head_end > MAX_HEAD_SIZE {
+        return Parsed::Invalid(431, "Request header fields too large");
+    }
+    let Ok(head) = str::from_utf8(&data[..head_end]) else {
+        return Parsed::Invalid(400, "Malformed request");
+    };
+
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target), Some(version), None) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Parsed::Invalid(400, "Malformed request line");
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Parsed::Invalid(505, "HTTP version not supported");
+    }
+
+    // HTTP/1.1 connections are persistent unless closed, HTTP/1.0 ones are closed unless kept alive
+    let mut keep_alive: bool = version != "HTTP/1.0";
+    let mut content_length: usize = 0;
+    let mut ttl: Option<&str> = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Parsed::Invalid(400, "Malformed header");
+        };
+        let value: &str = value.trim();
+
+        if name.eq_ignore_ascii_case("Content-Length") {
+            match parse_number(value.as_bytes()) {
+                Some(length) if length as usize <= MAX_BODY_SIZE => {
+                    content_length = length as usize
+                }
+                Some(_) => return Parsed::Invalid(413, "Content too large"),
+                None => return Parsed::Invalid(400, "Invalid Content-Length"),
+            }
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            return Parsed::Invalid(411, "Length required");
+        } else if name.eq_ignore_ascii_case("Connection") {
+            keep_alive = match value.to_ascii_lowercase().as_str() {
+                "close" => false,
+                "keep-alive" => true,
+                _ => keep_alive,
+            };
+        } else if name.eq_ignore_ascii_case("X-TTL") {
+            ttl = Some(value);
+        }
+    }
+
+    let length: usize = head_end + 4 + content_length;
+    if data.len() < length {
+        return Parsed::Incomplete;
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+    let request: HttpRequest = HttpRequest {
+        method,
+        path,
+        query,
+        ttl,
+        keep_alive,
+        body: &data[head_end + 4..length],
+    };
+    Parsed::Request(request, length)
+}
+
+/// Decode a percent-encoded path segment (e.g. `my%20key`). Returns `None` if it is not valid UTF-8 once
+/// decoded.
+pub(crate) fn percent_decode(segment: &str) -> Option<String> {
+    let bytes: &[u8] = segment.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex: &str = segment.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Quote the given text as a JSON string.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut quoted: String = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        507 => "Insufficient Storage",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// A range of IP addresses in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`. A bare address (without
+/// prefix length) is a range of that single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr, // The first address of the range, the bits past the prefix being cleared
+    prefix: u8,      // How many leading bits of the addresses are those of the network
+}
+
+impl IpRange {
+    /// Whether the given address is within the range. The IPv4 addresses mapped to IPv6 (`::ffff:a.b.c.d`)
+    /// are matched as the IPv4 addresses they map.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => network == mask_v4(address, self.prefix),
+            (IpAddr::V6(network), IpAddr::V6(address)) => network == mask_v6(address, self.prefix),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<IpRange, String> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (text, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address", address))?;
+
+        let bits: u8 = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix: u8 = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= bits => prefix,
+                _ => {
+                    return Err(format!(
+                        "'{}' is not a prefix length of 0 to {}",
+                        prefix, bits
+                    ))
+                }
+            },
+            None => bits,
+        };
+
+        let network: IpAddr = match address {
+            IpAddr::V4(address) => IpAddr::V4(mask_v4(address, prefix)),
+            IpAddr::V6(address) => IpAddr::V6(mask_v6(address, prefix)),
+        };
+        Ok(IpRange { network, prefix })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Which clients may connect to the server, by their IP address: those of no denied range, and of an
+/// allowed range unless no range is allowed. A denied range takes precedence over an allowed one, so that
+/// e.g. a host can be shut out of an allowed subnet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    allowed: Vec<IpRange>, // The ranges the clients must connect from, if any
+    denied: Vec<IpRange>,  // The ranges the clients may not connect from
+}
+
+impl IpFilter {
+    /// Create a new `IpFilter` with the given allowed and denied ranges.
+    ///
+    /// # Arguments
+    /// * `allowed` - The ranges the clients must connect from, or none to allow any address not denied.
+    /// * `denied` - The ranges the clients may not connect from.
+    pub fn new(allowed: Vec<IpRange>, denied: Vec<IpRange>) -> IpFilter {
+        IpFilter { allowed, denied }
+    }
+
+    /// Whether the filter lets every client connect.
+    pub fn is_open(&self) -> bool {
+        self.allowed.is_empty() && self.denied.is_empty()
+    }
+
+    /// Whether a client may connect from the given address.
+    pub fn permits(&self, address: IpAddr) -> bool {
+        if self.denied.iter().any(|range| range.contains(address)) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|range| range.contains(address))
+    }
+}
+
+/// Clear the bits of the given IPv4 address past the given prefix length.
+fn mask_v4(address: Ipv4Addr, prefix: u8) -> Ipv4Addr {
+    let mask: u32 = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    Ipv4Addr::from(u32::from(address) & mask)
+}
+
+/// Clear the bits of the given IPv6 address past the given prefix length.
+fn mask_v6(address: Ipv6Addr, prefix: u8) -> Ipv6Addr {
+    let mask: u128 = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+    Ipv6Addr::from(u128::from(address) & mask)
+}
@@ -0,0 +1,81 @@
+//! A simple in-memory cache with optional per-entry time-to-live (TTL).
+//!
+//! The cache can either be embedded in-process through the [`Cache`] trait, built by a [`CacheBuilder`] with
+//! the storage engines of the server (or an [`EmbeddedCache`] for its full feature set), or served to remote
+//! clients over TCP by a [`CacheServer`]:
+//!
+//! ```no_run
+//! use myrustcache::{Cache, CacheBuilder, EvictionPolicy};
+//!
+//! let cache = CacheBuilder::new()
+//!     .max_entries(10_000)
+//!     .eviction(EvictionPolicy::Lru)
+//!     .build::<String, String>()?;
+//! cache.put("answer".to_string(), "42".to_string(), Some(60))?;
+//! assert_eq!(cache.get(&"answer".to_string())?, Some("42".to_string()));
+//! # Ok::<(), myrustcache::CacheError>(())
+//! ```
+
+pub mod acl;
+pub mod audit;
+mod auth;
+mod binary;
+pub mod blocking;
+mod buffers;
+pub mod cache;
+pub mod client;
+pub mod clients;
+pub mod cluster;
+pub mod compression;
+pub mod config;
+mod connection;
+pub mod dataset;
+pub mod embedded;
+pub mod error;
+pub mod events;
+pub mod history;
+mod http;
+pub mod ipfilter;
+pub mod logging;
+mod memcached;
+mod metrics;
+mod monitor;
+mod msgpack;
+pub mod namespaces;
+mod preload;
+mod primary;
+mod protobuf;
+pub mod protocol;
+mod pubsub;
+mod ratelimit;
+pub mod rdb;
+mod replica;
+mod resp;
+mod runtime;
+pub mod script;
+pub mod server;
+mod shutdown;
+pub mod slowlog;
+mod snapshot;
+pub mod stats;
+mod transaction;
+mod udp;
+mod utils;
+pub mod values;
+pub mod warmup;
+mod websocket;
+pub mod workers;
+
+pub use buffers::BufferStats;
+pub use cache::{
+    Bytes, Cache, CacheBuilder, CacheFactory, Codec, EvictionPolicy, LeftRightCache, LfuCache,
+    LruCache, MmapCache, PutCondition, ShardedCache, SimpleCache, StorageEngine, SyncPolicy,
+    TieredCache, TypedCache,
+};
+pub use config::{ConfigError, ServerConfig};
+pub use embedded::EmbeddedCache;
+pub use error::{CacheError, CacheResult, ServerError};
+pub use server::{CacheServer, ServerCache};
+pub use shutdown::ShutdownHandle;
+pub use websocket::WebSocketPolicy;
+pub use workers::{RejectionPolicy, WorkerOptions};
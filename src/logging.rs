@@ -0,0 +1,105 @@
+use std::sync::{OnceLock, RwLock};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// The logger installed by `init`, whose filters can be replaced while the server runs.
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+/// An `env_logger` logger which can be rebuilt with other filters (e.g. with `CONFIG SET log_level`), since
+/// the logger of the `log` crate can only be installed once.
+struct ReloadableLogger {
+    inner: RwLock<(String, env_logger::Logger)>, // The filters, and the logger built from them
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner
+            .read()
+            .is_ok_and(|inner| inner.1.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Ok(inner) = self.inner.read() {
+            inner.1.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(inner) = self.inner.read() {
+            inner.1.flush();
+        }
+    }
+}
+
+/// Install the logger of the server, filtering the records as `RUST_LOG` does, or with the given filters
+/// (in the same syntax, e.g. `info` or `myrustcache=debug`) overriding it.
+///
+/// Does nothing if a logger is already installed.
+pub fn init(maybe_filters: Option<&str>) {
+    let filters: String = maybe_filters
+        .map(str::to_string)
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| "error".to_string());
+    let logger: env_logger::Logger = build(&filters);
+    let max_level: LevelFilter = logger.filter();
+
+    let reloadable: &ReloadableLogger = LOGGER.get_or_init(|| ReloadableLogger {
+        inner: RwLock::new((filters, logger)),
+    });
+    if log::set_logger(reloadable).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// The filters of the logger installed by `init`, or `None` if it was not.
+pub fn filters() -> Option<String> {
+    let inner = LOGGER.get()?.inner.read().ok()?;
+    Some(inner.0.clone())
+}
+
+/// Replace the filters of the logger installed by `init`.
+///
+/// # Errors
+/// * If the filters are invalid, or the logger was not installed by `init` (e.g. the cache is embedded in
+///   an application with a logger of its own).
+pub fn set_filters(filters: &str) -> Result<(), String> {
+    validate(filters)?;
+    let Some(reloadable) = LOGGER.get() else {
+        return Err("the logger cannot be reconfigured".to_string());
+    };
+
+    let logger: env_logger::Logger = build(filters);
+    let max_level: LevelFilter = logger.filter();
+    if let Ok(mut inner) = reloadable.inner.write() {
+        *inner = (filters.to_string(), logger);
+    }
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+fn build(filters: &str) -> env_logger::Logger {
+    env_logger::Builder::new().parse_filters(filters).build()
+}
+
+/// Check the directives of the given filters, which `env_logger` would only report on the standard error.
+fn validate(filters: &str) -> Result<(), String> {
+    let directives: &str = filters.split('/').next().unwrap_or_default();
+    for directive in directives.split(',').map(str::trim) {
+        let level: &str = match directive.split_once('=') {
+            Some((module, level)) if !module.is_empty() => level,
+            Some(_) => return Err(format!("invalid log directive '{}'", directive)),
+            None => directive,
+        };
+
+        // A directive without `=` may name a module instead of a level
+        let is_module: bool = !directive.contains('=')
+            && !level.is_empty()
+            && level
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == ':');
+        if level.parse::<LevelFilter>().is_err() && !is_module {
+            return Err(format!("invalid log level '{}'", level));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,477 @@
+use std::{
+    io::{self, BufWriter, IoSlice, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{debug, log, warn};
+use memchr::memchr;
+
+use crate::{
+    buffers::ConnectionBuffers,
+    cache::Bytes,
+    connection::{ClientHandler, ClientStream},
+    embedded::{EmbeddedCache, Lookup},
+    error::{CacheError, ServerError},
+    protocol::{self, Tokens},
+    server::{parse_number, write_all_vectored},
+    stats::CommandProtocol,
+    utils::{current_walltime, Decimal},
+};
+
+/// The longest command line accepted (a key is at most 250 bytes, as in memcached).
+const MAX_LINE_SIZE: usize = 2048;
+
+/// The longest key accepted, as in memcached.
+const MAX_KEY_SIZE: usize = 250;
+
+/// The largest value accepted, like the longest command of the text protocol.
+const MAX_VALUE_SIZE: usize = 1024 * 1024;
+
+/// Expiration times above 30 days are Unix timestamps rather than a number of seconds, as in memcached.
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+/// The number of bytes of the header holding the flags of a value stored over the memcached protocol.
+const FLAGS_SIZE: usize = 4;
+
+/// The commands followed by a data block.
+const STORAGE_COMMANDS: [&[u8]; 3] = [b"set", b"add", b"replace"];
+
+/// The CAS unique reported by `gets`, since values have no version to compare and swap.
+const NO_CAS: u64 = 0;
+
+/// The outcome of parsing the pending data of a connection.
+enum Parsed<'a> {
+    Command(Vec<&'a [u8]>, Option<&'a [u8]>, usize), // The tokens, the data block (if any) and the length
+    Incomplete,            // More data is needed to parse the next command
+    Invalid(&'static str), // The data does not follow the protocol
+}
+
+/// A handler for a client speaking the memcached text (ASCII) protocol.
+///
+/// The `get`, `gets`, `set`, `add`, `replace`, `delete`, `touch`, `flush_all`, `verbosity`, `version` and
+/// `quit` commands are mapped onto the `EmbeddedCache` served, so that memcached clients can use the cache as
+/// a drop-in replacement. The flags of a value are kept in a 4-byte header in front of it, so values stored
+/// over this protocol are meant to be read over it too.
+pub(crate) struct MemcachedClientHandler {
+    address: String, // The address of the client (IP:Port). Used for logging purposes.
+    stream: BufWriter<ClientStream>, // The client connection, buffering the responses until the next flush
+    cache: Arc<EmbeddedCache>,       // A reference to the cache instance shared across all handlers
+    buffers: ConnectionBuffers, // The received data, in buffers sized to the traffic of the client
+    closing: bool,              // Whether the client has sent `quit`
+    answered_error: bool,       // Whether the command being executed was answered with an error
+}
+
+impl ClientHandler for MemcachedClientHandler {
+    const PROTOCOL: &'static str = "memcached";
+    type Options = ();
+
+    fn new(
+        stream: ClientStream,
+        cache: Arc<EmbeddedCache>,
+        buffers: ConnectionBuffers,
+        _options: &Arc<()>,
+    ) -> MemcachedClientHandler {
+        MemcachedClientHandler {
+            address: stream.peer_address(),
+            stream: BufWriter::new(stream),
+            cache,
+            buffers,
+            closing: false,
+            answered_error: false,
+        }
+    }
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn connection(&mut self) -> (&ClientStream, &mut ConnectionBuffers) {
+        (self.stream.get_ref(), &mut self.buffers)
+    }
+
+    fn handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        self._handle_pending(pending)
+    }
+
+    fn reject_overflow(&mut self) {
+        let _ = self._write_response("SERVER_ERROR out of memory\r\n");
+        let _ = self.stream.flush();
+    }
+
+    fn reject_too_many_clients(&mut self) {
+        let _ = self._write_response("SERVER_ERROR too many open connections\r\n");
+        let _ = self.stream.flush();
+    }
+
+    fn reject_timeout(&mut self) {
+        let _ = self._write_response("SERVER_ERROR command timeout exceeded\r\n");
+        let _ = self.stream.flush();
+    }
+
+    fn is_closing(&self) -> bool {
+        self.closing
+    }
+}
+
+impl MemcachedClientHandler {
+    /// Handle the complete commands of the given pending data, draining them.
+    fn _handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        let mut start: usize = 0;
+
+        while !self.closing {
+            match parse(&pending[start..]) {
+                Parsed::Command(tokens, data, length) => {
+                    self._handle_command(&tokens, data)?;
+                    start += length;
+                }
+                Parsed::Incomplete => break,
+                Parsed::Invalid(reason) => {
+                    // The end of the faulty command is unknown, so the connection cannot be resynchronized
+                    warn!("Protocol error from {}: {}", self.address, reason);
+                    self._write_response(&format!("CLIENT_ERROR {}\r\n", reason))?;
+                    self.stream.flush()?;
+                    return Err(ServerError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        reason,
+                    )));
+                }
+            }
+        }
+        pending.drain(..start);
+
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Handle a command, reporting cache errors to the client.
+    fn _handle_command(
+        &mut self,
+        tokens: &[&[u8]],
+        data: Option<&[u8]>,
+    ) -> Result<(), ServerError> {
+        let Some((name, arguments)) = tokens.split_first() else {
+            return self._write_response("ERROR\r\n");
+        };
+        debug!(
+            "Received command from {} -> {}",
+            self.address,
+            String::from_utf8_lossy(name)
+        );
+        let started: Instant = Instant::now();
+        self.answered_error = false;
+
+        let result: Result<(), ServerError> = match (*name, data) {
+            (b"get", None) => self._handle_get_command(arguments, false),
+            (b"gets", None) => self._handle_get_command(arguments, true),
+            (b"set" | b"add" | b"replace", Some(data)) => {
+                self._handle_set_command(name, arguments, data)
+            }
+            (b"delete", None) => self._handle_delete_command(arguments),
+            (b"touch", None) => self._handle_touch_command(arguments),
+            (b"flush_all", None) => self._handle_flush_all_command(arguments),
+            (b"verbosity", None) => {
+                let (_, noreply) = split_noreply(arguments);
+                self._reply(noreply, "OK\r\n")
+            }
+            (b"version", None) => {
+                self._write_response(concat!("VERSION ", env!("CARGO_PKG_VERSION"), "\r\n"))
+            }
+            (b"quit", None) => {
+                self.closing = true;
+                Ok(())
+            }
+            _ => {
+                warn!(
+                    "Unknown command {} from {}",
+                    String::from_utf8_lossy(name),
+                    self.address
+                );
+                self._write_response("ERROR\r\n")
+            }
+        };
+        let elapsed: Duration = started.elapsed();
+        let failed: bool = result.is_err() || self.answered_error;
+        self.cache.record_command(
+            CommandProtocol::Memcached,
+            elapsed,
+            &self.address,
+            None,
+            failed,
+            || tokens.to_vec(),
+        );
+
+        match result {
+            Err(ServerError::Cache(e)) => {
+                log!(
+                    e.log_level(),
+                    "Failed to execute the command from {}: {}",
+                    self.address,
+                    e
+                );
+                match e {
+                    CacheError::OutOfMemory => {
+                        self._write_response("SERVER_ERROR out of memory storing object\r\n")
+                    }
+                    CacheError::ValueTooLarge(_) => {
+                        self._write_response("SERVER_ERROR object too large for cache\r\n")
+                    }
+                    e => self._write_response(&format!("SERVER_ERROR {}\r\n", e)),
+                }
+            }
+            other => other,
+        }
+    }
+
+    /// Handle `get <key>*` (or `gets <key>*`, `with_cas`), replying with the values of the keys found. Stale
+    /// values are returned as fresh ones, and keys cached as absent as missing.
+    fn _handle_get_command(&mut self, keys: &[&[u8]], with_cas: bool) -> Result<(), ServerError> {
+        if keys.is_empty() {
+            return self._write_response("ERROR\r\n");
+        }
+
+        for key in keys {
+            let Some(key) = self._parse_key(key) else {
+                continue;
+            };
+            if let Lookup::Fresh(value) | Lookup::Stale(value) = self.cache.get(key)? {
+                self._write_value(key, &value, with_cas)?;
+            }
+        }
+        self._write_response("END\r\n")
+    }
+
+    /// Handle `set <key> <flags> <exptime> <bytes> [noreply]`, followed by the data block. `add` only stores
+    /// the value of a missing key, and `replace` of an existing one (otherwise replying `NOT_STORED`).
+    fn _handle_set_command(
+        &mut self,
+        name: &[u8],
+        arguments: &[&[u8]],
+        data: &[u8],
+    ) -> Result<(), ServerError> {
+        let (arguments, noreply) = split_noreply(arguments);
+        let [key, flags, exptime, _] = arguments else {
+            return self._write_response("ERROR\r\n");
+        };
+        let (Some(key), Some(flags), Some(exptime)) = (
+            self._parse_key(key),
+            parse_number(flags).and_then(|flags| u32::try_from(flags).ok()),
+            parse_exptime(exptime),
+        ) else {
+            return self._write_response("CLIENT_ERROR bad command line format\r\n");
+        };
+
+        if name != b"set" {
+            let exists: bool = matches!(self.cache.get(key)?, Lookup::Fresh(_) | Lookup::Stale(_));
+            if exists != (name == b"replace") {
+                return self._reply(noreply, "NOT_STORED\r\n");
+            }
+        }
+
+        match exptime {
+            Expiry::Expired => {
+                self.cache.remove(key)?;
+            }
+            Expiry::After(maybe_ttl) => {
+                let mut value: Vec<u8> = Vec::with_capacity(FLAGS_SIZE + data.len());
+                value.extend_from_slice(&flags.to_be_bytes());
+                value.extend_from_slice(data);
+                self.cache.put(key, value.into(), maybe_ttl, None)?;
+            }
+        }
+        self._reply(noreply, "STORED\r\n")
+    }
+
+    /// Handle `delete <key> [noreply]`.
+    fn _handle_delete_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (arguments, noreply) = split_noreply(arguments);
+        let [key] = arguments else {
+            return self._write_response("ERROR\r\n");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_response("CLIENT_ERROR bad command line format\r\n");
+        };
+
+        match self.cache.remove(key)? {
+            Some(_) => self._reply(noreply, "DELETED\r\n"),
+            None => self._reply(noreply, "NOT_FOUND\r\n"),
+        }
+    }
+
+    /// Handle `touch <key> <exptime> [noreply]`, changing the expiration time of a value.
+    fn _handle_touch_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (arguments, noreply) = split_noreply(arguments);
+        let [key, exptime] = arguments else {
+            return self._write_response("ERROR\r\n");
+        };
+        let (Some(key), Some(exptime)) = (self._parse_key(key), parse_exptime(exptime)) else {
+            return self._write_response("CLIENT_ERROR bad command line format\r\n");
+        };
+
+        let touched: bool = match exptime {
+            Expiry::Expired => self.cache.remove(key)?.is_some(),
+            Expiry::After(maybe_ttl) => self.cache.expire(key, maybe_ttl)?,
+        };
+        match touched {
+            true => self._reply(noreply, "TOUCHED\r\n"),
+            false => self._reply(noreply, "NOT_FOUND\r\n"),
+        }
+    }
+
+    /// Handle `flush_all [0] [noreply]`, removing every key. Delayed flushes are not supported.
+    fn _handle_flush_all_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (arguments, noreply) = split_noreply(arguments);
+        match arguments {
+            [] | [b"0"] => {}
+            [_] => return self._write_response("CLIENT_ERROR delayed flush not supported\r\n"),
+            _ => return self._write_response("ERROR\r\n"),
+        }
+
+        let removed: usize = self.cache.clear_prefix("")?;
+        debug!("Flushed {} keys for {}", removed, self.address);
+        self._reply(noreply, "OK\r\n")
+    }
+
+    /// Validate that a key is UTF-8 text of at most 250 bytes. Returns `None` (and logs a warning) if not.
+    fn _parse_key<'a>(&self, key: &'a [u8]) -> Option<&'a str> {
+        let maybe_key: Option<&str> = protocol::as_key(key).filter(|key| key.len() <= MAX_KEY_SIZE);
+
+        if maybe_key.is_none() {
+            warn!("Command with an invalid key sent from {}", self.address);
+        }
+
+        maybe_key
+    }
+
+    /// Write a `VALUE` response straight from the buffer of the value, separating its flags from its data
+    /// (followed by a CAS unique for `gets`).
+    fn _write_value(
+        &mut self,
+        key: &str,
+        value: &Bytes,
+        with_cas: bool,
+    ) -> Result<(), ServerError> {
+        // Values stored over another protocol have no flags
+        let (flags, data): (u32, &[u8]) = match value.split_first_chunk::<FLAGS_SIZE>() {
+            Some((flags, data)) => (u32::from_be_bytes(*flags), data),
+            None => (0, value),
+        };
+
+        let (flags, length): (Decimal, Decimal) = (
+            Decimal::unsigned(flags as u64),
+            Decimal::unsigned(data.len() as u64),
+        );
+        let no_cas: Decimal = Decimal::unsigned(NO_CAS);
+        let (separator, cas): (&[u8], &[u8]) = match with_cas {
+            true => (b" ", no_cas.as_bytes()),
+            false => (b"", b""),
+        };
+        write_all_vectored(
+            &mut self.stream,
+            &mut [
+                IoSlice::new(b"VALUE "),
+                IoSlice::new(key.as_bytes()),
+                IoSlice::new(b" "),
+                IoSlice::new(flags.as_bytes()),
+                IoSlice::new(b" "),
+                IoSlice::new(length.as_bytes()),
+                IoSlice::new(separator),
+                IoSlice::new(cas),
+                IoSlice::new(b"\r\n"),
+                IoSlice::new(data),
+                IoSlice::new(b"\r\n"),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Write a response, unless the client has asked for none.
+    fn _reply(&mut self, noreply: bool, response: &str) -> Result<(), ServerError> {
+        match noreply {
+            true => Ok(()),
+            false => self._write_response(response),
+        }
+    }
+
+    /// Write a response back to the client (buffered until the next flush).
+    fn _write_response(&mut self, response: &str) -> Result<(), ServerError> {
+        self.answered_error |= ["ERROR", "CLIENT_ERROR", "SERVER_ERROR"]
+            .iter()
+            .any(|error| response.starts_with(error));
+        self.stream.write_all(response.as_bytes())?;
+        debug!("Response sent to {}: {}", self.address, response.trim());
+        Ok(())
+    }
+}
+
+/// When a value stored over the memcached protocol expires.
+enum Expiry {
+    After(Option<u64>), // After the given TTL in seconds, or never
+    Expired,            // Right away (i.e. a negative expiration time, or a timestamp in the past)
+}
+
+/// Parse an expiration time: zero for none, a number of seconds up to 30 days, or a Unix timestamp.
+fn parse_exptime(token: &[u8]) -> Option<Expiry> {
+    let exptime: i64 = std::str::from_utf8(token).ok()?.parse().ok()?;
+
+    let expiry: Expiry = match exptime {
+        ..=-1 => Expiry::Expired,
+        0 => Expiry::After(None),
+        1..=MAX_RELATIVE_EXPTIME => Expiry::After(Some(exptime as u64)),
+        timestamp => match (timestamp as u64).checked_sub(current_walltime() / 1000) {
+            Some(ttl) if ttl > 0 => Expiry::After(Some(ttl)),
+            _ => Expiry::Expired,
+        },
+    };
+    Some(expiry)
+}
+
+/// Split the trailing `noreply` option off the arguments of a command.
+fn split_noreply<'a, 'b>(arguments: &'a [&'b [u8]]) -> (&'a [&'b [u8]], bool) {
+    match arguments.split_last() {
+        Some((&b"noreply", rest)) => (rest, true),
+        _ => (arguments, false),
+    }
+}
+
+/// Parse the command at the start of the given data: a command line, followed by a data block for the
+/// storage commands.
+fn parse(data: &[u8]) -> Parsed<'_> {
+    let Some(end) = memchr(b'\n', data) else {
+        return match data.len() > MAX_LINE_SIZE {
+            true => Parsed::Invalid("line too long"),
+            false => Parsed::Incomplete,
+        };
+    };
+    if end > MAX_LINE_SIZE {
+        return Parsed::Invalid("line too long");
+    }
+
+    let tokens: Vec<&[u8]> = Tokens::new(&data[..end]).collect();
+    if !tokens
+        .first()
+        .is_some_and(|name| STORAGE_COMMANDS.contains(name))
+    {
+        return Parsed::Command(tokens, None, end + 1);
+    }
+
+    // The number of bytes of the data block is the last argument, unless `noreply` is
+    let (arguments, _) = split_noreply(&tokens);
+    let Some(size) = arguments.get(4).and_then(|size| parse_number(size)) else {
+        return Parsed::Command(tokens, None, end + 1); // Reported as an unknown command
+    };
+    let size: usize = size as usize;
+    if size > MAX_VALUE_SIZE {
+        return Parsed::Invalid("object too large for cache");
+    }
+
+    let block: &[u8] = &data[end + 1..];
+    if block.len() < size + 2 {
+        return Parsed::Incomplete;
+    }
+    if &block[size..size + 2] != b"\r\n" {
+        return Parsed::Invalid("bad data chunk");
+    }
+
+    Parsed::Command(tokens, Some(&block[..size]), end + 1 + size + 2)
+}
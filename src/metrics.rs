@@ -0,0 +1,220 @@
+use std::fmt::{Display, Write};
+
+use crate::{
+    cache::MemoryStats,
+    embedded::EmbeddedCache,
+    error::{CacheError, CacheResult},
+    stats::{CacheStats, CommandProtocol, LatencyHistogram, LATENCY_BUCKETS},
+};
+
+/// The path the metrics are exposed under, on the metrics listener (see `CacheServer::with_metrics_port`).
+pub(crate) const METRICS_PATH: &str = "/metrics";
+
+/// The content type of the Prometheus text exposition format.
+pub(crate) const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Render the counters of the cache and of the server in the Prometheus text exposition format: the
+/// uptime, the clients, the commands and their latencies per protocol, the keys, the hits, the misses and
+/// their ratio, the expired and evicted keys, and the memory taken by the entries (if the storage engine
+/// accounts for it).
+///
+/// # Errors
+/// * The error of the storage engine counting its keys (the keys are left out if it cannot count them).
+pub(crate) fn render(cache: &EmbeddedCache) -> CacheResult<String> {
+    let stats: &CacheStats = cache.stats();
+    let mut out: String = String::new();
+
+    write_metric(
+        &mut out,
+        "myrustcache_uptime_seconds",
+        "gauge",
+        "How long the server has been running.",
+        stats.uptime().as_secs(),
+    );
+    write_metric(
+        &mut out,
+        "myrustcache_connected_clients",
+        "gauge",
+        "The client connections open, across all the listeners.",
+        stats.connected_clients(),
+    );
+    let processed: Vec<(String, String)> = CommandProtocol::ALL
+        .iter()
+        .map(|protocol| {
+            let count: u64 = stats.latencies(*protocol).count();
+            (protocol_label(*protocol), count.to_string())
+        })
+        .collect();
+    write_labeled_metric(
+        &mut out,
+        "myrustcache_commands_processed_total",
+        "counter",
+        "The commands executed, per protocol.",
+        &processed,
+    );
+    write_latencies(&mut out, stats);
+
+    match cache.len() {
+        Ok(keys) => write_metric(
+            &mut out,
+            "myrustcache_keys",
+            "gauge",
+            "The keys cached with a value.",
+            keys,
+        ),
+        Err(CacheError::Unsupported(_)) => {}
+        Err(e) => return Err(e),
+    }
+    let (hits, misses): (u64, u64) = (stats.hits(), stats.misses());
+    write_metric(
+        &mut out,
+        "myrustcache_hits_total",
+        "counter",
+        "The lookups which found a value.",
+        hits,
+    );
+    write_metric(
+        &mut out,
+        "myrustcache_misses_total",
+        "counter",
+        "The lookups which found no value.",
+        misses,
+    );
+    let ratio: f64 = match hits + misses {
+        0 => 0.0,
+        lookups => hits as f64 / lookups as f64,
+    };
+    write_metric(
+        &mut out,
+        "myrustcache_hit_ratio",
+        "gauge",
+        "The share of the lookups which found a value, since the server started.",
+        ratio,
+    );
+    write_metric(
+        &mut out,
+        "myrustcache_expired_keys_total",
+        "counter",
+        "The expired keys deleted in the background.",
+        stats.expired(),
+    );
+    write_metric(
+        &mut out,
+        "myrustcache_oversized_writes_total",
+        "counter",
+        "The writes rejected for a key or a value over its max size.",
+        stats.oversized_writes(),
+    );
+    if let Some(compression) = cache.compression_stats() {
+        write_metric(
+            &mut out,
+            "myrustcache_compressed_writes_total",
+            "counter",
+            "The values written compressed.",
+            compression.compressed_writes,
+        );
+        write_metric(
+            &mut out,
+            "myrustcache_compression_saved_bytes_total",
+            "counter",
+            "The bytes saved by compressing the values written.",
+            compression.saved_bytes(),
+        );
+    }
+
+    if let Some(memory) = cache.memory_stats() {
+        write_memory(&mut out, &memory);
+    }
+    Ok(out)
+}
+
+/// Write the histograms of the latencies of the commands, one per protocol.
+fn write_latencies(out: &mut String, stats: &CacheStats) {
+    let name: &str = "myrustcache_command_duration_seconds";
+    let _ = writeln!(
+        out,
+        "# HELP {} How long the commands took, per protocol.",
+        name
+    );
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+
+    for protocol in CommandProtocol::ALL {
+        let histogram: &LatencyHistogram = stats.latencies(protocol);
+        let label: &str = protocol.as_str();
+        let mut cumulative: u64 = 0;
+        for (bucket, count) in histogram.buckets().into_iter().enumerate() {
+            cumulative += count;
+            let bound: String = match LATENCY_BUCKETS.get(bucket) {
+                Some(micros) => (*micros as f64 / 1_000_000.0).to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{}_bucket{{protocol=\"{}\",le=\"{}\"}} {}",
+                name, label, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_sum{{protocol=\"{}\"}} {}",
+            name,
+            label,
+            histogram.sum().as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "{}_count{{protocol=\"{}\"}} {}",
+            name, label, cumulative
+        );
+    }
+}
+
+/// Write the memory taken by the entries, and the entries evicted.
+fn write_memory(out: &mut String, memory: &MemoryStats) {
+    write_metric(
+        out,
+        "myrustcache_memory_used_bytes",
+        "gauge",
+        "The bytes taken by the keys, the values and their bookkeeping.",
+        memory.used_bytes,
+    );
+    write_metric(
+        out,
+        "myrustcache_memory_max_bytes",
+        "gauge",
+        "The most bytes the entries may take (0 without a limit).",
+        memory.max_bytes.unwrap_or(0),
+    );
+    write_metric(
+        out,
+        "myrustcache_evicted_keys_total",
+        "counter",
+        "The keys evicted to make room for others.",
+        memory.evictions,
+    );
+}
+
+/// Write a metric without labels, with its help and type.
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    write_labeled_metric(out, name, kind, help, &[(String::new(), value.to_string())]);
+}
+
+/// Write a metric, with its help and type, then a sample per set of labels (`{name="value"}`, or empty).
+fn write_labeled_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(String, String)],
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+/// The labels of the samples of a protocol.
+fn protocol_label(protocol: CommandProtocol) -> String {
+    format!("{{protocol=\"{}\"}}", protocol.as_str())
+}
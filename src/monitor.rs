@@ -0,0 +1,122 @@
+use std::{fmt::Display, time::SystemTime};
+
+use crate::stats::CommandProtocol;
+
+/// The channel of the monitor feed which the connections which have sent `MONITOR` are subscribed to (see
+/// `EmbeddedCache::monitors`).
+pub(crate) const MONITOR_CHANNEL: &str = "monitor";
+
+/// What the secret arguments of a command are replaced with (see `redact_secrets`).
+const REDACTED: &[u8] = b"(redacted)";
+
+/// Format a command executed by the server as a line of the monitor feed, in the format of Redis: the time
+/// it finished (in seconds since the epoch, with microseconds), the protocol and the address of the client,
+/// then the command and its arguments quoted, e.g. `1760523448.123456 [resp 127.0.0.1:51234] "SET" "a" "1"`.
+/// The credentials are redacted (see `redact_secrets`).
+pub(crate) fn format_command(
+    protocol: CommandProtocol,
+    client: impl Display,
+    arguments: &[&[u8]],
+) -> String {
+    let mut arguments: Vec<&[u8]> = arguments.to_vec();
+    redact_secrets(&mut arguments);
+
+    let now: f64 = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or(0.0);
+    let mut line: String = format!("{:.6} [{} {}]", now, protocol.as_str(), client);
+    for argument in &arguments {
+        line.push(' ');
+        quote(argument, &mut line);
+    }
+    line
+}
+
+/// Replace the credentials given to a command (listed with its arguments) with `(redacted)`, as Redis does:
+/// every argument of `AUTH`, and the user name and password following `AUTH` in `HELLO`.
+pub(crate) fn redact_secrets(command: &mut [&[u8]]) {
+    let Some((name, arguments)) = command.split_first_mut() else {
+        return;
+    };
+    if name.eq_ignore_ascii_case(b"AUTH") {
+        arguments.fill(REDACTED);
+    } else if name.eq_ignore_ascii_case(b"HELLO") {
+        if let Some(auth) = arguments
+            .iter()
+            .position(|argument| argument.eq_ignore_ascii_case(b"AUTH"))
+        {
+            let end: usize = arguments.len().min(auth + 3);
+            arguments[auth + 1..end].fill(REDACTED);
+        }
+    }
+}
+
+/// Quote an argument, escaping the quotes, the backslashes and the bytes which are not printable ASCII, so
+/// that a line of the feed never spans several lines.
+fn quote(argument: &[u8], out: &mut String) {
+    out.push('"');
+    for byte in argument {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            byte if byte.is_ascii_graphic() || *byte == b' ' => out.push(*byte as char),
+            byte => out.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redacted<'a>(command: &[&'a [u8]]) -> Vec<&'a [u8]> {
+        let mut command: Vec<&[u8]> = command.to_vec();
+        redact_secrets(&mut command);
+        command
+    }
+
+    #[test]
+    fn redacts_every_argument_of_auth() {
+        assert_eq!(redacted(&[b"AUTH", b"master"]), [b"AUTH", REDACTED]);
+        assert_eq!(
+            redacted(&[b"auth", b"admin", b"apw"]),
+            [b"auth", REDACTED, REDACTED]
+        );
+    }
+
+    #[test]
+    fn redacts_the_credentials_of_hello() {
+        assert_eq!(
+            redacted(&[b"HELLO", b"3", b"AUTH", b"admin", b"apw", b"SETNAME", b"app"]),
+            [
+                b"HELLO" as &[u8],
+                b"3",
+                b"AUTH",
+                REDACTED,
+                REDACTED,
+                b"SETNAME",
+                b"app"
+            ]
+        );
+        assert_eq!(redacted(&[b"HELLO", b"3"]), [b"HELLO" as &[u8], b"3"]);
+    }
+
+    #[test]
+    fn keeps_the_arguments_of_other_commands() {
+        assert_eq!(
+            redacted(&[b"SET", b"auth", b"apw"]),
+            [b"SET" as &[u8], b"auth", b"apw"]
+        );
+    }
+
+    #[test]
+    fn formats_redacted_commands() {
+        let line: String = format_command(CommandProtocol::Resp, "127.0.0.1:1", &[b"AUTH", b"pw"]);
+        assert!(line.ends_with(r#"[resp 127.0.0.1:1] "AUTH" "(redacted)""#));
+    }
+}
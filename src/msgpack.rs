@@ -0,0 +1,311 @@
+use std::str;
+
+/// The deepest nesting of arrays and maps accepted, so that a hostile request cannot exhaust the stack.
+const MAX_DEPTH: usize = 32;
+
+/// A MessagePack value, borrowing its strings and binaries from the decoded data.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value<'a> {
+    Nil,
+    Bool(bool),
+    Int(i64), // A negative integer
+    Uint(u64),
+    Float(f64),
+    Str(&'a [u8]), // The bytes of a string (left to the caller to validate as UTF-8)
+    Bin(&'a [u8]),
+    Array(Vec<Value<'a>>),
+    Map(Vec<(Value<'a>, Value<'a>)>),
+}
+
+impl<'a> Value<'a> {
+    /// The value of the given field, if this is a map holding it (with a string key).
+    pub fn get(&self, field: &str) -> Option<&Value<'a>> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(key, _)| *key == Value::Str(field.as_bytes()))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// The bytes of a string or a binary.
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            Value::Str(bytes) | Value::Bin(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// The text of a string, if it is valid UTF-8.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self {
+            Value::Str(bytes) => str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    /// The value of a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Uint(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// The outcome of decoding the pending data of a connection.
+pub(crate) enum Decoded<'a> {
+    Value(Value<'a>, usize), // A value, and the number of bytes it takes
+    Incomplete,              // More data is needed to decode the next value
+    Invalid(&'static str), // The data is not MessagePack, so the connection cannot be resynchronized
+}
+
+/// Decode the value at the start of the given data.
+///
+/// # Arguments
+/// * `data` - The data received and not handled yet.
+/// * `max_size` - The largest string, binary, array or map accepted (in bytes or elements).
+pub(crate) fn decode(data: &[u8], max_size: usize) -> Decoded<'_> {
+    let mut decoder: Decoder = Decoder {
+        data,
+        offset: 0,
+        max_size,
+    };
+    match decoder.value(0) {
+        Ok(value) => Decoded::Value(value, decoder.offset),
+        Err(None) => Decoded::Incomplete,
+        Err(Some(reason)) => Decoded::Invalid(reason),
+    }
+}
+
+/// Reads the values of the data one after the other. Errors are `None` when more data is needed.
+struct Decoder<'a> {
+    data: &'a [u8],
+    offset: usize,   // The position of the next value
+    max_size: usize, // The largest string, binary, array or map accepted
+}
+
+type DecodeResult<T> = Result<T, Option<&'static str>>;
+
+impl<'a> Decoder<'a> {
+    fn value(&mut self, depth: usize) -> DecodeResult<Value<'a>> {
+        if depth > MAX_DEPTH {
+            return Err(Some("too deeply nested"));
+        }
+
+        let marker: u8 = self.take(1)?[0];
+        let value: Value = match marker {
+            0x00..=0x7F => Value::Uint(u64::from(marker)),
+            0x80..=0x8F => self.map(usize::from(marker & 0x0F), depth)?,
+            0x90..=0x9F => self.array(usize::from(marker & 0x0F), depth)?,
+            0xA0..=0xBF => Value::Str(self.take(usize::from(marker & 0x1F))?),
+            0xC0 => Value::Nil,
+            0xC2 => Value::Bool(false),
+            0xC3 => Value::Bool(true),
+            0xC4 => {
+                let length: usize = self.length(1)?;
+                Value::Bin(self.take(length)?)
+            }
+            0xC5 => {
+                let length: usize = self.length(2)?;
+                Value::Bin(self.take(length)?)
+            }
+            0xC6 => {
+                let length: usize = self.length(4)?;
+                Value::Bin(self.take(length)?)
+            }
+            0xCA => Value::Float(f64::from(f32::from_bits(self.number(4)? as u32))),
+            0xCB => Value::Float(f64::from_bits(self.number(8)?)),
+            0xCC => Value::Uint(self.number(1)?),
+            0xCD => Value::Uint(self.number(2)?),
+            0xCE => Value::Uint(self.number(4)?),
+            0xCF => Value::Uint(self.number(8)?),
+            0xD0 => signed(self.number(1)? as u8 as i8 as i64),
+            0xD1 => signed(self.number(2)? as u16 as i16 as i64),
+            0xD2 => signed(self.number(4)? as u32 as i32 as i64),
+            0xD3 => signed(self.number(8)? as i64),
+            0xD9 => {
+                let length: usize = self.length(1)?;
+                Value::Str(self.take(length)?)
+            }
+            0xDA => {
+                let length: usize = self.length(2)?;
+                Value::Str(self.take(length)?)
+            }
+            0xDB => {
+                let length: usize = self.length(4)?;
+                Value::Str(self.take(length)?)
+            }
+            0xDC => {
+                let length: usize = self.length(2)?;
+                self.array(length, depth)?
+            }
+            0xDD => {
+                let length: usize = self.length(4)?;
+                self.array(length, depth)?
+            }
+            0xDE => {
+                let length: usize = self.length(2)?;
+                self.map(length, depth)?
+            }
+            0xDF => {
+                let length: usize = self.length(4)?;
+                self.map(length, depth)?
+            }
+            0xE0..=0xFF => Value::Int(i64::from(marker as i8)),
+            _ => return Err(Some("unsupported type")), // Extension types
+        };
+        Ok(value)
+    }
+
+    fn array(&mut self, length: usize, depth: usize) -> DecodeResult<Value<'a>> {
+        let mut values: Vec<Value> = Vec::with_capacity(length.min(16));
+        for _ in 0..length {
+            values.push(self.value(depth + 1)?);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn map(&mut self, length: usize, depth: usize) -> DecodeResult<Value<'a>> {
+        let mut entries: Vec<(Value, Value)> = Vec::with_capacity(length.min(16));
+        for _ in 0..length {
+            let key: Value = self.value(depth + 1)?;
+            entries.push((key, self.value(depth + 1)?));
+        }
+        Ok(Value::Map(entries))
+    }
+
+    /// Read the length of a string, binary, array or map, held in the given number of bytes.
+    fn length(&mut self, size: usize) -> DecodeResult<usize> {
+        let length: usize = self.number(size)? as usize;
+        match length <= self.max_size {
+            true => Ok(length),
+            false => Err(Some("value too large")),
+        }
+    }
+
+    /// Read a big-endian unsigned number held in the given number of bytes.
+    fn number(&mut self, size: usize) -> DecodeResult<u64> {
+        let bytes: &[u8] = self.take(size)?;
+        Ok(bytes
+            .iter()
+            .fold(0, |number, &b| (number << 8) | u64::from(b)))
+    }
+
+    fn take(&mut self, length: usize) -> DecodeResult<&'a [u8]> {
+        let bytes: &[u8] = self
+            .data
+            .get(self.offset..self.offset + length)
+            .ok_or(None)?;
+        self.offset += length;
+        Ok(bytes)
+    }
+}
+
+/// A signed integer, decoded as `Uint` if it is not negative so that integers compare regardless of their
+/// encoding.
+fn signed(value: i64) -> Value<'static> {
+    match u64::try_from(value) {
+        Ok(value) => Value::Uint(value),
+        Err(_) => Value::Int(value),
+    }
+}
+
+/// Encode a value at the end of the given buffer.
+pub(crate) fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Nil => out.push(0xC0),
+        Value::Bool(value) => out.push(if *value { 0xC3 } else { 0xC2 }),
+        Value::Int(value) => {
+            out.push(0xD3);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        Value::Uint(value) => encode_uint(*value, out),
+        Value::Float(value) => {
+            out.push(0xCB);
+            out.extend_from_slice(&value.to_bits().to_be_bytes());
+        }
+        Value::Str(bytes) => {
+            match bytes.len() {
+                length @ 0..=31 => out.push(0xA0 | length as u8),
+                length @ 32..=0xFF => out.extend_from_slice(&[0xD9, length as u8]),
+                length => encode_length(0xDA, 0xDB, length, out),
+            }
+            out.extend_from_slice(bytes);
+        }
+        Value::Bin(bytes) => {
+            encode_bin_header(bytes.len(), out);
+            out.extend_from_slice(bytes);
+        }
+        Value::Array(values) => {
+            match values.len() {
+                length @ 0..=15 => out.push(0x90 | length as u8),
+                length => encode_length(0xDC, 0xDD, length, out),
+            }
+            values.iter().for_each(|value| encode(value, out));
+        }
+        Value::Map(entries) => {
+            encode_map_header(entries.len(), out);
+            for (key, value) in entries {
+                encode(key, out);
+                encode(value, out);
+            }
+        }
+    }
+}
+
+/// Encode the header of a map of the given number of entries, which are to follow.
+pub(crate) fn encode_map_header(length: usize, out: &mut Vec<u8>) {
+    match length {
+        0..=15 => out.push(0x80 | length as u8),
+        _ => encode_length(0xDE, 0xDF, length, out),
+    }
+}
+
+/// Encode the header of a binary of the given length, whose bytes are to follow.
+pub(crate) fn encode_bin_header(length: usize, out: &mut Vec<u8>) {
+    match length {
+        0..=0xFF => out.extend_from_slice(&[0xC4, length as u8]),
+        _ => encode_length(0xC5, 0xC6, length, out),
+    }
+}
+
+/// Encode a string.
+pub(crate) fn encode_str(text: &str, out: &mut Vec<u8>) {
+    encode(&Value::Str(text.as_bytes()), out);
+}
+
+fn encode_uint(value: u64, out: &mut Vec<u8>) {
+    match value {
+        0..=0x7F => out.push(value as u8),
+        0x80..=0xFF => out.extend_from_slice(&[0xCC, value as u8]),
+        0x100..=0xFFFF => {
+            out.push(0xCD);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            out.push(0xCE);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xCF);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+/// Encode a length held in 2 or 4 bytes after its marker (the first one of `marker16` or `marker32` that
+/// fits).
+fn encode_length(marker16: u8, marker32: u8, length: usize, out: &mut Vec<u8>) {
+    match u16::try_from(length) {
+        Ok(length) => {
+            out.push(marker16);
+            out.extend_from_slice(&length.to_be_bytes());
+        }
+        Err(_) => {
+            out.push(marker32);
+            out.extend_from_slice(&(length as u32).to_be_bytes());
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use crate::{
+    error::{CacheError, CacheResult},
+    utils::current_monotime,
+};
+
+/// Separates the name of a namespace from the keys of the namespace, which are cached as
+/// `<namespace>:<key>`.
+pub const NAMESPACE_SEPARATOR: char = ':';
+
+/// The longest name of a namespace.
+pub const MAX_NAMESPACE_LENGTH: usize = 64;
+
+/// Whether the given name is a valid namespace: 1 to 64 ASCII letters, digits, `_` and `-`.
+pub fn is_valid_namespace(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAMESPACE_LENGTH
+        && name
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// The namespace of a cached key (the part before the first `:`, past the prefix of its database), if any.
+pub fn namespace_of(key: &str) -> Option<&str> {
+    let (_, key) = split_database(key);
+    key.split_once(NAMESPACE_SEPARATOR)
+        .map(|(namespace, _)| namespace)
+}
+
+/// Heads the keys of the databases other than the first one (see `database_prefix`). A control character,
+/// so that the keys of the first database cannot start with it by accident.
+pub const DATABASE_MARKER: char = '\u{1}';
+
+/// The prefix of the keys of the given database: none for database 0, whose keys are those cached without
+/// databases, and `\u{1}<index>:` for the others.
+pub fn database_prefix(index: usize) -> String {
+    match index {
+        0 => String::new(),
+        index => format!("{}{}{}", DATABASE_MARKER, index, NAMESPACE_SEPARATOR),
+    }
+}
+
+/// Split a cached key into the index of its database and its key within the database.
+pub fn split_database(key: &str) -> (usize, &str) {
+    key.strip_prefix(DATABASE_MARKER)
+        .and_then(|rest| rest.split_once(NAMESPACE_SEPARATOR))
+        .and_then(|(index, rest)| Some((index.parse().ok()?, rest)))
+        .unwrap_or((0, key))
+}
+
+/// Whether a cached key starts with the given prefix within the database of the prefix, so that the empty
+/// prefix of database 0 does not take in the keys of the other databases.
+pub fn in_keyspace(key: &str, prefix: &str) -> bool {
+    key.starts_with(prefix) && split_database(key).0 == split_database(prefix).0
+}
+
+/// What happens to a write which would make a namespace exceed its quota.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuotaPolicy {
+    /// The write is rejected with `CacheError::QuotaExceeded`.
+    #[default]
+    Reject,
+
+    /// The least recently written keys of the namespace are removed to make room for the write. Only the
+    /// write rate limit still rejects writes.
+    Evict,
+}
+
+/// The limits of a namespace, so that a tenant exceeding its budget cannot evict the data of the others.
+/// The unset limits are not enforced.
+///
+/// Parsed from `entries=10000,bytes=67108864,writes=500,policy=evict` (every field is optional).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NamespaceQuota {
+    pub max_entries: Option<usize>, // The most keys holding a value
+    pub max_bytes: Option<usize>,   // The most bytes of keys and values
+    pub max_writes_per_second: Option<u32>, // The most values put per second
+    pub policy: QuotaPolicy, // Whether the writes over the limits are rejected or evict other keys
+}
+
+impl FromStr for NamespaceQuota {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<NamespaceQuota, String> {
+        let mut quota: NamespaceQuota = NamespaceQuota::default();
+
+        for field in text.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let Some((name, value)) = field.split_once('=') else {
+                return Err(format!("'{}' is not a name=value pair", field));
+            };
+            let invalid = || format!("'{}' is not a valid value for {}", value, name);
+
+            match name.trim() {
+                "entries" => quota.max_entries = Some(value.trim().parse().map_err(|_| invalid())?),
+                "bytes" => quota.max_bytes = Some(value.trim().parse().map_err(|_| invalid())?),
+                "writes" => {
+                    quota.max_writes_per_second = Some(value.trim().parse().map_err(|_| invalid())?)
+                }
+                "policy" => {
+                    quota.policy = match value.trim() {
+                        "reject" => QuotaPolicy::Reject,
+                        "evict" => QuotaPolicy::Evict,
+                        _ => return Err(invalid()),
+                    }
+                }
+                name => return Err(format!("unknown quota '{}'", name)),
+            }
+        }
+        Ok(quota)
+    }
+}
+
+/// What a namespace takes of its quota (see `EmbeddedCache::namespace_usage`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamespaceUsage {
+    pub quota: NamespaceQuota, // The limits of the namespace
+    pub entries: usize,        // The keys holding a value counted against the quota
+    pub bytes: usize,          // The size of their keys and values
+}
+
+/// The keys of a namespace with a quota, as written through the cache.
+#[derive(Default)]
+struct Usage {
+    entries: HashMap<String, Entry>,        // The keys holding a value
+    by_write: BTreeMap<u64, String>, // The keys by write sequence, oldest first (the eviction order)
+    by_expiration: BTreeSet<(u64, String)>, // The keys put with a TTL, by expiration time (monotime)
+    bytes: usize,                           // The size of the keys and values
+    next_write: u64,                        // The sequence of the next write
+    window: u64,                            // The second (of monotime) the writes are counted in
+    window_writes: u32,                     // The values put during that second
+}
+
+struct Entry {
+    size: usize,             // The size of the key and value
+    write: u64,              // The sequence of the last write
+    expires_at: Option<u64>, // When the key expires (monotime), if put with a TTL
+}
+
+impl Usage {
+    fn insert(&mut self, key: &str, size: usize, expires_at: Option<u64>) {
+        self.remove(key);
+
+        let write: u64 = self.next_write;
+        self.next_write += 1;
+        self.by_write.insert(write, key.to_string());
+        if let Some(expires_at) = expires_at {
+            self.by_expiration.insert((expires_at, key.to_string()));
+        }
+        self.bytes += size;
+        let entry: Entry = Entry {
+            size,
+            write,
+            expires_at,
+        };
+        self.entries.insert(key.to_string(), entry);
+    }
+
+    /// Forget every key, keeping the write sequence and the writes of the current second.
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.by_write.clear();
+        self.by_expiration.clear();
+        self.bytes = 0;
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry: Entry = self.entries.remove(key)?;
+        self.by_write.remove(&entry.write);
+        if let Some(expires_at) = entry.expires_at {
+            self.by_expiration.remove(&(expires_at, key.to_string()));
+        }
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn set_expiration(&mut self, key: &str, expires_at: Option<u64>) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+        if let Some(previous) = entry.expires_at {
+            self.by_expiration.remove(&(previous, key.to_string()));
+        }
+        if let Some(expires_at) = expires_at {
+            self.by_expiration.insert((expires_at, key.to_string()));
+        }
+        entry.expires_at = expires_at;
+    }
+
+    /// Stop counting the keys whose TTL has elapsed, which the cache no longer holds.
+    fn purge_expired(&mut self, now: u64) {
+        while let Some((expires_at, _)) = self.by_expiration.first() {
+            if *expires_at > now {
+                break;
+            }
+            if let Some((_, key)) = self.by_expiration.pop_first() {
+                if let Some(entry) = self.entries.remove(&key) {
+                    self.by_write.remove(&entry.write);
+                    self.bytes -= entry.size;
+                }
+            }
+        }
+    }
+
+    /// Whether the namespace would stay within its quota with this many more entries and bytes.
+    fn fits(&self, quota: &NamespaceQuota, entries: usize, bytes: usize) -> bool {
+        quota
+            .max_entries
+            .is_none_or(|max| self.entries.len() + entries <= max)
+            && quota.max_bytes.is_none_or(|max| self.bytes + bytes <= max)
+    }
+}
+
+/// Enforces the quotas of the namespaces of a cache, counting the keys written to each of them.
+///
+/// The keys are counted as they are written, so the keys of a namespace already cached when its quota is
+/// set (e.g. in a persistent storage engine) are not counted, and the keys expired are only discounted
+/// once their TTL has elapsed.
+pub(crate) struct NamespaceQuotas {
+    namespaces: HashMap<String, (NamespaceQuota, Mutex<Usage>)>,
+}
+
+impl NamespaceQuotas {
+    pub fn new(quotas: Vec<(String, NamespaceQuota)>) -> NamespaceQuotas {
+        let namespaces: HashMap<String, (NamespaceQuota, Mutex<Usage>)> = quotas
+            .into_iter()
+            .map(|(namespace, quota)| (namespace, (quota, Mutex::new(Usage::default()))))
+            .collect();
+        NamespaceQuotas { namespaces }
+    }
+
+    /// Check that the namespace of the given key may take a new value for it, counting the value if so.
+    ///
+    /// # Arguments
+    /// * `key` - The key written, in a namespace with a quota or not.
+    /// * `size` - The size of the key and of its new value.
+    /// * `expires_at` - When the value expires (monotime), if put with a TTL.
+    ///
+    /// # Returns
+    /// * The keys to remove from the cache to make room for the value (with `QuotaPolicy::Evict`).
+    ///
+    /// # Errors
+    /// * `CacheError::QuotaExceeded` if the value does not fit within the quota of the namespace, or if
+    ///   the namespace has exceeded its write rate.
+    pub fn admit(
+        &self,
+        key: &str,
+        size: usize,
+        expires_at: Option<u64>,
+    ) -> CacheResult<Vec<String>> {
+        let Some((namespace, (quota, usage))) = self._quota_of(key) else {
+            return Ok(Vec::new());
+        };
+        let exceeded = |limit: &str| {
+            CacheError::QuotaExceeded(format!("namespace '{}' exceeds its {}", namespace, limit))
+        };
+
+        let mut usage = usage.lock()?;
+        let now: u64 = current_monotime();
+        usage.purge_expired(now);
+
+        if let Some(max) = quota.max_writes_per_second {
+            if usage.window != now / 1000 {
+                usage.window = now / 1000;
+                usage.window_writes = 0;
+            }
+            if usage.window_writes >= max {
+                return Err(exceeded("write rate"));
+            }
+            usage.window_writes += 1;
+        }
+
+        // A key written again replaces its previous value, so it only takes the difference
+        let (entries, bytes): (usize, usize) = match usage.entries.get(key) {
+            Some(previous) => (0, size.saturating_sub(previous.size)),
+            None => (1, size),
+        };
+        if usage.fits(quota, entries, bytes) {
+            usage.insert(key, size, expires_at);
+            return Ok(Vec::new());
+        }
+
+        // Evicting is pointless if the value would not even fit in an empty namespace
+        if quota.policy == QuotaPolicy::Reject || !Usage::default().fits(quota, 1, size) {
+            let limit: &str = match usage.fits(quota, entries, 0) {
+                true => "memory quota",
+                false => "entry quota",
+            };
+            return Err(exceeded(limit));
+        }
+
+        // Evict the least recently written keys of the namespace until the value fits
+        usage.remove(key);
+        let mut evicted: Vec<String> = Vec::new();
+        while !usage.fits(quota, 1, size) {
+            let Some((_, oldest)) = usage.by_write.first_key_value() else {
+                break;
+            };
+            let oldest: String = oldest.clone();
+            usage.remove(&oldest);
+            evicted.push(oldest);
+        }
+
+        usage.insert(key, size, expires_at);
+        Ok(evicted)
+    }
+
+    /// Count a value put without checking the quota (e.g. a write replicated from a primary).
+    pub fn record(&self, key: &str, size: usize, expires_at: Option<u64>) -> CacheResult<()> {
+        if let Some((_, (_, usage))) = self._quota_of(key) {
+            usage.lock()?.insert(key, size, expires_at);
+        }
+        Ok(())
+    }
+
+    /// Update when the value of a key expires (monotime), if it is counted.
+    pub fn set_expiration(&self, key: &str, expires_at: Option<u64>) -> CacheResult<()> {
+        if let Some((_, (_, usage))) = self._quota_of(key) {
+            usage.lock()?.set_expiration(key, expires_at);
+        }
+        Ok(())
+    }
+
+    /// What the given namespace takes of its quota, if it has one, leaving out the keys whose TTL has
+    /// elapsed.
+    pub fn usage(&self, namespace: &str) -> CacheResult<Option<NamespaceUsage>> {
+        let Some((quota, usage)) = self.namespaces.get(namespace) else {
+            return Ok(None);
+        };
+        let mut usage = usage.lock()?;
+        usage.purge_expired(current_monotime());
+        Ok(Some(NamespaceUsage {
+            quota: quota.clone(),
+            entries: usage.entries.len(),
+            bytes: usage.bytes,
+        }))
+    }
+
+    /// Stop counting the values of every key, which were all removed.
+    pub fn forget_all(&self) -> CacheResult<()> {
+        for (_, usage) in self.namespaces.values() {
+            usage.lock()?.clear();
+        }
+        Ok(())
+    }
+
+    /// Stop counting the value of a key, which was removed.
+    pub fn forget(&self, key: &str) -> CacheResult<()> {
+        if let Some((_, (_, usage))) = self._quota_of(key) {
+            usage.lock()?.remove(key);
+        }
+        Ok(())
+    }
+
+    fn _quota_of<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> Option<(&'a str, &'a (NamespaceQuota, Mutex<Usage>))> {
+        if self.namespaces.is_empty() {
+            return None;
+        }
+        let namespace: &str = namespace_of(key)?;
+        self.namespaces
+            .get(namespace)
+            .map(|quota| (namespace, quota))
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind},
+    mem,
+    path::Path,
+};
+
+use crate::{
+    cache::PutCondition,
+    dataset::{self, DatasetFormat},
+    embedded::EmbeddedCache,
+    error::ServerError,
+    rdb::ImportStats,
+    Bytes,
+};
+
+/// Load the key/value/TTL triples of a seed file into the cache, so that a freshly deployed node does not
+/// serve a cold cache. The keys already cached (e.g. restored from a snapshot) are kept, and counted as
+/// skipped, so that the seed only fills the gaps.
+///
+/// The format is told by the extension of the file:
+/// * `.csv` - CSV (RFC 4180) with a header row naming the `key` and `value` columns, and optionally a `ttl`
+///   column (in seconds, empty or `0` for none). The other columns are ignored.
+/// * Anything else - JSON Lines, one object per key with `key`, `value` and optionally `ttl` (in seconds)
+///   fields, or any other record of a JSON dataset (see `DatasetFormat::Json`).
+///
+/// # Errors
+/// * `ServerError::Io` if the file cannot be read or is not valid.
+/// * `ServerError::Cache` if a key cannot be put into the cache.
+pub(crate) fn preload(cache: &EmbeddedCache, path: &Path) -> Result<ImportStats, ServerError> {
+    let reader: BufReader<File> = BufReader::new(File::open(path)?);
+    let mut present: usize = 0;
+    let mut put = |key: String, value: Vec<u8>, ttl: Option<u64>| -> Result<(), ServerError> {
+        if !cache.put_if(&key, Bytes::from(value), ttl, None, PutCondition::IfAbsent)? {
+            present += 1;
+        }
+        Ok(())
+    };
+
+    let is_csv: bool = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    let mut stats: ImportStats = match is_csv {
+        true => read_csv(reader, &mut put)?,
+        false => dataset::read_records(reader, DatasetFormat::Json, &mut put)?,
+    };
+    stats.imported -= present;
+    stats.skipped += present;
+    Ok(stats)
+}
+
+/// Read the records of a CSV seed file, handing each of them to `visit` along with its value and TTL.
+fn read_csv<R: BufRead>(
+    reader: R,
+    visit: &mut impl FnMut(String, Vec<u8>, Option<u64>) -> Result<(), ServerError>,
+) -> Result<ImportStats, ServerError> {
+    let mut csv: CsvReader<R> = CsvReader { reader, line: 0 };
+    let mut stats: ImportStats = ImportStats::default();
+    let Some(header) = csv.next_record()? else {
+        return Ok(stats);
+    };
+
+    let column = |name: &str| header.iter().position(|column| column.trim() == name);
+    let (Some(key_column), Some(value_column)) = (column("key"), column("value")) else {
+        let message: String = "the header must name a key and a value column".to_string();
+        return Err(invalid(1, message).into());
+    };
+    let ttl_column: Option<usize> = column("ttl");
+
+    while let Some(mut fields) = csv.next_record()? {
+        if fields.len() == 1 && fields[0].is_empty() {
+            continue; // A blank line
+        }
+        if fields.len() != header.len() {
+            let message: String =
+                format!("expected {} fields, found {}", header.len(), fields.len());
+            return Err(invalid(csv.line, message).into());
+        }
+
+        let ttl: Option<u64> = match ttl_column.map(|column| fields[column].trim()) {
+            None | Some("" | "0") => None,
+            Some(ttl) => match ttl.parse() {
+                Ok(ttl) => Some(ttl),
+                Err(_) => {
+                    let message: String = format!("invalid TTL '{}'", ttl);
+                    return Err(invalid(csv.line, message).into());
+                }
+            },
+        };
+        visit(
+            mem::take(&mut fields[key_column]),
+            mem::take(&mut fields[value_column]).into_bytes(),
+            ttl,
+        )?;
+        stats.imported += 1;
+    }
+    Ok(stats)
+}
+
+/// Reads the records of a CSV file (RFC 4180), where quoted fields may span several lines.
+struct CsvReader<R: BufRead> {
+    reader: R,
+    line: usize, // The number of the last line read
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn next_record(&mut self) -> io::Result<Option<Vec<String>>> {
+        let mut line: String = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+
+        let mut fields: Vec<String> = Vec::new();
+        let mut field: String = String::new();
+        let mut quoted: bool = false;
+        loop {
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (quoted, c) {
+                    (true, '"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    (true, '"') => quoted = false,
+                    (true, c) => field.push(c),
+                    (false, '"') if field.is_empty() => quoted = true,
+                    (false, ',') => fields.push(mem::take(&mut field)),
+                    (false, '\r' | '\n') => {}
+                    (false, c) => field.push(c),
+                }
+            }
+            if !quoted {
+                break;
+            }
+
+            // The line break belongs to the quoted field, which goes on with the next line
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(invalid(self.line, "unterminated quoted field".to_string()));
+            }
+            self.line += 1;
+        }
+
+        fields.push(field);
+        Ok(Some(fields))
+    }
+}
+
+fn invalid(line: usize, message: String) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid seed file: line {}: {}", line, message),
+    )
+}
@@ -0,0 +1,254 @@
+use std::{
+    io::Write,
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::{
+    cache::{Bytes, SinkError, WriteSink},
+    connection::ClientStream,
+    embedded::EmbeddedCache,
+    error::{CacheError, ServerError},
+    rdb,
+    utils::{current_walltime, random_u64},
+};
+
+/// How many writes may be waiting to be sent to a replica before it is considered too far behind and
+/// dropped. It then connects again and takes a new full copy of the data.
+const REPLICA_BACKLOG: usize = 100_000;
+
+/// How often the replicas are pinged when there is no write to stream (as Redis primaries do), so that
+/// they can tell an idle primary from a lost one.
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the thread streaming the writes to a replica checks whether the replica is still attached.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The `PING` command, as sent to the idle replicas.
+const PING: &[u8] = b"*1\r\n$4\r\nPING\r\n";
+
+/// A write encoded as a RESP command, shared by the queues of all the replicas.
+type Command = Arc<[u8]>;
+
+/// The stream of the writes of a primary to the replicas attached to it with `SYNC` or `PSYNC` (e.g. other
+/// nodes started with `--replicaof`, see `Replica`).
+///
+/// Wrapped around the storage engine like the append-only log (see `WriteThroughCache`), it encodes every
+/// write as a `SET` command (with `PXAT` for a TTL) or a `DEL` command (`FLUSHALL` for all the keys), and
+/// queues it for every replica. They are idempotent, so a replica applying a write which its full copy of
+/// the data already had ends up with the same value. When no replica is attached, the writes only cost a
+/// check of the count.
+pub(crate) struct ReplicationFeed {
+    replication_id: String, // The id of the stream, new at every start (there is no partial resync)
+    state: Mutex<FeedState>, // The queues of the replicas attached, and the offset of the stream
+    attached: AtomicUsize,  // The number of replicas attached, read without taking the lock
+}
+
+struct FeedState {
+    queues: Vec<SyncSender<Command>>, // The writes waiting to be sent to each replica
+    offset: u64,                      // The number of bytes of commands fed so far
+}
+
+impl ReplicationFeed {
+    pub fn new() -> ReplicationFeed {
+        ReplicationFeed {
+            replication_id: format!(
+                "{:016x}{:016x}{:08x}",
+                random_u64(),
+                random_u64(),
+                random_u64() as u32
+            ),
+            state: Mutex::new(FeedState {
+                queues: Vec::new(),
+                offset: 0,
+            }),
+            attached: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of replicas the writes are streamed to.
+    pub fn replicas(&self) -> usize {
+        self.attached.load(Ordering::Relaxed)
+    }
+
+    /// Start queuing the writes for a new replica.
+    ///
+    /// # Returns
+    /// * The queue of the writes, and the offset of the stream it starts at.
+    fn attach(&self) -> Result<(Receiver<Command>, u64), CacheError> {
+        let (sender, receiver) = mpsc::sync_channel(REPLICA_BACKLOG);
+        let mut state = self.state.lock()?;
+        state.queues.push(sender);
+        self.attached.store(state.queues.len(), Ordering::Relaxed);
+        Ok((receiver, state.offset))
+    }
+
+    /// Queue a write for every replica, dropping those which are gone or too far behind.
+    fn feed(&self, arguments: &[&[u8]]) -> Result<(), SinkError> {
+        if self.attached.load(Ordering::Relaxed) == 0 {
+            return Ok(());
+        }
+        let command: Command = encode(arguments).into();
+
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "the replication lock is poisoned")?;
+        state.offset += command.len() as u64;
+        state
+            .queues
+            .retain(|queue| match queue.try_send(Arc::clone(&command)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!(
+                        "Dropping a replica more than {} writes behind",
+                        REPLICA_BACKLOG
+                    );
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+        self.attached.store(state.queues.len(), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl WriteSink<String, Bytes> for ReplicationFeed {
+    fn on_put(&self, key: &String, value: &Bytes, ttl: Option<u64>) -> Result<(), SinkError> {
+        match ttl {
+            Some(ttl) => {
+                let expires_at: String = current_walltime()
+                    .saturating_add(ttl.saturating_mul(1000))
+                    .to_string();
+                self.feed(&[
+                    b"SET",
+                    key.as_bytes(),
+                    value,
+                    b"PXAT",
+                    expires_at.as_bytes(),
+                ])
+            }
+            None => self.feed(&[b"SET", key.as_bytes(), value]),
+        }
+    }
+
+    fn on_remove(&self, key: &String) -> Result<(), SinkError> {
+        self.feed(&[b"DEL", key.as_bytes()])
+    }
+
+    fn on_clear(&self) -> Result<(), SinkError> {
+        self.feed(&[b"FLUSHALL"])
+    }
+}
+
+/// The link of a replica to the thread streaming the writes to it, which stops once the link is dropped
+/// (i.e. when the connection of the replica is closed).
+pub(crate) struct ReplicaLink {
+    closed: Arc<AtomicBool>,
+}
+
+impl Drop for ReplicaLink {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Attach a replica which has sent `SYNC` or `PSYNC`: on a thread of its own, answer `+FULLRESYNC`, send a
+/// full copy of the data (an RDB dump, see `rdb::export`), then stream the writes to the replica as they
+/// happen, pinging it while there are none.
+///
+/// Called holding the exclusive side of the transaction lock (see `EmbeddedCache::transaction_lock`), so
+/// that no command of a client is half applied: every write is in the copy, in the stream, or in both.
+///
+/// # Arguments
+/// * `feed` - The stream of the writes of the cache.
+/// * `cache` - The cache the copy of the data is taken from.
+/// * `stream` - The connection of the replica, written to without buffering.
+/// * `address` - The address of the replica, for logging purposes.
+///
+/// # Errors
+/// * `ServerError::Cache` if the replication lock is poisoned.
+/// * `ServerError::Io` if the thread cannot be spawned.
+pub(crate) fn attach_replica(
+    feed: &ReplicationFeed,
+    cache: &Arc<EmbeddedCache>,
+    stream: ClientStream,
+    address: &str,
+) -> Result<ReplicaLink, ServerError> {
+    let (queue, offset) = feed.attach()?;
+    let closed: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let header: String = format!("+FULLRESYNC {} {}\r\n", feed.replication_id, offset);
+
+    let cache: Arc<EmbeddedCache> = Arc::clone(cache);
+    let replica_closed: Arc<AtomicBool> = Arc::clone(&closed);
+    let address: String = address.to_string();
+    thread::Builder::new()
+        .name("replica-feed".to_string())
+        .spawn(move || {
+            match stream_writes(&cache, &stream, &header, queue, &replica_closed) {
+                Ok(()) => info!("Stopped streaming the writes to the replica {}", address),
+                Err(e) => warn!("Lost the replica {}: {}", address, e),
+            }
+            let _ = stream.shutdown(Shutdown::Both);
+        })?;
+    Ok(ReplicaLink { closed })
+}
+
+/// Send the full copy of the data to a replica, then the writes of the queue, until the replica is gone,
+/// dropped for falling behind, or its link is closed.
+fn stream_writes(
+    cache: &EmbeddedCache,
+    mut stream: &ClientStream,
+    header: &str,
+    queue: Receiver<Command>,
+    closed: &AtomicBool,
+) -> Result<(), ServerError> {
+    let started: Instant = Instant::now();
+    let mut dump: Vec<u8> = Vec::new();
+    let keys: usize = rdb::export(cache, &mut dump)?;
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(format!("${}\r\n", dump.len()).as_bytes())?;
+    stream.write_all(&dump)?;
+    drop(dump);
+    info!(
+        "Sent {} key(s) to the replica {} in {:?}",
+        keys,
+        stream.peer_address(),
+        started.elapsed()
+    );
+
+    let mut last_sent: Instant = Instant::now();
+    while !closed.load(Ordering::Relaxed) {
+        match queue.recv_timeout(POLL_INTERVAL) {
+            Ok(command) => stream.write_all(&command)?,
+            Err(RecvTimeoutError::Timeout) if last_sent.elapsed() >= PING_INTERVAL => {
+                stream.write_all(PING)?
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()), // Dropped for falling behind
+        }
+        last_sent = Instant::now();
+    }
+    Ok(())
+}
+
+/// Encode a command as a RESP array of bulk strings.
+fn encode(arguments: &[&[u8]]) -> Vec<u8> {
+    let size: usize = arguments.iter().map(|argument| argument.len() + 16).sum();
+    let mut command: Vec<u8> = Vec::with_capacity(size + 16);
+    command.extend_from_slice(format!("*{}\r\n", arguments.len()).as_bytes());
+    for argument in arguments {
+        command.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+        command.extend_from_slice(argument);
+        command.extend_from_slice(b"\r\n");
+    }
+    command
+}
@@ -0,0 +1,164 @@
+/// The operations of the commands (the `Command.Op` enum of `proto/myrustcache.proto`).
+pub(crate) const OP_PING: u64 = 0;
+pub(crate) const OP_GET: u64 = 1;
+pub(crate) const OP_SET: u64 = 2;
+pub(crate) const OP_SET_NULL: u64 = 3;
+pub(crate) const OP_DELETE: u64 = 4;
+
+/// The statuses of the replies (the `Reply.Status` enum of `proto/myrustcache.proto`).
+pub(crate) const STATUS_OK: u64 = 0;
+pub(crate) const STATUS_STALE: u64 = 1;
+pub(crate) const STATUS_NEGATIVE: u64 = 2;
+pub(crate) const STATUS_NOT_FOUND: u64 = 3;
+pub(crate) const STATUS_RECOMPUTE: u64 = 4;
+pub(crate) const STATUS_INVALID: u64 = 5;
+pub(crate) const STATUS_ERROR: u64 = 6;
+
+/// The wire types of the fields.
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LENGTH_DELIMITED: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+/// A `Command` message, borrowing its key and value from the received data.
+#[derive(Debug, Default)]
+pub(crate) struct Command<'a> {
+    pub id: u64,
+    pub op: u64,
+    pub key: &'a [u8],
+    pub value: &'a [u8],
+    pub ttl: Option<u64>,
+    pub soft_ttl: Option<u64>,
+    pub lock_ttl: Option<u64>,
+}
+
+/// The outcome of decoding the pending data of a connection.
+pub(crate) enum Decoded<'a> {
+    Command(Command<'a>, usize), // A command, and the number of bytes it takes (with its length prefix)
+    Incomplete,                  // More data is needed to decode the next command
+    Invalid(&'static str), // The data is not a command, so the connection cannot be resynchronized
+}
+
+/// Decode the command at the start of the given data: a `Command` message prefixed by its length as a
+/// varint (i.e. the delimited format of the protobuf libraries, e.g. `writeDelimitedTo` in Java).
+///
+/// # Arguments
+/// * `data` - The data received and not handled yet.
+/// * `max_size` - The largest message accepted.
+pub(crate) fn decode(data: &[u8], max_size: usize) -> Decoded<'_> {
+    let (length, prefix) = match read_varint(data) {
+        Ok(Some(varint)) => varint,
+        Ok(None) => return Decoded::Incomplete,
+        Err(reason) => return Decoded::Invalid(reason),
+    };
+    if length > max_size as u64 {
+        return Decoded::Invalid("message too large");
+    }
+
+    let end: usize = prefix + length as usize;
+    let Some(message) = data.get(prefix..end) else {
+        return Decoded::Incomplete;
+    };
+    match decode_command(message) {
+        Ok(command) => Decoded::Command(command, end),
+        Err(reason) => Decoded::Invalid(reason),
+    }
+}
+
+/// Decode the fields of a complete `Command` message. Unknown fields are skipped, as in protobuf.
+fn decode_command(mut message: &[u8]) -> Result<Command<'_>, &'static str> {
+    let mut command: Command = Command::default();
+
+    while !message.is_empty() {
+        let (tag, length) = read_varint(message)?.ok_or("truncated field")?;
+        message = &message[length..];
+
+        match tag & 0x7 {
+            WIRE_VARINT => {
+                let (value, length) = read_varint(message)?.ok_or("truncated field")?;
+                message = &message[length..];
+                match tag >> 3 {
+                    1 => command.id = value,
+                    2 => command.op = value,
+                    5 => command.ttl = Some(value),
+                    6 => command.soft_ttl = Some(value),
+                    7 => command.lock_ttl = Some(value),
+                    _ => {}
+                }
+            }
+            WIRE_LENGTH_DELIMITED => {
+                let (size, length) = read_varint(message)?.ok_or("truncated field")?;
+                let bytes: &[u8] = message
+                    .get(length..length + size as usize)
+                    .ok_or("truncated field")?;
+                message = &message[length + bytes.len()..];
+                match tag >> 3 {
+                    3 => command.key = bytes,
+                    4 => command.value = bytes,
+                    _ => {}
+                }
+            }
+            WIRE_FIXED64 => message = message.get(8..).ok_or("truncated field")?,
+            WIRE_FIXED32 => message = message.get(4..).ok_or("truncated field")?,
+            _ => return Err("unsupported wire type"),
+        }
+    }
+    Ok(command)
+}
+
+/// Encode the beginning of a `Reply` message prefixed by its length, up to the bytes of its value (if not
+/// empty), which are to follow.
+pub(crate) fn encode_reply_head(id: u64, status: u64, error: &str, value_length: usize) -> Vec<u8> {
+    let mut fields: Vec<u8> = Vec::with_capacity(32);
+    if id != 0 {
+        write_varint(1 << 3 | WIRE_VARINT, &mut fields);
+        write_varint(id, &mut fields);
+    }
+    if status != STATUS_OK {
+        write_varint(2 << 3 | WIRE_VARINT, &mut fields);
+        write_varint(status, &mut fields);
+    }
+    if !error.is_empty() {
+        write_varint(4 << 3 | WIRE_LENGTH_DELIMITED, &mut fields);
+        write_varint(error.len() as u64, &mut fields);
+        fields.extend_from_slice(error.as_bytes());
+    }
+    if value_length > 0 {
+        write_varint(3 << 3 | WIRE_LENGTH_DELIMITED, &mut fields);
+        write_varint(value_length as u64, &mut fields);
+    }
+
+    let mut head: Vec<u8> = Vec::with_capacity(fields.len() + 5);
+    write_varint((fields.len() + value_length) as u64, &mut head);
+    head.extend_from_slice(&fields);
+    head
+}
+
+/// Read the varint at the start of the given data, along with the number of bytes it takes.
+///
+/// # Returns
+/// * `None` if the data ends before the varint.
+fn read_varint(data: &[u8]) -> Result<Option<(u64, usize)>, &'static str> {
+    let mut value: u64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        if i == 10 {
+            return Err("varint too long");
+        }
+        value |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+    match data.len() >= 10 {
+        true => Err("varint too long"),
+        false => Ok(None),
+    }
+}
+
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
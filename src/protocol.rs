@@ -0,0 +1,513 @@
+use std::{fmt, str};
+
+use memchr::{memchr2, memchr3};
+
+use crate::namespaces::DATABASE_MARKER;
+
+/// Iterator over the whitespace separated tokens of a raw message received from a client.
+///
+/// Tokens are returned as byte slices borrowed from the message, so values are never required to be valid
+/// UTF-8. Use [`as_key`] for tokens that must be text.
+///
+/// In the bulk mode of the text protocol (after `HELLO BULK`), a `$<length>` token stands for the next
+/// bulk payload following the line (see [`bulk_end`]), so that the token holds any byte, whitespace and
+/// line terminators included.
+#[derive(Clone)]
+pub struct Tokens<'a> {
+    remaining: &'a [u8], // The part of the message that has not been tokenized yet
+    payloads: Option<&'a [u8]>, // The bulk payloads following the message not taken yet, in the bulk mode
+}
+
+impl<'a> Tokens<'a> {
+    /// Create a tokenizer over the given raw message.
+    pub fn new(message: &'a [u8]) -> Tokens<'a> {
+        Tokens {
+            remaining: message,
+            payloads: None,
+        }
+    }
+
+    /// Create a tokenizer over the given raw message, whose `$<length>` tokens are replaced by the given
+    /// bulk payloads (as checked by [`bulk_end`]).
+    pub fn with_payloads(message: &'a [u8], payloads: &'a [u8]) -> Tokens<'a> {
+        Tokens {
+            remaining: message,
+            payloads: Some(payloads),
+        }
+    }
+
+    /// Take the payload of the given length from the bulk payloads left, along with its line terminator.
+    fn _take_payload(&mut self, length: usize) -> Option<&'a [u8]> {
+        let payloads: &'a [u8] = self.payloads?;
+        let payload: &'a [u8] = payloads.get(..length)?;
+        let rest: &'a [u8] = &payloads[length..];
+        let rest: &'a [u8] = rest.strip_prefix(b"\r").unwrap_or(rest);
+        self.payloads = Some(rest.strip_prefix(b"\n").unwrap_or(rest));
+        Some(payload)
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a [u8];
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a [u8]> {
+        // Skip the leading whitespace, if any
+        let start: usize = self
+            .remaining
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())?;
+        let rest: &'a [u8] = &self.remaining[start..];
+
+        // The token ends at the next whitespace (or at the end of the message)
+        let end: usize = find_whitespace(rest).unwrap_or(rest.len());
+        self.remaining = &rest[end..];
+
+        let token: &'a [u8] = &rest[..end];
+        match self
+            .payloads
+            .is_some()
+            .then(|| bulk_length(token))
+            .flatten()
+        {
+            Some(length) => self._take_payload(length).or(Some(token)),
+            None => Some(token),
+        }
+    }
+}
+
+/// The bulk payloads following a line in the bulk mode of the text protocol.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Bulk {
+    Complete(usize), // The payloads end at the given offset of the data following the line
+    Incomplete,      // The payloads have not been received in full yet
+    Invalid,         // A payload is not followed by a line terminator
+}
+
+/// Find the end of the bulk payloads announced by the `$<length>` tokens of a line, in the data following
+/// it. Every payload is made of exactly `length` raw bytes, followed by `\r\n` (or `\n`).
+pub fn bulk_end(line: &[u8], data: &[u8]) -> Bulk {
+    let mut end: usize = 0;
+    for length in Tokens::new(line).filter_map(bulk_length) {
+        end = end.saturating_add(length);
+        match data.get(end..).unwrap_or_default() {
+            [] | [b'\r'] => return Bulk::Incomplete,
+            [b'\r', b'\n', ..] => end += 2,
+            [b'\n', ..] => end += 1,
+            _ => return Bulk::Invalid,
+        }
+    }
+    Bulk::Complete(end)
+}
+
+/// The length announced by a `$<length>` token, if the token is one.
+fn bulk_length(token: &[u8]) -> Option<usize> {
+    let digits: &[u8] = token.strip_prefix(b"$")?;
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// The length from which the whitespace is searched with SIMD. Setting up a SIMD search costs more than
+/// scanning a few bytes, so short tokens (e.g. commands and most keys) are scanned one byte at a time.
+const SIMD_SCAN_THRESHOLD: usize = 64;
+
+/// Find the first ASCII whitespace of the given bytes.
+#[inline]
+fn find_whitespace(bytes: &[u8]) -> Option<usize> {
+    let head: &[u8] = &bytes[..bytes.len().min(SIMD_SCAN_THRESHOLD)];
+    if let Some(index) = head.iter().position(u8::is_ascii_whitespace) {
+        return Some(index);
+    }
+    if head.len() == bytes.len() {
+        return None;
+    }
+
+    // The common separators are searched first, then the rare ones within the token found, so that both
+    // searches can use SIMD
+    let rest: &[u8] = &bytes[head.len()..];
+    let end: usize = memchr3(b' ', b'\t', b'\n', rest).unwrap_or(rest.len());
+    match memchr2(b'\r', b'\x0c', &rest[..end]) {
+        Some(index) => Some(head.len() + index),
+        None if end < rest.len() => Some(head.len() + end),
+        None => None,
+    }
+}
+
+/// Interpret a token as a cache key. Keys are text, so they have to be valid UTF-8, and may not start with
+/// `namespaces::DATABASE_MARKER`, which heads the keys of the other databases than the first one.
+pub fn as_key(token: &[u8]) -> Option<&str> {
+    str::from_utf8(token)
+        .ok()
+        .filter(|key| !key.starts_with(DATABASE_MARKER))
+}
+
+/// The errors of the text protocol in strict mode (after `HELLO STRICT`), answered as `ERR <code> <name>`
+/// (e.g. `ERR 1101 missing_key`) instead of free text, so that clients can branch on them reliably.
+///
+/// The codes are grouped by their first two digits: `11xx` for malformed commands, `12xx` for invalid
+/// arguments and `13xx` for failures of the server. Codes are never reused once published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnknownCommand,         // 1100: The command is unknown
+    MissingKey,             // 1101: The command needs a key
+    MissingValue,           // 1102: The command needs a value
+    UnexpectedArgument,     // 1103: The command has an argument it does not take
+    UnsupportedProtocol,    // 1104: HELLO names an unknown protocol
+    MissingNamespace,       // 1105: SELECT needs a namespace
+    AuthRequired,           // 1106: The connection must authenticate with AUTH first
+    NoPermission,           // 1107: The user may not execute the command, or access its key
+    Subscribed,             // 1108: Only SUBSCRIBE and UNSUBSCRIBE are taken while subscribed
+    Transaction,            // 1109: MULTI, EXEC or DISCARD out of place, or a failed transaction
+    Moved,      // 1110: The keys belong to another node of the cluster, or to different slots
+    InvalidKey, // 1201: The key is not valid UTF-8, or starts with the database marker
+    InvalidTtl, // 1202: A TTL (or SOFT, or LOCK) is not a number of seconds
+    ValueTooLarge, // 1203: The command holding a value is longer than 1 MiB, or its max size
+    MessageTooLong, // 1204: Any other command is longer than 1 MiB
+    InvalidJitter, // 1205: JITTER is not a percentage (0 to 100)
+    InvalidNamespace, // 1206: The name of a namespace is invalid
+    InvalidRequestId, // 1207: The request id in front of the command is empty or too long
+    InvalidParameter, // 1208: CONFIG names an unknown parameter, or an invalid value for it
+    InvalidPassword, // 1209: AUTH presents a wrong password, or no password is required
+    NotAnInteger, // 1210: INCR and the like find a value, or get an increment, that is not an integer
+    InvalidBulk,  // 1211: A bulk payload is not followed by a line terminator
+    InvalidDatabase, // 1212: SELECT or SWAPDB names a database out of range
+    WrongType,    // 1213: The key holds a value of another type than the command works on
+    NotANumber,   // 1214: A score is not a number, or would not be one
+    ScriptError,  // 1215: EVAL has an invalid script, or the script fails or runs too long
+    NoSuchClient, // 1216: CLIENT KILL names no connected client
+    KeyTooLarge,  // 1217: The key written is longer than its max size
+    OutOfMemory, // 1301: The server has no buffer memory left for the command, or reached maxmemory
+    ReadOnly,    // 1302: The server is read-only (e.g. a replica)
+    Internal,    // 1303: The cache failed to execute the command
+    QuotaExceeded, // 1304: The write would make the namespace exceed its quota
+    SoftDeleteDisabled, // 1305: UNDELETE needs the deleted values to be kept as tombstones
+    HistoryDisabled, // 1306: HISTORY and GETVERSION need the versions of the keys to be kept
+    TooManyClients, // 1307: The server already serves as many clients as it may
+    RateLimited, // 1308: The connection sends more commands than its rate limit allows
+    SnapshotError, // 1309: SAVE, BGSAVE or BGREWRITEAOF disabled, already in progress, or failing
+    DatasetError, // 1310: EXPORT or IMPORT disabled, naming an invalid file, or failing
+    AccessTrackingDisabled, // 1311: OBJECT IDLETIME and OBJECT FREQ need the reads of the keys to be tracked
+    CommandTimeout, // 1312: The commands of the connection took longer than the command timeout
+}
+
+impl ErrorCode {
+    /// Every error code, in the order of their codes.
+    pub const ALL: [ErrorCode; 40] = [
+        ErrorCode::UnknownCommand,
+        ErrorCode::MissingKey,
+        ErrorCode::MissingValue,
+        ErrorCode::UnexpectedArgument,
+        ErrorCode::UnsupportedProtocol,
+        ErrorCode::MissingNamespace,
+        ErrorCode::AuthRequired,
+        ErrorCode::NoPermission,
+        ErrorCode::Subscribed,
+        ErrorCode::Transaction,
+        ErrorCode::Moved,
+        ErrorCode::InvalidKey,
+        ErrorCode::InvalidTtl,
+        ErrorCode::ValueTooLarge,
+        ErrorCode::MessageTooLong,
+        ErrorCode::InvalidJitter,
+        ErrorCode::InvalidNamespace,
+        ErrorCode::InvalidRequestId,
+        ErrorCode::InvalidParameter,
+        ErrorCode::InvalidPassword,
+        ErrorCode::NotAnInteger,
+        ErrorCode::InvalidBulk,
+        ErrorCode::InvalidDatabase,
+        ErrorCode::WrongType,
+        ErrorCode::NotANumber,
+        ErrorCode::ScriptError,
+        ErrorCode::NoSuchClient,
+        ErrorCode::KeyTooLarge,
+        ErrorCode::OutOfMemory,
+        ErrorCode::ReadOnly,
+        ErrorCode::Internal,
+        ErrorCode::QuotaExceeded,
+        ErrorCode::SoftDeleteDisabled,
+        ErrorCode::HistoryDisabled,
+        ErrorCode::TooManyClients,
+        ErrorCode::RateLimited,
+        ErrorCode::SnapshotError,
+        ErrorCode::DatasetError,
+        ErrorCode::AccessTrackingDisabled,
+        ErrorCode::CommandTimeout,
+    ];
+
+    /// The numeric code of the error.
+    pub fn code(&self) -> u16 {
+        match self {
+            ErrorCode::UnknownCommand => 1100,
+            ErrorCode::MissingKey => 1101,
+            ErrorCode::MissingValue => 1102,
+            ErrorCode::UnexpectedArgument => 1103,
+            ErrorCode::UnsupportedProtocol => 1104,
+            ErrorCode::MissingNamespace => 1105,
+            ErrorCode::AuthRequired => 1106,
+            ErrorCode::NoPermission => 1107,
+            ErrorCode::Subscribed => 1108,
+            ErrorCode::Transaction => 1109,
+            ErrorCode::Moved => 1110,
+            ErrorCode::InvalidKey => 1201,
+            ErrorCode::InvalidTtl => 1202,
+            ErrorCode::ValueTooLarge => 1203,
+            ErrorCode::MessageTooLong => 1204,
+            ErrorCode::InvalidJitter => 1205,
+            ErrorCode::InvalidNamespace => 1206,
+            ErrorCode::InvalidRequestId => 1207,
+            ErrorCode::InvalidParameter => 1208,
+            ErrorCode::InvalidPassword => 1209,
+            ErrorCode::NotAnInteger => 1210,
+            ErrorCode::InvalidBulk => 1211,
+            ErrorCode::InvalidDatabase => 1212,
+            ErrorCode::WrongType => 1213,
+            ErrorCode::NotANumber => 1214,
+            ErrorCode::ScriptError => 1215,
+            ErrorCode::NoSuchClient => 1216,
+            ErrorCode::KeyTooLarge => 1217,
+            ErrorCode::OutOfMemory => 1301,
+            ErrorCode::ReadOnly => 1302,
+            ErrorCode::Internal => 1303,
+            ErrorCode::QuotaExceeded => 1304,
+            ErrorCode::SoftDeleteDisabled => 1305,
+            ErrorCode::HistoryDisabled => 1306,
+            ErrorCode::TooManyClients => 1307,
+            ErrorCode::RateLimited => 1308,
+            ErrorCode::SnapshotError => 1309,
+            ErrorCode::DatasetError => 1310,
+            ErrorCode::AccessTrackingDisabled => 1311,
+            ErrorCode::CommandTimeout => 1312,
+        }
+    }
+
+    /// The name of the error, following its code in the responses.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::UnknownCommand => "unknown_command",
+            ErrorCode::MissingKey => "missing_key",
+            ErrorCode::MissingValue => "missing_value",
+            ErrorCode::UnexpectedArgument => "unexpected_argument",
+            ErrorCode::UnsupportedProtocol => "unsupported_protocol",
+            ErrorCode::MissingNamespace => "missing_namespace",
+            ErrorCode::AuthRequired => "auth_required",
+            ErrorCode::NoPermission => "no_permission",
+            ErrorCode::Subscribed => "subscribed",
+            ErrorCode::Transaction => "transaction",
+            ErrorCode::Moved => "moved",
+            ErrorCode::InvalidKey => "invalid_key",
+            ErrorCode::InvalidTtl => "invalid_ttl",
+            ErrorCode::ValueTooLarge => "value_too_large",
+            ErrorCode::MessageTooLong => "message_too_long",
+            ErrorCode::InvalidJitter => "invalid_jitter",
+            ErrorCode::InvalidNamespace => "invalid_namespace",
+            ErrorCode::InvalidRequestId => "invalid_request_id",
+            ErrorCode::InvalidParameter => "invalid_parameter",
+            ErrorCode::InvalidPassword => "invalid_password",
+            ErrorCode::NotAnInteger => "not_an_integer",
+            ErrorCode::InvalidBulk => "invalid_bulk",
+            ErrorCode::InvalidDatabase => "invalid_database",
+            ErrorCode::WrongType => "wrong_type",
+            ErrorCode::NotANumber => "not_a_number",
+            ErrorCode::ScriptError => "script_error",
+            ErrorCode::NoSuchClient => "no_such_client",
+            ErrorCode::KeyTooLarge => "key_too_large",
+            ErrorCode::OutOfMemory => "out_of_memory",
+            ErrorCode::ReadOnly => "read_only",
+            ErrorCode::Internal => "internal",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::SoftDeleteDisabled => "soft_delete_disabled",
+            ErrorCode::HistoryDisabled => "history_disabled",
+            ErrorCode::TooManyClients => "too_many_clients",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::SnapshotError => "snapshot_error",
+            ErrorCode::DatasetError => "dataset_error",
+            ErrorCode::AccessTrackingDisabled => "access_tracking_disabled",
+            ErrorCode::CommandTimeout => "command_timeout",
+        }
+    }
+
+    /// A human-readable description of the error.
+    pub fn description(&self) -> &'static str {
+        match self {
+            ErrorCode::UnknownCommand => "Unknown command",
+            ErrorCode::MissingKey => "Missing key",
+            ErrorCode::MissingValue => "Missing value",
+            ErrorCode::UnexpectedArgument => "Unexpected argument",
+            ErrorCode::UnsupportedProtocol => "Unsupported protocol",
+            ErrorCode::MissingNamespace => "Missing namespace",
+            ErrorCode::AuthRequired => "Authentication required",
+            ErrorCode::NoPermission => "No permission",
+            ErrorCode::Subscribed => "Only SUBSCRIBE and UNSUBSCRIBE are accepted while subscribed",
+            ErrorCode::Transaction => "Transaction error",
+            ErrorCode::Moved => "The keys are served by another node of the cluster",
+            ErrorCode::InvalidKey => "Key must be valid UTF-8",
+            ErrorCode::InvalidTtl => "TTL must be a number of seconds",
+            ErrorCode::ValueTooLarge => "Value too large",
+            ErrorCode::MessageTooLong => "Message too long",
+            ErrorCode::InvalidJitter => "Jitter must be a percentage between 0 and 100",
+            ErrorCode::InvalidNamespace => "Invalid namespace",
+            ErrorCode::InvalidRequestId => "Invalid request id",
+            ErrorCode::InvalidParameter => "Invalid parameter",
+            ErrorCode::InvalidPassword => "Invalid password",
+            ErrorCode::NotAnInteger => "Value is not an integer or out of range",
+            ErrorCode::InvalidBulk => "Bulk payload not followed by a line terminator",
+            ErrorCode::InvalidDatabase => "DB index is out of range",
+            ErrorCode::WrongType => "The key holds a value of another type",
+            ErrorCode::NotANumber => "Score is not a valid number",
+            ErrorCode::ScriptError => "Script error",
+            ErrorCode::NoSuchClient => "No such client",
+            ErrorCode::KeyTooLarge => "Key too large",
+            ErrorCode::OutOfMemory => "Out of memory",
+            ErrorCode::ReadOnly => "The server is read-only",
+            ErrorCode::Internal => "The cache failed to execute the command",
+            ErrorCode::QuotaExceeded => "The namespace has exceeded its quota",
+            ErrorCode::SoftDeleteDisabled => "Soft delete is disabled",
+            ErrorCode::HistoryDisabled => "The versions of the keys are not kept",
+            ErrorCode::TooManyClients => "Max number of clients reached",
+            ErrorCode::RateLimited => "Rate limit exceeded",
+            ErrorCode::SnapshotError => "Snapshot error",
+            ErrorCode::DatasetError => "Dataset error",
+            ErrorCode::AccessTrackingDisabled => "The reads of the keys are not tracked",
+            ErrorCode::CommandTimeout => "Command timeout exceeded",
+        }
+    }
+
+    /// The error with the given numeric code, if any.
+    pub fn from_code(code: u16) -> Option<ErrorCode> {
+        ErrorCode::ALL
+            .into_iter()
+            .find(|error| error.code() == code)
+    }
+
+    /// Parse a response of the strict mode (e.g. `ERR 1101 missing_key`). Returns `None` if the response
+    /// is not an error, or if its code is unknown (i.e. newer than this library).
+    pub fn parse(response: &str) -> Option<ErrorCode> {
+        let mut parts = response.trim_end().split(' ');
+        match (
+            parts.next(),
+            parts.next().and_then(|code| code.parse().ok()),
+        ) {
+            (Some("ERR"), Some(code)) => ErrorCode::from_code(code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    /// Format the error as sent by the server, without the line terminator.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ERR {} {}", self.code(), self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(message: &[u8]) -> Vec<&[u8]> {
+        Tokens::new(message).collect()
+    }
+
+    #[test]
+    fn splits_on_any_whitespace() {
+        let expected: Vec<&[u8]> = vec![b"SET", b"key", b"value"];
+        assert_eq!(tokens(b"SET key value"), expected);
+        assert_eq!(tokens(b"  SET\tkey \x0c value\r\n"), expected);
+    }
+
+    #[test]
+    fn empty_and_blank_messages_have_no_tokens() {
+        assert!(tokens(b"").is_empty());
+        assert!(tokens(b" \t\r\n").is_empty());
+    }
+
+    #[test]
+    fn quotes_and_escapes_are_plain_bytes() {
+        // There is no quoting: quotes and backslashes are kept, and whitespace always separates tokens
+        let expected: Vec<&[u8]> = vec![b"SET", b"\"a", b"b\"", b"c\\", b"\\n"];
+        assert_eq!(tokens(b"SET \"a b\" c\\ \\n"), expected);
+        // An unterminated quote is taken as is
+        let expected: Vec<&[u8]> = vec![b"SET", b"key", b"\"value"];
+        assert_eq!(tokens(b"SET key \"value"), expected);
+    }
+
+    #[test]
+    fn tokens_are_not_required_to_be_utf8() {
+        let expected: Vec<&[u8]> = vec![b"SET", b"key", b"\xff\xfe"];
+        assert_eq!(tokens(b"SET key \xff\xfe"), expected);
+        assert_eq!(as_key(b"key"), Some("key"));
+        assert_eq!(as_key(b"\xff"), None);
+        assert_eq!(as_key(b"\x011:secret"), None);
+        assert_eq!(as_key(b"a\x01"), Some("a\u{1}"));
+    }
+
+    #[test]
+    fn finds_whitespace_after_the_simd_threshold() {
+        for separator in [b' ', b'\t', b'\n', b'\r', b'\x0c'] {
+            let mut message: Vec<u8> = vec![b'a'; SIMD_SCAN_THRESHOLD * 2];
+            message.push(separator);
+            message.extend_from_slice(b"b");
+            let expected: Vec<&[u8]> = vec![&message[..SIMD_SCAN_THRESHOLD * 2], b"b"];
+            assert_eq!(tokens(&message), expected);
+        }
+        let message: Vec<u8> = vec![b'a'; SIMD_SCAN_THRESHOLD * 2];
+        assert_eq!(tokens(&message), vec![&message[..]]);
+    }
+
+    #[test]
+    fn bulk_lengths_are_literal_outside_the_bulk_mode() {
+        let expected: Vec<&[u8]> = vec![b"SET", b"key", b"$5"];
+        assert_eq!(tokens(b"SET key $5"), expected);
+    }
+
+    #[test]
+    fn bulk_lengths_take_the_payloads() {
+        let line: &[u8] = b"MSET a $11 b $0";
+        let data: &[u8] = b"hello world\r\n\r\nrest";
+        assert_eq!(bulk_end(line, data), Bulk::Complete(15));
+
+        let parts: Vec<&[u8]> = Tokens::with_payloads(line, &data[..15]).collect();
+        let expected: Vec<&[u8]> = vec![b"MSET", b"a", b"hello world", b"b", b""];
+        assert_eq!(parts, expected);
+    }
+
+    #[test]
+    fn bulk_payloads_hold_any_byte() {
+        let line: &[u8] = b"SET key $6";
+        let data: &[u8] = b"a \r\n\"\0\n";
+        assert_eq!(bulk_end(line, data), Bulk::Complete(7));
+
+        let parts: Vec<&[u8]> = Tokens::with_payloads(line, data).collect();
+        let expected: Vec<&[u8]> = vec![b"SET", b"key", b"a \r\n\"\0"];
+        assert_eq!(parts, expected);
+    }
+
+    #[test]
+    fn bulk_payloads_must_be_terminated() {
+        assert_eq!(bulk_end(b"SET key $5", b""), Bulk::Incomplete);
+        assert_eq!(bulk_end(b"SET key $5", b"hello"), Bulk::Incomplete);
+        assert_eq!(bulk_end(b"SET key $5", b"hello\r"), Bulk::Incomplete);
+        assert_eq!(bulk_end(b"SET key $5", b"hello!\r\n"), Bulk::Invalid);
+        assert_eq!(bulk_end(b"SET key $5", b"hello\n"), Bulk::Complete(6));
+    }
+
+    #[test]
+    fn only_digits_make_a_bulk_length() {
+        assert_eq!(bulk_length(b"$42"), Some(42));
+        assert_eq!(bulk_length(b"$"), None);
+        assert_eq!(bulk_length(b"$-1"), None);
+        assert_eq!(bulk_length(b"$4x"), None);
+        assert_eq!(bulk_length(b"42"), None);
+        assert_eq!(bulk_end(b"SET key $ $x", b""), Bulk::Complete(0));
+    }
+
+    #[test]
+    fn missing_payloads_leave_the_token() {
+        let parts: Vec<&[u8]> = Tokens::with_payloads(b"SET key $5", b"abc").collect();
+        let expected: Vec<&[u8]> = vec![b"SET", b"key", b"$5"];
+        assert_eq!(parts, expected);
+    }
+}
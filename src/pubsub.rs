@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::debug;
+
+use crate::{
+    cache::Bytes,
+    connection::ClientStream,
+    error::{CacheError, CacheResult, ServerError},
+};
+
+/// How many messages a subscriber may fall behind by before it is dropped.
+pub const MESSAGE_QUEUE_CAPACITY: usize = 1024;
+
+/// How often the thread forwarding the messages of a connection checks whether the connection is closed.
+const MESSAGES_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A message published on a channel, as received by its subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: String,
+    pub payload: Bytes,
+}
+
+/// The queues of the subscribers, and the subscribers of every channel.
+#[derive(Default)]
+struct Channels {
+    senders: HashMap<u64, SyncSender<Message>>, // The queue of the messages not received yet, by subscriber
+    subscribers: HashMap<String, Vec<u64>>,     // The subscribers of every channel with any
+}
+
+/// Delivers the messages published on channels to the subscribers of the channels, e.g. the client
+/// connections which have sent `SUBSCRIBE`.
+///
+/// A subscriber has a single queue, whatever the number of its channels, so that it receives the messages
+/// in the order they were published.
+pub(crate) struct PubSub {
+    channels: Mutex<Channels>,
+    next_id: AtomicU64,      // The id of the next subscriber registered
+    registered: AtomicUsize, // The number of subscribers registered, read without locking the channels
+}
+
+impl PubSub {
+    pub fn new() -> PubSub {
+        PubSub {
+            channels: Mutex::new(Channels::default()),
+            next_id: AtomicU64::new(1),
+            registered: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether any subscriber is registered, without locking the channels: e.g. so that the commands are
+    /// only formatted for the monitor feed while someone is watching it.
+    pub fn has_subscribers(&self) -> bool {
+        self.registered.load(Ordering::Relaxed) > 0
+    }
+
+    /// Register a new subscriber, with no channel yet.
+    ///
+    /// # Returns
+    /// * The id of the subscriber, and the queue of the messages it receives.
+    pub fn register(&self) -> CacheResult<(u64, Receiver<Message>)> {
+        let (sender, receiver) = mpsc::sync_channel::<Message>(MESSAGE_QUEUE_CAPACITY);
+        let id: u64 = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.channels.lock()?;
+        state.senders.insert(id, sender);
+        self.registered
+            .store(state.senders.len(), Ordering::Relaxed);
+        Ok((id, receiver))
+    }
+
+    /// Unregister a subscriber from the given channels and drop its queue.
+    pub fn unregister(&self, id: u64, channels: &[String]) -> CacheResult<()> {
+        let mut state = self.channels.lock()?;
+        state.senders.remove(&id);
+        self.registered
+            .store(state.senders.len(), Ordering::Relaxed);
+        for channel in channels {
+            remove_subscriber(&mut state, channel, id);
+        }
+        Ok(())
+    }
+
+    /// Subscribe a registered subscriber to the given channel.
+    pub fn subscribe(&self, id: u64, channel: &str) -> CacheResult<()> {
+        let mut state = self.channels.lock()?;
+        let subscribers: &mut Vec<u64> = state.subscribers.entry(channel.to_string()).or_default();
+        if !subscribers.contains(&id) {
+            subscribers.push(id);
+        }
+        Ok(())
+    }
+
+    /// Unsubscribe a subscriber from the given channel.
+    pub fn unsubscribe(&self, id: u64, channel: &str) -> CacheResult<()> {
+        remove_subscriber(&mut *self.channels.lock()?, channel, id);
+        Ok(())
+    }
+
+    /// Publish a message to the subscribers of its channel. The subscribers which are gone or too far
+    /// behind are dropped (the latter find their queue disconnected once they have caught up).
+    ///
+    /// # Returns
+    /// * The number of subscribers the message was queued for.
+    pub fn publish(&self, channel: &str, payload: Bytes) -> CacheResult<usize> {
+        let mut state = self.channels.lock()?;
+        let Channels {
+            senders,
+            subscribers,
+        } = &mut *state;
+        let Some(ids) = subscribers.get_mut(channel) else {
+            return Ok(0);
+        };
+
+        let mut received: usize = 0;
+        ids.retain(|id| {
+            let Some(sender) = senders.get(id) else {
+                return false;
+            };
+            let message: Message = Message {
+                channel: channel.to_string(),
+                payload: payload.clone(),
+            };
+            match sender.try_send(message) {
+                Ok(()) => {
+                    received += 1;
+                    true
+                }
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                    senders.remove(id);
+                    false
+                }
+            }
+        });
+        if ids.is_empty() {
+            subscribers.remove(channel);
+        }
+        self.registered.store(senders.len(), Ordering::Relaxed);
+        Ok(received)
+    }
+}
+
+/// Remove a subscriber from the subscribers of a channel, forgetting the channel once it has none.
+fn remove_subscriber(state: &mut Channels, channel: &str, id: u64) {
+    if let Some(subscribers) = state.subscribers.get_mut(channel) {
+        subscribers.retain(|subscriber| *subscriber != id);
+        if subscribers.is_empty() {
+            state.subscribers.remove(channel);
+        }
+    }
+}
+
+/// The channels a client connection is subscribed to, whose messages are pushed to the client by a thread
+/// of its own while the handler of the connection waits for its next command.
+///
+/// The handler and the thread write to the connection in turn, holding the lock of `writes`, so that the
+/// messages are never written in the middle of a response. The connection is unsubscribed from all its
+/// channels once dropped.
+pub(crate) struct Subscriptions {
+    pubsub: Arc<PubSub>,
+    id: u64,                             // The id of the connection as a subscriber
+    channels: Vec<String>,               // The channels subscribed to, in the order they were
+    receiver: Option<Receiver<Message>>, // The queue of the messages, until a thread forwards them
+    writes: Arc<Mutex<()>>,              // Held while the connection is written to
+    closed: Arc<AtomicBool>, // Whether the connection is closed, to stop forwarding the messages
+}
+
+impl Subscriptions {
+    /// Register a client connection as a subscriber, with no channel yet.
+    pub fn new(pubsub: Arc<PubSub>) -> CacheResult<Subscriptions> {
+        let (id, receiver) = pubsub.register()?;
+        Ok(Subscriptions {
+            pubsub,
+            id,
+            channels: Vec::new(),
+            receiver: Some(receiver),
+            writes: Arc::new(Mutex::new(())),
+            closed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Subscribe to the given channel (if not subscribed yet), returning the number of channels subscribed
+    /// to.
+    pub fn subscribe(&mut self, channel: &str) -> CacheResult<usize> {
+        if !self.channels.iter().any(|subscribed| subscribed == channel) {
+            self.pubsub.subscribe(self.id, channel)?;
+            self.channels.push(channel.to_string());
+        }
+        Ok(self.channels.len())
+    }
+
+    /// Unsubscribe from the given channel (if subscribed), returning the number of channels still
+    /// subscribed to.
+    pub fn unsubscribe(&mut self, channel: &str) -> CacheResult<usize> {
+        self.pubsub.unsubscribe(self.id, channel)?;
+        self.channels.retain(|subscribed| subscribed != channel);
+        Ok(self.channels.len())
+    }
+
+    /// The channels subscribed to, in the order they were.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// The lock to hold while writing to the connection.
+    pub fn writes(&self) -> Arc<Mutex<()>> {
+        Arc::clone(&self.writes)
+    }
+
+    /// Forward the messages to the client on a thread of its own, encoded by the given function, unless
+    /// they already are. A client which falls too far behind is disconnected, since it has missed messages.
+    ///
+    /// # Arguments
+    /// * `stream` - The client connection, written to without buffering.
+    /// * `address` - The address of the client, for logging purposes.
+    /// * `encode` - Encodes a message as pushed to the client by the protocol of the connection.
+    pub fn forward(
+        &mut self,
+        stream: ClientStream,
+        address: &str,
+        encode: fn(&Message) -> Vec<u8>,
+    ) {
+        let Some(receiver) = self.receiver.take() else {
+            return;
+        };
+        let writes: Arc<Mutex<()>> = Arc::clone(&self.writes);
+        let closed: Arc<AtomicBool> = Arc::clone(&self.closed);
+        let address: String = address.to_string();
+        thread::spawn(move || {
+            if let Err(e) = forward_messages(receiver, stream, &writes, &closed, encode) {
+                debug!("Stopped forwarding the messages to {}: {}", address, e);
+            }
+        });
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        let _ = self.pubsub.unregister(self.id, &self.channels);
+    }
+}
+
+/// Write the messages of the given queue to the client, until the connection is closed.
+fn forward_messages(
+    receiver: Receiver<Message>,
+    mut stream: ClientStream,
+    writes: &Mutex<()>,
+    closed: &AtomicBool,
+    encode: fn(&Message) -> Vec<u8>,
+) -> Result<(), ServerError> {
+    while !closed.load(Ordering::Relaxed) {
+        let message: Message = match receiver.recv_timeout(MESSAGES_POLL_INTERVAL) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                let _guard = writes.lock().map_err(CacheError::from)?;
+                stream.shutdown(Shutdown::Both)?;
+                return Ok(());
+            }
+        };
+
+        let encoded: Vec<u8> = encode(&message);
+        let _guard = writes.lock().map_err(CacheError::from)?;
+        stream.write_all(&encoded)?;
+    }
+    Ok(())
+}
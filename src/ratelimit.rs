@@ -0,0 +1,44 @@
+use std::time::Instant;
+
+/// How many commands a client may send: a steady rate, and a burst above it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RateLimit {
+    pub rate: u32,  // The commands per second allowed in the long run
+    pub burst: u32, // The commands allowed at once, after a pause of the client
+}
+
+/// A token bucket limiting the commands of a connection: every command takes a token, and the bucket is
+/// refilled at the rate of the limit, up to its burst.
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64, // The tokens left, fractional since the bucket is refilled continuously
+    refilled_at: Instant, // When the tokens were last refilled
+}
+
+impl TokenBucket {
+    /// A full bucket of the given limit.
+    pub fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Take a token for a command.
+    ///
+    /// # Returns
+    /// * `false` if the bucket is empty, i.e. the command exceeds the limit.
+    pub fn try_take(&mut self) -> bool {
+        let now: Instant = Instant::now();
+        let elapsed: f64 = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate as f64).min(self.limit.burst as f64);
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
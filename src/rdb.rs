@@ -0,0 +1,457 @@
+use std::io::{self, ErrorKind, Read, Write};
+
+use log::{debug, warn};
+
+use crate::{embedded::EmbeddedCache, error::ServerError, utils::current_walltime, Bytes};
+
+/// The opcodes of the RDB format, found where a value type is expected.
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+/// The value types of the RDB format. Only strings can be imported; the other types are skipped.
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+
+/// The special encodings of strings, held in the low bits of a length whose two high bits are set.
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+/// The oldest and newest versions of the RDB format supported (Redis 2.6 to 7.2).
+const MIN_VERSION: u32 = 1;
+const MAX_VERSION: u32 = 11;
+
+/// The version of the RDB format written by `export` (Redis 5 and later read it).
+const EXPORT_VERSION: u32 = 9;
+
+/// The largest string accepted, so that a corrupt length cannot exhaust the memory.
+const MAX_STRING_SIZE: u64 = 512 * 1024 * 1024;
+
+/// The outcome of importing an RDB file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportStats {
+    pub imported: usize, // The string keys imported
+    pub expired: usize,  // The keys whose expiration time had already passed
+    pub skipped: usize,  // The keys not imported: not strings, not in database 0 or not UTF-8
+}
+
+/// Import the string keys of database 0 of a Redis RDB dump (as written by `SAVE` or `BGSAVE`) into the
+/// given cache, along with their TTLs. Keys of other types (lists, sets, hashes, ...) and of other
+/// databases are skipped, as the cache only holds string values in a single keyspace.
+///
+/// The checksum at the end of the file is not verified.
+///
+/// # Arguments
+/// * `reader` - The content of the RDB file, preferably buffered.
+/// * `cache` - The cache to put the keys into, replacing the values of the keys it already holds.
+///
+/// # Errors
+/// * `ServerError::Io` if the file cannot be read, is not an RDB file, or holds a value that cannot be
+///   skipped (streams, module types and functions).
+/// * `ServerError::Cache` if a key cannot be put into the cache.
+pub fn import<R: Read>(reader: R, cache: &EmbeddedCache) -> Result<ImportStats, ServerError> {
+    read_strings(reader, |key, value, ttl| {
+        cache.apply_put(&key, Bytes::from(value), ttl)?;
+        Ok(())
+    })
+}
+
+/// Read the string keys of database 0 of a Redis RDB dump, handing each of them to `visit` along with its
+/// value and remaining TTL (in seconds, rounded up). This is `import` for the keys to go somewhere else
+/// than a local cache, such as a remote server. Expired keys and the keys that cannot be imported are
+/// only counted.
+///
+/// # Errors
+/// * `ServerError::Io` if the file cannot be read, is not an RDB file, or holds a value that cannot be
+///   skipped (streams, module types and functions).
+/// * Any error returned by `visit`, which stops the reading.
+pub fn read_strings<R: Read>(
+    reader: R,
+    mut visit: impl FnMut(String, Vec<u8>, Option<u64>) -> Result<(), ServerError>,
+) -> Result<ImportStats, ServerError> {
+    let mut rdb: RdbReader<R> = RdbReader { reader };
+    let mut stats: ImportStats = ImportStats::default();
+
+    let mut header: [u8; 9] = [0; 9];
+    let version: Option<u32> = match rdb.reader.read_exact(&mut header) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+        result => result.map(|_| parse_version(&header))?,
+    };
+    match version {
+        Some(version) if (MIN_VERSION..=MAX_VERSION).contains(&version) => {
+            debug!("Importing an RDB file of version {}", version)
+        }
+        Some(version) => return Err(invalid(format!("unsupported RDB version {}", version)).into()),
+        None => return Err(invalid("not an RDB file".to_string()).into()),
+    }
+
+    let mut database: u64 = 0;
+    let mut expires_at: Option<u64> = None; // The expiration time of the next key (in ms since the epoch)
+    loop {
+        let value_type: u8 = rdb.byte()?;
+        match value_type {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => database = rdb.length()?,
+            OPCODE_RESIZEDB => {
+                rdb.length()?;
+                rdb.length()?;
+            }
+            OPCODE_AUX => {
+                rdb.skip_string()?;
+                rdb.skip_string()?;
+            }
+            OPCODE_EXPIRETIME => expires_at = Some(u64::from(rdb.u32_le()?) * 1000),
+            OPCODE_EXPIRETIME_MS => expires_at = Some(rdb.u64_le()?),
+            OPCODE_FREQ => {
+                rdb.byte()?;
+            }
+            OPCODE_IDLE => {
+                rdb.length()?;
+            }
+            OPCODE_MODULE_AUX | OPCODE_FUNCTION2 => {
+                return Err(invalid("functions and modules are not supported".to_string()).into())
+            }
+            _ => {
+                let key: Vec<u8> = rdb.string()?;
+                let expires_at: Option<u64> = expires_at.take();
+
+                if value_type != TYPE_STRING {
+                    rdb.skip_value(value_type)?;
+                    stats.skipped += 1;
+                    continue;
+                }
+                let value: Vec<u8> = rdb.string()?;
+
+                let Ok(key) = String::from_utf8(key) else {
+                    warn!("Skipping a key of the RDB file which is not UTF-8");
+                    stats.skipped += 1;
+                    continue;
+                };
+                if database != 0 {
+                    stats.skipped += 1;
+                    continue;
+                }
+
+                // The TTLs of the cache are in seconds, so the remaining time is rounded up
+                let ttl: Option<u64> = match expires_at {
+                    Some(expires_at) => match expires_at.checked_sub(current_walltime()) {
+                        Some(remaining) if remaining > 0 => Some(remaining.div_ceil(1000)),
+                        _ => {
+                            stats.expired += 1;
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                visit(key, value, ttl)?;
+                stats.imported += 1;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Export the keys holding a value of the cache as a Redis RDB dump (of database 0, with their expiration
+/// times), which `import` reads back, e.g. to warm up another node. The keys cached as known to be absent
+/// and the soft TTLs are not exported. The dump has no checksum, which Redis reads as disabled.
+///
+/// # Returns
+/// * The number of keys exported.
+///
+/// # Errors
+/// * `ServerError::Io` if the dump cannot be written.
+/// * `ServerError::Cache` if the storage engine cannot be scanned for its keys.
+pub fn export<W: Write>(cache: &EmbeddedCache, writer: W) -> Result<usize, ServerError> {
+    let mut rdb: RdbWriter<W> = RdbWriter { writer };
+    rdb.writer
+        .write_all(format!("REDIS{:04}", EXPORT_VERSION).as_bytes())?;
+    rdb.writer.write_all(&[OPCODE_SELECTDB])?;
+    rdb.length(0)?;
+
+    let mut exported: usize = 0;
+    let mut result: io::Result<()> = Ok(());
+    cache.for_each_value(&mut |key, value, expires_at| {
+        if result.is_ok() {
+            result = rdb.string_entry(key.as_bytes(), value, expires_at);
+            exported += 1;
+        }
+    })?;
+    result?;
+
+    // The checksum, all zeros when disabled
+    rdb.writer.write_all(&[OPCODE_EOF])?;
+    rdb.writer.write_all(&[0; 8])?;
+    rdb.writer.flush()?;
+    Ok(exported)
+}
+
+/// Writes the primitives of the RDB format.
+struct RdbWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> RdbWriter<W> {
+    /// Write a length in the shortest encoding that holds it.
+    fn length(&mut self, length: u64) -> io::Result<()> {
+        match length {
+            0..=0x3F => self.writer.write_all(&[length as u8]),
+            0x40..=0x3FFF => self
+                .writer
+                .write_all(&[0x40 | (length >> 8) as u8, length as u8]),
+            0x4000..=0xFFFF_FFFF => {
+                self.writer.write_all(&[0x80])?;
+                self.writer.write_all(&(length as u32).to_be_bytes())
+            }
+            _ => {
+                self.writer.write_all(&[0x81])?;
+                self.writer.write_all(&length.to_be_bytes())
+            }
+        }
+    }
+
+    fn string(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.length(bytes.len() as u64)?;
+        self.writer.write_all(bytes)
+    }
+
+    /// Write a string key, preceded by its expiration time (in milliseconds since the epoch), if any.
+    fn string_entry(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> io::Result<()> {
+        if let Some(expires_at) = expires_at {
+            self.writer.write_all(&[OPCODE_EXPIRETIME_MS])?;
+            self.writer.write_all(&expires_at.to_le_bytes())?;
+        }
+        self.writer.write_all(&[TYPE_STRING])?;
+        self.string(key)?;
+        self.string(value)
+    }
+}
+
+/// Parse the version of the RDB format from the header of the file (`REDIS` followed by 4 digits).
+fn parse_version(header: &[u8; 9]) -> Option<u32> {
+    match &header[..5] {
+        b"REDIS" => std::str::from_utf8(&header[5..]).ok()?.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Reads the primitives of the RDB format.
+struct RdbReader<R: Read> {
+    reader: R,
+}
+
+/// A length, or the special encoding of a string (see `ENCODING_*`).
+enum Length {
+    Plain(u64),
+    Encoded(u8),
+}
+
+impl<R: Read> RdbReader<R> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte: [u8; 1] = [0];
+        self.reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        let mut bytes: [u8; 4] = [0; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64_le(&mut self) -> io::Result<u64> {
+        let mut bytes: [u8; 8] = [0; 8];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn bytes(&mut self, length: u64) -> io::Result<Vec<u8>> {
+        if length > MAX_STRING_SIZE {
+            return Err(invalid(format!("string of {} bytes", length)));
+        }
+        let mut bytes: Vec<u8> = vec![0; length as usize];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Read a length, whose two high bits tell how it is encoded.
+    fn encoded_length(&mut self) -> io::Result<Length> {
+        let first: u8 = self.byte()?;
+        let length: u64 = match first >> 6 {
+            0 => u64::from(first & 0x3F),
+            1 => u64::from(first & 0x3F) << 8 | u64::from(self.byte()?),
+            2 => match first {
+                0x80 => {
+                    let mut bytes: [u8; 4] = [0; 4];
+                    self.reader.read_exact(&mut bytes)?;
+                    u64::from(u32::from_be_bytes(bytes))
+                }
+                0x81 => {
+                    let mut bytes: [u8; 8] = [0; 8];
+                    self.reader.read_exact(&mut bytes)?;
+                    u64::from_be_bytes(bytes)
+                }
+                _ => return Err(invalid(format!("invalid length encoding {:#x}", first))),
+            },
+            _ => return Ok(Length::Encoded(first & 0x3F)),
+        };
+        Ok(Length::Plain(length))
+    }
+
+    fn length(&mut self) -> io::Result<u64> {
+        match self.encoded_length()? {
+            Length::Plain(length) => Ok(length),
+            Length::Encoded(_) => Err(invalid("unexpected string encoding".to_string())),
+        }
+    }
+
+    /// Read a string, which may be held as an integer or compressed with LZF.
+    fn string(&mut self) -> io::Result<Vec<u8>> {
+        match self.encoded_length()? {
+            Length::Plain(length) => self.bytes(length),
+            Length::Encoded(ENCODING_INT8) => Ok((self.byte()? as i8).to_string().into_bytes()),
+            Length::Encoded(ENCODING_INT16) => {
+                let mut bytes: [u8; 2] = [0; 2];
+                self.reader.read_exact(&mut bytes)?;
+                Ok(i16::from_le_bytes(bytes).to_string().into_bytes())
+            }
+            Length::Encoded(ENCODING_INT32) => Ok((self.u32_le()? as i32).to_string().into_bytes()),
+            Length::Encoded(ENCODING_LZF) => {
+                let compressed_length: u64 = self.length()?;
+                let length: u64 = self.length()?;
+                if length > MAX_STRING_SIZE {
+                    return Err(invalid(format!("string of {} bytes", length)));
+                }
+                let compressed: Vec<u8> = self.bytes(compressed_length)?;
+                lzf_decompress(&compressed, length as usize)
+                    .ok_or_else(|| invalid("corrupt LZF string".to_string()))
+            }
+            Length::Encoded(encoding) => {
+                Err(invalid(format!("unknown string encoding {}", encoding)))
+            }
+        }
+    }
+
+    fn skip_string(&mut self) -> io::Result<()> {
+        self.string().map(|_| ())
+    }
+
+    /// Skip a value of a type other than string.
+    fn skip_value(&mut self, value_type: u8) -> io::Result<()> {
+        match value_type {
+            TYPE_LIST | TYPE_SET | TYPE_LIST_QUICKLIST => {
+                for _ in 0..self.length()? {
+                    self.skip_string()?;
+                }
+            }
+            TYPE_HASH => {
+                for _ in 0..self.length()? {
+                    self.skip_string()?;
+                    self.skip_string()?;
+                }
+            }
+            TYPE_ZSET => {
+                for _ in 0..self.length()? {
+                    self.skip_string()?;
+                    // A score held as text, unless its length is one of the markers of NaN and infinities
+                    let length: u8 = self.byte()?;
+                    if length < 253 {
+                        self.bytes(u64::from(length))?;
+                    }
+                }
+            }
+            TYPE_ZSET_2 => {
+                for _ in 0..self.length()? {
+                    self.skip_string()?;
+                    self.u64_le()?;
+                }
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                for _ in 0..self.length()? {
+                    self.length()?; // The container kind of the node
+                    self.skip_string()?;
+                }
+            }
+            TYPE_HASH_ZIPMAP | TYPE_LIST_ZIPLIST | TYPE_SET_INTSET | TYPE_ZSET_ZIPLIST
+            | TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK | TYPE_ZSET_LISTPACK | TYPE_SET_LISTPACK => {
+                self.skip_string()?;
+            }
+            _ => return Err(invalid(format!("unsupported value type {}", value_type))),
+        }
+        Ok(())
+    }
+}
+
+/// Decompress the given LZF data, which must decompress to exactly `length` bytes.
+fn lzf_decompress(input: &[u8], length: usize) -> Option<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::with_capacity(length);
+    let mut i: usize = 0;
+
+    while i < input.len() {
+        let control: usize = usize::from(input[i]);
+        i += 1;
+
+        if control < 32 {
+            // A literal run of `control + 1` bytes
+            let literal: &[u8] = input.get(i..i + control + 1)?;
+            output.extend_from_slice(literal);
+            i += literal.len();
+        } else {
+            // A back reference of `length + 2` bytes, `offset + 1` bytes back
+            let mut run: usize = control >> 5;
+            if run == 7 {
+                run += usize::from(*input.get(i)?);
+                i += 1;
+            }
+            let offset: usize = ((control & 0x1F) << 8 | usize::from(*input.get(i)?)) + 1;
+            i += 1;
+
+            let start: usize = output.len().checked_sub(offset)?;
+            for j in 0..run + 2 {
+                output.push(output[start + j]);
+            }
+        }
+        if output.len() > length {
+            return None;
+        }
+    }
+
+    match output.len() == length {
+        true => Some(output),
+        false => None,
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid RDB file: {}", message),
+    )
+}
@@ -0,0 +1,472 @@
+use std::{
+    io::{self, BufReader, Cursor, ErrorKind, Read, Write},
+    net::TcpStream,
+    str,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use memchr::memchr;
+
+use crate::{
+    cache::Bytes,
+    embedded::EmbeddedCache,
+    error::{CacheError, ServerError},
+    rdb::{self, ImportStats},
+    resp::{self, Parsed},
+    server::parse_number,
+    utils::{current_walltime, expires_after},
+};
+
+/// How long to wait before connecting again to the primary after losing it.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// How often the replicated offset is acknowledged to the primary (as Redis replicas do).
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the primary may stay silent before it is considered lost. It pings its replicas every 10
+/// seconds by default.
+const PRIMARY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The size of the chunks read from the primary.
+const READ_SIZE: usize = 16 * 1024;
+
+/// A replica of a primary (a Redis server, or another node accepting replicas, see `ReplicationFeed`): it
+/// takes a full copy of the data (an RDB dump) when attached, then applies the stream of the write commands
+/// of the primary, reconnecting (with a partial resynchronization if the primary still has the missed
+/// commands) whenever the connection is lost.
+///
+/// Only the string keys of database 0 of a Redis primary are replicated. The commands which cannot be mirrored exactly (e.g.
+/// `INCR` or `APPEND`, whose TTL would be lost) remove their key instead, so that the cache misses rather
+/// than serving a wrong value.
+pub(crate) struct Replica {
+    primary: String,                // The address of the primary (host:port)
+    auth: Option<String>,           // The password of the primary, if it requires one
+    listening_port: u16, // The port announced to the primary (shown by `INFO replication`)
+    cache: Arc<EmbeddedCache>, // The cache the data is replicated into
+    replication_id: Option<String>, // The id of the replication stream, once synchronized
+    offset: u64,         // The offset of the last command applied in the replication stream
+    database: u64,       // The database selected by the primary for the next commands
+}
+
+impl Replica {
+    pub fn new(
+        primary: String,
+        auth: Option<String>,
+        listening_port: u16,
+        cache: Arc<EmbeddedCache>,
+    ) -> Replica {
+        Replica {
+            primary,
+            auth,
+            listening_port,
+            cache,
+            replication_id: None,
+            offset: 0,
+            database: 0,
+        }
+    }
+
+    /// Replicate the primary on a thread of its own, for as long as the process runs.
+    ///
+    /// # Errors
+    /// * If the replication thread cannot be spawned.
+    pub fn spawn(mut self) -> io::Result<()> {
+        thread::Builder::new()
+            .name("replica".to_string())
+            .spawn(move || loop {
+                if let Err(e) = self._replicate() {
+                    warn!("Lost the replication of {}: {}", self.primary, e);
+                }
+                self.cache.mark_replication_lost();
+                thread::sleep(RECONNECT_DELAY);
+            })?;
+        Ok(())
+    }
+
+    /// Connect to the primary, synchronize with it and apply its commands until the connection is lost.
+    fn _replicate(&mut self) -> Result<(), ServerError> {
+        let mut stream: TcpStream = TcpStream::connect(&self.primary)?;
+        stream.set_read_timeout(Some(PRIMARY_TIMEOUT))?;
+        let mut pending: Vec<u8> = Vec::new();
+
+        if let Some(password) = &self.auth {
+            let password: String = password.clone();
+            self._send_command(&mut stream, &["AUTH", &password])?;
+            self._expect_reply(&mut stream, &mut pending, "+OK")?;
+        }
+        self._send_command(&mut stream, &["PING"])?;
+        self._expect_reply(&mut stream, &mut pending, "+PONG")?;
+
+        let port: String = self.listening_port.to_string();
+        self._send_command(&mut stream, &["REPLCONF", "listening-port", &port])?;
+        self._expect_reply(&mut stream, &mut pending, "+OK")?;
+        self._send_command(&mut stream, &["REPLCONF", "capa", "psync2"])?;
+        self._expect_reply(&mut stream, &mut pending, "+OK")?;
+
+        // Ask for the commands following the last one applied, if any, or for a full copy of the data
+        let (replication_id, offset) = match &self.replication_id {
+            Some(id) => (id.clone(), (self.offset + 1).to_string()),
+            None => ("?".to_string(), "-1".to_string()),
+        };
+        self._send_command(&mut stream, &["PSYNC", &replication_id, &offset])?;
+
+        let reply: String = read_line(&mut stream, &mut pending)?;
+        let mut fields = reply.split(' ');
+        match fields.next() {
+            Some("+FULLRESYNC") => {
+                let id: Option<&str> = fields.next();
+                let offset: Option<u64> = fields.next().and_then(|o| parse_number(o.as_bytes()));
+                let (Some(id), Some(offset)) = (id, offset) else {
+                    return Err(protocol_error(format!("unexpected reply '{}'", reply)).into());
+                };
+                self._load_snapshot(&mut stream, &mut pending)?;
+                self.replication_id = Some(id.to_string());
+                self.offset = offset;
+                self.database = 0;
+            }
+            Some("+CONTINUE") => {
+                if let Some(id) = fields.next() {
+                    self.replication_id = Some(id.to_string());
+                }
+                info!("Resumed the replication of {}", self.primary);
+            }
+            _ => return Err(protocol_error(format!("unexpected reply '{}'", reply)).into()),
+        }
+
+        self._apply_stream(&mut stream, pending)
+    }
+
+    /// Replace the data of the cache with the RDB dump sent by the primary after `+FULLRESYNC`.
+    fn _load_snapshot(
+        &mut self,
+        stream: &mut TcpStream,
+        pending: &mut Vec<u8>,
+    ) -> Result<(), ServerError> {
+        // The primary sends newlines to keep the connection alive while it is preparing the dump
+        let mut header: String = read_line(stream, pending)?;
+        while header.is_empty() {
+            header = read_line(stream, pending)?;
+        }
+        let Some(length) = header
+            .strip_prefix('$')
+            .and_then(|length| parse_number(length.as_bytes()))
+        else {
+            return Err(protocol_error(format!("unexpected dump header '{}'", header)).into());
+        };
+
+        match self.cache.apply_clear() {
+            Ok(removed) => debug!("Removed {} key(s) before the full synchronization", removed),
+            Err(CacheError::Unsupported(_)) => {
+                warn!("The storage engine cannot be cleared, so keys deleted on the primary may remain")
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // The dump is read up to its length only, the command stream following it
+        let buffered: Vec<u8> = std::mem::take(pending);
+        let mut dump = BufReader::new(Cursor::new(buffered).chain(&mut *stream).take(length));
+        let stats: ImportStats = rdb::import(&mut dump, &self.cache)?;
+
+        // Skip the checksum following the end of the dump, and keep what follows it for the command stream
+        io::copy(&mut dump, &mut io::sink())?;
+        let (cursor, _) = dump.into_inner().into_inner().into_inner();
+        let consumed: usize = cursor.position() as usize;
+        pending.extend_from_slice(&cursor.into_inner()[consumed..]);
+
+        info!(
+            "Synchronized with {}: {} key(s) replicated ({} expired, {} skipped)",
+            self.primary, stats.imported, stats.expired, stats.skipped
+        );
+        Ok(())
+    }
+
+    /// Apply the commands of the primary as they come, acknowledging the replicated offset periodically.
+    fn _apply_stream(
+        &mut self,
+        stream: &mut TcpStream,
+        mut pending: Vec<u8>,
+    ) -> Result<(), ServerError> {
+        stream.set_read_timeout(Some(ACK_INTERVAL))?;
+        self.cache.mark_replicated();
+        let mut last_received: Instant = Instant::now();
+        let mut last_ack: Instant = Instant::now();
+        let mut buffer: Vec<u8> = vec![0; READ_SIZE];
+
+        loop {
+            let mut start: usize = 0;
+            loop {
+                match resp::parse(&pending[start..]) {
+                    Parsed::Command(arguments, length) => {
+                        if let Some(reply) = self._apply_command(&arguments)? {
+                            stream.write_all(&reply)?;
+                        }
+                        self.offset += length as u64;
+                        start += length;
+                    }
+                    Parsed::Incomplete => break,
+                    Parsed::Invalid(reason) => {
+                        return Err(protocol_error(reason.to_string()).into())
+                    }
+                }
+            }
+            pending.drain(..start);
+
+            if last_ack.elapsed() >= ACK_INTERVAL {
+                stream.write_all(&self._ack())?;
+                last_ack = Instant::now();
+            }
+
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    return Err(
+                        protocol_error("the primary closed the connection".to_string()).into(),
+                    )
+                }
+                Ok(n) => {
+                    pending.extend_from_slice(&buffer[..n]);
+                    last_received = Instant::now();
+                    self.cache.mark_replicated();
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if last_received.elapsed() >= PRIMARY_TIMEOUT {
+                        return Err(protocol_error("the primary timed out".to_string()).into());
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Apply a command of the replication stream to the cache.
+    ///
+    /// # Returns
+    /// * The reply to send to the primary, for the commands expecting one (`REPLCONF GETACK`).
+    fn _apply_command(&mut self, arguments: &[&[u8]]) -> Result<Option<Vec<u8>>, ServerError> {
+        let Some(name) = arguments.first() else {
+            return Ok(None);
+        };
+        let name: String = String::from_utf8_lossy(name).to_ascii_uppercase();
+
+        match name.as_str() {
+            "SELECT" => {
+                let database: Option<u64> = arguments.get(1).and_then(|db| parse_number(db));
+                self.database = database.unwrap_or(0);
+                return Ok(None);
+            }
+            "REPLCONF" => {
+                let getack: bool = arguments
+                    .get(1)
+                    .is_some_and(|sub| sub.eq_ignore_ascii_case(b"GETACK"));
+                return Ok(getack.then(|| self._ack()));
+            }
+            "PING" | "MULTI" | "EXEC" => return Ok(None),
+            "FLUSHALL" => {
+                self._flush()?;
+                return Ok(None);
+            }
+            _ => {}
+        }
+        if self.database != 0 {
+            return Ok(None);
+        }
+
+        match (name.as_str(), arguments) {
+            ("SET", [_, key, value, options @ ..]) => self._apply_set(key, value, options)?,
+            ("SETEX", [_, key, seconds, value]) => {
+                let ttl: Option<u64> = parse_number(seconds);
+                self._put(key, value, ttl.map(|seconds| seconds.saturating_mul(1000)))?;
+            }
+            ("PSETEX", [_, key, milliseconds, value]) => {
+                self._put(key, value, parse_number(milliseconds))?;
+            }
+            ("SETNX" | "GETSET", [_, key, value]) => self._put(key, value, None)?,
+            ("MSET" | "MSETNX", [_, pairs @ ..]) => {
+                for pair in pairs.chunks_exact(2) {
+                    self._put(pair[0], pair[1], None)?;
+                }
+            }
+            ("DEL" | "UNLINK", [_, keys @ ..]) => {
+                for key in keys {
+                    self._remove(key)?;
+                }
+            }
+            ("PEXPIREAT", [_, key, at, ..]) => self._expire_at(key, parse_number(at))?,
+            ("EXPIREAT", [_, key, at, ..]) => {
+                let at: Option<u64> = parse_number(at).map(|at| at.saturating_mul(1000));
+                self._expire_at(key, at)?;
+            }
+            ("PEXPIRE", [_, key, ttl, ..]) => {
+                let at: Option<u64> =
+                    parse_number(ttl).map(|ttl| current_walltime().saturating_add(ttl));
+                self._expire_at(key, at)?;
+            }
+            ("EXPIRE", [_, key, ttl, ..]) => {
+                let at: Option<u64> =
+                    parse_number(ttl).map(|ttl| expires_after(current_walltime(), ttl));
+                self._expire_at(key, at)?;
+            }
+            ("PERSIST", [_, key]) => {
+                if let Some(key) = self._parse_key(key) {
+                    self.cache.apply_expire(key, None)?;
+                }
+            }
+            ("FLUSHDB", _) => self._flush()?,
+            ("RENAME" | "RENAMENX", [_, source, destination]) => {
+                self._remove(source)?;
+                self._remove(destination)?;
+            }
+            (
+                "INCR" | "DECR" | "INCRBY" | "DECRBY" | "INCRBYFLOAT" | "APPEND" | "SETRANGE"
+                | "SETBIT" | "RESTORE",
+                [_, key, ..],
+            ) => {
+                self._remove(key)?;
+            }
+            _ => debug!("Ignoring the replicated command {}", name),
+        }
+        Ok(None)
+    }
+
+    /// Apply a `SET` command, with its expiration options (the other options only matter on the primary).
+    fn _apply_set(&self, key: &[u8], value: &[u8], options: &[&[u8]]) -> Result<(), ServerError> {
+        let mut expires_at: Option<u64> = None;
+        let mut i: usize = 0;
+        while i < options.len() {
+            let option: String = String::from_utf8_lossy(options[i]).to_ascii_uppercase();
+            let argument: Option<u64> = options.get(i + 1).and_then(|arg| parse_number(arg));
+            let now: u64 = current_walltime();
+            match (option.as_str(), argument) {
+                ("EX", Some(seconds)) => expires_at = Some(expires_after(now, seconds)),
+                ("PX", Some(milliseconds)) => expires_at = Some(now.saturating_add(milliseconds)),
+                ("EXAT", Some(at)) => expires_at = Some(at.saturating_mul(1000)),
+                ("PXAT", Some(at)) => expires_at = Some(at),
+                ("KEEPTTL", _) => {
+                    // The TTL of the key is not known here, so the key is dropped rather than kept forever
+                    self._remove(key)?;
+                    return Ok(());
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            }
+            i += 2;
+        }
+
+        match expires_at {
+            Some(at) => match at.checked_sub(current_walltime()) {
+                Some(remaining) if remaining > 0 => self._put(key, value, Some(remaining)),
+                _ => self._remove(key),
+            },
+            None => self._put(key, value, None),
+        }
+    }
+
+    /// Put a value with a TTL in milliseconds, rounded up to the seconds of the cache.
+    fn _put(&self, key: &[u8], value: &[u8], ttl: Option<u64>) -> Result<(), ServerError> {
+        let Some(key) = self._parse_key(key) else {
+            return Ok(());
+        };
+        let ttl: Option<u64> = ttl.map(|ttl| ttl.div_ceil(1000));
+        Ok(self.cache.apply_put(key, Bytes::from(value), ttl)?)
+    }
+
+    /// Expire a key at the given wall-clock time (in milliseconds since the epoch), or now if it has passed.
+    fn _expire_at(&self, key: &[u8], at: Option<u64>) -> Result<(), ServerError> {
+        let Some(at) = at else {
+            return Ok(());
+        };
+        match at.checked_sub(current_walltime()) {
+            Some(remaining) if remaining > 0 => {
+                if let Some(key) = self._parse_key(key) {
+                    self.cache
+                        .apply_expire(key, Some(remaining.div_ceil(1000)))?;
+                }
+                Ok(())
+            }
+            _ => self._remove(key),
+        }
+    }
+
+    fn _remove(&self, key: &[u8]) -> Result<(), ServerError> {
+        if let Some(key) = self._parse_key(key) {
+            self.cache.apply_remove(key)?;
+        }
+        Ok(())
+    }
+
+    fn _flush(&self) -> Result<(), ServerError> {
+        match self.cache.apply_clear() {
+            Ok(_) | Err(CacheError::Unsupported(_)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The keys which are not UTF-8 are not replicated, as the cache cannot hold them.
+    fn _parse_key<'a>(&self, key: &'a [u8]) -> Option<&'a str> {
+        str::from_utf8(key).ok()
+    }
+
+    /// The `REPLCONF ACK <offset>` command acknowledging the commands applied.
+    fn _ack(&self) -> Vec<u8> {
+        encode_command(&["REPLCONF", "ACK", &self.offset.to_string()])
+    }
+
+    fn _send_command(&self, stream: &mut TcpStream, arguments: &[&str]) -> io::Result<()> {
+        stream.write_all(&encode_command(arguments))
+    }
+
+    /// Read the reply to a command of the handshake, failing unless it starts with the expected one.
+    fn _expect_reply(
+        &self,
+        stream: &mut TcpStream,
+        pending: &mut Vec<u8>,
+        expected: &str,
+    ) -> Result<(), ServerError> {
+        let reply: String = read_line(stream, pending)?;
+        match reply.starts_with(expected) {
+            true => Ok(()),
+            false => Err(protocol_error(format!(
+                "{} answered '{}' instead of '{}'",
+                self.primary, reply, expected
+            ))
+            .into()),
+        }
+    }
+}
+
+/// Encode a command as an array of bulk strings.
+fn encode_command(arguments: &[&str]) -> Vec<u8> {
+    let mut command: Vec<u8> = format!("*{}\r\n", arguments.len()).into_bytes();
+    for argument in arguments {
+        command.extend_from_slice(format!("${}\r\n{}\r\n", argument.len(), argument).as_bytes());
+    }
+    command
+}
+
+/// Read a line (without its CRLF) from the primary, keeping the data following it in `pending`.
+fn read_line(stream: &mut TcpStream, pending: &mut Vec<u8>) -> io::Result<String> {
+    let mut buffer: [u8; 1024] = [0; 1024];
+    loop {
+        if let Some(end) = memchr(b'\n', pending) {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line: &[u8] = line.strip_suffix(b"\r\n").unwrap_or(&line[..end]);
+            return Ok(String::from_utf8_lossy(line).into_owned());
+        }
+        if pending.len() > READ_SIZE {
+            return Err(protocol_error("line too long".to_string()));
+        }
+
+        match stream.read(&mut buffer)? {
+            0 => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+            n => pending.extend_from_slice(&buffer[..n]),
+        }
+    }
+}
+
+fn protocol_error(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,3125 @@
+use std::{
+    borrow::Cow,
+    io::{self, BufWriter, IoSlice, Write},
+    iter, str,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, log, warn};
+use memchr::memchr;
+
+use crate::{
+    acl::AclUser,
+    auth::{Auth, DEFAULT_USER},
+    blocking::BlockingPop,
+    buffers::ConnectionBuffers,
+    cache::{Bytes, PutCondition},
+    clients::ClientInfo,
+    cluster::{self, Cluster, SLOT_COUNT},
+    connection::{ClientHandler, ClientStream},
+    dataset::{DatasetFormat, Datasets, ImportMode},
+    embedded::{EmbeddedCache, Lookup, MemoryReport},
+    error::{CacheError, ServerError},
+    monitor::MONITOR_CHANNEL,
+    namespaces,
+    primary::{self, ReplicaLink},
+    protocol::{self, Tokens},
+    pubsub::{Message, Subscriptions},
+    runtime::RuntimeConfig,
+    script::Reply,
+    server::{
+        parse_integer, parse_number, parse_timeout, wait_popped, write_all_vectored,
+        DEFAULT_SCAN_COUNT,
+    },
+    shutdown::ShutdownHandle,
+    slowlog::{SlowLogEntry, DEFAULT_SLOWLOG_GET_COUNT},
+    snapshot::Snapshots,
+    stats::CommandProtocol,
+    transaction::{Transaction, Watch},
+    utils::Decimal,
+    values::{self, BloomFilter, RateDecision, ScoreBound, SetOperation, StreamId},
+};
+
+/// The longest bulk string (e.g. a value) accepted, like the longest command of the text protocol.
+const MAX_BULK_SIZE: usize = 1024 * 1024;
+
+/// The largest number of arguments of a command.
+const MAX_ARGUMENTS: usize = 1024;
+
+/// The outcome of parsing the pending data of a connection.
+pub(crate) enum Parsed<'a> {
+    Command(Vec<&'a [u8]>, usize), // A command with its arguments, and the number of bytes it takes
+    Incomplete,                    // More data is needed to parse the next command
+    Invalid(&'static str),         // The data does not follow the protocol
+}
+
+/// The settings of the RESP listener.
+pub(crate) struct RespOptions {
+    pub runtime: Arc<RuntimeConfig>, // The parameters the clients can change with `CONFIG`
+    pub auth: Arc<Auth>,             // Who may execute commands
+    pub snapshots: Option<Arc<Snapshots>>, // Where `SAVE` and `BGSAVE` save the cache to, if anywhere
+    pub datasets: Option<Arc<Datasets>>, // Where `EXPORT` and `IMPORT` write and read datasets, if anywhere
+    pub cluster: Option<Arc<Cluster>>, // The cluster the node belongs to, if any, redirecting the other keys
+    pub shutdown: ShutdownHandle,      // Stops the server on `SHUTDOWN`
+}
+
+/// A handler for a client speaking the Redis serialization protocol (RESP2, or RESP3 after `HELLO 3`).
+///
+/// The `GET`, `MGET`, `SET` (with `EX`, `PX`, `NX` or `XX`), `MSET`, `GETSET`, `GETDEL`, `DEL`, `EXISTS`,
+/// `RENAME`, `RENAMENX`, `EXPIRE`, `EXPIREAT`, `PEXPIREAT`, `EXPIRETIME`, `PEXPIRETIME`, `PERSIST`, `TOUCH`,
+/// `TTL`, `TYPE`, `INCR`, `DECR`, `INCRBY`, `DECRBY`, `APPEND`, `STRLEN`, `SETBIT`, `GETBIT`, `BITCOUNT`,
+/// `HSET`, `HGET`, `HDEL`, `HGETALL`, `LPUSH`, `RPUSH`, `LPOP`, `RPOP`, `BLPOP`, `BRPOP`, `LRANGE`, `SADD`,
+/// `SREM`, `SISMEMBER`, `SMEMBERS`, `SINTER`, `SUNION`, `SDIFF`, `ZADD`, `ZINCRBY`, `ZREM`, `ZSCORE`,
+/// `ZRANGE`, `ZRANGEBYSCORE`, `PFADD`, `PFCOUNT`, `PFMERGE`, `BF.RESERVE`, `BF.ADD`, `BF.EXISTS`,
+/// `RATELIMIT`, `XADD`, `XRANGE`, `XREAD`, `XLEN`, `MULTI`, `EXEC`, `DISCARD`, `WATCH`, `UNWATCH`, `EVAL`,
+/// `SUBSCRIBE`, `UNSUBSCRIBE`, `PUBLISH`, `MONITOR`, `KEYS`, `SCAN`, `RANDOMKEY`, `SAMPLE`, `DBSIZE`, `INFO`,
+/// `SLOWLOG`, `CONFIG`, `MEMORY`, `OBJECT`, `SAVE`, `BGSAVE`, `LASTSAVE`, `SHUTDOWN`, `BGREWRITEAOF`,
+/// `EXPORT`, `IMPORT`, `SYNC`, `PSYNC`, `REPLCONF`, `CLUSTER`, `FLUSHDB`, `FLUSHALL`, `SWAPDB`, `PING`, `ECHO`,
+/// `HELLO`, `SELECT` and `QUIT` commands are mapped onto the `EmbeddedCache` served, so that existing Redis
+/// clients can use the cache (`SELECT` switching between its databases, see `EmbeddedCache::with_databases`).
+/// `COMMAND` and `CLIENT`, which clients send when connecting (e.g. `COMMAND DOCS` for the hints of
+/// `redis-cli`, `CLIENT SETINFO` for the name of the library), are accepted and ignored, but for `CLIENT
+/// LIST` and `CLIENT KILL` (see `EmbeddedCache::clients`).
+/// Commands are arrays of bulk strings, or inline commands (i.e. lines) as typed in a terminal. If the
+/// server has a password or users, only `AUTH` and `QUIT` are accepted until the client authenticates,
+/// and then only the commands and keys the user is allowed (the connection commands always are).
+pub(crate) struct RespClientHandler {
+    address: String, // The address of the client (IP:Port). Used for logging purposes.
+    stream: BufWriter<ClientStream>, // The client connection, buffering the responses until the next flush
+    cache: Arc<EmbeddedCache>,       // A reference to the cache instance shared across all handlers
+    buffers: ConnectionBuffers, // The received data, in buffers sized to the traffic of the client
+    version: u8,                // The protocol version negotiated with `HELLO` (2 or 3)
+    closing: bool,              // Whether the client has sent `QUIT`
+    database: usize,            // The database selected with `SELECT` (0 by default)
+    auth: Arc<Auth>,            // Who may execute commands
+    user: Option<usize>, // The user the client has authenticated as with `AUTH` (see `Auth::user`), if any
+    subscriptions: Option<Subscriptions>, // The channels subscribed to with `SUBSCRIBE`, once it is sent
+    monitoring: bool, // Whether the client has sent `MONITOR`, its subscription being the monitor feed
+    answered_error: bool, // Whether the command being executed was answered with an error
+    transaction: Option<Transaction<Vec<Vec<u8>>>>, // The commands queued since `MULTI`, until `EXEC`
+    watch: Option<Watch>, // The keys watched with `WATCH`, until the next `EXEC` or `DISCARD`
+    blocking: bool, // Whether the command being executed may wait (i.e. BLPOP or BRPOP outside of EXEC)
+    snapshots: Option<Arc<Snapshots>>, // Where `SAVE` and `BGSAVE` save the cache to, if anywhere
+    datasets: Option<Arc<Datasets>>, // Where `EXPORT` and `IMPORT` write and read datasets, if anywhere
+    replica: Option<ReplicaLink>, // The stream of the writes, once the client has attached as a replica
+    cluster: Option<Arc<Cluster>>, // The cluster the node belongs to, if any, redirecting the other keys
+    shutdown: ShutdownHandle,      // Stops the server on `SHUTDOWN`
+    runtime: Arc<RuntimeConfig>,   // The parameters the clients can change with `CONFIG`
+}
+
+impl ClientHandler for RespClientHandler {
+    const PROTOCOL: &'static str = "RESP";
+    type Options = RespOptions;
+
+    fn new(
+        stream: ClientStream,
+        cache: Arc<EmbeddedCache>,
+        buffers: ConnectionBuffers,
+        options: &Arc<RespOptions>,
+    ) -> RespClientHandler {
+        RespClientHandler {
+            address: stream.peer_address(),
+            stream: BufWriter::new(stream),
+            cache,
+            buffers,
+            version: 2,
+            closing: false,
+            database: 0,
+            auth: Arc::clone(&options.auth),
+            user: None,
+            subscriptions: None,
+            monitoring: false,
+            answered_error: false,
+            transaction: None,
+            watch: None,
+            blocking: false,
+            snapshots: options.snapshots.clone(),
+            datasets: options.datasets.clone(),
+            replica: None,
+            cluster: options.cluster.clone(),
+            shutdown: options.shutdown.clone(),
+            runtime: Arc::clone(&options.runtime),
+        }
+    }
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn connection(&mut self) -> (&ClientStream, &mut ConnectionBuffers) {
+        (self.stream.get_ref(), &mut self.buffers)
+    }
+
+    fn handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        self._handle_pending(pending)
+    }
+
+    fn reject_overflow(&mut self) {
+        let _ = self._write_error("ERR out of buffer memory");
+        let _ = self.stream.flush();
+    }
+
+    fn reject_too_many_clients(&mut self) {
+        let _ = self._write_error("ERR max number of clients reached");
+        let _ = self.stream.flush();
+    }
+
+    fn reject_timeout(&mut self) {
+        let _ = self._write_error("ERR command timeout exceeded");
+        let _ = self.stream.flush();
+    }
+
+    fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    fn is_listening(&self) -> bool {
+        self.subscriptions
+            .as_ref()
+            .is_some_and(|subscriptions| !subscriptions.channels().is_empty())
+    }
+}
+
+impl RespClientHandler {
+    /// Handle the complete commands of the given pending data, draining them.
+    fn _handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        let mut start: usize = 0;
+
+        // The messages of the channels subscribed to are not pushed in the middle of the responses
+        let writes: Option<Arc<Mutex<()>>> = self.subscriptions.as_ref().map(Subscriptions::writes);
+        let _guard = match &writes {
+            Some(writes) => Some(writes.lock().map_err(CacheError::from)?),
+            None => None,
+        };
+
+        while !self.closing {
+            match parse(&pending[start..]) {
+                Parsed::Command(arguments, length) => {
+                    self._handle_command(&arguments)?;
+                    start += length;
+                }
+                Parsed::Incomplete => break,
+                Parsed::Invalid(reason) => {
+                    warn!("Protocol error from {}: {}", self.address, reason);
+                    self._write_error(&format!("ERR Protocol error: {}", reason))?;
+                    self.stream.flush()?;
+                    return Err(ServerError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        reason,
+                    )));
+                }
+            }
+        }
+        pending.drain(..start);
+
+        self.stream.flush()?;
+
+        // The messages are pushed once the replies to the first subscriptions are written
+        if let Some(subscriptions) = &mut self.subscriptions {
+            let encode: fn(&Message) -> Vec<u8> = match (self.monitoring, self.version) {
+                (true, _) => encode_monitored_command,
+                (false, 3) => encode_resp3_message,
+                (false, _) => encode_resp2_message,
+            };
+            subscriptions.forward(self.stream.get_ref().try_clone()?, &self.address, encode);
+        }
+        Ok(())
+    }
+
+    /// Handle a command, reporting cache errors to the client.
+    fn _handle_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((name, arguments)) = arguments.split_first() else {
+            return Ok(()); // An empty command is ignored, like an empty line
+        };
+        if self.replica.is_some() {
+            // A replica only acknowledges its offset (`REPLCONF ACK`), and the connection carries the
+            // stream of the writes, which no reply may come in the middle of
+            return Ok(());
+        }
+        debug!(
+            "Received command from {} -> {}",
+            self.address,
+            String::from_utf8_lossy(name)
+        );
+
+        let name: String = String::from_utf8_lossy(name).to_ascii_uppercase();
+        if self.user.is_none() && self.auth.is_required() && name != "AUTH" && name != "QUIT" {
+            warn!("Unauthenticated command from {}", self.address);
+            return self._write_error("NOAUTH Authentication required.");
+        }
+        if let Some(reason) = self._denied(&name, arguments) {
+            warn!("Command from {} denied: {}", self.address, reason);
+            return self._write_error(&format!("NOPERM {}", reason));
+        }
+        if let Some(redirect) = self._cluster_redirect(&name, arguments) {
+            debug!("Redirecting {} from {}: {}", name, self.address, redirect);
+            return self._write_error(&redirect);
+        }
+        if self.monitoring && name != "QUIT" {
+            return self._write_error(&format!(
+                "ERR Can't execute '{}': only QUIT is allowed while monitoring",
+                name.to_lowercase()
+            ));
+        }
+        let subscribed: bool = self.is_listening() && self.version != 3; // RESP3 has push messages
+        if subscribed && !matches!(name.as_str(), "SUBSCRIBE" | "UNSUBSCRIBE" | "PING" | "QUIT") {
+            return self._write_error(&format!(
+                "ERR Can't execute '{}': only SUBSCRIBE / UNSUBSCRIBE / PING / QUIT are allowed in this context",
+                name.to_lowercase()
+            ));
+        }
+
+        // The commands sent after MULTI are queued until EXEC, and the others may not interleave with the
+        // transactions of the other clients
+        match (name.as_str(), &mut self.transaction) {
+            ("MULTI", _) => return self._handle_multi_command(arguments),
+            ("EXEC", _) => return self._handle_exec_command(arguments),
+            ("DISCARD", _) => return self._handle_discard_command(arguments),
+            ("WATCH", Some(_)) => {
+                return self._write_error("ERR WATCH inside MULTI is not allowed")
+            }
+            ("QUIT", _) | (_, None) => {}
+            (
+                "HELLO" | "SUBSCRIBE" | "UNSUBSCRIBE" | "MONITOR" | "SYNC" | "PSYNC",
+                Some(transaction),
+            ) => {
+                transaction.fail();
+                return self._write_error(&format!(
+                    "ERR Command '{}' not allowed inside a transaction",
+                    name.to_lowercase()
+                ));
+            }
+            (_, Some(transaction)) => {
+                let mut queued: Vec<Vec<u8>> = vec![name.clone().into_bytes()];
+                queued.extend(arguments.iter().map(|argument| argument.to_vec()));
+                return match transaction.queue(queued) {
+                    true => self._write_simple("QUEUED"),
+                    false => self._write_error("ERR too many commands queued in the transaction"),
+                };
+            }
+        }
+        let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+        if name == "BLPOP" || name == "BRPOP" {
+            // A client waiting for a value does not hold up the transactions of the other clients meanwhile
+            // (see `_handle_blocking_pop_command`)
+            self.blocking = true;
+            let result: Result<(), ServerError> = self._execute_command(&name, arguments);
+            self.blocking = false;
+            return result;
+        }
+        if matches!(
+            name.as_str(),
+            "EVAL" | "IMPORT" | "BGREWRITEAOF" | "SYNC" | "PSYNC"
+        ) {
+            // A script executes several commands, which those of the other clients may not come in between,
+            // as does an import, and a rewrite of the append-only log or a replica starts with no command
+            // half applied
+            let _guard = cache.transaction_lock()?;
+            return self._execute_command(&name, arguments);
+        }
+        let _guard = cache.command_lock()?;
+        self._execute_command(&name, arguments)
+    }
+
+    /// Execute a command (whose name is in uppercase), answering the cache errors to the client.
+    fn _execute_command(&mut self, name: &str, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let started: Instant = Instant::now();
+        self.answered_error = false;
+        let subscribed: bool = self.is_listening() && self.version != 3;
+        let result: Result<(), ServerError> = match name {
+            "GET" => self._handle_get_command(arguments),
+            "MGET" => self._handle_mget_command(arguments),
+            "SET" => self._handle_set_command(arguments),
+            "MSET" => self._handle_mset_command(arguments),
+            "DEL" => self._handle_del_command(arguments),
+            "GETSET" => self._handle_get_set_command(arguments),
+            "GETDEL" => self._handle_get_del_command(arguments),
+            "EXISTS" => self._handle_exists_command(arguments),
+            "TYPE" => self._handle_type_command(arguments),
+            "KEYS" => self._handle_keys_command(arguments),
+            "SCAN" => self._handle_scan_command(arguments),
+            "RANDOMKEY" => self._handle_random_key_command(arguments),
+            "SAMPLE" => self._handle_sample_command(arguments),
+            "RENAME" => self._handle_rename_command(arguments, "rename", false),
+            "RENAMENX" => self._handle_rename_command(arguments, "renamenx", true),
+            "EXPIRE" => self._handle_expire_command(arguments),
+            "EXPIREAT" => self._handle_expire_at_command(arguments, "expireat", 1000),
+            "PEXPIREAT" => self._handle_expire_at_command(arguments, "pexpireat", 1),
+            "EXPIRETIME" => self._handle_expire_time_command(arguments, "expiretime", 1000),
+            "PEXPIRETIME" => self._handle_expire_time_command(arguments, "pexpiretime", 1),
+            "PERSIST" => self._handle_persist_command(arguments),
+            "TOUCH" => self._handle_touch_command(arguments),
+            "TTL" => self._handle_ttl_command(arguments),
+            "INCR" => self._handle_incr_command(arguments, "incr", false),
+            "DECR" => self._handle_incr_command(arguments, "decr", true),
+            "INCRBY" => self._handle_incr_command(arguments, "incrby", false),
+            "DECRBY" => self._handle_incr_command(arguments, "decrby", true),
+            "APPEND" => self._handle_append_command(arguments),
+            "STRLEN" => self._handle_strlen_command(arguments),
+            "SETBIT" => self._handle_setbit_command(arguments),
+            "GETBIT" => self._handle_getbit_command(arguments),
+            "BITCOUNT" => self._handle_bitcount_command(arguments),
+            "HSET" => self._handle_hset_command(arguments),
+            "HGET" => self._handle_hget_command(arguments),
+            "HDEL" => self._handle_hdel_command(arguments),
+            "HGETALL" => self._handle_hgetall_command(arguments),
+            "LPUSH" => self._handle_push_command(arguments, "lpush", true),
+            "RPUSH" => self._handle_push_command(arguments, "rpush", false),
+            "LPOP" => self._handle_pop_command(arguments, "lpop", true),
+            "RPOP" => self._handle_pop_command(arguments, "rpop", false),
+            "BLPOP" => self._handle_blocking_pop_command(arguments, "blpop", true),
+            "BRPOP" => self._handle_blocking_pop_command(arguments, "brpop", false),
+            "LRANGE" => self._handle_lrange_command(arguments),
+            "SADD" => self._handle_sadd_command(arguments),
+            "SREM" => self._handle_srem_command(arguments),
+            "SISMEMBER" => self._handle_sismember_command(arguments),
+            "SMEMBERS" => self._handle_smembers_command(arguments),
+            "SINTER" => {
+                self._handle_set_combine_command(arguments, "sinter", SetOperation::Intersection)
+            }
+            "SUNION" => self._handle_set_combine_command(arguments, "sunion", SetOperation::Union),
+            "SDIFF" => {
+                self._handle_set_combine_command(arguments, "sdiff", SetOperation::Difference)
+            }
+            "ZADD" => self._handle_zadd_command(arguments),
+            "PFADD" => self._handle_pfadd_command(arguments),
+            "PFCOUNT" => self._handle_pfcount_command(arguments),
+            "PFMERGE" => self._handle_pfmerge_command(arguments),
+            "BF.RESERVE" => self._handle_bf_reserve_command(arguments),
+            "BF.ADD" => self._handle_bf_element_command(arguments, "bf.add", true),
+            "BF.EXISTS" => self._handle_bf_element_command(arguments, "bf.exists", false),
+            "RATELIMIT" => self._handle_ratelimit_command(arguments),
+            "ZINCRBY" => self._handle_zincrby_command(arguments),
+            "ZREM" => self._handle_zrem_command(arguments),
+            "ZSCORE" => self._handle_zscore_command(arguments),
+            "ZRANGE" => self._handle_zrange_command(arguments, "zrange", false),
+            "ZRANGEBYSCORE" => self._handle_zrange_command(arguments, "zrangebyscore", true),
+            "XADD" => self._handle_xadd_command(arguments),
+            "XRANGE" => self._handle_xrange_command(arguments),
+            "XREAD" => self._handle_xread_command(arguments),
+            "XLEN" => self._handle_xlen_command(arguments),
+            "DBSIZE" => self._handle_dbsize_command(arguments),
+            "INFO" => self._handle_info_command(arguments),
+            "SLOWLOG" => self._handle_slowlog_command(arguments),
+            "CONFIG" => self._handle_config_command(arguments),
+            "MEMORY" => self._handle_memory_command(arguments),
+            "OBJECT" => self._handle_object_command(arguments),
+            "SAVE" => self._handle_save_command(arguments),
+            "BGSAVE" => self._handle_bgsave_command(arguments),
+            "LASTSAVE" => self._handle_lastsave_command(arguments),
+            "SHUTDOWN" => self._handle_shutdown_command(arguments),
+            "BGREWRITEAOF" => self._handle_bgrewriteaof_command(arguments),
+            "EXPORT" => self._handle_export_command(arguments),
+            "IMPORT" => self._handle_import_command(arguments),
+            "SYNC" => self._handle_sync_command(arguments, "sync", 0),
+            "PSYNC" => self._handle_sync_command(arguments, "psync", 2),
+            "REPLCONF" => self._handle_replconf_command(arguments),
+            "FLUSHDB" => self._handle_flushdb_command(arguments),
+            "FLUSHALL" => self._handle_flushall_command(arguments),
+            "SWAPDB" => self._handle_swapdb_command(arguments),
+            "PING" if subscribed => self._handle_subscribed_ping_command(arguments),
+            "PING" => self._handle_ping_command(arguments),
+            "SUBSCRIBE" => self._handle_subscribe_command(arguments),
+            "UNSUBSCRIBE" => self._handle_unsubscribe_command(arguments),
+            "PUBLISH" => self._handle_publish_command(arguments),
+            "MONITOR" => self._handle_monitor_command(arguments),
+            "WATCH" => self._handle_watch_command(arguments),
+            "EVAL" => self._handle_eval_command(arguments),
+            "UNWATCH" => match arguments {
+                [] => {
+                    self.watch = None;
+                    self._write_simple("OK")
+                }
+                _ => self._write_arity_error("unwatch"),
+            },
+            "ECHO" => match arguments {
+                [message] => self._write_bulk(message),
+                _ => self._write_arity_error("echo"),
+            },
+            "COMMAND" => self._write_array_header(0),
+            "CLIENT" => self._handle_client_command(arguments),
+            "CLUSTER" => self._handle_cluster_command(arguments),
+            "HELLO" => self._handle_hello_command(arguments),
+            "SELECT" => self._handle_select_command(arguments),
+            "AUTH" => self._handle_auth_command(arguments),
+            "QUIT" => {
+                self.closing = true;
+                self._write_simple("OK")
+            }
+            _ => {
+                warn!("Unknown command {} from {}", name, self.address);
+                self._write_error(&format!("ERR unknown command '{}'", name))
+            }
+        };
+        let elapsed: Duration = started.elapsed();
+        let user: Option<&str> = self
+            .user
+            .and_then(|index| self.auth.user(index))
+            .map(|(name, _)| name.as_str());
+        let failed: bool = result.is_err() || self.answered_error;
+        self.cache.record_command(
+            CommandProtocol::Resp,
+            elapsed,
+            &self.address,
+            user,
+            failed,
+            || {
+                iter::once(name.as_bytes())
+                    .chain(arguments.iter().copied())
+                    .collect()
+            },
+        );
+
+        match result {
+            Err(ServerError::Cache(e)) => {
+                log!(
+                    e.log_level(),
+                    "Failed to execute the command from {}: {}",
+                    self.address,
+                    e
+                );
+                match e {
+                    CacheError::OutOfMemory => {
+                        self._write_error("OOM command not allowed when used memory > 'maxmemory'")
+                    }
+                    CacheError::WrongType => self._write_error(
+                        "WRONGTYPE Operation against a key holding the wrong kind of value",
+                    ),
+                    CacheError::NotANumber => {
+                        self._write_error("ERR resulting score is not a number (NaN)")
+                    }
+                    CacheError::ReadOnly => {
+                        self._write_error("READONLY You can't write against a read only cache.")
+                    }
+                    e => self._write_error(&format!("ERR {}", e)),
+                }
+            }
+            other => other,
+        }
+    }
+
+    /// Why the authenticated user may not execute the given command, if they may not: every key of its
+    /// arguments has to be allowed, along with the command itself.
+    fn _denied(&self, name: &str, arguments: &[&[u8]]) -> Option<String> {
+        let (_, user) = self.user.and_then(|index| self.auth.user(index))?;
+        // `CLIENT LIST` and `CLIENT KILL` are administrative, unlike the other `CLIENT` subcommands
+        let administrative: bool = name == "CLIENT"
+            && arguments.first().is_some_and(|subcommand| {
+                subcommand.eq_ignore_ascii_case(b"LIST") || subcommand.eq_ignore_ascii_case(b"KILL")
+            });
+        if !administrative
+            && matches!(
+                name,
+                "AUTH" | "QUIT" | "PING" | "ECHO" | "COMMAND" | "CLIENT" | "HELLO" | "SELECT"
+            )
+        {
+            return None;
+        }
+
+        if !user.allows_command(name.as_bytes()) {
+            return Some(format!(
+                "this user has no permissions to run the '{}' command",
+                name.to_lowercase()
+            ));
+        }
+        if matches!(
+            name,
+            "FLUSHDB" | "FLUSHALL" | "SWAPDB" | "MONITOR" | "EXPORT" | "IMPORT" | "SYNC" | "PSYNC"
+        ) && !user.allows_all_keys()
+        {
+            return Some("this user has no permissions to access every key".to_string());
+        }
+        let keys: Vec<&[u8]> = command_keys(name, arguments);
+        keys.iter().find(|key| !user.allows_key(key)).map(|key| {
+            format!(
+                "this user has no permissions to access the key '{}'",
+                String::from_utf8_lossy(key)
+            )
+        })
+    }
+
+    /// The redirection to answer a command on keys whose hash slot another node of the cluster owns (see
+    /// `Cluster::redirect`), if the node belongs to a cluster.
+    fn _cluster_redirect(&self, name: &str, arguments: &[&[u8]]) -> Option<String> {
+        let cluster: &Cluster = self.cluster.as_deref()?;
+        cluster.redirect(&command_keys(name, arguments))
+    }
+
+    /// Handle `CLUSTER SLOTS`, answering which node owns each range of hash slots (as `[first, last, [host,
+    /// port]]` arrays), `CLUSTER KEYSLOT key`, answering the hash slot of a key, and `CLUSTER INFO`.
+    fn _handle_cluster_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some(cluster) = self.cluster.clone() else {
+            return self._write_error("ERR This instance has cluster support disabled");
+        };
+        let subcommand: String = arguments
+            .first()
+            .map(|subcommand| String::from_utf8_lossy(subcommand).to_ascii_uppercase())
+            .unwrap_or_default();
+
+        match (subcommand.as_str(), arguments) {
+            ("SLOTS", [_]) => {
+                let ranges: Vec<(u16, u16, &str)> = cluster.slots().ranges();
+                self._write_array_header(ranges.len())?;
+                for (first, last, node) in ranges {
+                    let (host, port) = node.rsplit_once(':').unwrap_or((node, "0"));
+                    self._write_array_header(3)?;
+                    self._write_integer(first as i64)?;
+                    self._write_integer(last as i64)?;
+                    self._write_array_header(2)?;
+                    self._write_bulk(host.as_bytes())?;
+                    self._write_integer(port.parse::<i64>().unwrap_or(0))?;
+                }
+                Ok(())
+            }
+            ("KEYSLOT", [_, key]) => self._write_integer(cluster::key_slot(key) as i64),
+            ("INFO", [_]) => {
+                // Every slot is assigned (see `SlotMap`), so the cluster is always up
+                let nodes: usize = cluster.slots().nodes().len();
+                let fields: [(&str, usize); 6] = [
+                    ("cluster_enabled", 1),
+                    ("cluster_slots_assigned", SLOT_COUNT),
+                    ("cluster_slots_ok", SLOT_COUNT),
+                    ("cluster_known_nodes", nodes),
+                    ("cluster_size", nodes),
+                    ("myself_slots", cluster.owned_slots()),
+                ];
+                let mut info: String = "cluster_state:ok\r\n".to_string();
+                for (name, value) in fields {
+                    info.push_str(&format!("{}:{}\r\n", name, value));
+                }
+                self._write_bulk(info.as_bytes())
+            }
+            ("SLOTS" | "KEYSLOT" | "INFO" | "", _) => self._write_arity_error("cluster"),
+            _ => self._write_error(&format!(
+                "ERR unknown subcommand '{}'. Try CLUSTER SLOTS, CLUSTER KEYSLOT or CLUSTER INFO.",
+                subcommand.to_lowercase()
+            )),
+        }
+    }
+
+    /// Handle `AUTH password` for the default user, or `AUTH user password` (as sent by the clients of
+    /// Redis 6 and later, e.g. with the `default` user).
+    fn _handle_auth_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (name, password): (&[u8], &[u8]) = match arguments {
+            [password] => (DEFAULT_USER.as_bytes(), password),
+            [name, password] => (name, password),
+            _ => return self._write_arity_error("auth"),
+        };
+
+        if !self.auth.is_required() {
+            return self._write_error("ERR AUTH called without any password configured");
+        }
+        let Some(user) = self.auth.authenticate(name, password) else {
+            warn!(
+                "Failed authentication of {} from {}",
+                String::from_utf8_lossy(name),
+                self.address
+            );
+            return self._write_error("WRONGPASS invalid username-password pair");
+        };
+
+        self.user = Some(user);
+        self._write_simple("OK")
+    }
+
+    /// Handle `GET key`. Stale values are returned as fresh ones, and keys cached as absent as missing.
+    fn _handle_get_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("get");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        match self.cache.get(key)? {
+            Lookup::Fresh(value) | Lookup::Stale(value) => self._write_bulk(&value),
+            Lookup::Negative | Lookup::Missing | Lookup::Recompute => self._write_null(),
+        }
+    }
+
+    /// Handle `MGET key [key ...]`, replying with an array of the values (null for the keys without one).
+    fn _handle_mget_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("mget");
+        }
+
+        // The keys which are not UTF-8 have no value
+        let keys: Vec<Option<Cow<str>>> =
+            arguments.iter().map(|key| self._parse_key(key)).collect();
+        let valid: Vec<&str> = keys.iter().flatten().map(|key| key.as_ref()).collect();
+        let mut lookups = self.cache.get_many(&valid)?.into_iter();
+
+        self._write_array_header(arguments.len())?;
+        for key in keys {
+            let lookup: Lookup = match key {
+                Some(_) => lookups.next().unwrap_or(Lookup::Missing),
+                None => Lookup::Missing,
+            };
+            match lookup {
+                Lookup::Fresh(value) | Lookup::Stale(value) => self._write_bulk(&value)?,
+                Lookup::Negative | Lookup::Missing | Lookup::Recompute => self._write_null()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle `MSET key value [key value ...]`, putting the values (without a TTL) as a single write.
+    fn _handle_mset_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+            return self._write_arity_error("mset");
+        }
+
+        let mut entries: Vec<(Cow<str>, Bytes)> = Vec::with_capacity(arguments.len() / 2);
+        for pair in arguments.chunks_exact(2) {
+            let Some(key) = self._parse_key(pair[0]) else {
+                return self._write_error("ERR key must be valid UTF-8");
+            };
+            entries.push((key, Bytes::from(pair[1])));
+        }
+        let entries: Vec<(&str, Bytes)> = entries
+            .iter()
+            .map(|(key, value)| (key.as_ref(), value.clone()))
+            .collect();
+        self.cache.put_many(entries)?;
+        self._write_simple("OK")
+    }
+
+    /// Handle `SET key value [EX seconds | PX milliseconds] [NX | XX]`, replying with a null reply if the
+    /// condition declines the value.
+    fn _handle_set_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, value, options @ ..] = arguments else {
+            return self._write_arity_error("set");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let mut maybe_ttl: Option<u64> = None;
+        let mut maybe_condition: Option<PutCondition> = None;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let option: String = String::from_utf8_lossy(option).to_ascii_uppercase();
+            match option.as_str() {
+                "EX" | "PX" if maybe_ttl.is_none() => {
+                    let ttl: Option<u64> = options.next().and_then(|ttl| parse_number(ttl));
+                    // TTLs are kept in seconds, so milliseconds are rounded up
+                    maybe_ttl = match (option.as_str(), ttl) {
+                        ("EX", Some(seconds)) if seconds > 0 => Some(seconds),
+                        ("PX", Some(millis)) if millis > 0 => Some(millis.div_ceil(1000)),
+                        _ => return self._write_error("ERR invalid expire time in 'set' command"),
+                    };
+                }
+                "NX" if maybe_condition != Some(PutCondition::IfPresent) => {
+                    maybe_condition = Some(PutCondition::IfAbsent);
+                }
+                "XX" if maybe_condition != Some(PutCondition::IfAbsent) => {
+                    maybe_condition = Some(PutCondition::IfPresent);
+                }
+                _ => return self._write_error("ERR syntax error"),
+            }
+        }
+
+        let value: Bytes = (*value).into();
+        match maybe_condition {
+            Some(condition) => match self.cache.put_if(key, value, maybe_ttl, None, condition)? {
+                true => self._write_simple("OK"),
+                false => self._write_null(),
+            },
+            None => {
+                self.cache.put(key, value, maybe_ttl, None)?;
+                self._write_simple("OK")
+            }
+        }
+    }
+
+    /// Handle `DEL key [key ...]`, replying with the number of values removed.
+    fn _handle_del_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("del");
+        }
+
+        let mut removed: i64 = 0;
+        for key in arguments {
+            if let Some(key) = self._parse_key(key) {
+                removed += i64::from(self.cache.remove(&key)?.is_some());
+            }
+        }
+        self._write_integer(removed)
+    }
+
+    /// Handle `EXISTS key [key ...]`, replying with the number of keys holding a value (a key given twice
+    /// being counted twice, as in Redis).
+    fn _handle_exists_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("exists");
+        }
+
+        // The keys which are not UTF-8 have no value
+        let keys: Vec<Cow<str>> = arguments
+            .iter()
+            .filter_map(|key| self._parse_key(key))
+            .collect();
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        let existing: usize = self.cache.exists(&keys)?;
+        self._write_integer(existing as i64)
+    }
+
+    /// Handle `TYPE key`, replying with the type of the value of the key, or `none` if it has no value.
+    fn _handle_type_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("type");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let name: &str = self
+            .cache
+            .value_type(key)?
+            .map_or("none", |kind| kind.name());
+        self._write_simple(name)
+    }
+
+    /// Handle `KEYS pattern`, replying with an array of the keys matching the glob pattern (among the keys
+    /// the user may access).
+    fn _handle_keys_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [pattern] = arguments else {
+            return self._write_arity_error("keys");
+        };
+        let Some(pattern) = self._parse_key(pattern) else {
+            return self._write_error("ERR pattern must be valid UTF-8");
+        };
+
+        let mut keys: Vec<String> = self.cache.keys(&pattern)?;
+        self._retain_accessible(&mut keys);
+        self._write_array_header(keys.len())?;
+        for key in keys {
+            self._write_bulk(namespaces::split_database(&key).1.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Handle `RANDOMKEY`, replying with a key picked at random (among the keys the user may access), or null
+    /// if there is none.
+    fn _handle_random_key_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("randomkey");
+        }
+
+        let pattern: Cow<str> = self._database_key("*");
+        let keys: Vec<String> = self
+            .cache
+            .sample_keys(&pattern, 1, &|key| self._is_accessible(key))?;
+        match keys.first() {
+            Some(key) => self._write_bulk(namespaces::split_database(key).1.as_bytes()),
+            None => self._write_null(),
+        }
+    }
+
+    /// Handle `SAMPLE count`, replying with an array of that many distinct keys picked at random (among the
+    /// keys the user may access), or all of them if there are fewer.
+    fn _handle_sample_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [count] = arguments else {
+            return self._write_arity_error("sample");
+        };
+        let Some(count) = parse_number(count).filter(|count| *count > 0) else {
+            return self._write_error("ERR value is out of range, must be positive");
+        };
+
+        let pattern: Cow<str> = self._database_key("*");
+        let keys: Vec<String> = self
+            .cache
+            .sample_keys(&pattern, count as usize, &|key| self._is_accessible(key))?;
+        self._write_array_header(keys.len())?;
+        for key in keys {
+            self._write_bulk(namespaces::split_database(&key).1.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Handle `SCAN cursor [MATCH pattern] [COUNT count]`, replying with the cursor of the next call (0 once
+    /// every key is examined) and an array of the keys of the batch matching the pattern.
+    fn _handle_scan_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [cursor, options @ ..] = arguments else {
+            return self._write_arity_error("scan");
+        };
+        let Some(cursor) = parse_number(cursor) else {
+            return self._write_error("ERR invalid cursor");
+        };
+
+        let mut pattern: Cow<str> = self._database_key("*");
+        let mut count: usize = DEFAULT_SCAN_COUNT;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let option: String = String::from_utf8_lossy(option).to_ascii_uppercase();
+            let Some(value) = options.next() else {
+                return self._write_error("ERR syntax error");
+            };
+            match option.as_str() {
+                "MATCH" => match self._parse_key(value) {
+                    Some(value) => pattern = value,
+                    None => return self._write_error("ERR pattern must be valid UTF-8"),
+                },
+                "COUNT" => match parse_number(value) {
+                    Some(number) if number > 0 => count = number as usize,
+                    Some(_) => return self._write_error("ERR syntax error"),
+                    None => {
+                        return self._write_error("ERR value is not an integer or out of range")
+                    }
+                },
+                _ => return self._write_error("ERR syntax error"),
+            }
+        }
+
+        let (next, mut keys): (u64, Vec<String>) = self.cache.scan(cursor, &pattern, count)?;
+        self._retain_accessible(&mut keys);
+        self._write_array_header(2)?;
+        self._write_bulk(next.to_string().as_bytes())?;
+        self._write_array_header(keys.len())?;
+        for key in keys {
+            self._write_bulk(namespaces::split_database(&key).1.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Handle `DBSIZE`, replying with the number of keys with a value in the selected database.
+    fn _handle_dbsize_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("dbsize");
+        }
+        let keys: usize = match self.cache.databases() {
+            1 => self.cache.len()?,
+            _ => {
+                let prefix: String = namespaces::database_prefix(self.database);
+                self.cache.keyspace_stats(&prefix)?.keys
+            }
+        };
+        self._write_integer(keys as i64)
+    }
+
+    /// Handle `INFO` (with any section), answering the statistics of the server (see `EmbeddedCache::info`)
+    /// as `name:value` lines, under a single `# Stats` section.
+    fn _handle_info_command(&mut self, _arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let mut info: String = "# Stats\r\n".to_string();
+        for (name, value) in self.cache.info()? {
+            info.push_str(&format!("{}:{}\r\n", name, value));
+        }
+        self._write_bulk(info.as_bytes())
+    }
+
+    /// Handle `SLOWLOG GET [count]`, replying with the last commands which took longer than the threshold of
+    /// the slow log (10 unless told otherwise), newest first, as Redis does: arrays of the id, the time (in
+    /// seconds since the epoch), the duration (in microseconds), the arguments, the client address and its
+    /// name (always empty). Also handles `SLOWLOG LEN` and `SLOWLOG RESET`.
+    fn _handle_slowlog_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((subcommand, arguments)) = arguments.split_first() else {
+            return self._write_arity_error("slowlog");
+        };
+
+        match (
+            String::from_utf8_lossy(subcommand)
+                .to_ascii_uppercase()
+                .as_str(),
+            arguments,
+        ) {
+            ("GET", [] | [_]) => {
+                let count: usize = match arguments.first() {
+                    Some(count) => match parse_number(count) {
+                        Some(count) => count as usize,
+                        None => {
+                            return self._write_error("ERR value is not an integer or out of range")
+                        }
+                    },
+                    None => DEFAULT_SLOWLOG_GET_COUNT,
+                };
+                let entries: Vec<SlowLogEntry> = self.cache.slow_log().get(count)?;
+                self._write_array_header(entries.len())?;
+                for entry in entries {
+                    self._write_array_header(6)?;
+                    self._write_integer(entry.id as i64)?;
+                    self._write_integer((entry.timestamp / 1000) as i64)?;
+                    self._write_integer(entry.duration.as_micros() as i64)?;
+                    self._write_array_header(entry.arguments.len())?;
+                    for argument in &entry.arguments {
+                        self._write_bulk(argument.as_bytes())?;
+                    }
+                    self._write_bulk(entry.client.as_bytes())?;
+                    self._write_bulk(b"")?;
+                }
+                Ok(())
+            }
+            ("LEN", []) => {
+                let len: usize = self.cache.slow_log().len()?;
+                self._write_integer(len as i64)
+            }
+            ("RESET", []) => {
+                self.cache.slow_log().reset()?;
+                self._write_simple("OK")
+            }
+            ("GET" | "LEN" | "RESET", _) => self._write_arity_error("slowlog"),
+            (other, _) => self._write_error(&format!("ERR unknown subcommand 'SLOWLOG {}'", other)),
+        }
+    }
+
+    /// Handle `MEMORY USAGE key [SAMPLES count]`, replying with the bytes taken by the key, its value and
+    /// their bookkeeping (the samples being ignored, as the sizes are not sampled), or a null if it has no
+    /// value, and `MEMORY STATS`, replying with a map of `keys.count`, `dataset.bytes`, `maxmemory`,
+    /// `evicted_keys` and `keys.bytes-per-key`.
+    fn _handle_memory_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((subcommand, arguments)) = arguments.split_first() else {
+            return self._write_arity_error("memory");
+        };
+
+        match (
+            String::from_utf8_lossy(subcommand)
+                .to_ascii_uppercase()
+                .as_str(),
+            arguments,
+        ) {
+            ("USAGE", [key] | [key, _, _]) => {
+                if let [_, option, count] = arguments {
+                    if !option.eq_ignore_ascii_case(b"SAMPLES") {
+                        return self._write_error("ERR syntax error");
+                    }
+                    if parse_number(count).is_none() {
+                        return self._write_error("ERR value is not an integer or out of range");
+                    }
+                }
+                let Some(key) = self._parse_key(key) else {
+                    return self._write_error("ERR key must be valid UTF-8");
+                };
+                match self.cache.memory_usage(&key)? {
+                    Some(bytes) => self._write_integer(bytes as i64),
+                    None => self._write_null(),
+                }
+            }
+            ("STATS", []) => {
+                let report: MemoryReport = self.cache.memory_report()?;
+                let fields: [(&str, usize); 5] = [
+                    ("keys.count", report.keys),
+                    ("dataset.bytes", report.used_bytes),
+                    ("maxmemory", report.max_bytes.unwrap_or(0)),
+                    ("evicted_keys", report.evictions as usize),
+                    ("keys.bytes-per-key", report.bytes_per_key()),
+                ];
+                self._write_map_header(fields.len())?;
+                for (name, value) in fields {
+                    self._write_bulk(name.as_bytes())?;
+                    self._write_integer(value as i64)?;
+                }
+                Ok(())
+            }
+            ("USAGE" | "STATS", _) => self._write_arity_error("memory"),
+            (other, _) => self._write_error(&format!("ERR unknown subcommand 'MEMORY {}'", other)),
+        }
+    }
+
+    /// Handle `OBJECT IDLETIME key`, replying with how long ago the key was last read (or written, if it
+    /// never was) in seconds, and `OBJECT FREQ key`, replying with how many times it was read, or a null if
+    /// it has no value. The reads of the keys must be tracked (see `EmbeddedCache::key_access`).
+    fn _handle_object_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((subcommand, arguments)) = arguments.split_first() else {
+            return self._write_arity_error("object");
+        };
+        let subcommand: String = String::from_utf8_lossy(subcommand).to_ascii_uppercase();
+        if subcommand != "IDLETIME" && subcommand != "FREQ" {
+            return self._write_error(&format!("ERR unknown subcommand 'OBJECT {}'", subcommand));
+        }
+        let [key] = arguments else {
+            return self._write_arity_error("object");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        if !self.cache.tracks_access() {
+            return self._write_error(
+                "ERR the reads of the keys are not tracked, set MYRUSTCACHE_TRACK_ACCESS to track them",
+            );
+        }
+
+        match self.cache.key_access(&key)? {
+            None => self._write_null(),
+            Some(access) if subcommand == "FREQ" => self._write_integer(access.reads as i64),
+            Some(access) => self._write_integer(access.idle_seconds() as i64),
+        }
+    }
+
+    /// Handle `SAVE`, saving a snapshot of the cache (see `Snapshots`) before answering `OK`.
+    fn _handle_save_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("save");
+        }
+        let Some(snapshots) = self.snapshots.clone() else {
+            return self._write_error("ERR snapshots are disabled");
+        };
+        match snapshots.save(&self.cache) {
+            Ok(Some(_)) => self._write_simple("OK"),
+            Ok(None) => self._write_error("ERR Background save already in progress"),
+            Err(ServerError::Cache(e)) => Err(e.into()),
+            Err(e) => {
+                error!("Failed to save a snapshot: {}", e);
+                self._write_error("ERR failed to save the snapshot")
+            }
+        }
+    }
+
+    /// Handle `BGSAVE`, saving a snapshot of the cache in the background (see `Snapshots`).
+    fn _handle_bgsave_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("bgsave");
+        }
+        let Some(snapshots) = self.snapshots.clone() else {
+            return self._write_error("ERR snapshots are disabled");
+        };
+        match snapshots.save_in_background(&self.cache) {
+            Ok(true) => self._write_simple("Background saving started"),
+            Ok(false) => self._write_error("ERR Background save already in progress"),
+            Err(ServerError::Cache(e)) => Err(e.into()),
+            Err(e) => {
+                error!("Failed to start saving a snapshot: {}", e);
+                self._write_error("ERR failed to start saving the snapshot")
+            }
+        }
+    }
+
+    /// Handle `LASTSAVE`, answering when the last snapshot was saved (in seconds since the epoch), or 0 if
+    /// none was since the server started.
+    fn _handle_lastsave_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("lastsave");
+        }
+        let Some(snapshots) = &self.snapshots else {
+            return self._write_error("ERR snapshots are disabled");
+        };
+        let last_save: u64 = snapshots.last_save();
+        self._write_integer(last_save as i64)
+    }
+
+    /// Handle `CONFIG GET <pattern>...`, replying with the names and values of the operational parameters
+    /// matching the patterns (a map in RESP3, and an array of the names followed by their values in RESP2),
+    /// and `CONFIG SET <name> <value>`, changing a parameter while the server runs (see `RuntimeConfig`). The
+    /// names are answered in the spelling of Redis (e.g. `maxmemory-policy`).
+    fn _handle_config_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((subcommand, arguments)) = arguments.split_first() else {
+            return self._write_arity_error("config");
+        };
+
+        match (
+            String::from_utf8_lossy(subcommand)
+                .to_ascii_uppercase()
+                .as_str(),
+            arguments,
+        ) {
+            ("GET", [_, ..]) => {
+                let mut values: Vec<(&str, String)> = Vec::new();
+                for pattern in arguments {
+                    for (name, value) in self.runtime.get(&String::from_utf8_lossy(pattern)) {
+                        if !values.iter().any(|(other, _)| *other == name) {
+                            values.push((name, value));
+                        }
+                    }
+                }
+                self._write_map_header(values.len())?;
+                for (name, value) in values {
+                    self._write_bulk(name.replace('_', "-").as_bytes())?;
+                    self._write_bulk(value.as_bytes())?;
+                }
+                Ok(())
+            }
+            ("SET", [name, value]) => {
+                let name: Cow<str> = String::from_utf8_lossy(name);
+                let value: Cow<str> = String::from_utf8_lossy(value);
+                match self.runtime.set(&name, &value) {
+                    Ok(()) => {
+                        info!(
+                            "Configuration changed by {}: {}={}",
+                            self.address, name, value
+                        );
+                        self._write_simple("OK")
+                    }
+                    Err(message) => self._write_error(&format!("ERR {}", message)),
+                }
+            }
+            ("GET" | "SET", _) => self._write_arity_error("config"),
+            (other, _) => self._write_error(&format!("ERR unknown subcommand 'CONFIG {}'", other)),
+        }
+    }
+
+    /// Handle `SHUTDOWN [NOSAVE|SAVE]`, replying `+OK` before the server shuts down as it does on `SIGTERM`
+    /// (saving a final snapshot if enabled, unless `NOSAVE` is given). `SAVE` fails if snapshots are
+    /// disabled.
+    fn _handle_shutdown_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let save: Option<bool> = match arguments {
+            [] => None,
+            [mode] if mode.eq_ignore_ascii_case(b"SAVE") => Some(true),
+            [mode] if mode.eq_ignore_ascii_case(b"NOSAVE") => Some(false),
+            [_] => return self._write_error("ERR syntax error"),
+            _ => return self._write_arity_error("shutdown"),
+        };
+        if save == Some(true) && self.snapshots.is_none() {
+            return self._write_error("ERR snapshots are disabled");
+        }
+
+        warn!("Shutdown requested by {}", self.address);
+        self._write_simple("OK")?;
+        match save {
+            Some(false) => self.shutdown.shutdown_without_snapshot(),
+            _ => self.shutdown.shutdown(),
+        }
+        Ok(())
+    }
+
+    /// Handle `BGREWRITEAOF`, rewriting the append-only log in the background into a compacted one (see
+    /// `EmbeddedCache::rewrite_append_only_log`).
+    fn _handle_bgrewriteaof_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("bgrewriteaof");
+        }
+        if self.cache.append_only_log().is_none() {
+            return self._write_error("ERR the append-only log is disabled");
+        }
+        match self.cache.rewrite_append_only_log()? {
+            true => self._write_simple("Background append only file rewriting started"),
+            false => {
+                self._write_error("ERR Background append only file rewriting already in progress")
+            }
+        }
+    }
+
+    /// Handle `SYNC`, or `PSYNC replicationid offset` (always answered with a full resynchronization, as no
+    /// backlog of the writes is kept), attaching the client as a replica: it is sent a full copy of the data,
+    /// then the stream of the writes (see `primary::attach_replica`).
+    fn _handle_sync_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        arity: usize,
+    ) -> Result<(), ServerError> {
+        if arguments.len() != arity {
+            return self._write_arity_error(command);
+        }
+        let Some(feed) = self.cache.replication_feed().cloned() else {
+            return self._write_error("ERR this node does not accept replicas");
+        };
+        if self.is_listening() {
+            return self._write_error("ERR a subscribed client cannot attach as a replica");
+        }
+
+        // The replies written so far go first, the connection then carrying the writes only
+        self.stream.flush()?;
+        let stream: ClientStream = self.stream.get_ref().try_clone()?;
+        self.replica = Some(primary::attach_replica(
+            &feed,
+            &self.cache,
+            stream,
+            &self.address,
+        )?);
+        info!(
+            "Attached the replica {} ({} replica(s) now)",
+            self.address,
+            feed.replicas()
+        );
+        Ok(())
+    }
+
+    /// Handle `REPLCONF option value...`, which replicas send to describe themselves before `PSYNC`
+    /// (e.g. `REPLCONF listening-port 6380`). The options are accepted and ignored.
+    fn _handle_replconf_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("replconf");
+        }
+        self._write_simple("OK")
+    }
+
+    /// Handle `EXPORT file [JSON | CBOR]`, exporting every key of the cache to the given file of the export
+    /// directory (see `Datasets`), and answering the number of keys exported.
+    fn _handle_export_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (name, format): (&[u8], Option<DatasetFormat>) = match arguments {
+            [name] => (name, None),
+            [name, format] => match str::from_utf8(format).map(str::parse) {
+                Ok(Ok(format)) => (name, Some(format)),
+                _ => return self._write_error("ERR syntax error"),
+            },
+            _ => return self._write_arity_error("export"),
+        };
+        let Some(datasets) = self.datasets.clone() else {
+            return self._write_error("ERR exports are disabled");
+        };
+
+        let name: String = String::from_utf8_lossy(name).into_owned();
+        match datasets.export(&self.cache, &name, format) {
+            Ok(keys) => self._write_integer(keys as i64),
+            Err(e) => self._write_dataset_error(e, "export"),
+        }
+    }
+
+    /// Handle `IMPORT file [JSON | CBOR] [MERGE | REPLACE]`, importing the given file of the export directory
+    /// into the cache (see `Datasets`), and answering the number of keys imported.
+    fn _handle_import_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((name, options)) = arguments.split_first() else {
+            return self._write_arity_error("import");
+        };
+        let mut format: Option<DatasetFormat> = None;
+        let mut mode: ImportMode = ImportMode::default();
+        for option in options {
+            let option: &str = str::from_utf8(option).unwrap_or_default();
+            match (
+                option.parse::<DatasetFormat>(),
+                option.parse::<ImportMode>(),
+            ) {
+                (Ok(parsed), _) => format = Some(parsed),
+                (_, Ok(parsed)) => mode = parsed,
+                _ => return self._write_error("ERR syntax error"),
+            }
+        }
+        let Some(datasets) = self.datasets.clone() else {
+            return self._write_error("ERR imports are disabled");
+        };
+
+        let name: String = String::from_utf8_lossy(name).into_owned();
+        match datasets.import(&self.cache, &name, format, mode) {
+            Ok(stats) => self._write_integer(stats.imported as i64),
+            Err(e) => self._write_dataset_error(e, "import"),
+        }
+    }
+
+    /// Answer a failed export or import: cache errors as usual, and the others with what is wrong with the
+    /// file if the client can fix it.
+    fn _write_dataset_error(
+        &mut self,
+        error: ServerError,
+        action: &str,
+    ) -> Result<(), ServerError> {
+        match error {
+            ServerError::Cache(e) => Err(e.into()),
+            ServerError::Io(e) if e.kind() == io::ErrorKind::NotFound => {
+                self._write_error("ERR no such file")
+            }
+            ServerError::Io(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData
+                ) =>
+            {
+                self._write_error(&format!("ERR {}", e))
+            }
+            e => {
+                error!("Failed to {} a dataset: {}", action, e);
+                self._write_error(&format!("ERR failed to {} the dataset", action))
+            }
+        }
+    }
+
+    /// Handle `FLUSHDB [ASYNC | SYNC]`, removing every key of the selected database. With a single database,
+    /// the keys are removed at once like `FLUSHALL`, else they are removed in the background either way.
+    fn _handle_flushdb_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let in_background: bool = match arguments {
+            [] => false,
+            [mode] if mode.eq_ignore_ascii_case(b"SYNC") => false,
+            [mode] if mode.eq_ignore_ascii_case(b"ASYNC") => true,
+            _ => return self._write_error("ERR syntax error"),
+        };
+        if self.cache.databases() == 1 {
+            self.cache.clear(in_background)?;
+        } else {
+            let prefix: String = namespaces::database_prefix(self.database);
+            self.cache.clear_prefix_in_background(&prefix)?;
+        }
+        info!(
+            "{} has flushed the keys of the database {}",
+            self.address, self.database
+        );
+        self._write_simple("OK")
+    }
+
+    /// Handle `SWAPDB index1 index2`, swapping the keys of two databases (see
+    /// `EmbeddedCache::swap_databases`).
+    fn _handle_swapdb_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [first, second] = arguments else {
+            return self._write_arity_error("swapdb");
+        };
+        let (Some(first), Some(second)) =
+            (self._parse_database(first), self._parse_database(second))
+        else {
+            return self._write_error("ERR DB index is out of range");
+        };
+
+        let moved: usize = self.cache.swap_databases(first, second)?;
+        info!(
+            "{} has swapped the databases {} and {} ({} keys moved)",
+            self.address, first, second, moved
+        );
+        self._write_simple("OK")
+    }
+
+    /// Handle `FLUSHALL [ASYNC | SYNC]`, removing every key at once (their memory being released in the
+    /// background with `ASYNC`).
+    fn _handle_flushall_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let in_background: bool = match arguments {
+            [] => false,
+            [mode] if mode.eq_ignore_ascii_case(b"SYNC") => false,
+            [mode] if mode.eq_ignore_ascii_case(b"ASYNC") => true,
+            _ => return self._write_error("ERR syntax error"),
+        };
+        self.cache.clear(in_background)?;
+        info!("{} has flushed all the keys", self.address);
+        self._write_simple("OK")
+    }
+
+    /// Handle `GETSET key value`, replying with the value replaced (null if the key had none).
+    fn _handle_get_set_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, value] = arguments else {
+            return self._write_arity_error("getset");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        match self.cache.get_set(key, (*value).into())? {
+            Some(previous) => self._write_bulk(&previous),
+            None => self._write_null(),
+        }
+    }
+
+    /// Handle `GETDEL key`, replying with the value removed (null if the key had none).
+    fn _handle_get_del_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("getdel");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        match self.cache.get_del(key)? {
+            Some(value) => self._write_bulk(&value),
+            None => self._write_null(),
+        }
+    }
+
+    /// Handle `RENAME key newkey`, replying with OK (or an error if the key has no value), and `RENAMENX key
+    /// newkey`, replying with 1 if the key was renamed, 0 if the new key already has a value.
+    fn _handle_rename_command(
+        &mut self,
+        arguments: &[&[u8]],
+        name: &str,
+        if_absent: bool,
+    ) -> Result<(), ServerError> {
+        let [from, to] = arguments else {
+            return self._write_arity_error(name);
+        };
+        let (Some(from), Some(to)) = (self._parse_key(from), self._parse_key(to)) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+
+        match (self.cache.rename(&from, &to, if_absent)?, if_absent) {
+            (None, _) => self._write_error("ERR no such key"),
+            (Some(_), false) => self._write_simple("OK"),
+            (Some(renamed), true) => self._write_integer(i64::from(renamed)),
+        }
+    }
+
+    /// Handle `EXPIRE key seconds`, replying with 1 if the key has a value, 0 otherwise.
+    fn _handle_expire_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, ttl] = arguments else {
+            return self._write_arity_error("expire");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(ttl) = parse_number(ttl) else {
+            return self._write_error("ERR value is not an integer or out of range");
+        };
+
+        // A TTL of zero deletes the key, as in Redis
+        let updated: bool = match ttl {
+            0 => self.cache.remove(key)?.is_some(),
+            _ => self.cache.expire(key, Some(ttl))?,
+        };
+        self._write_integer(i64::from(updated))
+    }
+
+    /// Handle `EXPIREAT key unix-time-seconds` and `PEXPIREAT key unix-time-milliseconds`, replying with 1 if
+    /// the key has a value, which then expires at that time (or is deleted if it has passed), 0 otherwise.
+    ///
+    /// # Arguments
+    /// * `command` - The name of the command, in lowercase, for the arity error.
+    /// * `unit` - The milliseconds of the unit of the time.
+    fn _handle_expire_at_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        unit: u64,
+    ) -> Result<(), ServerError> {
+        let [key, at] = arguments else {
+            return self._write_arity_error(command);
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(at) = parse_number(at) else {
+            return self._write_error("ERR value is not an integer or out of range");
+        };
+
+        let updated: bool = self.cache.expire_at(key, at.saturating_mul(unit))?;
+        self._write_integer(i64::from(updated))
+    }
+
+    /// Handle `EXPIRETIME key` and `PEXPIRETIME key`, replying with the Unix time (in seconds or
+    /// milliseconds) at which the value of the key expires, -1 if it has no TTL, or -2 if the key has no
+    /// value.
+    ///
+    /// # Arguments
+    /// * `command` - The name of the command, in lowercase, for the arity error.
+    /// * `unit` - The milliseconds of the unit of the time.
+    fn _handle_expire_time_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        unit: u64,
+    ) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error(command);
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let at: i64 = match self.cache.expire_time(key)? {
+            Some(Some(at)) => (at / unit) as i64,
+            Some(None) => -1,
+            None => -2,
+        };
+        self._write_integer(at)
+    }
+
+    /// Handle `PERSIST key`, replying with 1 if the key had a TTL which was removed, 0 otherwise.
+    fn _handle_persist_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("persist");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let persisted: bool = match self.cache.remaining_ttl(key)? {
+            Some(Some(_)) => self.cache.expire(key, None)?,
+            _ => false,
+        };
+        self._write_integer(i64::from(persisted))
+    }
+
+    /// Handle `TOUCH key [key ...]`, restarting the TTL of the values for the TTL they were given, and
+    /// replying with the number of keys holding a value.
+    fn _handle_touch_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("touch");
+        }
+
+        // The keys which are not UTF-8 have no value
+        let keys: Vec<Cow<str>> = arguments
+            .iter()
+            .filter_map(|key| self._parse_key(key))
+            .collect();
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        let touched: usize = self.cache.touch(&keys)?;
+        self._write_integer(touched as i64)
+    }
+
+    /// Handle `TTL key`, replying with the seconds the value has left to live, -1 if it has no TTL, or -2
+    /// if the key has no value.
+    fn _handle_ttl_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("ttl");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let ttl: i64 = match self.cache.remaining_ttl(key)? {
+            Some(Some(remaining)) => remaining.div_ceil(1000) as i64,
+            Some(None) => -1,
+            None => -2,
+        };
+        self._write_integer(ttl)
+    }
+
+    /// Handle `INCR key`, `DECR key`, `INCRBY key increment` and `DECRBY key decrement`, replying with
+    /// the new value of the counter.
+    ///
+    /// # Arguments
+    /// * `command` - The name of the command, in lowercase, for the arity error.
+    /// * `negate` - Whether the increment is subtracted (DECR and DECRBY).
+    fn _handle_incr_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        negate: bool,
+    ) -> Result<(), ServerError> {
+        let (key, increment): (&[u8], Option<i64>) = match (command.ends_with("by"), arguments) {
+            (false, [key]) => (key, Some(1)),
+            (true, [key, increment]) => (key, parse_integer(increment)),
+            _ => return self._write_arity_error(command),
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let delta: Option<i64> = match negate {
+            true => increment.and_then(i64::checked_neg),
+            false => increment,
+        };
+        match delta.map(|delta| self.cache.incr_by(key, delta)) {
+            Some(Ok(counter)) => self._write_integer(counter),
+            None | Some(Err(CacheError::NotAnInteger)) => {
+                self._write_error("ERR value is not an integer or out of range")
+            }
+            Some(Err(e)) => Err(e.into()),
+        }
+    }
+
+    /// Handle `APPEND key value`, replying with the length of the new value.
+    fn _handle_append_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, suffix] = arguments else {
+            return self._write_arity_error("append");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let length: usize = self.cache.append(key, suffix)?;
+        self._write_integer(length as i64)
+    }
+
+    /// Handle `STRLEN key`, replying with the length of the value, or 0 if the key has no value.
+    fn _handle_strlen_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("strlen");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let length: usize = match self.cache.get(key)? {
+            Lookup::Fresh(value) | Lookup::Stale(value) => value.len(),
+            _ => 0,
+        };
+        self._write_integer(length as i64)
+    }
+
+    /// Handle `HSET key field value [field value ...]`, replying with the number of fields added.
+    fn _handle_hset_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((key, pairs)) = arguments.split_first() else {
+            return self._write_arity_error("hset");
+        };
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return self._write_arity_error("hset");
+        }
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let fields: Vec<(&[u8], Bytes)> = pairs
+            .chunks_exact(2)
+            .map(|pair| (pair[0], Bytes::from(pair[1])))
+            .collect();
+        let added: usize = self.cache.hset(key, &fields)?;
+        self._write_integer(added as i64)
+    }
+
+    /// Handle `HGET key field`, replying with the value of the field, or null if it is not set.
+    fn _handle_hget_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, field] = arguments else {
+            return self._write_arity_error("hget");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        match self.cache.hget(key, field)? {
+            Some(value) => self._write_bulk(&value),
+            None => self._write_null(),
+        }
+    }
+
+    /// Handle `HDEL key field [field ...]`, replying with the number of fields removed.
+    fn _handle_hdel_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((key, fields)) = arguments
+            .split_first()
+            .filter(|(_, fields)| !fields.is_empty())
+        else {
+            return self._write_arity_error("hdel");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let removed: usize = self.cache.hdel(key, fields)?;
+        self._write_integer(removed as i64)
+    }
+
+    /// Handle `HGETALL key`, replying with the fields and their values as a map (an empty one if the key
+    /// has no value).
+    fn _handle_hgetall_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("hgetall");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let fields: Vec<(Bytes, Bytes)> = self.cache.hgetall(key)?;
+        self._write_map_header(fields.len())?;
+        for (field, value) in &fields {
+            self._write_bulk(field)?;
+            self._write_bulk(value)?;
+        }
+        Ok(())
+    }
+
+    /// Handle `LPUSH key value [value ...]` or `RPUSH key value [value ...]`, replying with the length of
+    /// the list.
+    fn _handle_push_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        left: bool,
+    ) -> Result<(), ServerError> {
+        let Some((key, items)) = arguments
+            .split_first()
+            .filter(|(_, items)| !items.is_empty())
+        else {
+            return self._write_arity_error(command);
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let items: Vec<Bytes> = items.iter().map(|item| Bytes::from(*item)).collect();
+        let length: usize = self.cache.list_push(key, &items, left)?;
+        self._write_integer(length as i64)
+    }
+
+    /// Handle `LPOP key [count]` or `RPOP key [count]`, replying with the value popped, or with an array
+    /// of the values popped given a count (null if the key has no value either way).
+    fn _handle_pop_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        left: bool,
+    ) -> Result<(), ServerError> {
+        let (key, count): (&[u8], Option<&[u8]>) = match arguments {
+            [key] => (key, None),
+            [key, count] => (key, Some(count)),
+            _ => return self._write_arity_error(command),
+        };
+        let count: Option<usize> = match count.map(parse_integer) {
+            Some(Some(count)) if count >= 0 => Some(count as usize),
+            Some(_) => return self._write_error("ERR value is out of range, must be positive"),
+            None => None,
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let popped: Vec<Bytes> = self.cache.list_pop(key, count.unwrap_or(1), left)?;
+        match (count, popped.first()) {
+            (None, Some(value)) => self._write_bulk(value),
+            (Some(_), Some(_)) => {
+                self._write_array_header(popped.len())?;
+                popped.iter().try_for_each(|value| self._write_bulk(value))
+            }
+            (_, None) => self._write_null(),
+        }
+    }
+
+    /// Handle `BLPOP key [key ...] timeout` or `BRPOP key [key ...] timeout`, popping a value from the first
+    /// non-empty list of the keys or, if all of them are empty, waiting for a value to be pushed to any of
+    /// them for at most the timeout in seconds (`0` waiting forever), the clients waiting being handed the
+    /// values in the order they started waiting. Replies with an array of the key and the value, or with a
+    /// null array once the time is up; inside a transaction, the command replies right away.
+    fn _handle_blocking_pop_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        left: bool,
+    ) -> Result<(), ServerError> {
+        let Some((timeout, names)) = arguments
+            .split_last()
+            .filter(|(_, names)| !names.is_empty())
+        else {
+            return self._write_arity_error(command);
+        };
+        let Some(timeout) = parse_timeout(timeout) else {
+            return self._write_error("ERR timeout is not a float or out of range");
+        };
+        let mut keys: Vec<Cow<str>> = Vec::with_capacity(names.len());
+        for name in names {
+            let Some(key) = self._parse_key(name) else {
+                return self._write_error("ERR key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+
+        let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+        let popped: Option<(usize, Bytes)> = match self.blocking {
+            true => {
+                let outcome: BlockingPop = {
+                    let _guard = cache.command_lock()?;
+                    cache.list_pop_or_wait(&keys, left)?
+                };
+                match outcome {
+                    BlockingPop::Popped(index, value) => Some((index, value)),
+                    BlockingPop::Waiting(waiter) => {
+                        self.stream.flush()?; // The replies to the previous commands are not held back
+                        let connection: Option<&ClientStream> = Some(self.stream.get_ref());
+                        wait_popped(&cache, &waiter, &keys, left, timeout, connection)?
+                    }
+                }
+            }
+            false => match cache.list_pop_or_wait(&keys, left)? {
+                BlockingPop::Popped(index, value) => Some((index, value)),
+                BlockingPop::Waiting(waiter) => waiter.cancel()?,
+            },
+        };
+        match popped {
+            Some((index, value)) => {
+                self._write_array_header(2)?;
+                self._write_bulk(names[index])?;
+                self._write_bulk(&value)
+            }
+            None => self._write_null_array(),
+        }
+    }
+
+    /// Handle `LRANGE key start stop`, replying with the values from the start index to the stop one, both
+    /// included, where negative indexes count from the tail.
+    fn _handle_lrange_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, start, stop] = arguments else {
+            return self._write_arity_error("lrange");
+        };
+        let (Some(start), Some(stop)) = (parse_integer(start), parse_integer(stop)) else {
+            return self._write_error("ERR value is not an integer or out of range");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let items: Vec<Bytes> = self.cache.list_range(key, start, stop)?;
+        self._write_array_header(items.len())?;
+        items.iter().try_for_each(|item| self._write_bulk(item))
+    }
+
+    /// Handle `SADD key member [member ...]`, replying with the number of members added.
+    fn _handle_sadd_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((key, members)) = arguments
+            .split_first()
+            .filter(|(_, members)| !members.is_empty())
+        else {
+            return self._write_arity_error("sadd");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let members: Vec<Bytes> = members.iter().map(|member| Bytes::from(*member)).collect();
+        let added: usize = self.cache.sadd(key, &members)?;
+        self._write_integer(added as i64)
+    }
+
+    /// Handle `SREM key member [member ...]`, replying with the number of members removed.
+    fn _handle_srem_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((key, members)) = arguments
+            .split_first()
+            .filter(|(_, members)| !members.is_empty())
+        else {
+            return self._write_arity_error("srem");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let removed: usize = self.cache.srem(key, members)?;
+        self._write_integer(removed as i64)
+    }
+
+    /// Handle `SISMEMBER key member`, replying with 1 if the member is in the set, or 0 otherwise.
+    fn _handle_sismember_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, member] = arguments else {
+            return self._write_arity_error("sismember");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let found: bool = self.cache.sismember(key, member)?;
+        self._write_integer(i64::from(found))
+    }
+
+    /// Handle `SMEMBERS key`, replying with the members of the set (an empty array if the key has no
+    /// value).
+    fn _handle_smembers_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("smembers");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let members: Vec<Bytes> = self.cache.smembers(key)?;
+        self._write_set(&members)
+    }
+
+    /// Handle `SINTER key [key ...]`, `SUNION key [key ...]` or `SDIFF key [key ...]`, replying with the
+    /// members of the combined set.
+    fn _handle_set_combine_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        operation: SetOperation,
+    ) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error(command);
+        }
+        let mut keys: Vec<Cow<str>> = Vec::with_capacity(arguments.len());
+        for key in arguments {
+            let Some(key) = self._parse_key(key) else {
+                return self._write_error("ERR key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        let members: Vec<Bytes> = self.cache.set_combine(&keys, operation)?;
+        self._write_set(&members)
+    }
+
+    /// Handle `ZADD key score member [score member ...]`, replying with the number of members added.
+    fn _handle_zadd_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((key, pairs)) = arguments.split_first() else {
+            return self._write_arity_error("zadd");
+        };
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return self._write_arity_error("zadd");
+        }
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let mut members: Vec<(f64, &[u8])> = Vec::with_capacity(pairs.len() / 2);
+        for pair in pairs.chunks_exact(2) {
+            let Some(score) = values::parse_score(pair[0]) else {
+                return self._write_error("ERR value is not a valid float");
+            };
+            members.push((score, pair[1]));
+        }
+        let added: usize = self.cache.zadd(key, &members)?;
+        self._write_integer(added as i64)
+    }
+
+    /// Handle `ZINCRBY key increment member`, replying with the new score.
+    fn _handle_zincrby_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, increment, member] = arguments else {
+            return self._write_arity_error("zincrby");
+        };
+        let Some(increment) = values::parse_score(increment) else {
+            return self._write_error("ERR value is not a valid float");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let score: f64 = self.cache.zincrby(key, member, increment)?;
+        self._write_double(score)
+    }
+
+    /// Handle `ZREM key member [member ...]`, replying with the number of members removed.
+    fn _handle_zrem_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((key, members)) = arguments
+            .split_first()
+            .filter(|(_, members)| !members.is_empty())
+        else {
+            return self._write_arity_error("zrem");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let removed: usize = self.cache.zrem(key, members)?;
+        self._write_integer(removed as i64)
+    }
+
+    /// Handle `ZSCORE key member`, replying with the score of the member, or null if it is not in the
+    /// sorted set.
+    fn _handle_zscore_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, member] = arguments else {
+            return self._write_arity_error("zscore");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        match self.cache.zscore(key, member)? {
+            Some(score) => self._write_double(score),
+            None => self._write_null(),
+        }
+    }
+
+    /// Handle `ZRANGE key start stop [WITHSCORES]` or `ZRANGEBYSCORE key min max [WITHSCORES]`, replying
+    /// with the members by score, each followed by its score with `WITHSCORES` (as a pair in RESP3).
+    fn _handle_zrange_command(
+        &mut self,
+        arguments: &[&[u8]],
+        command: &str,
+        by_score: bool,
+    ) -> Result<(), ServerError> {
+        let (key, start, stop, with_scores): (&[u8], &[u8], &[u8], bool) = match arguments {
+            [key, start, stop] => (key, start, stop, false),
+            [key, start, stop, option] if option.eq_ignore_ascii_case(b"WITHSCORES") => {
+                (key, start, stop, true)
+            }
+            [_, _, _, _] => return self._write_error("ERR syntax error"),
+            _ => return self._write_arity_error(command),
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let members: Vec<(Bytes, f64)> = match by_score {
+            true => match (ScoreBound::parse(start), ScoreBound::parse(stop)) {
+                (Some(min), Some(max)) => self.cache.zrange_by_score(key, min, max)?,
+                _ => return self._write_error("ERR min or max is not a float"),
+            },
+            false => match (parse_integer(start), parse_integer(stop)) {
+                (Some(start), Some(stop)) => self.cache.zrange(key, start, stop)?,
+                _ => return self._write_error("ERR value is not an integer or out of range"),
+            },
+        };
+        let flat: bool = with_scores && self.version != 3; // RESP2 has no pairs
+        self._write_array_header(if flat {
+            members.len() * 2
+        } else {
+            members.len()
+        })?;
+        for (member, score) in &members {
+            if with_scores && self.version == 3 {
+                self._write_array_header(2)?;
+            }
+            self._write_bulk(member)?;
+            if with_scores {
+                self._write_double(*score)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle `SETBIT key offset bit`, replying with the previous bit.
+    fn _handle_setbit_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, offset, bit] = arguments else {
+            return self._write_arity_error("setbit");
+        };
+        let Some(offset) = values::parse_bit_offset(offset) else {
+            return self._write_error("ERR bit offset is not an integer or out of range");
+        };
+        let bit: bool = match *bit {
+            b"0" => false,
+            b"1" => true,
+            _ => return self._write_error("ERR bit is not an integer or out of range"),
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let previous: bool = self.cache.setbit(key, offset, bit)?;
+        self._write_integer(i64::from(previous))
+    }
+
+    /// Handle `GETBIT key offset`, replying with the bit at the offset.
+    fn _handle_getbit_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, offset] = arguments else {
+            return self._write_arity_error("getbit");
+        };
+        let Some(offset) = values::parse_bit_offset(offset) else {
+            return self._write_error("ERR bit offset is not an integer or out of range");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let bit: bool = self.cache.getbit(key, offset)?;
+        self._write_integer(i64::from(bit))
+    }
+
+    /// Handle `BITCOUNT key [start end]`, replying with the number of bits set in the value (or in a range
+    /// of its bytes).
+    fn _handle_bitcount_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (key, start, stop): (&[u8], i64, i64) = match arguments {
+            [key] => (key, 0, -1),
+            [key, start, stop] => match (parse_integer(start), parse_integer(stop)) {
+                (Some(start), Some(stop)) => (key, start, stop),
+                _ => return self._write_error("ERR value is not an integer or out of range"),
+            },
+            _ => return self._write_arity_error("bitcount"),
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let count: u64 = self.cache.bitcount(key, start, stop)?;
+        self._write_integer(count as i64)
+    }
+
+    /// Handle `PFADD key [element ...]`, replying with 1 if the estimate may have changed, or 0 otherwise.
+    fn _handle_pfadd_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((key, elements)) = arguments.split_first() else {
+            return self._write_arity_error("pfadd");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let changed: bool = self.cache.pfadd(key, elements)?;
+        self._write_integer(i64::from(changed))
+    }
+
+    /// Handle `PFCOUNT key [key ...]`, replying with the estimated number of distinct elements.
+    fn _handle_pfcount_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("pfcount");
+        }
+        let mut keys: Vec<Cow<str>> = Vec::with_capacity(arguments.len());
+        for key in arguments {
+            let Some(key) = self._parse_key(key) else {
+                return self._write_error("ERR key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        let count: u64 = self.cache.pfcount(&keys)?;
+        self._write_integer(count as i64)
+    }
+
+    /// Handle `PFMERGE destkey [sourcekey ...]`.
+    fn _handle_pfmerge_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("pfmerge");
+        }
+        let mut keys: Vec<Cow<str>> = Vec::with_capacity(arguments.len());
+        for key in arguments {
+            let Some(key) = self._parse_key(key) else {
+                return self._write_error("ERR key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+
+        let sources: Vec<&str> = keys[1..].iter().map(|key| key.as_ref()).collect();
+        self.cache.pfmerge(&keys[0], &sources)?;
+        self._write_simple("OK")
+    }
+
+    /// Handle `BF.RESERVE key error_rate capacity`, replying with an error if the key already has a value,
+    /// as in RedisBloom (which also scales the filters past their capacity, while these ones do not).
+    fn _handle_bf_reserve_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key, error_rate, capacity] = arguments else {
+            return self._write_arity_error("bf.reserve");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(error_rate) = values::parse_score(error_rate) else {
+            return self._write_error("ERR bad error rate");
+        };
+        let Some(capacity) = parse_number(capacity) else {
+            return self._write_error("ERR bad capacity");
+        };
+        let Some(filter) = BloomFilter::new(capacity, error_rate) else {
+            return self._write_error(
+                "ERR the error rate must be between 0 and 1, and the filter within 64 MiB",
+            );
+        };
+
+        match self.cache.bf_reserve(key, filter)? {
+            true => self._write_simple("OK"),
+            false => self._write_error("ERR item exists"),
+        }
+    }
+
+    /// Handle `BF.ADD key item` (or `BF.EXISTS key item`), replying with 1 if the item was not in the
+    /// filter yet (or may have been added), or 0 otherwise.
+    fn _handle_bf_element_command(
+        &mut self,
+        arguments: &[&[u8]],
+        name: &str,
+        add: bool,
+    ) -> Result<(), ServerError> {
+        let [key, element] = arguments else {
+            return self._write_arity_error(name);
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let answer: bool = match add {
+            true => self.cache.bf_add(key, element)?,
+            false => self.cache.bf_exists(key, element)?,
+        };
+        self._write_integer(i64::from(answer))
+    }
+
+    /// Handle `RATELIMIT key max_tokens refill_per_sec [cost]`, replying with an array of 1 if there were
+    /// enough tokens (or else 0), the tokens left, and the milliseconds until there are enough tokens (0 if
+    /// there were, -1 if the cost is above the most tokens).
+    fn _handle_ratelimit_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (key, capacity, refill_rate, cost) = match arguments {
+            [key, capacity, refill_rate] => (key, capacity, refill_rate, None),
+            [key, capacity, refill_rate, cost] => (key, capacity, refill_rate, Some(cost)),
+            _ => return self._write_arity_error("ratelimit"),
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(capacity) = parse_number(capacity).filter(|capacity| *capacity > 0) else {
+            return self._write_error("ERR max_tokens must be a positive integer");
+        };
+        let Some(refill_rate) = values::parse_score(refill_rate)
+            .filter(|refill_rate| *refill_rate > 0.0 && refill_rate.is_finite())
+        else {
+            return self._write_error("ERR refill_per_sec must be a positive number");
+        };
+        let cost: u64 = match cost.map(|cost| parse_number(cost)) {
+            Some(Some(cost)) => cost,
+            Some(None) => return self._write_error("ERR cost must be a non-negative integer"),
+            None => 1,
+        };
+
+        let decision: RateDecision = self.cache.rate_limit(key, capacity, refill_rate, cost)?;
+        self._write_array_header(3)?;
+        self._write_integer(i64::from(decision.allowed))?;
+        self._write_integer(decision.remaining as i64)?;
+        self._write_integer(decision.retry_after.map_or(-1, |millis| millis as i64))
+    }
+
+    /// Handle `XADD key [MAXLEN [=|~] count] * value`, replying with the ID given to the value (which is
+    /// always generated, hence `*`).
+    fn _handle_xadd_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (key, max_len, id, value) = match arguments {
+            [key, id, value] => (key, None, id, value),
+            [key, option, max_len, id, value] if option.eq_ignore_ascii_case(b"MAXLEN") => {
+                (key, Some(max_len), id, value)
+            }
+            [key, option, b"=" | b"~", max_len, id, value]
+                if option.eq_ignore_ascii_case(b"MAXLEN") =>
+            {
+                (key, Some(max_len), id, value)
+            }
+            [_, _, _, _, _] | [_, _, _, _, _, _] => return self._write_error("ERR syntax error"),
+            _ => return self._write_arity_error("xadd"),
+        };
+        if *id != b"*" {
+            return self._write_error("ERR only generated IDs (*) are supported");
+        }
+        let max_len: Option<usize> = match max_len.map(|max_len| parse_number(max_len)) {
+            None => None,
+            Some(Some(max_len)) => Some(max_len as usize),
+            Some(None) => return self._write_error("ERR value is not an integer or out of range"),
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let id: StreamId = self.cache.xadd(key, Bytes::from(*value), max_len)?;
+        self._write_bulk(id.to_string().as_bytes())
+    }
+
+    /// Handle `XRANGE key start end [COUNT count]`, replying with the entries (see `_write_entries`).
+    fn _handle_xrange_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (key, start, end, count) = match arguments {
+            [key, start, end] => (key, start, end, None),
+            [key, start, end, option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+                (key, start, end, Some(count))
+            }
+            [_, _, _, _, _] => return self._write_error("ERR syntax error"),
+            _ => return self._write_arity_error("xrange"),
+        };
+        let (Some(start), Some(end)) = (
+            StreamId::parse_bound(start, true),
+            StreamId::parse_bound(end, false),
+        ) else {
+            return self._write_error("ERR Invalid stream ID specified as stream command argument");
+        };
+        let count: Option<usize> = match count.map(|count| parse_number(count)) {
+            None => None,
+            Some(Some(count)) => Some(count as usize),
+            Some(None) => return self._write_error("ERR value is not an integer or out of range"),
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let entries: Vec<(StreamId, Bytes)> = self.cache.xrange(key, start, end, count)?;
+        self._write_entries(&entries)
+    }
+
+    /// Handle `XREAD [COUNT count] STREAMS key [key ...] id [id ...]`, replying with the entries of every
+    /// stream appended after its ID, keyed by stream (as a map in RESP3), or null if there is none.
+    fn _handle_xread_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let (count, streams): (Option<&[u8]>, &[&[u8]]) = match arguments {
+            [option, streams @ ..] if option.eq_ignore_ascii_case(b"STREAMS") => (None, streams),
+            [option, count, keyword, streams @ ..]
+                if option.eq_ignore_ascii_case(b"COUNT")
+                    && keyword.eq_ignore_ascii_case(b"STREAMS") =>
+            {
+                (Some(count), streams)
+            }
+            _ => return self._write_error("ERR syntax error"),
+        };
+        if streams.is_empty() || !streams.len().is_multiple_of(2) {
+            return self._write_error(
+                "ERR Unbalanced 'xread' list of streams: for each stream key an ID must be specified.",
+            );
+        }
+        let count: Option<usize> = match count.map(parse_number) {
+            None => None,
+            Some(Some(count)) => Some(count as usize),
+            Some(None) => return self._write_error("ERR value is not an integer or out of range"),
+        };
+
+        let (names, ids) = streams.split_at(streams.len() / 2);
+        let mut keys: Vec<Cow<str>> = Vec::with_capacity(names.len());
+        let mut after: Vec<StreamId> = Vec::with_capacity(ids.len());
+        for (name, id) in names.iter().zip(ids) {
+            let Some(key) = self._parse_key(name) else {
+                return self._write_error("ERR key must be valid UTF-8");
+            };
+            let Some(id) = StreamId::parse(id, 0) else {
+                return self
+                    ._write_error("ERR Invalid stream ID specified as stream command argument");
+            };
+            keys.push(key);
+            after.push(id);
+        }
+
+        let streams: Vec<(&str, StreamId)> =
+            keys.iter().map(|key| key.as_ref()).zip(after).collect();
+        let entries: Vec<Vec<(StreamId, Bytes)>> = self.cache.xread(&streams, count)?;
+        let found: usize = entries.iter().filter(|entries| !entries.is_empty()).count();
+        if found == 0 {
+            return self._write_null();
+        }
+        match self.version {
+            3 => self._write_map_header(found)?,
+            _ => self._write_array_header(found)?, // RESP2 has an array of pairs
+        }
+        for (name, entries) in names.iter().zip(&entries) {
+            if entries.is_empty() {
+                continue;
+            }
+            if self.version != 3 {
+                self._write_array_header(2)?;
+            }
+            self._write_bulk(name)?;
+            self._write_entries(entries)?;
+        }
+        Ok(())
+    }
+
+    /// Handle `XLEN key`, replying with the number of entries.
+    fn _handle_xlen_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [key] = arguments else {
+            return self._write_arity_error("xlen");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error("ERR key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let length: usize = self.cache.xlen(key)?;
+        self._write_integer(length as i64)
+    }
+
+    /// Handle `PING [message]`.
+    fn _handle_ping_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        match arguments {
+            [] => self._write_simple("PONG"),
+            [message] => self._write_bulk(message),
+            _ => self._write_arity_error("ping"),
+        }
+    }
+
+    /// Handle `PING [message]` while subscribed to channels in RESP2, replying with `pong` and the message
+    /// (empty if none) in an array, like the messages pushed.
+    fn _handle_subscribed_ping_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let message: &[u8] = match arguments {
+            [] => b"",
+            [message] => message,
+            _ => return self._write_arity_error("ping"),
+        };
+        self._write_array_header(2)?;
+        self._write_bulk(b"pong")?;
+        self._write_bulk(message)
+    }
+
+    /// Handle `MULTI`, opening a transaction: the next commands are queued (each answered with `QUEUED`)
+    /// until `EXEC` executes them, or `DISCARD` drops them.
+    fn _handle_multi_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("multi");
+        }
+        if self.transaction.is_some() {
+            return self._write_error("ERR MULTI calls can not be nested");
+        }
+        self.transaction = Some(Transaction::new());
+        self._write_simple("OK")
+    }
+
+    /// Handle `EXEC`, executing the commands queued since `MULTI` in a row while the commands of the other
+    /// clients wait (see `EmbeddedCache::transaction_lock`), answering an array of their replies. A command
+    /// failing does not stop the others, but none are executed if one could not be queued, or if a key
+    /// watched with `WATCH` has changed (answering a null array).
+    fn _handle_exec_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("exec");
+        }
+        let Some(transaction) = self.transaction.take() else {
+            return self._write_error("ERR EXEC without MULTI");
+        };
+        let watch: Option<Watch> = self.watch.take();
+        if transaction.is_failed() {
+            return self
+                ._write_error("EXECABORT Transaction discarded because of previous errors.");
+        }
+
+        let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+        let _guard = cache.transaction_lock()?;
+        if watch.is_some_and(|watch| watch.is_changed()) {
+            return self._write_null_array();
+        }
+
+        let commands: Vec<Vec<Vec<u8>>> = transaction.into_commands();
+        self._write_array_header(commands.len())?;
+        for command in &commands {
+            let Some((name, arguments)) = command.split_first() else {
+                continue;
+            };
+            let name: String = String::from_utf8_lossy(name).into_owned();
+            let arguments: Vec<&[u8]> = arguments.iter().map(Vec::as_slice).collect();
+            self._execute_command(&name, &arguments)?;
+        }
+        Ok(())
+    }
+
+    /// Handle `DISCARD`, dropping the commands queued since `MULTI` and unwatching the keys.
+    fn _handle_discard_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("discard");
+        }
+        match self.transaction.take() {
+            Some(_) => {
+                self.watch = None;
+                self._write_simple("OK")
+            }
+            None => self._write_error("ERR DISCARD without MULTI"),
+        }
+    }
+
+    /// Handle `WATCH key [key ...]`, watching keys for optimistic concurrency: the next `EXEC` executes
+    /// nothing (answering a null array) if any of them has changed in between.
+    fn _handle_watch_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("watch");
+        }
+        let mut keys: Vec<String> = Vec::with_capacity(arguments.len());
+        for key in arguments {
+            let Some(key) = self._parse_key(key) else {
+                return self._write_error("ERR key must be valid UTF-8");
+            };
+            keys.push(key.into_owned());
+        }
+
+        let watch: &mut Watch = match &mut self.watch {
+            Some(watch) => watch,
+            None => self.watch.insert(Watch::new(Arc::clone(&self.cache))),
+        };
+        for key in &keys {
+            watch.watch(key)?;
+        }
+        self._write_simple("OK")
+    }
+
+    /// Handle `EVAL script numkeys [key ...] [arg ...]`, running a script on the given keys and the other
+    /// arguments (see `EmbeddedCache::eval`) while the commands of the other clients wait, and replying with
+    /// the value the script returns.
+    fn _handle_eval_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [script, key_count, rest @ ..] = arguments else {
+            return self._write_arity_error("eval");
+        };
+        let Some(key_count) = parse_number(key_count) else {
+            return self._write_error("ERR value is not an integer or out of range");
+        };
+        if key_count > rest.len() as u64 {
+            return self._write_error("ERR Number of keys can't be greater than number of args");
+        }
+        let (names, args): (&[&[u8]], &[&[u8]]) = rest.split_at(key_count as usize);
+
+        let mut keys: Vec<(&str, String)> = Vec::with_capacity(names.len());
+        for name in names {
+            let Some(name) = protocol::as_key(name) else {
+                return self._write_error("ERR key must be valid UTF-8");
+            };
+            keys.push((name, self._database_key(name).into_owned()));
+        }
+        let reply: Reply = self.cache.eval(script, &keys, args)?;
+        self._write_reply(&reply)
+    }
+
+    /// Handle `SUBSCRIBE channel [channel ...]`, replying with a `subscribe` push for every channel, along
+    /// with the number of channels subscribed to. The messages published on the channels are then pushed
+    /// to the client (see `encode_resp2_message`), and only the commands on subscriptions are accepted
+    /// until it unsubscribes from all of them, unless it speaks RESP3.
+    fn _handle_subscribe_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if arguments.is_empty() {
+            return self._write_arity_error("subscribe");
+        }
+        let subscriptions: &mut Subscriptions = match &mut self.subscriptions {
+            Some(subscriptions) => subscriptions,
+            None => self
+                .subscriptions
+                .insert(Subscriptions::new(Arc::clone(self.cache.pubsub()))?),
+        };
+
+        let mut replies: Vec<(&[u8], usize)> = Vec::with_capacity(arguments.len());
+        for channel in arguments {
+            let count: usize = subscriptions.subscribe(&String::from_utf8_lossy(channel))?;
+            replies.push((channel, count));
+        }
+        for (channel, count) in replies {
+            self._write_push_header(3)?;
+            self._write_bulk(b"subscribe")?;
+            self._write_bulk(channel)?;
+            self._write_integer(count as i64)?;
+        }
+        Ok(())
+    }
+
+    /// Handle `UNSUBSCRIBE [channel ...]` (from every channel if none is given), replying with an
+    /// `unsubscribe` push for every channel, along with the number of channels still subscribed to.
+    fn _handle_unsubscribe_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let channels: Vec<String> = match (arguments, &self.subscriptions) {
+            ([], Some(subscriptions)) => subscriptions.channels().to_vec(),
+            ([], None) => Vec::new(),
+            (channels, _) => channels
+                .iter()
+                .map(|channel| String::from_utf8_lossy(channel).into_owned())
+                .collect(),
+        };
+        if channels.is_empty() {
+            self._write_push_header(3)?;
+            self._write_bulk(b"unsubscribe")?;
+            self._write_null()?;
+            return self._write_integer(0);
+        }
+
+        for channel in &channels {
+            let count: usize = match &mut self.subscriptions {
+                Some(subscriptions) => subscriptions.unsubscribe(channel)?,
+                None => 0,
+            };
+            self._write_push_header(3)?;
+            self._write_bulk(b"unsubscribe")?;
+            self._write_bulk(channel.as_bytes())?;
+            self._write_integer(count as i64)?;
+        }
+        Ok(())
+    }
+
+    /// Handle `PUBLISH channel message`, replying with the number of subscribers it was queued for.
+    fn _handle_publish_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [channel, message] = arguments else {
+            return self._write_arity_error("publish");
+        };
+
+        let channel: Cow<str> = String::from_utf8_lossy(channel);
+        let received: usize = self.cache.publish(&channel, Bytes::from(*message))?;
+        self._write_integer(received as i64)
+    }
+
+    /// Handle `MONITOR`, turning the connection into a feed of the commands executed by the server, over
+    /// any protocol: replies `+OK`, then pushes a simple string per command (see
+    /// `monitor::format_command`). Only QUIT is accepted afterwards.
+    fn _handle_monitor_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if !arguments.is_empty() {
+            return self._write_arity_error("monitor");
+        }
+        if self.is_listening() {
+            return self._write_error("ERR a subscribed client cannot monitor the commands");
+        }
+
+        let mut monitor: Subscriptions = Subscriptions::new(Arc::clone(self.cache.monitors()))?;
+        monitor.subscribe(MONITOR_CHANNEL)?;
+        self.subscriptions = Some(monitor);
+        self.monitoring = true;
+        self._write_simple("OK")
+    }
+
+    /// Handle `HELLO [protover ...]`, switching to the requested protocol version and replying with the
+    /// properties of the server. Authentication and client names are ignored.
+    fn _handle_hello_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        if let Some(version) = arguments.first() {
+            match parse_number(version) {
+                Some(version @ (2 | 3)) => self.version = version as u8,
+                _ => return self._write_error("NOPROTO unsupported protocol version"),
+            }
+        }
+
+        let properties: [(&str, &str); 5] = [
+            ("server", "myrustcache"),
+            ("version", env!("CARGO_PKG_VERSION")),
+            ("proto", if self.version == 3 { "3" } else { "2" }),
+            ("mode", "standalone"),
+            ("role", "master"),
+        ];
+
+        self._write_map_header(properties.len())?;
+        for (name, value) in properties {
+            self._write_bulk(name.as_bytes())?;
+            match name {
+                "proto" => self._write_integer(i64::from(self.version))?,
+                _ => self._write_bulk(value.as_bytes())?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle `CLIENT SETNAME`, `CLIENT SETINFO` and `CLIENT GETNAME`, which clients send when connecting.
+    /// The names are not kept, since connections are only known by their address.
+    ///
+    /// `CLIENT LIST` replies with a bulk string of a line per client connection open across all the
+    /// listeners (e.g. `id=3 addr=127.0.0.1:51234 protocol=RESP age=120 idle=4 commands=57 cmd=get`), and
+    /// `CLIENT KILL addr` disconnects a client.
+    fn _handle_client_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let Some((subcommand, arguments)) = arguments.split_first() else {
+            return self._write_arity_error("client");
+        };
+
+        match (
+            String::from_utf8_lossy(subcommand)
+                .to_ascii_uppercase()
+                .as_str(),
+            arguments,
+        ) {
+            ("SETNAME" | "SETINFO", _) => self._write_simple("OK"),
+            ("GETNAME", _) => self._write_null(),
+            ("LIST", []) => {
+                let clients: Vec<ClientInfo> = self.cache.clients()?;
+                let mut list: String = String::new();
+                for client in &clients {
+                    list.push_str(&format!(
+                        "id={} addr={} protocol={} age={} idle={} commands={} cmd={}\n",
+                        client.id,
+                        client.address,
+                        client.protocol,
+                        client.age.as_secs(),
+                        client.idle.as_secs(),
+                        client.commands,
+                        client
+                            .last_command
+                            .as_deref()
+                            .map_or("NULL".to_string(), str::to_lowercase)
+                    ));
+                }
+                self._write_bulk(list.as_bytes())
+            }
+            ("KILL", [address]) => {
+                match self.cache.kill_client(&String::from_utf8_lossy(address))? {
+                    true => self._write_simple("OK"),
+                    false => self._write_error("ERR No such client"),
+                }
+            }
+            ("LIST" | "KILL", _) => self._write_arity_error("client"),
+            (other, _) => self._write_error(&format!("ERR unknown subcommand 'CLIENT {}'", other)),
+        }
+    }
+
+    /// Handle `SELECT index`, switching to the given database (index 0 being the only one, unless the cache
+    /// has several).
+    fn _handle_select_command(&mut self, arguments: &[&[u8]]) -> Result<(), ServerError> {
+        let [index] = arguments else {
+            return self._write_arity_error("select");
+        };
+        match self._parse_database(index) {
+            Some(index) => {
+                self.database = index;
+                self._write_simple("OK")
+            }
+            None => self._write_error("ERR DB index is out of range"),
+        }
+    }
+
+    /// Validate that a key is UTF-8 text (see `protocol::as_key`), then place it in the selected database.
+    /// Returns `None` (and logs a warning) if it is not a valid key.
+    fn _parse_key<'a>(&self, key: &'a [u8]) -> Option<Cow<'a, str>> {
+        let maybe_key: Option<&str> = protocol::as_key(key);
+
+        if maybe_key.is_none() {
+            warn!("Command with an invalid key sent from {}", self.address);
+        }
+
+        maybe_key.map(|key| self._database_key(key))
+    }
+
+    /// The key of the cache holding the given key of the selected database.
+    fn _database_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        match self.database {
+            0 => Cow::Borrowed(key),
+            index => Cow::Owned(format!("{}{}", namespaces::database_prefix(index), key)),
+        }
+    }
+
+    /// Keep the keys of the cache found by `KEYS` or `SCAN` which belong to the selected database, and which
+    /// the user (if any) may access.
+    fn _retain_accessible(&self, keys: &mut Vec<String>) {
+        keys.retain(|key| self._is_accessible(key));
+    }
+
+    /// Whether a key of the cache belongs to the selected database, and the user (if any) may access it.
+    fn _is_accessible(&self, key: &str) -> bool {
+        let user: Option<&(String, AclUser)> = self.user.and_then(|index| self.auth.user(index));
+        let (database, key) = namespaces::split_database(key);
+        database == self.database && user.is_none_or(|(_, user)| user.allows_key(key.as_bytes()))
+    }
+
+    /// Parse the index of a database, which has to be below the number of databases.
+    fn _parse_database(&self, index: &[u8]) -> Option<usize> {
+        parse_number(index)
+            .and_then(|index| usize::try_from(index).ok())
+            .filter(|index| *index < self.cache.databases())
+    }
+
+    fn _write_simple(&mut self, message: &str) -> Result<(), ServerError> {
+        write_all_vectored(
+            &mut self.stream,
+            &mut [
+                IoSlice::new(b"+"),
+                IoSlice::new(message.as_bytes()),
+                IoSlice::new(b"\r\n"),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn _write_error(&mut self, message: &str) -> Result<(), ServerError> {
+        self.answered_error = true;
+        write_all_vectored(
+            &mut self.stream,
+            &mut [
+                IoSlice::new(b"-"),
+                IoSlice::new(message.as_bytes()),
+                IoSlice::new(b"\r\n"),
+            ],
+        )?;
+        debug!("Error sent to {}: {}", self.address, message);
+        Ok(())
+    }
+
+    fn _write_arity_error(&mut self, command: &str) -> Result<(), ServerError> {
+        self._write_error(&format!(
+            "ERR wrong number of arguments for '{}' command",
+            command
+        ))
+    }
+
+    /// Write a line made of the given type byte and integer (e.g. `*3` or `:42`), without allocating it.
+    fn _write_header(&mut self, kind: u8, value: i64) -> Result<(), ServerError> {
+        let value: Decimal = Decimal::new(value);
+        write_all_vectored(
+            &mut self.stream,
+            &mut [
+                IoSlice::new(&[kind]),
+                IoSlice::new(value.as_bytes()),
+                IoSlice::new(b"\r\n"),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn _write_array_header(&mut self, length: usize) -> Result<(), ServerError> {
+        self._write_header(b'*', length as i64)
+    }
+
+    /// Write the header of a push of the given number of elements: a push in RESP3, and an array in RESP2.
+    fn _write_push_header(&mut self, length: usize) -> Result<(), ServerError> {
+        let header: u8 = if self.version == 3 { b'>' } else { b'*' };
+        self._write_header(header, length as i64)
+    }
+
+    /// Write the header of a map of the given number of entries: a map in RESP3, and an array of the keys
+    /// followed by their values in RESP2.
+    fn _write_map_header(&mut self, length: usize) -> Result<(), ServerError> {
+        match self.version {
+            3 => self._write_header(b'%', length as i64),
+            _ => self._write_header(b'*', length as i64 * 2),
+        }
+    }
+
+    /// Write the given members as a set in RESP3, and as an array in RESP2.
+    fn _write_set(&mut self, members: &[Bytes]) -> Result<(), ServerError> {
+        let header: u8 = if self.version == 3 { b'~' } else { b'*' };
+        self._write_header(header, members.len() as i64)?;
+        members
+            .iter()
+            .try_for_each(|member| self._write_bulk(member))
+    }
+
+    /// Write a score as a double in RESP3, and as a bulk string in RESP2.
+    fn _write_double(&mut self, value: f64) -> Result<(), ServerError> {
+        match self.version {
+            3 => {
+                write!(self.stream, ",{}\r\n", value)?;
+                Ok(())
+            }
+            _ => self._write_bulk(value.to_string().as_bytes()),
+        }
+    }
+
+    /// Write the given stream entries as an array of entries shaped like the ones of Redis streams: the ID,
+    /// then an array of the fields and their values, which is the value under a single `value` field.
+    fn _write_entries(&mut self, entries: &[(StreamId, Bytes)]) -> Result<(), ServerError> {
+        self._write_array_header(entries.len())?;
+        for (id, value) in entries {
+            self._write_array_header(2)?;
+            self._write_bulk(id.to_string().as_bytes())?;
+            self._write_array_header(2)?;
+            self._write_bulk(b"value")?;
+            self._write_bulk(value)?;
+        }
+        Ok(())
+    }
+
+    fn _write_integer(&mut self, value: i64) -> Result<(), ServerError> {
+        self._write_header(b':', value)
+    }
+
+    /// Write a bulk string (e.g. a value) straight from its buffer.
+    fn _write_bulk(&mut self, value: &[u8]) -> Result<(), ServerError> {
+        let length: Decimal = Decimal::unsigned(value.len() as u64);
+        write_all_vectored(
+            &mut self.stream,
+            &mut [
+                IoSlice::new(b"$"),
+                IoSlice::new(length.as_bytes()),
+                IoSlice::new(b"\r\n"),
+                IoSlice::new(value),
+                IoSlice::new(b"\r\n"),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Write the reply of a script, as the RESP type it stands for.
+    fn _write_reply(&mut self, reply: &Reply) -> Result<(), ServerError> {
+        match reply {
+            Reply::Nil => self._write_null(),
+            Reply::Integer(integer) => self._write_integer(*integer),
+            Reply::Bulk(value) => self._write_bulk(value),
+            Reply::Status(status) => self._write_simple(status),
+            Reply::Error(message) => self._write_error(message),
+            Reply::Array(items) => {
+                self._write_array_header(items.len())?;
+                for item in items {
+                    self._write_reply(item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn _write_null(&mut self) -> Result<(), ServerError> {
+        let null: &[u8] = match self.version {
+            3 => b"_\r\n",
+            _ => b"$-1\r\n",
+        };
+        self.stream.write_all(null)?;
+        Ok(())
+    }
+
+    /// Write a null array (e.g. the reply of a blocking command once its time is up): a null in RESP3.
+    fn _write_null_array(&mut self) -> Result<(), ServerError> {
+        let null: &[u8] = match self.version {
+            3 => b"_\r\n",
+            _ => b"*-1\r\n",
+        };
+        self.stream.write_all(null)?;
+        Ok(())
+    }
+}
+
+/// Encode a message published on a channel as pushed to a RESP2 client: an array of `message`, the
+/// channel and the message.
+fn encode_resp2_message(message: &Message) -> Vec<u8> {
+    encode_message(b'*', message)
+}
+
+/// Encode a message published on a channel as pushed to a RESP3 client: a push of `message`, the channel
+/// and the message.
+fn encode_resp3_message(message: &Message) -> Vec<u8> {
+    encode_message(b'>', message)
+}
+
+/// Encode a command of the monitor feed as pushed to a monitoring client: a simple string of its line (see
+/// `monitor::format_command`), which never spans several lines.
+fn encode_monitored_command(message: &Message) -> Vec<u8> {
+    let mut encoded: Vec<u8> = Vec::with_capacity(message.payload.len() + 3);
+    encoded.push(b'+');
+    encoded.extend_from_slice(&message.payload);
+    encoded.extend_from_slice(b"\r\n");
+    encoded
+}
+
+fn encode_message(header: u8, message: &Message) -> Vec<u8> {
+    let mut encoded: Vec<u8> =
+        Vec::with_capacity(32 + message.channel.len() + message.payload.len());
+    encoded.push(header);
+    encoded.extend_from_slice(b"3\r\n$7\r\nmessage\r\n");
+    for part in [message.channel.as_bytes(), message.payload.as_ref()] {
+        encoded.push(b'$');
+        encoded.extend_from_slice(Decimal::unsigned(part.len() as u64).as_bytes());
+        encoded.extend_from_slice(b"\r\n");
+        encoded.extend_from_slice(part);
+        encoded.extend_from_slice(b"\r\n");
+    }
+    encoded
+}
+
+/// The keys of the arguments of a command (whose name is in uppercase): the first argument of the commands on
+/// a key, every argument of `MGET`, `DEL`, `EXISTS`, `TOUCH`, the set combinations, `PFCOUNT`, `PFMERGE` and
+/// `WATCH`, the first two of `RENAME`, every other one of `MSET`, the one following the subcommand of
+/// `MEMORY` and `OBJECT`, every argument but the timeout of `BLPOP` and `BRPOP`, the number of keys given
+/// after the script of `EVAL`, and the first half of the ones following `STREAMS` in `XREAD`.
+pub(crate) fn command_keys<'a>(name: &str, arguments: &[&'a [u8]]) -> Vec<&'a [u8]> {
+    match name {
+        "GET" | "SET" | "EXPIRE" | "EXPIREAT" | "PEXPIREAT" | "EXPIRETIME" | "PEXPIRETIME"
+        | "PERSIST" | "TTL" | "TYPE" | "INCR" | "DECR" | "INCRBY" | "DECRBY" | "APPEND"
+        | "STRLEN" | "SETBIT" | "GETBIT" | "BITCOUNT" | "GETSET" | "GETDEL" | "HSET" | "HGET"
+        | "HDEL" | "HGETALL" | "LPUSH" | "RPUSH" | "LPOP" | "RPOP" | "LRANGE" | "PFADD"
+        | "SADD" | "SREM" | "SISMEMBER" | "SMEMBERS" | "ZADD" | "ZINCRBY" | "ZREM" | "ZSCORE"
+        | "ZRANGE" | "ZRANGEBYSCORE" | "XADD" | "XRANGE" | "XLEN" | "BF.RESERVE" | "BF.ADD"
+        | "BF.EXISTS" | "RATELIMIT" => arguments.iter().take(1).copied().collect(),
+        "MGET" | "DEL" | "EXISTS" | "TOUCH" | "SINTER" | "SUNION" | "SDIFF" | "PFCOUNT"
+        | "PFMERGE" | "WATCH" => arguments.to_vec(),
+        "RENAME" | "RENAMENX" => arguments.iter().take(2).copied().collect(),
+        "MSET" => arguments.iter().step_by(2).copied().collect(),
+        "MEMORY" | "OBJECT" => arguments.iter().skip(1).take(1).copied().collect(),
+        "BLPOP" | "BRPOP" => arguments[..arguments.len().saturating_sub(1)].to_vec(),
+        "EVAL" => {
+            let count: usize = arguments
+                .get(1)
+                .and_then(|count| parse_number(count))
+                .unwrap_or(0) as usize;
+            arguments.iter().skip(2).take(count).copied().collect()
+        }
+        "XREAD" => {
+            let streams: Vec<&[u8]> = arguments
+                .iter()
+                .skip_while(|argument| !argument.eq_ignore_ascii_case(b"STREAMS"))
+                .skip(1)
+                .copied()
+                .collect();
+            streams[..streams.len() / 2].to_vec()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Parse the command at the start of the given data: an array of bulk strings, or an inline command.
+pub(crate) fn parse(data: &[u8]) -> Parsed<'_> {
+    if data.first() != Some(&b'*') {
+        return parse_inline(data);
+    }
+
+    let Some((count, mut offset)) = parse_header(data) else {
+        return match memchr(b'\n', data) {
+            Some(_) => Parsed::Invalid("invalid multibulk length"),
+            None => Parsed::Incomplete,
+        };
+    };
+    if count > MAX_ARGUMENTS as i64 {
+        return Parsed::Invalid("invalid multibulk length");
+    }
+
+    let mut arguments: Vec<&[u8]> = Vec::with_capacity(count.max(0) as usize);
+    for _ in 0..count.max(0) {
+        let rest: &[u8] = &data[offset..];
+        if rest.is_empty() {
+            return Parsed::Incomplete;
+        }
+        if rest[0] != b'$' {
+            return Parsed::Invalid("expected '$'");
+        }
+
+        let Some((length, header)) = parse_header(rest) else {
+            return match memchr(b'\n', rest) {
+                Some(_) => Parsed::Invalid("invalid bulk length"),
+                None => Parsed::Incomplete,
+            };
+        };
+        if !(0..=MAX_BULK_SIZE as i64).contains(&length) {
+            return Parsed::Invalid("invalid bulk length");
+        }
+
+        let length: usize = length as usize;
+        if rest.len() < header + length + 2 {
+            return Parsed::Incomplete;
+        }
+        if &rest[header + length..header + length + 2] != b"\r\n" {
+            return Parsed::Invalid("expected CRLF after bulk string");
+        }
+
+        arguments.push(&rest[header..header + length]);
+        offset += header + length + 2;
+    }
+
+    Parsed::Command(arguments, offset)
+}
+
+/// Parse the header line of an array or a bulk string (e.g. `*3\r\n` or `$5\r\n`).
+///
+/// # Returns
+/// * The number it holds and the length of the line, or `None` if the line is incomplete or invalid.
+fn parse_header(data: &[u8]) -> Option<(i64, usize)> {
+    let end: usize = memchr(b'\n', data)?;
+    let line: &[u8] = data[1..end].strip_suffix(b"\r")?;
+    let number: i64 = str::from_utf8(line).ok()?.parse().ok()?;
+    Some((number, end + 1))
+}
+
+/// Parse an inline command, i.e. a line of whitespace separated arguments.
+fn parse_inline(data: &[u8]) -> Parsed<'_> {
+    match memchr(b'\n', data) {
+        Some(end) => Parsed::Command(Tokens::new(&data[..end]).collect(), end + 1),
+        None if data.len() > MAX_BULK_SIZE => Parsed::Invalid("too big inline request"),
+        None => Parsed::Incomplete,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(data: &[u8]) -> (Vec<&[u8]>, usize) {
+        match parse(data) {
+            Parsed::Command(arguments, length) => (arguments, length),
+            Parsed::Incomplete => panic!("incomplete command"),
+            Parsed::Invalid(error) => panic!("invalid command: {error}"),
+        }
+    }
+
+    fn invalid(data: &[u8]) -> &'static str {
+        match parse(data) {
+            Parsed::Invalid(error) => error,
+            _ => panic!("valid command"),
+        }
+    }
+
+    fn incomplete(data: &[u8]) -> bool {
+        matches!(parse(data), Parsed::Incomplete)
+    }
+
+    #[test]
+    fn parses_arrays_of_bulk_strings() {
+        let data: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$11\r\nhello world\r\n*1\r\n";
+        let expected: Vec<&[u8]> = vec![b"SET", b"key", b"hello world"];
+        assert_eq!(command(data), (expected, data.len() - 4));
+    }
+
+    #[test]
+    fn bulk_strings_hold_any_byte() {
+        let data: &[u8] = b"*2\r\n$3\r\nGET\r\n$4\r\n\r\n\0\xff\r\n";
+        let expected: Vec<&[u8]> = vec![b"GET", b"\r\n\0\xff"];
+        assert_eq!(command(data), (expected, data.len()));
+
+        let data: &[u8] = b"*1\r\n$0\r\n\r\n";
+        let expected: Vec<&[u8]> = vec![b""];
+        assert_eq!(command(data), (expected, data.len()));
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_command() {
+        let data: &[u8] = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
+        for end in 1..data.len() {
+            assert!(incomplete(&data[..end]), "{:?}", &data[..end]);
+        }
+        assert!(incomplete(b"PING"));
+    }
+
+    #[test]
+    fn rejects_malformed_framing() {
+        assert_eq!(invalid(b"*x\r\n"), "invalid multibulk length");
+        assert_eq!(invalid(b"*1\n"), "invalid multibulk length");
+        assert_eq!(invalid(b"*1\r\n+GET\r\n"), "expected '$'");
+        assert_eq!(invalid(b"*1\r\n$x\r\n"), "invalid bulk length");
+        assert_eq!(
+            invalid(b"*1\r\n$3\r\nGETX\r\n"),
+            "expected CRLF after bulk string"
+        );
+    }
+
+    #[test]
+    fn caps_lengths_before_reading_the_data() {
+        let data: Vec<u8> = format!("*{}\r\n", MAX_ARGUMENTS + 1).into_bytes();
+        assert_eq!(invalid(&data), "invalid multibulk length");
+        let data: Vec<u8> = format!("*1\r\n${}\r\n", MAX_BULK_SIZE + 1).into_bytes();
+        assert_eq!(invalid(&data), "invalid bulk length");
+        assert_eq!(invalid(b"*1\r\n$99999999999\r\n"), "invalid bulk length");
+        assert_eq!(invalid(b"*1\r\n$-1\r\n"), "invalid bulk length");
+    }
+
+    #[test]
+    fn parses_inline_commands() {
+        let expected: Vec<&[u8]> = vec![b"SET", b"key", b"value"];
+        assert_eq!(command(b"SET key  value\r\nGET"), (expected, 16));
+        assert_eq!(command(b"\n"), (Vec::new(), 1));
+
+        let data: Vec<u8> = vec![b'a'; MAX_BULK_SIZE + 1];
+        assert_eq!(invalid(&data), "too big inline request");
+    }
+}
@@ -0,0 +1,208 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    buffers::BufferBudget,
+    cache::MaxMemoryPolicy,
+    config::MIN_CONNECTION_BUFFER_LIMIT,
+    connection::IdleTimeout,
+    embedded::{EmbeddedCache, MAX_TTL_JITTER},
+    error::CacheError,
+    events::KeyspaceNotifications,
+    logging,
+    workers::{RejectionPolicy, WorkerPool},
+};
+
+/// The parameters which can be read and changed while the server runs, named after the fields of
+/// `ServerConfig`, in alphabetical order.
+const PARAMETERS: [&str; 16] = [
+    "connection_buffer_limit",
+    "idle_timeout_seconds",
+    "keyspace_notifications",
+    "log_level",
+    "max_key_bytes",
+    "max_value_bytes",
+    "maxmemory",
+    "maxmemory_policy",
+    "read_only",
+    "script_time_limit_ms",
+    "slowlog_max_len",
+    "slowlog_threshold_us",
+    "ttl_jitter_percent",
+    "worker_queue",
+    "worker_rejection",
+    "worker_threads",
+];
+
+/// The operational parameters of a running server, read and changed with `CONFIG GET` and `CONFIG SET`
+/// without restarting it. The changes are not saved: the server starts again from its configuration.
+pub(crate) struct RuntimeConfig {
+    cache: Arc<EmbeddedCache>, // Holds the TTL jitter, the keyspace notifications, the script time limit, etc.
+    buffers: Arc<BufferBudget>, // Holds the limit of the connection buffers
+    workers: Arc<WorkerPool>,  // Holds the sizing of the worker pool
+    idle_timeout: Arc<IdleTimeout>, // How long the connections may stay idle, shared with the listeners
+}
+
+impl RuntimeConfig {
+    pub fn new(
+        cache: Arc<EmbeddedCache>,
+        buffers: Arc<BufferBudget>,
+        workers: Arc<WorkerPool>,
+        idle_timeout: Arc<IdleTimeout>,
+    ) -> RuntimeConfig {
+        RuntimeConfig {
+            cache,
+            buffers,
+            workers,
+            idle_timeout,
+        }
+    }
+
+    /// The current values of the parameters matching the given pattern: a parameter name, or a prefix
+    /// followed by `*` (e.g. `worker_*`, or `*` for all of them). The names may be spelled as in Redis too
+    /// (see `parameter_name`).
+    ///
+    /// # Returns
+    /// * The names and values of the matching parameters, in alphabetical order.
+    pub fn get(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let pattern: String = parameter_name(pattern);
+        PARAMETERS
+            .iter()
+            .filter(|name| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => **name == pattern,
+            })
+            .map(|name| (*name, self._value(name)))
+            .collect()
+    }
+
+    /// Change the value of a parameter, taking effect right away. The name may be spelled as in Redis too
+    /// (see `parameter_name`).
+    ///
+    /// # Errors
+    /// * If the parameter is unknown, or the value is invalid for it.
+    pub fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        let name: &str = &parameter_name(name);
+        match name {
+            "connection_buffer_limit" => {
+                let limit: usize = parse(name, value)?;
+                if limit < MIN_CONNECTION_BUFFER_LIMIT {
+                    return Err(format!(
+                        "connection_buffer_limit must be at least {} bytes",
+                        MIN_CONNECTION_BUFFER_LIMIT
+                    ));
+                }
+                self.buffers.set_limit(limit);
+            }
+            "idle_timeout_seconds" => {
+                let seconds: u64 = parse(name, value)?;
+                self.idle_timeout
+                    .set((seconds > 0).then(|| Duration::from_secs(seconds)));
+            }
+            "keyspace_notifications" => self
+                .cache
+                .set_keyspace_notifications(value.parse::<KeyspaceNotifications>()?)
+                .map_err(|e| e.to_string())?,
+            "log_level" => logging::set_filters(value)?,
+            "max_key_bytes" => self.cache.set_max_key_bytes(parse(name, value)?),
+            "max_value_bytes" => self.cache.set_max_value_bytes(parse(name, value)?),
+            "maxmemory" => self
+                .cache
+                .set_max_memory(parse(name, value)?)
+                .map_err(max_memory_error)?,
+            "maxmemory_policy" => self
+                .cache
+                .set_max_memory_policy(value.parse::<MaxMemoryPolicy>()?)
+                .map_err(max_memory_error)?,
+            "read_only" => match value.parse::<bool>() {
+                Ok(read_only) => self.cache.set_read_only(read_only),
+                Err(_) => return Err(format!("read_only must be true or false, got '{}'", value)),
+            },
+            "script_time_limit_ms" => {
+                let limit: u64 = parse(name, value)?;
+                if limit == 0 {
+                    return Err("script_time_limit_ms must be at least 1".to_string());
+                }
+                self.cache.set_script_time_limit(limit);
+            }
+            "slowlog_max_len" => self
+                .cache
+                .slow_log()
+                .set_max_len(parse(name, value)?)
+                .map_err(|e| e.to_string())?,
+            "slowlog_threshold_us" => self.cache.slow_log().set_threshold_us(parse(name, value)?),
+            "ttl_jitter_percent" => {
+                let percent: u32 = parse(name, value)?;
+                if percent > MAX_TTL_JITTER {
+                    return Err(format!(
+                        "ttl_jitter_percent must be at most {}",
+                        MAX_TTL_JITTER
+                    ));
+                }
+                self.cache.set_ttl_jitter(percent);
+            }
+            "worker_queue" => self.workers.set_queue_depth(parse(name, value)?),
+            "worker_rejection" => self
+                .workers
+                .set_rejection(value.parse::<RejectionPolicy>()?),
+            "worker_threads" => {
+                let threads: usize = parse(name, value)?;
+                if threads == 0 {
+                    return Err("worker_threads must be at least 1".to_string());
+                }
+                self.workers.set_threads(threads);
+            }
+            _ => return Err(format!("unknown parameter '{}'", name)),
+        }
+        Ok(())
+    }
+
+    fn _value(&self, name: &str) -> String {
+        match name {
+            "connection_buffer_limit" => self.buffers.stats().limit.to_string(),
+            "idle_timeout_seconds" => self
+                .idle_timeout
+                .get()
+                .map_or(0, |timeout| timeout.as_secs())
+                .to_string(),
+            "keyspace_notifications" => self.cache.keyspace_notifications().to_string(),
+            "log_level" => logging::filters().unwrap_or_else(|| log::max_level().to_string()),
+            "max_key_bytes" => self.cache.max_key_bytes().to_string(),
+            "max_value_bytes" => self.cache.max_value_bytes().to_string(),
+            "maxmemory" => self.cache.max_memory().0.to_string(),
+            "maxmemory_policy" => self.cache.max_memory().1.to_string(),
+            "read_only" => self.cache.is_read_only().to_string(),
+            "script_time_limit_ms" => self.cache.script_time_limit().to_string(),
+            "slowlog_max_len" => self.cache.slow_log().max_len().to_string(),
+            "slowlog_threshold_us" => self.cache.slow_log().threshold_us().to_string(),
+            "ttl_jitter_percent" => self.cache.ttl_jitter().to_string(),
+            "worker_queue" => self.workers.options().queue_depth.to_string(),
+            "worker_rejection" => self.workers.options().rejection.to_string(),
+            "worker_threads" => self.workers.options().threads.to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// The name of a parameter as given by a client, in the spelling of `ServerConfig`: case-insensitive, and
+/// with `-` for `_` as in Redis (e.g. `maxmemory-policy` for `maxmemory_policy`).
+fn parameter_name(name: &str) -> String {
+    name.to_ascii_lowercase().replace('-', "_")
+}
+
+/// The error of a max memory the storage engine cannot take.
+fn max_memory_error(error: CacheError) -> String {
+    match error {
+        CacheError::Unsupported(_) => {
+            "maxmemory is only enforced by the lru and lfu storage engines, the others having no limit"
+                .to_string()
+        }
+        error => error.to_string(),
+    }
+}
+
+/// Parse the value of a numeric parameter.
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("{} must be a non-negative integer, got '{}'", name, value))
+}
@@ -0,0 +1,2230 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
+};
+
+/// The deepest nesting of the blocks, expressions and function calls of a script.
+const MAX_DEPTH: usize = 200;
+
+/// How much of the stack of its thread a script may take to be parsed or run, in bytes, beyond which it
+/// fails rather than overflowing the stack (the threads serving the clients have the default 2 MiB).
+const MAX_STACK_SIZE: usize = 512 * 1024;
+
+/// The number of steps executed between two checks of the time limit of a script.
+const STEPS_PER_CHECK: u32 = 1024;
+
+/// The longest string a script may build (e.g. with `..` or `string.rep`).
+const MAX_STRING_LENGTH: usize = 64 * 1024 * 1024;
+
+const KEYWORDS: [&str; 21] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+// Ordered so that the longest symbols are matched first
+const SYMBOLS: [&str; 26] = [
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(",
+    ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+/// The reply of a script, or of a command called by a script, converted from and to the values of the
+/// script the way Redis converts them (e.g. a table `{ok = 'done'}` is a status reply, and a nil bulk
+/// reply is `false`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Nil,
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Status(String),
+    Error(String),
+    Array(Vec<Reply>),
+}
+
+/// A script of `EVAL`, written in a subset of Lua 5.1: local and global variables, tables, functions
+/// (with closures), `if`, `while`, `repeat`, numeric and generic `for` loops, and the usual operators.
+/// Coroutines, metatables, varargs and `goto` are not supported, nor is most of the standard library: it
+/// has `tonumber`, `tostring`, `type`, `pairs`, `ipairs`, `next`, `unpack`, `error` and `assert`, along
+/// with `string.len`, `sub`, `upper`, `lower` and `rep`, `table.insert`, `remove` and `concat`, and
+/// `math.floor`, `ceil`, `abs`, `max`, `min` and `huge`.
+///
+/// The script calls the commands of the cache with `redis.call` (failing on an error reply) and
+/// `redis.pcall` (returning the error reply as a table `{err = ...}`), and finds its keys and arguments in
+/// the `KEYS` and `ARGV` tables.
+pub struct Script {
+    body: Block,
+}
+
+impl Script {
+    /// Parse the source of a script.
+    ///
+    /// # Errors
+    /// * The syntax error, with its line (e.g. `line 2: 'end' expected near '<eof>'`).
+    pub fn parse(source: &[u8]) -> Result<Script, String> {
+        let tokens: Vec<(Token, usize)> = tokenize(source)?;
+        let mut parser: Parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+            loops: 0,
+            stack_base: stack_address(),
+        };
+        let body: Block = parser.block()?;
+        match parser.peek() {
+            Token::Eof => Ok(Script { body }),
+            _ => Err(parser.error("'<eof>' expected")),
+        }
+    }
+
+    /// Run the script to its end, or until it fails or exceeds its time limit.
+    ///
+    /// # Arguments
+    /// * `keys` - The keys the script works on, i.e. its `KEYS` table.
+    /// * `args` - The other arguments of the script, i.e. its `ARGV` table.
+    /// * `time_limit` - How long the script may run.
+    /// * `call` - Executes the commands called by the script (with `redis.call`), e.g. `GET counter`.
+    ///
+    /// # Returns
+    /// * The reply of the script, i.e. the value it returns.
+    ///
+    /// # Errors
+    /// * The runtime error of the script, with its line (e.g. `line 3: attempt to index a nil value`).
+    pub fn run(
+        &self,
+        keys: &[&[u8]],
+        args: &[&[u8]],
+        time_limit: Duration,
+        call: &mut dyn FnMut(&[Vec<u8>]) -> Reply,
+    ) -> Result<Reply, String> {
+        let mut interpreter: Interpreter = Interpreter::new(call, time_limit);
+        let keys: Value = interpreter.array(keys.iter().map(|key| string(key)).collect());
+        let args: Value = interpreter.array(args.iter().map(|arg| string(arg)).collect());
+        interpreter.globals.insert("KEYS".to_string(), keys);
+        interpreter.globals.insert("ARGV".to_string(), args);
+
+        let root: Rc<Scope> = Rc::new(Scope::new(None));
+        let result: Eval<Reply> = match interpreter.exec_statements(&self.body, &root) {
+            Ok(Flow::Return(values)) => to_reply(values.first().unwrap_or(&Value::Nil), 0),
+            Ok(_) => Ok(Reply::Nil),
+            Err(e) => Err(e),
+        };
+        interpreter.release();
+        result.map_err(|e| single_line(e.to_string().as_bytes()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Keyword(&'static str),
+    Number(f64),
+    Str(Vec<u8>),
+    Symbol(&'static str),
+    Eof,
+}
+
+impl Token {
+    /// How the token is shown in the syntax errors, e.g. `'end'`.
+    fn describe(&self) -> String {
+        match self {
+            Token::Name(name) => format!("'{}'", name),
+            Token::Keyword(keyword) => format!("'{}'", keyword),
+            Token::Number(number) => format!("'{}'", format_number(*number)),
+            Token::Str(text) => format!("'{}'", String::from_utf8_lossy(text)),
+            Token::Symbol(symbol) => format!("'{}'", symbol),
+            Token::Eof => "'<eof>'".to_string(),
+        }
+    }
+}
+
+/// Split the source of a script into its tokens, each with its line.
+fn tokenize(source: &[u8]) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut position: usize = 0;
+    let mut line: usize = 1;
+
+    while position < source.len() {
+        let rest: &[u8] = &source[position..];
+        let c: u8 = rest[0];
+        if c == b'\n' {
+            line += 1;
+            position += 1;
+        } else if c.is_ascii_whitespace() {
+            position += 1;
+        } else if rest.starts_with(b"--") {
+            position += 2;
+            match long_bracket(&source[position..]) {
+                Some(level) => position = read_long_string(source, position, level, &mut line)?.1,
+                None => {
+                    while position < source.len() && source[position] != b'\n' {
+                        position += 1;
+                    }
+                }
+            }
+        } else if let Some(level) = long_bracket(rest) {
+            let start_line: usize = line;
+            let (text, end): (Vec<u8>, usize) =
+                read_long_string(source, position, level, &mut line)?;
+            tokens.push((Token::Str(text), start_line));
+            position = end;
+        } else if c == b'"' || c == b'\'' {
+            let (text, end): (Vec<u8>, usize) = read_quoted_string(source, position, line)?;
+            tokens.push((Token::Str(text), line));
+            position = end;
+        } else if c.is_ascii_digit() || (c == b'.' && rest.get(1).is_some_and(u8::is_ascii_digit)) {
+            let mut end: usize = position;
+            while end < source.len()
+                && (source[end].is_ascii_alphanumeric()
+                    || source[end] == b'.'
+                    || ((source[end] == b'+' || source[end] == b'-')
+                        && matches!(source[end - 1], b'e' | b'E')
+                        && !source[position..end].starts_with(b"0x")))
+            {
+                end += 1;
+            }
+            let text: &str = std::str::from_utf8(&source[position..end]).unwrap_or_default();
+            let Some(number) = parse_number(text) else {
+                return Err(format!("line {}: malformed number near '{}'", line, text));
+            };
+            tokens.push((Token::Number(number), line));
+            position = end;
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let mut end: usize = position;
+            while end < source.len() && (source[end].is_ascii_alphanumeric() || source[end] == b'_')
+            {
+                end += 1;
+            }
+            let name: &str = std::str::from_utf8(&source[position..end]).unwrap_or_default();
+            let token: Token = match KEYWORDS.iter().find(|keyword| **keyword == name) {
+                Some(keyword) => Token::Keyword(keyword),
+                None => Token::Name(name.to_string()),
+            };
+            tokens.push((token, line));
+            position = end;
+        } else {
+            let Some(symbol) = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(symbol.as_bytes()))
+            else {
+                return Err(format!(
+                    "line {}: unexpected symbol near '{}'",
+                    line,
+                    String::from_utf8_lossy(&rest[..1])
+                ));
+            };
+            tokens.push((Token::Symbol(symbol), line));
+            position += symbol.len();
+        }
+    }
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+/// The level of the long bracket the given text starts with (e.g. 1 for `[=[`), if any.
+fn long_bracket(text: &[u8]) -> Option<usize> {
+    let rest: &[u8] = text.strip_prefix(b"[")?;
+    let level: usize = rest.iter().take_while(|c| **c == b'=').count();
+    (rest.get(level) == Some(&b'[')).then_some(level)
+}
+
+/// Read a long string (or comment) starting at the given position, e.g. `[[text]]`.
+///
+/// # Returns
+/// * The text of the string, and the position right after its closing bracket.
+fn read_long_string(
+    source: &[u8],
+    position: usize,
+    level: usize,
+    line: &mut usize,
+) -> Result<(Vec<u8>, usize), String> {
+    let closing: Vec<u8> = [&b"]"[..], &vec![b'='; level], b"]"].concat();
+    let mut start: usize = position + level + 2;
+    // A newline right after the opening bracket is not part of the string
+    if source.get(start) == Some(&b'\n') {
+        *line += 1;
+        start += 1;
+    }
+    let mut end: usize = start;
+    while end < source.len() {
+        if source[end..].starts_with(&closing) {
+            return Ok((source[start..end].to_vec(), end + closing.len()));
+        }
+        if source[end] == b'\n' {
+            *line += 1;
+        }
+        end += 1;
+    }
+    Err(format!("line {}: unfinished long string", line))
+}
+
+/// Read a quoted string starting at the given position, e.g. `'it\'s'`.
+///
+/// # Returns
+/// * The text of the string, with its escape sequences replaced, and the position right after it.
+fn read_quoted_string(
+    source: &[u8],
+    position: usize,
+    line: usize,
+) -> Result<(Vec<u8>, usize), String> {
+    let quote: u8 = source[position];
+    let mut text: Vec<u8> = Vec::new();
+    let mut index: usize = position + 1;
+    loop {
+        match source.get(index) {
+            None | Some(b'\n') => return Err(format!("line {}: unfinished string", line)),
+            Some(c) if *c == quote => return Ok((text, index + 1)),
+            Some(b'\\') => {
+                index += 1;
+                let escaped: u8 = match source.get(index) {
+                    Some(b'n') => b'\n',
+                    Some(b't') => b'\t',
+                    Some(b'r') => b'\r',
+                    Some(b'a') => 0x07,
+                    Some(b'b') => 0x08,
+                    Some(b'f') => 0x0c,
+                    Some(b'v') => 0x0b,
+                    Some(c @ (b'\\' | b'"' | b'\'' | b'\n')) => *c,
+                    Some(c) if c.is_ascii_digit() => {
+                        let digits: usize = source[index..]
+                            .iter()
+                            .take(3)
+                            .take_while(|c| c.is_ascii_digit())
+                            .count();
+                        let code: u32 = std::str::from_utf8(&source[index..index + digits])
+                            .unwrap_or_default()
+                            .parse::<u32>()
+                            .unwrap_or(u32::MAX);
+                        let Ok(code) = u8::try_from(code) else {
+                            return Err(format!("line {}: escape sequence too large", line));
+                        };
+                        index += digits - 1;
+                        code
+                    }
+                    _ => return Err(format!("line {}: invalid escape sequence", line)),
+                };
+                text.push(escaped);
+                index += 1;
+            }
+            Some(c) => {
+                text.push(*c);
+                index += 1;
+            }
+        }
+    }
+}
+
+/// Parse a number written in a script, or converted from a string (e.g. `42`, `0.5`, `1e3` or `0xff`).
+fn parse_number(text: &str) -> Option<f64> {
+    let text: &str = text.trim();
+    let (negative, unsigned): (bool, &str) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text),
+    };
+    if let Some(digits) = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    {
+        let number: f64 = u64::from_str_radix(digits, 16).ok()? as f64;
+        return Some(if negative { -number } else { number });
+    }
+    // Rust also takes `inf` and `NaN`, which Lua does not
+    if text.is_empty()
+        || !text
+            .bytes()
+            .all(|c| c.is_ascii_digit() || b"+-.eE".contains(&c))
+    {
+        return None;
+    }
+    text.parse::<f64>().ok()
+}
+
+type Block = Vec<(usize, Stat)>; // The statements, each with its line
+
+enum Stat {
+    Local(Vec<String>, Vec<Expr>),
+    LocalFunction(String, Rc<Function>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    If(Vec<(Expr, Block)>, Option<Block>),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    NumericFor(String, Expr, Expr, Option<Expr>, Block),
+    GenericFor(Vec<String>, Vec<Expr>, Block),
+    Do(Block),
+    Return(Vec<Expr>),
+    Break,
+}
+
+enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    Str(Rc<[u8]>),
+    Name(String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Method(Box<Expr>, String, Vec<Expr>),
+    Function(Rc<Function>),
+    Table(Vec<(Option<Expr>, Expr)>), // The fields, without a key for the positional ones
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+    Paren(Box<Expr>), // Keeps only the first value of a function call
+}
+
+struct Function {
+    parameters: Vec<String>,
+    body: Block,
+}
+
+#[derive(Clone, Copy)]
+enum UnaryOperator {
+    Negate,
+    Not,
+    Length,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BinaryOperator {
+    Or,
+    And,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    NotEqual,
+    Equal,
+    Concat,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+}
+
+/// The priority of the unary operators, above every binary operator but `^`.
+const UNARY_PRIORITY: u8 = 8;
+
+/// The binary operator of the given token, with its left and right priorities (the right one being
+/// lower for the right associative operators).
+fn binary_operator(token: &Token) -> Option<(BinaryOperator, u8, u8)> {
+    let operator: (BinaryOperator, u8, u8) = match token {
+        Token::Keyword("or") => (BinaryOperator::Or, 1, 1),
+        Token::Keyword("and") => (BinaryOperator::And, 2, 2),
+        Token::Symbol("<") => (BinaryOperator::Less, 3, 3),
+        Token::Symbol(">") => (BinaryOperator::Greater, 3, 3),
+        Token::Symbol("<=") => (BinaryOperator::LessOrEqual, 3, 3),
+        Token::Symbol(">=") => (BinaryOperator::GreaterOrEqual, 3, 3),
+        Token::Symbol("~=") => (BinaryOperator::NotEqual, 3, 3),
+        Token::Symbol("==") => (BinaryOperator::Equal, 3, 3),
+        Token::Symbol("..") => (BinaryOperator::Concat, 5, 4),
+        Token::Symbol("+") => (BinaryOperator::Add, 6, 6),
+        Token::Symbol("-") => (BinaryOperator::Subtract, 6, 6),
+        Token::Symbol("*") => (BinaryOperator::Multiply, 7, 7),
+        Token::Symbol("/") => (BinaryOperator::Divide, 7, 7),
+        Token::Symbol("%") => (BinaryOperator::Modulo, 7, 7),
+        Token::Symbol("^") => (BinaryOperator::Power, 10, 9),
+        _ => return None,
+    };
+    Some(operator)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    depth: usize,      // The nesting of the blocks and expressions being parsed
+    loops: usize,      // The number of loops around the statement being parsed, within its function
+    stack_base: usize, // The address of the stack where the parsing started (see `stack_address`)
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        let index: usize = (self.position + offset).min(self.tokens.len() - 1);
+        &self.tokens[index].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.position].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token: Token = self.tokens[self.position].0.clone();
+        if self.position < self.tokens.len() - 1 {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!(
+            "line {}: {} near {}",
+            self.line(),
+            message,
+            self.peek().describe()
+        )
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        let accepted: bool = matches!(self.peek(), Token::Symbol(s) if *s == symbol);
+        if accepted {
+            self.advance();
+        }
+        accepted
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        match self.accept_symbol(symbol) {
+            true => Ok(()),
+            false => Err(self.error(&format!("'{}' expected", symbol))),
+        }
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let accepted: bool = matches!(self.peek(), Token::Keyword(k) if *k == keyword);
+        if accepted {
+            self.advance();
+        }
+        accepted
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.accept_keyword(keyword) {
+            true => Ok(()),
+            false => Err(self.error(&format!("'{}' expected", keyword))),
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<String, String> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error("<name> expected")),
+        }
+    }
+
+    /// Go one level deeper into the blocks and expressions, failing beyond `MAX_DEPTH` or
+    /// `MAX_STACK_SIZE`.
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH || self.stack_base.abs_diff(stack_address()) > MAX_STACK_SIZE {
+            true => Err(self.error("chunk has too many syntax levels")),
+            false => Ok(()),
+        }
+    }
+
+    fn block(&mut self) -> Result<Block, String> {
+        self.enter()?;
+        let mut block: Block = Vec::new();
+        loop {
+            let line: usize = self.line();
+            match self.peek() {
+                Token::Eof | Token::Keyword("end" | "else" | "elseif" | "until") => break,
+                Token::Symbol(";") => {
+                    self.advance();
+                }
+                Token::Keyword("return") => {
+                    self.advance();
+                    let values: Vec<Expr> = match self.peek() {
+                        Token::Eof
+                        | Token::Symbol(";")
+                        | Token::Keyword("end" | "else" | "elseif" | "until") => Vec::new(),
+                        _ => self.expression_list()?,
+                    };
+                    self.accept_symbol(";");
+                    block.push((line, Stat::Return(values)));
+                    // A return statement ends its block
+                    if !matches!(
+                        self.peek(),
+                        Token::Eof | Token::Keyword("end" | "else" | "elseif" | "until")
+                    ) {
+                        return Err(self.error("'end' expected"));
+                    }
+                    break;
+                }
+                _ => {
+                    let statement: Stat = self.statement()?;
+                    block.push((line, statement));
+                }
+            }
+        }
+        self.depth -= 1;
+        Ok(block)
+    }
+
+    fn loop_block(&mut self) -> Result<Block, String> {
+        self.loops += 1;
+        let block: Block = self.block()?;
+        self.loops -= 1;
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<Stat, String> {
+        match self.peek() {
+            Token::Keyword("if") => {
+                self.advance();
+                let mut branches: Vec<(Expr, Block)> = Vec::new();
+                loop {
+                    let condition: Expr = self.expression()?;
+                    self.expect_keyword("then")?;
+                    branches.push((condition, self.block()?));
+                    if !self.accept_keyword("elseif") {
+                        break;
+                    }
+                }
+                let otherwise: Option<Block> = match self.accept_keyword("else") {
+                    true => Some(self.block()?),
+                    false => None,
+                };
+                self.expect_keyword("end")?;
+                Ok(Stat::If(branches, otherwise))
+            }
+            Token::Keyword("while") => {
+                self.advance();
+                let condition: Expr = self.expression()?;
+                self.expect_keyword("do")?;
+                let body: Block = self.loop_block()?;
+                self.expect_keyword("end")?;
+                Ok(Stat::While(condition, body))
+            }
+            Token::Keyword("do") => {
+                self.advance();
+                let body: Block = self.block()?;
+                self.expect_keyword("end")?;
+                Ok(Stat::Do(body))
+            }
+            Token::Keyword("repeat") => {
+                self.advance();
+                let body: Block = self.loop_block()?;
+                self.expect_keyword("until")?;
+                Ok(Stat::Repeat(body, self.expression()?))
+            }
+            Token::Keyword("for") => {
+                self.advance();
+                let name: String = self.expect_name()?;
+                if self.accept_symbol("=") {
+                    let start: Expr = self.expression()?;
+                    self.expect_symbol(",")?;
+                    let limit: Expr = self.expression()?;
+                    let step: Option<Expr> = match self.accept_symbol(",") {
+                        true => Some(self.expression()?),
+                        false => None,
+                    };
+                    self.expect_keyword("do")?;
+                    let body: Block = self.loop_block()?;
+                    self.expect_keyword("end")?;
+                    return Ok(Stat::NumericFor(name, start, limit, step, body));
+                }
+                let mut names: Vec<String> = vec![name];
+                while self.accept_symbol(",") {
+                    names.push(self.expect_name()?);
+                }
+                self.expect_keyword("in")?;
+                let values: Vec<Expr> = self.expression_list()?;
+                self.expect_keyword("do")?;
+                let body: Block = self.loop_block()?;
+                self.expect_keyword("end")?;
+                Ok(Stat::GenericFor(names, values, body))
+            }
+            Token::Keyword("function") => {
+                self.advance();
+                let mut target: Expr = Expr::Name(self.expect_name()?);
+                while self.accept_symbol(".") {
+                    let key: String = self.expect_name()?;
+                    target =
+                        Expr::Index(Box::new(target), Box::new(Expr::Str(key.as_bytes().into())));
+                }
+                if matches!(self.peek(), Token::Symbol(":")) {
+                    return Err(self.error("methods are not supported"));
+                }
+                let function: Rc<Function> = self.function_body()?;
+                Ok(Stat::Assign(vec![target], vec![Expr::Function(function)]))
+            }
+            Token::Keyword("local") => {
+                self.advance();
+                if self.accept_keyword("function") {
+                    let name: String = self.expect_name()?;
+                    return Ok(Stat::LocalFunction(name, self.function_body()?));
+                }
+                let mut names: Vec<String> = vec![self.expect_name()?];
+                while self.accept_symbol(",") {
+                    names.push(self.expect_name()?);
+                }
+                let values: Vec<Expr> = match self.accept_symbol("=") {
+                    true => self.expression_list()?,
+                    false => Vec::new(),
+                };
+                Ok(Stat::Local(names, values))
+            }
+            Token::Keyword("break") => {
+                if self.loops == 0 {
+                    return Err(self.error("no loop to break"));
+                }
+                self.advance();
+                Ok(Stat::Break)
+            }
+            _ => {
+                let expression: Expr = self.suffixed_expression()?;
+                if !matches!(self.peek(), Token::Symbol("=" | ",")) {
+                    return match expression {
+                        Expr::Call(..) | Expr::Method(..) => Ok(Stat::Call(expression)),
+                        _ => Err(self.error("syntax error")),
+                    };
+                }
+                let mut targets: Vec<Expr> = vec![expression];
+                while self.accept_symbol(",") {
+                    targets.push(self.suffixed_expression()?);
+                }
+                if targets
+                    .iter()
+                    .any(|target| !matches!(target, Expr::Name(_) | Expr::Index(..)))
+                {
+                    return Err(self.error("syntax error"));
+                }
+                self.expect_symbol("=")?;
+                Ok(Stat::Assign(targets, self.expression_list()?))
+            }
+        }
+    }
+
+    /// Parse the parameters and the body of a function, after its name (if any).
+    fn function_body(&mut self) -> Result<Rc<Function>, String> {
+        self.expect_symbol("(")?;
+        let mut parameters: Vec<String> = Vec::new();
+        if !self.accept_symbol(")") {
+            loop {
+                if matches!(self.peek(), Token::Symbol("...")) {
+                    return Err(self.error("varargs are not supported"));
+                }
+                parameters.push(self.expect_name()?);
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+            self.expect_symbol(")")?;
+        }
+        // The loops around the function cannot be broken from it
+        let loops: usize = std::mem::take(&mut self.loops);
+        let body: Block = self.block()?;
+        self.loops = loops;
+        self.expect_keyword("end")?;
+        Ok(Rc::new(Function { parameters, body }))
+    }
+
+    fn expression_list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut expressions: Vec<Expr> = vec![self.expression()?];
+        while self.accept_symbol(",") {
+            expressions.push(self.expression()?);
+        }
+        Ok(expressions)
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        self.subexpression(0)
+    }
+
+    /// Parse an expression whose binary operators have a higher priority than the given limit.
+    fn subexpression(&mut self, limit: u8) -> Result<Expr, String> {
+        let depth: usize = self.depth;
+        self.enter()?;
+        let unary: Option<UnaryOperator> = match self.peek() {
+            Token::Symbol("-") => Some(UnaryOperator::Negate),
+            Token::Keyword("not") => Some(UnaryOperator::Not),
+            Token::Symbol("#") => Some(UnaryOperator::Length),
+            _ => None,
+        };
+        let mut left: Expr = match unary {
+            Some(operator) => {
+                self.advance();
+                Expr::Unary(operator, Box::new(self.subexpression(UNARY_PRIORITY)?))
+            }
+            None => self.simple_expression()?,
+        };
+        while let Some((operator, left_priority, right_priority)) = binary_operator(self.peek()) {
+            if left_priority <= limit {
+                break;
+            }
+            // Every operator nests the expression deeper, e.g. `a + b + c` is `(a + b) + c`
+            self.enter()?;
+            self.advance();
+            let right: Expr = self.subexpression(right_priority)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn simple_expression(&mut self) -> Result<Expr, String> {
+        let expression: Expr = match self.peek().clone() {
+            Token::Number(number) => Expr::Number(number),
+            Token::Str(text) => Expr::Str(text.into()),
+            Token::Keyword("nil") => Expr::Nil,
+            Token::Keyword("true") => Expr::True,
+            Token::Keyword("false") => Expr::False,
+            Token::Keyword("function") => {
+                self.advance();
+                return Ok(Expr::Function(self.function_body()?));
+            }
+            Token::Symbol("{") => {
+                self.advance();
+                return self.table_constructor();
+            }
+            Token::Symbol("...") => return Err(self.error("varargs are not supported")),
+            _ => return self.suffixed_expression(),
+        };
+        self.advance();
+        Ok(expression)
+    }
+
+    fn primary_expression(&mut self) -> Result<Expr, String> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                self.advance();
+                Ok(Expr::Name(name))
+            }
+            Token::Symbol("(") => {
+                self.advance();
+                let expression: Expr = self.expression()?;
+                self.expect_symbol(")")?;
+                Ok(Expr::Paren(Box::new(expression)))
+            }
+            _ => Err(self.error("unexpected symbol")),
+        }
+    }
+
+    /// Parse a primary expression followed by its fields, indexes and calls, e.g. `redis.call('GET', k)`.
+    fn suffixed_expression(&mut self) -> Result<Expr, String> {
+        let depth: usize = self.depth;
+        let mut expression: Expr = self.primary_expression()?;
+        loop {
+            expression = match self.peek() {
+                Token::Symbol(".") => {
+                    self.advance();
+                    let key: String = self.expect_name()?;
+                    Expr::Index(
+                        Box::new(expression),
+                        Box::new(Expr::Str(key.as_bytes().into())),
+                    )
+                }
+                Token::Symbol("[") => {
+                    self.advance();
+                    let key: Expr = self.expression()?;
+                    self.expect_symbol("]")?;
+                    Expr::Index(Box::new(expression), Box::new(key))
+                }
+                Token::Symbol(":") => {
+                    self.advance();
+                    let name: String = self.expect_name()?;
+                    Expr::Method(Box::new(expression), name, self.call_arguments()?)
+                }
+                Token::Symbol("(" | "{") | Token::Str(_) => {
+                    Expr::Call(Box::new(expression), self.call_arguments()?)
+                }
+                _ => break,
+            };
+            self.enter()?;
+        }
+        self.depth = depth;
+        Ok(expression)
+    }
+
+    fn call_arguments(&mut self) -> Result<Vec<Expr>, String> {
+        match self.advance() {
+            Token::Str(text) => Ok(vec![Expr::Str(text.into())]),
+            Token::Symbol("{") => Ok(vec![self.table_constructor()?]),
+            Token::Symbol("(") => {
+                if self.accept_symbol(")") {
+                    return Ok(Vec::new());
+                }
+                let arguments: Vec<Expr> = self.expression_list()?;
+                self.expect_symbol(")")?;
+                Ok(arguments)
+            }
+            _ => Err(self.error("function arguments expected")),
+        }
+    }
+
+    /// Parse the fields of a table constructor, after its opening brace.
+    fn table_constructor(&mut self) -> Result<Expr, String> {
+        let mut fields: Vec<(Option<Expr>, Expr)> = Vec::new();
+        while !self.accept_symbol("}") {
+            if self.accept_symbol("[") {
+                let key: Expr = self.expression()?;
+                self.expect_symbol("]")?;
+                self.expect_symbol("=")?;
+                fields.push((Some(key), self.expression()?));
+            } else if matches!(
+                (self.peek(), self.peek_at(1)),
+                (Token::Name(_), Token::Symbol("="))
+            ) {
+                let key: String = self.expect_name()?;
+                self.advance();
+                fields.push((Some(Expr::Str(key.as_bytes().into())), self.expression()?));
+            } else {
+                fields.push((None, self.expression()?));
+            }
+            if !self.accept_symbol(",") && !self.accept_symbol(";") {
+                self.expect_symbol("}")?;
+                break;
+            }
+        }
+        Ok(Expr::Table(fields))
+    }
+}
+
+#[derive(Clone)]
+enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Str(Rc<[u8]>),
+    Table(Rc<RefCell<Table>>),
+    Function(Rc<Closure>),
+    Builtin(Builtin),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) | Value::Builtin(_) => "function",
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// The number held by the value, converting a string the way Lua does for arithmetic.
+    fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::Str(text) => parse_number(std::str::from_utf8(text).ok()?),
+            _ => None,
+        }
+    }
+
+    /// The string held by the value, converting a number the way Lua does for concatenation.
+    fn to_bytes(&self) -> Option<Rc<[u8]>> {
+        match self {
+            Value::Str(text) => Some(Rc::clone(text)),
+            Value::Number(number) => Some(format_number(*number).as_bytes().into()),
+            _ => None,
+        }
+    }
+
+    /// The value as shown by `tostring`, e.g. `nil` or `table: 0x55d0c3a4e2a0`.
+    fn to_display(&self) -> Rc<[u8]> {
+        match self {
+            Value::Nil => b"nil"[..].into(),
+            Value::Boolean(boolean) => boolean.to_string().as_bytes().into(),
+            Value::Number(number) => format_number(*number).as_bytes().into(),
+            Value::Str(text) => Rc::clone(text),
+            Value::Table(table) => format!("table: {:p}", Rc::as_ptr(table)).as_bytes().into(),
+            Value::Function(function) => format!("function: {:p}", Rc::as_ptr(function))
+                .as_bytes()
+                .into(),
+            Value::Builtin(builtin) => format!("function: builtin: {}", builtin.name())
+                .as_bytes()
+                .into(),
+        }
+    }
+}
+
+/// The address of a local variable of the caller's frame, telling how deep the stack of the thread is.
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker: u8 = 0;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+fn string(text: &[u8]) -> Value {
+    Value::Str(text.into())
+}
+
+/// Format a number the way Lua does, e.g. `3` rather than `3.0`.
+fn format_number(number: f64) -> String {
+    if number.fract() == 0.0 && number.abs() < 1e15 {
+        format!("{}", number as i64)
+    } else if number.is_nan() {
+        "nan".to_string()
+    } else if number.is_infinite() {
+        (if number > 0.0 { "inf" } else { "-inf" }).to_string()
+    } else {
+        format!("{}", number)
+    }
+}
+
+/// Whether two values are equal without conversion: tables and functions are only equal to themselves.
+fn raw_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Boolean(left), Value::Boolean(right)) => left == right,
+        (Value::Number(left), Value::Number(right)) => left == right,
+        (Value::Str(left), Value::Str(right)) => left == right,
+        (Value::Table(left), Value::Table(right)) => Rc::ptr_eq(left, right),
+        (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+        (Value::Builtin(left), Value::Builtin(right)) => left == right,
+        _ => false,
+    }
+}
+
+/// A function of a script, with the scope it was defined in.
+struct Closure {
+    function: Rc<Function>,
+    scope: Rc<Scope>,
+}
+
+/// The local variables of a block, within those of the blocks around it.
+struct Scope {
+    variables: RefCell<Vec<(String, Rc<RefCell<Value>>)>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    fn new(parent: Option<Rc<Scope>>) -> Scope {
+        Scope {
+            variables: RefCell::new(Vec::new()),
+            parent,
+        }
+    }
+
+    fn declare(&self, name: &str, value: Value) -> Rc<RefCell<Value>> {
+        let variable: Rc<RefCell<Value>> = Rc::new(RefCell::new(value));
+        self.variables
+            .borrow_mut()
+            .push((name.to_string(), Rc::clone(&variable)));
+        variable
+    }
+
+    /// The local variable with the given name, the latest declared one hiding the others.
+    fn lookup(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let variables = self.variables.borrow();
+        match variables
+            .iter()
+            .rev()
+            .find(|(variable, _)| variable == name)
+        {
+            Some((_, value)) => Some(Rc::clone(value)),
+            None => self.parent.as_ref()?.lookup(name),
+        }
+    }
+}
+
+/// A key of a table: tables and functions are keyed by their address, which stays the same as long as
+/// they are kept by the table (along with the value).
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Boolean(bool),
+    Integer(i64),
+    Float(u64),
+    Str(Rc<[u8]>),
+    Reference(usize),
+    Builtin(Builtin),
+}
+
+impl Key {
+    fn of(value: &Value) -> Eval<Key> {
+        match value {
+            Value::Nil => fail("table index is nil"),
+            Value::Boolean(boolean) => Ok(Key::Boolean(*boolean)),
+            Value::Number(number) if number.is_nan() => fail("table index is NaN"),
+            Value::Number(number) if number.fract() == 0.0 && number.abs() < 9.2e18 => {
+                Ok(Key::Integer(*number as i64))
+            }
+            Value::Number(number) => Ok(Key::Float(number.to_bits())),
+            Value::Str(text) => Ok(Key::Str(Rc::clone(text))),
+            Value::Table(table) => Ok(Key::Reference(Rc::as_ptr(table) as *const u8 as usize)),
+            Value::Function(function) => {
+                Ok(Key::Reference(Rc::as_ptr(function) as *const u8 as usize))
+            }
+            Value::Builtin(builtin) => Ok(Key::Builtin(*builtin)),
+        }
+    }
+}
+
+/// A table of a script, with its sequence `1..n` kept apart from the other fields, which keep the order
+/// they were first set in (so that `next` is cheap, and stays valid while fields are cleared).
+#[derive(Default)]
+struct Table {
+    array: Vec<Value>,
+    hash: HashMap<Key, usize>, // The position of each other field within `entries`
+    entries: Vec<(Value, Value)>, // The other fields, whose value is nil once cleared
+}
+
+impl Table {
+    fn get(&self, key: &Value) -> Value {
+        match Key::of(key) {
+            Ok(Key::Integer(index)) if index >= 1 && index as usize <= self.array.len() => {
+                self.array[index as usize - 1].clone()
+            }
+            Ok(key) => match self.hash.get(&key) {
+                Some(position) => self.entries[*position].1.clone(),
+                None => Value::Nil,
+            },
+            Err(_) => Value::Nil,
+        }
+    }
+
+    fn get_field(&self, name: &str) -> Value {
+        self.get(&string(name.as_bytes()))
+    }
+
+    fn set(&mut self, key: Value, value: Value) -> Eval<()> {
+        let length: usize = self.array.len();
+        match Key::of(&key)? {
+            Key::Integer(index) if index >= 1 && index as usize <= length => {
+                self.array[index as usize - 1] = value;
+                while matches!(self.array.last(), Some(Value::Nil)) {
+                    self.array.pop();
+                }
+            }
+            Key::Integer(index) if index as usize == length + 1 && !matches!(value, Value::Nil) => {
+                self.array.push(value);
+                self._clear_entry(&Key::Integer(index));
+                // The fields following the sequence now extend it
+                loop {
+                    let next: Key = Key::Integer(self.array.len() as i64 + 1);
+                    let Some(position) = self.hash.get(&next) else {
+                        break;
+                    };
+                    let value: Value =
+                        std::mem::replace(&mut self.entries[*position].1, Value::Nil);
+                    if matches!(value, Value::Nil) {
+                        break;
+                    }
+                    self.array.push(value);
+                }
+            }
+            key_of => match self.hash.get(&key_of) {
+                Some(position) => self.entries[*position].1 = value,
+                None if matches!(value, Value::Nil) => {}
+                None => {
+                    self.hash.insert(key_of, self.entries.len());
+                    self.entries.push((key, value));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn set_field(&mut self, name: &str, value: Value) {
+        let _ = self.set(string(name.as_bytes()), value);
+    }
+
+    fn _clear_entry(&mut self, key: &Key) {
+        if let Some(position) = self.hash.get(key) {
+            self.entries[*position].1 = Value::Nil;
+        }
+    }
+
+    /// The field following the given one (the first field for nil), as `next` does.
+    fn next(&self, key: &Value) -> Eval<Option<(Value, Value)>> {
+        let (mut index, mut position): (usize, usize) = match key {
+            Value::Nil => (0, 0),
+            _ => {
+                let key: Key = Key::of(key)?;
+                match (self.hash.get(&key), &key) {
+                    (Some(position), _) => (usize::MAX, position + 1),
+                    // The sequence may have shrunk since, when its last fields were cleared
+                    (None, Key::Integer(index)) if *index >= 1 => (*index as usize, 0),
+                    _ => return fail("invalid key to 'next'"),
+                }
+            }
+        };
+        while index < self.array.len() {
+            if !matches!(self.array[index], Value::Nil) {
+                return Ok(Some((
+                    Value::Number((index + 1) as f64),
+                    self.array[index].clone(),
+                )));
+            }
+            index += 1;
+        }
+        while position < self.entries.len() {
+            let (key, value): &(Value, Value) = &self.entries[position];
+            if !matches!(value, Value::Nil) {
+                return Ok(Some((key.clone(), value.clone())));
+            }
+            position += 1;
+        }
+        Ok(None)
+    }
+}
+
+/// The functions of the library of the scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Builtin {
+    RedisCall,
+    RedisPcall,
+    RedisErrorReply,
+    RedisStatusReply,
+    ToNumber,
+    ToString,
+    Type,
+    Pairs,
+    Ipairs,
+    IpairsNext,
+    Next,
+    Unpack,
+    Error,
+    Assert,
+    StringLen,
+    StringSub,
+    StringUpper,
+    StringLower,
+    StringRep,
+    TableInsert,
+    TableRemove,
+    TableConcat,
+    MathFloor,
+    MathCeil,
+    MathAbs,
+    MathMax,
+    MathMin,
+}
+
+impl Builtin {
+    fn name(self) -> &'static str {
+        match self {
+            Builtin::RedisCall => "call",
+            Builtin::RedisPcall => "pcall",
+            Builtin::RedisErrorReply => "error_reply",
+            Builtin::RedisStatusReply => "status_reply",
+            Builtin::ToNumber => "tonumber",
+            Builtin::ToString => "tostring",
+            Builtin::Type => "type",
+            Builtin::Pairs => "pairs",
+            Builtin::Ipairs => "ipairs",
+            Builtin::IpairsNext => "ipairs_next",
+            Builtin::Next => "next",
+            Builtin::Unpack => "unpack",
+            Builtin::Error => "error",
+            Builtin::Assert => "assert",
+            Builtin::StringLen => "len",
+            Builtin::StringSub => "sub",
+            Builtin::StringUpper => "upper",
+            Builtin::StringLower => "lower",
+            Builtin::StringRep => "rep",
+            Builtin::TableInsert => "insert",
+            Builtin::TableRemove => "remove",
+            Builtin::TableConcat => "concat",
+            Builtin::MathFloor => "floor",
+            Builtin::MathCeil => "ceil",
+            Builtin::MathAbs => "abs",
+            Builtin::MathMax => "max",
+            Builtin::MathMin => "min",
+        }
+    }
+}
+
+/// The given status or error as a single line, as the protocols answer them.
+fn single_line(text: &[u8]) -> String {
+    String::from_utf8_lossy(text).replace(['\r', '\n'], " ")
+}
+
+/// Convert the value returned by a script into its reply.
+fn to_reply(value: &Value, depth: usize) -> Eval<Reply> {
+    if depth > MAX_DEPTH {
+        return fail("reached the nesting limit converting the reply");
+    }
+    let reply: Reply = match value {
+        Value::Nil | Value::Boolean(false) | Value::Function(_) | Value::Builtin(_) => Reply::Nil,
+        Value::Boolean(true) => Reply::Integer(1),
+        Value::Number(number) => Reply::Integer(*number as i64),
+        Value::Str(text) => Reply::Bulk(text.to_vec()),
+        Value::Table(table) => {
+            let table = table.borrow();
+            if let Value::Str(message) = table.get_field("err") {
+                return Ok(Reply::Error(single_line(&message)));
+            }
+            if let Value::Str(status) = table.get_field("ok") {
+                return Ok(Reply::Status(single_line(&status)));
+            }
+            // Like in Redis, the array stops at the first nil
+            let mut items: Vec<Reply> = Vec::new();
+            for item in table
+                .array
+                .iter()
+                .take_while(|item| !matches!(item, Value::Nil))
+            {
+                items.push(to_reply(item, depth + 1)?);
+            }
+            Reply::Array(items)
+        }
+    };
+    Ok(reply)
+}
+
+/// A runtime error of a script, located at the line of the statement failing.
+struct RuntimeError {
+    message: String,
+    line: Option<usize>,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+type Eval<T> = Result<T, RuntimeError>;
+
+fn fail<T>(message: impl Into<String>) -> Eval<T> {
+    Err(RuntimeError {
+        message: message.into(),
+        line: None,
+    })
+}
+
+/// How a block of statements ends.
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+struct Interpreter<'a> {
+    globals: HashMap<String, Value>,
+    strings: Rc<RefCell<Table>>, // The `string` library, also indexed by the strings (e.g. `s:upper()`)
+    call: &'a mut dyn FnMut(&[Vec<u8>]) -> Reply,
+    time_limit: Duration,
+    deadline: Instant,
+    steps: u32,
+    depth: usize,
+    stack_base: usize, // The address of the stack where the script started (see `stack_address`)
+    // The tables and the scopes of the closures created, cleared once the script is over so that their
+    // reference cycles (e.g. `t.self = t`) do not leak
+    tables: Vec<Weak<RefCell<Table>>>,
+    scopes: Vec<Weak<Scope>>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(call: &'a mut dyn FnMut(&[Vec<u8>]) -> Reply, time_limit: Duration) -> Interpreter<'a> {
+        let mut interpreter: Interpreter = Interpreter {
+            globals: HashMap::new(),
+            strings: Rc::new(RefCell::new(Table::default())),
+            call,
+            time_limit,
+            deadline: Instant::now() + time_limit,
+            steps: 0,
+            depth: 0,
+            stack_base: stack_address(),
+            tables: Vec::new(),
+            scopes: Vec::new(),
+        };
+
+        let libraries: [(&str, &[Builtin]); 5] = [
+            (
+                "redis",
+                &[
+                    Builtin::RedisCall,
+                    Builtin::RedisPcall,
+                    Builtin::RedisErrorReply,
+                    Builtin::RedisStatusReply,
+                ],
+            ),
+            (
+                "string",
+                &[
+                    Builtin::StringLen,
+                    Builtin::StringSub,
+                    Builtin::StringUpper,
+                    Builtin::StringLower,
+                    Builtin::StringRep,
+                ],
+            ),
+            (
+                "table",
+                &[
+                    Builtin::TableInsert,
+                    Builtin::TableRemove,
+                    Builtin::TableConcat,
+                ],
+            ),
+            (
+                "math",
+                &[
+                    Builtin::MathFloor,
+                    Builtin::MathCeil,
+                    Builtin::MathAbs,
+                    Builtin::MathMax,
+                    Builtin::MathMin,
+                ],
+            ),
+            (
+                "",
+                &[
+                    Builtin::ToNumber,
+                    Builtin::ToString,
+                    Builtin::Type,
+                    Builtin::Pairs,
+                    Builtin::Ipairs,
+                    Builtin::Next,
+                    Builtin::Unpack,
+                    Builtin::Error,
+                    Builtin::Assert,
+                ],
+            ),
+        ];
+        for (library, builtins) in libraries {
+            if library.is_empty() {
+                for builtin in builtins {
+                    interpreter
+                        .globals
+                        .insert(builtin.name().to_string(), Value::Builtin(*builtin));
+                }
+                continue;
+            }
+            let table: Rc<RefCell<Table>> = match library {
+                "string" => Rc::clone(&interpreter.strings),
+                _ => interpreter.table(),
+            };
+            for builtin in builtins {
+                table
+                    .borrow_mut()
+                    .set_field(builtin.name(), Value::Builtin(*builtin));
+            }
+            if library == "math" {
+                table
+                    .borrow_mut()
+                    .set_field("huge", Value::Number(f64::INFINITY));
+            }
+            interpreter
+                .globals
+                .insert(library.to_string(), Value::Table(table));
+        }
+        interpreter
+    }
+
+    fn table(&mut self) -> Rc<RefCell<Table>> {
+        let table: Rc<RefCell<Table>> = Rc::new(RefCell::new(Table::default()));
+        self.tables.push(Rc::downgrade(&table));
+        table
+    }
+
+    fn array(&mut self, values: Vec<Value>) -> Value {
+        let table: Rc<RefCell<Table>> = self.table();
+        table.borrow_mut().array = values;
+        Value::Table(table)
+    }
+
+    /// Clear the tables and the scopes of the closures created by the script, dropping their references.
+    fn release(&mut self) {
+        self.globals.clear();
+        for table in self.tables.drain(..).filter_map(|table| table.upgrade()) {
+            std::mem::take(&mut *table.borrow_mut());
+        }
+        for scope in self.scopes.drain(..).filter_map(|scope| scope.upgrade()) {
+            let mut scope: Option<Rc<Scope>> = Some(scope);
+            while let Some(current) = scope {
+                std::mem::take(&mut *current.variables.borrow_mut());
+                scope = current.parent.clone();
+            }
+        }
+    }
+
+    /// Count a step of the script, failing once it exceeds its time limit.
+    fn tick(&mut self) -> Eval<()> {
+        self.steps = self.steps.wrapping_add(1);
+        if self.steps.is_multiple_of(STEPS_PER_CHECK) && Instant::now() >= self.deadline {
+            return fail(format!(
+                "the script exceeded its time limit of {} ms",
+                self.time_limit.as_millis()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Go one level deeper into the expressions and function calls, failing beyond `MAX_DEPTH` or
+    /// `MAX_STACK_SIZE`.
+    fn enter(&mut self) -> Eval<()> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH || self.stack_base.abs_diff(stack_address()) > MAX_STACK_SIZE {
+            true => fail("stack overflow"),
+            false => Ok(()),
+        }
+    }
+
+    fn exec_block(&mut self, block: &Block, parent: &Rc<Scope>) -> Eval<Flow> {
+        let scope: Rc<Scope> = Rc::new(Scope::new(Some(Rc::clone(parent))));
+        self.exec_statements(block, &scope)
+    }
+
+    fn exec_statements(&mut self, block: &Block, scope: &Rc<Scope>) -> Eval<Flow> {
+        for (line, statement) in block {
+            self.tick()?;
+            match self.exec(statement, scope) {
+                Ok(Flow::Normal) => {}
+                Err(mut e) => {
+                    e.line.get_or_insert(*line);
+                    return Err(e);
+                }
+                flow => return flow,
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, statement: &Stat, scope: &Rc<Scope>) -> Eval<Flow> {
+        match statement {
+            Stat::Local(names, expressions) => {
+                let values: Vec<Value> = self.eval_list(expressions, scope)?;
+                for (index, name) in names.iter().enumerate() {
+                    scope.declare(name, values.get(index).cloned().unwrap_or(Value::Nil));
+                }
+            }
+            Stat::LocalFunction(name, function) => {
+                // Declared before the function is defined, so that it may call itself
+                let variable: Rc<RefCell<Value>> = scope.declare(name, Value::Nil);
+                *variable.borrow_mut() = self.closure(function, scope);
+            }
+            Stat::Assign(targets, expressions) => {
+                let values: Vec<Value> = self.eval_list(expressions, scope)?;
+                for (index, target) in targets.iter().enumerate() {
+                    let value: Value = values.get(index).cloned().unwrap_or(Value::Nil);
+                    self.assign(target, value, scope)?;
+                }
+            }
+            Stat::Call(expression) => {
+                self.eval_multiple(expression, scope)?;
+            }
+            Stat::If(branches, otherwise) => {
+                for (condition, block) in branches {
+                    if self.eval(condition, scope)?.is_truthy() {
+                        return self.exec_block(block, scope);
+                    }
+                }
+                if let Some(block) = otherwise {
+                    return self.exec_block(block, scope);
+                }
+            }
+            Stat::While(condition, body) => {
+                while self.eval(condition, scope)?.is_truthy() {
+                    self.tick()?;
+                    match self.exec_block(body, scope)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stat::Repeat(body, condition) => loop {
+                self.tick()?;
+                // The condition sees the local variables of the body
+                let inner: Rc<Scope> = Rc::new(Scope::new(Some(Rc::clone(scope))));
+                match self.exec_statements(body, &inner)? {
+                    Flow::Normal => {}
+                    Flow::Break => break,
+                    flow => return Ok(flow),
+                }
+                if self.eval(condition, &inner)?.is_truthy() {
+                    break;
+                }
+            },
+            Stat::NumericFor(name, start, limit, step, body) => {
+                let start: f64 = self.for_number(start, scope, "initial")?;
+                let limit: f64 = self.for_number(limit, scope, "limit")?;
+                let step: f64 = match step {
+                    Some(step) => self.for_number(step, scope, "step")?,
+                    None => 1.0,
+                };
+                let mut index: f64 = start;
+                while (step > 0.0 && index <= limit) || (step <= 0.0 && index >= limit) {
+                    self.tick()?;
+                    let inner: Rc<Scope> = Rc::new(Scope::new(Some(Rc::clone(scope))));
+                    inner.declare(name, Value::Number(index));
+                    match self.exec_block(body, &inner)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                    index += step;
+                }
+            }
+            Stat::GenericFor(names, expressions, body) => {
+                let mut values: Vec<Value> = self.eval_list(expressions, scope)?;
+                values.resize(3, Value::Nil);
+                let (function, state): (Value, Value) = (values[0].clone(), values[1].clone());
+                let mut control: Value = values[2].clone();
+                loop {
+                    self.tick()?;
+                    let results: Vec<Value> = self.call_value(
+                        &function,
+                        vec![state.clone(), control.clone()],
+                        "for iterator",
+                    )?;
+                    control = results.first().cloned().unwrap_or(Value::Nil);
+                    if matches!(control, Value::Nil) {
+                        break;
+                    }
+                    let inner: Rc<Scope> = Rc::new(Scope::new(Some(Rc::clone(scope))));
+                    for (index, name) in names.iter().enumerate() {
+                        inner.declare(name, results.get(index).cloned().unwrap_or(Value::Nil));
+                    }
+                    match self.exec_block(body, &inner)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stat::Do(block) => return self.exec_block(block, scope),
+            Stat::Return(expressions) => {
+                return Ok(Flow::Return(self.eval_list(expressions, scope)?))
+            }
+            Stat::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn for_number(&mut self, expression: &Expr, scope: &Rc<Scope>, what: &str) -> Eval<f64> {
+        match self.eval(expression, scope)?.to_number() {
+            Some(number) => Ok(number),
+            None => fail(format!("'for' {} value must be a number", what)),
+        }
+    }
+
+    fn assign(&mut self, target: &Expr, value: Value, scope: &Rc<Scope>) -> Eval<()> {
+        match target {
+            Expr::Name(name) => match scope.lookup(name) {
+                Some(variable) => *variable.borrow_mut() = value,
+                None => {
+                    self.globals.insert(name.clone(), value);
+                }
+            },
+            Expr::Index(object, key) => {
+                let object: Value = self.eval(object, scope)?;
+                let key: Value = self.eval(key, scope)?;
+                match object {
+                    Value::Table(table) => table.borrow_mut().set(key, value)?,
+                    other => {
+                        return fail(format!("attempt to index a {} value", other.type_name()))
+                    }
+                }
+            }
+            _ => return fail("cannot assign to this expression"),
+        }
+        Ok(())
+    }
+
+    fn closure(&mut self, function: &Rc<Function>, scope: &Rc<Scope>) -> Value {
+        self.scopes.push(Rc::downgrade(scope));
+        Value::Function(Rc::new(Closure {
+            function: Rc::clone(function),
+            scope: Rc::clone(scope),
+        }))
+    }
+
+    /// Evaluate a list of expressions, the last one giving all of its values (e.g. a function call).
+    fn eval_list(&mut self, expressions: &[Expr], scope: &Rc<Scope>) -> Eval<Vec<Value>> {
+        let mut values: Vec<Value> = Vec::with_capacity(expressions.len());
+        if let Some((last, others)) = expressions.split_last() {
+            for expression in others {
+                values.push(self.eval(expression, scope)?);
+            }
+            values.extend(self.eval_multiple(last, scope)?);
+        }
+        Ok(values)
+    }
+
+    /// Evaluate an expression, with all of its values if it is a function call.
+    fn eval_multiple(&mut self, expression: &Expr, scope: &Rc<Scope>) -> Eval<Vec<Value>> {
+        match expression {
+            Expr::Call(function, arguments) => {
+                let callee: Value = self.eval(function, scope)?;
+                let arguments: Vec<Value> = self.eval_list(arguments, scope)?;
+                self.call_value(&callee, arguments, &describe_callee(function))
+            }
+            Expr::Method(object, name, arguments) => {
+                let object: Value = self.eval(object, scope)?;
+                let callee: Value = self.index(&object, &string(name.as_bytes()))?;
+                let mut values: Vec<Value> = vec![object];
+                values.extend(self.eval_list(arguments, scope)?);
+                self.call_value(&callee, values, &format!("method '{}'", name))
+            }
+            _ => Ok(vec![self.eval(expression, scope)?]),
+        }
+    }
+
+    fn eval(&mut self, expression: &Expr, scope: &Rc<Scope>) -> Eval<Value> {
+        self.enter()?;
+        let value: Value = match expression {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Boolean(true),
+            Expr::False => Value::Boolean(false),
+            Expr::Number(number) => Value::Number(*number),
+            Expr::Str(text) => Value::Str(Rc::clone(text)),
+            Expr::Name(name) => match scope.lookup(name) {
+                Some(variable) => variable.borrow().clone(),
+                None => self.globals.get(name).cloned().unwrap_or(Value::Nil),
+            },
+            Expr::Index(object, key) => {
+                let object: Value = self.eval(object, scope)?;
+                let key: Value = self.eval(key, scope)?;
+                self.index(&object, &key)?
+            }
+            Expr::Call(..) | Expr::Method(..) => self
+                .eval_multiple(expression, scope)?
+                .into_iter()
+                .next()
+                .unwrap_or(Value::Nil),
+            Expr::Paren(inner) => self.eval(inner, scope)?,
+            Expr::Function(function) => self.closure(function, scope),
+            Expr::Table(fields) => {
+                let table: Rc<RefCell<Table>> = self.table();
+                let mut position: usize = 0;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    match key {
+                        Some(key) => {
+                            let key: Value = self.eval(key, scope)?;
+                            let value: Value = self.eval(value, scope)?;
+                            table.borrow_mut().set(key, value)?;
+                        }
+                        // The last positional field gives all of its values (e.g. `{redis.call(...)}`)
+                        None if index == fields.len() - 1 => {
+                            for value in self.eval_multiple(value, scope)? {
+                                position += 1;
+                                table
+                                    .borrow_mut()
+                                    .set(Value::Number(position as f64), value)?;
+                            }
+                        }
+                        None => {
+                            let value: Value = self.eval(value, scope)?;
+                            position += 1;
+                            table
+                                .borrow_mut()
+                                .set(Value::Number(position as f64), value)?;
+                        }
+                    }
+                }
+                Value::Table(table)
+            }
+            Expr::Unary(operator, operand) => {
+                let operand: Value = self.eval(operand, scope)?;
+                match operator {
+                    UnaryOperator::Not => Value::Boolean(!operand.is_truthy()),
+                    UnaryOperator::Negate => match operand.to_number() {
+                        Some(number) => Value::Number(-number),
+                        None => return arithmetic_error(&operand),
+                    },
+                    UnaryOperator::Length => match &operand {
+                        Value::Str(text) => Value::Number(text.len() as f64),
+                        Value::Table(table) => Value::Number(table.borrow().array.len() as f64),
+                        other => {
+                            return fail(format!(
+                                "attempt to get length of a {} value",
+                                other.type_name()
+                            ))
+                        }
+                    },
+                }
+            }
+            Expr::Binary(operator, left, right) => {
+                let left: Value = self.eval(left, scope)?;
+                match operator {
+                    BinaryOperator::And if !left.is_truthy() => left,
+                    BinaryOperator::Or if left.is_truthy() => left,
+                    BinaryOperator::And | BinaryOperator::Or => self.eval(right, scope)?,
+                    operator => {
+                        let right: Value = self.eval(right, scope)?;
+                        binary(*operator, &left, &right)?
+                    }
+                }
+            }
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn index(&mut self, object: &Value, key: &Value) -> Eval<Value> {
+        match object {
+            Value::Table(table) => Ok(table.borrow().get(key)),
+            Value::Str(_) => Ok(self.strings.borrow().get(key)),
+            other => fail(format!("attempt to index a {} value", other.type_name())),
+        }
+    }
+
+    /// Call a function of the script, or of its library.
+    ///
+    /// # Arguments
+    /// * `callee` - How the function is shown in the error if it is not a function, e.g. `global 'f'`.
+    fn call_value(
+        &mut self,
+        function: &Value,
+        arguments: Vec<Value>,
+        callee: &str,
+    ) -> Eval<Vec<Value>> {
+        match function {
+            Value::Builtin(builtin) => self.builtin(*builtin, arguments),
+            Value::Function(closure) => {
+                self.enter()?;
+                let scope: Rc<Scope> = Rc::new(Scope::new(Some(Rc::clone(&closure.scope))));
+                let mut arguments = arguments.into_iter();
+                for parameter in &closure.function.parameters {
+                    scope.declare(parameter, arguments.next().unwrap_or(Value::Nil));
+                }
+                let flow: Flow = self.exec_statements(&closure.function.body, &scope)?;
+                self.depth -= 1;
+                match flow {
+                    Flow::Return(values) => Ok(values),
+                    _ => Ok(Vec::new()),
+                }
+            }
+            other => fail(format!(
+                "attempt to call {} (a {} value)",
+                callee,
+                other.type_name()
+            )),
+        }
+    }
+
+    fn builtin(&mut self, builtin: Builtin, arguments: Vec<Value>) -> Eval<Vec<Value>> {
+        let name: &str = builtin.name();
+        let value: Value = match builtin {
+            Builtin::RedisCall | Builtin::RedisPcall => {
+                if arguments.is_empty() {
+                    return fail("please specify at least one argument for redis.call()");
+                }
+                let mut command: Vec<Vec<u8>> = Vec::with_capacity(arguments.len());
+                for argument in &arguments {
+                    match argument.to_bytes() {
+                        Some(bytes) => command.push(bytes.to_vec()),
+                        None => {
+                            return fail("redis commands arguments must be strings or integers")
+                        }
+                    }
+                }
+                match (self.call)(&command) {
+                    Reply::Error(message) if builtin == Builtin::RedisCall => return fail(message),
+                    reply => self.reply_value(reply),
+                }
+            }
+            Builtin::RedisErrorReply | Builtin::RedisStatusReply => {
+                let text: Rc<[u8]> = string_argument(&arguments, 0, name)?;
+                let table: Rc<RefCell<Table>> = self.table();
+                let field: &str = if builtin == Builtin::RedisErrorReply {
+                    "err"
+                } else {
+                    "ok"
+                };
+                table.borrow_mut().set_field(field, Value::Str(text));
+                Value::Table(table)
+            }
+            Builtin::ToNumber => match (argument(&arguments, 0), argument(&arguments, 1)) {
+                (Value::Str(text), Value::Number(base)) => std::str::from_utf8(&text)
+                    .ok()
+                    .and_then(|text| i64::from_str_radix(text.trim(), base as u32).ok())
+                    .map_or(Value::Nil, |number| Value::Number(number as f64)),
+                (value, _) => value.to_number().map_or(Value::Nil, Value::Number),
+            },
+            Builtin::ToString => Value::Str(argument(&arguments, 0).to_display()),
+            Builtin::Type => string(argument(&arguments, 0).type_name().as_bytes()),
+            Builtin::Pairs => {
+                let table: Rc<RefCell<Table>> = table_argument(&arguments, 0, name)?;
+                return Ok(vec![
+                    Value::Builtin(Builtin::Next),
+                    Value::Table(table),
+                    Value::Nil,
+                ]);
+            }
+            Builtin::Ipairs => {
+                let table: Rc<RefCell<Table>> = table_argument(&arguments, 0, name)?;
+                return Ok(vec![
+                    Value::Builtin(Builtin::IpairsNext),
+                    Value::Table(table),
+                    Value::Number(0.0),
+                ]);
+            }
+            Builtin::IpairsNext => {
+                let table: Rc<RefCell<Table>> = table_argument(&arguments, 0, name)?;
+                let index: f64 = number_argument(&arguments, 1, name)? + 1.0;
+                let value: Value = table.borrow().get(&Value::Number(index));
+                return match value {
+                    Value::Nil => Ok(vec![Value::Nil]),
+                    value => Ok(vec![Value::Number(index), value]),
+                };
+            }
+            Builtin::Next => {
+                let table: Rc<RefCell<Table>> = table_argument(&arguments, 0, name)?;
+                let next: Option<(Value, Value)> = table.borrow().next(&argument(&arguments, 1))?;
+                return match next {
+                    Some((key, value)) => Ok(vec![key, value]),
+                    None => Ok(vec![Value::Nil]),
+                };
+            }
+            Builtin::Unpack => {
+                let table: Rc<RefCell<Table>> = table_argument(&arguments, 0, name)?;
+                let values: Vec<Value> = table.borrow().array.clone();
+                return Ok(values);
+            }
+            Builtin::Error => {
+                let message: Value = argument(&arguments, 0);
+                return fail(String::from_utf8_lossy(&message.to_display()).into_owned());
+            }
+            Builtin::Assert => {
+                if argument(&arguments, 0).is_truthy() {
+                    return Ok(arguments);
+                }
+                return match argument(&arguments, 1) {
+                    Value::Nil => fail("assertion failed!"),
+                    message => fail(String::from_utf8_lossy(&message.to_display()).into_owned()),
+                };
+            }
+            Builtin::StringLen => Value::Number(string_argument(&arguments, 0, name)?.len() as f64),
+            Builtin::StringSub => {
+                let text: Rc<[u8]> = string_argument(&arguments, 0, name)?;
+                let length: i64 = text.len() as i64;
+                // Negative positions count from the end, e.g. `-1` for the last character
+                let position = |index: f64| -> i64 {
+                    let index: i64 = index as i64;
+                    if index < 0 {
+                        (length + index + 1).max(0)
+                    } else {
+                        index
+                    }
+                };
+                let start: i64 = position(number_argument(&arguments, 1, name)?).max(1);
+                let end: i64 = match argument(&arguments, 2) {
+                    Value::Nil => length,
+                    _ => position(number_argument(&arguments, 2, name)?).min(length),
+                };
+                match start <= end {
+                    true => string(&text[start as usize - 1..end as usize]),
+                    false => string(b""),
+                }
+            }
+            Builtin::StringUpper => {
+                string(&string_argument(&arguments, 0, name)?.to_ascii_uppercase())
+            }
+            Builtin::StringLower => {
+                string(&string_argument(&arguments, 0, name)?.to_ascii_lowercase())
+            }
+            Builtin::StringRep => {
+                let text: Rc<[u8]> = string_argument(&arguments, 0, name)?;
+                let count: usize = number_argument(&arguments, 1, name)?.max(0.0) as usize;
+                if text.len().saturating_mul(count) > MAX_STRING_LENGTH {
+                    return fail("resulting string too large");
+                }
+                string(&text.repeat(count))
+            }
+            Builtin::TableInsert => {
+                let table: Rc<RefCell<Table>> = table_argument(&arguments, 0, name)?;
+                let mut table = table.borrow_mut();
+                match arguments.len() {
+                    2 => table.array.push(arguments[1].clone()),
+                    3 => {
+                        let position: usize = number_argument(&arguments, 1, name)? as usize;
+                        if position < 1 || position > table.array.len() + 1 {
+                            return fail("bad argument #2 to 'insert' (position out of bounds)");
+                        }
+                        table.array.insert(position - 1, arguments[2].clone());
+                    }
+                    _ => return fail("wrong number of arguments to 'insert'"),
+                }
+                return Ok(Vec::new());
+            }
+            Builtin::TableRemove => {
+                let table: Rc<RefCell<Table>> = table_argument(&arguments, 0, name)?;
+                let mut table = table.borrow_mut();
+                let length: usize = table.array.len();
+                let position: usize = match argument(&arguments, 1) {
+                    Value::Nil => length,
+                    _ => number_argument(&arguments, 1, name)? as usize,
+                };
+                match position >= 1 && position <= length {
+                    true => table.array.remove(position - 1),
+                    false => Value::Nil,
+                }
+            }
+            Builtin::TableConcat => {
+                let table: Rc<RefCell<Table>> = table_argument(&arguments, 0, name)?;
+                let separator: Rc<[u8]> = match argument(&arguments, 1) {
+                    Value::Nil => b""[..].into(),
+                    _ => string_argument(&arguments, 1, name)?,
+                };
+                let mut text: Vec<u8> = Vec::new();
+                for (index, item) in table.borrow().array.iter().enumerate() {
+                    let Some(item) = item.to_bytes() else {
+                        return fail(format!(
+                            "invalid value (at index {}) in table for 'concat'",
+                            index + 1
+                        ));
+                    };
+                    if index > 0 {
+                        text.extend_from_slice(&separator);
+                    }
+                    text.extend_from_slice(&item);
+                    if text.len() > MAX_STRING_LENGTH {
+                        return fail("resulting string too large");
+                    }
+                }
+                Value::Str(text.into())
+            }
+            Builtin::MathFloor => Value::Number(number_argument(&arguments, 0, name)?.floor()),
+            Builtin::MathCeil => Value::Number(number_argument(&arguments, 0, name)?.ceil()),
+            Builtin::MathAbs => Value::Number(number_argument(&arguments, 0, name)?.abs()),
+            Builtin::MathMax | Builtin::MathMin => {
+                let mut result: f64 = number_argument(&arguments, 0, name)?;
+                for index in 1..arguments.len() {
+                    let number: f64 = number_argument(&arguments, index, name)?;
+                    result = match builtin {
+                        Builtin::MathMax => result.max(number),
+                        _ => result.min(number),
+                    };
+                }
+                Value::Number(result)
+            }
+        };
+        Ok(vec![value])
+    }
+
+    /// Convert the reply of a command called by the script into a value of the script.
+    fn reply_value(&mut self, reply: Reply) -> Value {
+        match reply {
+            Reply::Nil => Value::Boolean(false),
+            Reply::Integer(integer) => Value::Number(integer as f64),
+            Reply::Bulk(bytes) => Value::Str(bytes.into()),
+            Reply::Status(status) => {
+                let table: Rc<RefCell<Table>> = self.table();
+                table
+                    .borrow_mut()
+                    .set_field("ok", string(status.as_bytes()));
+                Value::Table(table)
+            }
+            Reply::Error(message) => {
+                let table: Rc<RefCell<Table>> = self.table();
+                table
+                    .borrow_mut()
+                    .set_field("err", string(message.as_bytes()));
+                Value::Table(table)
+            }
+            Reply::Array(items) => {
+                let values: Vec<Value> = items
+                    .into_iter()
+                    .map(|item| self.reply_value(item))
+                    .collect();
+                self.array(values)
+            }
+        }
+    }
+}
+
+/// How the function called by an expression is shown in the errors, e.g. `global 'f'` or `field 'call'`.
+fn describe_callee(function: &Expr) -> String {
+    match function {
+        Expr::Name(name) => format!("'{}'", name),
+        Expr::Index(_, key) => match key.as_ref() {
+            Expr::Str(name) => format!("field '{}'", String::from_utf8_lossy(name)),
+            _ => "a field".to_string(),
+        },
+        _ => "a value".to_string(),
+    }
+}
+
+fn arithmetic_error<T>(operand: &Value) -> Eval<T> {
+    fail(format!(
+        "attempt to perform arithmetic on a {} value",
+        operand.type_name()
+    ))
+}
+
+/// Apply a binary operator other than `and` and `or`, which are evaluated lazily.
+fn binary(operator: BinaryOperator, left: &Value, right: &Value) -> Eval<Value> {
+    let value: Value = match operator {
+        BinaryOperator::Equal => Value::Boolean(raw_equal(left, right)),
+        BinaryOperator::NotEqual => Value::Boolean(!raw_equal(left, right)),
+        BinaryOperator::Less
+        | BinaryOperator::Greater
+        | BinaryOperator::LessOrEqual
+        | BinaryOperator::GreaterOrEqual => {
+            let ordering: Option<std::cmp::Ordering> = match (left, right) {
+                (Value::Number(left), Value::Number(right)) => left.partial_cmp(right),
+                (Value::Str(left), Value::Str(right)) => Some(left.cmp(right)),
+                _ => {
+                    return fail(format!(
+                        "attempt to compare {} with {}",
+                        left.type_name(),
+                        right.type_name()
+                    ))
+                }
+            };
+            Value::Boolean(ordering.is_some_and(|ordering| match operator {
+                BinaryOperator::Less => ordering.is_lt(),
+                BinaryOperator::Greater => ordering.is_gt(),
+                BinaryOperator::LessOrEqual => ordering.is_le(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        BinaryOperator::Concat => {
+            let (Some(left_bytes), Some(right_bytes)) = (left.to_bytes(), right.to_bytes()) else {
+                let operand: &Value = if left.to_bytes().is_none() {
+                    left
+                } else {
+                    right
+                };
+                return fail(format!(
+                    "attempt to concatenate a {} value",
+                    operand.type_name()
+                ));
+            };
+            if left_bytes.len() + right_bytes.len() > MAX_STRING_LENGTH {
+                return fail("resulting string too large");
+            }
+            Value::Str([&left_bytes[..], &right_bytes[..]].concat().into())
+        }
+        _ => {
+            let Some(left_number) = left.to_number() else {
+                return arithmetic_error(left);
+            };
+            let Some(right_number) = right.to_number() else {
+                return arithmetic_error(right);
+            };
+            Value::Number(match operator {
+                BinaryOperator::Add => left_number + right_number,
+                BinaryOperator::Subtract => left_number - right_number,
+                BinaryOperator::Multiply => left_number * right_number,
+                BinaryOperator::Divide => left_number / right_number,
+                BinaryOperator::Modulo => {
+                    left_number - (left_number / right_number).floor() * right_number
+                }
+                _ => left_number.powf(right_number),
+            })
+        }
+    };
+    Ok(value)
+}
+
+fn argument(arguments: &[Value], index: usize) -> Value {
+    arguments.get(index).cloned().unwrap_or(Value::Nil)
+}
+
+fn argument_error<T>(index: usize, function: &str, expected: &str, got: &Value) -> Eval<T> {
+    fail(format!(
+        "bad argument #{} to '{}' ({} expected, got {})",
+        index + 1,
+        function,
+        expected,
+        got.type_name()
+    ))
+}
+
+fn table_argument(arguments: &[Value], index: usize, function: &str) -> Eval<Rc<RefCell<Table>>> {
+    match argument(arguments, index) {
+        Value::Table(table) => Ok(table),
+        other => argument_error(index, function, "table", &other),
+    }
+}
+
+fn number_argument(arguments: &[Value], index: usize, function: &str) -> Eval<f64> {
+    let value: Value = argument(arguments, index);
+    match value.to_number() {
+        Some(number) => Ok(number),
+        None => argument_error(index, function, "number", &value),
+    }
+}
+
+fn string_argument(arguments: &[Value], index: usize, function: &str) -> Eval<Rc<[u8]>> {
+    let value: Value = argument(arguments, index);
+    match value.to_bytes() {
+        Some(text) => Ok(text),
+        None => argument_error(index, function, "string", &value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Result<Reply, String> {
+        run_within(source, Duration::from_secs(5))
+    }
+
+    fn run_within(source: &str, time_limit: Duration) -> Result<Reply, String> {
+        let script: Script = Script::parse(source.as_bytes())?;
+        script.run(&[b"key"], &[b"7"], time_limit, &mut |command| {
+            Reply::Bulk(command.concat())
+        })
+    }
+
+    #[test]
+    fn returns_values_as_replies() {
+        assert_eq!(run("return 1 + 2"), Ok(Reply::Integer(3)));
+        assert_eq!(run("return 'a' .. 'b'"), Ok(Reply::Bulk(b"ab".to_vec())));
+        assert_eq!(
+            run("return {ok = 'done'}"),
+            Ok(Reply::Status("done".to_string()))
+        );
+        assert_eq!(run("return false"), Ok(Reply::Nil));
+        assert_eq!(
+            run("return {1, 'two'}"),
+            Ok(Reply::Array(vec![
+                Reply::Integer(1),
+                Reply::Bulk(b"two".to_vec())
+            ]))
+        );
+    }
+
+    #[test]
+    fn calls_commands_with_its_keys_and_arguments() {
+        assert_eq!(
+            run("return redis.call('GET', KEYS[1], ARGV[1])"),
+            Ok(Reply::Bulk(b"GETkey7".to_vec()))
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_their_line() {
+        let error: String = run("local x = 1\nif x then").unwrap_err();
+        assert!(error.starts_with("line 2:"), "{error}");
+        let error: String = run("local x\nreturn x.y").unwrap_err();
+        assert!(error.starts_with("line 2:"), "{error}");
+    }
+
+    #[test]
+    fn stops_at_the_time_limit() {
+        let error: String = run_within("while true do end", Duration::from_millis(50)).unwrap_err();
+        assert!(
+            error.contains("exceeded its time limit of 50 ms"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn limits_the_nesting_of_the_source() {
+        let source: String = format!("return {}1{}", "(".repeat(1000), ")".repeat(1000));
+        let error: String = run(&source).unwrap_err();
+        assert!(error.contains("too many syntax levels"), "{error}");
+    }
+
+    #[test]
+    fn limits_the_depth_of_the_calls() {
+        let error: String = run("local function f(n) return f(n + 1) end return f(1)").unwrap_err();
+        assert!(error.contains("stack overflow"), "{error}");
+    }
+
+    #[test]
+    fn limits_the_length_of_the_strings() {
+        let error: String = run("return string.rep('ab', 64 * 1024 * 1024)").unwrap_err();
+        assert!(error.contains("resulting string too large"), "{error}");
+
+        let source: &str = "local s = string.rep('a', 64 * 1024 * 1024) return s .. 'b'";
+        let error: String = run(source).unwrap_err();
+        assert!(error.contains("resulting string too large"), "{error}");
+    }
+}
@@ -0,0 +1,5263 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, ErrorKind, IoSlice, Write},
+    iter,
+    net::{TcpListener, UdpSocket},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    str,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, log, warn};
+
+use crate::{
+    acl::AclUser,
+    auth::{Auth, DEFAULT_USER},
+    binary::{self, Decoded, Request},
+    blocking::{BlockingPop, ListWaiter},
+    buffers::{BufferBudget, BufferStats, ConnectionBuffers, DEFAULT_BUFFER_LIMIT},
+    cache::{AppendOnlyLog, Bytes, Cache, CacheFactory, PutCondition},
+    clients::ClientInfo,
+    cluster::{Cluster, SlotMap},
+    config::ServerConfig,
+    connection::{accept_clients, ClientHandler, ClientListener, ClientStream, ConnectionLimits},
+    dataset::{DatasetFormat, Datasets, ImportMode},
+    embedded::{
+        EmbeddedCache, KeyspaceStats, Lookup, MemoryReport, TtlHistogram, DEFAULT_TTL_SAMPLE_SIZE,
+        MAX_TTL_JITTER, TTL_BUCKETS,
+    },
+    error::{CacheError, CacheResult, ServerError},
+    history::Version,
+    http::{HttpClientHandler, HttpRoutes},
+    ipfilter::IpFilter,
+    memcached::MemcachedClientHandler,
+    monitor::MONITOR_CHANNEL,
+    msgpack::{self, Value},
+    namespaces::{self, NamespaceUsage, NAMESPACE_SEPARATOR},
+    preload,
+    protobuf::{self, Command},
+    protocol::{self, Bulk, ErrorCode, Tokens},
+    pubsub::{Message, Subscriptions},
+    ratelimit::{RateLimit, TokenBucket},
+    rdb::{self, ImportStats},
+    replica::Replica,
+    resp::{RespClientHandler, RespOptions},
+    runtime::RuntimeConfig,
+    script::Reply,
+    shutdown::{ShutdownHandle, DEFAULT_SHUTDOWN_TIMEOUT, SHUTDOWN_POLL_INTERVAL},
+    slowlog::{self, SlowLogEntry, DEFAULT_SLOWLOG_GET_COUNT},
+    snapshot::Snapshots,
+    stats::CommandProtocol,
+    transaction::{Transaction, Watch},
+    udp::UdpServer,
+    utils::Decimal,
+    values::{self, BloomFilter, RateDecision, ScoreBound, SetOperation, StreamId},
+    warmup::WarmupSource,
+    websocket::{WebSocketClientHandler, WebSocketOptions, WebSocketPolicy},
+    workers::{WorkerOptions, WorkerPool},
+};
+
+/// The cache instantiation served over the network: string keys mapped to shared byte values.
+pub type ServerCache = dyn Cache<String, Bytes>;
+
+/// The named options of the PUT command, which may follow the value when no TTL is given.
+const PUT_OPTIONS: [&[u8]; 6] = [b"SOFT", b"JITTER", b"EX", b"PX", b"NX", b"XX"];
+
+/// The number of keys a SCAN command examines when it gives no `COUNT`, as in Redis.
+pub(crate) const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// How often a client waiting for a value with BLPOP or BRPOP is checked for having disconnected.
+const BLOCKED_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the append-only log is checked for having grown enough to be rewritten.
+const AOF_REWRITE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct CacheServer {
+    address: String,
+    cache: Arc<EmbeddedCache>, // The cache served to the clients, along with the state of its features
+    buffers: Arc<BufferBudget>, // Bounds the memory taken by the buffers of all the connections
+    resp_address: Option<String>, // The address of the listener speaking RESP, if enabled
+    memcached_address: Option<String>, // The address of the listener speaking the memcached protocol, if enabled
+    http_address: Option<String>,      // The address of the listener speaking HTTP, if enabled
+    websocket_address: Option<String>, // The address of the listener speaking WebSocket, if enabled
+    websocket_policy: WebSocketPolicy, // Who may open a WebSocket connection
+    udp_address: Option<String>,       // The address of the UDP listener, if enabled
+    metrics_address: Option<String>, // The address of the listener exporting the metrics, if enabled
+    replica_of: Option<(String, Option<String>)>, // The Redis primary replicated (and its password), if any
+    warmup_source: Option<WarmupSource>, // Where the data is loaded from before listening, if anywhere
+    preload: Option<PathBuf>, // The seed file filling the keys not loaded otherwise, if any
+    snapshots: Option<Arc<Snapshots>>, // Where the cache is saved to and restored from, if anywhere
+    datasets: Option<Arc<Datasets>>, // Where `EXPORT` and `IMPORT` write and read datasets, if anywhere
+    cluster: Option<Arc<Cluster>>, // The cluster the node belongs to, which owns some of the hash slots
+    aof_rewrite: Option<(u32, u64)>, // The growth (percent) and size (bytes) the AOF is rewritten past
+    workers: WorkerOptions, // The sizing of the pool of threads serving the client connections
+    password: Option<String>, // The password the clients must present with `AUTH`, if any
+    acl_users: Vec<(String, AclUser)>, // The users the clients may authenticate as, with their permissions
+    unix_socket: Option<PathBuf>, // The path of the Unix domain socket serving the text protocol, if any
+    tcp_enabled: bool, // Whether the text protocol is served on the TCP address (or only on the socket)
+    shutdown: ShutdownHandle, // Stops the listeners and drains the connections once the server shuts down
+    shutdown_timeout: Duration, // How long the connections are given to close when the server shuts down
+    limits: ConnectionLimits, // How many connections may be open at once, and how long they may stay idle
+    rate_limit: Option<RateLimit>, // How many commands each connection of the text protocol may send, if limited
+}
+
+impl CacheServer {
+    /// Create a new `CacheServer` instance with the given host and port.
+    ///
+    /// # Arguments
+    /// * `host` - The host on which the server will listen for incoming connections.
+    ///   It should be an IP address or a host name.
+    /// * `port` - The port on which the server will listen for incoming connections.
+    ///
+    /// # Errors
+    /// * `ServerError::Cache` if the expiration sweeper of the cache cannot be started.
+    pub fn new(host: &str, port: u16) -> Result<CacheServer, ServerError> {
+        CacheServer::with_cache(host, port, CacheFactory::new_cache())
+    }
+
+    /// Create a new `CacheServer` instance serving the given cache, which may be backed by any storage engine.
+    ///
+    /// # Arguments
+    /// * `host` - The host on which the server will listen for incoming connections.
+    /// * `port` - The port on which the server will listen for incoming connections.
+    /// * `cache` - The cache instance to serve to the clients.
+    ///
+    /// # Errors
+    /// * `ServerError::Cache` if the expiration sweeper of the cache cannot be started.
+    pub fn with_cache(
+        host: &str,
+        port: u16,
+        cache: Arc<ServerCache>,
+    ) -> Result<CacheServer, ServerError> {
+        let cache: EmbeddedCache = EmbeddedCache::new(cache)?;
+        Ok(CacheServer::with_embedded(host, port, Arc::new(cache)))
+    }
+
+    /// Create a new `CacheServer` instance serving an `EmbeddedCache`, which the application may keep using
+    /// in-process at the same time.
+    ///
+    /// # Arguments
+    /// * `host` - The host on which the server will listen for incoming connections.
+    /// * `port` - The port on which the server will listen for incoming connections.
+    /// * `cache` - The embedded cache to serve to the clients.
+    pub fn with_embedded(host: &str, port: u16, cache: Arc<EmbeddedCache>) -> CacheServer {
+        CacheServer {
+            address: format!("{}:{}", host, port),
+            cache,
+            buffers: Arc::new(BufferBudget::new(DEFAULT_BUFFER_LIMIT)),
+            resp_address: None,
+            memcached_address: None,
+            http_address: None,
+            metrics_address: None,
+            websocket_address: None,
+            websocket_policy: WebSocketPolicy::default(),
+            udp_address: None,
+            replica_of: None,
+            warmup_source: None,
+            preload: None,
+            snapshots: None,
+            datasets: None,
+            cluster: None,
+            aof_rewrite: None,
+            workers: WorkerOptions::default(),
+            password: None,
+            acl_users: Vec::new(),
+            unix_socket: None,
+            tcp_enabled: true,
+            shutdown: ShutdownHandle::default(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            limits: ConnectionLimits::default(),
+            rate_limit: None,
+        }
+    }
+
+    /// Create a new `CacheServer` instance from an already validated configuration.
+    ///
+    /// # Errors
+    /// * `ServerError::Cache` if the configured storage engine cannot be set up.
+    pub fn from_config(config: &ServerConfig) -> Result<CacheServer, ServerError> {
+        let cache: EmbeddedCache = EmbeddedCache::from_config(config)?
+            .with_read_only(config.read_only)
+            .with_replica(config.replica_of.is_some());
+        let mut server: CacheServer =
+            CacheServer::with_embedded(&config.host, config.port, Arc::new(cache));
+
+        server.buffers = Arc::new(BufferBudget::new(config.connection_buffer_limit));
+        server = server
+            .with_workers(config.worker_options())
+            .with_shutdown_timeout(Duration::from_secs(config.shutdown_timeout_seconds))
+            .with_max_clients(config.maxclients);
+        if config.idle_timeout_seconds != 0 {
+            server = server.with_idle_timeout(Duration::from_secs(config.idle_timeout_seconds));
+        }
+        if !config.allow_ips.is_empty() || !config.deny_ips.is_empty() {
+            let filter: IpFilter = IpFilter::new(config.allow_ips.clone(), config.deny_ips.clone());
+            server = server.with_ip_filter(filter);
+        }
+        if config.command_timeout_ms != 0 {
+            server = server.with_command_timeout(Duration::from_millis(config.command_timeout_ms));
+        }
+        if config.client_output_bytes != 0 {
+            server = server.with_output_limit(config.client_output_bytes);
+        }
+        if config.rate_limit != 0 {
+            server = server.with_rate_limit(config.rate_limit, config.rate_limit_burst);
+        }
+        if config.resp_port != 0 {
+            server = server.with_resp_port(config.resp_port);
+        }
+        if config.memcached_port != 0 {
+            server = server.with_memcached_port(config.memcached_port);
+        }
+        if config.http_port != 0 {
+            server = server.with_http_port(config.http_port);
+        }
+        if config.websocket_port != 0 {
+            let policy: WebSocketPolicy = WebSocketPolicy {
+                allowed_origins: config.websocket_origins.clone(),
+                token: config.websocket_token.clone(),
+            };
+            server = server.with_websocket_port(config.websocket_port, policy);
+        }
+        if config.udp_port != 0 {
+            server = server.with_udp_port(config.udp_port);
+        }
+        if config.metrics_port != 0 {
+            server = server.with_metrics_port(config.metrics_port);
+        }
+        if let Some(primary) = &config.replica_of {
+            server = server.with_replica_of(primary, config.replica_auth.clone());
+        }
+        if let Some(source) = &config.warmup_source {
+            server = server.with_warmup_source(source.clone());
+        }
+        if let Some(path) = &config.preload {
+            server = server.with_preload(path);
+        }
+        if let Some(path) = &config.snapshot_path {
+            let interval: Option<Duration> = Some(config.snapshot_interval_seconds)
+                .filter(|&seconds| seconds != 0)
+                .map(Duration::from_secs);
+            server = server.with_snapshots(path, interval);
+        }
+        if let Some(directory) = &config.export_dir {
+            server = server.with_export_dir(directory);
+        }
+        if let (Some(slots), Some(address)) = (&config.cluster_nodes, &config.cluster_address) {
+            server = server.with_cluster(slots.clone(), address);
+        }
+        if config.appendonly_path.is_some() && config.aof_rewrite_percentage > 0 {
+            server = server.with_aof_rewrite_threshold(
+                config.aof_rewrite_percentage,
+                config.aof_rewrite_min_size,
+            );
+        }
+        if let Some(password) = &config.requirepass {
+            server = server.with_password(password);
+        }
+        if !config.acl_users.is_empty() {
+            server = server.with_acl_users(config.acl_users.clone());
+        }
+        if let Some(path) = &config.unix_socket {
+            server = server.with_unix_socket(path);
+            if config.port == 0 {
+                server = server.without_tcp();
+            }
+        }
+        Ok(server)
+    }
+
+    /// Also listen on the given port (of the same host) for clients speaking the Redis serialization
+    /// protocol (RESP), so that existing Redis clients can use the cache.
+    pub fn with_resp_port(mut self, port: u16) -> CacheServer {
+        let host: &str = self.address.rsplit_once(':').map_or("", |(host, _)| host);
+        self.resp_address = Some(format!("{}:{}", host, port));
+        self
+    }
+
+    /// Also listen on the given port (of the same host) for clients speaking the memcached text protocol,
+    /// so that applications already using memcached can use the cache.
+    pub fn with_memcached_port(mut self, port: u16) -> CacheServer {
+        let host: &str = self.address.rsplit_once(':').map_or("", |(host, _)| host);
+        self.memcached_address = Some(format!("{}:{}", host, port));
+        self
+    }
+
+    /// Also listen on the given port (of the same host) for HTTP clients, exposing the keys as REST
+    /// resources (`GET`, `PUT` and `DELETE` on `/keys/{key}`).
+    pub fn with_http_port(mut self, port: u16) -> CacheServer {
+        let host: &str = self.address.rsplit_once(':').map_or("", |(host, _)| host);
+        self.http_address = Some(format!("{}:{}", host, port));
+        self
+    }
+
+    /// Also listen on the given port (of the same host) for WebSocket clients (e.g. browsers) speaking the
+    /// text protocol, accepting the connections the given policy allows.
+    pub fn with_websocket_port(mut self, port: u16, policy: WebSocketPolicy) -> CacheServer {
+        let host: &str = self.address.rsplit_once(':').map_or("", |(host, _)| host);
+        self.websocket_address = Some(format!("{}:{}", host, port));
+        self.websocket_policy = policy;
+        self
+    }
+
+    /// Also listen on the given UDP port (of the same host) for single-datagram `GET` and `SET` commands,
+    /// for the clients favoring latency over reliability.
+    pub fn with_udp_port(mut self, port: u16) -> CacheServer {
+        let host: &str = self.address.rsplit_once(':').map_or("", |(host, _)| host);
+        self.udp_address = Some(format!("{}:{}", host, port));
+        self
+    }
+
+    /// Also listen on the given port (of the same host) for Prometheus to scrape the metrics of the server
+    /// (`GET /metrics`): the clients, the commands and their latencies, the hits and misses, the keys and
+    /// the memory.
+    pub fn with_metrics_port(mut self, port: u16) -> CacheServer {
+        let host: &str = self.address.rsplit_once(':').map_or("", |(host, _)| host);
+        self.metrics_address = Some(format!("{}:{}", host, port));
+        self
+    }
+
+    /// Replicate the string keys of a Redis primary (`host:port`) into the cache once started, attaching to
+    /// it as a replica does. The cache should be a replica (see `EmbeddedCache::with_replica`), so that it
+    /// only holds the data of the primary.
+    pub fn with_replica_of(mut self, primary: &str, password: Option<String>) -> CacheServer {
+        self.replica_of = Some((primary.to_string(), password));
+        self
+    }
+
+    /// Require the clients of the text protocol (over TCP or WebSocket) and of RESP to present the given
+    /// password with `AUTH` before any other command. The memcached, HTTP and UDP listeners, which have no
+    /// such command, are not protected by it.
+    pub fn with_password(mut self, password: &str) -> CacheServer {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// Also serve the text protocol on a Unix domain socket created at the given path, for the clients of
+    /// the same host (e.g. `redis-cli -s` like tools, or co-located services), sparing them the TCP stack.
+    /// A socket left at the path by a previous run is replaced, unless a server is still listening on it.
+    pub fn with_unix_socket(mut self, path: impl AsRef<Path>) -> CacheServer {
+        self.unix_socket = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Serve the text protocol only on the Unix domain socket (see `with_unix_socket`), rather than also on
+    /// the TCP address. The other protocols are still served on their ports. Ignored without a socket.
+    pub fn without_tcp(mut self) -> CacheServer {
+        self.tcp_enabled = false;
+        self
+    }
+
+    /// Let the clients authenticate as the given users with `AUTH <user> <password>`, restricting the
+    /// commands they may execute and the keys they may access (see `AclUser`). Like the password of the
+    /// server (the one of the `default` user), the users are required to connect once given.
+    pub fn with_acl_users(mut self, users: Vec<(String, AclUser)>) -> CacheServer {
+        self.acl_users = users;
+        self
+    }
+
+    /// Load the data of the given source (see `WarmupSource`) when started, before listening for clients, so
+    /// that a node freshly (re)started does not send a wave of misses to the backend. A source that cannot
+    /// be loaded is reported, and the server starts with the keys loaded so far.
+    pub fn with_warmup_source(mut self, source: WarmupSource) -> CacheServer {
+        self.warmup_source = Some(source);
+        self
+    }
+
+    /// Load the key/value/TTL triples of the given seed file (see `preload::preload`) when started, once the
+    /// snapshot and the warm-up source (if any) are loaded, and before listening for clients. The keys loaded
+    /// otherwise are kept. A seed file that cannot be loaded stops the server.
+    pub fn with_preload(mut self, path: impl AsRef<Path>) -> CacheServer {
+        self.preload = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Save snapshots of the cache (an RDB dump of every key, see `rdb::export`) to the given file on `SAVE`,
+    /// in the background on `BGSAVE`, every `interval` (if any) and when the server shuts down, and load the
+    /// snapshot when started, before the warm-up source (if any), so that a restart does not lose the data.
+    pub fn with_snapshots(
+        mut self,
+        path: impl AsRef<Path>,
+        interval: Option<Duration>,
+    ) -> CacheServer {
+        self.snapshots = Some(Arc::new(Snapshots::new(
+            path.as_ref().to_path_buf(),
+            interval,
+        )));
+        self
+    }
+
+    /// Let the clients export the keys of the cache with `EXPORT` to the files of the given directory, and
+    /// import them back with `IMPORT`, as JSON Lines or CBOR (see `dataset::DatasetFormat`).
+    pub fn with_export_dir(mut self, directory: impl AsRef<Path>) -> CacheServer {
+        self.datasets = Some(Arc::new(Datasets::new(directory.as_ref().to_path_buf())));
+        self
+    }
+
+    /// Serve the keys of the hash slots the given node (`host:port`, as named in the slot map) owns only, the
+    /// other keys being redirected to their node with `MOVED` over RESP, as Redis Cluster does (see
+    /// `cluster::SlotMap`), and answered with a `moved` error naming their node over the text protocol. The
+    /// memcached, HTTP, WebSocket and UDP listeners, which cannot redirect their clients, may not be enabled.
+    pub fn with_cluster(mut self, slots: SlotMap, address: &str) -> CacheServer {
+        self.cluster = Some(Arc::new(Cluster::new(slots, address.to_string())));
+        self
+    }
+
+    /// Rewrite the append-only log of the cache (if any, see `EmbeddedCache::from_config`) in the background
+    /// once it has grown by `percentage` percent since it was last rewritten, and to at least `min_size`
+    /// bytes, as with `BGREWRITEAOF`.
+    pub fn with_aof_rewrite_threshold(mut self, percentage: u32, min_size: u64) -> CacheServer {
+        self.aof_rewrite = Some((percentage, min_size));
+        self
+    }
+
+    /// Serve the client connections of all the listeners on a pool of threads sized with the given options
+    /// (see `WorkerOptions`), rather than the defaults.
+    pub fn with_workers(mut self, options: WorkerOptions) -> CacheServer {
+        self.workers = options;
+        self
+    }
+
+    /// Give the open connections the given time to answer the requests in flight when the server shuts
+    /// down (see `shutdown`), rather than 10 seconds. The connections still open afterwards are dropped.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> CacheServer {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Serve at most the given number of clients at once, across all the listeners (0 for no limit). The
+    /// clients connecting beyond are answered with an error of their protocol and disconnected.
+    pub fn with_max_clients(mut self, max_clients: usize) -> CacheServer {
+        self.limits.max_clients = max_clients;
+        self
+    }
+
+    /// Close the connections of the clients which have sent nothing for the given time, rather than keeping
+    /// them open until the clients close them. The WebSocket connections listening to keyspace events are
+    /// never idle.
+    pub fn with_idle_timeout(self, timeout: Duration) -> CacheServer {
+        self.limits.idle_timeout.set(Some(timeout));
+        self
+    }
+
+    /// Disconnect the clients whose commands received at once take longer than the given time (not counting
+    /// the time blocked waiting for other clients, e.g. by `BLPOP`), after answering them with an error of
+    /// their protocol. The commands are not interrupted, but the client cannot keep the worker busy with
+    /// more. The clients leaving their responses unread for that long are disconnected as well.
+    pub fn with_command_timeout(mut self, timeout: Duration) -> CacheServer {
+        self.limits.command_timeout = Some(timeout);
+        self
+    }
+
+    /// Stop writing to a client once it leaves the given number of bytes of responses unread (as buffered
+    /// by the socket, which the OS may round up), and disconnect it unless it reads them within the command
+    /// timeout (10 seconds if there is none), so that a slow client neither ties up a worker nor memory.
+    pub fn with_output_limit(mut self, bytes: usize) -> CacheServer {
+        self.limits.output_limit = bytes;
+        self
+    }
+
+    /// Only accept the TCP clients whose address the given filter permits, on every listener (the clients of
+    /// the Unix domain socket, being local, are always accepted). The others are logged and disconnected
+    /// right away, without any answer, and their UDP requests are dropped.
+    pub fn with_ip_filter(mut self, filter: IpFilter) -> CacheServer {
+        self.limits.ip_filter = Arc::new(filter);
+        self
+    }
+
+    /// Limit every connection of the text protocol (over TCP or the Unix domain socket) to the given number
+    /// of commands per second, with bursts of up to `burst` commands (at least one, the rate if zero). The
+    /// commands beyond are answered with a `rate_limited` error rather than executed, so that a runaway
+    /// client cannot starve the others.
+    pub fn with_rate_limit(mut self, rate: u32, burst: u32) -> CacheServer {
+        let burst: u32 = match burst {
+            0 => rate.max(1),
+            burst => burst,
+        };
+        self.rate_limit = Some(RateLimit { rate, burst });
+        self
+    }
+
+    /// A handle shutting the server down from another thread (e.g. of the application embedding it, or on
+    /// a signal with `ShutdownHandle::shutdown_on_signals`).
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Shut the server down: the listeners stop accepting connections, and the open connections stop
+    /// reading requests once the ones in flight are answered. `start` then returns, once the connections
+    /// are closed (or the shutdown timeout is over) and the cache is flushed.
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown();
+    }
+
+    /// The memory taken by the buffers of the client connections.
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.buffers.stats()
+    }
+
+    /// Import the string keys of a Redis RDB dump into the cache (see `rdb::import`), typically before
+    /// starting the server to migrate the data of an existing Redis deployment.
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the file cannot be read or is not a supported RDB file.
+    /// * `ServerError::Cache` if a key cannot be put into the cache.
+    pub fn import_rdb(&self, path: &Path) -> Result<ImportStats, ServerError> {
+        let file: File = File::open(path)?;
+        let stats: ImportStats = rdb::import(BufReader::new(file), &self.cache)?;
+
+        info!(
+            "Imported {} key(s) from {} ({} expired, {} skipped)",
+            stats.imported,
+            path.display(),
+            stats.expired,
+            stats.skipped
+        );
+        Ok(stats)
+    }
+
+    /// Start the server and listen for incoming connections from clients, until it is shut down (see
+    /// `shutdown`).
+    ///
+    /// # Errors
+    /// * `ServerError::Bind` if the server cannot listen on its address (e.g. the port is already in use), or
+    ///   if a listener which cannot redirect its clients is enabled in a cluster (see `with_cluster`).
+    pub fn start(&self) -> Result<(), ServerError> {
+        // Restore the last snapshot, then load the data of the warm-up source and of the seed file, if any,
+        // before any client can miss it
+        if let Some(snapshots) = &self.snapshots {
+            self._restore(snapshots)?;
+            snapshots.spawn_timer(&self.cache, self.shutdown.clone())?;
+        }
+        if let Some(source) = &self.warmup_source {
+            self._warm_up(source);
+        }
+        if let Some(path) = &self.preload {
+            self._preload(path)?;
+        }
+        if let Some((percentage, min_size)) = self.aof_rewrite {
+            self._spawn_aof_rewrite_timer(percentage, min_size)?;
+        }
+
+        // The clients of a cluster can only be redirected to the node owning their keys over RESP and the
+        // text protocol
+        if self.cluster.is_some() {
+            let unredirected: [&Option<String>; 4] = [
+                &self.memcached_address,
+                &self.http_address,
+                &self.websocket_address,
+                &self.udp_address,
+            ];
+            if let Some(address) = unredirected.into_iter().flatten().next() {
+                return Err(ServerError::Bind {
+                    address: address.clone(),
+                    source: io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "the clients of this listener cannot be redirected between the nodes of a cluster",
+                    ),
+                });
+            }
+        }
+
+        // Bind the server to the specified port and socket
+        let mut listeners: Vec<ClientListener> = Vec::new();
+        let mut port: u16 = 0;
+        if self.tcp_enabled || self.unix_socket.is_none() {
+            let listener: TcpListener = self._bind(&self.address)?;
+            port = listener.local_addr()?.port();
+            listeners.push(ClientListener::Tcp(listener));
+        }
+        if let Some(path) = &self.unix_socket {
+            listeners.push(ClientListener::Unix(self._bind_unix(path)?));
+        }
+        let workers: Arc<WorkerPool> = Arc::new(WorkerPool::new(&self.workers));
+        let auth: Arc<Auth> = Arc::new(Auth::new(self.password.clone(), self.acl_users.clone()));
+        // The operational parameters, which the clients of the text protocol and of RESP can change
+        let runtime: Arc<RuntimeConfig> = Arc::new(RuntimeConfig::new(
+            Arc::clone(&self.cache),
+            Arc::clone(&self.buffers),
+            Arc::clone(&workers),
+            Arc::clone(&self.limits.idle_timeout),
+        ));
+
+        // Serve the clients of the other protocols on their own listeners, if enabled
+        let mut threads: Vec<JoinHandle<()>> = Vec::new();
+        threads.extend(self._serve::<RespClientHandler>(
+            self.resp_address.as_deref(),
+            &workers,
+            RespOptions {
+                runtime: Arc::clone(&runtime),
+                auth: Arc::clone(&auth),
+                snapshots: self.snapshots.clone(),
+                datasets: self.datasets.clone(),
+                cluster: self.cluster.clone(),
+                shutdown: self.shutdown.clone(),
+            },
+        )?);
+        threads.extend(self._serve::<MemcachedClientHandler>(
+            self.memcached_address.as_deref(),
+            &workers,
+            (),
+        )?);
+        threads.extend(self._serve::<HttpClientHandler>(
+            self.http_address.as_deref(),
+            &workers,
+            HttpRoutes::Keys,
+        )?);
+        threads.extend(self._serve::<HttpClientHandler>(
+            self.metrics_address.as_deref(),
+            &workers,
+            HttpRoutes::Metrics,
+        )?);
+        threads.extend(self._serve::<WebSocketClientHandler>(
+            self.websocket_address.as_deref(),
+            &workers,
+            WebSocketOptions {
+                policy: self.websocket_policy.clone(),
+                auth: Arc::clone(&auth),
+            },
+        )?);
+        if auth.is_required() {
+            let unprotected: [(&str, &Option<String>); 3] = [
+                ("memcached", &self.memcached_address),
+                ("HTTP", &self.http_address),
+                ("UDP", &self.udp_address),
+            ];
+            for (protocol, _) in unprotected.iter().filter(|(_, address)| address.is_some()) {
+                warn!(
+                    "The {} listener does not require the password of the server",
+                    protocol
+                );
+            }
+        }
+
+        if let Some(address) = &self.udp_address {
+            let socket: UdpSocket =
+                UdpSocket::bind(address).map_err(|source| ServerError::Bind {
+                    address: address.clone(),
+                    source,
+                })?;
+            info!("Server has started on {} (UDP)", address);
+            let threads: usize = thread::available_parallelism().map_or(1, |n| n.get());
+            UdpServer::new(
+                socket,
+                Arc::clone(&self.cache),
+                self.shutdown.clone(),
+                Arc::clone(&self.limits.ip_filter),
+            )
+            .spawn(threads)?;
+        }
+
+        // Replicate the Redis primary, if any, while serving the data replicated so far
+        if let Some((primary, password)) = &self.replica_of {
+            Replica::new(
+                primary.clone(),
+                password.clone(),
+                port,
+                Arc::clone(&self.cache),
+            )
+            .spawn()?;
+        }
+
+        // Listen for incoming connections
+        let options: Arc<TextOptions> = Arc::new(TextOptions {
+            runtime,
+            auth,
+            rate_limit: self.rate_limit,
+            snapshots: self.snapshots.clone(),
+            datasets: self.datasets.clone(),
+            cluster: self.cluster.clone(),
+            shutdown: self.shutdown.clone(),
+        });
+
+        // The last listener is served on the calling thread, and the other one (if any) on a thread of its own
+        let last: Option<ClientListener> = listeners.pop();
+        for listener in listeners {
+            let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+            let buffers: Arc<BufferBudget> = Arc::clone(&self.buffers);
+            let workers: Arc<WorkerPool> = Arc::clone(&workers);
+            let options: Arc<TextOptions> = Arc::clone(&options);
+            let limits: ConnectionLimits = self.limits.clone();
+            let shutdown: ShutdownHandle = self.shutdown.clone();
+            threads.push(thread::spawn(move || {
+                accept_clients::<TcpClientHandler>(
+                    listener, cache, buffers, workers, options, limits, shutdown,
+                )
+            }));
+        }
+        if let Some(listener) = last {
+            accept_clients::<TcpClientHandler>(
+                listener,
+                Arc::clone(&self.cache),
+                Arc::clone(&self.buffers),
+                workers,
+                options,
+                self.limits.clone(),
+                self.shutdown.clone(),
+            );
+        }
+
+        // The listeners only stop once the server shuts down
+        for thread in threads {
+            let _ = thread.join();
+        }
+        self._stop();
+        Ok(())
+    }
+
+    /// Finish shutting the server down once its listeners have stopped: drain the open connections, make
+    /// the data of the cache durable (saving a snapshot, if enabled), and remove the Unix domain socket (if
+    /// any).
+    fn _stop(&self) {
+        self.shutdown.drain(self.shutdown_timeout);
+
+        if self.snapshots.is_some() && self.shutdown.skips_snapshot() {
+            info!("Not saving a final snapshot, as requested");
+        } else if let Some(snapshots) = &self.snapshots {
+            match snapshots.save(&self.cache) {
+                Ok(Some(_)) => {}
+                Ok(None) => warn!("Shutting down while a snapshot is being saved"),
+                Err(e) => error!("Failed to save a snapshot: {}", e),
+            }
+        }
+        if let Err(e) = self.cache.flush() {
+            error!("Failed to flush the cache: {}", e);
+        }
+        if let Some(path) = &self.unix_socket {
+            let _ = fs::remove_file(path);
+        }
+        info!("Server has shut down");
+    }
+
+    /// Load the last snapshot into the cache, if there is one, unless the append-only log has already
+    /// restored the data (being more recent). Unlike a warm-up source, a snapshot which cannot be loaded
+    /// stops the server, rather than it starting empty and overwriting the snapshot.
+    fn _restore(&self, snapshots: &Snapshots) -> Result<(), ServerError> {
+        if let Some(log) = self.cache.append_only_log() {
+            if log.size()? > 0 {
+                info!(
+                    "Not loading the snapshot {}, the append-only log {} being more recent",
+                    snapshots.path().display(),
+                    log.path().display()
+                );
+                return Ok(());
+            }
+        }
+
+        let started: Instant = Instant::now();
+        match snapshots.load(&self.cache)? {
+            Some(stats) => info!(
+                "Restored {} key(s) from the snapshot {} in {:?} ({} expired)",
+                stats.imported,
+                snapshots.path().display(),
+                started.elapsed(),
+                stats.expired
+            ),
+            None => info!(
+                "No snapshot at {} yet, starting empty",
+                snapshots.path().display()
+            ),
+        }
+        Ok(())
+    }
+
+    /// Check every second on a thread of its own whether the append-only log of the cache (if any) has grown
+    /// past the threshold given with `with_aof_rewrite_threshold`, rewriting it if so, until the server
+    /// shuts down.
+    fn _spawn_aof_rewrite_timer(&self, percentage: u32, min_size: u64) -> Result<(), ServerError> {
+        if self.cache.append_only_log().is_none() {
+            return Ok(());
+        }
+
+        let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+        let shutdown: ShutdownHandle = self.shutdown.clone();
+        thread::Builder::new()
+            .name("aof-rewrite-timer".to_string())
+            .spawn(move || {
+                let mut last_check: Instant = Instant::now();
+                while !shutdown.is_shutting_down() {
+                    thread::sleep(SHUTDOWN_POLL_INTERVAL);
+                    if last_check.elapsed() < AOF_REWRITE_CHECK_INTERVAL {
+                        continue;
+                    }
+                    last_check = Instant::now();
+
+                    let rewrite = || -> CacheResult<()> {
+                        let log: &Arc<AppendOnlyLog> = match cache.append_only_log() {
+                            Some(log) if log.needs_rewrite(percentage, min_size)? => log,
+                            _ => return Ok(()),
+                        };
+                        info!(
+                            "Rewriting the append-only log {} ({} bytes)",
+                            log.path().display(),
+                            log.size()?
+                        );
+                        let _guard = cache.transaction_lock()?;
+                        cache.rewrite_append_only_log().map(|_| ())
+                    };
+                    if let Err(e) = rewrite() {
+                        error!("Failed to rewrite the append-only log: {}", e);
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Load the data of the given warm-up source into the cache, reporting the failures rather than
+    /// returning them: a node which cannot be warmed up (e.g. the first node of a cluster) still serves.
+    fn _warm_up(&self, source: &WarmupSource) {
+        info!("Warming up from {}", source);
+        let started: Instant = Instant::now();
+        match source.load(&self.cache) {
+            Ok(stats) => info!(
+                "Warmed up from {} with {} key(s) in {:?} ({} expired, {} skipped)",
+                source,
+                stats.imported,
+                started.elapsed(),
+                stats.expired,
+                stats.skipped
+            ),
+            Err(e) => warn!("Failed to warm up from {}, starting cold: {}", source, e),
+        }
+    }
+
+    /// Load the given seed file into the cache (see `preload::preload`).
+    fn _preload(&self, path: &Path) -> Result<(), ServerError> {
+        let started: Instant = Instant::now();
+        let stats: ImportStats = preload::preload(&self.cache, path)?;
+        info!(
+            "Preloaded {} key(s) from {} in {:?} ({} expired, {} already cached)",
+            stats.imported,
+            path.display(),
+            started.elapsed(),
+            stats.expired,
+            stats.skipped
+        );
+        Ok(())
+    }
+
+    /// Accept the clients of a protocol on a listener bound to the given address (if any), on a thread of
+    /// its own, serving them on the given workers.
+    ///
+    /// # Returns
+    /// * The thread of the listener, which stops once the server shuts down, or `None` if not enabled.
+    fn _serve<H: ClientHandler>(
+        &self,
+        maybe_address: Option<&str>,
+        workers: &Arc<WorkerPool>,
+        options: H::Options,
+    ) -> Result<Option<JoinHandle<()>>, ServerError> {
+        let Some(address) = maybe_address else {
+            return Ok(None);
+        };
+
+        let listener: TcpListener = self._bind(address)?;
+        let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+        let buffers: Arc<BufferBudget> = Arc::clone(&self.buffers);
+        let workers: Arc<WorkerPool> = Arc::clone(workers);
+        let options: Arc<H::Options> = Arc::new(options);
+        let limits: ConnectionLimits = self.limits.clone();
+        let shutdown: ShutdownHandle = self.shutdown.clone();
+        let thread: JoinHandle<()> = thread::spawn(move || {
+            accept_clients::<H>(
+                ClientListener::Tcp(listener),
+                cache,
+                buffers,
+                workers,
+                options,
+                limits,
+                shutdown,
+            )
+        });
+        Ok(Some(thread))
+    }
+
+    /// Bind a listener of the server to the given address and port.
+    fn _bind(&self, address: &str) -> Result<TcpListener, ServerError> {
+        match TcpListener::bind(address) {
+            Ok(listener) => {
+                info!("Server has started on {}", address);
+                Ok(listener)
+            }
+            Err(source) => Err(ServerError::Bind {
+                address: address.to_string(),
+                source,
+            }),
+        }
+    }
+
+    /// Bind the listener of the Unix domain socket at the given path, replacing the socket left there by
+    /// a previous run (e.g. one which crashed). A socket a server is still listening on is kept, failing
+    /// the bind instead.
+    fn _bind_unix(&self, path: &Path) -> Result<UnixListener, ServerError> {
+        let stale: bool = fs::symlink_metadata(path)
+            .is_ok_and(|metadata| metadata.file_type().is_socket())
+            && UnixStream::connect(path).is_err();
+        if stale {
+            debug!("Removing the stale socket {}", path.display());
+            let _ = fs::remove_file(path);
+        }
+
+        match UnixListener::bind(path) {
+            Ok(listener) => {
+                info!(
+                    "Server has started on {} (Unix domain socket)",
+                    path.display()
+                );
+                Ok(listener)
+            }
+            Err(source) => Err(ServerError::Bind {
+                address: path.display().to_string(),
+                source,
+            }),
+        }
+    }
+}
+
+/// The settings of the listener of the text protocol.
+struct TextOptions {
+    runtime: Arc<RuntimeConfig>, // The parameters the clients can change with `CONFIG`
+    auth: Arc<Auth>,             // Who may execute commands
+    rate_limit: Option<RateLimit>, // How many commands each connection may send, if limited
+    snapshots: Option<Arc<Snapshots>>, // Where `SAVE` and `BGSAVE` save the cache to, if anywhere
+    datasets: Option<Arc<Datasets>>, // Where `EXPORT` and `IMPORT` write and read datasets, if anywhere
+    cluster: Option<Arc<Cluster>>, // The cluster the node belongs to, which owns some of the hash slots
+    shutdown: ShutdownHandle,      // Stops the server on `SHUTDOWN`
+}
+
+/// A handler struct created for each client connection of the text protocol, over TCP or a Unix domain
+/// socket.
+///
+/// Objects of this struct are responsible for handling the client connection, reading
+/// messages from the client, executing cache commands, and sending responses back to
+/// the client.
+struct TcpClientHandler {
+    session: TextSession<BufWriter<ClientStream>>, // Executes the commands, writing the responses to the client
+    buffers: ConnectionBuffers, // The received data, in buffers sized to the traffic of the client
+}
+
+impl ClientHandler for TcpClientHandler {
+    const PROTOCOL: &'static str = "text";
+    type Options = TextOptions;
+
+    /// Create a new `TcpClientHandler` instance with the given TCP stream and cache.
+    /// The address of the client is automatically determined from the stream.
+    fn new(
+        stream: ClientStream,
+        cache: Arc<EmbeddedCache>,
+        buffers: ConnectionBuffers,
+        options: &Arc<TextOptions>,
+    ) -> TcpClientHandler {
+        let connection: Option<ClientStream> = stream.try_clone().ok();
+        let mut session: TextSession<BufWriter<ClientStream>> =
+            TextSession::new(stream.peer_address(), BufWriter::new(stream), cache)
+                .with_runtime(Arc::clone(&options.runtime))
+                .with_auth(Arc::clone(&options.auth))
+                .with_pushes();
+        if let Some(limit) = options.rate_limit {
+            session = session.with_rate_limit(limit);
+        }
+        if let Some(connection) = connection {
+            session = session.with_connection(connection);
+        }
+        if let Some(snapshots) = &options.snapshots {
+            session = session.with_snapshots(Arc::clone(snapshots));
+        }
+        if let Some(datasets) = &options.datasets {
+            session = session.with_datasets(Arc::clone(datasets));
+        }
+        if let Some(cluster) = &options.cluster {
+            session = session.with_cluster(Arc::clone(cluster));
+        }
+        session = session.with_shutdown(options.shutdown.clone());
+        TcpClientHandler { session, buffers }
+    }
+
+    fn address(&self) -> &str {
+        &self.session.address
+    }
+
+    fn connection(&mut self) -> (&ClientStream, &mut ConnectionBuffers) {
+        (self.session.stream.get_ref(), &mut self.buffers)
+    }
+
+    /// Handle every complete command (i.e. line or frame) received so far, leaving an incomplete one
+    /// pending, then flush the responses.
+    fn handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        // The messages of the channels subscribed to are not pushed in the middle of the responses
+        let writes: Option<Arc<Mutex<()>>> = self
+            .session
+            .subscriptions
+            .as_ref()
+            .map(Subscriptions::writes);
+        let _guard = match &writes {
+            Some(writes) => Some(writes.lock().map_err(CacheError::from)?),
+            None => None,
+        };
+        self.session.handle_pending(pending)?;
+
+        // The messages are pushed once the responses to the first subscriptions are written
+        if let Some(subscriptions) = &mut self.session.subscriptions {
+            let encode: fn(&Message) -> Vec<u8> = match (self.session.monitoring, self.session.bulk)
+            {
+                (true, _) => encode_monitored_command,
+                (false, true) => encode_bulk_message,
+                (false, false) => encode_text_message,
+            };
+            let stream: ClientStream = self.session.stream.get_ref().try_clone()?;
+            subscriptions.forward(stream, &self.session.address, encode);
+        }
+        Ok(())
+    }
+
+    fn reject_overflow(&mut self) {
+        let _ = self
+            .session
+            ._write_error(ErrorCode::OutOfMemory, "Out of buffer memory");
+        let _ = self.session.stream.flush();
+    }
+
+    fn reject_too_many_clients(&mut self) {
+        let _ = self
+            .session
+            ._write_error(ErrorCode::TooManyClients, "Max number of clients reached");
+        let _ = self.session.stream.flush();
+    }
+
+    fn reject_timeout(&mut self) {
+        let _ = self
+            .session
+            ._write_error(ErrorCode::CommandTimeout, "Command timeout exceeded");
+        let _ = self.session.stream.flush();
+    }
+
+    fn is_listening(&self) -> bool {
+        self.session.is_subscribed()
+    }
+}
+
+/// The execution of the commands of the text protocol received from a client, writing their responses
+/// to the given writer (e.g. the client connection, or the messages of a WebSocket).
+///
+/// Every command is a line terminated by `\n`, and several of them may be sent at once (pipelining).
+/// Responses are buffered and flushed once all the complete commands received so far have been handled,
+/// so a batch of pipelined commands is answered with a single write. After `HELLO BINARY`, `HELLO MSGPACK`
+/// or `HELLO PROTOBUF`, the commands and responses are binary frames, MessagePack maps or protobuf messages
+/// instead. After `HELLO STRICT`, malformed commands are rejected with the codes of `ErrorCode` rather than
+/// free-text errors. After `HELLO BULK`, a `$<length>` argument is sent as raw bytes following the line,
+/// and values are answered as `$<length>` lines followed by their raw bytes, so that they may hold any byte.
+/// After `SELECT <namespace>`, the keys of the commands are those of the namespace, and after `SELECT <index>`
+/// (with several databases) those of the database.
+///
+/// A text command may be prefixed by a request id chosen by the client (e.g. `@42 GET foo`), which is
+/// echoed in front of its response (`@42 bar`) and in the logs, so that clients multiplexing their callers
+/// over a connection, and operators, can tell which command a response or an error belongs to.
+pub(crate) struct TextSession<W: Write> {
+    address: String, // The address of the client (IP:Port). Used for logging purposes.
+    stream: W,       // Where the responses are written, buffered until the next flush
+    cache: Arc<EmbeddedCache>, // A reference to the cache instance shared across all sessions
+    framing: Framing, // How the commands and responses are framed, as negotiated with `HELLO`
+    strict: bool, // Whether the text commands are strictly validated, answering errors with their code
+    bulk: bool, // Whether the text commands and values may carry bulk payloads (see `protocol::bulk_end`)
+    namespace: Option<String>, // The namespace selected with `SELECT`, or `None` for the default one
+    database: usize,           // The database selected with `SELECT <index>` (0 by default)
+    request_id: Option<Vec<u8>>, // The id of the text command being handled, echoed in its response
+    runtime: Option<Arc<RuntimeConfig>>, // The parameters changed with `CONFIG`, if the session may do so
+    auth: Arc<Auth>,                     // Who may execute commands (anyone by default)
+    user: Option<usize>, // The user the client has authenticated as with `AUTH` (see `Auth::user`), if any
+    rate_limit: Option<TokenBucket>, // Limits the commands of the client, if they are limited
+    throttled: bool, // Whether the last command exceeded the rate limit (to log the first one only)
+    answered_error: bool, // Whether the command being executed was answered with an error
+    pushes: bool, // Whether messages can be pushed to the client, who may then subscribe to channels
+    subscriptions: Option<Subscriptions>, // The channels subscribed to with `SUBSCRIBE`, once it is sent
+    monitoring: bool, // Whether the client has sent `MONITOR`, its subscription being the monitor feed
+    transaction: Option<Transaction<QueuedMessage>>, // The commands queued since `MULTI`, until `EXEC`
+    watch: Option<Watch>, // The keys watched with `WATCH`, until the next `EXEC` or `DISCARD`
+    connection: Option<ClientStream>, // The client connection, checked for a disconnection while BLPOP waits
+    blocking: bool, // Whether the command being executed may wait (i.e. BLPOP or BRPOP outside of EXEC)
+    snapshots: Option<Arc<Snapshots>>, // Where `SAVE` and `BGSAVE` save the cache to, if anywhere
+    datasets: Option<Arc<Datasets>>, // Where `EXPORT` and `IMPORT` write and read datasets, if anywhere
+    cluster: Option<Arc<Cluster>>, // The cluster the node belongs to, whose other nodes own some of the keys
+    shutdown: Option<ShutdownHandle>, // Stops the server on `SHUTDOWN`, if the session may do so
+}
+
+/// A text message queued by a transaction, along with its bulk payloads (if any).
+struct QueuedMessage {
+    message: Vec<u8>,
+    payloads: Option<Vec<u8>>,
+}
+
+/// How the commands and responses of a `TextSession` are framed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Text,        // Lines of whitespace separated tokens
+    Binary,      // Length-prefixed binary frames (see `binary`)
+    MessagePack, // MessagePack maps with typed fields (see `_handle_msgpack_request`)
+    Protobuf,    // Length-prefixed protobuf messages (see `proto/myrustcache.proto`)
+}
+
+impl<W: Write> TextSession<W> {
+    /// The longest command accepted. A client sending a longer line is disconnected.
+    const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+    /// The longest request id accepted in front of a text command.
+    const MAX_REQUEST_ID_SIZE: usize = 64;
+
+    pub fn new(address: String, stream: W, cache: Arc<EmbeddedCache>) -> TextSession<W> {
+        TextSession {
+            address,
+            stream,
+            cache,
+            framing: Framing::Text,
+            strict: false,
+            bulk: false,
+            namespace: None,
+            database: 0,
+            request_id: None,
+            runtime: None,
+            auth: Arc::new(Auth::default()),
+            user: None,
+            rate_limit: None,
+            throttled: false,
+            answered_error: false,
+            pushes: false,
+            subscriptions: None,
+            monitoring: false,
+            transaction: None,
+            watch: None,
+            connection: None,
+            blocking: false,
+            snapshots: None,
+            datasets: None,
+            cluster: None,
+            shutdown: None,
+        }
+    }
+
+    /// Let the session read and change the operational parameters of the server with `CONFIG`.
+    pub fn with_runtime(mut self, runtime: Arc<RuntimeConfig>) -> TextSession<W> {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Require the client to authenticate with `AUTH` before any other command, if the given `Auth` says so.
+    pub fn with_auth(mut self, auth: Arc<Auth>) -> TextSession<W> {
+        self.auth = auth;
+        self
+    }
+
+    /// Let the client shut the server down with `SHUTDOWN`.
+    pub fn with_shutdown(mut self, shutdown: ShutdownHandle) -> TextSession<W> {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Let the client save snapshots of the cache with `SAVE` and `BGSAVE`.
+    pub fn with_snapshots(mut self, snapshots: Arc<Snapshots>) -> TextSession<W> {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
+    /// Let the client export and import the keys of the cache with `EXPORT` and `IMPORT`.
+    pub fn with_datasets(mut self, datasets: Arc<Datasets>) -> TextSession<W> {
+        self.datasets = Some(datasets);
+        self
+    }
+
+    /// Answer the commands on keys whose hash slot another node of the given cluster owns with a `moved`
+    /// error naming that node, and only let the client use the text framing, whose keys are checked.
+    pub fn with_cluster(mut self, cluster: Arc<Cluster>) -> TextSession<W> {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// Answer the commands of the client beyond the given limit with a `rate_limited` error.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> TextSession<W> {
+        self.rate_limit = Some(TokenBucket::new(limit));
+        self
+    }
+
+    /// Let the client subscribe to channels with `SUBSCRIBE`, the messages of which the caller pushes to the
+    /// client (see `Subscriptions::forward`).
+    pub fn with_pushes(mut self) -> TextSession<W> {
+        self.pushes = true;
+        self
+    }
+
+    /// Stop waiting for a value with `BLPOP` or `BRPOP` once the client has closed the given connection,
+    /// rather than once the time is up.
+    pub fn with_connection(mut self, connection: ClientStream) -> TextSession<W> {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// Whether the client is subscribed to any channel.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscriptions
+            .as_ref()
+            .is_some_and(|subscriptions| !subscriptions.channels().is_empty())
+    }
+
+    /// The writer the responses are written to.
+    pub fn writer(&mut self) -> &mut W {
+        &mut self.stream
+    }
+
+    /// The cache the commands are executed on.
+    pub fn cache(&self) -> &Arc<EmbeddedCache> {
+        &self.cache
+    }
+
+    /// Handle the complete commands of the given pending data, draining them, then flush the responses.
+    pub fn handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        let mut start: usize = 0;
+
+        // The client may switch to another framing in the middle of the data, so its framing is checked
+        // before every command
+        loop {
+            match self.framing {
+                Framing::Binary => {
+                    match binary::decode(&pending[start..], Self::MAX_MESSAGE_SIZE) {
+                        Decoded::Request(request, length) => {
+                            self._handle_frame(&request)?;
+                            start += length;
+                            continue;
+                        }
+                        Decoded::Incomplete => break,
+                        Decoded::Invalid(reason) => {
+                            warn!("Invalid frame from {}: {}", self.address, reason);
+                            self._write_frame(binary::STATUS_INVALID, 0, 0, reason.as_bytes())?;
+                            return self._close_invalid(reason);
+                        }
+                    }
+                }
+                Framing::MessagePack => {
+                    match msgpack::decode(&pending[start..], Self::MAX_MESSAGE_SIZE) {
+                        msgpack::Decoded::Value(request, length) => {
+                            self._handle_msgpack_request(&request)?;
+                            start += length;
+                            continue;
+                        }
+                        msgpack::Decoded::Incomplete => break,
+                        msgpack::Decoded::Invalid(reason) => {
+                            warn!("Invalid MessagePack from {}: {}", self.address, reason);
+                            self._write_msgpack(None, "invalid", Some(reason), None)?;
+                            return self._close_invalid(reason);
+                        }
+                    }
+                }
+                Framing::Protobuf => {
+                    match protobuf::decode(&pending[start..], Self::MAX_MESSAGE_SIZE) {
+                        protobuf::Decoded::Command(command, length) => {
+                            self._handle_protobuf_command(&command)?;
+                            start += length;
+                            continue;
+                        }
+                        protobuf::Decoded::Incomplete => break,
+                        protobuf::Decoded::Invalid(reason) => {
+                            warn!("Invalid protobuf message from {}: {}", self.address, reason);
+                            self._write_protobuf(0, protobuf::STATUS_INVALID, reason, b"")?;
+                            return self._close_invalid(reason);
+                        }
+                    }
+                }
+                Framing::Text => {}
+            }
+
+            let Some(end) = memchr::memchr(b'\n', &pending[start..]) else {
+                break;
+            };
+            let message: &[u8] = &pending[start..start + end];
+            let rest: &[u8] = &pending[start + end + 1..];
+            let payloads: Option<&[u8]> = match self.bulk {
+                true => match protocol::bulk_end(message, rest) {
+                    Bulk::Complete(length) => Some(&rest[..length]),
+                    Bulk::Incomplete => break,
+                    Bulk::Invalid => {
+                        warn!("Invalid bulk payload from {}", self.address);
+                        self._write_error(ErrorCode::InvalidBulk, "Invalid bulk payload")?;
+                        return self._close_invalid("invalid bulk payload");
+                    }
+                },
+                false => None,
+            };
+            debug!(
+                "Received message from {} -> {}",
+                self.address,
+                String::from_utf8_lossy(message)
+            );
+
+            self._handle_message(message, payloads)?;
+            start += end + 1 + payloads.map_or(0, <[u8]>::len);
+        }
+        pending.drain(..start);
+
+        if self.framing == Framing::Text && pending.len() > Self::MAX_MESSAGE_SIZE {
+            warn!("Too long message from {}", self.address);
+            let code: ErrorCode = match Tokens::new(pending).next() {
+                Some(
+                    b"PUT" | b"SET" | b"MSET" | b"APPEND" | b"HSET" | b"LPUSH" | b"RPUSH" | b"XADD",
+                ) => ErrorCode::ValueTooLarge,
+                _ => ErrorCode::MessageTooLong,
+            };
+            self._write_error(code, "Message too long")?;
+            return self._close_invalid("message too long");
+        }
+
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Handle the incoming message from the client, along with the bulk payloads following it (if any).
+    ///
+    /// Cache errors are reported to the client, while I/O errors are returned to the caller since they
+    /// leave the connection in an unusable state.
+    fn _handle_message(
+        &mut self,
+        message: &[u8],
+        payloads: Option<&[u8]>,
+    ) -> Result<(), ServerError> {
+        let mut parts: Tokens = match payloads {
+            Some(payloads) => Tokens::with_payloads(message, payloads),
+            None => Tokens::new(message),
+        };
+        let mut command: Option<&[u8]> = parts.next();
+
+        if let Some(id) = command.and_then(|token| token.strip_prefix(b"@")) {
+            if id.is_empty() || id.len() > Self::MAX_REQUEST_ID_SIZE {
+                warn!("Invalid request id from {}", self.address);
+                return self._write_error(ErrorCode::InvalidRequestId, "Invalid request id");
+            }
+            self.request_id = Some(id.to_vec());
+            command = parts.next();
+        }
+
+        // Only AUTH is accepted until the client authenticates, if it has to, and then only the commands and
+        // keys the user is allowed
+        let authenticated: bool = self.user.is_some() || !self.auth.is_required();
+        let result: Result<(), ServerError> = match command {
+            Some(_) if self._throttled() => {
+                self._write_error(ErrorCode::RateLimited, "Rate limit exceeded")
+            }
+            Some(name) if !authenticated && name != b"AUTH" => {
+                warn!("Unauthenticated command from {}", self.address);
+                self._write_error(ErrorCode::AuthRequired, "NOAUTH Authentication required")
+            }
+            Some(_) if self.monitoring => self._write_error(
+                ErrorCode::Subscribed,
+                "No command is accepted while monitoring",
+            ),
+            Some(name)
+                if self.is_subscribed() && name != b"SUBSCRIBE" && name != b"UNSUBSCRIBE" =>
+            {
+                self._write_error(
+                    ErrorCode::Subscribed,
+                    "Only SUBSCRIBE and UNSUBSCRIBE are accepted while subscribed",
+                )
+            }
+            Some(name) => match self._denied(name, parts.clone()) {
+                Some(reason) => {
+                    warn!(
+                        "Command{} from {} denied: {}",
+                        self._request_id_suffix(),
+                        self.address,
+                        reason
+                    );
+                    self._write_error(ErrorCode::NoPermission, &format!("NOPERM {}", reason))
+                }
+                None => match self._cluster_redirect(name, parts.clone()) {
+                    Some(redirect) => {
+                        debug!(
+                            "Redirecting command{} from {}: {}",
+                            self._request_id_suffix(),
+                            self.address,
+                            redirect
+                        );
+                        self._write_redirect(&redirect)
+                    }
+                    None => self._execute_or_queue(name, parts, message, payloads),
+                },
+            },
+            None => self._execute_message(command, parts),
+        };
+        self.request_id = None;
+        result
+    }
+
+    /// Execute a command the client is allowed to, or queue it if a transaction is open (see `MULTI`).
+    fn _execute_or_queue(
+        &mut self,
+        name: &[u8],
+        parts: Tokens,
+        message: &[u8],
+        payloads: Option<&[u8]>,
+    ) -> Result<(), ServerError> {
+        match (name, &mut self.transaction) {
+            (b"MULTI", _) => self._handle_multi_command(parts),
+            (b"EXEC", _) => self._handle_exec_command(parts),
+            (b"DISCARD", _) => self._handle_discard_command(parts),
+            (b"WATCH", Some(_)) => {
+                self._write_error(ErrorCode::Transaction, "WATCH inside MULTI is not allowed")
+            }
+            (b"HELLO" | b"SUBSCRIBE" | b"UNSUBSCRIBE" | b"MONITOR", Some(transaction)) => {
+                transaction.fail();
+                let message: String = format!(
+                    "{} cannot be queued in a transaction",
+                    String::from_utf8_lossy(name)
+                );
+                self._write_error(ErrorCode::Transaction, &message)
+            }
+            (_, Some(transaction)) => {
+                let queued: QueuedMessage = QueuedMessage {
+                    message: message.to_vec(),
+                    payloads: payloads.map(<[u8]>::to_vec),
+                };
+                match transaction.queue(queued) {
+                    true => self._write_response("QUEUED\n"),
+                    false => self._write_error(ErrorCode::Transaction, "Too many commands queued"),
+                }
+            }
+            // A client waiting for a value does not hold up the transactions of the other clients meanwhile
+            // (see `_handle_blocking_pop_command`)
+            (b"BLPOP" | b"BRPOP", None) => {
+                self.blocking = true;
+                let result: Result<(), ServerError> = self._execute_message(Some(name), parts);
+                self.blocking = false;
+                result
+            }
+            // A script executes several commands, which those of the other clients may not come in between,
+            // as does an import, and a rewrite of the append-only log starts with no command half applied
+            (b"EVAL" | b"IMPORT" | b"BGREWRITEAOF", None) => {
+                let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+                let _guard = cache.transaction_lock()?;
+                self._execute_message(Some(name), parts)
+            }
+            (_, None) => {
+                let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+                let _guard = cache.command_lock()?;
+                self._execute_message(Some(name), parts)
+            }
+        }
+    }
+
+    /// Execute a request of the binary framings, which have no transactions, holding the shared side of the
+    /// lock isolating the transactions of the other clients (see `EmbeddedCache::command_lock`).
+    fn _isolated(
+        &mut self,
+        execute: impl FnOnce(&mut Self) -> Result<(), ServerError>,
+    ) -> Result<(), ServerError> {
+        let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+        let _guard = cache.command_lock()?;
+        execute(self)
+    }
+
+    /// Take a token of the rate limit (if any) for a command, returning whether the command exceeds it.
+    fn _throttled(&mut self) -> bool {
+        let throttled: bool = self
+            .rate_limit
+            .as_mut()
+            .is_some_and(|bucket| !bucket.try_take());
+        if throttled && !self.throttled {
+            warn!("Rate limiting the commands of {}", self.address);
+        }
+        self.throttled = throttled;
+        throttled
+    }
+
+    /// Why the authenticated user may not execute the given command (with the given arguments), if they may
+    /// not. The user may always authenticate again, select a namespace (whose keys are still checked) and
+    /// tell who they are. Users with restricted permissions may only use the text framing, in which their
+    /// commands are checked.
+    fn _denied(&self, command: &[u8], arguments: Tokens) -> Option<String> {
+        let (_, user) = self.user.and_then(|index| self.auth.user(index))?;
+        match (command, arguments.clone().next()) {
+            (b"AUTH" | b"SELECT" | b"USE", _) | (b"ACL", Some(b"WHOAMI")) => return None,
+            (b"HELLO", None | Some(b"TEXT" | b"STRICT" | b"BULK")) => return None,
+            (b"HELLO", Some(_)) if !user.is_unrestricted() => {
+                return Some("only the text framing is available to this user".to_string())
+            }
+            _ => {}
+        }
+
+        if !user.allows_command(command) {
+            return Some(format!(
+                "this user has no permissions to run the '{}' command",
+                String::from_utf8_lossy(command)
+            ));
+        }
+        if matches!(
+            command,
+            b"FLUSH" | b"FLUSHNS" | b"FLUSHALL" | b"SWAPDB" | b"MONITOR" | b"EXPORT" | b"IMPORT"
+        ) && !user.allows_all_keys()
+        {
+            return Some("this user has no permissions to access every key".to_string());
+        }
+
+        // The keys are checked within the namespace, a key which is not UTF-8 being left to the command to
+        // reject
+        text_command_keys(command, arguments)
+            .into_iter()
+            .filter_map(protocol::as_key)
+            .find(|key| !user.allows_key(self._namespaced(key).as_bytes()))
+            .map(|key| format!("this user has no permissions to access the key '{}'", key))
+    }
+
+    /// The redirection to answer a command on keys whose hash slot another node of the cluster owns (see
+    /// `Cluster::redirect`), if the node belongs to a cluster. The slot is that of the keys of the cache,
+    /// within the selected namespace.
+    fn _cluster_redirect(&self, command: &[u8], arguments: Tokens) -> Option<String> {
+        let cluster: &Cluster = self.cluster.as_deref()?;
+        let keys: Vec<Cow<str>> = text_command_keys(command, arguments)
+            .into_iter()
+            .filter_map(protocol::as_key)
+            .map(|key| self._cache_key(key))
+            .collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_bytes()).collect();
+        cluster.redirect(&keys)
+    }
+
+    /// Execute the given command of a text message, leaving its request id (if any) to the caller.
+    fn _execute_message(
+        &mut self,
+        command: Option<&[u8]>,
+        parts: Tokens,
+    ) -> Result<(), ServerError> {
+        let (started, arguments): (Instant, Tokens) = (Instant::now(), parts.clone());
+        self.answered_error = false;
+        let result: Result<(), ServerError> = match command {
+            Some(b"GET") => self._handle_get_command(parts),
+            Some(b"MGET") => self._handle_mget_command(parts),
+            Some(b"EXISTS") => self._handle_exists_command(parts),
+            Some(b"TYPE") => self._handle_type_command(parts),
+            Some(b"KEYS") => self._handle_keys_command(parts),
+            Some(b"SCAN") => self._handle_scan_command(parts),
+            Some(b"RANDOMKEY") => self._handle_random_key_command(parts),
+            Some(b"SAMPLE") => self._handle_sample_command(parts),
+            Some(b"PUT" | b"SET") => self._handle_put_command(parts),
+            Some(b"MSET") => self._handle_mset_command(parts),
+            Some(b"SETNULL") => self._handle_set_null_command(parts),
+            Some(b"DEL" | b"RM") => self._handle_remove_command(parts),
+            Some(b"GETSET") => self._handle_get_set_command(parts),
+            Some(b"GETDEL") => self._handle_get_del_command(parts),
+            Some(b"UNDELETE") => self._handle_undelete_command(parts),
+            Some(b"RENAME") => self._handle_rename_command(parts, false),
+            Some(b"RENAMENX") => self._handle_rename_command(parts, true),
+            Some(b"EXPIRE") => self._handle_expire_command(parts),
+            Some(b"EXPIREAT") => self._handle_expire_at_command(parts),
+            Some(b"EXPIRETIME") => self._handle_expire_time_command(parts),
+            Some(b"PERSIST") => self._handle_persist_command(parts),
+            Some(b"TOUCH") => self._handle_touch_command(parts),
+            Some(b"TTL") => self._handle_ttl_command(parts),
+            Some(b"INCR") => self._handle_incr_command(parts, false, false),
+            Some(b"DECR") => self._handle_incr_command(parts, true, false),
+            Some(b"INCRBY") => self._handle_incr_command(parts, false, true),
+            Some(b"DECRBY") => self._handle_incr_command(parts, true, true),
+            Some(b"APPEND") => self._handle_append_command(parts),
+            Some(b"STRLEN") => self._handle_strlen_command(parts),
+            Some(b"SETBIT") => self._handle_setbit_command(parts),
+            Some(b"GETBIT") => self._handle_getbit_command(parts),
+            Some(b"BITCOUNT") => self._handle_bitcount_command(parts),
+            Some(b"HSET") => self._handle_hset_command(parts),
+            Some(b"HGET") => self._handle_hget_command(parts),
+            Some(b"HDEL") => self._handle_hdel_command(parts),
+            Some(b"HGETALL") => self._handle_hgetall_command(parts),
+            Some(b"LPUSH") => self._handle_push_command(parts, true),
+            Some(b"RPUSH") => self._handle_push_command(parts, false),
+            Some(b"LPOP") => self._handle_pop_command(parts, true),
+            Some(b"RPOP") => self._handle_pop_command(parts, false),
+            Some(b"BLPOP") => self._handle_blocking_pop_command(parts, true),
+            Some(b"BRPOP") => self._handle_blocking_pop_command(parts, false),
+            Some(b"LRANGE") => self._handle_lrange_command(parts),
+            Some(b"SADD") => self._handle_sadd_command(parts),
+            Some(b"SREM") => self._handle_srem_command(parts),
+            Some(b"SISMEMBER") => self._handle_sismember_command(parts),
+            Some(b"SMEMBERS") => self._handle_smembers_command(parts),
+            Some(b"SINTER") => self._handle_set_combine_command(parts, SetOperation::Intersection),
+            Some(b"SUNION") => self._handle_set_combine_command(parts, SetOperation::Union),
+            Some(b"SDIFF") => self._handle_set_combine_command(parts, SetOperation::Difference),
+            Some(b"ZADD") => self._handle_zadd_command(parts),
+            Some(b"PFADD") => self._handle_pfadd_command(parts),
+            Some(b"PFCOUNT") => self._handle_pfcount_command(parts),
+            Some(b"PFMERGE") => self._handle_pfmerge_command(parts),
+            Some(b"BF.RESERVE") => self._handle_bf_reserve_command(parts),
+            Some(b"BF.ADD") => self._handle_bf_element_command(parts, true),
+            Some(b"BF.EXISTS") => self._handle_bf_element_command(parts, false),
+            Some(b"RATELIMIT") => self._handle_ratelimit_command(parts),
+            Some(b"ZINCRBY") => self._handle_zincrby_command(parts),
+            Some(b"ZREM") => self._handle_zrem_command(parts),
+            Some(b"ZSCORE") => self._handle_zscore_command(parts),
+            Some(b"ZRANGE") => self._handle_zrange_command(parts, false),
+            Some(b"ZRANGEBYSCORE") => self._handle_zrange_command(parts, true),
+            Some(b"XADD") => self._handle_xadd_command(parts),
+            Some(b"XRANGE") => self._handle_xrange_command(parts),
+            Some(b"XREAD") => self._handle_xread_command(parts),
+            Some(b"XLEN") => self._handle_xlen_command(parts),
+            Some(b"HISTORY") => self._handle_history_command(parts),
+            Some(b"GETVERSION") => self._handle_get_version_command(parts),
+            Some(b"HELLO") => self._handle_hello_command(parts),
+            Some(b"SELECT") => self._handle_select_command(parts, false),
+            Some(b"USE") => self._handle_select_command(parts, true),
+            Some(b"FLUSH") => self._handle_flush_command(parts),
+            Some(b"FLUSHNS") => self._handle_flush_namespace_command(parts),
+            Some(b"FLUSHALL") => self._handle_flushall_command(parts),
+            Some(b"SWAPDB") => self._handle_swapdb_command(parts),
+            Some(b"DBSIZE") => self._handle_dbsize_command(parts),
+            Some(b"SAVE") => self._handle_save_command(parts, false),
+            Some(b"BGSAVE") => self._handle_save_command(parts, true),
+            Some(b"LASTSAVE") => self._handle_lastsave_command(parts),
+            Some(b"SHUTDOWN") => self._handle_shutdown_command(parts),
+            Some(b"BGREWRITEAOF") => self._handle_bgrewriteaof_command(parts),
+            Some(b"EXPORT") => self._handle_export_command(parts),
+            Some(b"IMPORT") => self._handle_import_command(parts),
+            Some(b"STATS") => self._handle_stats_command(parts),
+            Some(b"INFO") => self._handle_info_command(parts),
+            Some(b"SLOWLOG") => self._handle_slowlog_command(parts),
+            Some(b"MEMORY") => self._handle_memory_command(parts),
+            Some(b"OBJECT") => self._handle_object_command(parts),
+            Some(b"CLIENT") => self._handle_client_command(parts),
+            Some(b"TTLSTATS") => self._handle_ttl_stats_command(parts),
+            Some(b"ROLE") => self._handle_role_command(parts),
+            Some(b"CONFIG") => self._handle_config_command(parts),
+            Some(b"AUTH") => self._handle_auth_command(parts),
+            Some(b"ACL") => self._handle_acl_command(parts),
+            Some(b"SUBSCRIBE") => self._handle_subscribe_command(parts),
+            Some(b"UNSUBSCRIBE") => self._handle_unsubscribe_command(parts),
+            Some(b"PUBLISH") => self._handle_publish_command(parts),
+            Some(b"MONITOR") => self._handle_monitor_command(parts),
+            Some(b"WATCH") => self._handle_watch_command(parts),
+            Some(b"UNWATCH") => self._handle_unwatch_command(parts),
+            Some(b"EVAL") => self._handle_eval_command(parts),
+            Some(unknown) => self._handle_unknown_command(unknown),
+            None if self.request_id.is_some() => {
+                self._write_error(ErrorCode::UnknownCommand, "Missing command")
+            }
+            None => Ok(()),
+        };
+        if let Some(name) = command {
+            let elapsed: Duration = started.elapsed();
+            let user: Option<&str> = self
+                .user
+                .and_then(|index| self.auth.user(index))
+                .map(|(name, _)| name.as_str());
+            let failed: bool = result.is_err() || self.answered_error;
+            self.cache.record_command(
+                CommandProtocol::Text,
+                elapsed,
+                &self.address,
+                user,
+                failed,
+                || iter::once(name).chain(arguments).collect(),
+            );
+        }
+
+        match result {
+            Err(ServerError::Cache(e)) => {
+                log!(
+                    e.log_level(),
+                    "Failed to execute the command{} from {}: {}",
+                    self._request_id_suffix(),
+                    self.address,
+                    e
+                );
+                let code: ErrorCode = match e {
+                    CacheError::ReadOnly => ErrorCode::ReadOnly,
+                    CacheError::QuotaExceeded(_) => ErrorCode::QuotaExceeded,
+                    CacheError::OutOfMemory => ErrorCode::OutOfMemory,
+                    CacheError::KeyTooLarge(_) => ErrorCode::KeyTooLarge,
+                    CacheError::ValueTooLarge(_) => ErrorCode::ValueTooLarge,
+                    CacheError::WrongType => ErrorCode::WrongType,
+                    CacheError::NotANumber => ErrorCode::NotANumber,
+                    CacheError::Script(_) => ErrorCode::ScriptError,
+                    _ => ErrorCode::Internal,
+                };
+                self._write_error(code, &e.to_string())
+            }
+            other => other,
+        }
+    }
+
+    /// Handle a GET command (e.g. `GET my_key`, or `GET my_key LOCK 10` with stampede protection).
+    fn _handle_get_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let address: &str = self.address.as_str();
+        let maybe_key: Option<&[u8]> = parts.next();
+
+        if maybe_key.is_none() {
+            warn!("GET command sent from {} without a key", address);
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let Some(key) = self._parse_key(maybe_key.unwrap()) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        let option: Option<&[u8]> = parts.next();
+        let maybe_lock_ttl: Option<u64> = match option {
+            Some(b"LOCK") => parts.next().and_then(parse_number),
+            _ => None,
+        };
+        if self.strict {
+            if option == Some(b"LOCK") && maybe_lock_ttl.is_none() {
+                return self._write_strict_error(ErrorCode::InvalidTtl);
+            }
+            if (option.is_some() && option != Some(b"LOCK")) || parts.next().is_some() {
+                return self._write_strict_error(ErrorCode::UnexpectedArgument);
+            }
+        }
+
+        let lookup: Lookup = match maybe_lock_ttl {
+            Some(lock_ttl) => {
+                // Waiting for the value may take a while, so answer the preceding commands first
+                self.stream.flush()?;
+                self.cache.get_or_lock(key, lock_ttl)?
+            }
+            None => self.cache.get(key)?,
+        };
+
+        match lookup {
+            Lookup::Fresh(value) => self._write_value(&value),
+            Lookup::Stale(value) => {
+                debug!("Serving the stale value of {} to {}", key, self.address);
+                self._write_stale_value(&value)
+            }
+            Lookup::Negative => self._write_response("NEGATIVE\n"),
+            Lookup::Missing => self._write_response("NULL\n"),
+            Lookup::Recompute => {
+                debug!("{} has to recompute {}", self.address, key);
+                self._write_response("RECOMPUTE\n")
+            }
+        }
+    }
+
+    /// Handle an MGET command (e.g. `MGET key1 key2`), looking up several keys at once. Answers one line per
+    /// key, as GET does: the value (flagged as `STALE` once its soft TTL has elapsed), `NEGATIVE` or `NULL`.
+    fn _handle_mget_command(&mut self, parts: Tokens) -> Result<(), ServerError> {
+        let mut keys: Vec<Cow<str>> = Vec::new();
+        for token in parts {
+            let Some(key) = self._parse_key(token) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        for lookup in self.cache.get_many(&keys)? {
+            match lookup {
+                Lookup::Fresh(value) => self._write_value(&value)?,
+                Lookup::Stale(value) => self._write_stale_value(&value)?,
+                Lookup::Negative => self._write_response("NEGATIVE\n")?,
+                Lookup::Missing | Lookup::Recompute => self._write_response("NULL\n")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle an EXISTS command (e.g. `EXISTS key1 key2`), answering how many of the keys have a value (a
+    /// key given twice being counted twice).
+    fn _handle_exists_command(&mut self, parts: Tokens) -> Result<(), ServerError> {
+        let mut keys: Vec<Cow<str>> = Vec::new();
+        for token in parts {
+            let Some(key) = self._parse_key(token) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        let existing: usize = self.cache.exists(&keys)?;
+        self._write_line(format_args!("{}", existing))
+    }
+
+    /// Handle a TYPE command (e.g. `TYPE my_key`), answering the type of the value of a key (`string`,
+    /// `hash`, `list`, `set`, `zset`, `hyperloglog` or `stream`), or `none` if it has no value.
+    fn _handle_type_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let name: &str = self
+            .cache
+            .value_type(key)?
+            .map_or("none", |kind| kind.name());
+        self._write_line(format_args!("{}", name))
+    }
+
+    /// Handle a KEYS command (e.g. `KEYS session:*`), answering the keys of the selected namespace which
+    /// match a glob pattern (`*`, `?` and classes such as `[abc]`), separated by spaces, or `NULL` if none
+    /// does. Only the keys the user may access are listed. Every key of the cache is visited, so it is meant
+    /// for inspecting the cache rather than for serving requests.
+    fn _handle_keys_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(pattern) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing pattern");
+        };
+        let Some(pattern) = self._parse_key(pattern) else {
+            return self._write_error(ErrorCode::InvalidKey, "Pattern must be valid UTF-8");
+        };
+        let pattern: &str = &pattern;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let mut keys: Vec<String> = self.cache.keys(pattern)?;
+        self._retain_accessible(&mut keys);
+        if keys.is_empty() {
+            return self._write_response("NULL\n");
+        }
+
+        let prefix: String = self._namespace_prefix();
+        let keys: Vec<&str> = keys
+            .iter()
+            .map(|key| key.strip_prefix(prefix.as_str()).unwrap_or(key))
+            .collect();
+        self._write_line(format_args!("{}", keys.join(" ")))
+    }
+
+    /// Handle a RANDOMKEY command, answering a key of the selected namespace picked at random (among the keys
+    /// the user may access), or `NULL` if there is none.
+    fn _handle_random_key_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        self._write_sample(1)
+    }
+
+    /// Handle a SAMPLE command (e.g. `SAMPLE 100`), answering that many distinct keys of the selected
+    /// namespace picked at random (among the keys the user may access), separated by spaces, or `NULL` if
+    /// there is none. Every key of the cache is visited, but only the keys picked are kept.
+    fn _handle_sample_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(count) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing count");
+        };
+        let Some(count) = parse_number(count).filter(|count| *count > 0) else {
+            return self._write_error(ErrorCode::InvalidParameter, "Invalid count");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        self._write_sample(count as usize)
+    }
+
+    fn _write_sample(&mut self, count: usize) -> Result<(), ServerError> {
+        let pattern: Cow<str> = self._cache_key("*");
+        let keys: Vec<String> = self
+            .cache
+            .sample_keys(&pattern, count, &|key| self._is_accessible(key))?;
+        if keys.is_empty() {
+            return self._write_response("NULL\n");
+        }
+
+        let prefix: String = self._namespace_prefix();
+        let keys: Vec<&str> = keys
+            .iter()
+            .map(|key| key.strip_prefix(prefix.as_str()).unwrap_or(key))
+            .collect();
+        self._write_line(format_args!("{}", keys.join(" ")))
+    }
+
+    /// Handle a SCAN command (e.g. `SCAN 0 MATCH session:* COUNT 100`), examining the next batch of keys of
+    /// the selected namespace (`COUNT` of them, 10 by default). Answers the cursor of the next batch (0 once
+    /// every key is examined), followed by the keys of the batch matching the pattern (if any), separated by
+    /// spaces.
+    fn _handle_scan_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(cursor) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing cursor");
+        };
+        let Some(cursor) = parse_number(cursor) else {
+            return self._write_error(ErrorCode::InvalidParameter, "Invalid cursor");
+        };
+
+        let mut maybe_pattern: Option<&[u8]> = None;
+        let mut count: usize = DEFAULT_SCAN_COUNT;
+        while let Some(option) = parts.next() {
+            match option {
+                b"MATCH" => match parts.next() {
+                    Some(pattern) => maybe_pattern = Some(pattern),
+                    None => return self._write_error(ErrorCode::MissingValue, "Missing pattern"),
+                },
+                b"COUNT" => match parts.next().and_then(parse_number) {
+                    Some(number) if number > 0 => count = number as usize,
+                    _ => return self._write_error(ErrorCode::InvalidParameter, "Invalid count"),
+                },
+                _ if self.strict => {
+                    return self._write_strict_error(ErrorCode::UnexpectedArgument);
+                }
+                _ => {}
+            }
+        }
+        let Some(pattern) = self._parse_key(maybe_pattern.unwrap_or(b"*")) else {
+            return self._write_error(ErrorCode::InvalidKey, "Pattern must be valid UTF-8");
+        };
+        let pattern: &str = &pattern;
+
+        let (next, mut keys): (u64, Vec<String>) = self.cache.scan(cursor, pattern, count)?;
+        self._retain_accessible(&mut keys);
+
+        let prefix: String = self._namespace_prefix();
+        let mut response: String = next.to_string();
+        for key in &keys {
+            response.push(' ');
+            response.push_str(key.strip_prefix(prefix.as_str()).unwrap_or(key));
+        }
+        response.push('\n');
+        self._write_response(&response)
+    }
+
+    /// Handle an MSET command (e.g. `MSET key1 value1 key2 value2`), putting several values (without a TTL)
+    /// as a single write. Answers `OK`.
+    fn _handle_mset_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let mut entries: Vec<(Cow<str>, Bytes)> = Vec::new();
+        while let Some(key) = parts.next() {
+            let Some(key) = self._parse_key(key) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            let Some(value) = parts.next() else {
+                return self._write_error(ErrorCode::MissingValue, "Missing value");
+            };
+            entries.push((key, Bytes::from(value)));
+        }
+        if entries.is_empty() {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let entries: Vec<(&str, Bytes)> = entries
+            .iter()
+            .map(|(key, value)| (key.as_ref(), value.clone()))
+            .collect();
+        self.cache.put_many(entries)?;
+        self._write_response("OK\n")
+    }
+
+    /// Handle a PUT command (e.g. `PUT my_key my_value 3600`, or `PUT my_key my_value 3600 SOFT 60` to
+    /// flag the value as stale after 60 seconds while still serving it until it expires). `JITTER 10`
+    /// spreads the TTLs by up to 10% instead of the configured jitter. As in Redis, the TTL can also be
+    /// given as `EX 3600` (seconds) or `PX 3600000` (milliseconds, rounded up to seconds), and `NX` (or
+    /// `XX`) only puts the value if the key has none (or has one), answering `NULL` otherwise.
+    fn _handle_put_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let address: &str = self.address.as_str();
+        let maybe_key: Option<&[u8]> = parts.next();
+        let maybe_value: Option<&[u8]> = parts.next();
+
+        let mut maybe_ttl: Option<u64> = None;
+        let mut maybe_soft_ttl: Option<u64> = None;
+        let mut maybe_jitter: Option<u64> = None;
+        let mut maybe_condition: Option<PutCondition> = None;
+        let mut problem: Option<ErrorCode> = None; // The first invalid argument, reported in strict mode
+        let mut option: Option<&[u8]> = parts.next();
+        if let Some(ttl) = option.and_then(parse_number) {
+            maybe_ttl = Some(ttl);
+            option = parts.next();
+        } else if option.is_some_and(|name| !PUT_OPTIONS.contains(&name)) {
+            problem = Some(ErrorCode::InvalidTtl);
+        }
+        while let Some(name) = option {
+            match name {
+                b"EX" | b"PX" => {
+                    let ttl: Option<u64> = parts.next().and_then(parse_number);
+                    let ttl: Option<u64> = match name {
+                        b"PX" => ttl.map(|millis| millis.div_ceil(1000)),
+                        _ => ttl,
+                    };
+                    if ttl.is_none() || maybe_ttl.is_some() {
+                        problem.get_or_insert(ErrorCode::InvalidTtl);
+                    }
+                    maybe_ttl = ttl.or(maybe_ttl);
+                }
+                b"NX" | b"XX" => {
+                    let condition: PutCondition = match name {
+                        b"NX" => PutCondition::IfAbsent,
+                        _ => PutCondition::IfPresent,
+                    };
+                    if maybe_condition.is_some_and(|other| other != condition) {
+                        problem.get_or_insert(ErrorCode::UnexpectedArgument);
+                    }
+                    maybe_condition = Some(condition);
+                }
+                b"SOFT" => {
+                    maybe_soft_ttl = parts.next().and_then(parse_number);
+                    if maybe_soft_ttl.is_none() {
+                        problem.get_or_insert(ErrorCode::InvalidTtl);
+                    }
+                }
+                b"JITTER" => {
+                    maybe_jitter = parts.next().and_then(parse_number);
+                    if maybe_jitter.is_none_or(|jitter| jitter > MAX_TTL_JITTER as u64) {
+                        problem.get_or_insert(ErrorCode::InvalidJitter);
+                    }
+                }
+                _ => {
+                    problem.get_or_insert(ErrorCode::UnexpectedArgument);
+                }
+            }
+            option = parts.next();
+        }
+
+        if maybe_key.is_none() || maybe_value.is_none() {
+            warn!("PUT command sent from {} without a key or value", address);
+            let code: ErrorCode = match maybe_key {
+                None => ErrorCode::MissingKey,
+                Some(_) => ErrorCode::MissingValue,
+            };
+            return self._write_error(code, "Missing key & value");
+        }
+
+        let Some(key) = self._parse_key(maybe_key.unwrap()) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if let Some(code) = problem.filter(|_| self.strict) {
+            return self._write_strict_error(code);
+        }
+
+        let value: Bytes = Bytes::from(maybe_value.unwrap());
+        let jitter: u32 = match maybe_jitter {
+            Some(jitter) => jitter.min(MAX_TTL_JITTER as u64) as u32,
+            None => self.cache.ttl_jitter(),
+        };
+        let written: bool = match maybe_condition {
+            Some(condition) => self.cache.put_if_with_jitter(
+                key,
+                value,
+                maybe_ttl,
+                maybe_soft_ttl,
+                jitter,
+                condition,
+            )?,
+            None => {
+                self.cache
+                    .put_with_jitter(key, value, maybe_ttl, maybe_soft_ttl, jitter)?;
+                true
+            }
+        };
+
+        match written {
+            true => self._write_response("OK\n"),
+            false => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle a SETNULL command (e.g. `SETNULL my_key 60`), caching the key as known to be absent.
+    fn _handle_set_null_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let address: &str = self.address.as_str();
+        let maybe_key: Option<&[u8]> = parts.next();
+        let maybe_ttl_token: Option<&[u8]> = parts.next();
+        let maybe_ttl: Option<u64> = maybe_ttl_token.and_then(parse_number);
+
+        if maybe_key.is_none() {
+            warn!("SETNULL command sent from {} without a key", address);
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let Some(key) = self._parse_key(maybe_key.unwrap()) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict {
+            if maybe_ttl_token.is_some() && maybe_ttl.is_none() {
+                return self._write_strict_error(ErrorCode::InvalidTtl);
+            }
+            if parts.next().is_some() {
+                return self._write_strict_error(ErrorCode::UnexpectedArgument);
+            }
+        }
+
+        self.cache.put_negative(key, maybe_ttl)?;
+
+        self._write_response("OK\n")
+    }
+
+    fn _handle_remove_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let address: &str = self.address.as_str();
+        let maybe_key: Option<&[u8]> = parts.next();
+
+        if maybe_key.is_none() {
+            warn!("DEL command sent from {} without a key", address);
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let Some(key) = self._parse_key(maybe_key.unwrap()) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match self.cache.remove(key)? {
+            Some(value) => self._write_value(&value),
+            None => self._write_response("<NULL>\n"),
+        }
+    }
+
+    /// Handle a GETSET command (e.g. `GETSET my_key XYZ`), putting a value without a TTL and answering the
+    /// value it replaces, or `NULL` if the key had none.
+    fn _handle_get_set_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(value) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing value");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match self.cache.get_set(key, Bytes::from(value))? {
+            Some(previous) => self._write_value(&previous),
+            None => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle a GETDEL command (e.g. `GETDEL my_key`), removing a key and answering its value, or `NULL` if
+    /// it had none.
+    fn _handle_get_del_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match self.cache.get_del(key)? {
+            Some(value) => self._write_value(&value),
+            None => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle an UNDELETE command (e.g. `UNDELETE my_key`), restoring the value of a key deleted within the
+    /// tombstone window. Answers `OK`, or `NULL` if there is no value to restore.
+    fn _handle_undelete_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if self.cache.tombstone_window().is_none() {
+            let message: &str =
+                "Soft delete is disabled (see MYRUSTCACHE_TOMBSTONE_WINDOW_SECONDS)";
+            return self._write_error(ErrorCode::SoftDeleteDisabled, message);
+        }
+
+        match self.cache.undelete(key)? {
+            true => self._write_response("OK\n"),
+            false => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle a RENAME command (e.g. `RENAME old_key new_key`), moving the value of a key to another one
+    /// along with its TTL. Answers `OK`, or `NULL` if the key has no value. RENAMENX only renames the key
+    /// if the new one has no value, answering `1` if it did, `0` if not, or `NULL` if the key has no value.
+    fn _handle_rename_command(
+        &mut self,
+        mut parts: Tokens,
+        if_absent: bool,
+    ) -> Result<(), ServerError> {
+        let (Some(from), Some(to)) = (parts.next(), parts.next()) else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let (Some(from), Some(to)) = (self._parse_key(from), self._parse_key(to)) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match (self.cache.rename(&from, &to, if_absent)?, if_absent) {
+            (None, _) => self._write_response("NULL\n"),
+            (Some(_), false) => self._write_response("OK\n"),
+            (Some(renamed), true) => self._write_line(format_args!("{}", u8::from(renamed))),
+        }
+    }
+
+    /// Handle an EXPIRE command (e.g. `EXPIRE my_key 60`), changing the TTL of the value of a key. A TTL of
+    /// zero deletes the key. Answers `OK`, or `NULL` if the key has no value.
+    fn _handle_expire_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let ttl: u64 = match parts.next().map(parse_number) {
+            Some(Some(ttl)) => ttl,
+            Some(None) => return self._write_error(ErrorCode::InvalidTtl, "Invalid TTL"),
+            None => return self._write_error(ErrorCode::InvalidTtl, "Missing TTL"),
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let updated: bool = match ttl {
+            0 => self.cache.remove(key)?.is_some(),
+            _ => self.cache.expire(key, Some(ttl))?,
+        };
+        match updated {
+            true => self._write_response("OK\n"),
+            false => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle an EXPIREAT command (e.g. `EXPIREAT my_key 1893456000`), expiring the value of a key at a
+    /// Unix time in seconds, or deleting it if that time has passed. Answers `OK`, or `NULL` if the key has
+    /// no value.
+    fn _handle_expire_at_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let at: u64 = match parts.next().map(parse_number) {
+            Some(Some(at)) => at,
+            Some(None) => return self._write_error(ErrorCode::InvalidTtl, "Invalid time"),
+            None => return self._write_error(ErrorCode::InvalidTtl, "Missing time"),
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match self.cache.expire_at(key, at.saturating_mul(1000))? {
+            true => self._write_response("OK\n"),
+            false => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle an EXPIRETIME command (e.g. `EXPIRETIME my_key`), answering the Unix time in seconds at which
+    /// the value of a key expires, `-1` if it has no TTL, or `-2` if the key has no value.
+    fn _handle_expire_time_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let at: i64 = match self.cache.expire_time(key)? {
+            Some(Some(at)) => (at / 1000) as i64,
+            Some(None) => -1,
+            None => -2,
+        };
+        self._write_line(format_args!("{}", at))
+    }
+
+    /// Handle a PERSIST command (e.g. `PERSIST my_key`), removing the TTL of the value of a key. Answers
+    /// `OK`, or `NULL` if the key has no value.
+    fn _handle_persist_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match self.cache.expire(key, None)? {
+            true => self._write_response("OK\n"),
+            false => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle a TOUCH command (e.g. `TOUCH key1 key2`), restarting the TTL of the values of the keys for
+    /// the TTL they were given, and answering how many of the keys have a value.
+    fn _handle_touch_command(&mut self, parts: Tokens) -> Result<(), ServerError> {
+        let mut keys: Vec<Cow<str>> = Vec::new();
+        for token in parts {
+            let Some(key) = self._parse_key(token) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        let touched: usize = self.cache.touch(&keys)?;
+        self._write_line(format_args!("{}", touched))
+    }
+
+    /// Handle a TTL command (e.g. `TTL my_key`), answering the seconds the value of a key has left to live,
+    /// `-1` if it has no TTL, or `-2` if the key has no value.
+    fn _handle_ttl_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let ttl: i64 = match self.cache.remaining_ttl(key)? {
+            Some(Some(remaining)) => remaining.div_ceil(1000) as i64,
+            Some(None) => -1,
+            None => -2,
+        };
+        self._write_line(format_args!("{}", ttl))
+    }
+
+    /// Handle an INCR, DECR, INCRBY or DECRBY command (e.g. `INCRBY my_key 5`), adding to (or subtracting
+    /// from) the integer held by a key, a missing key counting as 0. Answers the new value.
+    ///
+    /// # Arguments
+    /// * `negate` - Whether the increment is subtracted (DECR and DECRBY).
+    /// * `by` - Whether the increment follows the key (INCRBY and DECRBY), rather than being 1.
+    fn _handle_incr_command(
+        &mut self,
+        mut parts: Tokens,
+        negate: bool,
+        by: bool,
+    ) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let increment: i64 = match by {
+            true => match parts.next().map(parse_integer) {
+                Some(Some(increment)) => increment,
+                Some(None) => {
+                    return self._write_error(ErrorCode::NotAnInteger, "Invalid increment")
+                }
+                None => return self._write_error(ErrorCode::MissingValue, "Missing increment"),
+            },
+            false => 1,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let delta: Option<i64> = match negate {
+            true => increment.checked_neg(),
+            false => Some(increment),
+        };
+        match delta.map(|delta| self.cache.incr_by(key, delta)) {
+            Some(Ok(counter)) => self._write_line(format_args!("{}", counter)),
+            None | Some(Err(CacheError::NotAnInteger)) => self._write_error(
+                ErrorCode::NotAnInteger,
+                "Value is not an integer or out of range",
+            ),
+            Some(Err(e)) => Err(e.into()),
+        }
+    }
+
+    /// Handle an APPEND command (e.g. `APPEND my_key suffix`), appending to the value of a key, or giving
+    /// it the suffix as its value if it has none. Answers the length of the new value.
+    fn _handle_append_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(suffix) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing value");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let length: usize = self.cache.append(key, suffix)?;
+        self._write_line(format_args!("{}", length))
+    }
+
+    /// Handle a STRLEN command (e.g. `STRLEN my_key`), answering the length of the value of a key in
+    /// bytes, or `0` if the key has no value.
+    fn _handle_strlen_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let length: usize = match self.cache.get(key)? {
+            Lookup::Fresh(value) | Lookup::Stale(value) => value.len(),
+            _ => 0,
+        };
+        self._write_line(format_args!("{}", length))
+    }
+
+    /// Handle an HSET command (e.g. `HSET user:1 name Alice age 30`), setting fields of the hash of a key,
+    /// which is given a new hash if it has no value. Answers the number of fields added (rather than changed).
+    fn _handle_hset_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let mut fields: Vec<(&[u8], Bytes)> = Vec::new();
+        while let Some(field) = parts.next() {
+            let Some(value) = parts.next() else {
+                return self._write_error(ErrorCode::MissingValue, "Missing value of a field");
+            };
+            fields.push((field, Bytes::from(value)));
+        }
+        if fields.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing field");
+        }
+
+        let added: usize = self.cache.hset(key, &fields)?;
+        self._write_line(format_args!("{}", added))
+    }
+
+    /// Handle an HGET command (e.g. `HGET user:1 name`), answering the value of a field of the hash of a
+    /// key, or `NULL` if the key has no value or the field is not set.
+    fn _handle_hget_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(field) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing field");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match self.cache.hget(key, field)? {
+            Some(value) => self._write_value(&value),
+            None => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle an HDEL command (e.g. `HDEL user:1 age`), removing fields of the hash of a key, the key being
+    /// removed along with the last field of its hash. Answers the number of fields removed.
+    fn _handle_hdel_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let fields: Vec<&[u8]> = parts.collect();
+        if fields.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing field");
+        }
+
+        let removed: usize = self.cache.hdel(key, &fields)?;
+        self._write_line(format_args!("{}", removed))
+    }
+
+    /// Handle an HGETALL command (e.g. `HGETALL user:1`), answering the number of fields of the hash of a
+    /// key (`0` if the key has no value) on a line, followed by every field and its value, each on a line
+    /// of its own, in the order the fields were first set.
+    fn _handle_hgetall_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let fields: Vec<(Bytes, Bytes)> = self.cache.hgetall(key)?;
+        self._write_line(format_args!("{}", fields.len()))?;
+        for (field, value) in &fields {
+            self._write_value(field)?;
+            self._write_value(value)?;
+        }
+        Ok(())
+    }
+
+    /// Handle an LPUSH or RPUSH command (e.g. `RPUSH jobs job1 job2`), pushing values at the head or the
+    /// tail of the list of a key, which is given a new list if it has no value. Answers the length of the
+    /// list.
+    ///
+    /// # Arguments
+    /// * `left` - Whether the values are pushed at the head (LPUSH), one after the other.
+    fn _handle_push_command(&mut self, mut parts: Tokens, left: bool) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let items: Vec<Bytes> = parts.map(Bytes::from).collect();
+        if items.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing value");
+        }
+
+        let length: usize = self.cache.list_push(key, &items, left)?;
+        self._write_line(format_args!("{}", length))
+    }
+
+    /// Handle an LPOP or RPOP command (e.g. `LPOP jobs`, or `LPOP jobs 10`), popping values from the head
+    /// or the tail of the list of a key, which is removed along with its last value. Answers the value
+    /// popped, or `NULL` if the key has no value; with a count, answers the number of values popped on a
+    /// line, followed by every value on a line of its own.
+    ///
+    /// # Arguments
+    /// * `left` - Whether the values are popped from the head (LPOP).
+    fn _handle_pop_command(&mut self, mut parts: Tokens, left: bool) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let count: Option<usize> = match parts.next().map(parse_integer) {
+            Some(Some(count)) if count >= 0 => Some(count as usize),
+            Some(_) => return self._write_error(ErrorCode::NotAnInteger, "Invalid count"),
+            None => None,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let popped: Vec<Bytes> = self.cache.list_pop(key, count.unwrap_or(1), left)?;
+        if count.is_some() {
+            return self._write_values(&popped);
+        }
+        match popped.first() {
+            Some(value) => self._write_value(value),
+            None => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle a BLPOP or BRPOP command (e.g. `BLPOP jobs:urgent jobs 5`), popping a value from the head or
+    /// the tail of the first non-empty list of some keys or, if all of them are empty, waiting for a value
+    /// to be pushed to any of them for at most a number of seconds (which may have decimals, `0` waiting
+    /// forever). The clients waiting are handed the values pushed in the order they started waiting.
+    /// Answers the key on a line followed by the value popped, or `NULL` once the time is up. Inside a
+    /// transaction, the command answers right away.
+    ///
+    /// # Arguments
+    /// * `left` - Whether the value is popped from the head (BLPOP).
+    fn _handle_blocking_pop_command(
+        &mut self,
+        parts: Tokens,
+        left: bool,
+    ) -> Result<(), ServerError> {
+        let mut names: Vec<&[u8]> = parts.collect();
+        let Some(timeout) = names.pop() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        if names.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing timeout");
+        }
+        let Some(timeout) = parse_timeout(timeout) else {
+            return self._write_error(ErrorCode::InvalidParameter, "Invalid timeout");
+        };
+        let mut keys: Vec<Cow<str>> = Vec::with_capacity(names.len());
+        for name in &names {
+            let Some(key) = self._parse_key(name) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+
+        let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+        let popped: Option<(usize, Bytes)> = match self.blocking {
+            true => {
+                let outcome: BlockingPop = {
+                    let _guard = cache.command_lock()?;
+                    cache.list_pop_or_wait(&keys, left)?
+                };
+                match outcome {
+                    BlockingPop::Popped(index, value) => Some((index, value)),
+                    BlockingPop::Waiting(waiter) => {
+                        self.stream.flush()?; // The responses to the previous commands are not held back
+                        let connection: Option<&ClientStream> = self.connection.as_ref();
+                        wait_popped(&cache, &waiter, &keys, left, timeout, connection)?
+                    }
+                }
+            }
+            false => match cache.list_pop_or_wait(&keys, left)? {
+                BlockingPop::Popped(index, value) => Some((index, value)),
+                BlockingPop::Waiting(waiter) => waiter.cancel()?,
+            },
+        };
+        match popped {
+            Some((index, value)) => {
+                self._write_parts(&[names[index], b"\n"])?;
+                self._write_value(&value)
+            }
+            None => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle an LRANGE command (e.g. `LRANGE jobs 0 -1`), answering the values of the list of a key from
+    /// a start index to a stop one, both included, where negative indexes count from the tail (`-1` being
+    /// the last value): the number of values on a line (`0` if the key has no value), followed by every
+    /// value on a line of its own.
+    fn _handle_lrange_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let (start, stop): (i64, i64) = match (parts.next(), parts.next()) {
+            (Some(start), Some(stop)) => match (parse_integer(start), parse_integer(stop)) {
+                (Some(start), Some(stop)) => (start, stop),
+                _ => return self._write_error(ErrorCode::NotAnInteger, "Invalid index"),
+            },
+            _ => return self._write_error(ErrorCode::MissingValue, "Missing index"),
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let items: Vec<Bytes> = self.cache.list_range(key, start, stop)?;
+        self._write_values(&items)
+    }
+
+    /// Handle an SADD command (e.g. `SADD tags red blue`), adding members to the set of a key, which is
+    /// given a new set if it has no value. Answers the number of members added (rather than already in it).
+    fn _handle_sadd_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let members: Vec<Bytes> = parts.map(Bytes::from).collect();
+        if members.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing member");
+        }
+
+        let added: usize = self.cache.sadd(key, &members)?;
+        self._write_line(format_args!("{}", added))
+    }
+
+    /// Handle an SREM command (e.g. `SREM tags red`), removing members from the set of a key, the key being
+    /// removed along with the last member of its set. Answers the number of members removed.
+    fn _handle_srem_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let members: Vec<&[u8]> = parts.collect();
+        if members.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing member");
+        }
+
+        let removed: usize = self.cache.srem(key, &members)?;
+        self._write_line(format_args!("{}", removed))
+    }
+
+    /// Handle an SISMEMBER command (e.g. `SISMEMBER tags red`), answering `1` if a member is in the set of
+    /// a key, or `0` otherwise.
+    fn _handle_sismember_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(member) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing member");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let found: bool = self.cache.sismember(key, member)?;
+        self._write_response(if found { "1\n" } else { "0\n" })
+    }
+
+    /// Handle an SMEMBERS command (e.g. `SMEMBERS tags`), answering the number of members of the set of a
+    /// key on a line (`0` if the key has no value), followed by every member on a line of its own.
+    fn _handle_smembers_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let members: Vec<Bytes> = self.cache.smembers(key)?;
+        self._write_values(&members)
+    }
+
+    /// Handle an SINTER, SUNION or SDIFF command (e.g. `SINTER tags:a tags:b`), answering the members of
+    /// the intersection, the union or the difference of the sets of the keys (the members of the first one
+    /// which are in none of the others) like SMEMBERS does.
+    fn _handle_set_combine_command(
+        &mut self,
+        parts: Tokens,
+        operation: SetOperation,
+    ) -> Result<(), ServerError> {
+        let mut keys: Vec<Cow<str>> = Vec::new();
+        for token in parts {
+            let Some(key) = self._parse_key(token) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        let members: Vec<Bytes> = self.cache.set_combine(&keys, operation)?;
+        self._write_values(&members)
+    }
+
+    /// Handle a ZADD command (e.g. `ZADD scores 10 alice 7.5 bob`), adding members with their scores to the
+    /// sorted set of a key (or changing their scores), which is given a new sorted set if it has no value.
+    /// Answers the number of members added (rather than already in it).
+    fn _handle_zadd_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let mut members: Vec<(f64, &[u8])> = Vec::new();
+        while let Some(score) = parts.next() {
+            let Some(score) = values::parse_score(score) else {
+                return self._write_error(ErrorCode::NotANumber, "Invalid score");
+            };
+            let Some(member) = parts.next() else {
+                return self._write_error(ErrorCode::MissingValue, "Missing member of a score");
+            };
+            members.push((score, member));
+        }
+        if members.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing member");
+        }
+
+        let added: usize = self.cache.zadd(key, &members)?;
+        self._write_line(format_args!("{}", added))
+    }
+
+    /// Handle a ZINCRBY command (e.g. `ZINCRBY scores 5 alice`), adding to the score of a member of the
+    /// sorted set of a key, which is added with the increment as its score if it is not in the sorted set.
+    /// Answers the new score.
+    fn _handle_zincrby_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let (increment, member): (f64, &[u8]) = match (parts.next(), parts.next()) {
+            (Some(increment), Some(member)) => match values::parse_score(increment) {
+                Some(increment) => (increment, member),
+                None => return self._write_error(ErrorCode::NotANumber, "Invalid increment"),
+            },
+            _ => return self._write_error(ErrorCode::MissingValue, "Missing increment or member"),
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let score: f64 = self.cache.zincrby(key, member, increment)?;
+        self._write_line(format_args!("{}", score))
+    }
+
+    /// Handle a ZREM command (e.g. `ZREM scores bob`), removing members from the sorted set of a key, the
+    /// key being removed along with the last member of its sorted set. Answers the number of members
+    /// removed.
+    fn _handle_zrem_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let members: Vec<&[u8]> = parts.collect();
+        if members.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing member");
+        }
+
+        let removed: usize = self.cache.zrem(key, &members)?;
+        self._write_line(format_args!("{}", removed))
+    }
+
+    /// Handle a ZSCORE command (e.g. `ZSCORE scores alice`), answering the score of a member of the sorted
+    /// set of a key, or `NULL` if the key has no value or the member is not in its sorted set.
+    fn _handle_zscore_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(member) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing member");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match self.cache.zscore(key, member)? {
+            Some(score) => self._write_line(format_args!("{}", score)),
+            None => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle a ZRANGE command (e.g. `ZRANGE scores 0 9`), answering the members of the sorted set of a key
+    /// from a rank to another, both included, where negative ranks count from the highest score, or a
+    /// ZRANGEBYSCORE command (e.g. `ZRANGEBYSCORE scores (5 +inf`), answering the members whose scores are
+    /// within two bounds (`(` excluding a bound). Answers the number of members on a line (`0` if the key
+    /// has no value), followed by every member on a line of its own, by score, each followed by its score
+    /// on another line with `WITHSCORES`.
+    ///
+    /// # Arguments
+    /// * `by_score` - Whether the range is of scores (ZRANGEBYSCORE), rather than of ranks.
+    fn _handle_zrange_command(
+        &mut self,
+        mut parts: Tokens,
+        by_score: bool,
+    ) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let (Some(start), Some(stop)) = (parts.next(), parts.next()) else {
+            return self._write_error(ErrorCode::MissingValue, "Missing range");
+        };
+        let with_scores: bool = match parts.next() {
+            Some(option) if option.eq_ignore_ascii_case(b"WITHSCORES") => true,
+            Some(_) if self.strict => {
+                return self._write_strict_error(ErrorCode::UnexpectedArgument)
+            }
+            _ => false,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let members: Vec<(Bytes, f64)> = match by_score {
+            true => match (ScoreBound::parse(start), ScoreBound::parse(stop)) {
+                (Some(min), Some(max)) => self.cache.zrange_by_score(key, min, max)?,
+                _ => return self._write_error(ErrorCode::NotANumber, "Invalid score"),
+            },
+            false => match (parse_integer(start), parse_integer(stop)) {
+                (Some(start), Some(stop)) => self.cache.zrange(key, start, stop)?,
+                _ => return self._write_error(ErrorCode::NotAnInteger, "Invalid rank"),
+            },
+        };
+        self._write_line(format_args!("{}", members.len()))?;
+        for (member, score) in &members {
+            self._write_value(member)?;
+            if with_scores {
+                self._write_line(format_args!("{}", score))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a SETBIT command (e.g. `SETBIT active:2025-10-15 42 1`), setting (or clearing) the bit of the
+    /// value of a key at an offset, the value growing with zero bytes as needed. Answers the previous bit.
+    fn _handle_setbit_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let (Some(offset), Some(bit)) = (parts.next(), parts.next()) else {
+            return self._write_error(ErrorCode::MissingValue, "Missing offset or bit");
+        };
+        let Some(offset) = values::parse_bit_offset(offset) else {
+            return self._write_error(ErrorCode::NotAnInteger, "Invalid bit offset");
+        };
+        let bit: bool = match bit {
+            b"0" => false,
+            b"1" => true,
+            _ => return self._write_error(ErrorCode::NotAnInteger, "Bit must be 0 or 1"),
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let previous: bool = self.cache.setbit(key, offset, bit)?;
+        self._write_response(if previous { "1\n" } else { "0\n" })
+    }
+
+    /// Handle a GETBIT command (e.g. `GETBIT active:2025-10-15 42`), answering the bit of the value of a
+    /// key at an offset, `0` past the end of the value or if the key has no value.
+    fn _handle_getbit_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(offset) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing offset");
+        };
+        let Some(offset) = values::parse_bit_offset(offset) else {
+            return self._write_error(ErrorCode::NotAnInteger, "Invalid bit offset");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let bit: bool = self.cache.getbit(key, offset)?;
+        self._write_response(if bit { "1\n" } else { "0\n" })
+    }
+
+    /// Handle a BITCOUNT command (e.g. `BITCOUNT active:2025-10-15`, or `BITCOUNT my_key 0 -1` for a range
+    /// of bytes, negative indexes counting from the end), answering the number of bits set in the value of
+    /// a key.
+    fn _handle_bitcount_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let (start, stop): (i64, i64) = match (parts.next(), parts.next()) {
+            (None, _) => (0, -1),
+            (Some(start), Some(stop)) => match (parse_integer(start), parse_integer(stop)) {
+                (Some(start), Some(stop)) => (start, stop),
+                _ => return self._write_error(ErrorCode::NotAnInteger, "Invalid index"),
+            },
+            (Some(_), None) => return self._write_error(ErrorCode::MissingValue, "Missing index"),
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let count: u64 = self.cache.bitcount(key, start, stop)?;
+        self._write_line(format_args!("{}", count))
+    }
+
+    /// Handle a PFADD command (e.g. `PFADD visitors:2025-10-15 alice bob`), adding elements to the
+    /// HyperLogLog of a key, which is given a new HyperLogLog if it has no value. Answers `1` if its
+    /// estimate may have changed (or the key was given one), or `0` otherwise.
+    fn _handle_pfadd_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let elements: Vec<&[u8]> = parts.collect();
+
+        let changed: bool = self.cache.pfadd(key, &elements)?;
+        self._write_response(if changed { "1\n" } else { "0\n" })
+    }
+
+    /// Handle a PFCOUNT command (e.g. `PFCOUNT visitors:2025-10-15 visitors:2025-10-16`), answering the
+    /// estimated number of distinct elements added to the HyperLogLogs of the keys.
+    fn _handle_pfcount_command(&mut self, parts: Tokens) -> Result<(), ServerError> {
+        let mut keys: Vec<Cow<str>> = Vec::new();
+        for token in parts {
+            let Some(key) = self._parse_key(token) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_ref()).collect();
+        let count: u64 = self.cache.pfcount(&keys)?;
+        self._write_line(format_args!("{}", count))
+    }
+
+    /// Handle a PFMERGE command (e.g. `PFMERGE visitors:week visitors:mon visitors:tue`), merging the
+    /// HyperLogLogs of source keys into the one of a destination key. Answers `OK`.
+    fn _handle_pfmerge_command(&mut self, parts: Tokens) -> Result<(), ServerError> {
+        let mut keys: Vec<Cow<str>> = Vec::new();
+        for token in parts {
+            let Some(key) = self._parse_key(token) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            keys.push(key);
+        }
+        let Some((destination, sources)) = keys.split_first() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+
+        let sources: Vec<&str> = sources.iter().map(|key| key.as_ref()).collect();
+        self.cache.pfmerge(destination, &sources)?;
+        self._write_response("OK\n")
+    }
+
+    /// Handle a BF.RESERVE command (e.g. `BF.RESERVE seen:emails 0.001 1000000`), giving a key an empty
+    /// Bloom filter sized for a number of elements at a rate of false positives. Answers `OK`, or `NULL` if
+    /// the key already has a value.
+    fn _handle_bf_reserve_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let (error_rate, capacity): (f64, u64) = match (parts.next(), parts.next()) {
+            (Some(error_rate), Some(capacity)) => {
+                match (values::parse_score(error_rate), parse_number(capacity)) {
+                    (Some(error_rate), Some(capacity)) => (error_rate, capacity),
+                    _ => {
+                        return self._write_error(
+                            ErrorCode::InvalidParameter,
+                            "Invalid error rate or capacity",
+                        )
+                    }
+                }
+            }
+            _ => {
+                return self._write_error(ErrorCode::MissingValue, "Missing error rate or capacity")
+            }
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        let Some(filter) = BloomFilter::new(capacity, error_rate) else {
+            return self._write_error(
+                ErrorCode::InvalidParameter,
+                "The error rate must be between 0 and 1, and the filter within 64 MiB",
+            );
+        };
+
+        match self.cache.bf_reserve(key, filter)? {
+            true => self._write_response("OK\n"),
+            false => self._write_response("NULL\n"),
+        }
+    }
+
+    /// Handle a BF.ADD (or BF.EXISTS) command (e.g. `BF.ADD seen:emails alice@example.com`), adding an
+    /// element to the Bloom filter of a key, which is given a filter of 100 elements at 1% of false positives
+    /// if it has no value (or checking whether the element may have been added). Answers `1` if the element
+    /// was not in the filter yet (or may have been added), or else `0`.
+    fn _handle_bf_element_command(
+        &mut self,
+        mut parts: Tokens,
+        add: bool,
+    ) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let Some(element) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing element");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let answer: bool = match add {
+            true => self.cache.bf_add(key, element)?,
+            false => self.cache.bf_exists(key, element)?,
+        };
+        self._write_response(if answer { "1\n" } else { "0\n" })
+    }
+
+    /// Handle a RATELIMIT command (e.g. `RATELIMIT ratelimit:api:alice 100 10`, or `RATELIMIT
+    /// ratelimit:api:alice 100 10 5` for an action costing 5 tokens), taking tokens from the token bucket of a
+    /// key, which holds up to 100 tokens and is refilled with 10 tokens per second. Answers `1` if there were
+    /// enough tokens (or else `0`), the tokens left, and the milliseconds until there are enough tokens (`0`
+    /// if there were, `-1` if the action costs more than the bucket holds), e.g. `0 2 300`.
+    fn _handle_ratelimit_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let (capacity, refill_rate): (u64, f64) = match (parts.next(), parts.next()) {
+            (Some(capacity), Some(refill_rate)) => {
+                match (parse_number(capacity), values::parse_score(refill_rate)) {
+                    (Some(capacity), Some(refill_rate))
+                        if capacity > 0 && refill_rate > 0.0 && refill_rate.is_finite() =>
+                    {
+                        (capacity, refill_rate)
+                    }
+                    _ => {
+                        return self._write_error(
+                            ErrorCode::InvalidParameter,
+                            "The most tokens and the refill rate must be above 0",
+                        )
+                    }
+                }
+            }
+            _ => {
+                return self._write_error(
+                    ErrorCode::MissingValue,
+                    "Missing most tokens or refill rate",
+                )
+            }
+        };
+        let cost: u64 = match parts.next().map(parse_number) {
+            Some(Some(cost)) => cost,
+            Some(None) => return self._write_error(ErrorCode::InvalidParameter, "Invalid cost"),
+            None => 1,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let decision: RateDecision = self.cache.rate_limit(key, capacity, refill_rate, cost)?;
+        let retry_after: i64 = decision.retry_after.map_or(-1, |millis| millis as i64);
+        self._write_line(format_args!(
+            "{} {} {}",
+            u8::from(decision.allowed),
+            decision.remaining,
+            retry_after
+        ))
+    }
+
+    /// Handle an XADD command (e.g. `XADD events:orders order-42-paid`, or `XADD events:orders MAXLEN 1000
+    /// order-42-paid` to keep the 1000 newest entries only), appending a value to the stream of a key, which
+    /// is given a new stream if it has no value. Answers the ID of the value, e.g. `1760523448123-0`.
+    fn _handle_xadd_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let mut max_len: Option<usize> = None;
+        let mut value: Option<&[u8]> = parts.next();
+        if value.is_some_and(|option| option.eq_ignore_ascii_case(b"MAXLEN")) {
+            match parts.next().and_then(parse_number) {
+                Some(number) => max_len = Some(number as usize),
+                None => return self._write_error(ErrorCode::InvalidParameter, "Invalid length"),
+            }
+            value = parts.next();
+        }
+        let Some(value) = value else {
+            return self._write_error(ErrorCode::MissingValue, "Missing value");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let id: StreamId = self.cache.xadd(key, Bytes::from(value), max_len)?;
+        self._write_line(format_args!("{}", id))
+    }
+
+    /// Handle an XRANGE command (e.g. `XRANGE events:orders - +`, or `XRANGE events:orders 1760523448123 +
+    /// COUNT 10`), answering the entries of the stream of a key whose IDs are from one to another, both
+    /// included (`-` and `+` being the lowest and highest IDs). Answers the number of entries on a line
+    /// (`0` if the key has no value), followed by the ID of every entry on a line, then its value on
+    /// another.
+    fn _handle_xrange_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let (Some(start), Some(end)) = (parts.next(), parts.next()) else {
+            return self._write_error(ErrorCode::MissingValue, "Missing range");
+        };
+        let (Some(start), Some(end)) = (
+            StreamId::parse_bound(start, true),
+            StreamId::parse_bound(end, false),
+        ) else {
+            return self._write_error(ErrorCode::InvalidParameter, "Invalid stream ID");
+        };
+        let count: Option<usize> = match parts.next() {
+            Some(option) if option.eq_ignore_ascii_case(b"COUNT") => {
+                match parts.next().and_then(parse_number) {
+                    Some(number) => Some(number as usize),
+                    None => return self._write_error(ErrorCode::InvalidParameter, "Invalid count"),
+                }
+            }
+            Some(_) if self.strict => {
+                return self._write_strict_error(ErrorCode::UnexpectedArgument)
+            }
+            _ => None,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let entries: Vec<(StreamId, Bytes)> = self.cache.xrange(key, start, end, count)?;
+        self._write_entries(&entries)
+    }
+
+    /// Handle an XREAD command (e.g. `XREAD COUNT 100 STREAMS events:orders events:payments 1760523448123-0
+    /// 0`), answering the entries of the streams of keys appended after IDs (the last ones a consumer read,
+    /// or `0` for all of them), up to a number of them per stream with `COUNT`. Answers, for every key in
+    /// order, the key and its number of entries on a line, followed by the ID of every entry on a line,
+    /// then its value on another.
+    fn _handle_xread_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let mut count: Option<usize> = None;
+        loop {
+            match parts.next() {
+                Some(option) if option.eq_ignore_ascii_case(b"COUNT") => {
+                    match parts.next().and_then(parse_number) {
+                        Some(number) => count = Some(number as usize),
+                        None => {
+                            return self._write_error(ErrorCode::InvalidParameter, "Invalid count")
+                        }
+                    }
+                }
+                Some(option) if option.eq_ignore_ascii_case(b"STREAMS") => break,
+                Some(_) => {
+                    return self._write_error(ErrorCode::InvalidParameter, "Expected STREAMS")
+                }
+                None => return self._write_error(ErrorCode::MissingKey, "Missing STREAMS"),
+            }
+        }
+        let tokens: Vec<&[u8]> = parts.collect();
+        if tokens.is_empty() {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+        if !tokens.len().is_multiple_of(2) {
+            return self._write_error(ErrorCode::MissingValue, "Missing stream ID");
+        }
+
+        let (names, ids) = tokens.split_at(tokens.len() / 2);
+        let mut keys: Vec<Cow<str>> = Vec::with_capacity(names.len());
+        let mut after: Vec<StreamId> = Vec::with_capacity(ids.len());
+        for (name, id) in names.iter().zip(ids) {
+            let Some(key) = self._parse_key(name) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            let Some(id) = StreamId::parse(id, 0) else {
+                return self._write_error(ErrorCode::InvalidParameter, "Invalid stream ID");
+            };
+            keys.push(key);
+            after.push(id);
+        }
+
+        let streams: Vec<(&str, StreamId)> =
+            keys.iter().map(|key| key.as_ref()).zip(after).collect();
+        let entries: Vec<Vec<(StreamId, Bytes)>> = self.cache.xread(&streams, count)?;
+        for (name, entries) in names.iter().zip(&entries) {
+            self._write_parts(&[name, format!(" {}\n", entries.len()).as_bytes()])?;
+            for (id, value) in entries {
+                self._write_line(format_args!("{}", id))?;
+                self._write_value(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle an XLEN command (e.g. `XLEN events:orders`), answering the number of entries of the stream of
+    /// a key (`0` if the key has no value).
+    fn _handle_xlen_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let length: usize = self.cache.xlen(key)?;
+        self._write_line(format_args!("{}", length))
+    }
+
+    /// Handle a MULTI command, opening a transaction: the next commands are queued (each answered with
+    /// `QUEUED`) until `EXEC` executes them, or `DISCARD` drops them.
+    fn _handle_multi_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if self.transaction.is_some() {
+            return self._write_error(ErrorCode::Transaction, "MULTI calls cannot be nested");
+        }
+        self.transaction = Some(Transaction::new());
+        self._write_response("OK\n")
+    }
+
+    /// Handle an EXEC command, executing the commands queued since `MULTI` in a row, while the commands of
+    /// the other clients wait (see `EmbeddedCache::transaction_lock`). Answers the number of commands on a
+    /// line, followed by their responses (with their request ids, if any) in order. A command failing does
+    /// not stop the others, but none are executed if one could not be queued, or if a key watched with
+    /// `WATCH` has changed (answering `NULL`).
+    fn _handle_exec_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        let Some(transaction) = self.transaction.take() else {
+            return self._write_error(ErrorCode::Transaction, "EXEC without MULTI");
+        };
+        let watch: Option<Watch> = self.watch.take();
+        if transaction.is_failed() {
+            return self._write_error(
+                ErrorCode::Transaction,
+                "EXECABORT Transaction discarded because of previous errors",
+            );
+        }
+
+        let cache: Arc<EmbeddedCache> = Arc::clone(&self.cache);
+        let _guard = cache.transaction_lock()?;
+        if watch.is_some_and(|watch| watch.is_changed()) {
+            return self._write_response("NULL\n");
+        }
+
+        let commands: Vec<QueuedMessage> = transaction.into_commands();
+        self._write_line(format_args!("{}", commands.len()))?;
+        let request_id: Option<Vec<u8>> = self.request_id.take();
+        for queued in &commands {
+            let mut parts: Tokens = match &queued.payloads {
+                Some(payloads) => Tokens::with_payloads(&queued.message, payloads),
+                None => Tokens::new(&queued.message),
+            };
+            let mut command: Option<&[u8]> = parts.next();
+            if let Some(id) = command.and_then(|token| token.strip_prefix(b"@")) {
+                self.request_id = Some(id.to_vec());
+                command = parts.next();
+            }
+            let result: Result<(), ServerError> = self._execute_message(command, parts);
+            self.request_id = None;
+            result?;
+        }
+        self.request_id = request_id;
+        Ok(())
+    }
+
+    /// Handle a DISCARD command, dropping the commands queued since `MULTI` and unwatching the keys.
+    fn _handle_discard_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        match self.transaction.take() {
+            Some(_) => {
+                self.watch = None;
+                self._write_response("OK\n")
+            }
+            None => self._write_error(ErrorCode::Transaction, "DISCARD without MULTI"),
+        }
+    }
+
+    /// Handle a WATCH command (e.g. `WATCH balance:alice balance:bob`), watching keys for optimistic
+    /// concurrency: the next `EXEC` executes nothing (answering `NULL`) if any of them has changed in between,
+    /// e.g. after the client has read them and queued the writes based on them.
+    fn _handle_watch_command(&mut self, parts: Tokens) -> Result<(), ServerError> {
+        let keys: Vec<&[u8]> = parts.collect();
+        if keys.is_empty() {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+        let mut cache_keys: Vec<String> = Vec::with_capacity(keys.len());
+        for key in keys {
+            let Some(key) = self._parse_key(key) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            cache_keys.push(key.into_owned());
+        }
+
+        let watch: &mut Watch = match &mut self.watch {
+            Some(watch) => watch,
+            None => self.watch.insert(Watch::new(Arc::clone(&self.cache))),
+        };
+        for key in &cache_keys {
+            watch.watch(key)?;
+        }
+        self._write_response("OK\n")
+    }
+
+    /// Handle an UNWATCH command, unwatching all the keys watched with `WATCH`.
+    fn _handle_unwatch_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        self.watch = None;
+        self._write_response("OK\n")
+    }
+
+    /// Handle an EVAL command (e.g. `EVAL $75 1 counter 10`, followed by the script in the bulk mode),
+    /// running a script on the given number of keys and the other arguments (see `EmbeddedCache::eval`),
+    /// while the commands of the other clients wait. Answers the value the script returns: a value, `NULL`, a
+    /// number or a status on a line, or the number of values on a line followed by every one of them.
+    fn _handle_eval_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(script) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing script");
+        };
+        let Some(key_count) = parts.next().and_then(parse_number) else {
+            return self._write_error(ErrorCode::NotAnInteger, "Invalid number of keys");
+        };
+        let arguments: Vec<&[u8]> = parts.collect();
+        if key_count > arguments.len() as u64 {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        }
+        let (names, args): (&[&[u8]], &[&[u8]]) = arguments.split_at(key_count as usize);
+
+        let mut keys: Vec<(&str, String)> = Vec::with_capacity(names.len());
+        for name in names {
+            let Some(name) = protocol::as_key(name) else {
+                return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+            };
+            keys.push((name, self._cache_key(name).into_owned()));
+        }
+        let reply: Reply = self.cache.eval(script, &keys, args)?;
+        self._write_reply(&reply)
+    }
+
+    /// Handle a SUBSCRIBE command (e.g. `SUBSCRIBE orders payments`), subscribing the connection to
+    /// channels. Answers the number of channels subscribed to. The messages published on the channels are
+    /// then pushed to the client as `MESSAGE <channel> <message>` lines (with the message as a bulk
+    /// payload in the bulk mode), and only SUBSCRIBE and UNSUBSCRIBE are accepted until the client
+    /// unsubscribes from all of them.
+    fn _handle_subscribe_command(&mut self, parts: Tokens) -> Result<(), ServerError> {
+        if !self.pushes || self.framing != Framing::Text {
+            return self._write_error(
+                ErrorCode::UnknownCommand,
+                "SUBSCRIBE is not available on this connection",
+            );
+        }
+        let channels: Vec<&[u8]> = parts.collect();
+        if channels.is_empty() {
+            return self._write_error(ErrorCode::MissingValue, "Missing channel");
+        }
+
+        let subscriptions: &mut Subscriptions = match &mut self.subscriptions {
+            Some(subscriptions) => subscriptions,
+            None => self
+                .subscriptions
+                .insert(Subscriptions::new(Arc::clone(self.cache.pubsub()))?),
+        };
+        let mut count: usize = 0;
+        for channel in channels {
+            count = subscriptions.subscribe(&String::from_utf8_lossy(channel))?;
+        }
+        self._write_line(format_args!("{}", count))
+    }
+
+    /// Handle an UNSUBSCRIBE command (e.g. `UNSUBSCRIBE orders`), unsubscribing the connection from
+    /// channels (from all of them if none is given). Answers the number of channels still subscribed to.
+    fn _handle_unsubscribe_command(&mut self, parts: Tokens) -> Result<(), ServerError> {
+        let Some(subscriptions) = &mut self.subscriptions else {
+            return self._write_response("0\n");
+        };
+        let mut channels: Vec<String> = parts
+            .map(|channel| String::from_utf8_lossy(channel).into_owned())
+            .collect();
+        if channels.is_empty() {
+            channels = subscriptions.channels().to_vec();
+        }
+
+        let mut count: usize = subscriptions.channels().len();
+        for channel in &channels {
+            count = subscriptions.unsubscribe(channel)?;
+        }
+        self._write_line(format_args!("{}", count))
+    }
+
+    /// Handle a PUBLISH command (e.g. `PUBLISH orders order-42-paid`), publishing a message on a channel.
+    /// Answers the number of subscribers it was queued for.
+    fn _handle_publish_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let (Some(channel), Some(message)) = (parts.next(), parts.next()) else {
+            return self._write_error(ErrorCode::MissingValue, "Missing channel or message");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let channel: Cow<str> = String::from_utf8_lossy(channel);
+        let received: usize = self.cache.publish(&channel, Bytes::from(message))?;
+        self._write_line(format_args!("{}", received))
+    }
+
+    /// Handle a MONITOR command, turning the connection into a feed of the commands executed by the server,
+    /// over any protocol. Answers OK, then pushes a line per command (see `monitor::format_command`), e.g.
+    /// `1760523448.123456 [resp 127.0.0.1:51234] "SET" "my_key" "ABC"`. No command is accepted afterwards:
+    /// the client closes the connection to stop monitoring.
+    fn _handle_monitor_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if !self.pushes || self.framing != Framing::Text {
+            return self._write_error(
+                ErrorCode::UnknownCommand,
+                "MONITOR is not available on this connection",
+            );
+        }
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let mut monitor: Subscriptions = Subscriptions::new(Arc::clone(self.cache.monitors()))?;
+        monitor.subscribe(MONITOR_CHANNEL)?;
+        self.subscriptions = Some(monitor);
+        self.monitoring = true;
+        self._write_response("OK\n")
+    }
+
+    /// Handle a HISTORY command (e.g. `HISTORY my_key`), answering with the versions of a key, newest first,
+    /// as `<version>:<written at>:<bytes>` (or `<version>:<written at>:deleted` for a removal), where the
+    /// time is in milliseconds since the epoch, e.g. `0:1760523448123:3 1:1760523440000:deleted`. Answers
+    /// `NULL` if the key has no version.
+    fn _handle_history_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if !self.cache.keeps_versions() {
+            return self._write_history_disabled();
+        }
+
+        let versions: Vec<Version> = self.cache.versions(key)?;
+        if versions.is_empty() {
+            return self._write_response("NULL\n");
+        }
+        let entries: Vec<String> = versions
+            .iter()
+            .enumerate()
+            .map(|(number, version)| match &version.value {
+                Some(value) => format!("{}:{}:{}", number, version.written_at, value.len()),
+                None => format!("{}:{}:deleted", number, version.written_at),
+            })
+            .collect();
+        self._write_line(format_args!("{}", entries.join(" ")))
+    }
+
+    /// Handle a GETVERSION command (e.g. `GETVERSION my_key 1`), answering with a version of a key as listed
+    /// by HISTORY (0 being the latest): its value, `DELETED` if the key was removed, or `NULL` if there is no
+    /// such version.
+    fn _handle_get_version_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        let number: usize = match parts.next().map(parse_number) {
+            Some(Some(number)) => number as usize,
+            Some(None) => {
+                return self._write_error(ErrorCode::UnexpectedArgument, "Invalid version")
+            }
+            None => return self._write_error(ErrorCode::MissingValue, "Missing version"),
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if !self.cache.keeps_versions() {
+            return self._write_history_disabled();
+        }
+
+        match self.cache.versions(key)?.into_iter().nth(number) {
+            Some(Version {
+                value: Some(value), ..
+            }) => self._write_value(&value),
+            Some(Version { value: None, .. }) => self._write_response("DELETED\n"),
+            None => self._write_response("NULL\n"),
+        }
+    }
+
+    fn _write_history_disabled(&mut self) -> Result<(), ServerError> {
+        let message: &str =
+            "The versions of the keys are not kept (see MYRUSTCACHE_HISTORY_VERSIONS)";
+        self._write_error(ErrorCode::HistoryDisabled, message)
+    }
+
+    /// Handle a HELLO command: `HELLO BINARY`, `HELLO MSGPACK` and `HELLO PROTOBUF` switch the connection to
+    /// the binary framing, MessagePack or protobuf once answered. `HELLO STRICT` keeps the text protocol with
+    /// strict validation, `HELLO BULK` keeps it with bulk payloads, and `HELLO TEXT` goes back to the default
+    /// (lenient validation, no bulk payloads).
+    fn _handle_hello_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        match parts.next() {
+            // The keys of the binary framings are not redirected to the node of the cluster owning them
+            Some(b"BINARY" | b"MSGPACK" | b"PROTOBUF") if self.cluster.is_some() => self
+                ._write_error(
+                    ErrorCode::UnsupportedProtocol,
+                    "Only the text framing is available in a cluster",
+                ),
+            Some(b"BINARY") => {
+                self._write_response("OK BINARY\n")?;
+                self.framing = Framing::Binary;
+                debug!("{} has switched to the binary protocol", self.address);
+                Ok(())
+            }
+            Some(b"MSGPACK") => {
+                self._write_response("OK MSGPACK\n")?;
+                self.framing = Framing::MessagePack;
+                debug!("{} has switched to MessagePack", self.address);
+                Ok(())
+            }
+            Some(b"PROTOBUF") => {
+                self._write_response("OK PROTOBUF\n")?;
+                self.framing = Framing::Protobuf;
+                debug!("{} has switched to protobuf", self.address);
+                Ok(())
+            }
+            Some(b"STRICT") => {
+                self.strict = true;
+                debug!("{} has switched to strict validation", self.address);
+                self._write_response("OK STRICT\n")
+            }
+            Some(b"BULK") => {
+                self.bulk = true;
+                debug!("{} has switched to bulk payloads", self.address);
+                self._write_response("OK BULK\n")
+            }
+            Some(b"TEXT") | None => {
+                self.strict = false;
+                self.bulk = false;
+                self._write_response("OK TEXT\n")
+            }
+            Some(_) => self._write_error(ErrorCode::UnsupportedProtocol, "Unsupported protocol"),
+        }
+    }
+
+    /// Handle a SELECT command (e.g. `SELECT sessions`), isolating the keys of the following commands of the
+    /// connection in the given namespace. `SELECT default` goes back to the default namespace, whose keys are
+    /// not prefixed, so it sees the keys of every namespace (as `<namespace>:<key>`). `SELECT <index>` (e.g.
+    /// `SELECT 1`) switches to the given database instead, keeping the namespace, even if there is only one.
+    /// `USE <namespace>` (if `use_namespace`) always selects a namespace, whatever its name.
+    fn _handle_select_command(
+        &mut self,
+        mut parts: Tokens,
+        use_namespace: bool,
+    ) -> Result<(), ServerError> {
+        let Some(name) = parts.next() else {
+            return self._write_error(ErrorCode::MissingNamespace, "Missing namespace");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        if !use_namespace && name.iter().all(u8::is_ascii_digit) {
+            let Some(index) = self._parse_database(name) else {
+                return self._write_error(ErrorCode::InvalidDatabase, "DB index is out of range");
+            };
+            self.database = index;
+            debug!("{} has selected the database {}", self.address, index);
+            return self._write_response("OK\n");
+        }
+
+        if !namespaces::is_valid_namespace(name) {
+            let message: &str = "Namespaces are made of at most 64 letters, digits, '_' and '-'";
+            return self._write_error(ErrorCode::InvalidNamespace, message);
+        }
+
+        self.namespace = match name {
+            b"default" => None,
+            name => Some(String::from_utf8_lossy(name).into_owned()),
+        };
+        debug!(
+            "{} has selected the namespace {:?}",
+            self.address, self.namespace
+        );
+        self._write_response("OK\n")
+    }
+
+    /// Handle a FLUSH command, removing every key of the selected namespace (of all namespaces in the
+    /// default one), or only those starting with the given prefix (e.g. `FLUSH user:42:`). The keys are
+    /// removed in the background, and the command is answered with the number of keys being removed.
+    fn _handle_flush_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let maybe_prefix: Option<&[u8]> = parts.next();
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let mut prefix: String = self._namespace_prefix();
+        if let Some(token) = maybe_prefix {
+            let Some(token) = protocol::as_key(token) else {
+                return self._write_error(ErrorCode::InvalidKey, "Prefix must be valid UTF-8");
+            };
+            prefix.push_str(token);
+        }
+        self._flush(&prefix)
+    }
+
+    /// Handle a FLUSHNS command (e.g. `FLUSHNS sessions`), removing every key of the given namespace in the
+    /// background. Only available in the default namespace, so that a namespace cannot flush the others.
+    fn _handle_flush_namespace_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(name) = parts.next() else {
+            return self._write_error(ErrorCode::MissingNamespace, "Missing namespace");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if self.namespace.is_some() {
+            let message: &str = "FLUSHNS is only available in the default namespace";
+            return self._write_error(ErrorCode::InvalidNamespace, message);
+        }
+        if !namespaces::is_valid_namespace(name) || name == b"default" {
+            let message: &str = "Namespaces are made of at most 64 letters, digits, '_' and '-'";
+            return self._write_error(ErrorCode::InvalidNamespace, message);
+        }
+
+        let prefix: String = format!(
+            "{}{}{}",
+            namespaces::database_prefix(self.database),
+            String::from_utf8_lossy(name),
+            NAMESPACE_SEPARATOR
+        );
+        self._flush(&prefix)
+    }
+
+    /// Handle a FLUSHALL command, removing every key of every namespace at once. With `ASYNC`, the memory
+    /// of the keys removed is released in the background. Only available in the default namespace, so that
+    /// a namespace cannot flush the others. Answers `OK`.
+    fn _handle_flushall_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let in_background: bool = match parts.next() {
+            None | Some(b"SYNC") => false,
+            Some(b"ASYNC") => true,
+            Some(_) => {
+                return self._write_error(ErrorCode::InvalidParameter, "Expected ASYNC or SYNC")
+            }
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if self.namespace.is_some() {
+            let message: &str = "FLUSHALL is only available in the default namespace";
+            return self._write_error(ErrorCode::InvalidNamespace, message);
+        }
+
+        self.cache.clear(in_background)?;
+        info!("{} has flushed all the keys", self.address);
+        self._write_response("OK\n")
+    }
+
+    /// Handle a DBSIZE command, answering the number of keys with a value: those of the selected namespace,
+    /// or all those of the selected database in the default one.
+    fn _handle_dbsize_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let keys: usize = match self.namespace.is_none() && self.cache.databases() == 1 {
+            true => self.cache.len()?,
+            false => self.cache.keyspace_stats(&self._namespace_prefix())?.keys,
+        };
+        self._write_line(format_args!("{}", keys))
+    }
+
+    /// Handle a SAVE command, saving a snapshot of the cache before answering `OK`, or a BGSAVE command,
+    /// answering `OK` once the snapshot is being saved in the background (see `Snapshots`).
+    fn _handle_save_command(
+        &mut self,
+        mut parts: Tokens,
+        in_background: bool,
+    ) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        let Some(snapshots) = self.snapshots.clone() else {
+            return self._write_error(ErrorCode::SnapshotError, "Snapshots are disabled");
+        };
+
+        let started: Result<bool, ServerError> = match in_background {
+            true => snapshots.save_in_background(&self.cache),
+            false => snapshots.save(&self.cache).map(|keys| keys.is_some()),
+        };
+        match started {
+            Ok(true) => self._write_response("OK\n"),
+            Ok(false) => self._write_error(
+                ErrorCode::SnapshotError,
+                "A snapshot is already being saved",
+            ),
+            Err(ServerError::Cache(e)) => Err(e.into()),
+            Err(e) => {
+                error!("Failed to save a snapshot: {}", e);
+                self._write_error(ErrorCode::SnapshotError, "Failed to save the snapshot")
+            }
+        }
+    }
+
+    /// Handle a SHUTDOWN command, answering `OK` before the server shuts down as it does on `SIGTERM`: the
+    /// listeners stop, the open connections are drained, then a final snapshot is saved (if enabled) and
+    /// the cache flushed. `SHUTDOWN SAVE` fails if snapshots are disabled, and `SHUTDOWN NOSAVE` skips the
+    /// snapshot.
+    fn _handle_shutdown_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(shutdown) = self.shutdown.clone() else {
+            return self._write_error(
+                ErrorCode::UnknownCommand,
+                "SHUTDOWN is not available on this connection",
+            );
+        };
+        let save: Option<bool> = match parts.next() {
+            None => None,
+            Some(b"SAVE") => Some(true),
+            Some(b"NOSAVE") => Some(false),
+            Some(_) => {
+                let message: &str = "Invalid mode, try SHUTDOWN SAVE or SHUTDOWN NOSAVE";
+                return self._write_error(ErrorCode::UnexpectedArgument, message);
+            }
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if save == Some(true) && self.snapshots.is_none() {
+            return self._write_error(ErrorCode::SnapshotError, "Snapshots are disabled");
+        }
+
+        warn!("Shutdown requested by {}", self.address);
+        self._write_response("OK\n")?;
+        match save {
+            Some(false) => shutdown.shutdown_without_snapshot(),
+            _ => shutdown.shutdown(),
+        }
+        Ok(())
+    }
+
+    /// Handle a LASTSAVE command, answering when the last snapshot was saved (in seconds since the epoch), or
+    /// 0 if none was since the server started.
+    fn _handle_lastsave_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        let Some(snapshots) = &self.snapshots else {
+            return self._write_error(ErrorCode::SnapshotError, "Snapshots are disabled");
+        };
+        let last_save: u64 = snapshots.last_save();
+        self._write_line(format_args!("{}", last_save))
+    }
+
+    /// Handle a BGREWRITEAOF command, answering `OK` once the append-only log is being rewritten in the
+    /// background into a compacted one (see `EmbeddedCache::rewrite_append_only_log`).
+    fn _handle_bgrewriteaof_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if self.cache.append_only_log().is_none() {
+            let message: &str = "The append-only log is disabled";
+            return self._write_error(ErrorCode::SnapshotError, message);
+        }
+
+        match self.cache.rewrite_append_only_log()? {
+            true => self._write_response("OK\n"),
+            false => {
+                let message: &str = "The append-only log is already being rewritten";
+                self._write_error(ErrorCode::SnapshotError, message)
+            }
+        }
+    }
+
+    /// Handle an EXPORT command (e.g. `EXPORT backup.jsonl`, or `EXPORT backup.bin CBOR`), exporting every
+    /// key of the cache to the given file of the export directory (see `Datasets`), in the given format or
+    /// else the one told by its extension. Answers the number of keys exported.
+    fn _handle_export_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(name) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing file name");
+        };
+        let format: Option<DatasetFormat> = match parts.next() {
+            Some(format) => match str::from_utf8(format).map(str::parse) {
+                Ok(Ok(format)) => Some(format),
+                _ => {
+                    let message: &str = "Format must be JSON or CBOR";
+                    return self._write_error(ErrorCode::DatasetError, message);
+                }
+            },
+            None => None,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        let Some(datasets) = self.datasets.clone() else {
+            return self._write_error(ErrorCode::DatasetError, "Exports are disabled");
+        };
+
+        let name: String = String::from_utf8_lossy(name).into_owned();
+        match datasets.export(&self.cache, &name, format) {
+            Ok(keys) => self._write_line(format_args!("{}", keys)),
+            Err(e) => self._write_dataset_error(e, "export"),
+        }
+    }
+
+    /// Handle an IMPORT command (e.g. `IMPORT backup.jsonl`, or `IMPORT backup.bin CBOR REPLACE`), importing
+    /// the given file of the export directory into the cache (see `Datasets`), in the given format or else
+    /// the one told by its extension. Its keys are merged into the cache, or replace them with `REPLACE`.
+    /// Answers the number of keys imported.
+    fn _handle_import_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(name) = parts.next() else {
+            return self._write_error(ErrorCode::MissingValue, "Missing file name");
+        };
+        let mut format: Option<DatasetFormat> = None;
+        let mut mode: ImportMode = ImportMode::default();
+        for option in parts {
+            let option: &str = str::from_utf8(option).unwrap_or_default();
+            match (
+                option.parse::<DatasetFormat>(),
+                option.parse::<ImportMode>(),
+            ) {
+                (Ok(parsed), _) => format = Some(parsed),
+                (_, Ok(parsed)) => mode = parsed,
+                _ => {
+                    let message: &str = "Options must be JSON or CBOR, and MERGE or REPLACE";
+                    return self._write_error(ErrorCode::DatasetError, message);
+                }
+            }
+        }
+        let Some(datasets) = self.datasets.clone() else {
+            return self._write_error(ErrorCode::DatasetError, "Imports are disabled");
+        };
+
+        let name: String = String::from_utf8_lossy(name).into_owned();
+        match datasets.import(&self.cache, &name, format, mode) {
+            Ok(stats) => self._write_line(format_args!("{}", stats.imported)),
+            Err(e) => self._write_dataset_error(e, "import"),
+        }
+    }
+
+    /// Report a failed export or import to the client: cache errors as usual, and the others as
+    /// `dataset_error`, telling what is wrong with the file if the client can fix it.
+    fn _write_dataset_error(
+        &mut self,
+        error: ServerError,
+        action: &str,
+    ) -> Result<(), ServerError> {
+        match error {
+            ServerError::Cache(e) => Err(e.into()),
+            ServerError::Io(e) if e.kind() == ErrorKind::NotFound => {
+                self._write_error(ErrorCode::DatasetError, "No such file")
+            }
+            ServerError::Io(e)
+                if matches!(e.kind(), ErrorKind::InvalidInput | ErrorKind::InvalidData) =>
+            {
+                self._write_error(ErrorCode::DatasetError, &e.to_string())
+            }
+            e => {
+                error!("Failed to {} a dataset: {}", action, e);
+                let message: String = format!("Failed to {} the dataset", action);
+                self._write_error(ErrorCode::DatasetError, &message)
+            }
+        }
+    }
+
+    /// Handle a SWAPDB command (e.g. `SWAPDB 0 1`), swapping the keys of two databases, so that the clients
+    /// of either database see the keys of the other. The keys are renamed one at a time (see
+    /// `EmbeddedCache::swap_databases`). Answers `OK`.
+    fn _handle_swapdb_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let (Some(first), Some(second)) = (parts.next(), parts.next()) else {
+            return self._write_error(ErrorCode::MissingValue, "Missing database index");
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        let (Some(first), Some(second)) =
+            (self._parse_database(first), self._parse_database(second))
+        else {
+            return self._write_error(ErrorCode::InvalidDatabase, "DB index is out of range");
+        };
+
+        let moved: usize = self.cache.swap_databases(first, second)?;
+        info!(
+            "{} has swapped the databases {} and {} ({} keys moved)",
+            self.address, first, second, moved
+        );
+        self._write_response("OK\n")
+    }
+
+    /// Remove the keys starting with the given prefix in the background, answering how many there are.
+    fn _flush(&mut self, prefix: &str) -> Result<(), ServerError> {
+        let count: usize = self.cache.clear_prefix_in_background(prefix)?;
+        info!(
+            "{} is flushing {} keys starting with '{}'",
+            self.address, count, prefix
+        );
+        self._write_line(format_args!("{}", count))
+    }
+
+    /// Handle a STATS command, answering with the keys of the selected namespace (of all namespaces in the
+    /// default one), e.g. `namespace=sessions keys=12 negative_keys=1 bytes=1024`. With a storage engine
+    /// accounting for its memory, the memory of the whole cache follows, e.g.
+    /// `used_memory=4096 maxmemory=1048576 evictions=3` (`maxmemory=0` without a limit). In a namespace with
+    /// a quota, what it takes of the quota comes before, along with its limits, e.g.
+    /// `quota_keys=12 quota_bytes=1024 max_keys=10000`.
+    fn _handle_stats_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let stats: KeyspaceStats = self.cache.keyspace_stats(&self._namespace_prefix())?;
+        let namespace: &str = self.namespace.as_deref().unwrap_or("default");
+        let mut response: String = format!(
+            "namespace={} keys={} negative_keys={} bytes={}",
+            namespace, stats.keys, stats.negative_keys, stats.bytes
+        );
+        let usage: Option<NamespaceUsage> = match &self.namespace {
+            Some(namespace) => self.cache.namespace_usage(namespace)?,
+            None => None,
+        };
+        if let Some(usage) = usage {
+            response.push_str(&format!(
+                " quota_keys={} quota_bytes={}",
+                usage.entries, usage.bytes
+            ));
+            let limits: [(&str, Option<usize>); 3] = [
+                ("max_keys", usage.quota.max_entries),
+                ("max_bytes", usage.quota.max_bytes),
+                (
+                    "max_writes",
+                    usage.quota.max_writes_per_second.map(|max| max as usize),
+                ),
+            ];
+            for (name, limit) in limits {
+                if let Some(limit) = limit {
+                    response.push_str(&format!(" {}={}", name, limit));
+                }
+            }
+        }
+        if let Some(memory) = self.cache.memory_stats() {
+            response.push_str(&format!(
+                " used_memory={} maxmemory={} evictions={}",
+                memory.used_bytes,
+                memory.max_bytes.unwrap_or(0),
+                memory.evictions
+            ));
+        }
+        response.push('\n');
+        self._write_response(&response)
+    }
+
+    /// Handle an INFO command, answering with the statistics of the whole server (see `EmbeddedCache::info`)
+    /// as `name:value` pairs separated by spaces, e.g. `uptime_seconds:3600 connected_clients:2
+    /// commands_processed:1042 keys:12 hits:900 misses:87 expired:3`.
+    fn _handle_info_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let info: Vec<String> = self
+            .cache
+            .info()?
+            .into_iter()
+            .map(|(name, value)| format!("{}:{}", name, value))
+            .collect();
+        self._write_line(format_args!("{}", info.join(" ")))
+    }
+
+    /// Handle a CLIENT command: `CLIENT LIST` answers with the client connections open across all the
+    /// listeners, in the order they connected, as
+    /// `<id>:<protocol>:<age>:<idle>:<commands>:<command>:<address>`, where the age and the idle time are in
+    /// seconds and the command is the name of the last one sent (`-` if none), e.g.
+    /// `3:RESP:120:4:57:GET:127.0.0.1:51234`. `CLIENT KILL <address>` disconnects a client.
+    fn _handle_client_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let subcommand: Option<&[u8]> = parts.next();
+        let address: Option<&[u8]> = match subcommand {
+            Some(b"KILL") => match parts.next() {
+                Some(address) => Some(address),
+                None => return self._write_error(ErrorCode::MissingValue, "Missing address"),
+            },
+            _ => None,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match (subcommand, address) {
+            (Some(b"LIST"), _) => {
+                let clients: Vec<ClientInfo> = self.cache.clients()?;
+                if clients.is_empty() {
+                    return self._write_response("NULL\n");
+                }
+                let clients: Vec<String> = clients
+                    .iter()
+                    .map(|client| {
+                        let mut command: String = String::new();
+                        match &client.last_command {
+                            Some(name) => slowlog::percent_encode(name, &mut command),
+                            None => command.push('-'),
+                        }
+                        format!(
+                            "{}:{}:{}:{}:{}:{}:{}",
+                            client.id,
+                            client.protocol,
+                            client.age.as_secs(),
+                            client.idle.as_secs(),
+                            client.commands,
+                            command,
+                            client.address
+                        )
+                    })
+                    .collect();
+                self._write_line(format_args!("{}", clients.join(" ")))
+            }
+            (Some(b"KILL"), Some(address)) => {
+                match self.cache.kill_client(&String::from_utf8_lossy(address))? {
+                    true => self._write_response("OK\n"),
+                    false => self._write_error(ErrorCode::NoSuchClient, "No such client"),
+                }
+            }
+            _ => self._write_error(
+                ErrorCode::UnknownCommand,
+                "Unknown CLIENT subcommand, try CLIENT LIST or CLIENT KILL <address>",
+            ),
+        }
+    }
+
+    /// Handle a SLOWLOG command: `SLOWLOG GET` (or `SLOWLOG GET 50`) answers with the last 10 (or 50) commands
+    /// which took longer than the threshold of the slow log, newest first, as
+    /// `<id>:<finished at>:<duration>:<command>:<client>`, where the time is in milliseconds since the epoch,
+    /// the duration in microseconds, and the command a single token (see `SlowLogEntry::command_line`), e.g.
+    /// `7:1760523448123:15230:KEYS,*:127.0.0.1:51234`, or `NULL` if none is logged. `SLOWLOG LEN` answers
+    /// with the number of commands logged, and `SLOWLOG RESET` drops them.
+    fn _handle_slowlog_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let subcommand: Option<&[u8]> = parts.next();
+        let count: usize = match (subcommand, parts.next()) {
+            (Some(b"GET"), Some(count)) => match parse_number(count) {
+                Some(count) => count as usize,
+                None => return self._write_error(ErrorCode::UnexpectedArgument, "Invalid count"),
+            },
+            _ => DEFAULT_SLOWLOG_GET_COUNT,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match subcommand {
+            Some(b"GET") => {
+                let entries: Vec<SlowLogEntry> = self.cache.slow_log().get(count)?;
+                if entries.is_empty() {
+                    return self._write_response("NULL\n");
+                }
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|entry| {
+                        format!(
+                            "{}:{}:{}:{}:{}",
+                            entry.id,
+                            entry.timestamp,
+                            entry.duration.as_micros(),
+                            entry.command_line(),
+                            entry.client
+                        )
+                    })
+                    .collect();
+                self._write_line(format_args!("{}", entries.join(" ")))
+            }
+            Some(b"LEN") => {
+                let len: usize = self.cache.slow_log().len()?;
+                self._write_line(format_args!("{}", len))
+            }
+            Some(b"RESET") => {
+                self.cache.slow_log().reset()?;
+                self._write_response("OK\n")
+            }
+            _ => self._write_error(
+                ErrorCode::UnknownCommand,
+                "Unknown SLOWLOG subcommand, try SLOWLOG GET, SLOWLOG LEN or SLOWLOG RESET",
+            ),
+        }
+    }
+
+    /// Handle a MEMORY command: `MEMORY USAGE <key>` answers the bytes taken by the key, its value and their
+    /// bookkeeping (see `EmbeddedCache::memory_usage`), or `NULL` if it has no value, and `MEMORY STATS`
+    /// sums up the memory of all the keys as `name=value` pairs separated by spaces, e.g. `keys=1200
+    /// used_memory=245760 maxmemory=1048576 evictions=0 bytes_per_key=204 accounting=engine` (`accounting=
+    /// estimated` with a storage engine which does not account for its memory, the bytes being estimated).
+    fn _handle_memory_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        match parts.next() {
+            Some(b"USAGE") => {
+                let Some(key) = parts.next() else {
+                    return self._write_error(ErrorCode::MissingKey, "Missing key");
+                };
+                let Some(key) = self._parse_key(key) else {
+                    return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+                };
+                let key: &str = &key;
+                if self.strict && parts.next().is_some() {
+                    return self._write_strict_error(ErrorCode::UnexpectedArgument);
+                }
+
+                match self.cache.memory_usage(key)? {
+                    Some(bytes) => self._write_line(format_args!("{}", bytes)),
+                    None => self._write_response("NULL\n"),
+                }
+            }
+            Some(b"STATS") => {
+                if self.strict && parts.next().is_some() {
+                    return self._write_strict_error(ErrorCode::UnexpectedArgument);
+                }
+
+                let report: MemoryReport = self.cache.memory_report()?;
+                self._write_line(format_args!(
+                    "keys={} used_memory={} maxmemory={} evictions={} bytes_per_key={} accounting={}",
+                    report.keys,
+                    report.used_bytes,
+                    report.max_bytes.unwrap_or(0),
+                    report.evictions,
+                    report.bytes_per_key(),
+                    match report.estimated {
+                        true => "estimated",
+                        false => "engine",
+                    }
+                ))
+            }
+            _ => self._write_error(
+                ErrorCode::UnknownCommand,
+                "Unknown MEMORY subcommand, try MEMORY USAGE <key> or MEMORY STATS",
+            ),
+        }
+    }
+
+    /// Handle an OBJECT command, with the reads of the keys tracked (see `EmbeddedCache::key_access`):
+    /// `OBJECT IDLETIME <key>` answers how long ago the key was last read (or written, if it never was), in
+    /// seconds, and `OBJECT FREQ <key>` how many times it was read, or `NULL` if it has no value.
+    fn _handle_object_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let subcommand: Option<&[u8]> = parts.next();
+        if !matches!(subcommand, Some(b"IDLETIME" | b"FREQ")) {
+            return self._write_error(
+                ErrorCode::UnknownCommand,
+                "Unknown OBJECT subcommand, try OBJECT IDLETIME <key> or OBJECT FREQ <key>",
+            );
+        }
+        let Some(key) = parts.next() else {
+            return self._write_error(ErrorCode::MissingKey, "Missing key");
+        };
+        let Some(key) = self._parse_key(key) else {
+            return self._write_error(ErrorCode::InvalidKey, "Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+        if !self.cache.tracks_access() {
+            let message: &str =
+                "The reads of the keys are not tracked (see MYRUSTCACHE_TRACK_ACCESS)";
+            return self._write_error(ErrorCode::AccessTrackingDisabled, message);
+        }
+
+        match (subcommand, self.cache.key_access(key)?) {
+            (_, None) => self._write_response("NULL\n"),
+            (Some(b"FREQ"), Some(access)) => self._write_line(format_args!("{}", access.reads)),
+            (_, Some(access)) => self._write_line(format_args!("{}", access.idle_seconds())),
+        }
+    }
+
+    /// Handle a ROLE command, answering with the role of the node: `role=primary`, or for a replica the
+    /// state of its link to the primary and how long ago the primary was last heard from, in seconds, e.g.
+    /// `role=replica link=up lag=2` (or `role=replica link=down`). Clients use it to decide whether they
+    /// can read from the replica (see `client::Client`).
+    fn _handle_role_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let response: String = match (self.cache.is_replica(), self.cache.replication_lag()) {
+            (false, _) => "role=primary\n".to_string(),
+            (true, Some(lag)) => format!("role=replica link=up lag={}\n", lag),
+            (true, None) => "role=replica link=down\n".to_string(),
+        };
+        self._write_response(&response)
+    }
+
+    /// Handle an AUTH command, authenticating the connection as the default user with the password of the
+    /// server (e.g. `AUTH secret`, see `CacheServer::with_password`), or as another user with their password
+    /// (e.g. `AUTH reader secret`, see `CacheServer::with_acl_users`).
+    fn _handle_auth_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let (name, password): (&[u8], &[u8]) = match (parts.next(), parts.next()) {
+            (Some(password), None) => (DEFAULT_USER.as_bytes(), password),
+            (Some(name), Some(password)) => (name, password),
+            (None, _) => return self._write_error(ErrorCode::MissingValue, "Missing password"),
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        if !self.auth.is_required() {
+            return self._write_error(ErrorCode::InvalidPassword, "No password is set");
+        }
+        let Some(user) = self.auth.authenticate(name, password) else {
+            warn!(
+                "Failed authentication of {} from {}",
+                String::from_utf8_lossy(name),
+                self.address
+            );
+            return self._write_error(
+                ErrorCode::InvalidPassword,
+                "WRONGPASS Invalid username-password pair",
+            );
+        };
+
+        self.user = Some(user);
+        self._write_response("OK\n")
+    }
+
+    /// Handle an ACL command: `ACL WHOAMI` answers with the name of the user of the connection (`default`
+    /// if it has not authenticated as another one), and `ACL LIST` with the permissions of every user, e.g.
+    /// `default:commands=*,keys=* reader:commands=GET,keys=app:*`.
+    fn _handle_acl_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let subcommand: Option<&[u8]> = parts.next();
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match subcommand {
+            Some(b"WHOAMI") => {
+                let name: String = match self.user.and_then(|index| self.auth.user(index)) {
+                    Some((name, _)) => name.clone(),
+                    None => DEFAULT_USER.to_string(),
+                };
+                self._write_line(format_args!("{}", name))
+            }
+            Some(b"LIST") => {
+                let users: Vec<String> = match self.auth.users() {
+                    [] => vec![format!("{}:{}", DEFAULT_USER, AclUser::unrestricted(""))],
+                    users => users
+                        .iter()
+                        .map(|(name, user)| format!("{}:{}", name, user))
+                        .collect(),
+                };
+                self._write_line(format_args!("{}", users.join(" ")))
+            }
+            _ => self._write_error(ErrorCode::UnknownCommand, "Unknown ACL subcommand"),
+        }
+    }
+
+    /// Handle a CONFIG command: `CONFIG GET <pattern>` answers with the values of the operational parameters
+    /// matching the pattern (e.g. `CONFIG GET worker_*`), as `name=value` pairs (or `NULL` if none match),
+    /// and `CONFIG SET <name> <value>` changes a parameter while the server runs (see `RuntimeConfig`).
+    fn _handle_config_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let Some(runtime) = self.runtime.clone() else {
+            return self._write_error(
+                ErrorCode::UnknownCommand,
+                "CONFIG is not available on this connection",
+            );
+        };
+
+        let subcommand: Option<&[u8]> = parts.next();
+        let maybe_name: Option<String> = parts
+            .next()
+            .map(|name| String::from_utf8_lossy(name).to_string());
+        let maybe_value: Option<String> = parts
+            .next()
+            .map(|value| String::from_utf8_lossy(value).to_string());
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        match (subcommand, maybe_name, maybe_value) {
+            (Some(b"GET"), Some(pattern), None) => {
+                let values: Vec<(&str, String)> = runtime.get(&pattern);
+                if values.is_empty() {
+                    return self._write_response("NULL\n");
+                }
+                let pairs: Vec<String> = values
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                self._write_line(format_args!("{}", pairs.join(" ")))
+            }
+            (Some(b"SET"), Some(name), Some(value)) => match runtime.set(&name, &value) {
+                Ok(()) => {
+                    info!(
+                        "Configuration changed by the command{} from {}: {}={}",
+                        self._request_id_suffix(),
+                        self.address,
+                        name,
+                        value
+                    );
+                    self._write_response("OK\n")
+                }
+                Err(message) => self._write_error(ErrorCode::InvalidParameter, &message),
+            },
+            (Some(b"GET" | b"SET"), _, _) => self._write_error(
+                ErrorCode::UnexpectedArgument,
+                "Usage: CONFIG GET <pattern> or CONFIG SET <name> <value>",
+            ),
+            _ => self._write_error(ErrorCode::UnknownCommand, "Unknown CONFIG subcommand"),
+        }
+    }
+
+    /// Handle a TTLSTATS command (e.g. `TTLSTATS`, or `TTLSTATS 1000` to sample at most 1000 keys), answering
+    /// with the remaining TTLs of a sample of the keys of the selected namespace, e.g.
+    /// `sampled=1000 persistent=10 <1m=40 <5m=100 <15m=150 <1h=300 <6h=200 <1d=150 >=1d=50`.
+    fn _handle_ttl_stats_command(&mut self, mut parts: Tokens) -> Result<(), ServerError> {
+        let sample_size: usize = match parts.next() {
+            Some(token) => match parse_number(token) {
+                Some(size) if size > 0 => size as usize,
+                _ => {
+                    return self._write_error(ErrorCode::UnexpectedArgument, "Invalid sample size")
+                }
+            },
+            None => DEFAULT_TTL_SAMPLE_SIZE,
+        };
+        if self.strict && parts.next().is_some() {
+            return self._write_strict_error(ErrorCode::UnexpectedArgument);
+        }
+
+        let histogram: TtlHistogram = self
+            .cache
+            .ttl_histogram(&self._namespace_prefix(), sample_size)?;
+        let mut response: String = format!(
+            "sampled={} persistent={}",
+            histogram.sampled, histogram.persistent
+        );
+        for (bucket, count) in histogram.buckets.iter().enumerate() {
+            match TTL_BUCKETS.get(bucket) {
+                Some(&bound) => {
+                    response.push_str(&format!(" <{}={}", format_duration(bound), count))
+                }
+                None => {
+                    let bound: u64 = TTL_BUCKETS[TTL_BUCKETS.len() - 1];
+                    response.push_str(&format!(" >={}={}", format_duration(bound), count))
+                }
+            }
+        }
+        response.push('\n');
+        self._write_response(&response)
+    }
+
+    /// Handle a request of the binary protocol, answering with a frame carrying its id.
+    fn _handle_frame(&mut self, request: &Request) -> Result<(), ServerError> {
+        if self._throttled() {
+            return self._write_frame(binary::STATUS_ERROR, 0, request.id, b"Rate limit exceeded");
+        }
+        let result: Result<(), ServerError> =
+            self._isolated(|session| session._execute_frame(request));
+
+        match result {
+            Err(ServerError::Cache(e)) => {
+                log!(
+                    e.log_level(),
+                    "Failed to execute the request from {}: {}",
+                    self.address,
+                    e
+                );
+                let message: String = e.to_string();
+                self._write_frame(binary::STATUS_ERROR, 0, request.id, message.as_bytes())
+            }
+            other => other,
+        }
+    }
+
+    /// Execute a request of the binary protocol, leaving cache errors to the caller.
+    fn _execute_frame(&mut self, request: &Request) -> Result<(), ServerError> {
+        let id: u32 = request.id;
+        if id == 0 {
+            warn!("Frame without a request id from {}", self.address);
+            return self._write_frame(binary::STATUS_INVALID, 0, id, b"Missing request id");
+        }
+        if request.opcode == binary::OP_NOOP {
+            return self._write_frame(binary::STATUS_OK, 0, id, b"");
+        }
+
+        let Some(key) = self._parse_key(request.key) else {
+            return self._write_frame(binary::STATUS_INVALID, 0, id, b"Key must be valid UTF-8");
+        };
+        let key: &str = &key;
+
+        match request.opcode {
+            binary::OP_GET => {
+                let lookup: Lookup = match request.ttl {
+                    Some(lock_ttl) => {
+                        // Waiting for the value may take a while, so answer the preceding requests first
+                        self.stream.flush()?;
+                        self.cache.get_or_lock(key, lock_ttl)?
+                    }
+                    None => self.cache.get(key)?,
+                };
+
+                match lookup {
+                    Lookup::Fresh(value) => self._write_frame(binary::STATUS_OK, 0, id, &value),
+                    Lookup::Stale(value) => {
+                        self._write_frame(binary::STATUS_OK, binary::FLAG_STALE, id, &value)
+                    }
+                    Lookup::Negative => self._write_frame(binary::STATUS_NEGATIVE, 0, id, b""),
+                    Lookup::Missing => self._write_frame(binary::STATUS_NOT_FOUND, 0, id, b""),
+                    Lookup::Recompute => self._write_frame(binary::STATUS_RECOMPUTE, 0, id, b""),
+                }
+            }
+            binary::OP_SET => {
+                let value: Bytes = Bytes::from(request.value);
+                self.cache.put(key, value, request.ttl, request.soft_ttl)?;
+                self._write_frame(binary::STATUS_OK, 0, id, b"")
+            }
+            binary::OP_SET_NULL => {
+                self.cache.put_negative(key, request.ttl)?;
+                self._write_frame(binary::STATUS_OK, 0, id, b"")
+            }
+            binary::OP_DELETE => match self.cache.remove(key)? {
+                Some(value) => self._write_frame(binary::STATUS_OK, 0, id, &value),
+                None => self._write_frame(binary::STATUS_NOT_FOUND, 0, id, b""),
+            },
+            opcode => {
+                warn!("Unknown opcode {:#04x} from {}", opcode, self.address);
+                self._write_frame(binary::STATUS_UNKNOWN_OPCODE, 0, id, b"")
+            }
+        }
+    }
+
+    /// Handle a MessagePack request: a map with an `op` (`get`, `set`, `setnull`, `del` or `ping`), a `key`,
+    /// and depending on the operation a `value` (binary or string), a `ttl`, a `soft_ttl` or a `lock` (the
+    /// TTL of the lock taken by a `get` on a miss). The `id` of the request, if any, is echoed back.
+    ///
+    /// Responses are maps with a `status` (`ok`, `stale`, `negative`, `not_found`, `recompute`, `invalid`
+    /// or `error`), along with the `value` (a binary) or the `error` message if any.
+    fn _handle_msgpack_request(&mut self, request: &Value) -> Result<(), ServerError> {
+        let id: Option<&Value> = request.get("id");
+        if self._throttled() {
+            return self._write_msgpack(id, "error", Some("Rate limit exceeded"), None);
+        }
+        let result: Result<(), ServerError> =
+            self._isolated(|session| session._execute_msgpack_request(request, id));
+
+        match result {
+            Err(ServerError::Cache(e)) => {
+                log!(
+                    e.log_level(),
+                    "Failed to execute the request from {}: {}",
+                    self.address,
+                    e
+                );
+                self._write_msgpack(id, "error", Some(&e.to_string()), None)
+            }
+            other => other,
+        }
+    }
+
+    /// Execute a MessagePack request, leaving cache errors to the caller.
+    fn _execute_msgpack_request(
+        &mut self,
+        request: &Value,
+        id: Option<&Value>,
+    ) -> Result<(), ServerError> {
+        let Some(op) = request.get("op").and_then(Value::as_str) else {
+            return self._write_msgpack(id, "invalid", Some("Missing op"), None);
+        };
+        if op == "ping" {
+            return self._write_msgpack(id, "ok", None, None);
+        }
+
+        let Some(key) = request
+            .get("key")
+            .and_then(Value::as_str)
+            .and_then(|key| protocol::as_key(key.as_bytes()))
+        else {
+            return self._write_msgpack(id, "invalid", Some("Missing or invalid key"), None);
+        };
+        let key: Cow<str> = self._cache_key(key);
+        let key: &str = &key;
+        let field = |name: &str| request.get(name).and_then(Value::as_u64);
+
+        match op {
+            "get" => {
+                let lookup: Lookup = match field("lock") {
+                    Some(lock_ttl) => {
+                        // Waiting for the value may take a while, so answer the preceding requests first
+                        self.stream.flush()?;
+                        self.cache.get_or_lock(key, lock_ttl)?
+                    }
+                    None => self.cache.get(key)?,
+                };
+
+                match lookup {
+                    Lookup::Fresh(value) => self._write_msgpack(id, "ok", None, Some(&value)),
+                    Lookup::Stale(value) => self._write_msgpack(id, "stale", None, Some(&value)),
+                    Lookup::Negative => self._write_msgpack(id, "negative", None, None),
+                    Lookup::Missing => self._write_msgpack(id, "not_found", None, None),
+                    Lookup::Recompute => self._write_msgpack(id, "recompute", None, None),
+                }
+            }
+            "set" => {
+                let Some(value) = request.get("value").and_then(Value::as_bytes) else {
+                    return self._write_msgpack(id, "invalid", Some("Missing value"), None);
+                };
+                self.cache
+                    .put(key, Bytes::from(value), field("ttl"), field("soft_ttl"))?;
+                self._write_msgpack(id, "ok", None, None)
+            }
+            "setnull" => {
+                self.cache.put_negative(key, field("ttl"))?;
+                self._write_msgpack(id, "ok", None, None)
+            }
+            "del" => match self.cache.remove(key)? {
+                Some(value) => self._write_msgpack(id, "ok", None, Some(&value)),
+                None => self._write_msgpack(id, "not_found", None, None),
+            },
+            _ => {
+                warn!("Unknown op {} from {}", op, self.address);
+                self._write_msgpack(id, "invalid", Some("Unknown op"), None)
+            }
+        }
+    }
+
+    /// Handle a `Command` message of `proto/myrustcache.proto`, answering with a `Reply` carrying its id.
+    fn _handle_protobuf_command(&mut self, command: &Command) -> Result<(), ServerError> {
+        if self._throttled() {
+            let message: &str = "Rate limit exceeded";
+            return self._write_protobuf(command.id, protobuf::STATUS_ERROR, message, b"");
+        }
+        let result: Result<(), ServerError> =
+            self._isolated(|session| session._execute_protobuf_command(command));
+
+        match result {
+            Err(ServerError::Cache(e)) => {
+                log!(
+                    e.log_level(),
+                    "Failed to execute the command from {}: {}",
+                    self.address,
+                    e
+                );
+                let message: String = e.to_string();
+                self._write_protobuf(command.id, protobuf::STATUS_ERROR, &message, b"")
+            }
+            other => other,
+        }
+    }
+
+    /// Execute a `Command` message, leaving cache errors to the caller.
+    fn _execute_protobuf_command(&mut self, command: &Command) -> Result<(), ServerError> {
+        let id: u64 = command.id;
+        if command.op == protobuf::OP_PING {
+            return self._write_protobuf(id, protobuf::STATUS_OK, "", b"");
+        }
+
+        let Some(key) = self._parse_key(command.key) else {
+            let message: &str = "Key must be valid UTF-8";
+            return self._write_protobuf(id, protobuf::STATUS_INVALID, message, b"");
+        };
+        let key: &str = &key;
+
+        match command.op {
+            protobuf::OP_GET => {
+                let lookup: Lookup = match command.lock_ttl {
+                    Some(lock_ttl) => {
+                        // Waiting for the value may take a while, so answer the preceding commands first
+                        self.stream.flush()?;
+                        self.cache.get_or_lock(key, lock_ttl)?
+                    }
+                    None => self.cache.get(key)?,
+                };
+
+                match lookup {
+                    Lookup::Fresh(value) => {
+                        self._write_protobuf(id, protobuf::STATUS_OK, "", &value)
+                    }
+                    Lookup::Stale(value) => {
+                        self._write_protobuf(id, protobuf::STATUS_STALE, "", &value)
+                    }
+                    Lookup::Negative => {
+                        self._write_protobuf(id, protobuf::STATUS_NEGATIVE, "", b"")
+                    }
+                    Lookup::Missing => {
+                        self._write_protobuf(id, protobuf::STATUS_NOT_FOUND, "", b"")
+                    }
+                    Lookup::Recompute => {
+                        self._write_protobuf(id, protobuf::STATUS_RECOMPUTE, "", b"")
+                    }
+                }
+            }
+            protobuf::OP_SET => {
+                let value: Bytes = Bytes::from(command.value);
+                self.cache.put(key, value, command.ttl, command.soft_ttl)?;
+                self._write_protobuf(id, protobuf::STATUS_OK, "", b"")
+            }
+            protobuf::OP_SET_NULL => {
+                self.cache.put_negative(key, command.ttl)?;
+                self._write_protobuf(id, protobuf::STATUS_OK, "", b"")
+            }
+            protobuf::OP_DELETE => match self.cache.remove(key)? {
+                Some(value) => self._write_protobuf(id, protobuf::STATUS_OK, "", &value),
+                None => self._write_protobuf(id, protobuf::STATUS_NOT_FOUND, "", b""),
+            },
+            op => {
+                warn!("Unknown op {} from {}", op, self.address);
+                self._write_protobuf(id, protobuf::STATUS_INVALID, "Unknown op", b"")
+            }
+        }
+    }
+
+    /// Validate that a key token is UTF-8 text (see `protocol::as_key`), then place it in the selected
+    /// database and namespace. Returns `None` (and logs a warning) if it is not a valid key.
+    fn _parse_key<'a>(&self, token: &'a [u8]) -> Option<Cow<'a, str>> {
+        let maybe_key: Option<&str> = protocol::as_key(token);
+
+        if maybe_key.is_none() {
+            warn!("Command with an invalid key sent from {}", self.address);
+        }
+
+        maybe_key.map(|key| self._cache_key(key))
+    }
+
+    /// The given key of the selected namespace: `<namespace>:<key>`, or the key itself in the default
+    /// namespace. The permissions of the users are checked against it, whatever the selected database.
+    fn _namespaced<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        match &self.namespace {
+            Some(namespace) => Cow::Owned(format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// The key of the cache holding the given key of the selected database and namespace.
+    fn _cache_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        match self.database {
+            0 => self._namespaced(key),
+            index => Cow::Owned(format!(
+                "{}{}",
+                namespaces::database_prefix(index),
+                self._namespaced(key)
+            )),
+        }
+    }
+
+    /// The prefix of the keys of the selected database and namespace (empty in the default namespace of
+    /// database 0).
+    fn _namespace_prefix(&self) -> String {
+        let mut prefix: String = namespaces::database_prefix(self.database);
+        if let Some(namespace) = &self.namespace {
+            prefix.push_str(namespace);
+            prefix.push(NAMESPACE_SEPARATOR);
+        }
+        prefix
+    }
+
+    /// Keep the keys of the cache found by KEYS or SCAN which belong to the selected database, and which
+    /// the user (if any) may access.
+    fn _retain_accessible(&self, keys: &mut Vec<String>) {
+        keys.retain(|key| self._is_accessible(key));
+    }
+
+    /// Whether a key of the cache belongs to the selected database, and the user (if any) may access it.
+    fn _is_accessible(&self, key: &str) -> bool {
+        let user: Option<&(String, AclUser)> = self.user.and_then(|index| self.auth.user(index));
+        let (database, key) = namespaces::split_database(key);
+        database == self.database && user.is_none_or(|(_, user)| user.allows_key(key.as_bytes()))
+    }
+
+    /// Parse the index of a database, which has to be below the number of databases.
+    fn _parse_database(&self, token: &[u8]) -> Option<usize> {
+        parse_number(token)
+            .and_then(|index| usize::try_from(index).ok())
+            .filter(|index| *index < self.cache.databases())
+    }
+
+    /// Handle an unknown command.
+    fn _handle_unknown_command(&mut self, command: &[u8]) -> Result<(), ServerError> {
+        warn!(
+            "Unknown command {}{} from {}",
+            String::from_utf8_lossy(command),
+            self._request_id_suffix(),
+            self.address
+        );
+        if self.strict {
+            return self._write_strict_error(ErrorCode::UnknownCommand);
+        }
+        self._write_parts(&[b"Error: ", command, b" is unknown\n"])
+    }
+
+    /// Write a cached value followed by a line terminator back to the client, preceded by its `$<length>`
+    /// line in the bulk mode.
+    ///
+    /// The value is written straight from the shared buffer (no copy into an intermediate response string).
+    fn _write_value(&mut self, value: &[u8]) -> Result<(), ServerError> {
+        match self.bulk {
+            true => {
+                let length: Decimal = Decimal::unsigned(value.len() as u64);
+                self._write_parts(&[b"$", length.as_bytes(), b"\n", value, b"\n"])?
+            }
+            false => self._write_parts(&[value, b"\n"])?,
+        }
+        debug!("Value of {} bytes sent to {}", value.len(), self.address);
+        Ok(())
+    }
+
+    /// Write the number of the given values on a line back to the client, followed by every value (see
+    /// `_write_value`).
+    fn _write_values(&mut self, values: &[Bytes]) -> Result<(), ServerError> {
+        self._write_line(format_args!("{}", values.len()))?;
+        for value in values {
+            self._write_value(value)?;
+        }
+        Ok(())
+    }
+
+    /// Write the reply of a script back to the client: a value (see `_write_value`), `NULL`, an integer or a
+    /// status on a line, an error, or the number of the items of an array on a line followed by every item.
+    fn _write_reply(&mut self, reply: &Reply) -> Result<(), ServerError> {
+        match reply {
+            Reply::Nil => self._write_response("NULL\n"),
+            Reply::Integer(integer) => self._write_line(format_args!("{}", integer)),
+            Reply::Bulk(value) => self._write_value(value),
+            Reply::Status(status) => self._write_line(format_args!("{}", status)),
+            Reply::Error(message) => self._write_error(ErrorCode::ScriptError, message),
+            Reply::Array(items) => {
+                self._write_line(format_args!("{}", items.len()))?;
+                for item in items {
+                    self._write_reply(item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the number of the given stream entries on a line back to the client, followed by the ID of
+    /// every entry on a line, then its value (see `_write_value`).
+    fn _write_entries(&mut self, entries: &[(StreamId, Bytes)]) -> Result<(), ServerError> {
+        self._write_line(format_args!("{}", entries.len()))?;
+        for (id, value) in entries {
+            self._write_line(format_args!("{}", id))?;
+            self._write_value(value)?;
+        }
+        Ok(())
+    }
+
+    /// Write a value whose soft TTL has elapsed, flagged as `STALE`, back to the client.
+    fn _write_stale_value(&mut self, value: &[u8]) -> Result<(), ServerError> {
+        match self.bulk {
+            true => {
+                let length: Decimal = Decimal::unsigned(value.len() as u64);
+                self._write_parts(&[b"STALE $", length.as_bytes(), b"\n", value, b"\n"])?
+            }
+            false => self._write_parts(&[b"STALE ", value, b"\n"])?,
+        }
+        debug!(
+            "Stale value of {} bytes sent to {}",
+            value.len(),
+            self.address
+        );
+        Ok(())
+    }
+
+    /// Write a response made of several parts (e.g. a header, a value and a terminator) back to the client
+    /// with a single vectored write, so the parts are neither concatenated nor copied.
+    fn _write_parts<const N: usize>(&mut self, parts: &[&[u8]; N]) -> Result<(), ServerError> {
+        self._write_request_id()?;
+        let mut slices: [IoSlice; N] = parts.map(IoSlice::new);
+        write_all_vectored(&mut self.stream, &mut slices)?;
+        Ok(())
+    }
+
+    /// Write a response frame of the binary protocol, its value straight from the given buffer.
+    fn _write_frame(
+        &mut self,
+        status: u8,
+        flags: u32,
+        id: u32,
+        value: &[u8],
+    ) -> Result<(), ServerError> {
+        let header: [u8; binary::HEADER_SIZE] =
+            binary::response_header(status, flags, id, value.len());
+        self._write_parts(&[&header, value])
+    }
+
+    /// Write a `Reply` message, its value straight from the given buffer.
+    fn _write_protobuf(
+        &mut self,
+        id: u64,
+        status: u64,
+        error: &str,
+        value: &[u8],
+    ) -> Result<(), ServerError> {
+        let head: Vec<u8> = protobuf::encode_reply_head(id, status, error, value.len());
+        self._write_parts(&[&head, value])
+    }
+
+    /// Write a MessagePack response, its value straight from the given buffer.
+    fn _write_msgpack(
+        &mut self,
+        id: Option<&Value>,
+        status: &str,
+        error: Option<&str>,
+        value: Option<&[u8]>,
+    ) -> Result<(), ServerError> {
+        let fields: usize = 1 + usize::from(id.is_some()) + usize::from(error.is_some());
+        let mut head: Vec<u8> = Vec::with_capacity(64);
+        msgpack::encode_map_header(fields + usize::from(value.is_some()), &mut head);
+
+        if let Some(id) = id {
+            msgpack::encode_str("id", &mut head);
+            msgpack::encode(id, &mut head);
+        }
+        msgpack::encode_str("status", &mut head);
+        msgpack::encode_str(status, &mut head);
+        if let Some(error) = error {
+            msgpack::encode_str("error", &mut head);
+            msgpack::encode_str(error, &mut head);
+        }
+
+        // The value comes last, so that it is written without being copied into the head
+        let value: &[u8] = match value {
+            Some(value) => {
+                msgpack::encode_str("value", &mut head);
+                msgpack::encode_bin_header(value.len(), &mut head);
+                value
+            }
+            None => b"",
+        };
+        self._write_parts(&[&head, value])
+    }
+
+    /// Flush the report of a request that does not follow the protocol, then close the connection since the
+    /// end of the request is unknown.
+    fn _close_invalid(&mut self, reason: &'static str) -> Result<(), ServerError> {
+        self.stream.flush()?;
+        Err(ServerError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            reason,
+        )))
+    }
+
+    /// Write a response back to the client (buffered until the next flush).
+    fn _write_response(&mut self, response: &str) -> Result<(), ServerError> {
+        self._write_request_id()?;
+        self.stream.write_all(response.as_bytes())?;
+        debug!("Response sent to {}: {}", self.address, response.trim());
+        Ok(())
+    }
+
+    /// Write a response line back to the client, formatted straight into the buffer of the connection
+    /// rather than into a string of its own.
+    fn _write_line(&mut self, line: fmt::Arguments<'_>) -> Result<(), ServerError> {
+        self._write_request_id()?;
+        self.stream.write_fmt(line)?;
+        self.stream.write_all(b"\n")?;
+        debug!("Response sent to {}: {}", self.address, line);
+        Ok(())
+    }
+
+    /// Write an error: its code in strict mode (e.g. `ERR 1101 missing_key`), or else the given message
+    /// (e.g. `Error: Missing key`).
+    fn _write_error(&mut self, code: ErrorCode, message: &str) -> Result<(), ServerError> {
+        match self.strict {
+            true => self._write_strict_error(code),
+            false => {
+                self.answered_error = true;
+                self._write_line(format_args!("Error: {}", message))
+            }
+        }
+    }
+
+    /// Write the redirection of a command to the node of the cluster owning its keys, which follows the code
+    /// of the error in strict mode too (e.g. `ERR 1110 moved MOVED 3999 10.0.0.2:6379`).
+    fn _write_redirect(&mut self, redirect: &str) -> Result<(), ServerError> {
+        self.answered_error = true;
+        match self.strict {
+            true => self._write_line(format_args!("{} {}", ErrorCode::Moved, redirect)),
+            false => self._write_line(format_args!("Error: {}", redirect)),
+        }
+    }
+
+    /// Write the code of an error detected only in strict mode.
+    fn _write_strict_error(&mut self, code: ErrorCode) -> Result<(), ServerError> {
+        self.answered_error = true;
+        self._write_line(format_args!("{}", code))
+    }
+
+    /// Write the id of the text command being handled, if any, in front of its response (e.g. `@42 `).
+    fn _write_request_id(&mut self) -> Result<(), ServerError> {
+        if let Some(id) = &self.request_id {
+            self.stream.write_all(b"@")?;
+            self.stream.write_all(id)?;
+            self.stream.write_all(b" ")?;
+        }
+        Ok(())
+    }
+
+    /// The id of the text command being handled, if any, for the logs (e.g. ` @42`).
+    fn _request_id_suffix(&self) -> String {
+        match &self.request_id {
+            Some(id) => format!(" @{}", String::from_utf8_lossy(id)),
+            None => String::new(),
+        }
+    }
+}
+
+/// Encode a message published on a channel as pushed to a subscribed client: `MESSAGE <channel> <message>`.
+fn encode_text_message(message: &Message) -> Vec<u8> {
+    let mut encoded: Vec<u8> = format!("MESSAGE {} ", message.channel).into_bytes();
+    encoded.extend_from_slice(&message.payload);
+    encoded.push(b'\n');
+    encoded
+}
+
+/// Encode a command of the monitor feed as pushed to a monitoring client: its line (see
+/// `monitor::format_command`).
+fn encode_monitored_command(message: &Message) -> Vec<u8> {
+    let mut encoded: Vec<u8> = message.payload.to_vec();
+    encoded.push(b'\n');
+    encoded
+}
+
+/// Encode a message published on a channel as pushed to a subscribed client in the bulk mode, with the
+/// message as a bulk payload: `MESSAGE <channel> $<length>` and the message on the next line.
+fn encode_bulk_message(message: &Message) -> Vec<u8> {
+    let mut encoded: Vec<u8> =
+        format!("MESSAGE {} ${}\n", message.channel, message.payload.len()).into_bytes();
+    encoded.extend_from_slice(&message.payload);
+    encoded.push(b'\n');
+    encoded
+}
+
+/// The keys of the given text command, as sent by the client: the first argument of the commands on a key
+/// (every argument of MGET, EXISTS, TOUCH, the set combinations, PFCOUNT, PFMERGE and WATCH, the first two
+/// of RENAME, every other one of MSET, the one following the subcommand of MEMORY and OBJECT, the first half
+/// of the ones following STREAMS in XREAD, the number of keys given after the script of EVAL, and every
+/// argument but the timeout of BLPOP and BRPOP).
+fn text_command_keys<'a>(command: &[u8], mut arguments: Tokens<'a>) -> Vec<&'a [u8]> {
+    let first: Option<&[u8]> = arguments.next();
+    match command {
+        b"GET" | b"PUT" | b"SET" | b"SETNULL" | b"DEL" | b"RM" | b"UNDELETE" | b"HISTORY"
+        | b"GETVERSION" | b"EXPIRE" | b"EXPIREAT" | b"EXPIRETIME" | b"PERSIST" | b"TTL"
+        | b"TYPE" | b"INCR" | b"DECR" | b"INCRBY" | b"DECRBY" | b"APPEND" | b"STRLEN"
+        | b"SETBIT" | b"GETBIT" | b"BITCOUNT" | b"GETSET" | b"GETDEL" | b"HSET" | b"HGET"
+        | b"HDEL" | b"HGETALL" | b"LPUSH" | b"RPUSH" | b"LPOP" | b"RPOP" | b"LRANGE" | b"PFADD"
+        | b"SADD" | b"SREM" | b"SISMEMBER" | b"SMEMBERS" | b"ZADD" | b"ZINCRBY" | b"ZREM"
+        | b"ZSCORE" | b"ZRANGE" | b"ZRANGEBYSCORE" | b"XADD" | b"XRANGE" | b"XLEN"
+        | b"BF.RESERVE" | b"BF.ADD" | b"BF.EXISTS" | b"RATELIMIT" => first.into_iter().collect(),
+        b"MGET" | b"EXISTS" | b"TOUCH" | b"SINTER" | b"SUNION" | b"SDIFF" | b"PFCOUNT"
+        | b"PFMERGE" | b"WATCH" => first.into_iter().chain(arguments).collect(),
+        b"RENAME" | b"RENAMENX" => first.into_iter().chain(arguments.take(1)).collect(),
+        b"XREAD" => {
+            let streams: Vec<&[u8]> = first
+                .into_iter()
+                .chain(arguments)
+                .skip_while(|token| !token.eq_ignore_ascii_case(b"STREAMS"))
+                .skip(1)
+                .collect();
+            streams[..streams.len() / 2].to_vec()
+        }
+        b"MSET" => first.into_iter().chain(arguments).step_by(2).collect(),
+        b"MEMORY" | b"OBJECT" => arguments.take(1).collect(),
+        b"EVAL" => {
+            let count: usize = arguments.next().and_then(parse_number).unwrap_or(0) as usize;
+            arguments.take(count).collect()
+        }
+        b"BLPOP" | b"BRPOP" => {
+            let mut keys: Vec<&[u8]> = first.into_iter().chain(arguments).collect();
+            keys.pop();
+            keys
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Parse a numeric argument of a command (e.g. a TTL). Returns `None` if the token is not a number.
+pub(crate) fn parse_number(token: &[u8]) -> Option<u64> {
+    str::from_utf8(token).ok()?.parse().ok()
+}
+
+/// Parse a signed integer argument of a command (e.g. the increment of INCRBY). Returns `None` if the token
+/// is not an integer.
+pub(crate) fn parse_integer(token: &[u8]) -> Option<i64> {
+    str::from_utf8(token).ok()?.parse().ok()
+}
+
+/// Parse the timeout of a blocking command, in seconds which may have decimals (e.g. `0.5`). Returns `None` if
+/// the token is not a positive number or zero, which waits forever (`Some(None)`).
+pub(crate) fn parse_timeout(token: &[u8]) -> Option<Option<Duration>> {
+    let seconds: f64 = str::from_utf8(token).ok()?.parse().ok()?;
+    match seconds {
+        0.0 => Some(None),
+        seconds => Duration::try_from_secs_f64(seconds).ok().map(Some),
+    }
+}
+
+/// Wait for a value to be handed over to a client blocked by BLPOP or BRPOP (see
+/// `EmbeddedCache::list_pop_or_wait`), for at most the given time (or forever if `None`), or until the
+/// client has closed the given connection, if any, in which case the value handed over in the meantime is
+/// pushed back where it was popped from.
+///
+/// # Arguments
+/// * `keys` - The keys waited for, as given to `list_pop_or_wait`.
+/// * `left` - Whether the value is popped from the head of the list.
+///
+/// # Returns
+/// * The index of the key and the value popped from its list, or `None` if none was pushed in time.
+pub(crate) fn wait_popped(
+    cache: &EmbeddedCache,
+    waiter: &ListWaiter,
+    keys: &[&str],
+    left: bool,
+    timeout: Option<Duration>,
+    connection: Option<&ClientStream>,
+) -> CacheResult<Option<(usize, Bytes)>> {
+    let deadline: Option<Instant> = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    loop {
+        let remaining: Option<Duration> =
+            deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let wait: Duration =
+            remaining.map_or(BLOCKED_POLL_INTERVAL, |r| r.min(BLOCKED_POLL_INTERVAL));
+        if let Some(popped) = waiter.wait(wait)? {
+            return Ok(Some(popped));
+        }
+
+        let closed: bool = connection.is_some_and(ClientStream::is_closed);
+        if closed || remaining.is_some_and(|remaining| remaining <= wait) {
+            let popped: Option<(usize, Bytes)> = waiter.cancel()?;
+            return match (closed, popped) {
+                (true, Some((index, value))) => {
+                    cache.list_push(keys[index], &[value], left)?;
+                    Ok(None)
+                }
+                (_, popped) => Ok(popped),
+            };
+        }
+    }
+}
+
+/// Format a number of seconds in the largest unit dividing it, e.g. `15m` or `6h`.
+fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0 => "0s".to_string(),
+        s if s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Write all the given buffers to the writer, retrying on partial writes until every byte is written.
+pub(crate) fn write_all_vectored(
+    writer: &mut impl Write,
+    mut buffers: &mut [IoSlice<'_>],
+) -> io::Result<()> {
+    IoSlice::advance_slices(&mut buffers, 0); // Skip the leading empty buffers, if any
+
+    while !buffers.is_empty() {
+        match writer.write_vectored(buffers) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(n) => IoSlice::advance_slices(&mut buffers, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(users: &str) -> TextSession<Vec<u8>> {
+        let cache: Arc<EmbeddedCache> =
+            Arc::new(EmbeddedCache::with_sweeper(CacheFactory::new_cache(), None).unwrap());
+        let users: Vec<(String, AclUser)> = users
+            .split(';')
+            .filter_map(|user| user.split_once(':'))
+            .map(|(name, acl)| (name.to_string(), acl.parse().unwrap()))
+            .collect();
+        TextSession::new("test".to_string(), Vec::new(), cache)
+            .with_auth(Arc::new(Auth::new(None, users)))
+    }
+
+    fn send(session: &mut TextSession<Vec<u8>>, command: &str) -> String {
+        let mut pending: Vec<u8> = format!("{}\n", command).into_bytes();
+        session.handle_pending(&mut pending).unwrap();
+        String::from_utf8(std::mem::take(session.writer())).unwrap()
+    }
+
+    #[test]
+    fn flushes_need_access_to_every_key() {
+        let mut session = session("app:password=pw,commands=*,keys=app:*");
+        session
+            .cache()
+            .put("other:x", Bytes::from(&b"1"[..]), None, None)
+            .unwrap();
+        assert_eq!(send(&mut session, "AUTH app pw"), "OK\n");
+
+        for command in ["FLUSH", "FLUSH other:", "FLUSHNS other", "FLUSHALL"] {
+            assert!(
+                send(&mut session, command).starts_with("Error: NOPERM"),
+                "{}",
+                command
+            );
+        }
+        assert_eq!(send(&mut session, "SET app:y 2"), "OK\n");
+        assert_eq!(session.cache().exists(&["other:x"]).unwrap(), 1);
+    }
+
+    #[test]
+    fn background_flushes_keep_the_keys_written_since() {
+        let mut session = session("");
+        let keys: usize = 5000;
+        for i in 0..keys {
+            send(&mut session, &format!("SET p:{} old", i));
+        }
+        send(&mut session, "SET q:0 old");
+
+        assert_eq!(send(&mut session, "FLUSH p:"), format!("{}\n", keys));
+        for i in (0..keys).step_by(2) {
+            assert_eq!(send(&mut session, &format!("SET p:{} new", i)), "OK\n");
+        }
+        thread::sleep(Duration::from_millis(500));
+
+        for i in 0..keys {
+            let expected: &str = if i % 2 == 0 { "new\n" } else { "NULL\n" };
+            assert_eq!(
+                send(&mut session, &format!("GET p:{}", i)),
+                expected,
+                "p:{}",
+                i
+            );
+        }
+        assert_eq!(send(&mut session, "GET q:0"), "old\n");
+    }
+}
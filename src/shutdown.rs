@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::connection::ClientStream;
+
+/// How often the listeners, and the thread watching for signals, check whether the server is shutting down.
+pub(crate) const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the open connections are given to answer the requests in flight when the server shuts down.
+pub(crate) const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the draining connections are counted while waiting for them to close.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Set by the handler of `SIGTERM` and `SIGINT`, which may do nothing else (see `shutdown_on_signals`).
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+/// A handle stopping a running `CacheServer` (see `CacheServer::shutdown_handle`), which can be cloned and
+/// sent to the threads of an application embedding the server.
+///
+/// Once shut down, the listeners stop accepting connections, and the connections stop reading requests
+/// once the ones in flight are answered.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    requested: AtomicBool,     // Whether the server is shutting down
+    skip_snapshot: AtomicBool, // Whether the final snapshot is skipped (see `shutdown_without_snapshot`)
+    connections: Mutex<HashMap<u64, ClientStream>>, // The open client connections (clones of their streams)
+    next_id: AtomicU64,                             // The identifier of the next connection tracked
+}
+
+impl ShutdownHandle {
+    /// Shut the server down: `CacheServer::start` returns once the open connections are drained.
+    pub fn shutdown(&self) {
+        if !self.state.requested.swap(true, Ordering::SeqCst) {
+            info!("Shutting down...");
+        }
+    }
+
+    /// Shut the server down (see `shutdown`) without saving a final snapshot of the cache, e.g. on
+    /// `SHUTDOWN NOSAVE`. The cache is still flushed.
+    pub fn shutdown_without_snapshot(&self) {
+        self.state.skip_snapshot.store(true, Ordering::SeqCst);
+        self.shutdown();
+    }
+
+    /// Whether the server is shutting down.
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    /// Whether the final snapshot of the cache is skipped (see `shutdown_without_snapshot`).
+    pub(crate) fn skips_snapshot(&self) -> bool {
+        self.state.skip_snapshot.load(Ordering::SeqCst)
+    }
+
+    /// Shut the server down when the process receives `SIGTERM` or `SIGINT` (e.g. from `kill` or Ctrl-C),
+    /// rather than being killed with the requests in flight.
+    ///
+    /// # Errors
+    /// * If the signal handlers cannot be installed.
+    pub fn shutdown_on_signals(&self) -> io::Result<()> {
+        for signal in [libc::SIGTERM, libc::SIGINT] {
+            let handler: extern "C" fn(libc::c_int) = on_signal;
+            // SAFETY: the handler only stores into an atomic, which is async-signal-safe.
+            if unsafe { libc::signal(signal, handler as libc::sighandler_t) } == libc::SIG_ERR {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let handle: ShutdownHandle = self.clone();
+        thread::Builder::new()
+            .name("signals".to_string())
+            .spawn(move || {
+                while !SIGNALED.load(Ordering::SeqCst) {
+                    if handle.is_shutting_down() {
+                        return;
+                    }
+                    thread::sleep(SHUTDOWN_POLL_INTERVAL);
+                }
+                info!("Received a termination signal");
+                handle.shutdown();
+            })?;
+        Ok(())
+    }
+
+    /// Track an accepted connection until the returned guard is dropped, so that it can be drained.
+    ///
+    /// # Errors
+    /// * If the stream cannot be cloned.
+    pub(crate) fn track(&self, stream: &ClientStream) -> io::Result<TrackedConnection> {
+        let id: u64 = self.state.next_id.fetch_add(1, Ordering::Relaxed);
+        let clone: ClientStream = stream.try_clone()?;
+        if let Ok(mut connections) = self.state.connections.lock() {
+            connections.insert(id, clone);
+        }
+
+        // A connection accepted while draining is drained right away
+        if self.is_shutting_down() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+        Ok(TrackedConnection {
+            state: Arc::clone(&self.state),
+            id,
+        })
+    }
+
+    /// Stop reading requests from the open connections, then wait for them to answer the requests in
+    /// flight and close, for at most the given time.
+    ///
+    /// # Returns
+    /// * The number of connections still open once the time is up.
+    pub(crate) fn drain(&self, timeout: Duration) -> usize {
+        let deadline: Instant = Instant::now() + timeout;
+        let open: usize = self._open_connections(|stream| {
+            let _ = stream.shutdown(Shutdown::Read);
+        });
+        if open > 0 {
+            info!("Draining {} connection(s)...", open);
+        }
+
+        loop {
+            let open: usize = self.open_connections();
+            if open == 0 || Instant::now() >= deadline {
+                if open > 0 {
+                    warn!("Shutting down with {} connection(s) still open", open);
+                }
+                return open;
+            }
+            thread::sleep(DRAIN_INTERVAL);
+        }
+    }
+
+    /// The number of open connections, across all the listeners.
+    pub(crate) fn open_connections(&self) -> usize {
+        self._open_connections(|_| {})
+    }
+
+    /// Visit the open connections, returning their number.
+    fn _open_connections(&self, visit: impl Fn(&ClientStream)) -> usize {
+        match self.state.connections.lock() {
+            Ok(connections) => {
+                connections.values().for_each(visit);
+                connections.len()
+            }
+            Err(_) => 0,
+        }
+    }
+}
+
+/// Stops tracking a connection (see `ShutdownHandle::track`) when dropped, i.e. once the connection is closed.
+pub(crate) struct TrackedConnection {
+    state: Arc<ShutdownState>,
+    id: u64,
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.state.connections.lock() {
+            connections.remove(&self.id);
+        }
+    }
+}
+
+extern "C" fn on_signal(_: libc::c_int) {
+    SIGNALED.store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+use crate::{
+    error::{CacheError, CacheResult},
+    monitor::redact_secrets,
+    utils::current_walltime,
+};
+
+/// How long a command runs before it is logged, in microseconds, unless told otherwise.
+pub const DEFAULT_SLOWLOG_THRESHOLD_US: u64 = 10_000;
+
+/// The number of slow commands kept, unless told otherwise.
+pub const DEFAULT_SLOWLOG_MAX_LEN: usize = 128;
+
+/// The number of commands `SLOWLOG GET` answers with, unless told otherwise.
+pub const DEFAULT_SLOWLOG_GET_COUNT: usize = 10;
+
+/// The most arguments of a command kept in the log, the others being summed up in a last one.
+const MAX_ARGUMENTS: usize = 32;
+
+/// The longest argument kept in the log, in bytes, the rest being summed up.
+const MAX_ARGUMENT_SIZE: usize = 128;
+
+/// A command which took longer than the threshold of the slow log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowLogEntry {
+    pub id: u64,            // Increases with every command logged, even once the log is reset
+    pub timestamp: u64,     // When the command finished, in milliseconds since the epoch
+    pub duration: Duration, // How long the command took
+    pub client: String,     // The address of the client (IP:Port)
+    pub arguments: Vec<String>, // The command and its arguments, shortened if too many or too long
+}
+
+impl SlowLogEntry {
+    /// The command and its arguments joined by `,`, as a single token: the `%`, `,`, `:`, whitespace and
+    /// control characters of the arguments are percent-encoded (e.g. `SET,my%20key,ABC`).
+    pub fn command_line(&self) -> String {
+        let mut line: String = String::new();
+        for (index, argument) in self.arguments.iter().enumerate() {
+            if index > 0 {
+                line.push(',');
+            }
+            percent_encode(argument, &mut line);
+        }
+        line
+    }
+}
+
+/// Append text as part of a single token of the text protocol, percent-encoding its `%`, `,`, `:`,
+/// whitespace and control characters.
+pub(crate) fn percent_encode(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '%' | ',' | ':' => out.push_str(&format!("%{:02X}", c as u32)),
+            c if c.is_whitespace() || c.is_control() => {
+                let mut utf8: [u8; 4] = [0; 4];
+                for byte in c.encode_utf8(&mut utf8).bytes() {
+                    out.push_str(&format!("%{:02X}", byte));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+/// Keeps the last commands which took longer than a threshold, to diagnose the latency spikes (e.g. a large
+/// `MGET` or a `KEYS`), queried with `SLOWLOG GET`, `SLOWLOG LEN` and `SLOWLOG RESET`.
+///
+/// The log is bounded: once full, the oldest command is dropped for every new one.
+#[derive(Debug)]
+pub struct SlowLog {
+    entries: Mutex<VecDeque<SlowLogEntry>>, // The commands logged, the newest first
+    next_id: AtomicU64,                     // The id of the next command logged
+    threshold_us: AtomicU64, // How long a command runs before it is logged, in microseconds
+    max_len: AtomicUsize,    // The number of commands kept (0 disables the log)
+}
+
+impl Default for SlowLog {
+    fn default() -> SlowLog {
+        SlowLog::new(DEFAULT_SLOWLOG_THRESHOLD_US, DEFAULT_SLOWLOG_MAX_LEN)
+    }
+}
+
+impl SlowLog {
+    /// Create a new `SlowLog` keeping the last `max_len` commands which took longer than `threshold_us`
+    /// microseconds (every command if zero).
+    pub fn new(threshold_us: u64, max_len: usize) -> SlowLog {
+        SlowLog {
+            entries: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(0),
+            threshold_us: AtomicU64::new(threshold_us),
+            max_len: AtomicUsize::new(max_len),
+        }
+    }
+
+    /// How long a command runs before it is logged, in microseconds.
+    pub fn threshold_us(&self) -> u64 {
+        self.threshold_us.load(Ordering::Relaxed)
+    }
+
+    /// Change how long a command runs before it is logged (see `new`), e.g. with `CONFIG SET`.
+    pub fn set_threshold_us(&self, threshold_us: u64) {
+        self.threshold_us.store(threshold_us, Ordering::Relaxed);
+    }
+
+    /// The number of commands kept.
+    pub fn max_len(&self) -> usize {
+        self.max_len.load(Ordering::Relaxed)
+    }
+
+    /// Change the number of commands kept, e.g. with `CONFIG SET`, dropping the oldest ones beyond.
+    pub fn set_max_len(&self, max_len: usize) -> CacheResult<()> {
+        self.max_len.store(max_len, Ordering::Relaxed);
+        self.entries
+            .lock()
+            .map_err(CacheError::from)?
+            .truncate(max_len);
+        Ok(())
+    }
+
+    /// Log a command if it took longer than the threshold.
+    ///
+    /// # Arguments
+    /// * `elapsed` - How long the command took.
+    /// * `client` - The address of the client which sent the command.
+    /// * `command` - Lists the command and its arguments, only called if the command is logged. Its
+    ///   credentials are redacted (see `monitor::redact_secrets`).
+    pub fn record<'a>(
+        &self,
+        elapsed: Duration,
+        client: impl Display,
+        command: impl FnOnce() -> Vec<&'a [u8]>,
+    ) -> CacheResult<()> {
+        let max_len: usize = self.max_len();
+        if max_len == 0 || elapsed.as_micros() < self.threshold_us() as u128 {
+            return Ok(());
+        }
+
+        let mut arguments: Vec<&[u8]> = command();
+        redact_secrets(&mut arguments);
+        let entry: SlowLogEntry = SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: current_walltime(),
+            duration: elapsed,
+            client: client.to_string(),
+            arguments: shorten(arguments),
+        };
+        let mut entries: MutexGuard<VecDeque<SlowLogEntry>> =
+            self.entries.lock().map_err(CacheError::from)?;
+        entries.push_front(entry);
+        entries.truncate(max_len);
+        Ok(())
+    }
+
+    /// The last commands logged, the newest first.
+    ///
+    /// # Arguments
+    /// * `count` - The most commands returned.
+    pub fn get(&self, count: usize) -> CacheResult<Vec<SlowLogEntry>> {
+        let entries: MutexGuard<VecDeque<SlowLogEntry>> =
+            self.entries.lock().map_err(CacheError::from)?;
+        Ok(entries.iter().take(count).cloned().collect())
+    }
+
+    /// The number of commands logged.
+    pub fn len(&self) -> CacheResult<usize> {
+        Ok(self.entries.lock().map_err(CacheError::from)?.len())
+    }
+
+    /// Whether no command is logged.
+    pub fn is_empty(&self) -> CacheResult<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Drop every command logged.
+    pub fn reset(&self) -> CacheResult<()> {
+        self.entries.lock().map_err(CacheError::from)?.clear();
+        Ok(())
+    }
+}
+
+/// Keep at most `MAX_ARGUMENTS` arguments of at most `MAX_ARGUMENT_SIZE` bytes each, as Redis does, so
+/// that a huge `MSET` does not fill the memory of the log.
+fn shorten(arguments: Vec<&[u8]>) -> Vec<String> {
+    let total: usize = arguments.len();
+    let kept: usize = match total > MAX_ARGUMENTS {
+        true => MAX_ARGUMENTS - 1,
+        false => total,
+    };
+
+    let mut shortened: Vec<String> = arguments[..kept]
+        .iter()
+        .map(|argument| match argument.len() > MAX_ARGUMENT_SIZE {
+            true => format!(
+                "{}... ({} more bytes)",
+                String::from_utf8_lossy(&argument[..MAX_ARGUMENT_SIZE]),
+                argument.len() - MAX_ARGUMENT_SIZE
+            ),
+            false => String::from_utf8_lossy(argument).to_string(),
+        })
+        .collect();
+    if kept < total {
+        shortened.push(format!("... ({} more arguments)", total - kept));
+    }
+    shortened
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_the_credentials_of_the_commands() {
+        let log: SlowLog = SlowLog::new(0, 8);
+        log.record(Duration::from_millis(1), "127.0.0.1:1", || {
+            vec![b"AUTH", b"admin", b"apw"]
+        })
+        .unwrap();
+        log.record(Duration::from_millis(1), "127.0.0.1:1", || {
+            vec![b"GET", b"apw"]
+        })
+        .unwrap();
+
+        let entries: Vec<SlowLogEntry> = log.get(8).unwrap();
+        assert_eq!(entries[0].command_line(), "GET,apw");
+        assert_eq!(entries[1].command_line(), "AUTH,(redacted),(redacted)");
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+
+use crate::{
+    embedded::EmbeddedCache,
+    error::ServerError,
+    rdb::{self, ImportStats},
+    shutdown::{ShutdownHandle, SHUTDOWN_POLL_INTERVAL},
+    utils::current_walltime,
+};
+
+/// Point-in-time snapshots of the cache served, saved to a file on `SAVE`, in the background on `BGSAVE` or
+/// every so often, and loaded back when the server starts, so that a restart does not lose the data.
+///
+/// A snapshot is an RDB dump of every key with its value and expiration time (see `rdb::export`), taken
+/// while the writes go on. It is written to a temporary file next to the snapshot, synced to disk and then
+/// renamed over the snapshot, so that a crash in the middle of a save leaves the previous snapshot whole.
+/// One snapshot is saved at a time.
+pub(crate) struct Snapshots {
+    path: PathBuf,              // Where the snapshot is saved to and loaded from
+    interval: Option<Duration>, // How often a snapshot is saved in the background, if at all
+    saving: AtomicBool,         // Whether a snapshot is being saved
+    last_save: AtomicU64, // When the last snapshot was saved (walltime, in seconds), or 0 if never
+}
+
+impl Snapshots {
+    pub fn new(path: PathBuf, interval: Option<Duration>) -> Snapshots {
+        Snapshots {
+            path,
+            interval,
+            saving: AtomicBool::new(false),
+            last_save: AtomicU64::new(0),
+        }
+    }
+
+    /// Load the snapshot into the cache, replacing the values of the keys it already holds. The keys which
+    /// have expired since the snapshot was saved are left out.
+    ///
+    /// # Returns
+    /// * What was loaded, or `None` if there is no snapshot yet.
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the snapshot cannot be read, or is not an RDB dump.
+    /// * `ServerError::Cache` if a key cannot be put into the cache.
+    pub fn load(&self, cache: &EmbeddedCache) -> Result<Option<ImportStats>, ServerError> {
+        let file: File = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        rdb::import(BufReader::new(file), cache).map(Some)
+    }
+
+    /// Save a snapshot of the cache, unless another one is being saved.
+    ///
+    /// # Returns
+    /// * The number of keys saved, or `None` if another snapshot is being saved.
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the snapshot cannot be written.
+    /// * `ServerError::Cache` if the storage engine cannot be scanned for its keys.
+    pub fn save(&self, cache: &EmbeddedCache) -> Result<Option<usize>, ServerError> {
+        if self.saving.swap(true, Ordering::SeqCst) {
+            return Ok(None);
+        }
+        let result: Result<usize, ServerError> = self._write(cache);
+        self.saving.store(false, Ordering::SeqCst);
+        result.map(Some)
+    }
+
+    /// Save a snapshot of the cache on a thread of its own, unless another one is being saved. The failures
+    /// are logged.
+    ///
+    /// # Returns
+    /// * Whether the snapshot is being saved (`false` if another one is).
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the thread cannot be spawned.
+    pub fn save_in_background(
+        self: &Arc<Self>,
+        cache: &Arc<EmbeddedCache>,
+    ) -> Result<bool, ServerError> {
+        if self.saving.swap(true, Ordering::SeqCst) {
+            return Ok(false);
+        }
+
+        let snapshots: Arc<Snapshots> = Arc::clone(self);
+        let cache: Arc<EmbeddedCache> = Arc::clone(cache);
+        let spawned = thread::Builder::new()
+            .name("snapshot".to_string())
+            .spawn(move || {
+                if let Err(e) = snapshots._write(&cache) {
+                    error!("Failed to save a snapshot: {}", e);
+                }
+                snapshots.saving.store(false, Ordering::SeqCst);
+            });
+        if let Err(e) = spawned {
+            self.saving.store(false, Ordering::SeqCst);
+            return Err(e.into());
+        }
+        Ok(true)
+    }
+
+    /// Save a snapshot of the cache every `interval` (if any) on a thread of its own, until the server shuts
+    /// down. A save is skipped while another one is in progress.
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the thread cannot be spawned.
+    pub fn spawn_timer(
+        self: &Arc<Self>,
+        cache: &Arc<EmbeddedCache>,
+        shutdown: ShutdownHandle,
+    ) -> Result<(), ServerError> {
+        let Some(interval) = self.interval else {
+            return Ok(());
+        };
+
+        let snapshots: Arc<Snapshots> = Arc::clone(self);
+        let cache: Arc<EmbeddedCache> = Arc::clone(cache);
+        thread::Builder::new()
+            .name("snapshot-timer".to_string())
+            .spawn(move || {
+                let mut last_save: Instant = Instant::now();
+                while !shutdown.is_shutting_down() {
+                    thread::sleep(SHUTDOWN_POLL_INTERVAL.min(interval));
+                    if last_save.elapsed() < interval {
+                        continue;
+                    }
+                    if let Err(e) = snapshots.save(&cache) {
+                        error!("Failed to save a snapshot: {}", e);
+                    }
+                    last_save = Instant::now();
+                }
+            })?;
+        Ok(())
+    }
+
+    /// When the last snapshot was saved (in seconds since the epoch), or 0 if none was since the server
+    /// started.
+    pub fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::Relaxed)
+    }
+
+    /// The path the snapshot is saved to and loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write a snapshot of the cache to a temporary file, then rename it over the snapshot once on disk.
+    fn _write(&self, cache: &EmbeddedCache) -> Result<usize, ServerError> {
+        let started: Instant = Instant::now();
+        let mut temporary: OsString = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary: PathBuf = PathBuf::from(temporary);
+
+        let written = || -> Result<usize, ServerError> {
+            let mut writer: BufWriter<File> = BufWriter::new(File::create(&temporary)?);
+            let keys: usize = rdb::export(cache, &mut writer)?;
+            writer
+                .into_inner()
+                .map_err(|e| e.into_error())?
+                .sync_all()?;
+            fs::rename(&temporary, &self.path)?;
+            Ok(keys)
+        };
+        let keys: usize = match written() {
+            Ok(keys) => keys,
+            Err(e) => {
+                let _ = fs::remove_file(&temporary);
+                return Err(e);
+            }
+        };
+
+        // The rename only lasts once the directory is on disk too
+        if let Some(directory) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let _ = File::open(directory).and_then(|directory| directory.sync_all());
+        }
+        self.last_save
+            .store(current_walltime() / 1000, Ordering::Relaxed);
+        info!(
+            "Saved a snapshot of {} key(s) to {} in {:?}",
+            keys,
+            self.path.display(),
+            started.elapsed()
+        );
+        Ok(keys)
+    }
+}
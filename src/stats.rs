@@ -0,0 +1,187 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The upper bounds of the buckets of the command latencies, in microseconds (the last bucket holding the
+/// slower commands).
+pub const LATENCY_BUCKETS: [u64; 12] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 100_000, 500_000, 1_000_000,
+];
+
+/// The protocols whose commands are counted, each with a histogram of their latencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandProtocol {
+    Text, // The text protocol, also over WebSocket
+    Resp,
+    Memcached,
+    Http,
+    Udp,
+}
+
+impl CommandProtocol {
+    /// Every protocol, in the order of their histograms.
+    pub const ALL: [CommandProtocol; 5] = [
+        CommandProtocol::Text,
+        CommandProtocol::Resp,
+        CommandProtocol::Memcached,
+        CommandProtocol::Http,
+        CommandProtocol::Udp,
+    ];
+
+    /// The name of the protocol, e.g. for the labels of metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandProtocol::Text => "text",
+            CommandProtocol::Resp => "resp",
+            CommandProtocol::Memcached => "memcached",
+            CommandProtocol::Http => "http",
+            CommandProtocol::Udp => "udp",
+        }
+    }
+}
+
+/// How long the commands of a protocol took, as counts of commands per bucket of `LATENCY_BUCKETS`.
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1], // The commands per bucket, the slower ones last
+    sum_micros: AtomicU64,                           // The total time taken by the commands
+}
+
+impl LatencyHistogram {
+    /// The number of commands of every bucket of `LATENCY_BUCKETS`, the commands slower than the last
+    /// bound last.
+    pub fn buckets(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// The number of commands.
+    pub fn count(&self) -> u64 {
+        self.buckets().iter().sum()
+    }
+
+    /// The total time taken by the commands.
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+
+    fn _record(&self, elapsed: Duration) {
+        let micros: u64 = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket: usize = LATENCY_BUCKETS.partition_point(|bound| *bound < micros);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+}
+
+/// The counters of a served cache, reported by the `INFO` command (see `EmbeddedCache::info`): how the
+/// lookups went, how many entries expired, and how busy the server is.
+#[derive(Debug)]
+pub struct CacheStats {
+    started: Instant,                 // When the cache was created
+    hits: AtomicU64,                  // The lookups which found a value (fresh or stale)
+    misses: AtomicU64,                // The lookups which found no value (or a negative entry)
+    expired: AtomicU64,               // The expired entries deleted by the sweeper
+    oversized_writes: AtomicU64,      // The writes rejected for a key or a value over its max size
+    connected_clients: AtomicUsize,   // The client connections open, across all the listeners
+    latencies: [LatencyHistogram; 5], // The latencies of the commands, per protocol (see `CommandProtocol`)
+}
+
+impl Default for CacheStats {
+    fn default() -> CacheStats {
+        CacheStats {
+            started: Instant::now(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            oversized_writes: AtomicU64::new(0),
+            connected_clients: AtomicUsize::new(0),
+            latencies: Default::default(),
+        }
+    }
+}
+
+impl CacheStats {
+    /// How long ago the cache was created.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The number of lookups which found a value, fresh or stale.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of lookups which found no value, including those finding a negative entry.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The number of expired entries deleted in the background (see `SweeperOptions`). The entries found
+    /// expired when accessed are not counted.
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// The number of writes rejected for a key or a value longer than its max size (see
+    /// `EmbeddedCache::with_max_sizes`).
+    pub fn oversized_writes(&self) -> u64 {
+        self.oversized_writes.load(Ordering::Relaxed)
+    }
+
+    /// The number of client connections open, across all the listeners.
+    pub fn connected_clients(&self) -> usize {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    /// The number of commands executed, across all the protocols.
+    pub fn commands_processed(&self) -> u64 {
+        self.latencies.iter().map(LatencyHistogram::count).sum()
+    }
+
+    /// How long the commands of the given protocol took.
+    pub fn latencies(&self, protocol: CommandProtocol) -> &LatencyHistogram {
+        &self.latencies[protocol as usize]
+    }
+
+    pub(crate) fn record_lookup(&self, hit: bool) {
+        let counter: &AtomicU64 = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_expired(&self, expired: usize) {
+        self.expired.fetch_add(expired as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_oversized_write(&self) {
+        self.oversized_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_command(&self, protocol: CommandProtocol, elapsed: Duration) {
+        self.latencies[protocol as usize]._record(elapsed);
+    }
+
+    /// Count a client connection as open until the returned guard is dropped.
+    pub(crate) fn client_connected(self: &Arc<CacheStats>) -> ConnectedClient {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        ConnectedClient {
+            stats: Arc::clone(self),
+        }
+    }
+}
+
+/// Counts a client connection as open (see `CacheStats::client_connected`) until dropped.
+pub(crate) struct ConnectedClient {
+    stats: Arc<CacheStats>,
+}
+
+impl Drop for ConnectedClient {
+    fn drop(&mut self) {
+        self.stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{embedded::EmbeddedCache, error::CacheResult};
+
+/// The commands queued by a client between `MULTI` and `EXEC`, which are then executed in a row while no
+/// other client connection executes any command (see `EmbeddedCache::transaction_lock`).
+///
+/// A command which cannot be queued (e.g. `SUBSCRIBE`) fails the transaction, whose `EXEC` then executes
+/// none of its commands. The commands failing when executed do not stop the others.
+pub(crate) struct Transaction<C> {
+    commands: Vec<C>, // The commands queued, in the order they were sent
+    failed: bool,     // Whether a command could not be queued
+}
+
+impl<C> Transaction<C> {
+    /// The most commands a transaction may queue, beyond which it fails (and its commands are dropped).
+    pub const MAX_COMMANDS: usize = 10_000;
+
+    pub fn new() -> Transaction<C> {
+        Transaction {
+            commands: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// Queue a command, or fail the transaction if it has too many already.
+    ///
+    /// # Returns
+    /// * Whether the command was queued.
+    pub fn queue(&mut self, command: C) -> bool {
+        if self.commands.len() >= Self::MAX_COMMANDS {
+            self.fail();
+            return false;
+        }
+        self.commands.push(command);
+        true
+    }
+
+    /// Fail the transaction, e.g. because a command could not be queued.
+    pub fn fail(&mut self) {
+        self.failed = true;
+        self.commands.clear();
+    }
+
+    /// Whether the transaction has failed, and executes nothing.
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    /// The commands queued, in the order they were sent.
+    pub fn into_commands(self) -> Vec<C> {
+        self.commands
+    }
+}
+
+/// The keys watched by a client with `WATCH`: its next transaction is aborted if any of them has changed
+/// since (see `EmbeddedCache::watch`). The keys are unwatched once dropped.
+pub(crate) struct Watch {
+    cache: Arc<EmbeddedCache>,
+    keys: Vec<String>, // The keys watched, within the cache (i.e. with their database and namespace)
+    changed: Arc<AtomicBool>, // Raised once any of the keys changes
+}
+
+impl Watch {
+    pub fn new(cache: Arc<EmbeddedCache>) -> Watch {
+        Watch {
+            cache,
+            keys: Vec::new(),
+            changed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Watch the given key of the cache, if not watched yet.
+    pub fn watch(&mut self, key: &str) -> CacheResult<()> {
+        if !self.keys.iter().any(|watched| watched == key) {
+            self.cache.watch(key, &self.changed)?;
+            self.keys.push(key.to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether any of the keys has changed since it was watched. Only reliable while the transactions are
+    /// isolated (see `EmbeddedCache::transaction_lock`), as a key may change anytime otherwise.
+    pub fn is_changed(&self) -> bool {
+        self.changed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let _ = self.cache.unwatch(&self.keys, &self.changed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Bytes, CacheFactory};
+
+    fn cache() -> Arc<EmbeddedCache> {
+        Arc::new(EmbeddedCache::with_sweeper(CacheFactory::new_cache(), None).unwrap())
+    }
+
+    fn put(cache: &EmbeddedCache, key: &str) {
+        cache
+            .put(key, Bytes::from(&b"value"[..]), None, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn queues_commands_in_order() {
+        let mut transaction: Transaction<u32> = Transaction::new();
+        assert!(transaction.queue(1));
+        assert!(transaction.queue(2));
+        assert!(!transaction.is_failed());
+        assert_eq!(transaction.into_commands(), vec![1, 2]);
+    }
+
+    #[test]
+    fn failing_drops_the_commands() {
+        let mut transaction: Transaction<u32> = Transaction::new();
+        transaction.queue(1);
+        transaction.fail();
+        assert!(transaction.is_failed());
+        assert!(transaction.into_commands().is_empty());
+    }
+
+    #[test]
+    fn fails_beyond_the_max_commands() {
+        let mut transaction: Transaction<usize> = Transaction::new();
+        for command in 0..Transaction::<usize>::MAX_COMMANDS {
+            assert!(transaction.queue(command));
+        }
+        assert!(!transaction.queue(0));
+        assert!(transaction.is_failed());
+        assert!(transaction.into_commands().is_empty());
+    }
+
+    #[test]
+    fn watch_sees_writes_to_its_keys_only() {
+        let cache: Arc<EmbeddedCache> = cache();
+        let mut watch: Watch = Watch::new(Arc::clone(&cache));
+        watch.watch("a").unwrap();
+        watch.watch("a").unwrap();
+
+        put(&cache, "b");
+        assert!(!watch.is_changed());
+        put(&cache, "a");
+        assert!(watch.is_changed());
+    }
+
+    #[test]
+    fn watch_sees_removals_and_expiry_changes() {
+        let cache: Arc<EmbeddedCache> = cache();
+        put(&cache, "a");
+        put(&cache, "b");
+
+        let mut watch: Watch = Watch::new(Arc::clone(&cache));
+        watch.watch("a").unwrap();
+        cache.remove("a").unwrap();
+        assert!(watch.is_changed());
+
+        let mut watch: Watch = Watch::new(Arc::clone(&cache));
+        watch.watch("b").unwrap();
+        cache.expire("b", Some(100)).unwrap();
+        assert!(watch.is_changed());
+    }
+
+    #[test]
+    fn dropped_watches_are_not_raised() {
+        let cache: Arc<EmbeddedCache> = cache();
+        let mut watch: Watch = Watch::new(Arc::clone(&cache));
+        watch.watch("a").unwrap();
+        let changed: Arc<AtomicBool> = Arc::clone(&watch.changed);
+        drop(watch);
+
+        put(&cache, "a");
+        assert!(!changed.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, log, warn};
+
+use crate::{
+    embedded::{EmbeddedCache, Lookup},
+    error::ServerError,
+    ipfilter::IpFilter,
+    protocol::{self, Tokens},
+    server::parse_number,
+    shutdown::{ShutdownHandle, SHUTDOWN_POLL_INTERVAL},
+    stats::CommandProtocol,
+    Bytes,
+};
+
+/// The size of the header in front of the requests and responses (as in memcached's UDP mode).
+const HEADER_SIZE: usize = 8;
+
+/// The largest datagram sent or accepted, so that it fits in a single IP packet on common networks (no
+/// fragmentation, which would make the loss of a datagram more likely).
+const MAX_DATAGRAM_SIZE: usize = 1400;
+
+/// Serves single-datagram `GET` and `SET` commands over UDP, for the clients that favor latency over
+/// reliability: there is no connection to set up, but a request or its response may be lost, so clients
+/// retry (or fall back to TCP) after a timeout.
+///
+/// A datagram is an 8-byte header followed by a command of the text protocol (`GET x` or `SET x ABC 60`),
+/// and is answered by a datagram with the same header followed by the response of the text protocol. The
+/// header holds (big-endian) the request id chosen by the client, the sequence number (0) and count (1)
+/// of the datagrams of the message, and 2 reserved bytes, like memcached's UDP frame header. Values which
+/// do not fit in a datagram are answered with an error, for the client to get them over TCP.
+pub(crate) struct UdpServer {
+    socket: UdpSocket, // The socket receiving the requests and sending the responses
+    cache: Arc<EmbeddedCache>, // A reference to the cache instance shared across all handlers
+    shutdown: ShutdownHandle, // Stops serving the requests once the server shuts down
+    ip_filter: Arc<IpFilter>, // The addresses the requests may come from, the others being dropped
+}
+
+impl UdpServer {
+    pub fn new(
+        socket: UdpSocket,
+        cache: Arc<EmbeddedCache>,
+        shutdown: ShutdownHandle,
+        ip_filter: Arc<IpFilter>,
+    ) -> UdpServer {
+        UdpServer {
+            socket,
+            cache,
+            shutdown,
+            ip_filter,
+        }
+    }
+
+    /// Serve the requests on the given number of threads, each receiving from the same socket.
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the socket cannot be shared between the threads.
+    pub fn spawn(self, threads: usize) -> Result<(), ServerError> {
+        // Wake up regularly to find out whether the server is shutting down
+        self.socket.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+
+        for _ in 1..threads {
+            let server: UdpServer = UdpServer::new(
+                self.socket.try_clone()?,
+                Arc::clone(&self.cache),
+                self.shutdown.clone(),
+                Arc::clone(&self.ip_filter),
+            );
+            thread::spawn(move || server.serve());
+        }
+        thread::spawn(move || self.serve());
+        Ok(())
+    }
+
+    fn serve(self) {
+        // One more byte than accepted, to tell the datagrams too large from those just fitting
+        let mut request: [u8; MAX_DATAGRAM_SIZE + 1] = [0; MAX_DATAGRAM_SIZE + 1];
+        let mut response: Vec<u8> = Vec::with_capacity(MAX_DATAGRAM_SIZE);
+
+        while !self.shutdown.is_shutting_down() {
+            let (length, client) = match self.socket.recv_from(&mut request) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    error!("Failed to receive a UDP datagram: {}", e);
+                    continue;
+                }
+            };
+
+            // Not answered, as any connection of a denied address would be dropped
+            if !self.ip_filter.permits(client.ip()) {
+                debug!("Denied address, dropping the UDP request from {}", client);
+                continue;
+            }
+
+            response.clear();
+            if let Err(e) = self._handle_datagram(&request[..length], client, &mut response) {
+                log!(
+                    e.log_level(),
+                    "Failed to execute the UDP command from {}: {}",
+                    client,
+                    e
+                );
+                response.truncate(HEADER_SIZE);
+                response.extend_from_slice(format!("Error: {}\n", e).as_bytes());
+            }
+            if response.is_empty() {
+                continue;
+            }
+
+            if let Err(e) = self.socket.send_to(&response, client) {
+                warn!("Failed to answer {} over UDP: {}", client, e);
+            }
+        }
+    }
+
+    /// Execute the command of a datagram, writing the datagram answering it (if any) into `response`.
+    fn _handle_datagram(
+        &self,
+        datagram: &[u8],
+        client: SocketAddr,
+        response: &mut Vec<u8>,
+    ) -> Result<(), ServerError> {
+        let Some((header, command)) = datagram.split_first_chunk::<HEADER_SIZE>() else {
+            debug!("Dropping a truncated UDP datagram from {}", client);
+            return Ok(());
+        };
+
+        // The response is a single datagram with the request id of the client
+        response.extend_from_slice(&header[..2]);
+        response.extend_from_slice(&[0, 0, 0, 1, 0, 0]);
+        if header[2..6] != [0, 0, 0, 1] || datagram.len() > MAX_DATAGRAM_SIZE {
+            return write_response(response, "Error: Requests must fit in a single datagram\n");
+        }
+
+        let started: Instant = Instant::now();
+        let mut parts: Tokens = Tokens::new(command);
+        let result: Result<(), ServerError> = match parts.next() {
+            Some(b"GET") => self._handle_get_command(parts, response),
+            Some(b"SET") => self._handle_set_command(parts, response),
+            _ => write_response(response, "Error: Unknown command\n"),
+        };
+        let elapsed: Duration = started.elapsed();
+        let failed: bool = result.is_err() || response[HEADER_SIZE..].starts_with(b"Error");
+        self.cache
+            .record_command(CommandProtocol::Udp, elapsed, client, None, failed, || {
+                Tokens::new(command).collect()
+            });
+        result
+    }
+
+    fn _handle_get_command(
+        &self,
+        mut parts: Tokens,
+        response: &mut Vec<u8>,
+    ) -> Result<(), ServerError> {
+        let Some(key) = parts.next() else {
+            return write_response(response, "Error: Missing key\n");
+        };
+        let Some(key) = protocol::as_key(key) else {
+            return write_response(response, "Error: Key must be valid UTF-8\n");
+        };
+
+        let (prefix, value): (&[u8], Bytes) = match self.cache.get(key)? {
+            Lookup::Fresh(value) => (b"", value),
+            Lookup::Stale(value) => (b"STALE ", value),
+            Lookup::Negative => return write_response(response, "NEGATIVE\n"),
+            Lookup::Missing | Lookup::Recompute => return write_response(response, "NULL\n"),
+        };
+        if response.len() + prefix.len() + value.len() + 1 > MAX_DATAGRAM_SIZE {
+            return write_response(response, "Error: Value too large for UDP\n");
+        }
+
+        response.extend_from_slice(prefix);
+        response.extend_from_slice(&value);
+        response.push(b'\n');
+        Ok(())
+    }
+
+    fn _handle_set_command(
+        &self,
+        mut parts: Tokens,
+        response: &mut Vec<u8>,
+    ) -> Result<(), ServerError> {
+        let (Some(key), Some(value)) = (parts.next(), parts.next()) else {
+            return write_response(response, "Error: Missing key or value\n");
+        };
+        let Some(key) = protocol::as_key(key) else {
+            return write_response(response, "Error: Key must be valid UTF-8\n");
+        };
+
+        let ttl: Option<u64> = match parts.next() {
+            Some(ttl) => match parse_number(ttl) {
+                Some(ttl) => Some(ttl),
+                None => return write_response(response, "Error: Invalid TTL\n"),
+            },
+            None => None,
+        };
+        self.cache.put(key, Bytes::from(value), ttl, None)?;
+        write_response(response, "OK\n")
+    }
+}
+
+fn write_response(response: &mut Vec<u8>, text: &str) -> Result<(), ServerError> {
+    response.extend_from_slice(text.as_bytes());
+    Ok(())
+}
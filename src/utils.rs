@@ -0,0 +1,249 @@
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::LazyLock,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The monotonic time instant when the program started. Actually, it keeps the time when this constant is first accessed.
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// The alphabet of base64, mapping each 6 bits of the encoded data to a character.
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+thread_local! {
+    /// The state of the random number generator of the thread, seeded from the random keys of the
+    /// standard library (which are different for every thread).
+    static RANDOM_STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+
+    /// How long the thread has been blocked waiting for other clients (e.g. by `BLPOP`), in total.
+    static BLOCKED_TIME: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Account for the time the current thread has been blocked waiting for other clients since the given
+/// instant, which is not spent executing commands (see `blocked_time`).
+pub fn record_blocked(since: Instant) {
+    BLOCKED_TIME.with(|blocked| blocked.set(blocked.get() + since.elapsed()));
+}
+
+/// How long the current thread has been blocked waiting for other clients in total, e.g. so that the time
+/// a connection waits with `BLPOP` does not count towards the timeout of its commands.
+pub fn blocked_time() -> Duration {
+    BLOCKED_TIME.with(Cell::get)
+}
+
+/// Returns the current monotonic time (never goes backwards) in milliseconds since the program started.
+///
+/// It is used to calculate the expiration time of cache entries. Since it is monotonic, it is not affected
+/// by system time changes.
+#[inline]
+pub fn current_monotime() -> u64 {
+    EPOCH.elapsed().as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Returns the current wall-clock time in milliseconds since the Unix epoch.
+///
+/// Unlike the monotonic time, it is meaningful across process restarts, so it is used for expiration times
+/// that are persisted.
+#[inline]
+pub fn current_walltime() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
+/// The time (in milliseconds) at which something given a TTL (in seconds) at the given time expires. A TTL
+/// too long to be represented expires at `u64::MAX`, i.e. never in practice, rather than overflowing.
+#[inline]
+pub fn expires_after(now: u64, ttl: u64) -> u64 {
+    now.saturating_add(ttl.saturating_mul(1000))
+}
+
+/// Converts a monotonic expiration time (see `current_monotime`) into a wall-clock one.
+pub fn monotime_to_walltime(monotime: u64) -> u64 {
+    current_walltime().saturating_add(monotime.saturating_sub(current_monotime()))
+}
+
+/// Converts a wall-clock expiration time (see `current_walltime`) into a monotonic one. Times in the past
+/// are mapped to the current monotonic time, i.e. they are already expired.
+pub fn walltime_to_monotime(walltime: u64) -> u64 {
+    current_monotime().saturating_add(walltime.saturating_sub(current_walltime()))
+}
+
+/// Returns a pseudo-random number (xorshift64*). Fast, but not suitable for anything security related.
+pub fn random_u64() -> u64 {
+    RANDOM_STATE.with(|state| {
+        let mut x: u64 = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+/// Encode the given data in base64 (with padding).
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut encoded: String = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes: [u8; 3] = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits: u32 =
+            (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => {
+                    encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char)
+                }
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+/// Decode the given base64 text (with padding, or without). Returns `None` if it is not valid base64.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text: &[u8] = text.trim_end_matches('=').as_bytes();
+    if text.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut decoded: Vec<u8> = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.chunks(4) {
+        let mut bits: u32 = 0;
+        for (i, &byte) in chunk.iter().enumerate() {
+            let sextet: usize = BASE64_ALPHABET.iter().position(|&c| c == byte)?;
+            bits |= (sextet as u32) << (18 - 6 * i);
+        }
+        decoded.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(decoded)
+}
+
+/// An integer written in decimal into a buffer of its own, e.g. for the length in front of a value to be
+/// written along with the value (see `write_all_vectored`) without allocating a string.
+pub struct Decimal {
+    digits: [u8; 20], // Room for the longest integer, `i64::MIN` (or `u64::MAX`), the digits at the end
+    start: usize,     // The index of the first digit (or of the sign)
+}
+
+impl Decimal {
+    pub fn new(value: i64) -> Decimal {
+        let mut decimal: Decimal = Decimal::unsigned(value.unsigned_abs());
+        if value < 0 {
+            decimal.start -= 1;
+            decimal.digits[decimal.start] = b'-';
+        }
+        decimal
+    }
+
+    pub fn unsigned(mut value: u64) -> Decimal {
+        let mut decimal: Decimal = Decimal {
+            digits: [0; 20],
+            start: 20,
+        };
+        loop {
+            decimal.start -= 1;
+            decimal.digits[decimal.start] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                return decimal;
+            }
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.digits[self.start..]
+    }
+}
+
+/// Compare two byte strings in a time independent of where they differ, so that secrets (e.g. tokens or
+/// passwords) cannot be guessed by timing the comparisons.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Whether the given text matches a glob pattern, where `*` matches any sequence of bytes, `?` any single
+/// byte, and a class such as `[abc]`, `[a-z]` or `[^0-9]` any single byte it holds (or, negated with `^`
+/// or `!`, does not hold). A `\` matches the following byte literally, e.g. `\*` a `*`.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None; // The position after the last `*`, and its match end
+
+    while t < text.len() {
+        // The length of the pattern matching the next byte, if it does
+        let step: Option<usize> = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match match_class(&pattern[p..], text[t]) {
+                Some((matched, len)) => matched.then_some(len),
+                None => (text[t] == b'[').then_some(1), // Not closed, so a literal `[`
+            },
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(2),
+            Some(&c) => (c == text[t]).then_some(1),
+            None => None,
+        };
+
+        match step {
+            Some(len) => {
+                p += len;
+                t += 1;
+            }
+            None => match backtrack {
+                // Let the last `*` match one more byte
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Match a byte against the class opening the given pattern (e.g. `[abc]`, `[a-z]` or `[^0-9]`).
+///
+/// # Returns
+/// * Whether the byte matches, along with the length of the class, or `None` if the class is not closed.
+fn match_class(pattern: &[u8], byte: u8) -> Option<(bool, usize)> {
+    let negated: bool = matches!(pattern.get(1), Some(b'^' | b'!'));
+    let start: usize = if negated { 2 } else { 1 };
+    let mut matched: bool = false;
+
+    let mut i: usize = start;
+    while i < pattern.len() {
+        // A `]` right at the start is a member of the class, e.g. in `[]x]`
+        if pattern[i] == b']' && i > start {
+            return Some((matched != negated, i + 1));
+        }
+        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+            i += 1;
+        }
+
+        let low: u8 = pattern[i];
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some(b'-'), Some(&high)) if high != b']' => {
+                matched |= (low.min(high)..=low.max(high)).contains(&byte);
+                i += 3;
+            }
+            _ => {
+                matched |= low == byte;
+                i += 1;
+            }
+        }
+    }
+    None
+}
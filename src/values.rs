@@ -0,0 +1,1406 @@
+use std::{
+    any::Any,
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, mem,
+    ops::RangeInclusive,
+    str,
+};
+
+use crate::{
+    cache::{Bytes, NativeValue},
+    error::{CacheError, CacheResult},
+};
+
+/// Heads the values of the data types other than strings, followed by a byte telling the type. A string
+/// which starts with it is stored behind the header too (see `STRING_TYPE`), so that no string can be
+/// mistaken for a value of another type.
+const TYPE_MAGIC: [u8; 4] = [0xC1, b'M', b'R', b'C'];
+
+/// The byte following `TYPE_MAGIC` in the values of the strings which start with `TYPE_MAGIC` themselves.
+const STRING_TYPE: u8 = b't';
+
+/// The byte following `TYPE_MAGIC` in the values of hashes.
+const HASH_TYPE: u8 = b'h';
+
+/// The byte following `TYPE_MAGIC` in the values of lists.
+const LIST_TYPE: u8 = b'l';
+
+/// The byte following `TYPE_MAGIC` in the values of sets.
+const SET_TYPE: u8 = b's';
+
+/// The byte following `TYPE_MAGIC` in the values of sorted sets.
+const SORTED_SET_TYPE: u8 = b'z';
+
+/// The byte following `TYPE_MAGIC` in the values of HyperLogLogs.
+const HYPERLOGLOG_TYPE: u8 = b'p';
+
+/// The byte following `TYPE_MAGIC` in the values of streams.
+const STREAM_TYPE: u8 = b'x';
+
+/// The byte following `TYPE_MAGIC` in the values of Bloom filters.
+const BLOOM_TYPE: u8 = b'b';
+
+/// The byte following `TYPE_MAGIC` in the values of token buckets.
+const RATE_BUCKET_TYPE: u8 = b'r';
+
+/// The size of the header of a Bloom filter, after its type: its number of hashes, capacity, error rate
+/// and number of elements added.
+const BLOOM_HEADER_SIZE: usize = 4 + 8 + 8 + 8;
+
+/// The size of an encoded score of a sorted set member, preceded by its length.
+const SCORE_CHUNK_SIZE: usize = 4 + 8;
+
+/// The number of bits of the hash of an element picking its HyperLogLog register.
+const HYPERLOGLOG_BITS: u32 = 14;
+
+/// The number of registers of a HyperLogLog, for a standard error of about 0.81%.
+const HYPERLOGLOG_REGISTERS: usize = 1 << HYPERLOGLOG_BITS;
+
+/// The data types of the values of an `EmbeddedCache`.
+///
+/// Strings are cached as they are, while the values of the other types are encoded in bytes behind a
+/// header (see `TYPE_MAGIC`), so that every storage engine, dump and replica keeps them like any value. The
+/// collections are held in their native form until their bytes are read (see `Collection`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    String,      // Any bytes
+    Hash,        // Fields mapped to values (see `Hash`)
+    List,        // Values in order (see `List`)
+    Set,         // Distinct members in no order (see `Set`)
+    SortedSet,   // Distinct members ordered by their scores (see `SortedSet`)
+    HyperLogLog, // An estimate of the number of distinct elements added (see `HyperLogLog`)
+    Stream,      // Values appended with increasing IDs (see `Stream`)
+    BloomFilter, // The elements possibly added, with no false negatives (see `BloomFilter`)
+    RateBucket,  // The tokens left to a rate limit (see `RateBucket`)
+}
+
+impl ValueType {
+    /// The type of the given cached value.
+    pub fn of(value: &Bytes) -> ValueType {
+        match value.header().strip_prefix(&TYPE_MAGIC) {
+            Some([HASH_TYPE, ..]) => ValueType::Hash,
+            Some([LIST_TYPE, ..]) => ValueType::List,
+            Some([SET_TYPE, ..]) => ValueType::Set,
+            Some([SORTED_SET_TYPE, ..]) => ValueType::SortedSet,
+            Some([HYPERLOGLOG_TYPE, ..]) => ValueType::HyperLogLog,
+            Some([STREAM_TYPE, ..]) => ValueType::Stream,
+            Some([BLOOM_TYPE, ..]) => ValueType::BloomFilter,
+            Some([RATE_BUCKET_TYPE, ..]) => ValueType::RateBucket,
+            _ => ValueType::String,
+        }
+    }
+
+    /// The name of the type, as answered to the clients.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueType::String => "string",
+            ValueType::Hash => "hash",
+            ValueType::List => "list",
+            ValueType::Set => "set",
+            ValueType::SortedSet => "zset",
+            ValueType::HyperLogLog => "hyperloglog",
+            ValueType::Stream => "stream",
+            ValueType::BloomFilter => "bloom",
+            ValueType::RateBucket => "ratelimit",
+        }
+    }
+}
+
+/// Encode a string as a cached value: as it is, unless it starts with `TYPE_MAGIC`, in which case it is put
+/// behind `TYPE_MAGIC` and `STRING_TYPE`.
+pub fn encode_string(value: Bytes) -> Bytes {
+    if !value.starts_with(&TYPE_MAGIC) {
+        return value;
+    }
+    let mut encoded: Vec<u8> = Vec::with_capacity(TYPE_MAGIC.len() + 1 + value.len());
+    encoded.extend_from_slice(&TYPE_MAGIC);
+    encoded.push(STRING_TYPE);
+    encoded.extend_from_slice(&value);
+    Bytes::from(encoded)
+}
+
+/// Decode the cached value of a string encoded by `encode_string`. The values of the other types are
+/// returned as they are.
+pub fn decode_string(value: Bytes) -> Bytes {
+    match value.strip_prefix(&TYPE_MAGIC) {
+        Some([STRING_TYPE, string @ ..]) => Bytes::from(string),
+        _ => value,
+    }
+}
+
+/// Check that the value of a command on strings (e.g. `APPEND`), if any, is a string.
+///
+/// # Errors
+/// * `CacheError::WrongType` if the value is of another type.
+pub fn expect_string(value: Option<&Bytes>) -> CacheResult<()> {
+    match value.map(ValueType::of) {
+        None | Some(ValueType::String) => Ok(()),
+        Some(_) => Err(CacheError::WrongType),
+    }
+}
+
+/// The longest string SETBIT may grow to, in bytes: 64 MiB, i.e. bits for over 500 million offsets.
+pub const MAX_BITMAP_BYTES: usize = 64 * 1024 * 1024;
+
+/// Parse the offset of a bit, which must be within `MAX_BITMAP_BYTES`. Returns `None` if the token is not
+/// such an offset.
+pub fn parse_bit_offset(token: &[u8]) -> Option<usize> {
+    let offset: usize = str::from_utf8(token).ok()?.parse().ok()?;
+    (offset < MAX_BITMAP_BYTES * 8).then_some(offset)
+}
+
+/// The bit of a string at the given offset, the offsets counting from the most significant bit of the
+/// first byte. The bits past the end of the string are 0.
+pub fn get_bit(value: &[u8], offset: usize) -> bool {
+    value
+        .get(offset / 8)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Set or clear the bit of a string at the given offset (see `get_bit`), growing the string with zero
+/// bytes as needed. Returns the previous bit.
+pub fn set_bit(value: &mut Vec<u8>, offset: usize, bit: bool) -> bool {
+    let index: usize = offset / 8;
+    if value.len() <= index {
+        value.resize(index + 1, 0);
+    }
+    let mask: u8 = 0x80 >> (offset % 8);
+    let previous: bool = value[index] & mask != 0;
+    match bit {
+        true => value[index] |= mask,
+        false => value[index] &= !mask,
+    }
+    previous
+}
+
+/// The number of bits set in a string, from the `start` byte to the `stop` one, both included, where
+/// negative indexes count from the end (`-1` being the last byte).
+pub fn count_bits(value: &[u8], start: i64, stop: i64) -> u64 {
+    match resolve_range(value.len(), start, stop) {
+        Some(range) => value[range]
+            .iter()
+            .map(|byte| u64::from(byte.count_ones()))
+            .sum(),
+        None => 0,
+    }
+}
+
+/// Whether the given cached value is a hash, a list or a (sorted) set without any element, which the key
+/// does not keep.
+pub fn is_empty_collection(value: &Bytes) -> bool {
+    value.len() == TYPE_MAGIC.len() + 1 && ValueType::of(value) != ValueType::String
+}
+
+/// A hash, a list or a (sorted) set, which the cached values hold in its native form (see
+/// `Bytes::from_native`), so that the writes to it modify it in place rather than decode and encode it
+/// again. A value read from its encoding (e.g. loaded from a dump) is decoded by the first write to it.
+pub trait Collection: NativeValue + Clone + Default {
+    /// The header of the encoding of the collection: `TYPE_MAGIC`, then the byte telling its type.
+    const HEADER: [u8; 5];
+
+    /// Decode the cached value of the collection, an absent value being an empty collection.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the value is not of the type of the collection.
+    fn decode(value: Option<&[u8]>) -> CacheResult<Self>;
+
+    /// The collection of the given cached value (an empty one if `None`), read in place if the value holds
+    /// it in its native form.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the value is not of the type of the collection.
+    fn read(value: Option<&Bytes>) -> CacheResult<Cow<'_, Self>> {
+        let Some(value) = value else {
+            return Ok(Cow::Owned(Self::default()));
+        };
+        if let Some(collection) = value.native::<Self>() {
+            return Ok(Cow::Borrowed(collection));
+        }
+        if !value.header().starts_with(&Self::HEADER) {
+            return Err(CacheError::WrongType); // Without encoding a native value of another type
+        }
+        Self::decode(Some(value)).map(Cow::Owned)
+    }
+
+    /// The collection of the given cached value to modify in place, the value being given its native form
+    /// first if it holds its encoding.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the value is not of the type of the collection, which is then left as
+    ///   it is.
+    fn modify(value: &mut Bytes) -> CacheResult<&mut Self> {
+        if value.native::<Self>().is_none() {
+            if !value.header().starts_with(&Self::HEADER) {
+                return Err(CacheError::WrongType);
+            }
+            *value = Bytes::from_native(Self::decode(Some(value))?);
+        }
+        value.native_mut().ok_or(CacheError::WrongType)
+    }
+}
+
+/// The value of a hash: fields mapped to values, in the order the fields were first set.
+///
+/// Encoded as `TYPE_MAGIC`, `HASH_TYPE`, then every field and its value, each preceded by its length as a
+/// 32-bit big-endian number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hash {
+    fields: Vec<(Bytes, Bytes)>,
+    positions: HashMap<Bytes, usize>, // The indexes of the fields in `fields`
+    size: usize,                      // The bytes of the encoded fields and values
+}
+
+impl Hash {
+    /// The value of the given field, if it is set.
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        let index: usize = *self.positions.get(field)?;
+        self.fields.get(index).map(|(_, value)| value)
+    }
+
+    /// Set the value of the given field, returning whether the field is new.
+    pub fn set(&mut self, field: &[u8], value: Bytes) -> bool {
+        if let Some(&index) = self.positions.get(field) {
+            let current: &mut Bytes = &mut self.fields[index].1;
+            self.size = self.size - current.len() + value.len();
+            *current = value;
+            return false;
+        }
+
+        let field: Bytes = Bytes::from(field);
+        self.size += chunk_size(&field) + chunk_size(&value);
+        self.positions.insert(field.clone(), self.fields.len());
+        self.fields.push((field, value));
+        true
+    }
+
+    /// Remove the given field, returning whether it was set. The fields set after it are moved up, so this
+    /// takes a time proportional to their number.
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        let Some(index) = self.positions.remove(field) else {
+            return false;
+        };
+        let (name, value): (Bytes, Bytes) = self.fields.remove(index);
+        self.size -= chunk_size(&name) + chunk_size(&value);
+        for (name, _) in &self.fields[index..] {
+            if let Some(position) = self.positions.get_mut(name) {
+                *position -= 1;
+            }
+        }
+        true
+    }
+
+    /// The number of fields set.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Whether no field is set.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// The fields and their values, in the order the fields were first set.
+    pub fn fields(&self) -> &[(Bytes, Bytes)] {
+        &self.fields
+    }
+}
+
+impl Collection for Hash {
+    const HEADER: [u8; 5] = collection_header(HASH_TYPE);
+
+    fn decode(value: Option<&[u8]>) -> CacheResult<Hash> {
+        let Some(value) = value else {
+            return Ok(Hash::default());
+        };
+        let chunks: Vec<&[u8]> = decode_chunks(value, HASH_TYPE)?;
+        if !chunks.len().is_multiple_of(2) {
+            return Err(CacheError::WrongType);
+        }
+
+        let mut hash: Hash = Hash::default();
+        for pair in chunks.chunks_exact(2) {
+            hash.set(pair[0], Bytes::from(pair[1]));
+        }
+        Ok(hash)
+    }
+}
+
+impl NativeValue for Hash {
+    fn encode(&self) -> Vec<u8> {
+        let chunks = self
+            .fields
+            .iter()
+            .flat_map(|(field, value)| [field.as_ref(), value.as_ref()]);
+        encode_chunks(HASH_TYPE, chunks)
+    }
+
+    fn encoded_len(&self) -> usize {
+        Self::HEADER.len() + self.size
+    }
+
+    /// The slots of the fields (in the order and in the index) and the bytes of the fields and values.
+    fn heap_size(&self) -> usize {
+        let slot: usize = mem::size_of::<(Bytes, Bytes)>();
+        self.fields.capacity() * slot + self.positions.capacity() * slot + self.size
+    }
+
+    fn header(&self) -> &[u8] {
+        &Self::HEADER
+    }
+
+    fn clone_native(&self) -> Box<dyn NativeValue> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The value of a list: values in order, pushed and popped at either end.
+///
+/// Encoded as `TYPE_MAGIC`, `LIST_TYPE`, then every value preceded by its length as a 32-bit big-endian
+/// number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    items: VecDeque<Bytes>,
+    size: usize, // The bytes of the encoded values
+}
+
+impl List {
+    /// Push a value at the head (`left`) or the tail of the list.
+    pub fn push(&mut self, value: Bytes, left: bool) {
+        self.size += chunk_size(&value);
+        match left {
+            true => self.items.push_front(value),
+            false => self.items.push_back(value),
+        }
+    }
+
+    /// Pop the value at the head (`left`) or the tail of the list, if any.
+    pub fn pop(&mut self, left: bool) -> Option<Bytes> {
+        let value: Bytes = match left {
+            true => self.items.pop_front()?,
+            false => self.items.pop_back()?,
+        };
+        self.size -= chunk_size(&value);
+        Some(value)
+    }
+
+    /// The values from the `start` index to the `stop` one, both included, where negative indexes count
+    /// from the tail (`-1` being the last value). Indexes out of range are clamped to the list.
+    pub fn range(&self, start: i64, stop: i64) -> Vec<Bytes> {
+        match resolve_range(self.items.len(), start, stop) {
+            Some(range) => self.items.range(range).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The number of values.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Whether the list has no value.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl Collection for List {
+    const HEADER: [u8; 5] = collection_header(LIST_TYPE);
+
+    fn decode(value: Option<&[u8]>) -> CacheResult<List> {
+        let mut list: List = List::default();
+        if let Some(value) = value {
+            for item in decode_chunks(value, LIST_TYPE)? {
+                list.push(Bytes::from(item), false);
+            }
+        }
+        Ok(list)
+    }
+}
+
+impl NativeValue for List {
+    fn encode(&self) -> Vec<u8> {
+        encode_chunks(LIST_TYPE, self.items.iter().map(Bytes::as_ref))
+    }
+
+    fn encoded_len(&self) -> usize {
+        Self::HEADER.len() + self.size
+    }
+
+    /// The slots of the values and their bytes.
+    fn heap_size(&self) -> usize {
+        self.items.capacity() * mem::size_of::<Bytes>() + self.size
+    }
+
+    fn header(&self) -> &[u8] {
+        &Self::HEADER
+    }
+
+    fn clone_native(&self) -> Box<dyn NativeValue> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The value of a set: distinct members, in no particular order.
+///
+/// Encoded as `TYPE_MAGIC`, `SET_TYPE`, then every member preceded by its length as a 32-bit big-endian
+/// number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Set {
+    members: HashSet<Bytes>,
+    size: usize, // The bytes of the encoded members
+}
+
+/// How the members of several sets are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    Intersection, // The members of every set
+    Union,        // The members of any set
+    Difference,   // The members of the first set which are in none of the others
+}
+
+impl Set {
+    /// Add a member, returning whether it is new.
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        let size: usize = chunk_size(&member);
+        let added: bool = self.members.insert(member);
+        if added {
+            self.size += size;
+        }
+        added
+    }
+
+    /// Remove a member, returning whether it was in the set.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let removed: bool = self.members.remove(member);
+        if removed {
+            self.size -= chunk_size(member);
+        }
+        removed
+    }
+
+    /// Whether the given member is in the set.
+    pub fn contains(&self, member: &[u8]) -> bool {
+        self.members.contains(member)
+    }
+
+    /// Combine the given sets, in order (the first one being the set the others are subtracted from for
+    /// `SetOperation::Difference`).
+    ///
+    /// # Returns
+    /// * The members of the combined set, in no particular order.
+    pub fn combine(sets: &[&Set], operation: SetOperation) -> Vec<Bytes> {
+        let Some((first, others)) = sets.split_first() else {
+            return Vec::new();
+        };
+        let members: Vec<&Bytes> = match operation {
+            SetOperation::Intersection => first
+                .members
+                .iter()
+                .filter(|member| others.iter().all(|set| set.contains(member)))
+                .collect(),
+            SetOperation::Union => sets
+                .iter()
+                .enumerate()
+                .flat_map(|(index, set)| {
+                    // The members of the sets before are already in
+                    let before: &[&Set] = &sets[..index];
+                    set.members
+                        .iter()
+                        .filter(move |member| !before.iter().any(|set| set.contains(member)))
+                })
+                .collect(),
+            SetOperation::Difference => first
+                .members
+                .iter()
+                .filter(|member| !others.iter().any(|set| set.contains(member)))
+                .collect(),
+        };
+        members.into_iter().cloned().collect()
+    }
+
+    /// The number of members.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Whether the set has no member.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+impl Collection for Set {
+    const HEADER: [u8; 5] = collection_header(SET_TYPE);
+
+    fn decode(value: Option<&[u8]>) -> CacheResult<Set> {
+        let mut set: Set = Set::default();
+        if let Some(value) = value {
+            for member in decode_chunks(value, SET_TYPE)? {
+                set.insert(Bytes::from(member));
+            }
+        }
+        Ok(set)
+    }
+}
+
+impl NativeValue for Set {
+    fn encode(&self) -> Vec<u8> {
+        encode_chunks(SET_TYPE, self.members.iter().map(Bytes::as_ref))
+    }
+
+    fn encoded_len(&self) -> usize {
+        Self::HEADER.len() + self.size
+    }
+
+    /// The slots of the members and their bytes.
+    fn heap_size(&self) -> usize {
+        self.members.capacity() * mem::size_of::<Bytes>() + self.size
+    }
+
+    fn header(&self) -> &[u8] {
+        &Self::HEADER
+    }
+
+    fn clone_native(&self) -> Box<dyn NativeValue> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The value of a sorted set: distinct members, each with a score, ordered by their scores (and the members
+/// with the same score by their bytes).
+///
+/// Encoded as `TYPE_MAGIC`, `SORTED_SET_TYPE`, then every score (as the 8 big-endian bytes of a 64-bit
+/// float) and its member, each preceded by its length as a 32-bit big-endian number.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    members: Vec<(f64, Bytes)>,  // Sorted by `rank_order`
+    scores: HashMap<Bytes, f64>, // The scores of the members
+    size: usize,                 // The bytes of the encoded scores and members
+}
+
+/// A bound of a range of scores, e.g. `5` or `(5` (excluding 5), as given to `ZRANGEBYSCORE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    /// Parse a bound: a score (see `parse_score`), preceded by `(` if it is excluded from the range.
+    pub fn parse(token: &[u8]) -> Option<ScoreBound> {
+        match token.strip_prefix(b"(") {
+            Some(score) => parse_score(score).map(|score| ScoreBound {
+                score,
+                exclusive: true,
+            }),
+            None => parse_score(token).map(|score| ScoreBound {
+                score,
+                exclusive: false,
+            }),
+        }
+    }
+
+    /// Whether the given score is above this bound, as the minimum of a range.
+    fn admits_above(&self, score: f64) -> bool {
+        score > self.score || (!self.exclusive && score == self.score)
+    }
+
+    /// Whether the given score is below this bound, as the maximum of a range.
+    fn admits_below(&self, score: f64) -> bool {
+        score < self.score || (!self.exclusive && score == self.score)
+    }
+}
+
+/// Parse the score of a sorted set member: a decimal number, `inf` or `-inf`. Returns `None` if the token
+/// is not a number.
+pub fn parse_score(token: &[u8]) -> Option<f64> {
+    let score: f64 = str::from_utf8(token).ok()?.parse().ok()?;
+    (!score.is_nan()).then_some(score + 0.0) // Adding 0 turns -0 into 0
+}
+
+impl SortedSet {
+    /// The score of the given member, if it is in the sorted set.
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Add the given member with the given score, or change its score, returning whether it is new.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        let added: bool = !self.remove(member);
+        let entry: (f64, Bytes) = (score, Bytes::from(member));
+        let index: usize = self
+            .members
+            .binary_search_by(|probe| rank_order(probe, &entry))
+            .unwrap_or_else(|index| index);
+        self.size += SCORE_CHUNK_SIZE + chunk_size(member);
+        self.scores.insert(entry.1.clone(), score);
+        self.members.insert(index, entry);
+        added
+    }
+
+    /// Add the given increment to the score of the given member (0 if it is not in the sorted set).
+    ///
+    /// # Returns
+    /// * The new score.
+    ///
+    /// # Errors
+    /// * `CacheError::NotANumber` if the new score would not be a number (e.g. `inf` plus `-inf`), the
+    ///   sorted set being left as it is.
+    pub fn increment(&mut self, member: &[u8], increment: f64) -> CacheResult<f64> {
+        let score: f64 = self.score(member).unwrap_or(0.0) + increment;
+        if score.is_nan() {
+            return Err(CacheError::NotANumber);
+        }
+        self.insert(member, score);
+        Ok(score)
+    }
+
+    /// Remove the given member, returning whether it was in the sorted set.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let Some((member, score)) = self.scores.remove_entry(member) else {
+            return false;
+        };
+        let entry: (f64, Bytes) = (score, member);
+        if let Ok(index) = self
+            .members
+            .binary_search_by(|probe| rank_order(probe, &entry))
+        {
+            self.members.remove(index);
+        }
+        self.size -= SCORE_CHUNK_SIZE + chunk_size(&entry.1);
+        true
+    }
+
+    /// The members from the `start` rank to the `stop` one, both included, with their scores, where
+    /// negative ranks count from the highest score (`-1` being the last member).
+    pub fn range(&self, start: i64, stop: i64) -> Vec<(Bytes, f64)> {
+        match resolve_range(self.members.len(), start, stop) {
+            Some(range) => self.members[range]
+                .iter()
+                .map(|(score, member)| (member.clone(), *score))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The members whose scores are within the given bounds, with their scores, in order.
+    pub fn range_by_score(&self, min: ScoreBound, max: ScoreBound) -> Vec<(Bytes, f64)> {
+        self.members
+            .iter()
+            .skip_while(|(score, _)| !min.admits_above(*score))
+            .take_while(|(score, _)| max.admits_below(*score))
+            .map(|(score, member)| (member.clone(), *score))
+            .collect()
+    }
+
+    /// The number of members.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Whether the sorted set has no member.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+impl Collection for SortedSet {
+    const HEADER: [u8; 5] = collection_header(SORTED_SET_TYPE);
+
+    fn decode(value: Option<&[u8]>) -> CacheResult<SortedSet> {
+        let Some(value) = value else {
+            return Ok(SortedSet::default());
+        };
+        let chunks: Vec<&[u8]> = decode_chunks(value, SORTED_SET_TYPE)?;
+        if !chunks.len().is_multiple_of(2) {
+            return Err(CacheError::WrongType);
+        }
+
+        let mut set: SortedSet = SortedSet::default();
+        for pair in chunks.chunks_exact(2) {
+            let score: [u8; 8] = pair[0].try_into().map_err(|_| CacheError::WrongType)?;
+            set.insert(pair[1], f64::from_be_bytes(score));
+        }
+        Ok(set)
+    }
+}
+
+impl NativeValue for SortedSet {
+    fn encode(&self) -> Vec<u8> {
+        let scores: Vec<[u8; 8]> = self
+            .members
+            .iter()
+            .map(|(score, _)| score.to_be_bytes())
+            .collect();
+        let chunks = scores
+            .iter()
+            .zip(&self.members)
+            .flat_map(|(score, (_, member))| [score.as_slice(), member.as_ref()]);
+        encode_chunks(SORTED_SET_TYPE, chunks)
+    }
+
+    fn encoded_len(&self) -> usize {
+        Self::HEADER.len() + self.size
+    }
+
+    /// The slots of the members (in the order and in the index) and the bytes of the scores and members.
+    fn heap_size(&self) -> usize {
+        let slot: usize = mem::size_of::<(f64, Bytes)>();
+        self.members.capacity() * slot + self.scores.capacity() * slot + self.size
+    }
+
+    fn header(&self) -> &[u8] {
+        &Self::HEADER
+    }
+
+    fn clone_native(&self) -> Box<dyn NativeValue> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A HyperLogLog: an estimate of the number of distinct elements added to it, in a bounded size whatever
+/// the number of elements.
+///
+/// Encoded as `TYPE_MAGIC`, `HYPERLOGLOG_TYPE`, then its `HYPERLOGLOG_REGISTERS` registers, a byte each.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>, // The longest run of leading zeros (plus one) seen by every register
+}
+
+impl Default for HyperLogLog {
+    fn default() -> HyperLogLog {
+        HyperLogLog {
+            registers: vec![0; HYPERLOGLOG_REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// Decode the cached value of a HyperLogLog, an absent value being an empty HyperLogLog.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the value is not a HyperLogLog.
+    pub fn decode(value: Option<&[u8]>) -> CacheResult<HyperLogLog> {
+        let Some(value) = value else {
+            return Ok(HyperLogLog::default());
+        };
+        match value.strip_prefix(&TYPE_MAGIC) {
+            Some([HYPERLOGLOG_TYPE, registers @ ..])
+                if registers.len() == HYPERLOGLOG_REGISTERS =>
+            {
+                Ok(HyperLogLog {
+                    registers: registers.to_vec(),
+                })
+            }
+            _ => Err(CacheError::WrongType),
+        }
+    }
+
+    /// Encode the HyperLogLog as a cached value.
+    pub fn encode(&self) -> Bytes {
+        let mut encoded: Vec<u8> = Vec::with_capacity(TYPE_MAGIC.len() + 1 + self.registers.len());
+        encoded.extend_from_slice(&TYPE_MAGIC);
+        encoded.push(HYPERLOGLOG_TYPE);
+        encoded.extend_from_slice(&self.registers);
+        Bytes::from(encoded)
+    }
+
+    /// Add an element, returning whether the estimate may have changed.
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash: u64 = stable_hash(element);
+        let index: usize = (hash >> (64 - HYPERLOGLOG_BITS)) as usize;
+        // The guard bit bounds the run of zeros of the remaining bits
+        let rest: u64 = (hash << HYPERLOGLOG_BITS) | (1 << (HYPERLOGLOG_BITS - 1));
+        let rank: u8 = rest.leading_zeros() as u8 + 1;
+        if rank <= self.registers[index] {
+            return false;
+        }
+        self.registers[index] = rank;
+        true
+    }
+
+    /// Merge another HyperLogLog into this one, which then estimates the elements added to either.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// The estimated number of distinct elements added.
+    pub fn count(&self) -> u64 {
+        let registers: f64 = HYPERLOGLOG_REGISTERS as f64;
+        let alpha: f64 = 0.7213 / (1.0 + 1.079 / registers);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-i32::from(*register)))
+            .sum();
+        let estimate: f64 = alpha * registers * registers / sum;
+
+        // Small cardinalities are better estimated from the registers still empty (linear counting)
+        let zeros: usize = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        if estimate <= 2.5 * registers && zeros > 0 {
+            return (registers * (registers / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+}
+
+/// A Bloom filter: whether an element may have been added to it, with no false negatives and a bounded
+/// rate of false positives, in a size set by its capacity whatever the size of the elements.
+///
+/// Encoded as `TYPE_MAGIC`, `BLOOM_TYPE`, then (big-endian) its number of hashes as a 32-bit number, its
+/// capacity and its number of elements added as 64-bit numbers around its error rate as a 64-bit float,
+/// then its bits. The error rate only holds up to the capacity, beyond which it grows as the filter fills.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    hashes: u32,     // The number of bits set by every element
+    capacity: u64,   // The number of elements the filter is sized for
+    error_rate: f64, // The rate of false positives once the filter holds its capacity
+    count: u64,      // The number of elements added which were not in the filter yet
+    bits: Vec<u8>,   // The bits of the filter, at least one byte
+}
+
+impl BloomFilter {
+    /// The capacity of the filters created by adding to a key without a value, as in RedisBloom.
+    pub const DEFAULT_CAPACITY: u64 = 100;
+
+    /// The error rate of the filters created by adding to a key without a value, as in RedisBloom.
+    pub const DEFAULT_ERROR_RATE: f64 = 0.01;
+
+    /// Create an empty filter sized for the given number of elements at the given rate of false positives,
+    /// taking `-capacity * ln(error_rate) / ln(2)^2` bits and `ln(2)` hashes per bit per element.
+    ///
+    /// # Returns
+    /// * `None` if the capacity is zero, the error rate is not strictly between 0 and 1, or the filter
+    ///   would take more than `MAX_BITMAP_BYTES`.
+    pub fn new(capacity: u64, error_rate: f64) -> Option<BloomFilter> {
+        let (hashes, size): (u32, usize) = BloomFilter::_sizing(capacity, error_rate)?;
+        Some(BloomFilter {
+            hashes,
+            capacity,
+            error_rate,
+            count: 0,
+            bits: vec![0; size],
+        })
+    }
+
+    /// Decode the cached value of a Bloom filter, an absent value being an empty filter of the default
+    /// capacity and error rate.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the value is not a Bloom filter.
+    pub fn decode(value: Option<&[u8]>) -> CacheResult<BloomFilter> {
+        let Some(value) = value else {
+            return Ok(BloomFilter::default());
+        };
+        let Some([BLOOM_TYPE, rest @ ..]) = value.strip_prefix(&TYPE_MAGIC) else {
+            return Err(CacheError::WrongType);
+        };
+        if rest.len() <= BLOOM_HEADER_SIZE {
+            return Err(CacheError::WrongType);
+        }
+
+        let (header, bits): (&[u8], &[u8]) = rest.split_at(BLOOM_HEADER_SIZE);
+        let number = |at: usize| -> u64 {
+            u64::from_be_bytes(header[at..at + 8].try_into().unwrap_or_default())
+        };
+        let hashes: u32 = u32::from_be_bytes(header[..4].try_into().unwrap_or_default());
+        let capacity: u64 = number(4);
+        let error_rate: f64 = f64::from_bits(number(12));
+
+        // The header must be one `new` gives, lest a forged one (e.g. of billions of hashes) stall its
+        // readers
+        if BloomFilter::_sizing(capacity, error_rate) != Some((hashes, bits.len())) {
+            return Err(CacheError::WrongType);
+        }
+        Ok(BloomFilter {
+            hashes,
+            capacity,
+            error_rate,
+            count: number(20),
+            bits: bits.to_vec(),
+        })
+    }
+
+    /// Encode the Bloom filter as a cached value.
+    pub fn encode(&self) -> Bytes {
+        let mut encoded: Vec<u8> =
+            Vec::with_capacity(TYPE_MAGIC.len() + 1 + BLOOM_HEADER_SIZE + self.bits.len());
+        encoded.extend_from_slice(&TYPE_MAGIC);
+        encoded.push(BLOOM_TYPE);
+        encoded.extend_from_slice(&self.hashes.to_be_bytes());
+        encoded.extend_from_slice(&self.capacity.to_be_bytes());
+        encoded.extend_from_slice(&self.error_rate.to_bits().to_be_bytes());
+        encoded.extend_from_slice(&self.count.to_be_bytes());
+        encoded.extend_from_slice(&self.bits);
+        Bytes::from(encoded)
+    }
+
+    /// Add an element, returning whether it was not in the filter yet (`false` if it may have been added
+    /// already).
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let mut added: bool = false;
+        for bit in self._bits_of(element) {
+            let mask: u8 = 0x80 >> (bit % 8);
+            added |= self.bits[bit / 8] & mask == 0;
+            self.bits[bit / 8] |= mask;
+        }
+        self.count += u64::from(added);
+        added
+    }
+
+    /// Whether the element may have been added (`false` if it certainly was not).
+    pub fn contains(&self, element: &[u8]) -> bool {
+        self._bits_of(element)
+            .all(|bit| self.bits[bit / 8] & (0x80 >> (bit % 8)) != 0)
+    }
+
+    /// The number of elements the filter is sized for.
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// The rate of false positives once the filter holds its capacity.
+    pub fn error_rate(&self) -> f64 {
+        self.error_rate
+    }
+
+    /// The number of elements added which were not in the filter yet.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The number of hashes and the size in bytes of the bits of a filter of the given capacity and error
+    /// rate (see `new`), or `None` if there is no such filter.
+    fn _sizing(capacity: u64, error_rate: f64) -> Option<(u32, usize)> {
+        if capacity == 0 || !(error_rate > 0.0 && error_rate < 1.0) {
+            return None;
+        }
+        let ln2: f64 = std::f64::consts::LN_2;
+        let bits: f64 = (-(capacity as f64) * error_rate.ln() / (ln2 * ln2)).ceil();
+        if bits > (MAX_BITMAP_BYTES * 8) as f64 {
+            return None;
+        }
+        let hashes: u32 = ((bits / capacity as f64) * ln2).round().max(1.0) as u32;
+        Some((hashes, (bits as usize).div_ceil(8).max(1)))
+    }
+
+    /// The bits of the given element, from two hashes combined as `h1 + i * h2` (Kirsch and Mitzenmacher),
+    /// which is as good as as many independent hashes.
+    fn _bits_of(&self, element: &[u8]) -> impl Iterator<Item = usize> {
+        let size: u64 = self.bits.len() as u64 * 8;
+        let first: u64 = stable_hash(element);
+        let second: u64 = stable_hash(&first.to_be_bytes()) | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % size) as usize)
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> BloomFilter {
+        BloomFilter::new(
+            BloomFilter::DEFAULT_CAPACITY,
+            BloomFilter::DEFAULT_ERROR_RATE,
+        )
+        .expect("the default sizing is valid")
+    }
+}
+
+/// A token bucket limiting the rate of some action: it holds up to a number of tokens, refilled at a steady
+/// rate, and every action takes some of them if there are enough left. Its capacity and refill rate are not
+/// kept, but given with every use, so that a limit can be changed at any time.
+///
+/// Encoded as `TYPE_MAGIC`, `RATE_BUCKET_TYPE`, then (big-endian) its tokens as a 64-bit float and the
+/// walltime they were counted at as a 64-bit number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateBucket {
+    tokens: f64, // The tokens left at the time they were counted, possibly a fraction of one
+    updated_at: u64, // The walltime (in milliseconds) the tokens were counted at
+}
+
+/// The outcome of taking tokens from a `RateBucket`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateDecision {
+    pub allowed: bool,  // Whether there were enough tokens, which were then taken
+    pub remaining: u64, // The whole tokens left after the action
+    pub retry_after: Option<u64>, // The milliseconds until there are enough tokens, if ever
+    pub full_after: u64, // The milliseconds until the bucket is full again
+}
+
+impl RateBucket {
+    /// Decode the cached value of a token bucket, an absent value being a full bucket of the given
+    /// capacity.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the value is not a token bucket.
+    pub fn decode(value: Option<&[u8]>, capacity: u64, now: u64) -> CacheResult<RateBucket> {
+        let Some(value) = value else {
+            return Ok(RateBucket {
+                tokens: capacity as f64,
+                updated_at: now,
+            });
+        };
+        let Some([RATE_BUCKET_TYPE, rest @ ..]) = value.strip_prefix(&TYPE_MAGIC) else {
+            return Err(CacheError::WrongType);
+        };
+        if rest.len() != 8 + 8 {
+            return Err(CacheError::WrongType);
+        }
+
+        let number = |at: usize| -> u64 {
+            u64::from_be_bytes(rest[at..at + 8].try_into().unwrap_or_default())
+        };
+        Ok(RateBucket {
+            tokens: f64::from_bits(number(0)),
+            updated_at: number(8),
+        })
+    }
+
+    /// Encode the token bucket as a cached value.
+    pub fn encode(&self) -> Bytes {
+        let mut encoded: Vec<u8> = Vec::with_capacity(TYPE_MAGIC.len() + 1 + 8 + 8);
+        encoded.extend_from_slice(&TYPE_MAGIC);
+        encoded.push(RATE_BUCKET_TYPE);
+        encoded.extend_from_slice(&self.tokens.to_bits().to_be_bytes());
+        encoded.extend_from_slice(&self.updated_at.to_be_bytes());
+        Bytes::from(encoded)
+    }
+
+    /// Refill the bucket for the time elapsed since its tokens were counted, then take the given number of
+    /// tokens if there are enough (a cost of 0 only telling how many are left).
+    ///
+    /// # Arguments
+    /// * `capacity` - The most tokens the bucket holds, at least 1.
+    /// * `refill_rate` - The tokens added per second, above 0.
+    /// * `cost` - The tokens to take.
+    /// * `now` - The current walltime in milliseconds. A clock going back refills nothing.
+    pub fn take(&mut self, capacity: u64, refill_rate: f64, cost: u64, now: u64) -> RateDecision {
+        let elapsed: f64 = now.saturating_sub(self.updated_at) as f64 / 1000.0;
+        let capacity: f64 = capacity as f64;
+        self.tokens = (self.tokens + elapsed * refill_rate).min(capacity);
+        self.updated_at = self.updated_at.max(now);
+
+        let cost: f64 = cost as f64;
+        let allowed: bool = cost <= self.tokens;
+        if allowed {
+            self.tokens -= cost;
+        }
+        let millis_until = |tokens: f64| -> u64 {
+            ((tokens - self.tokens).max(0.0) * 1000.0 / refill_rate).ceil() as u64
+        };
+        RateDecision {
+            allowed,
+            remaining: self.tokens as u64,
+            retry_after: match allowed {
+                true => Some(0),
+                false => (cost <= capacity).then(|| millis_until(cost)),
+            },
+            full_after: millis_until(capacity),
+        }
+    }
+}
+
+/// The ID of a stream entry: the walltime (in milliseconds) it was appended at, then a sequence number
+/// telling apart the entries appended within the same millisecond, written `<ms>-<seq>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    /// The lowest ID, which no entry has.
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+
+    /// The highest ID.
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parse an ID, `<ms>-<seq>` or `<ms>` alone, in which case its sequence number is the given one.
+    /// Returns `None` if the token is not an ID.
+    pub fn parse(token: &[u8], default_seq: u64) -> Option<StreamId> {
+        let token: &str = str::from_utf8(token).ok()?;
+        let (ms, seq): (u64, u64) = match token.split_once('-') {
+            Some((ms, seq)) => (ms.parse().ok()?, seq.parse().ok()?),
+            None => (token.parse().ok()?, default_seq),
+        };
+        Some(StreamId { ms, seq })
+    }
+
+    /// Parse a bound of a range of IDs, as given to `XRANGE`: an ID (see `parse`), `-` for the lowest ID or
+    /// `+` for the highest one. A millisecond alone covers all its entries, from the first (as the `start`
+    /// of the range) or to the last. Returns `None` if the token is not a bound.
+    pub fn parse_bound(token: &[u8], start: bool) -> Option<StreamId> {
+        match token {
+            b"-" => Some(StreamId::MIN),
+            b"+" => Some(StreamId::MAX),
+            _ => StreamId::parse(token, if start { 0 } else { u64::MAX }),
+        }
+    }
+
+    /// The ID following this one, if it is not the highest.
+    pub fn next(&self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => self.ms.checked_add(1).map(|ms| StreamId { ms, seq: 0 }),
+        }
+    }
+
+    /// The ID as encoded in the value of a stream: its milliseconds, then its sequence number.
+    fn to_bytes(self) -> [u8; 16] {
+        let mut bytes: [u8; 16] = [0; 16];
+        bytes[..8].copy_from_slice(&self.ms.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.seq.to_be_bytes());
+        bytes
+    }
+
+    /// Decode an ID encoded by `to_bytes`.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the bytes are not an ID.
+    fn from_bytes(bytes: &[u8]) -> CacheResult<StreamId> {
+        let (ms, seq) = bytes
+            .split_first_chunk::<8>()
+            .ok_or(CacheError::WrongType)?;
+        let seq: [u8; 8] = seq.try_into().map_err(|_| CacheError::WrongType)?;
+        Ok(StreamId {
+            ms: u64::from_be_bytes(*ms),
+            seq: u64::from_be_bytes(seq),
+        })
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The value of a stream: values appended one after the other, each given an ID above the ones of all the
+/// values appended before it, so that a consumer remembering the last ID it read can read what follows.
+///
+/// Encoded as `TYPE_MAGIC`, `STREAM_TYPE`, the last ID given (as its milliseconds then its sequence
+/// number, 8 big-endian bytes each), then every ID and its value, each preceded by its length as a 32-bit
+/// big-endian number. The last ID is kept apart from the entries, so that the IDs keep increasing when the
+/// stream is trimmed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stream {
+    last_id: StreamId,
+    entries: Vec<(StreamId, Bytes)>, // Sorted by ID
+}
+
+impl Stream {
+    /// Decode the cached value of a stream, an absent value being an empty stream.
+    ///
+    /// # Errors
+    /// * `CacheError::WrongType` if the value is not a stream.
+    pub fn decode(value: Option<&[u8]>) -> CacheResult<Stream> {
+        let Some(value) = value else {
+            return Ok(Stream::default());
+        };
+        let chunks: Vec<&[u8]> = decode_chunks(value, STREAM_TYPE)?;
+        let Some((last_id, chunks)) = chunks.split_first() else {
+            return Err(CacheError::WrongType);
+        };
+        if !chunks.len().is_multiple_of(2) {
+            return Err(CacheError::WrongType);
+        }
+
+        let mut entries: Vec<(StreamId, Bytes)> = Vec::with_capacity(chunks.len() / 2);
+        for pair in chunks.chunks_exact(2) {
+            entries.push((StreamId::from_bytes(pair[0])?, Bytes::from(pair[1])));
+        }
+        Ok(Stream {
+            last_id: StreamId::from_bytes(last_id)?,
+            entries,
+        })
+    }
+
+    /// Encode the stream as a cached value.
+    pub fn encode(&self) -> Bytes {
+        let ids: Vec<[u8; 16]> = self.entries.iter().map(|(id, _)| id.to_bytes()).collect();
+        let last_id: [u8; 16] = self.last_id.to_bytes();
+        let chunks = ids
+            .iter()
+            .zip(&self.entries)
+            .flat_map(|(id, (_, value))| [id.as_slice(), value.as_ref()]);
+        Bytes::from(encode_chunks(
+            STREAM_TYPE,
+            std::iter::once(last_id.as_slice()).chain(chunks),
+        ))
+    }
+
+    /// Append a value with an ID made of the given walltime (or of the milliseconds of the last ID, if the
+    /// clock went back), then drop the oldest entries beyond `max_len`, if any.
+    ///
+    /// # Returns
+    /// * The ID of the value.
+    pub fn append(&mut self, value: Bytes, now: u64, max_len: Option<usize>) -> StreamId {
+        let id: StreamId = match now > self.last_id.ms {
+            true => StreamId { ms: now, seq: 0 },
+            false => self.last_id.next().unwrap_or(StreamId::MAX),
+        };
+        self.last_id = id;
+        self.entries.push((id, value));
+        if let Some(max_len) = max_len {
+            let excess: usize = self.entries.len().saturating_sub(max_len);
+            self.entries.drain(..excess);
+        }
+        id
+    }
+
+    /// The entries whose IDs are from `start` to `end`, both included, in order, up to `count` of them if
+    /// given.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> Vec<(StreamId, Bytes)> {
+        let from: usize = self.entries.partition_point(|(id, _)| *id < start);
+        self.entries[from..]
+            .iter()
+            .take_while(|(id, _)| *id <= end)
+            .take(count.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the stream has no entry.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A 64-bit hash of the given bytes (FNV-1a, then the finalizer of MurmurHash3 to spread its bits) which is
+/// the same on every platform and in every version, unlike the hashers of the standard library, so that
+/// the cached HyperLogLogs remain valid.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// The order of the members of a sorted set: by score, then by bytes.
+fn rank_order(a: &(f64, Bytes), b: &(f64, Bytes)) -> Ordering {
+    a.0.total_cmp(&b.0)
+        .then_with(|| a.1.as_ref().cmp(b.1.as_ref()))
+}
+
+/// The indexes of a collection of the given length from the `start` index to the `stop` one, both
+/// included, where negative indexes count from the end (`-1` being the last element). Indexes out of range
+/// are clamped to the collection.
+///
+/// # Returns
+/// * `None` if the range is empty.
+fn resolve_range(length: usize, start: i64, stop: i64) -> Option<RangeInclusive<usize>> {
+    let length: i64 = length as i64;
+    let resolve = |index: i64| if index < 0 { length + index } else { index };
+    let (start, stop): (i64, i64) = (resolve(start).max(0), resolve(stop).min(length - 1));
+    (start <= stop).then_some(start as usize..=stop as usize)
+}
+
+/// Split the given cached value of the given type (e.g. `HASH_TYPE`) into its length-prefixed chunks.
+///
+/// # Errors
+/// * `CacheError::WrongType` if the value is not of the given type.
+fn decode_chunks(value: &[u8], value_type: u8) -> CacheResult<Vec<&[u8]>> {
+    let Some([found_type, encoded @ ..]) = value.strip_prefix(&TYPE_MAGIC) else {
+        return Err(CacheError::WrongType);
+    };
+    if *found_type != value_type {
+        return Err(CacheError::WrongType);
+    }
+
+    let mut rest: &[u8] = encoded;
+    let mut chunks: Vec<&[u8]> = Vec::new();
+    while !rest.is_empty() {
+        let (chunk, after) = take_chunk(rest).ok_or(CacheError::WrongType)?;
+        chunks.push(chunk);
+        rest = after;
+    }
+    Ok(chunks)
+}
+
+/// Encode the given chunks as a cached value of the given type, each preceded by its length.
+fn encode_chunks<'a>(value_type: u8, chunks: impl Iterator<Item = &'a [u8]> + Clone) -> Vec<u8> {
+    let size: usize = chunks.clone().map(chunk_size).sum();
+    let mut encoded: Vec<u8> = Vec::with_capacity(TYPE_MAGIC.len() + 1 + size);
+    encoded.extend_from_slice(&TYPE_MAGIC);
+    encoded.push(value_type);
+    for chunk in chunks {
+        encoded.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+        encoded.extend_from_slice(chunk);
+    }
+    encoded
+}
+
+/// The size of the given chunk once encoded, preceded by its length.
+fn chunk_size(chunk: &[u8]) -> usize {
+    4 + chunk.len()
+}
+
+/// The header of the values of the given type (e.g. `HASH_TYPE`): `TYPE_MAGIC`, then the type.
+const fn collection_header(value_type: u8) -> [u8; 5] {
+    let [a, b, c, d] = TYPE_MAGIC;
+    [a, b, c, d, value_type]
+}
+
+/// Split a chunk preceded by its 32-bit big-endian length off the given bytes, returning it along with
+/// the bytes following it.
+fn take_chunk(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (length, rest) = bytes.split_first_chunk::<4>()?;
+    let length: usize = u32::from_be_bytes(*length) as usize;
+    (rest.len() >= length).then(|| rest.split_at(length))
+}
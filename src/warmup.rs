@@ -0,0 +1,189 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use log::debug;
+
+use crate::{
+    embedded::EmbeddedCache,
+    error::ServerError,
+    rdb::{self, ImportStats},
+    server::parse_number,
+};
+
+/// How long to wait for the server of a dump to accept the connection, and then for each part of the dump.
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The path of the dump served by the HTTP listener of the nodes (see `rdb::export`).
+const PEER_DUMP_PATH: &str = "/dump";
+
+/// The largest response head (status line and headers) accepted from the server of a dump.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// Where a node loads its data from at startup, before accepting clients, so that a node freshly
+/// (re)started does not send a wave of misses to the backend. The data is a Redis RDB dump in every case
+/// (see `rdb::import`).
+///
+/// Parsed from a path (`/var/lib/myrustcache/dump.rdb`, or `file:dump.rdb`), an HTTP URL
+/// (`http://backup.local/cache/dump.rdb`), or a peer node (`peer:10.0.0.2:8080`). HTTPS is not supported,
+/// as the server is built without TLS: put a dump fetched over HTTPS behind a local proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarmupSource {
+    /// A dump file, e.g. written by Redis (`SAVE`) or fetched from a node beforehand.
+    File(PathBuf),
+
+    /// A dump fetched over HTTP, with a `GET` request of the given path.
+    Url {
+        host: String,
+        port: u16,
+        path: String,
+    },
+
+    /// Another node, whose whole cache is fetched from `GET /dump` on its HTTP listener.
+    Peer { host: String, port: u16 },
+}
+
+impl FromStr for WarmupSource {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<WarmupSource, String> {
+        let text: &str = text.trim();
+
+        if let Some(url) = text.strip_prefix("http://") {
+            let (authority, path) = match url.find('/') {
+                Some(slash) => (&url[..slash], &url[slash..]),
+                None => (url, "/"),
+            };
+            let (host, port): (&str, u16) = match authority.rsplit_once(':') {
+                Some((host, port)) => (host, parse_port(port)?),
+                None => (authority, 80),
+            };
+            if host.is_empty() {
+                return Err(format!("'{}' has no host", text));
+            }
+            return Ok(WarmupSource::Url {
+                host: host.to_string(),
+                port,
+                path: path.to_string(),
+            });
+        }
+        if text.starts_with("https://") {
+            return Err("HTTPS is not supported (the server is built without TLS)".to_string());
+        }
+
+        if let Some(address) = text.strip_prefix("peer:") {
+            return match address.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() => Ok(WarmupSource::Peer {
+                    host: host.to_string(),
+                    port: parse_port(port)?,
+                }),
+                _ => Err(format!("'{}' is not a host:port address", address)),
+            };
+        }
+
+        let path: &str = text.strip_prefix("file:").unwrap_or(text);
+        if path.is_empty() {
+            return Err("the path of the dump is empty".to_string());
+        }
+        Ok(WarmupSource::File(PathBuf::from(path)))
+    }
+}
+
+impl fmt::Display for WarmupSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarmupSource::File(path) => write!(f, "{}", path.display()),
+            WarmupSource::Url { host, port, path } => write!(f, "http://{}:{}{}", host, port, path),
+            WarmupSource::Peer { host, port } => write!(f, "peer:{}:{}", host, port),
+        }
+    }
+}
+
+impl WarmupSource {
+    /// Load the dump of the source into the cache, replacing the values of the keys it already holds.
+    ///
+    /// # Errors
+    /// * `ServerError::Io` if the dump cannot be fetched or read, or is not a supported RDB dump.
+    /// * `ServerError::Cache` if a key cannot be put into the cache.
+    pub fn load(&self, cache: &EmbeddedCache) -> Result<ImportStats, ServerError> {
+        match self {
+            WarmupSource::File(path) => rdb::import(BufReader::new(File::open(path)?), cache),
+            WarmupSource::Url { host, port, path } => rdb::import(fetch(host, *port, path)?, cache),
+            WarmupSource::Peer { host, port } => {
+                rdb::import(fetch(host, *port, PEER_DUMP_PATH)?, cache)
+            }
+        }
+    }
+}
+
+/// Parse a port number (1 to 65535).
+fn parse_port(text: &str) -> Result<u16, String> {
+    match parse_number(text.as_bytes()) {
+        Some(port) if (1..=u16::MAX as u64).contains(&port) => Ok(port as u16),
+        _ => Err(format!("'{}' is not a valid port number", text)),
+    }
+}
+
+/// Send a `GET` request of the given path over HTTP/1.1, returning the body of the response once its head
+/// has been read. The body is read until the server closes the connection, chunked bodies being refused.
+fn fetch(host: &str, port: u16, path: &str) -> io::Result<BufReader<TcpStream>> {
+    let address: SocketAddr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other(format!("cannot resolve {}", host)))?;
+    let mut stream: TcpStream = TcpStream::connect_timeout(&address, WARMUP_TIMEOUT)?;
+    stream.set_read_timeout(Some(WARMUP_TIMEOUT))?;
+    stream.write_all(
+        format!(
+            "GET {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\n\r\n",
+            path, host, port
+        )
+        .as_bytes(),
+    )?;
+
+    let mut body: BufReader<TcpStream> = BufReader::new(stream);
+    let mut line: String = String::new();
+    let mut head_size: usize = 0;
+    let mut status: Option<String> = None;
+    loop {
+        line.clear();
+        head_size += body.read_line(&mut line)?;
+        if line.is_empty() || head_size > MAX_HEAD_SIZE {
+            return Err(io::Error::other("invalid HTTP response"));
+        }
+        let line: &str = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        match status {
+            None => status = Some(line.to_string()),
+            Some(_) => {
+                let Some((name, value)) = line.split_once(':') else {
+                    return Err(io::Error::other("invalid HTTP response header"));
+                };
+                if name.eq_ignore_ascii_case("Transfer-Encoding")
+                    && !value.trim().eq_ignore_ascii_case("identity")
+                {
+                    return Err(io::Error::other("chunked HTTP responses are not supported"));
+                }
+            }
+        }
+    }
+
+    let status: String = status.unwrap_or_default();
+    debug!("Fetching a dump from {}:{}{}: {}", host, port, path, status);
+    match status.split(' ').nth(1) {
+        Some("200") => Ok(body),
+        _ => Err(io::Error::other(format!(
+            "{}:{}{} answered '{}'",
+            host, port, path, status
+        ))),
+    }
+}
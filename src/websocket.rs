@@ -0,0 +1,640 @@
+use std::{
+    io::{BufWriter, IoSlice, Write},
+    mem,
+    net::Shutdown,
+    str,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{debug, warn};
+use memchr::memmem;
+
+use crate::{
+    auth::Auth,
+    buffers::ConnectionBuffers,
+    connection::{ClientHandler, ClientStream},
+    embedded::EmbeddedCache,
+    error::{CacheError, ServerError},
+    events::KeyspaceEvent,
+    http::{json_string, percent_decode},
+    server::{write_all_vectored, TextSession},
+    utils::{base64_encode, constant_time_eq},
+};
+
+/// The largest handshake (request line and headers) accepted.
+const MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+
+/// The largest message accepted, like the longest command of the text protocol.
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// The GUID the key of a handshake is hashed with (RFC 6455).
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The opcodes of the frames.
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// The status codes of the close frames.
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+const CLOSE_TOO_BIG: u16 = 1009;
+const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+/// The path of the URL opening a stream of keyspace events instead of a command connection.
+const EVENTS_PATH: &str = "/events";
+
+/// How often the thread forwarding the keyspace events checks whether the connection is closed.
+const EVENTS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Who may open a WebSocket connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WebSocketPolicy {
+    /// The origins (e.g. `https://dashboard.example.com`) of the pages allowed to connect. Any origin is
+    /// allowed if empty, as are the clients sending no `Origin` header (i.e. those that are not browsers).
+    pub allowed_origins: Vec<String>,
+
+    /// The token the clients must present, either as the `token` query parameter of the URL (browsers
+    /// cannot set headers) or as an `Authorization: Bearer` header. No token is required if `None`.
+    pub token: Option<String>,
+}
+
+/// The settings of the WebSocket listener.
+pub(crate) struct WebSocketOptions {
+    pub policy: WebSocketPolicy, // Who may open a connection
+    pub auth: Arc<Auth>,         // Who may execute commands once connected
+}
+
+impl WebSocketPolicy {
+    fn allows_origin(&self, maybe_origin: Option<&str>) -> bool {
+        match maybe_origin {
+            Some(origin) if !self.allowed_origins.is_empty() => self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+            _ => true,
+        }
+    }
+
+    fn allows_token(&self, maybe_token: Option<&str>) -> bool {
+        match (&self.token, maybe_token) {
+            (None, _) => true,
+            (Some(token), Some(presented)) => {
+                constant_time_eq(token.as_bytes(), presented.as_bytes())
+            }
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// The outcome of parsing the pending data of a connection, before or after the handshake.
+enum Parsed<T> {
+    Complete(T, usize),    // The handshake or frame, and the number of bytes it takes
+    Incomplete,            // More data is needed
+    TooBig,                // The handshake or message is larger than accepted
+    Invalid(&'static str), // The data does not follow the protocol
+}
+
+/// The handshake request of a client.
+struct Handshake<'a> {
+    target: &'a str,         // The path and query of the URL
+    key: &'a str,            // The `Sec-WebSocket-Key` header
+    origin: Option<&'a str>, // The `Origin` header, sent by browsers
+    token: Option<&'a str>,  // The token of the `token` query parameter or `Authorization` header
+}
+
+/// A frame received from a client, with its payload unmasked.
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// A handler for a client speaking the text protocol over WebSocket (RFC 6455), e.g. a browser.
+///
+/// Once the handshake is accepted (see `WebSocketPolicy`), every text message holds one or more commands
+/// of the text protocol (the last one needing no line terminator), answered by a message holding their
+/// responses. Binary messages carry the frames of the binary protocol after `HELLO BINARY`.
+///
+/// A connection to `/events` (with an optional `pattern` query parameter) receives the keyspace events of
+/// the keys matching the pattern instead, as JSON text messages (e.g. `{"event":"set","key":"x"}`).
+pub(crate) struct WebSocketClientHandler {
+    address: String, // The address of the client (IP:Port). Used for logging purposes.
+    stream: BufWriter<ClientStream>, // The client connection, buffering the frames until the next flush
+    session: TextSession<Vec<u8>>,   // Executes the commands, writing their responses into a buffer
+    buffers: ConnectionBuffers, // The received data, in buffers sized to the traffic of the client
+    options: Arc<WebSocketOptions>, // Who may open a connection, and execute commands
+    open: bool,                 // Whether the handshake has been accepted
+    message: Vec<u8>,           // The fragments received so far of the current message
+    commands: Vec<u8>, // The commands received but not handled yet (e.g. a partial binary frame)
+    closing: bool,     // Whether the connection is being closed
+    events: Option<EventStream>, // The stream of keyspace events, for the connections to `/events`
+}
+
+/// The keyspace events pushed to a connection by a thread of its own, sharing the writes with the handler.
+struct EventStream {
+    writer: Arc<Mutex<ClientStream>>, // The client connection, locked for every frame written
+    closed: Arc<AtomicBool>, // Whether the connection is closed, to stop forwarding the events
+}
+
+impl Drop for WebSocketClientHandler {
+    fn drop(&mut self) {
+        if let Some(events) = &self.events {
+            events.closed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl ClientHandler for WebSocketClientHandler {
+    const PROTOCOL: &'static str = "WebSocket";
+    type Options = WebSocketOptions;
+
+    fn new(
+        stream: ClientStream,
+        cache: Arc<EmbeddedCache>,
+        buffers: ConnectionBuffers,
+        options: &Arc<WebSocketOptions>,
+    ) -> WebSocketClientHandler {
+        let address: String = stream.peer_address();
+        WebSocketClientHandler {
+            session: TextSession::new(address.clone(), Vec::new(), cache)
+                .with_auth(Arc::clone(&options.auth)),
+            address,
+            stream: BufWriter::new(stream),
+            buffers,
+            options: Arc::clone(options),
+            open: false,
+            message: Vec::new(),
+            commands: Vec::new(),
+            closing: false,
+            events: None,
+        }
+    }
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn connection(&mut self) -> (&ClientStream, &mut ConnectionBuffers) {
+        (self.stream.get_ref(), &mut self.buffers)
+    }
+
+    fn handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        self._handle_pending(pending)
+    }
+
+    fn reject_overflow(&mut self) {
+        let _ = self._write_close(CLOSE_TOO_BIG);
+        let _ = self.stream.flush();
+    }
+
+    fn reject_too_many_clients(&mut self) {
+        let _ = self._reject_handshake("503 Service Unavailable", "too many clients");
+        let _ = self.stream.flush();
+    }
+
+    fn reject_timeout(&mut self) {
+        let _ = self._write_close(CLOSE_POLICY_VIOLATION);
+        let _ = self.stream.flush();
+    }
+
+    fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    fn is_listening(&self) -> bool {
+        self.events.is_some()
+    }
+}
+
+impl WebSocketClientHandler {
+    /// Handle the handshake or the complete frames of the given pending data, draining them.
+    fn _handle_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), ServerError> {
+        let mut start: usize = 0;
+
+        if !self.open {
+            match parse_handshake(pending) {
+                Parsed::Complete(handshake, length) => {
+                    self._handle_handshake(&handshake)?;
+                    start = length;
+                }
+                Parsed::Incomplete => return Ok(()),
+                Parsed::TooBig => {
+                    self._reject_handshake("400 Bad Request", "handshake too large")?
+                }
+                Parsed::Invalid(reason) => self._reject_handshake("400 Bad Request", reason)?,
+            }
+        }
+
+        while !self.closing {
+            match parse_frame(&pending[start..]) {
+                Parsed::Complete(frame, length) => {
+                    self._handle_frame(frame)?;
+                    start += length;
+                }
+                Parsed::Incomplete => break,
+                Parsed::TooBig => {
+                    warn!("Too big message from {}", self.address);
+                    self._write_close(CLOSE_TOO_BIG)?;
+                }
+                Parsed::Invalid(reason) => {
+                    warn!("Protocol error from {}: {}", self.address, reason);
+                    self._write_close(CLOSE_PROTOCOL_ERROR)?;
+                }
+            }
+        }
+        pending.drain(..start);
+
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Accept the handshake if the policy allows the client, upgrading the connection.
+    fn _handle_handshake(&mut self, handshake: &Handshake) -> Result<(), ServerError> {
+        if !self.options.policy.allows_origin(handshake.origin) {
+            warn!(
+                "WebSocket from {} refused for its origin {}",
+                self.address,
+                handshake.origin.unwrap_or_default()
+            );
+            return self._reject_handshake("403 Forbidden", "origin not allowed");
+        }
+        if !self.options.policy.allows_token(handshake.token) {
+            warn!("WebSocket from {} refused for its token", self.address);
+            return self._reject_handshake("401 Unauthorized", "invalid token");
+        }
+
+        let accept: String = base64_encode(&sha1(
+            format!("{}{}", handshake.key, HANDSHAKE_GUID).as_bytes(),
+        ));
+        let response: String = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept
+        );
+        self.stream.write_all(response.as_bytes())?;
+        self.open = true;
+        debug!("WebSocket opened by {}", self.address);
+
+        let (path, query) = handshake
+            .target
+            .split_once('?')
+            .unwrap_or((handshake.target, ""));
+        if path == EVENTS_PATH {
+            let pattern: String = query
+                .split('&')
+                .find_map(|parameter| parameter.strip_prefix("pattern="))
+                .and_then(percent_decode)
+                .unwrap_or_else(|| "*".to_string());
+            self._open_events(&pattern)?;
+        }
+        Ok(())
+    }
+
+    /// Subscribe to the keyspace events matching the given pattern, and forward them on a thread of their
+    /// own until the connection is closed.
+    fn _open_events(&mut self, pattern: &str) -> Result<(), ServerError> {
+        let receiver: Receiver<KeyspaceEvent> = self.session.cache().subscribe(pattern)?;
+
+        // From now on, the frames are written straight to the connection, under its lock
+        self.stream.flush()?;
+        let writer: Arc<Mutex<ClientStream>> =
+            Arc::new(Mutex::new(self.stream.get_ref().try_clone()?));
+        let closed: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        self.events = Some(EventStream {
+            writer: Arc::clone(&writer),
+            closed: Arc::clone(&closed),
+        });
+
+        debug!(
+            "{} subscribed to the keyspace events of '{}'",
+            self.address, pattern
+        );
+        let address: String = self.address.clone();
+        thread::spawn(move || {
+            if let Err(e) = forward_events(receiver, &writer, &closed) {
+                debug!(
+                    "Stopped forwarding the keyspace events to {}: {}",
+                    address, e
+                );
+            }
+        });
+        Ok(())
+    }
+
+    fn _reject_handshake(&mut self, status: &str, reason: &str) -> Result<(), ServerError> {
+        let response: String = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reason.len(),
+            reason
+        );
+        self.stream.write_all(response.as_bytes())?;
+        self.closing = true;
+        Ok(())
+    }
+
+    /// Handle a frame: answer the control frames, and execute the commands of the complete messages.
+    fn _handle_frame(&mut self, frame: Frame) -> Result<(), ServerError> {
+        match frame.opcode {
+            OP_PING => self._write_frame(OP_PONG, &frame.payload),
+            OP_PONG => Ok(()),
+            OP_CLOSE => {
+                // Echo the status of the client, if any
+                let status: u16 = match frame.payload.first_chunk::<2>() {
+                    Some(status) => u16::from_be_bytes(*status),
+                    None => 1000,
+                };
+                self._write_close(status)
+            }
+            OP_TEXT | OP_BINARY | OP_CONTINUATION if self.events.is_some() => {
+                // The connections receiving the keyspace events do not take commands
+                self._write_close(CLOSE_UNSUPPORTED_DATA)
+            }
+            OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                if frame.opcode != OP_CONTINUATION && !self.message.is_empty() {
+                    self._write_close(CLOSE_PROTOCOL_ERROR)?;
+                    return Ok(());
+                }
+                if self.message.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                    self._write_close(CLOSE_TOO_BIG)?;
+                    return Ok(());
+                }
+
+                // The opcode of a message is the one of its first frame, kept in front of its payload
+                if self.message.is_empty() {
+                    self.message.push(frame.opcode);
+                }
+                self.message.extend_from_slice(&frame.payload);
+                match frame.fin {
+                    true => self._handle_message(),
+                    false => Ok(()),
+                }
+            }
+            _ => self._write_close(CLOSE_PROTOCOL_ERROR),
+        }
+    }
+
+    /// Execute the commands of a complete message, answering with a message holding their responses.
+    fn _handle_message(&mut self) -> Result<(), ServerError> {
+        let message: Vec<u8> = mem::take(&mut self.message);
+        let (opcode, payload) = (message[0], &message[1..]);
+
+        self.commands.extend_from_slice(payload);
+        if opcode == OP_TEXT && !self.commands.ends_with(b"\n") {
+            self.commands.push(b'\n');
+        }
+        let result: Result<(), ServerError> = self.session.handle_pending(&mut self.commands);
+
+        let responses: Vec<u8> = mem::take(self.session.writer());
+        if !responses.is_empty() {
+            let opcode: u8 = match str::from_utf8(&responses) {
+                Ok(_) => OP_TEXT,
+                Err(_) => OP_BINARY,
+            };
+            self._write_frame(opcode, &responses)?;
+        }
+
+        if let Err(e) = result {
+            warn!("Closing the WebSocket of {}: {}", self.address, e);
+            self._write_close(CLOSE_PROTOCOL_ERROR)?;
+        }
+        Ok(())
+    }
+
+    fn _write_close(&mut self, status: u16) -> Result<(), ServerError> {
+        self.closing = true;
+        self._write_frame(OP_CLOSE, &status.to_be_bytes())
+    }
+
+    /// Write a (final and unmasked) frame, its payload straight from the given buffer.
+    fn _write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), ServerError> {
+        let header: Vec<u8> = frame_header(opcode, payload.len());
+        let mut slices: [IoSlice; 2] = [IoSlice::new(&header), IoSlice::new(payload)];
+
+        match &self.events {
+            Some(events) => write_all_vectored(
+                &mut *events.writer.lock().map_err(CacheError::from)?,
+                &mut slices,
+            )?,
+            None => write_all_vectored(&mut self.stream, &mut slices)?,
+        }
+        Ok(())
+    }
+}
+
+/// Forward the keyspace events to the client as text messages, until the connection is closed. A client
+/// which falls too far behind is disconnected, since it has missed events.
+fn forward_events(
+    receiver: Receiver<KeyspaceEvent>,
+    writer: &Mutex<ClientStream>,
+    closed: &AtomicBool,
+) -> Result<(), ServerError> {
+    while !closed.load(Ordering::Relaxed) {
+        let event: KeyspaceEvent = match receiver.recv_timeout(EVENTS_POLL_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                let mut stream = writer.lock().map_err(CacheError::from)?;
+                let status: [u8; 2] = CLOSE_TRY_AGAIN_LATER.to_be_bytes();
+                stream.write_all(&frame_header(OP_CLOSE, status.len()))?;
+                stream.write_all(&status)?;
+                stream.shutdown(Shutdown::Both)?;
+                return Ok(());
+            }
+        };
+
+        let message: String = format!(
+            "{{\"event\":\"{}\",\"key\":{}}}",
+            event.kind.as_str(),
+            json_string(&event.key)
+        );
+        let mut stream = writer.lock().map_err(CacheError::from)?;
+        stream.write_all(&frame_header(OP_TEXT, message.len()))?;
+        stream.write_all(message.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Encode the header of a (final and unmasked) frame of the given payload length.
+fn frame_header(opcode: u8, length: usize) -> Vec<u8> {
+    let mut header: Vec<u8> = vec![0x80 | opcode];
+    match length {
+        0..=125 => header.push(length as u8),
+        126..=0xFFFF => {
+            header.push(126);
+            header.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        _ => {
+            header.push(127);
+            header.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    header
+}
+
+/// Parse the handshake request at the start of the given data.
+fn parse_handshake(data: &[u8]) -> Parsed<Handshake<'_>> {
+    let Some(head_end) = memmem::find(data, b"\r\n\r\n") else {
+        return match data.len() > MAX_HANDSHAKE_SIZE {
+            true => Parsed::TooBig,
+            false => Parsed::Incomplete,
+        };
+    };
+    let Ok(head) = str::from_utf8(&data[..head_end]) else {
+        return Parsed::Invalid("malformed handshake");
+    };
+
+    let mut lines = head.split("\r\n");
+    let request_line: &str = lines.next().unwrap_or_default();
+    let Some(target) = request_line
+        .strip_prefix("GET ")
+        .and_then(|rest| rest.strip_suffix(" HTTP/1.1"))
+    else {
+        return Parsed::Invalid("expected a GET request over HTTP/1.1");
+    };
+
+    let mut handshake: Handshake = Handshake {
+        target,
+        key: "",
+        origin: None,
+        token: target
+            .split_once('?')
+            .and_then(|(_, query)| query.split('&').find_map(|p| p.strip_prefix("token="))),
+    };
+    let mut upgrade: bool = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Parsed::Invalid("malformed header");
+        };
+        let value: &str = value.trim();
+
+        if name.eq_ignore_ascii_case("Upgrade") {
+            upgrade = value.eq_ignore_ascii_case("websocket");
+        } else if name.eq_ignore_ascii_case("Sec-WebSocket-Key") {
+            handshake.key = value;
+        } else if name.eq_ignore_ascii_case("Sec-WebSocket-Version") && value != "13" {
+            return Parsed::Invalid("unsupported WebSocket version");
+        } else if name.eq_ignore_ascii_case("Origin") {
+            handshake.origin = Some(value);
+        } else if name.eq_ignore_ascii_case("Authorization") {
+            handshake.token = handshake.token.or(value.strip_prefix("Bearer "));
+        }
+    }
+
+    if !upgrade || handshake.key.is_empty() {
+        return Parsed::Invalid("not a WebSocket handshake");
+    }
+    Parsed::Complete(handshake, head_end + 4)
+}
+
+/// Parse the frame at the start of the given data. The frames of clients must be masked.
+fn parse_frame(data: &[u8]) -> Parsed<Frame> {
+    let Some(&[first, second]) = data.first_chunk::<2>() else {
+        return Parsed::Incomplete;
+    };
+    if first & 0x70 != 0 {
+        return Parsed::Invalid("reserved bits set");
+    }
+    if second & 0x80 == 0 {
+        return Parsed::Invalid("unmasked frame");
+    }
+
+    let (length, mut offset): (u64, usize) = match second & 0x7F {
+        126 => match data.get(2..4) {
+            Some(bytes) => (u64::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4),
+            None => return Parsed::Incomplete,
+        },
+        127 => match data.get(2..10) {
+            Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap_or_default()), 10),
+            None => return Parsed::Incomplete,
+        },
+        length => (u64::from(length), 2),
+    };
+    if length > MAX_MESSAGE_SIZE as u64 {
+        return Parsed::TooBig;
+    }
+
+    let Some(mask) = data.get(offset..offset + 4) else {
+        return Parsed::Incomplete;
+    };
+    offset += 4;
+    let end: usize = offset + length as usize;
+    let Some(payload) = data.get(offset..end) else {
+        return Parsed::Incomplete;
+    };
+
+    let frame: Frame = Frame {
+        fin: first & 0x80 != 0,
+        opcode: first & 0x0F,
+        payload: payload
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ mask[i % 4])
+            .collect(),
+    };
+    Parsed::Complete(frame, end)
+}
+
+/// The SHA-1 digest of the given data, as required by the handshake (not for its security).
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message: Vec<u8> = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w: [u32; 80] = [0; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k): (u32, u32) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp: u32 = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut digest: [u8; 20] = [0; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
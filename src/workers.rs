@@ -0,0 +1,167 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use threadpool::ThreadPool;
+
+/// How long a listener waits before checking again whether the queue has room (see `RejectionPolicy::Wait`).
+const WAIT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What a listener does with a connection accepted while every worker is busy and the queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RejectionPolicy {
+    /// Close the connection right away, so that the client can retry or go to another node.
+    #[default]
+    Close,
+
+    /// Stop accepting connections until the queue has room, leaving the new clients in the backlog of the
+    /// listener (the OS refusing them once the backlog is full).
+    Wait,
+}
+
+impl FromStr for RejectionPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<RejectionPolicy, String> {
+        match text {
+            "close" => Ok(RejectionPolicy::Close),
+            "wait" => Ok(RejectionPolicy::Wait),
+            _ => Err(format!("unknown rejection policy '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for RejectionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectionPolicy::Close => write!(f, "close"),
+            RejectionPolicy::Wait => write!(f, "wait"),
+        }
+    }
+}
+
+/// Sizing options of the `WorkerPool` serving the client connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerOptions {
+    pub threads: usize, // The number of worker threads, each serving a connection until it is closed
+    pub queue_depth: usize, // The number of accepted connections which may wait for a worker
+    pub rejection: RejectionPolicy, // What happens to the connections accepted beyond the queue
+}
+
+impl Default for WorkerOptions {
+    fn default() -> WorkerOptions {
+        WorkerOptions {
+            threads: 512,
+            queue_depth: 1024,
+            rejection: RejectionPolicy::default(),
+        }
+    }
+}
+
+/// A fixed set of threads serving the client connections of all the listeners, so that a storm of
+/// connections cannot exhaust the threads of the OS.
+///
+/// Every connection takes a worker until it is closed. The connections accepted while every worker is
+/// busy wait in a queue of at most `queue_depth` connections, beyond which they are handled as the
+/// `RejectionPolicy` says.
+///
+/// The sizing can be changed while the server runs (e.g. with `CONFIG SET`). Fewer threads only stop once
+/// their connection is closed.
+pub(crate) struct WorkerPool {
+    pool: Mutex<ThreadPool>, // Resized in place, hence the lock (only taken to hand out a connection)
+    queued: Arc<AtomicUsize>, // The number of connections waiting for a worker
+    queue_depth: AtomicUsize, // The largest number of connections waiting for a worker
+    wait: AtomicBool,        // Whether the rejection policy is `RejectionPolicy::Wait`
+}
+
+impl WorkerPool {
+    pub fn new(options: &WorkerOptions) -> WorkerPool {
+        WorkerPool {
+            pool: Mutex::new(
+                threadpool::Builder::new()
+                    .num_threads(options.threads.max(1))
+                    .thread_name("worker".to_string())
+                    .build(),
+            ),
+            queued: Arc::new(AtomicUsize::new(0)),
+            queue_depth: AtomicUsize::new(options.queue_depth),
+            wait: AtomicBool::new(options.rejection == RejectionPolicy::Wait),
+        }
+    }
+
+    /// The current sizing of the pool.
+    pub fn options(&self) -> WorkerOptions {
+        WorkerOptions {
+            threads: self.pool.lock().map_or(0, |pool| pool.max_count()),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            rejection: match self.wait.load(Ordering::Relaxed) {
+                true => RejectionPolicy::Wait,
+                false => RejectionPolicy::Close,
+            },
+        }
+    }
+
+    /// Change the number of worker threads (at least one).
+    pub fn set_threads(&self, threads: usize) {
+        if let Ok(mut pool) = self.pool.lock() {
+            pool.set_num_threads(threads.max(1));
+        }
+    }
+
+    /// Change the number of connections which may wait for a worker.
+    pub fn set_queue_depth(&self, queue_depth: usize) {
+        self.queue_depth.store(queue_depth, Ordering::Relaxed);
+    }
+
+    /// Change what happens to the connections accepted beyond the queue.
+    pub fn set_rejection(&self, rejection: RejectionPolicy) {
+        self.wait
+            .store(rejection == RejectionPolicy::Wait, Ordering::Relaxed);
+    }
+
+    /// Serve a connection on a worker, once one is free.
+    ///
+    /// # Returns
+    /// * `false` if the connection was rejected (see `RejectionPolicy::Close`), dropping the job.
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) -> bool {
+        loop {
+            {
+                let Ok(pool) = self.pool.lock() else {
+                    return false;
+                };
+
+                // The idle workers take the queued connections right away, so a connection only waits in
+                // the queue while every worker is busy
+                let idle: usize = pool.max_count().saturating_sub(pool.active_count());
+                let queue_depth: usize = self.queue_depth.load(Ordering::Relaxed);
+                let reserved: bool = self
+                    .queued
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+                        Some(queued + 1).filter(|&queued| queued <= queue_depth + idle)
+                    })
+                    .is_ok();
+
+                if reserved {
+                    let queued: Arc<AtomicUsize> = Arc::clone(&self.queued);
+                    pool.execute(move || {
+                        queued.fetch_sub(1, Ordering::Relaxed);
+                        job();
+                    });
+                    return true;
+                }
+            }
+
+            match self.wait.load(Ordering::Relaxed) {
+                false => return false,
+                true => thread::sleep(WAIT_INTERVAL),
+            }
+        }
+    }
+}